@@ -0,0 +1,53 @@
+// Runs a ROM for a few seconds with Start held and prints a checksum of the last frame.
+//
+//   cargo build --release -p nes_rs_capi
+//   cc capi/examples/embed.c -Icapi/include -Ltarget/release -lnes_rs_capi -o embed
+//   LD_LIBRARY_PATH=target/release ./embed game.nes
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "nes_rs.h"
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <rom>\n", argv[0]);
+        return 2;
+    }
+    FILE *file = fopen(argv[1], "rb");
+    if (!file) {
+        perror(argv[1]);
+        return 1;
+    }
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    rewind(file);
+    uint8_t *rom = malloc(len);
+    fread(rom, 1, len, file);
+    fclose(file);
+
+    NesRs *nes = nes_rs_create();
+    if (!nes_rs_load_rom(nes, rom, len)) {
+        fprintf(stderr, "Could not load %s: %s\n", argv[1], nes_rs_last_error(nes));
+        return 1;
+    }
+    free(rom);
+
+    nes_rs_set_button(nes, 0, NES_RS_BUTTON_START, true);
+    for (int frame = 0; frame < 180; frame++) {
+        if (!nes_rs_run_frame(nes)) {
+            fprintf(stderr, "Stopped: %s\n", nes_rs_last_error(nes));
+            return 1;
+        }
+    }
+
+    const uint8_t *pixels = nes_rs_framebuffer(nes);
+    uint32_t sum = 0;
+    for (size_t i = 0; i < NES_RS_WIDTH * NES_RS_HEIGHT * 4; i++) {
+        sum = sum * 31 + pixels[i];
+    }
+    printf("Frame checksum: %08x\n", sum);
+
+    nes_rs_destroy(nes);
+    return 0;
+}
@@ -0,0 +1,80 @@
+#ifndef NES_RS_H
+#define NES_RS_H
+
+/* Generated by cbindgen from capi/src/lib.rs. Run `NES_RS_BLESS=1 cargo test -p nes_rs_capi` to update. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Size of the picture from `nes_rs_framebuffer`, in pixels.
+#define NES_RS_WIDTH 256
+
+#define NES_RS_HEIGHT 240
+
+// Number of controller ports `nes_rs_set_button` takes.
+#define NES_RS_PORTS 2
+
+// The buttons of a standard controller.
+typedef enum NesRsButton {
+  NES_RS_BUTTON_A = 0,
+  NES_RS_BUTTON_B = 1,
+  NES_RS_BUTTON_SELECT = 2,
+  NES_RS_BUTTON_START = 3,
+  NES_RS_BUTTON_UP = 4,
+  NES_RS_BUTTON_DOWN = 5,
+  NES_RS_BUTTON_LEFT = 6,
+  NES_RS_BUTTON_RIGHT = 7,
+} NesRsButton;
+
+// A console. Opaque to C.
+typedef struct NesRs NesRs;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Makes a console with nothing loaded. Free it with `nes_rs_destroy`.
+struct NesRs *nes_rs_create(void);
+
+// Frees a console from `nes_rs_create`. Null is ignored.
+void nes_rs_destroy(struct NesRs *nes);
+
+// The message for the last call that failed, valid until the next call that fails or until the
+// console is destroyed. Null if nothing has failed.
+const char *nes_rs_last_error(const struct NesRs *nes);
+
+// Loads an iNES file from `len` bytes at `data` and powers the console on. The bytes are copied.
+// On failure the console keeps the game it had.
+bool nes_rs_load_rom(struct NesRs *nes, const uint8_t *data, size_t len);
+
+// Presses the reset button.
+void nes_rs_reset(struct NesRs *nes);
+
+// Holds or releases `button` on controller `port` (0 or 1) from the next frame on.
+bool nes_rs_set_button(struct NesRs *nes, uint32_t port, enum NesRsButton button, bool pressed);
+
+// Runs the console until it has drawn a frame.
+bool nes_rs_run_frame(struct NesRs *nes);
+
+// The last frame, NES_RS_WIDTH x NES_RS_HEIGHT pixels as RGBA8, row by row with no padding. The
+// pointer stays valid for the life of the console and the contents change with each frame.
+const uint8_t *nes_rs_framebuffer(const struct NesRs *nes);
+
+// The number of bytes `nes_rs_save_state` needs, or 0 with no ROM loaded.
+size_t nes_rs_state_size(const struct NesRs *nes);
+
+// Writes a save state into the `len` bytes at `buffer`. Returns the number of bytes written, or 0
+// if there is no ROM or the buffer is too small. States are the same as the desktop's.
+size_t nes_rs_save_state(struct NesRs *nes, uint8_t *buffer, size_t len);
+
+// Restores a state from `nes_rs_save_state`, made with the same ROM.
+bool nes_rs_load_state(struct NesRs *nes, const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NES_RS_H */
@@ -0,0 +1,317 @@
+//! C API for embedding the emulator in C, C++, C# and anything else with a C FFI.
+//!
+//! `cargo build --release -p nes_rs_capi` builds `libnes_rs_capi.so`/`.dylib`/`.dll` and a static
+//! library, and `include/nes_rs.h` declares what they export. A console is an opaque `NesRs`
+//! handle from `nes_rs_create`. Load a ROM into it from memory, set the buttons, call
+//! `nes_rs_run_frame` once per frame and draw `nes_rs_framebuffer`. Functions that can fail return
+//! false (or 0) and leave a message for `nes_rs_last_error`. Panics are caught and reported the
+//! same way rather than unwinding into the caller.
+//!
+//! The header is generated with cbindgen from this file, and the `///` comments on the exported
+//! items become its documentation. After changing the API,
+//! `NES_RS_BLESS=1 cargo test -p nes_rs_capi` rewrites it; plain `cargo test` fails while it is out
+//! of date.
+
+// Every pointer is checked for null, and the rest is the contract in the header.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use nes_rs_core::cartridge::Cartridge;
+use nes_rs_core::emulator::Emulator;
+use nes_rs_core::joypad::JoypadButton;
+use nes_rs_core::render::constants::{NES_PIXEL_HEIGHT, NES_PIXEL_WIDTH};
+
+/// Size of the picture from `nes_rs_framebuffer`, in pixels.
+pub const NES_RS_WIDTH: u32 = 256;
+pub const NES_RS_HEIGHT: u32 = 240;
+// Written out above so the header gets numbers.
+const _: () = assert!(NES_RS_WIDTH == NES_PIXEL_WIDTH as u32 && NES_RS_HEIGHT == NES_PIXEL_HEIGHT as u32);
+
+/// Number of controller ports `nes_rs_set_button` takes.
+pub const NES_RS_PORTS: u32 = 2;
+
+/// The buttons of a standard controller.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NesRsButton {
+    A = 0,
+    B = 1,
+    Select = 2,
+    Start = 3,
+    Up = 4,
+    Down = 5,
+    Left = 6,
+    Right = 7,
+}
+
+impl NesRsButton {
+    fn joypad_button(self) -> JoypadButton {
+        match self {
+            NesRsButton::A => JoypadButton::BUTTON_A,
+            NesRsButton::B => JoypadButton::BUTTON_B,
+            NesRsButton::Select => JoypadButton::SELECT,
+            NesRsButton::Start => JoypadButton::START,
+            NesRsButton::Up => JoypadButton::UP,
+            NesRsButton::Down => JoypadButton::DOWN,
+            NesRsButton::Left => JoypadButton::LEFT,
+            NesRsButton::Right => JoypadButton::RIGHT,
+        }
+    }
+}
+
+/// A console. Opaque to C.
+pub struct NesRs {
+    // None until a ROM is loaded.
+    emulator: Option<Emulator>,
+    buttons: [JoypadButton; NES_RS_PORTS as usize],
+    // The last picture, as RGBA8.
+    framebuffer: Vec<u8>,
+    last_error: Option<CString>,
+}
+
+impl NesRs {
+    fn fail(&mut self, message: impl Into<String>) {
+        // A message with a NUL in it would be cut short there anyway.
+        let message = message.into().replace('\0', " ");
+        self.last_error = Some(CString::new(message).unwrap());
+    }
+
+    fn run_frame(&mut self) -> Result<(), String> {
+        let Some(emulator) = &mut self.emulator else {
+            return Err("No ROM is loaded".to_string());
+        };
+        for (port, buttons) in self.buttons.iter().enumerate() {
+            emulator.set_buttons(port, *buttons);
+        }
+        panic::catch_unwind(AssertUnwindSafe(|| emulator.run_frame())).map_err(|payload| {
+            payload
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| payload.downcast_ref::<&str>().map(|message| message.to_string()))
+                .unwrap_or_else(|| "The emulator crashed".to_string())
+        })?;
+        emulator.frame.write_rgba8(&mut self.framebuffer);
+        Ok(())
+    }
+}
+
+// Turns a handle from C into a reference, or None for a null pointer.
+unsafe fn handle<'a>(nes: *mut NesRs) -> Option<&'a mut NesRs> {
+    nes.as_mut()
+}
+
+/// Makes a console with nothing loaded. Free it with `nes_rs_destroy`.
+#[no_mangle]
+pub extern "C" fn nes_rs_create() -> *mut NesRs {
+    Box::into_raw(Box::new(NesRs {
+        emulator: None,
+        buttons: [JoypadButton::empty(); NES_RS_PORTS as usize],
+        framebuffer: vec![0; (NES_RS_WIDTH * NES_RS_HEIGHT * 4) as usize],
+        last_error: None,
+    }))
+}
+
+/// Frees a console from `nes_rs_create`. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn nes_rs_destroy(nes: *mut NesRs) {
+    if !nes.is_null() {
+        drop(Box::from_raw(nes));
+    }
+}
+
+/// The message for the last call that failed, valid until the next call that fails or until the
+/// console is destroyed. Null if nothing has failed.
+#[no_mangle]
+pub unsafe extern "C" fn nes_rs_last_error(nes: *const NesRs) -> *const c_char {
+    match nes.as_ref().and_then(|nes| nes.last_error.as_ref()) {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Loads an iNES file from `len` bytes at `data` and powers the console on. The bytes are copied.
+/// On failure the console keeps the game it had.
+#[no_mangle]
+pub unsafe extern "C" fn nes_rs_load_rom(nes: *mut NesRs, data: *const u8, len: usize) -> bool {
+    let Some(nes) = handle(nes) else {
+        return false;
+    };
+    if data.is_null() {
+        nes.fail("The ROM is null");
+        return false;
+    }
+    match Cartridge::new(std::slice::from_raw_parts(data, len)) {
+        Ok(cartridge) => {
+            nes.emulator = Some(Emulator::new(cartridge));
+            true
+        }
+        Err(e) => {
+            nes.fail(e);
+            false
+        }
+    }
+}
+
+/// Presses the reset button.
+#[no_mangle]
+pub unsafe extern "C" fn nes_rs_reset(nes: *mut NesRs) {
+    if let Some(emulator) = handle(nes).and_then(|nes| nes.emulator.as_mut()) {
+        emulator.power_on();
+    }
+}
+
+/// Holds or releases `button` on controller `port` (0 or 1) from the next frame on.
+#[no_mangle]
+pub unsafe extern "C" fn nes_rs_set_button(nes: *mut NesRs, port: u32, button: NesRsButton, pressed: bool) -> bool {
+    let Some(nes) = handle(nes) else {
+        return false;
+    };
+    let Some(buttons) = nes.buttons.get_mut(port as usize) else {
+        nes.fail(format!("There is no controller port {}", port));
+        return false;
+    };
+    buttons.set(button.joypad_button(), pressed);
+    true
+}
+
+/// Runs the console until it has drawn a frame.
+#[no_mangle]
+pub unsafe extern "C" fn nes_rs_run_frame(nes: *mut NesRs) -> bool {
+    let Some(nes) = handle(nes) else {
+        return false;
+    };
+    match nes.run_frame() {
+        Ok(()) => true,
+        Err(e) => {
+            nes.fail(e);
+            false
+        }
+    }
+}
+
+/// The last frame, NES_RS_WIDTH x NES_RS_HEIGHT pixels as RGBA8, row by row with no padding. The
+/// pointer stays valid for the life of the console and the contents change with each frame.
+#[no_mangle]
+pub unsafe extern "C" fn nes_rs_framebuffer(nes: *const NesRs) -> *const u8 {
+    match nes.as_ref() {
+        Some(nes) => nes.framebuffer.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// The number of bytes `nes_rs_save_state` needs, or 0 with no ROM loaded.
+#[no_mangle]
+pub unsafe extern "C" fn nes_rs_state_size(nes: *const NesRs) -> usize {
+    nes.as_ref()
+        .and_then(|nes| nes.emulator.as_ref())
+        .map_or(0, |emulator| emulator.save_state().len())
+}
+
+/// Writes a save state into the `len` bytes at `buffer`. Returns the number of bytes written, or 0
+/// if there is no ROM or the buffer is too small. States are the same as the desktop's.
+#[no_mangle]
+pub unsafe extern "C" fn nes_rs_save_state(nes: *mut NesRs, buffer: *mut u8, len: usize) -> usize {
+    let Some(nes) = handle(nes) else {
+        return 0;
+    };
+    let Some(emulator) = &nes.emulator else {
+        nes.fail("No ROM is loaded");
+        return 0;
+    };
+    let state = emulator.save_state();
+    if buffer.is_null() || state.len() > len {
+        nes.fail(format!("The state needs {} bytes, but the buffer has {}", state.len(), len));
+        return 0;
+    }
+    std::slice::from_raw_parts_mut(buffer, state.len()).copy_from_slice(&state);
+    state.len()
+}
+
+/// Restores a state from `nes_rs_save_state`, made with the same ROM.
+#[no_mangle]
+pub unsafe extern "C" fn nes_rs_load_state(nes: *mut NesRs, data: *const u8, len: usize) -> bool {
+    let Some(nes) = handle(nes) else {
+        return false;
+    };
+    let Some(emulator) = &mut nes.emulator else {
+        nes.fail("No ROM is loaded");
+        return false;
+    };
+    if data.is_null() {
+        nes.fail("The state is null");
+        return false;
+    }
+    match emulator.load_state(std::slice::from_raw_parts(data, len)) {
+        Ok(()) => {
+            emulator.frame.write_rgba8(&mut nes.framebuffer);
+            true
+        }
+        Err(e) => {
+            nes.fail(e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::path::Path;
+
+    const HEADER: &str = "include/nes_rs.h";
+
+    fn last_error(nes: *mut NesRs) -> String {
+        unsafe { CStr::from_ptr(nes_rs_last_error(nes)) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_header_is_up_to_date() {
+        let mut generated = Vec::new();
+        cbindgen::generate(env!("CARGO_MANIFEST_DIR")).unwrap().write(&mut generated);
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(HEADER);
+        if std::env::var_os(nes_rs_core::testrom::golden::BLESS_VAR).is_some() {
+            std::fs::write(&path, &generated).unwrap();
+        }
+        let checked_in = std::fs::read(&path).unwrap_or_default();
+        assert!(checked_in == generated, "{} is out of date; rerun with NES_RS_BLESS=1", HEADER);
+    }
+
+    #[test]
+    fn test_embedding() {
+        let nes = nes_rs_create();
+        unsafe {
+            assert!(!nes_rs_run_frame(nes));
+            assert_eq!(last_error(nes), "No ROM is loaded");
+            assert!(!nes_rs_load_rom(nes, [0u8; 4].as_ptr(), 4));
+            assert_eq!(last_error(nes), "File is too short to be an iNES file");
+
+            let rom = std::fs::read("../core/tests/nestest/nestest.nes").unwrap();
+            assert!(nes_rs_load_rom(nes, rom.as_ptr(), rom.len()));
+            assert!(nes_rs_set_button(nes, 0, NesRsButton::Start, true));
+            assert!(!nes_rs_set_button(nes, 2, NesRsButton::A, true));
+            for _ in 0..10 {
+                assert!(nes_rs_run_frame(nes));
+            }
+            let pixels = std::slice::from_raw_parts(nes_rs_framebuffer(nes), (NES_RS_WIDTH * NES_RS_HEIGHT * 4) as usize);
+            assert!(pixels.chunks_exact(4).any(|pixel| pixel != &pixels[..4]));
+
+            let mut state = vec![0; nes_rs_state_size(nes)];
+            assert_eq!(nes_rs_save_state(nes, state.as_mut_ptr(), 16), 0);
+            assert_eq!(nes_rs_save_state(nes, state.as_mut_ptr(), state.len()), state.len());
+            let saved = pixels.to_vec();
+            for _ in 0..30 {
+                nes_rs_run_frame(nes);
+            }
+            assert!(nes_rs_load_state(nes, state.as_ptr(), state.len()));
+            let pixels = std::slice::from_raw_parts(nes_rs_framebuffer(nes), saved.len());
+            assert_eq!(pixels, &saved[..]);
+            assert!(!nes_rs_load_state(nes, state.as_ptr(), 8));
+
+            nes_rs_destroy(nes);
+        }
+    }
+}
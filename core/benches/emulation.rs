@@ -0,0 +1,46 @@
+//! Emulation speed: instructions per second for the CPU loop, frames per second for the whole
+//! console running nestest's menu, and frames per second through each filter preset. Run with `cargo bench`; criterion compares each run with the
+//! last, so run it before and after a change.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use nes_rs_core::bench;
+use nes_rs_core::cartridge::Cartridge;
+use nes_rs_core::emulator::Emulator;
+use nes_rs_core::render::filters::{FilterPreset, Picture};
+
+const INSTRUCTIONS: u64 = 10_000;
+
+fn cpu(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    let mut cpu = bench::cpu();
+    group.bench_function("instructions", |b| b.iter(|| bench::run_instructions(&mut cpu, INSTRUCTIONS)));
+    group.finish();
+}
+
+fn console(c: &mut Criterion) {
+    let bytes = std::fs::read("tests/nestest/nestest.nes").unwrap();
+    let mut emulator = Emulator::new(Cartridge::new(&bytes).unwrap());
+    let mut group = c.benchmark_group("console");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("frames", |b| b.iter(|| emulator.run_frame()));
+    group.finish();
+}
+
+fn filters(c: &mut Criterion) {
+    let bytes = std::fs::read("tests/nestest/nestest.nes").unwrap();
+    let mut emulator = Emulator::new(Cartridge::new(&bytes).unwrap());
+    emulator.run_frame();
+    let picture = Picture::from_frame(&emulator.frame);
+    let mut group = c.benchmark_group("filters");
+    group.throughput(Throughput::Elements(1));
+    for preset in FilterPreset::ALL {
+        let mut chain = preset.chain();
+        group.bench_function(preset.name(), |b| b.iter(|| chain.apply(&picture).pixels.len()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, cpu, console, filters);
+criterion_main!(benches);
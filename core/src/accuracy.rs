@@ -0,0 +1,112 @@
+//! Accuracy profiles, which switch groups of timing details on or off together. `Fast` skips
+//! details that cost time and that most games never notice; `Accurate` emulates them, for games
+//! and test ROMs that do. Each detail has its own switch in `Accuracy`, which subsystems check
+//! where they have a cheaper path.
+//!
+//! Both profiles draw the picture a frame at a time, as there is no dot-by-dot PPU to switch to
+//! yet, and there is no APU; new switches belong here as those are written.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccuracyProfile {
+    #[default]
+    Fast,
+    Accurate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Accuracy {
+    // The CPU's extra bus accesses: an indexed access that crosses a page reads from the wrong page
+    // first, and read-modify-write instructions write the value they read back before the new one.
+    // Registers that react to being read or written, like $2002 and $2007, see them.
+    pub dummy_accesses: bool,
+    // OAM DMA ($4014) stalls the CPU for the 513 or 514 cycles the copy takes, instead of none.
+    pub dma_timing: bool,
+}
+
+impl AccuracyProfile {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccuracyProfile::Fast => "fast",
+            AccuracyProfile::Accurate => "accurate",
+        }
+    }
+
+    pub fn settings(&self) -> Accuracy {
+        let accurate = *self == AccuracyProfile::Accurate;
+        Accuracy {
+            dummy_accesses: accurate,
+            dma_timing: accurate,
+        }
+    }
+}
+
+impl Default for Accuracy {
+    fn default() -> Self {
+        AccuracyProfile::default().settings()
+    }
+}
+
+impl FromStr for AccuracyProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "fast" => Ok(AccuracyProfile::Fast),
+            "accurate" => Ok(AccuracyProfile::Accurate),
+            _ => Err(format!("Unknown accuracy profile {} (expected fast or accurate)", s)),
+        }
+    }
+}
+
+impl fmt::Display for AccuracyProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::cpu::{Mem, CPU};
+    use crate::debugger::watch::AccessKind;
+
+    // Runs LDA $02FF,X with X = 1, then INC $10, and returns the data accesses they made along
+    // with the cycles an OAM DMA took.
+    fn run(profile: AccuracyProfile) -> (Vec<(u16, u8, AccessKind)>, usize) {
+        let mut cpu = CPU::new(Bus::new(create_test_cartridge()));
+        cpu.bus.accuracy = profile.settings();
+        cpu.load(vec![0xbd, 0xff, 0x02, 0xe6, 0x10]);
+        cpu.program_counter = 0x0600;
+        cpu.register_x = 1;
+        cpu.mem_write(0x10, 5);
+        cpu.bus.access_log = Some(Vec::new());
+        cpu.execute_instruction();
+        cpu.execute_instruction();
+        let log = cpu.bus.access_log.take().unwrap();
+        let accesses = log.into_iter().filter(|(addr, _, _)| *addr < 0x0600).collect();
+
+        let cycles = cpu.bus.cycles;
+        cpu.mem_write(0x4014, 0x02);
+        (accesses, cpu.bus.cycles - cycles)
+    }
+
+    #[test]
+    fn test_accurate_profile_adds_dummy_accesses_and_dma_time() {
+        use AccessKind::*;
+        let (accesses, dma_cycles) = run(AccuracyProfile::Fast);
+        assert_eq!(accesses, [(0x0300, 0, Read), (0x0010, 5, Read), (0x0010, 6, Write)]);
+        assert_eq!(dma_cycles, 0);
+
+        let (accesses, dma_cycles) = run(AccuracyProfile::Accurate);
+        assert_eq!(
+            accesses,
+            [(0x0200, 0, Read), (0x0300, 0, Read), (0x0010, 5, Read), (0x0010, 5, Write), (0x0010, 6, Write)]
+        );
+        assert!(dma_cycles == 513 || dma_cycles == 514);
+    }
+}
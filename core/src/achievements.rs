@@ -0,0 +1,407 @@
+//! RetroAchievements, through the rcheevos client library (the `achievements` cargo feature).
+//!
+//! rcheevos does the work: it logs in, identifies the game by its hash, downloads its
+//! achievements and leaderboards, and checks their conditions against memory after every frame.
+//! It never touches the network itself. Each call it wants made to the server is queued as a
+//! `Request`, and the frontend sends it however it likes and hands the reply back to `respond`.
+//! What happens (logins, unlocks, leaderboard attempts) comes back as `Event`s.
+//!
+//! Hardcore mode is enforced here rather than by the frontend: while it is on and a game is
+//! loaded, `do_frame` keeps the emulator in hardcore mode (see `Emulator::set_hardcore`), and
+//! turning it on resets the console, as rcheevos asks.
+//!
+//! rcheevos calls back into us from inside its own functions, so all our state lives behind a raw
+//! pointer it passes back (its "userdata") and is only borrowed for the length of a callback.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ptr;
+
+use rcheevos_sys::*;
+
+use crate::emulator::Emulator;
+
+// A call to the RetroAchievements server, to be POSTed (or fetched, if there is no body) by the
+// frontend, which passes the reply to `Achievements::respond` with the same `id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub id: u64,
+    pub url: String,
+    pub post_data: Option<String>,
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    // The user's display name, and a token to log in with next time instead of the password.
+    LoggedIn { name: String, token: String },
+    LoginFailed(String),
+    // The game was identified and its achievements and leaderboards are active.
+    GameLoaded { title: String },
+    GameLoadFailed(String),
+    Unlocked { title: String, description: String, points: u32 },
+    LeaderboardStarted(String),
+    LeaderboardFailed(String),
+    LeaderboardSubmitted { title: String, score: String },
+    // A leaderboard's value as it is being played for, to show on screen until hidden.
+    TrackerShown { id: u32, display: String },
+    TrackerHidden(u32),
+    GameCompleted,
+    ServerError(String),
+    // Unlocks couldn't be sent and are being retried, and later were.
+    Disconnected,
+    Reconnected,
+}
+
+pub struct Achievements {
+    client: *mut rc_client_t,
+    state: *mut State,
+}
+
+struct State {
+    // The console being checked, only while `do_frame` runs.
+    emulator: *const Emulator,
+    next_id: u64,
+    requests: Vec<Request>,
+    waiting: HashMap<u64, (rc_client_server_callback_t, *mut c_void)>,
+    events: Vec<Event>,
+    // rcheevos asked for the console to be reset, which `do_frame` does.
+    reset: bool,
+}
+
+impl Achievements {
+    pub fn new() -> Self {
+        let state = Box::into_raw(Box::new(State {
+            emulator: ptr::null(),
+            next_id: 0,
+            requests: Vec::new(),
+            waiting: HashMap::new(),
+            events: Vec::new(),
+            reset: false,
+        }));
+        unsafe {
+            let client = rc_client_create(Some(read_memory), Some(server_call));
+            rc_client_set_userdata(client, state as *mut c_void);
+            rc_client_set_event_handler(client, Some(event_handler));
+            Achievements { client, state }
+        }
+    }
+
+    pub fn login_with_password(&mut self, user: &str, password: &str) {
+        let (user, password) = (c_string(user), c_string(password));
+        unsafe {
+            rc_client_begin_login_with_password(self.client, user.as_ptr(), password.as_ptr(), Some(logged_in), ptr::null_mut());
+        }
+    }
+
+    pub fn login_with_token(&mut self, user: &str, token: &str) {
+        let (user, token) = (c_string(user), c_string(token));
+        unsafe {
+            rc_client_begin_login_with_token(self.client, user.as_ptr(), token.as_ptr(), Some(logged_in), ptr::null_mut());
+        }
+    }
+
+    // Identifies the game from its iNES file and starts loading its achievements. Replaces the
+    // game loaded before, if any. Should follow logging in, but needn't wait for it.
+    pub fn load_game(&mut self, rom: &[u8]) {
+        unsafe {
+            rc_client_unload_game(self.client);
+            rc_client_begin_identify_and_load_game(
+                self.client,
+                RC_CONSOLE_NINTENDO,
+                ptr::null(),
+                rom.as_ptr(),
+                rom.len(),
+                Some(game_loaded),
+                ptr::null_mut(),
+            );
+        }
+    }
+
+    pub fn is_game_loaded(&self) -> bool {
+        unsafe { rc_client_is_game_loaded(self.client) != 0 }
+    }
+
+    // Hardcore mode earns achievements that count for more, in exchange for no save states or
+    // cheats. Turning it on with a game loaded resets the console on the next `do_frame`.
+    pub fn set_hardcore(&mut self, hardcore: bool) {
+        unsafe { rc_client_set_hardcore_enabled(self.client, hardcore as c_int) }
+    }
+
+    pub fn is_hardcore(&self) -> bool {
+        unsafe { rc_client_get_hardcore_enabled(self.client) != 0 }
+    }
+
+    // The part of the User-Agent header the server wants from rcheevos, e.g. "rcheevos/11.6".
+    pub fn user_agent(&self) -> String {
+        let mut buffer = [0 as c_char; 64];
+        unsafe {
+            rc_client_get_user_agent_clause(self.client, buffer.as_mut_ptr(), buffer.len());
+            CStr::from_ptr(buffer.as_ptr()).to_string_lossy().into_owned()
+        }
+    }
+
+    // Checks achievements and leaderboards against the frame `emulator` just finished. Call it
+    // once after every emulated frame.
+    pub fn do_frame(&mut self, emulator: &mut Emulator) {
+        if std::mem::take(&mut self.state().reset) {
+            emulator.power_on();
+            unsafe { rc_client_reset(self.client) };
+        }
+        emulator.set_hardcore(self.is_hardcore() && self.is_game_loaded());
+
+        self.state().emulator = emulator;
+        unsafe { rc_client_do_frame(self.client) };
+        self.state().emulator = ptr::null();
+    }
+
+    // Keeps things ticking over (retrying unlocks, keeping the session alive) while no frames
+    // run, e.g. when paused.
+    pub fn idle(&mut self) {
+        unsafe { rc_client_idle(self.client) }
+    }
+
+    // Calls for the frontend to send to the server, oldest first.
+    pub fn take_requests(&mut self) -> Vec<Request> {
+        std::mem::take(&mut self.state().requests)
+    }
+
+    // Hands back the server's reply to a request: its HTTP status, or 0 if it couldn't be sent at
+    // all, and its body.
+    pub fn respond(&mut self, id: u64, status: u16, body: &[u8]) {
+        let Some((callback, data)) = self.state().waiting.remove(&id) else {
+            return;
+        };
+        let response = rc_api_server_response_t {
+            body: body.as_ptr() as *const c_char,
+            body_length: body.len(),
+            http_status_code: match status {
+                0 => RC_API_SERVER_RESPONSE_RETRYABLE_CLIENT_ERROR as c_int,
+                status => status as c_int,
+            },
+        };
+        if let Some(callback) = callback {
+            unsafe { callback(&response, data) };
+        }
+    }
+
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.state().events)
+    }
+
+    fn state(&mut self) -> &mut State {
+        unsafe { &mut *self.state }
+    }
+}
+
+impl Default for Achievements {
+    fn default() -> Self {
+        Achievements::new()
+    }
+}
+
+impl Drop for Achievements {
+    fn drop(&mut self) {
+        unsafe {
+            rc_client_destroy(self.client);
+            drop(Box::from_raw(self.state));
+        }
+    }
+}
+
+fn c_string(text: &str) -> CString {
+    CString::new(text.replace('\0', "")).unwrap()
+}
+
+unsafe fn string(text: *const c_char) -> String {
+    match text.is_null() {
+        true => String::new(),
+        false => CStr::from_ptr(text).to_string_lossy().into_owned(),
+    }
+}
+
+unsafe fn state<'a>(client: *const rc_client_t) -> &'a mut State {
+    &mut *(rc_client_get_userdata(client) as *mut State)
+}
+
+// Every NES address exists, so reads outside a frame (rcheevos checks addresses when a game loads)
+// see zeros rather than failing, which would disable the achievements using them.
+unsafe extern "C" fn read_memory(address: u32, buffer: *mut u8, num_bytes: u32, client: *mut rc_client_t) -> u32 {
+    let emulator = state(client).emulator.as_ref();
+    let mut read = 0;
+    while read < num_bytes {
+        let Ok(addr) = u16::try_from(address + read) else {
+            break;
+        };
+        *buffer.add(read as usize) = emulator.map_or(0, |emulator| emulator.cpu.bus.peek(addr));
+        read += 1;
+    }
+    read
+}
+
+unsafe extern "C" fn server_call(
+    request: *const rc_api_request_t,
+    callback: rc_client_server_callback_t,
+    callback_data: *mut c_void,
+    client: *mut rc_client_t,
+) {
+    let state = state(client);
+    let id = state.next_id;
+    state.next_id += 1;
+    let optional = |text: *const c_char| (!text.is_null()).then(|| string(text));
+    state.requests.push(Request {
+        id,
+        url: string((*request).url),
+        post_data: optional((*request).post_data),
+        content_type: optional((*request).content_type),
+    });
+    state.waiting.insert(id, (callback, callback_data));
+}
+
+unsafe extern "C" fn logged_in(result: c_int, error_message: *const c_char, client: *mut rc_client_t, _: *mut c_void) {
+    let user = rc_client_get_user_info(client);
+    let event = match (result == RC_OK as c_int, user.as_ref()) {
+        (true, Some(user)) => Event::LoggedIn {
+            name: string(user.display_name),
+            token: string(user.token),
+        },
+        _ => Event::LoginFailed(string(error_message)),
+    };
+    state(client).events.push(event);
+}
+
+unsafe extern "C" fn game_loaded(result: c_int, error_message: *const c_char, client: *mut rc_client_t, _: *mut c_void) {
+    let game = rc_client_get_game_info(client);
+    let event = match (result == RC_OK as c_int, game.as_ref()) {
+        (true, Some(game)) => Event::GameLoaded { title: string(game.title) },
+        _ => Event::GameLoadFailed(string(error_message)),
+    };
+    let state = state(client);
+    state.events.push(event);
+    // The game has been running while it loaded, maybe with states loaded or cheats on.
+    if result == RC_OK as c_int && rc_client_get_hardcore_enabled(client) != 0 {
+        state.reset = true;
+    }
+}
+
+unsafe extern "C" fn event_handler(event: *const rc_client_event_t, client: *mut rc_client_t) {
+    let event = &*event;
+    let achievement = event.achievement.as_ref();
+    let leaderboard = || event.leaderboard.as_ref().map(|leaderboard| string(leaderboard.title)).unwrap_or_default();
+    let tracker = event.leaderboard_tracker.as_ref();
+    let event = match event.type_ {
+        RC_CLIENT_EVENT_ACHIEVEMENT_TRIGGERED => match achievement {
+            Some(achievement) => Event::Unlocked {
+                title: string(achievement.title),
+                description: string(achievement.description),
+                points: achievement.points,
+            },
+            None => return,
+        },
+        RC_CLIENT_EVENT_LEADERBOARD_STARTED => Event::LeaderboardStarted(leaderboard()),
+        RC_CLIENT_EVENT_LEADERBOARD_FAILED => Event::LeaderboardFailed(leaderboard()),
+        RC_CLIENT_EVENT_LEADERBOARD_SUBMITTED => Event::LeaderboardSubmitted {
+            title: leaderboard(),
+            score: event.leaderboard.as_ref().map(|leaderboard| string(leaderboard.tracker_value)).unwrap_or_default(),
+        },
+        RC_CLIENT_EVENT_LEADERBOARD_TRACKER_SHOW | RC_CLIENT_EVENT_LEADERBOARD_TRACKER_UPDATE => match tracker {
+            Some(tracker) => Event::TrackerShown {
+                id: tracker.id,
+                display: string(tracker.display.as_ptr()),
+            },
+            None => return,
+        },
+        RC_CLIENT_EVENT_LEADERBOARD_TRACKER_HIDE => match tracker {
+            Some(tracker) => Event::TrackerHidden(tracker.id),
+            None => return,
+        },
+        RC_CLIENT_EVENT_RESET => {
+            state(client).reset = true;
+            return;
+        }
+        RC_CLIENT_EVENT_GAME_COMPLETED => Event::GameCompleted,
+        RC_CLIENT_EVENT_SERVER_ERROR => match event.server_error.as_ref() {
+            Some(error) => Event::ServerError(format!("{}: {}", string(error.api), string(error.error_message))),
+            None => return,
+        },
+        RC_CLIENT_EVENT_DISCONNECTED => Event::Disconnected,
+        RC_CLIENT_EVENT_RECONNECTED => Event::Reconnected,
+        // Challenge and progress indicators, and scoreboards.
+        _ => return,
+    };
+    state(client).events.push(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    // Answers each request like the server would, from the replies in rcheevos' own tests.
+    fn serve(achievements: &mut Achievements) -> Vec<String> {
+        let mut calls = Vec::new();
+        while let Some(request) = achievements.take_requests().into_iter().next() {
+            let call = request.post_data.clone().unwrap_or_default();
+            let body = match call.split('&').next().unwrap_or_default() {
+                "r=login2" => r#"{"Success":true,"User":"User","DisplayName":"Player","Token":"ApiToken","Score":10}"#,
+                "r=gameid" => r#"{"Success":true,"GameID":1234}"#,
+                "r=patch" => concat!(
+                    r#"{"Success":true,"PatchData":{"ID":1234,"Title":"nestest","ConsoleID":7,"ImageIcon":"/Images/1.png","#,
+                    r#""Achievements":[{"ID":5501,"Title":"Poked","Description":"Set $0700 to 3","Flags":3,"Points":5,"#,
+                    r#""MemAddr":"0xH0700=3","Author":"User","BadgeName":"00001","Created":1,"Modified":1}],"#,
+                    r#""Leaderboards":[]}}"#
+                ),
+                "r=startsession" => r#"{"Success":true,"Unlocks":[],"HardcoreUnlocks":[]}"#,
+                _ => r#"{"Success":true}"#,
+            };
+            achievements.respond(request.id, 200, body.as_bytes());
+            calls.push(call);
+        }
+        calls
+    }
+
+    #[test]
+    fn test_unlocks_in_hardcore() {
+        let rom = std::fs::read("tests/nestest/nestest.nes").unwrap();
+        let mut emulator = Emulator::new(Cartridge::new(&rom).unwrap());
+        let mut achievements = Achievements::new();
+        achievements.set_hardcore(true);
+        achievements.login_with_password("User", "password");
+        serve(&mut achievements);
+        assert_eq!(
+            achievements.take_events(),
+            [Event::LoggedIn { name: "Player".to_string(), token: "ApiToken".to_string() }]
+        );
+
+        achievements.load_game(&rom);
+        serve(&mut achievements);
+        assert!(achievements.is_game_loaded());
+        assert_eq!(achievements.take_events(), [Event::GameLoaded { title: "nestest".to_string() }]);
+
+        // Loading in hardcore resets the console, and from then on states are off.
+        let state = emulator.save_state();
+        emulator.run_frame();
+        achievements.do_frame(&mut emulator);
+        assert!(emulator.is_hardcore());
+        assert_eq!(emulator.frame_count(), 0);
+        assert!(emulator.load_state(&state).is_err());
+
+        // An achievement has to see its condition false before it can trigger.
+        for _ in 0..2 {
+            emulator.run_frame();
+            achievements.do_frame(&mut emulator);
+        }
+        emulator.cpu.bus.poke(0x0700, 3);
+        achievements.do_frame(&mut emulator);
+        assert_eq!(
+            achievements.take_events(),
+            [Event::Unlocked { title: "Poked".to_string(), description: "Set $0700 to 3".to_string(), points: 5 }]
+        );
+        assert!(serve(&mut achievements).iter().any(|call| call.starts_with("r=awardachievement")));
+
+        achievements.set_hardcore(false);
+        achievements.do_frame(&mut emulator);
+        assert!(!emulator.is_hardcore());
+        assert!(emulator.load_state(&state).is_ok());
+    }
+}
@@ -0,0 +1,438 @@
+//! A small 6502 assembler, the inverse of `disasm`: for writing test programs as source instead of
+//! hex, and for patching code from the debugger.
+//!
+//! It takes one instruction per line in the usual syntax (`LDA #$05`, `STA $0200,X`, `JMP ($FFFC)`,
+//! `ASL A`), with `;` comments. Numbers are `$hex`, `%binary` or decimal, and `<`/`>` take the
+//! low/high byte. Labels are `name:`, constants `name = value`. Branches take a label or address
+//! and are encoded relative. Directives: `.org addr`, `.db`/`.byte` and `.dw`/`.word`. Prefix a
+//! mnemonic with `*` to get the unofficial opcode, as `disasm` writes them.
+//!
+//! An operand is assembled to zero page when it is a number (or constant) below $100 and the
+//! instruction has a zero page form; labels are always absolute, so a program's size never
+//! depends on where its labels end up.
+//!
+//! Reference: <https://www.nesdev.org/obelisk-6502-guide/reference.html>
+
+use std::collections::{HashMap, HashSet};
+
+use crate::bus::Bus;
+use crate::cpu::addressing::AddressingMode;
+use crate::cpu::opcodes::{OpCode, CPU_OPS_CODES, UNOFFICIAL_OPCODES};
+use crate::cpu::operations::Operation;
+use crate::disasm::is_branch;
+
+// Where programs go unless they say otherwise with `.org`. Tests load programs here.
+pub const DEFAULT_ORIGIN: u16 = 0x0600;
+
+// Assembles `source` as a test program, panicking on errors: `asm!("LDA #$05\nTAX\nBRK")`.
+#[macro_export]
+macro_rules! asm {
+    ($source:expr) => {
+        $crate::asm::assemble($source).unwrap_or_else(|e| panic!("{}", e))
+    };
+}
+
+// Assembles `source` with the code starting at `DEFAULT_ORIGIN`, or at its first `.org`.
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    assemble_at(source, DEFAULT_ORIGIN)
+}
+
+// Assembles `source` as if loaded at `origin`. A first `.org` before any code moves the origin; a
+// later one skips ahead, filling the gap with zeros.
+pub fn assemble_at(source: &str, origin: u16) -> Result<Vec<u8>, String> {
+    let lines = parse(source)?;
+    let labels: HashSet<&str> = lines.iter().filter_map(|line| line.label.as_deref()).collect();
+
+    // First pass: place the labels. Sizes don't depend on label values, so one pass is enough.
+    let mut symbols = HashMap::new();
+    let mut pc = origin as u32;
+    for line in &lines {
+        if let Some(label) = &line.label {
+            if symbols.insert(label.clone(), pc as u16).is_some() {
+                return Err(line.error(&format!("{} is defined twice", label)));
+            }
+        }
+        match &line.statement {
+            Statement::Constant(name, value) => {
+                let value = evaluate(value, &symbols).map_err(|e| line.error(&e))?;
+                symbols.insert(name.clone(), value);
+            }
+            statement => pc = line.advance(statement.size(&labels, &symbols), pc)?,
+        }
+        if pc > 0x10000 {
+            return Err(line.error("Past the end of memory"));
+        }
+    }
+
+    // Second pass: emit the bytes.
+    let mut bytes = Vec::new();
+    let mut pc = origin as u32;
+    for line in &lines {
+        let statement = &line.statement;
+        if let Statement::Org(_) = statement {
+            let next = line.advance(statement.size(&labels, &symbols), pc)?;
+            if !bytes.is_empty() {
+                bytes.resize(bytes.len() + (next - pc) as usize, 0);
+            }
+            pc = next;
+            continue;
+        }
+        let encoded = statement.encode(pc as u16, &labels, &symbols).map_err(|e| line.error(&e))?;
+        pc += encoded.len() as u32;
+        bytes.extend(encoded);
+    }
+    Ok(bytes)
+}
+
+// Assembles `source` at `addr` and writes it over whatever is there, ROM included, the way
+// `Bus::poke` does. Returns the number of bytes written.
+pub fn patch(bus: &mut Bus, addr: u16, source: &str) -> Result<usize, String> {
+    let bytes = assemble_at(source, addr)?;
+    for (i, byte) in bytes.iter().enumerate() {
+        bus.poke(addr.wrapping_add(i as u16), *byte);
+    }
+    Ok(bytes.len())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Implied,
+    Accumulator,
+    Immediate(String),
+    // An address, optionally indexed by 'X' or 'Y'.
+    Direct(String, Option<char>),
+    // "(addr)", "(addr,X)" or "(addr),Y".
+    Indirect(String, Option<char>),
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Empty,
+    Instruction { op: Operation, unofficial: bool, operand: Operand },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Org(String),
+    Constant(String, String),
+}
+
+// How a statement moves the program counter.
+enum Size {
+    Bytes(usize),
+    To(String),
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    number: usize,
+    label: Option<String>,
+    statement: Statement,
+}
+
+impl Line {
+    fn error(&self, message: &str) -> String {
+        format!("Line {}: {}", self.number, message)
+    }
+
+    fn advance(&self, size: Result<Size, String>, pc: u32) -> Result<u32, String> {
+        match size.map_err(|e| self.error(&e))? {
+            Size::Bytes(n) => Ok(pc + n as u32),
+            Size::To(addr) => match evaluate(&addr, &HashMap::new()).map_err(|e| self.error(&e))? as u32 {
+                addr if addr < pc => Err(self.error(".org can't move backwards")),
+                addr => Ok(addr),
+            },
+        }
+    }
+}
+
+fn parse(source: &str) -> Result<Vec<Line>, String> {
+    let mut lines = Vec::new();
+    for (i, text) in source.lines().enumerate() {
+        let number = i + 1;
+        let mut text = text.split(';').next().unwrap_or_default().trim();
+        let mut label = None;
+        if let Some((name, rest)) = text.split_once(':') {
+            if is_identifier(name.trim()) {
+                label = Some(name.trim().to_string());
+                text = rest.trim();
+            }
+        }
+        let statement = parse_statement(text).map_err(|e| format!("Line {}: {}", number, e))?;
+        lines.push(Line { number, label, statement });
+    }
+    Ok(lines)
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '@')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_statement(text: &str) -> Result<Statement, String> {
+    if text.is_empty() {
+        return Ok(Statement::Empty);
+    }
+    if let Some((name, value)) = text.split_once('=') {
+        if is_identifier(name.trim()) {
+            return Ok(Statement::Constant(name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let (word, rest) = match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    };
+    let list = || rest.split(',').map(|item| item.trim().to_string()).collect();
+    match word.to_ascii_lowercase().as_str() {
+        ".org" => return Ok(Statement::Org(rest.to_string())),
+        ".db" | ".byte" => return Ok(Statement::Bytes(list())),
+        ".dw" | ".word" => return Ok(Statement::Words(list())),
+        directive if directive.starts_with('.') => return Err(format!("Unknown directive {}", word)),
+        _ => {}
+    }
+
+    let (mnemonic, unofficial) = match word.strip_prefix('*') {
+        Some(mnemonic) => (mnemonic.to_ascii_uppercase(), true),
+        None => (word.to_ascii_uppercase(), false),
+    };
+    let op = CPU_OPS_CODES
+        .iter()
+        .find(|opcode| opcode.op.to_string() == mnemonic)
+        .map(|opcode| opcode.op)
+        .ok_or_else(|| format!("Unknown instruction {}", word))?;
+    Ok(Statement::Instruction { op, unofficial, operand: parse_operand(rest)? })
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let upper = text.to_ascii_uppercase();
+    if text.is_empty() {
+        Ok(Operand::Implied)
+    } else if upper == "A" {
+        Ok(Operand::Accumulator)
+    } else if let Some(value) = text.strip_prefix('#') {
+        Ok(Operand::Immediate(value.to_string()))
+    } else if !text.starts_with('(') {
+        match upper.char_indices().nth_back(1) {
+            Some((i, ',')) if upper.ends_with(['X', 'Y']) => Ok(Operand::Direct(text[..i].to_string(), upper.chars().last())),
+            _ => Ok(Operand::Direct(text, None)),
+        }
+    } else if upper.ends_with(",X)") {
+        Ok(Operand::Indirect(text[1..text.len() - 3].to_string(), Some('X')))
+    } else if upper.ends_with("),Y") {
+        Ok(Operand::Indirect(text[1..text.len() - 3].to_string(), Some('Y')))
+    } else if text.ends_with(')') {
+        Ok(Operand::Indirect(text[1..text.len() - 1].to_string(), None))
+    } else {
+        Err(format!("Unbalanced parentheses in {}", text))
+    }
+}
+
+// Evaluates a number, label or constant, optionally with `<` or `>` in front and `+n` or `-n`
+// after.
+fn evaluate(expr: &str, symbols: &HashMap<String, u16>) -> Result<u16, String> {
+    let expr = expr.trim();
+    if let Some(rest) = expr.strip_prefix('<') {
+        return Ok(evaluate(rest, symbols)? & 0xff);
+    }
+    if let Some(rest) = expr.strip_prefix('>') {
+        return Ok(evaluate(rest, symbols)? >> 8);
+    }
+    if let Some(i) = expr.rfind(['+', '-']).filter(|i| *i > 0) {
+        let (base, offset) = (evaluate(&expr[..i], symbols)?, evaluate(&expr[i + 1..], symbols)?);
+        return Ok(match &expr[i..=i] {
+            "+" => base.wrapping_add(offset),
+            _ => base.wrapping_sub(offset),
+        });
+    }
+    let number = |digits: &str, radix| {
+        u16::from_str_radix(digits, radix).map_err(|_| format!("Invalid number {}", expr))
+    };
+    if let Some(hex) = expr.strip_prefix('$') {
+        number(hex, 16)
+    } else if let Some(binary) = expr.strip_prefix('%') {
+        number(binary, 2)
+    } else if expr.starts_with(|c: char| c.is_ascii_digit()) {
+        number(expr, 10)
+    } else {
+        symbols.get(expr).copied().ok_or_else(|| format!("Unknown label {}", expr))
+    }
+}
+
+// Whether an operand goes in zero page: it takes a byte with `<` or `>`, or it is a number or
+// constant below $100 that doesn't involve a label.
+fn is_zero_page(expr: &str, labels: &HashSet<&str>, symbols: &HashMap<String, u16>) -> bool {
+    if expr.starts_with(['<', '>']) {
+        return true;
+    }
+    let uses_label = expr.split(['+', '-']).any(|term| labels.contains(term.trim()));
+    !uses_label && matches!(evaluate(expr, symbols), Ok(value) if value < 0x100)
+}
+
+// The opcode for `op` in `mode`, preferring an official or unofficial one as asked.
+fn find_opcode(op: Operation, mode: AddressingMode, bytes: u8, unofficial: bool) -> Option<&'static OpCode> {
+    let mut candidates = CPU_OPS_CODES
+        .iter()
+        .filter(|opcode| opcode.op == op && opcode.addressing_mode == mode && opcode.bytes == bytes);
+    let first = candidates.clone().next();
+    candidates
+        .find(|opcode| UNOFFICIAL_OPCODES.contains(&opcode.code) == unofficial)
+        .or(first)
+}
+
+// The opcode for an instruction, and the expression for its operand, if any.
+fn resolve<'a>(
+    op: Operation,
+    unofficial: bool,
+    operand: &'a Operand,
+    labels: &HashSet<&str>,
+    symbols: &HashMap<String, u16>,
+) -> Result<(&'static OpCode, Option<&'a str>), String> {
+    let find = |mode, bytes| find_opcode(op, mode, bytes, unofficial);
+    let direct = |expr: &str, zero_page, absolute| {
+        if is_zero_page(expr, labels, symbols) {
+            find(zero_page, 2).or_else(|| find(absolute, 3))
+        } else {
+            find(absolute, 3)
+        }
+    };
+
+    let (opcode, expr) = match operand {
+        Operand::Implied | Operand::Accumulator => (find(AddressingMode::NoneAddressing, 1), None),
+        Operand::Immediate(expr) => (find(AddressingMode::Immediate, 2), Some(expr)),
+        // Branches and JSR are listed without an addressing mode.
+        Operand::Direct(expr, None) if is_branch(op) => (find(AddressingMode::NoneAddressing, 2), Some(expr)),
+        Operand::Direct(expr, None) if op == Operation::JSR => (find(AddressingMode::NoneAddressing, 3), Some(expr)),
+        Operand::Direct(expr, None) => (direct(expr, AddressingMode::ZeroPage, AddressingMode::Absolute), Some(expr)),
+        Operand::Direct(expr, Some('X')) => {
+            (direct(expr, AddressingMode::ZeroPage_X, AddressingMode::Absolute_X), Some(expr))
+        }
+        Operand::Direct(expr, _) => (direct(expr, AddressingMode::ZeroPage_Y, AddressingMode::Absolute_Y), Some(expr)),
+        Operand::Indirect(expr, None) => (find(AddressingMode::Indirect, 3), Some(expr)),
+        Operand::Indirect(expr, Some('X')) => (find(AddressingMode::Indirect_X, 2), Some(expr)),
+        Operand::Indirect(expr, _) => (find(AddressingMode::Indirect_Y, 2), Some(expr)),
+    };
+    let opcode = opcode.ok_or_else(|| format!("{} doesn't take that operand", op))?;
+    Ok((opcode, expr.map(String::as_str)))
+}
+
+impl Statement {
+    fn size(&self, labels: &HashSet<&str>, symbols: &HashMap<String, u16>) -> Result<Size, String> {
+        Ok(match self {
+            Statement::Instruction { op, unofficial, operand } => {
+                Size::Bytes(resolve(*op, *unofficial, operand, labels, symbols)?.0.bytes as usize)
+            }
+            Statement::Bytes(items) => Size::Bytes(items.len()),
+            Statement::Words(items) => Size::Bytes(2 * items.len()),
+            Statement::Org(addr) => Size::To(addr.clone()),
+            Statement::Empty | Statement::Constant(..) => Size::Bytes(0),
+        })
+    }
+
+    fn encode(&self, pc: u16, labels: &HashSet<&str>, symbols: &HashMap<String, u16>) -> Result<Vec<u8>, String> {
+        let byte = |expr: &str| match evaluate(expr, symbols)? {
+            value if value > 0xff => Err(format!("{} doesn't fit in a byte", expr)),
+            value => Ok(value as u8),
+        };
+        match self {
+            Statement::Instruction { op, unofficial, operand } => {
+                let (opcode, expr) = resolve(*op, *unofficial, operand, labels, symbols)?;
+                let Some(expr) = expr else {
+                    return Ok(vec![opcode.code]);
+                };
+                if is_branch(*op) {
+                    let target = evaluate(expr, symbols)?;
+                    let offset = target as i32 - (pc as i32 + 2);
+                    if !(-128..=127).contains(&offset) {
+                        return Err(format!("Branch to ${:04X} is out of range", target));
+                    }
+                    return Ok(vec![opcode.code, offset as i8 as u8]);
+                }
+                match opcode.bytes {
+                    2 => Ok(vec![opcode.code, byte(expr)?]),
+                    _ => {
+                        let [low, high] = evaluate(expr, symbols)?.to_le_bytes();
+                        Ok(vec![opcode.code, low, high])
+                    }
+                }
+            }
+            Statement::Bytes(items) => items.iter().map(|item| byte(item)).collect(),
+            Statement::Words(items) => {
+                let words = items.iter().map(|item| evaluate(item, symbols)).collect::<Result<Vec<u16>, String>>()?;
+                Ok(words.into_iter().flat_map(u16::to_le_bytes).collect())
+            }
+            Statement::Empty | Statement::Org(_) | Statement::Constant(..) => Ok(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::disasm::disassemble_bytes;
+
+    fn round_trip(source: &str) -> Vec<String> {
+        disassemble_bytes(&assemble(source).unwrap(), DEFAULT_ORIGIN).iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn test_addressing_modes() {
+        let source = "LDA #$01\nLDA $44\nLDA $44,X\nLDX $44,Y\nLDA $0200\nLDA $0200,X\nLDA $0200,Y\n\
+            JMP ($FFFC)\nLDA ($44,X)\nLDA ($44),Y\nASL A\nINX";
+        assert_eq!(
+            round_trip(source),
+            vec![
+                "LDA #$01", "LDA $44", "LDA $44,X", "LDX $44,Y", "LDA $0200", "LDA $0200,X", "LDA $0200,Y",
+                "JMP ($FFFC)", "LDA ($44,X)", "LDA ($44),Y", "ASL A", "INX",
+            ]
+        );
+        // No zero page form: STA $44,Y has to be absolute.
+        assert_eq!(round_trip("STA $44,Y"), vec!["STA $0044,Y"]);
+        assert_eq!(round_trip("*NOP $44\nNOP"), vec!["*NOP $44", "NOP"]);
+    }
+
+    #[test]
+    fn test_labels_constants_and_directives() {
+        let source = "
+            count = 3
+            start:  LDX #count      ; loop three times
+            loop:   DEX
+                    BNE loop
+                    JSR sub
+                    JMP start
+            sub:    LDA #<table
+                    LDY count
+                    RTS
+            table:  .db 1, $02, %11
+                    .dw sub
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![
+                0xa2, 0x03, 0xca, 0xd0, 0xfd, 0x20, 0x0b, 0x06, 0x4c, 0x00, 0x06, 0xa9, 0x10, 0xa4, 0x03, 0x60,
+                0x01, 0x02, 0x03, 0x0b, 0x06,
+            ]
+        );
+        assert_eq!(assemble_at("here: JMP here", 0xc000).unwrap(), vec![0x4c, 0x00, 0xc0]);
+        assert_eq!(assemble(".org $8000\nNOP\n.org $8003\nNOP").unwrap(), vec![0xea, 0, 0, 0xea]);
+    }
+
+    #[test]
+    fn test_errors_name_the_line() {
+        assert_eq!(assemble("NOP\nFOO"), Err("Line 2: Unknown instruction FOO".to_string()));
+        assert!(assemble("LDA #$100").is_err());
+        assert!(assemble("BNE nowhere").is_err());
+        assert!(assemble("STX $0200,X").is_err());
+    }
+
+    #[test]
+    fn test_macro_and_patch() {
+        assert_eq!(crate::asm!("LDA #$05\nTAX\nBRK"), vec![0xa9, 0x05, 0xaa, 0x00]);
+
+        let mut bus = Bus::new(create_test_cartridge());
+        assert_eq!(patch(&mut bus, 0xc000, "JMP $C000"), Ok(3));
+        assert_eq!(bus.peek(0xc001), 0x00);
+        assert_eq!(bus.peek(0xc002), 0xc0);
+    }
+}
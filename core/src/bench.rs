@@ -0,0 +1,102 @@
+//! Workloads for measuring how fast the emulator runs, shared by the criterion benchmarks in
+//! benches/ and `nes_rs bench`: the CPU alone running a loop in RAM, the whole console running a
+//! game frame by frame, and the post-processing filters.
+
+use std::time::{Duration, Instant};
+
+use crate::asm;
+use crate::bus::Bus;
+use crate::cartridge::test::create_test_cartridge;
+use crate::cpu::{Mem, CPU};
+use crate::emulator::Emulator;
+use crate::render::filters::{FilterPreset, Picture};
+
+// Adds one to every byte of a page, over and over, with a mix of loads, stores, arithmetic and
+// branches.
+const CPU_LOOP: &str = "
+loop:
+    LDX #$00
+inner:
+    LDA $0200,X
+    CLC
+    ADC #$01
+    STA $0200,X
+    INX
+    BNE inner
+    INC $10
+    JMP loop
+";
+
+// A CPU with nothing but `CPU_LOOP` to run, from $0600.
+pub fn cpu() -> CPU {
+    let mut cpu = CPU::new(Bus::new(create_test_cartridge()));
+    for (i, byte) in asm!(CPU_LOOP).into_iter().enumerate() {
+        cpu.mem_write(0x0600 + i as u16, byte);
+    }
+    cpu.program_counter = 0x0600;
+    cpu
+}
+
+pub fn run_instructions(cpu: &mut CPU, instructions: u64) {
+    for _ in 0..instructions {
+        cpu.step_with_callback(|_| {});
+    }
+}
+
+// How much work was done in how long.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Speed {
+    pub count: u64,
+    pub elapsed: Duration,
+}
+
+impl Speed {
+    pub fn per_second(&self) -> f64 {
+        self.count as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+// Times `instructions` instructions of the CPU loop.
+pub fn measure_cpu(instructions: u64) -> Speed {
+    let mut cpu = cpu();
+    let start = Instant::now();
+    run_instructions(&mut cpu, instructions);
+    Speed { count: instructions, elapsed: start.elapsed() }
+}
+
+// Times `frames` frames of `emulator`, rendering included.
+pub fn measure_frames(emulator: &mut Emulator, frames: u64) -> Speed {
+    let start = Instant::now();
+    for _ in 0..frames {
+        emulator.run_frame();
+    }
+    Speed { count: frames, elapsed: start.elapsed() }
+}
+
+// Times `frames` runs of `preset`'s filters over the console's current picture.
+pub fn measure_filter(emulator: &Emulator, preset: FilterPreset, frames: u64) -> Speed {
+    let picture = Picture::from_frame(&emulator.frame);
+    let mut chain = preset.chain();
+    let start = Instant::now();
+    for _ in 0..frames {
+        std::hint::black_box(chain.apply(&picture));
+    }
+    Speed { count: frames, elapsed: start.elapsed() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_loop_runs() {
+        let mut cpu = cpu();
+        // Two passes over the page, with 6 instructions per byte and 3 per pass.
+        run_instructions(&mut cpu, 2 * (1 + 256 * 6 + 2));
+        assert_eq!(cpu.program_counter, 0x0600);
+        assert_eq!(cpu.mem_read(0x0200), 2);
+        assert_eq!(cpu.mem_read(0x02ff), 2);
+        assert_eq!(cpu.mem_read(0x10), 2);
+        assert_eq!(measure_cpu(100).count, 100);
+    }
+}
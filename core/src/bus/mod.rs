@@ -0,0 +1,685 @@
+//! NES Bus
+//!
+//! Reference: <http://wiki.nesdev.com/w/index.php/CPU_memory_map>
+
+use crate::accuracy::Accuracy;
+use crate::cartridge::{Cartridge, Console};
+use crate::cheat::Cheats;
+use crate::chips::{self, Chip};
+use crate::cpu::Mem;
+use crate::cpu::addressing::AddressingMode;
+#[cfg(feature = "experimental-jit")]
+use crate::cpu::block_cache::CodeWrites;
+use crate::cpu::opcodes::OPCODES_MAP;
+use crate::debugger::cdl::CodeDataLog;
+use crate::debugger::events::EventLog;
+use crate::debugger::watch::{AccessKind, WatchHit, Watchpoint};
+use crate::fcg::{self, Fcg};
+use crate::fds::Fds;
+use crate::host::SAMPLES_PER_FRAME;
+use crate::joypad::family_keyboard::FamilyKeyboard;
+use crate::joypad::four_score::FourScore;
+use crate::joypad::{Joypad, Port2Device};
+use crate::ppu::PPU;
+use crate::savestate::{StateReader, StateWriter};
+use crate::vs_system::VsSystem;
+
+/// |-----------------| $FFFF |-----------------|
+/// | PRG-ROM         |       |                 |
+/// |-----------------| $8000 |-----------------|
+/// | PRG-RAM or SRAM |       | PRG-RAM or SRAM |
+/// |-----------------| $6000 |-----------------|
+/// | Expansion       |       | Expansion       |
+/// | Modules         |       | Modules         |
+/// |-----------------| $4020 |-----------------|
+/// | APU/Input       |       |                 |
+/// | Registers       |       |                 |
+/// |- - - - - - - - -| $4000 |                 |
+/// | PPU Mirrors     |       | I/O Registers   |
+/// | $2000-$2007     |       |                 |
+/// |- - - - - - - - -| $2008 |                 |
+/// | PPU Registers   |       |                 |
+/// |-----------------| $2000 |-----------------|
+/// | WRAM Mirrors    |       |                 |
+/// | $0000-$07FF     |       |                 |
+/// |- - - - - - - - -| $0800 |                 |
+/// | WRAM            |       | 2K Internal     |
+/// |- - - - - - - - -| $0200 | Work RAM        |
+/// | Stack           |       |                 |
+/// |- - - - - - - - -| $0100 |                 |
+/// | Zero Page       |       |                 |
+/// |-----------------| $0000 |-----------------|
+//
+// Memmory map constants. Includes mirrors.
+pub const WRAM_START: u16 = 0x0000;
+pub const WRAM_END: u16 = 0x1FFF;
+pub const PPU_START: u16 = 0x2000;
+pub const PPU_MIRRORS_START: u16 = 0x2008;
+pub const PPU_MIRRORS_END: u16 = 0x3FFF;
+pub const PRG_RAM_START: u16 = 0x6000;
+pub const PRG_RAM_END: u16 = 0x7FFF;
+pub const PRG_ROM_START: u16 = 0x8000;
+pub const PRG_ROM_END: u16 = 0xFFFF;
+// What the Famicom Disk System's RAM adapter maps, when it is plugged in.
+pub const FDS_START: u16 = 0x4020;
+pub const FDS_END: u16 = 0xDFFF;
+
+pub struct Bus {
+    pub cpu_wram: [u8; WRAM_SIZE],
+    prg_ram: Vec<u8>,
+    prg_rom: Vec<u8>,
+    pub ppu: PPU,
+    pub cycles: usize,
+
+    pub joypad: Joypad,
+    pub joypad2: Joypad,
+    pub port2: Port2Device,
+    // When plugged in, the Four Score takes over both controller ports.
+    pub four_score: Option<FourScore>,
+    // The Family BASIC keyboard, on the expansion port alongside the controllers.
+    pub keyboard: Option<FamilyKeyboard>,
+    // Whether someone is talking or blowing into the Famicom's second controller, which has a
+    // microphone where its Select and Start buttons would be. It reads in $4016 bit 2.
+    pub microphone: bool,
+    // Controller port reads since the emulator last cleared it. Frames that never read input are
+    // lag frames.
+    pub input_reads: u32,
+    // Accesses matching a watchpoint are appended to `watch_hits` for the debugger to collect.
+    pub watchpoints: Vec<Watchpoint>,
+    pub watch_hits: Vec<WatchHit>,
+    // Code/data log, while one is being recorded.
+    pub cdl: Option<CodeDataLog>,
+    // Register accesses by scanline and dot, while the event viewer is open.
+    pub events: Option<EventLog>,
+    // Game Genie codes patching PRG-ROM reads, and RAM freezes.
+    pub cheats: Cheats,
+    // 64kB of plain RAM standing in for the whole memory map, for CPU tests that expect every
+    // address to be memory, like SingleStepTests. None on a console.
+    pub flat_ram: Option<Vec<u8>>,
+    // Every CPU read and write as (address, value, kind), in order, while some code wants them.
+    pub access_log: Option<Vec<(u16, u8, AccessKind)>>,
+
+    // The Famicom Disk System's RAM adapter, when a disk image is loaded. It takes over
+    // $4020-$DFFF, leaving the BIOS in PRG-ROM at $E000-$FFFF.
+    pub fds: Option<Fds>,
+    // The VS UniSystem's DIP switches, coin slots and CHR banking, on a VS System game.
+    pub vs_system: Option<VsSystem>,
+    // Bandai's FCG board's registers, on mappers 16 and 159.
+    pub fcg: Option<Fcg>,
+    // The board's EEPROMs and clocks, for its mapper to drive; see `chips`.
+    pub chips: Vec<Box<dyn Chip>>,
+    // Which timing details to emulate, from the emulator's accuracy profile.
+    pub accuracy: Accuracy,
+    // The frame's sound so far, one sample every `SAMPLES_PER_FRAME`th of a frame. Only the disk
+    // system makes any yet. Output, not state: the emulator takes it at the end of each frame.
+    pub samples: Vec<i16>,
+    // Counts half cycles towards the next sample, `SAMPLES_PER_FRAME` times as fast as they run.
+    sample_clock: usize,
+    // Writes to each page, for the block cache to notice code changing under it.
+    #[cfg(feature = "experimental-jit")]
+    pub code_writes: CodeWrites,
+}
+
+
+// 2K Work RAM
+const WRAM_SIZE: usize = 0x0800; 
+const PRG_RAM_SIZE: usize = 0x2000;
+
+// CPU cycles in a frame, doubled to make a whole number: an NTSC frame is 29780.5.
+const HALF_CYCLES_PER_FRAME: usize = 59561;
+// The disk system's sound channel at full volume, leaving room for the APU's once there is one.
+const FDS_LEVEL: f32 = 8192.0;
+
+impl Bus {
+    pub fn new(cartridge: Cartridge) -> Bus {
+        let vs_system = (cartridge.console == Console::VsSystem).then(|| VsSystem::new(cartridge.chr_rom.clone()));
+        let fcg = matches!(cartridge.mapper, fcg::MAPPER_24C02 | fcg::MAPPER_24C01)
+            .then(|| Fcg::new(cartridge.chr_rom.clone(), &cartridge.screen_mirroring));
+        let chips = chips::for_cartridge(&cartridge);
+        let mut ppu = PPU::new(cartridge.chr_rom, cartridge.screen_mirroring);
+        ppu.model = cartridge.ppu_model;
+        Bus {
+            cpu_wram: [0; WRAM_SIZE],
+            prg_ram: [0; PRG_RAM_SIZE].to_vec(),
+            prg_rom: cartridge.prg_rom,
+            fds: (!cartridge.disk.is_empty()).then(|| Fds::new(cartridge.disk)),
+            vs_system,
+            fcg,
+            chips,
+            ppu,
+            cycles: 7,
+            joypad: Joypad::new(),
+            joypad2: Joypad::new(),
+            port2: Port2Device::Joypad,
+            four_score: None,
+            keyboard: None,
+            microphone: false,
+            input_reads: 0,
+            watchpoints: Vec::new(),
+            watch_hits: Vec::new(),
+            cdl: None,
+            events: None,
+            cheats: Cheats::new(),
+            flat_ram: None,
+            access_log: None,
+            accuracy: Accuracy::default(),
+            samples: Vec::with_capacity(SAMPLES_PER_FRAME + 1),
+            sample_clock: 0,
+            #[cfg(feature = "experimental-jit")]
+            code_writes: CodeWrites::default(),
+        }
+    }
+
+    // With CHR-ROM, but with empty callback function.
+    pub fn default(rom: Cartridge) -> Self {
+        Bus::new(rom)
+    }
+
+    pub fn tick(&mut self, cycles: usize) {
+        // The disk drive and its sound keep real time, so they sit out the cycles that fall in
+        // overclocked scanlines, to the nearest CPU cycle, and only those.
+        let real_time = cycles - (self.ppu.stalled_cycles(cycles * 3) + 1) / 3;
+        if let Some(fds) = &mut self.fds {
+            fds.tick(real_time);
+            self.sample_clock += 2 * SAMPLES_PER_FRAME * real_time;
+            while self.sample_clock >= HALF_CYCLES_PER_FRAME {
+                self.sample_clock -= HALF_CYCLES_PER_FRAME;
+                self.samples.push((fds.audio.output() * FDS_LEVEL) as i16);
+            }
+        }
+        if let Some(fcg) = &mut self.fcg {
+            fcg.tick(cycles);
+        }
+        let frame_done = self.ppu.tick(cycles * 3);
+        if let (true, Some(events)) = (frame_done, &mut self.events) {
+            events.end_frame();
+        }
+
+        self.cycles += cycles;
+    }
+
+    pub fn read_prg_rom(&self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_rom_offset(addr)]
+    }
+
+    // Index into PRG-ROM of CPU address `addr`. Mirrors in case PRG ROM takes up only 16kB instead
+    // of 32kB, or goes through the FCG board's bank.
+    pub fn prg_rom_offset(&self, addr: u16) -> usize {
+        match &self.fcg {
+            Some(fcg) => fcg.prg_offset(addr, self.prg_rom.len()),
+            None => (addr - PRG_ROM_START) as usize % self.prg_rom.len(),
+        }
+    }
+
+    // The 16kB PRG-ROM bank CPU address `addr` falls in, or None outside ROM.
+    pub fn prg_rom_bank(&self, addr: u16) -> Option<usize> {
+        (addr >= PRG_ROM_START).then(|| self.prg_rom_offset(addr) / 0x4000)
+    }
+
+    pub fn read_prg_ram(&self, mut addr: u16) -> u8 {
+        if let Some(fcg) = &self.fcg {
+            return fcg.read(&self.chips);
+        }
+        addr -= PRG_RAM_START;
+        self.prg_ram[addr as usize]
+    }
+
+    fn write_to_prg_ram(&mut self, mut addr: u16, val: u8) {
+        addr -= PRG_RAM_START;
+        self.prg_ram[addr as usize] = val;
+    }
+
+    // Whether anything is holding the CPU's IRQ line low.
+    pub fn irq(&self) -> bool {
+        self.fds.as_ref().is_some_and(Fds::irq) || self.fcg.as_ref().is_some_and(Fcg::irq)
+    }
+
+    pub fn pull_nmi_status(&mut self) -> Option<u8> {
+        let status = self.ppu.nmi_interrupt.take();
+        if let (Some(_), Some(events)) = (status, &mut self.events) {
+            events.record_nmi(&self.ppu);
+        }
+        status
+    }
+
+    // Reads `addr` without side effects, for debuggers and memory viewers. Write-only and
+    // side-effecting registers read as 0.
+    pub fn peek(&self, addr: u16) -> u8 {
+        if let Some(ram) = &self.flat_ram {
+            return ram[addr as usize];
+        }
+        if let (FDS_START..=FDS_END, Some(fds)) = (addr, &self.fds) {
+            return self.cheats.patch_read(addr, fds.peek(addr));
+        }
+        match addr {
+            WRAM_START..=WRAM_END => self.cpu_wram[(addr & 0b111_1111_1111) as usize],
+            PPU_START..=PPU_MIRRORS_END => match addr & 0b00100000_00000111 {
+                0x2002 => self.ppu.status.bits() | self.ppu.model.status_id(),
+                0x2004 => self.ppu.oam_data[self.ppu.oam_addr as usize],
+                _ => 0,
+            },
+            PRG_RAM_START..=PRG_RAM_END => self.read_prg_ram(addr),
+            PRG_ROM_START..=PRG_ROM_END => self.cheats.patch_read(addr, self.read_prg_rom(addr)),
+            _ => 0,
+        }
+    }
+
+    // Writes `addr` straight into memory, bypassing registers and side effects. Unlike a CPU
+    // write this also patches PRG-ROM. Register addresses have no storage and are ignored.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        #[cfg(feature = "experimental-jit")]
+        self.code_writes.written(addr);
+        if let Some(ram) = &mut self.flat_ram {
+            ram[addr as usize] = value;
+            return;
+        }
+        if let (FDS_START..=FDS_END, Some(fds)) = (addr, &mut self.fds) {
+            fds.poke(addr, value);
+            return;
+        }
+        match addr {
+            WRAM_START..=WRAM_END => self.cpu_wram[(addr & 0b111_1111_1111) as usize] = value,
+            PRG_RAM_START..=PRG_RAM_END => self.write_to_prg_ram(addr, value),
+            PRG_ROM_START..=PRG_ROM_END => {
+                let index = self.prg_rom_offset(addr);
+                self.prg_rom[index] = value;
+            }
+            _ => {}
+        }
+    }
+
+    // Writes the values of the RAM freezes back into RAM. The emulator calls this after each frame.
+    pub fn apply_freezes(&mut self) {
+        for (addr, value) in self.cheats.frozen() {
+            #[cfg(feature = "experimental-jit")]
+            self.code_writes.written(addr);
+            match (addr, &mut self.fds) {
+                (PRG_RAM_START..=PRG_RAM_END, Some(fds)) => fds.poke(addr, value),
+                (PRG_RAM_START..=PRG_RAM_END, None) => self.prg_ram[(addr - PRG_RAM_START) as usize] = value,
+                _ => self.cpu_wram[(addr & 0b111_1111_1111) as usize] = value,
+            }
+        }
+    }
+
+    // Starts a code/data log for this cartridge, replacing any log already running.
+    pub fn start_code_data_log(&mut self) {
+        self.cdl = Some(CodeDataLog::new(self.prg_rom_len(), self.chr_rom_len()));
+    }
+
+    // Starts logging on top of `log`, e.g. one loaded from a file.
+    pub fn resume_code_data_log(&mut self, log: CodeDataLog) -> Result<(), String> {
+        if log.prg.len() != self.prg_rom_len() || log.chr.len() != self.chr_rom_len() {
+            return Err("Code/data log is for a different cartridge".to_string());
+        }
+        self.cdl = Some(log);
+        Ok(())
+    }
+
+    pub fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    // CHR-ROM size as the code/data log counts it: 0 for CHR-RAM.
+    pub fn chr_rom_len(&self) -> usize {
+        match self.ppu.chr_ram {
+            Some(_) => 0,
+            None => self.ppu.chr_rom.len(),
+        }
+    }
+
+    // Logs the instruction at `pc`, which is about to run, as code.
+    pub fn log_instruction(&mut self, pc: u16) {
+        let code = self.peek(pc);
+        let (len, indirect) = match OPCODES_MAP.get(&code) {
+            Some(opcode) => (
+                opcode.bytes as u16,
+                matches!(opcode.addressing_mode, AddressingMode::Indirect_X | AddressingMode::Indirect_Y),
+            ),
+            None => (1, false),
+        };
+        let offsets: Vec<(usize, u16)> = (0..len)
+            .map(|i| pc.wrapping_add(i))
+            .filter(|addr| *addr >= PRG_ROM_START)
+            .map(|addr| (self.prg_rom_offset(addr), addr))
+            .collect();
+        if let Some(cdl) = &mut self.cdl {
+            let flags = cdl.begin_instruction(pc, len, code, indirect);
+            for (offset, addr) in offsets {
+                cdl.log_prg(offset, addr, flags);
+            }
+        }
+    }
+
+    // Whether the code/data log has seen the byte at `addr` read as data but never run.
+    pub fn is_logged_data(&self, addr: u16) -> bool {
+        match &self.cdl {
+            Some(cdl) if addr >= PRG_ROM_START => cdl.is_data_only(self.prg_rom_offset(addr)),
+            _ => false,
+        }
+    }
+
+    // RAM and the controller ports. The Four Score's state is only there if it was plugged in;
+    // loading plugs it in or out to match. The PPU saves its own section.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.cpu_wram);
+        state.vec(&self.prg_ram);
+        state.u64(self.cycles as u64);
+        state.u32(self.input_reads);
+        self.joypad.save_state(state);
+        self.joypad2.save_state(state);
+        state.bool(self.four_score.is_some());
+        if let Some(four_score) = &self.four_score {
+            four_score.save_state(state);
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        #[cfg(feature = "experimental-jit")]
+        self.code_writes.written_everywhere();
+        state.bytes(&mut self.cpu_wram)?;
+        state.vec(&mut self.prg_ram)?;
+        self.cycles = state.u64()? as usize;
+        self.input_reads = state.u32()?;
+        self.joypad.load_state(state)?;
+        self.joypad2.load_state(state)?;
+        self.four_score = match state.bool()? {
+            true => {
+                let mut four_score = self.four_score.unwrap_or_default();
+                four_score.load_state(state)?;
+                Some(four_score)
+            }
+            false => None,
+        };
+        Ok(())
+    }
+}
+
+impl Mem for Bus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let value = self.read(addr);
+        if let Some(events) = &mut self.events {
+            events.record_access(&self.ppu, addr, value, AccessKind::Read);
+        }
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, AccessKind::Read, value, value);
+        }
+        if let Some(log) = &mut self.access_log {
+            log.push((addr, value, AccessKind::Read));
+        }
+        value
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        #[cfg(feature = "experimental-jit")]
+        self.code_writes.written(addr);
+        if !self.watchpoints.is_empty() {
+            let old = self.peek(addr);
+            self.check_watchpoints(addr, AccessKind::Write, old, data);
+        }
+        if let Some(events) = &mut self.events {
+            events.record_access(&self.ppu, addr, data, AccessKind::Write);
+        }
+        if let Some(log) = &mut self.access_log {
+            log.push((addr, data, AccessKind::Write));
+        }
+        self.write(addr, data);
+    }
+}
+
+impl Bus {
+    fn check_watchpoints(&mut self, addr: u16, kind: AccessKind, old: u8, new: u8) {
+        if self.watchpoints.iter().any(|watch| watch.matches(addr, kind)) {
+            self.watch_hits.push(WatchHit { addr, kind, old, new, pc: 0 });
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        if let Some(ram) = &self.flat_ram {
+            return ram[addr as usize];
+        }
+        if let (FDS_START..=FDS_END, Some(fds)) = (addr, &mut self.fds) {
+            return self.cheats.patch_read(addr, fds.read(addr));
+        }
+        match addr {
+            // WRAP start (0x0000 -> 0x1fff)
+            WRAM_START..=WRAM_END => {
+                // Take the last 11 bits.
+                let mirror_down_addr = addr & 0b111_1111_1111;
+                self.cpu_wram[mirror_down_addr as usize]
+            }
+
+            // PPU start (0x2000 -> 0x3fff)
+            // Write-only. TODO: open bus.
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => 0,
+
+            0x2002 => self.ppu.read_status(),
+
+            0x2004 => self.ppu.read_oam_data(),
+
+            0x2007 => {
+                if let Some(cdl) = &mut self.cdl {
+                    cdl.log_chr_read(&self.ppu);
+                }
+                self.ppu.read_data()
+            }
+
+            0x4016 => {
+                self.input_reads += 1;
+                let controller = match &mut self.four_score {
+                    Some(four_score) => four_score.read(0, self.joypad.button_status),
+                    None => self.joypad.read(),
+                };
+                // The VS System's service button has the microphone's bit.
+                let extra = match &self.vs_system {
+                    Some(vs_system) => vs_system.read(0),
+                    None => (self.microphone as u8) << 2,
+                };
+                controller | extra
+            }
+
+            0x4017 => {
+                self.input_reads += 1;
+                let controller = match (&mut self.four_score, &mut self.port2) {
+                    (Some(four_score), _) => four_score.read(1, self.joypad2.button_status),
+                    (None, Port2Device::Joypad) => self.joypad2.read(),
+                    (None, Port2Device::Zapper(zapper)) => zapper.read(&self.ppu),
+                    (None, Port2Device::PowerPad(power_pad)) => power_pad.read(),
+                };
+                let keyboard = self.keyboard.as_ref().map_or(0, FamilyKeyboard::read);
+                controller | keyboard | self.vs_system.as_ref().map_or(0, |vs_system| vs_system.read(1))
+            }
+
+            PPU_MIRRORS_START..=PPU_MIRRORS_END => {
+                // Mirrors $2008 - $4000 into $2000 - $2008
+                let mirror_down_addr = addr & 0b00100000_00000111;
+                self.read(mirror_down_addr)
+            },
+
+            PRG_RAM_START..=PRG_RAM_END => self.read_prg_ram(addr),
+
+            PRG_ROM_START..=PRG_ROM_END => {
+                let offset = self.prg_rom_offset(addr);
+                if let Some(cdl) = &mut self.cdl {
+                    cdl.log_read(offset, addr);
+                }
+                self.cheats.patch_read(addr, self.prg_rom[offset])
+            }
+
+            _ => {
+                tracing::debug!(target: "cpu", "Read from unmapped ${:04x}", addr);
+                0
+            }
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let Some(ram) = &mut self.flat_ram {
+            ram[addr as usize] = data;
+            return;
+        }
+        if let (FDS_START..=FDS_END, Some(fds)) = (addr, &mut self.fds) {
+            fds.write(addr, self.cheats.patch_write(addr, data));
+            // The BIOS sets mirroring through $4025.
+            self.ppu.mirroring = fds.mirroring();
+            return;
+        }
+        match addr {
+            WRAM_START..=WRAM_END => {
+                // Only accept 11 bits instead of 13 for RAM
+                let mirror_down_addr = addr & 0b111_1111_1111;
+                self.cpu_wram[mirror_down_addr as usize] = self.cheats.patch_write(mirror_down_addr, data);
+            }
+
+            // The RC2C05 PPUs have $2000 and $2001 the other way round.
+            0x2000 | 0x2001 if self.ppu.model.swaps_control_registers() => match addr {
+                0x2000 => self.ppu.write_to_mask(data),
+                _ => self.ppu.write_to_controller(data),
+            },
+
+            0x2000 => self.ppu.write_to_controller(data),
+
+            0x2001 => self.ppu.write_to_mask(data),
+
+            // Read-only.
+            0x2002 => {}
+
+            0x2003 => self.ppu.write_to_oam_addr(data),
+
+            0x2004 => self.ppu.write_to_oam_data(data),
+
+            0x2005 => self.ppu.write_to_scroll(data),
+
+            0x2006 => self.ppu.write_to_ppu_addr(data),
+
+            0x2007 => self.ppu.write_to_data(data),
+            
+            // Lazy DMA. TODO: handle cycle accuracy with this.
+            0x4014 => {
+                let mut buffer: [u8; 256] = [0; 256];
+                let hi: u16 = (data as u16) << 8;
+                for i in 0..256u16 {
+                    buffer[i as usize] = self.mem_read(hi + i);
+                }
+
+                self.ppu.write_oam_dma(&buffer);
+                // A read and a write per byte, plus a cycle to line up with reads, and another on
+                // odd cycles.
+                if self.accuracy.dma_timing {
+                    self.tick(513 + self.cycles % 2);
+                }
+            }
+
+            // Strobe is shared by both controller ports.
+            0x4016 => {
+                self.joypad.write(data);
+                self.joypad2.write(data);
+                if let Some(four_score) = &mut self.four_score {
+                    four_score.write(data);
+                }
+                if let Some(keyboard) = &mut self.keyboard {
+                    keyboard.write(data);
+                }
+                if let Port2Device::PowerPad(power_pad) = &mut self.port2 {
+                    power_pad.write(data);
+                }
+                if let Some(vs_system) = &mut self.vs_system {
+                    vs_system.write(data, &mut self.ppu);
+                }
+            }
+
+            // The VS System's coin counter.
+            0x4020 if self.vs_system.is_some() => {}
+
+            PPU_MIRRORS_START..=PPU_MIRRORS_END => {
+                // Mirrors PPU mirrors ($2008 - $4000) into $2000 - $2008
+                let mirror_down_addr = addr & 0b00100000_00000111;
+                self.write(mirror_down_addr, data);
+            }
+
+            PRG_RAM_START..=PRG_RAM_END => self.write_to_prg_ram(addr, self.cheats.patch_write(addr, data)),
+
+            PRG_ROM_START..=PRG_ROM_END if self.fcg.is_some() => {
+                if let Some(fcg) = &mut self.fcg {
+                    #[cfg(feature = "experimental-jit")]
+                    let prg_bank = fcg.prg_bank();
+                    fcg.write(addr, data, &mut self.ppu, &mut self.chips);
+                    // Code cached from the old bank at $8000-$BFFF is gone.
+                    #[cfg(feature = "experimental-jit")]
+                    if fcg.prg_bank() != prg_bank {
+                        self.code_writes.written_everywhere();
+                    }
+                }
+            }
+
+            // NROM has no registers.
+            PRG_ROM_START..=PRG_ROM_END => {
+                tracing::debug!(target: "mapper", "Ignored write of ${:02x} to PRG-ROM at ${:04x}", data, addr);
+            }
+
+            // The sound registers and the frame counter. There is no APU yet.
+            0x4000..=0x4013 | 0x4015 | 0x4017 => {
+                tracing::trace!(target: "apu", "Ignored write of ${:02x} to ${:04x}", data, addr);
+            }
+
+            _ => {
+                tracing::debug!(target: "cpu", "Ignored write of ${:02x} to unmapped ${:04x}", data, addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test::create_test_cartridge;
+
+    #[test]
+    fn test_peek_has_no_side_effects() {
+        let mut bus = Bus::new(create_test_cartridge());
+        bus.mem_write(0x0012, 0x34);
+        bus.mem_write(0x6001, 0x56);
+        assert_eq!(bus.peek(0x0812), 0x34);
+        assert_eq!(bus.peek(0x6001), 0x56);
+
+        bus.ppu.status.insert(crate::ppu::registers::status::PPUSTATUS::VBLANK_STARTED);
+        let status = bus.peek(0x2002);
+        assert_eq!(bus.peek(0x200a), status);
+        // Reading $2002 for real clears vblank; peeking must not.
+        assert_eq!(bus.mem_read(0x2002), status);
+        assert_ne!(bus.peek(0x2002), status);
+        assert_eq!(bus.input_reads, 0);
+    }
+
+    #[test]
+    fn test_watchpoints_record_hits() {
+        let mut bus = Bus::new(create_test_cartridge());
+        bus.mem_write(0x0012, 0x03);
+        bus.watchpoints.push(Watchpoint::new(0x0012, 0x0012, AccessKind::Write));
+
+        bus.mem_read(0x0012);
+        bus.mem_write(0x0812, 0x04);
+        assert_eq!(
+            bus.watch_hits,
+            vec![WatchHit { addr: 0x0812, kind: AccessKind::Write, old: 0x03, new: 0x04, pc: 0 }]
+        );
+    }
+    #[test]
+    fn test_wrong_way_register_accesses_are_ignored() {
+        let mut bus = Bus::new(create_test_cartridge());
+        bus.ppu.status.insert(crate::ppu::registers::status::PPUSTATUS::VBLANK_STARTED);
+        let status = bus.peek(0x2002);
+        bus.mem_write(0x2002, 0x00);
+        assert_eq!(bus.peek(0x2002), status);
+        assert_eq!(bus.mem_read(0x2000), 0);
+        assert_eq!(bus.mem_read(0x4014), 0);
+    }
+
+    #[test]
+    fn test_microphone_reads_in_4016_bit_2() {
+        let mut bus = Bus::new(create_test_cartridge());
+        assert_eq!(bus.mem_read(0x4016) & 0b100, 0);
+        bus.microphone = true;
+        assert_eq!(bus.mem_read(0x4016) & 0b100, 0b100);
+        assert_eq!(bus.mem_read(0x4017) & 0b100, 0);
+    }
+}
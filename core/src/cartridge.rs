@@ -0,0 +1,287 @@
+//! iNES (.NES) file parser
+//!
+//! Reference: https://www.nesdev.org/wiki/INES
+//!
+//! NES 2.0 headers are read as far as they agree with iNES, plus the larger ROM sizes and the
+//! region (see `region`). Their other fields (submappers, RAM sizes, expansion devices) are ignored.
+//!
+//! Reference: https://www.nesdev.org/wiki/NES_2.0
+
+use crate::fds::disk;
+use crate::fds::BIOS_SIZE;
+use crate::ppu::model::PpuModel;
+use crate::region::Region;
+
+const INES_IDENTIFIER: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const PRG_ROM_PAGE_SIZE: usize = 16384;
+const CHR_ROM_PAGE_SIZE: usize = 8192;
+// The iNES mapper number set aside for the Famicom Disk System.
+pub const FDS_MAPPER: u8 = 20;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+    // All four nametables show the first of the console's two, or the second. Only mappers set
+    // these; a header can't.
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+// What the game was made to run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    Nes,
+    // The VS UniSystem arcade board, with coin slots and DIP switches.
+    VsSystem,
+    // The PlayChoice-10 arcade board. Its games are NES games with an RGB PPU; the board's own
+    // menu and instruction screens aren't emulated.
+    PlayChoice10,
+}
+
+#[derive(Clone)]
+pub struct Cartridge {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mapper: u8,
+    pub screen_mirroring: Mirroring,
+    pub console: Console,
+    // iNES headers don't say which PPU an arcade board has, so VS System and PlayChoice-10 games
+    // start on the RP2C03, the most common, until set otherwise.
+    pub ppu_model: PpuModel,
+    // The sides of a Famicom Disk System disk, as the drive reads them, with the FDS BIOS as the
+    // PRG-ROM. Empty for a cartridge.
+    pub disk: Vec<Vec<u8>>,
+    // The console the header says the game is for. Only NES 2.0 headers say.
+    pub region: Option<Region>,
+}
+
+impl Cartridge {
+    // Creates a Cartridge from raw .nes file (array of u8s).
+    pub fn new(raw: &[u8]) -> Result<Cartridge, String> {
+        if raw.len() < 16 {
+            return Err("File is too short to be an iNES file".to_string());
+        }
+        if raw[0..4] != INES_IDENTIFIER {
+            return Err("File is not in iNES file format".to_string());
+        }
+
+        // NES 2.0 has the ROM sizes' high bits in byte 9, and a mapper number's in byte 8.
+        let nes2 = (raw[7] >> 2) & 0b11 == 0b10;
+        let (prg_high, chr_high) = match nes2 {
+            true => ((raw[9] & 0x0f) as usize, (raw[9] >> 4) as usize),
+            false => (0, 0),
+        };
+        if prg_high == 0x0f || chr_high == 0x0f {
+            return Err("NES 2.0 ROM sizes in exponent form are not supported".to_string());
+        }
+        if nes2 && raw[8] & 0x0f != 0 {
+            let mapper = (raw[8] as u16 & 0x0f) << 8 | (raw[7] & 0xf0 | raw[6] >> 4) as u16;
+            return Err(format!("Mapper {} is not supported", mapper));
+        }
+        let prg_rom_size = (prg_high << 8 | raw[4] as usize) * PRG_ROM_PAGE_SIZE;
+        let chr_rom_size = (chr_high << 8 | raw[5] as usize) * CHR_ROM_PAGE_SIZE;
+
+        // raw[6] bitflag breakdown
+        // ________
+        // 76543210
+        // ||||||||
+        // |||||||+- Nametable arrangement: 0: vertical arrangement ("horizontal mirrored") (CIRAM A10 = PPU A11)
+        // |||||||                          1: horizontal arrangement ("vertically mirrored") (CIRAM A10 = PPU A10)
+        // ||||||+-- 1: Cartridge contains battery-backed PRG RAM ($6000-7FFF) or other persistent memory
+        // |||||+--- 1: 512-byte trainer at $7000-$71FF (stored before PRG data)
+        // ||||+---- 1: Alternative nametable layout
+        // ++++----- Lower nybble of mapper number
+
+        // If there's a trainers
+        let trainer = raw[6] & 0b100 == 0b100;
+
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let screen_mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+
+        let console = match raw[7] & 0b11 {
+            0b01 => Console::VsSystem,
+            0b10 => Console::PlayChoice10,
+            _ => Console::Nes,
+        };
+
+        // Byte 7's bits 2 and 3 are 0 in iNES files and 2 in NES 2.0 ones. Anything else is an old
+        // header with junk (often a ripper's name) from byte 7 on.
+        let ines_ver = (raw[7] >> 2) & 0b11;
+        if ines_ver != 0 && !nes2 {
+            return Err("Unknown iNES header version".to_string());
+        }
+
+        // TODO: PRG-RAM size
+
+        if prg_rom_size == 0 {
+            return Err("Cartridge has no PRG-ROM".to_string());
+        }
+
+        let prg_rom_start = 16 + if trainer { 512 } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err(format!(
+                "File is truncated: the header promises {} bytes of ROM, but it has {}",
+                chr_rom_start + chr_rom_size,
+                raw.len()
+            ));
+        }
+
+        Ok(Cartridge {
+            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
+            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
+            mapper,
+            screen_mirroring,
+            console,
+            ppu_model: match console {
+                Console::Nes => PpuModel::Rp2c02,
+                Console::VsSystem | Console::PlayChoice10 => PpuModel::Rp2c03,
+            },
+            disk: Vec::new(),
+            region: nes2.then(|| Region::from_timing_byte(raw[12])),
+        })
+    }
+
+    // Creates the RAM adapter with the .fds disk image `raw` in the drive, running `bios`. Games
+    // get 8kB of CHR-RAM, and set mirroring themselves.
+    pub fn fds(raw: &[u8], bios: &[u8]) -> Result<Cartridge, String> {
+        if bios.len() != BIOS_SIZE {
+            return Err(format!("The FDS BIOS is {} bytes, not {}", bios.len(), BIOS_SIZE));
+        }
+        Ok(Cartridge {
+            prg_rom: bios.to_vec(),
+            chr_rom: Vec::new(),
+            mapper: FDS_MAPPER,
+            screen_mirroring: Mirroring::Horizontal,
+            console: Console::Nes,
+            ppu_model: PpuModel::Rp2c02,
+            disk: disk::parse(raw)?,
+            region: None,
+        })
+    }
+
+
+    // FNV-1a hash of the PRG and CHR data and any disk, used to identify a game independently of its file name.
+    pub fn hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in self.prg_rom.iter().chain(self.chr_rom.iter()).chain(self.disk.iter().flatten()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        hash
+    }
+}
+
+impl Default for Cartridge {
+    // Creates an empty cartridge.
+    fn default() -> Self {
+        Cartridge {
+            prg_rom: vec![0; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![0; CHR_ROM_PAGE_SIZE],
+            mapper: 0,
+            screen_mirroring: Mirroring::Horizontal,
+            console: Console::Nes,
+            ppu_model: PpuModel::Rp2c02,
+            disk: Vec::new(),
+            region: None,
+        }
+    }
+}
+
+pub mod test {
+    use super::*;
+
+    // Note that we must set the program counter manually with this test cartridge. 0xFFFC will NOT
+    // contain the "reset vector."
+    pub fn create_test_cartridge() -> Cartridge {
+        let mut header = vec![
+            0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        let mut pgr_rom = vec![0; 2 * PRG_ROM_PAGE_SIZE];
+        let mut chr_rom = vec![0; CHR_ROM_PAGE_SIZE];
+        header.append(&mut pgr_rom);
+        header.append(&mut chr_rom);
+        Cartridge::new(&header).unwrap()
+    }
+
+    #[test]
+    fn test_invalid_ines_identifier() {
+        let raw_data = vec![
+            // Incorrect iNES header
+            0x00, 0x00, 0x00, 0x00, // Invalid NES<EOF>
+            0x02, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        let result = Cartridge::new(&raw_data);
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap(), "File is not in iNES file format");
+    }
+    #[test]
+    fn test_unsupported_nes_version() {
+        let raw_data = vec![
+            // iNES header with version bits 01, which nothing uses
+            0x4E, 0x45, 0x53, 0x1A, // NES<EOF>
+            0x02, 0x01, 0x00, 0x04,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00,
+        ];
+
+        let result = Cartridge::new(&raw_data);
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap(), "Unknown iNES header version");
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        // NES 2.0 (flags 7 bits 2-3 = 10), PAL timing in byte 12.
+        let mut raw_data = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x00, 0x08, 0, 0, 0, 0, 0x01, 0, 0, 0];
+        raw_data.extend(vec![0; 2 * PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE]);
+        let cartridge = Cartridge::new(&raw_data).unwrap();
+        assert_eq!((cartridge.region, cartridge.prg_rom.len()), (Some(Region::Pal), 2 * PRG_ROM_PAGE_SIZE));
+        assert_eq!(create_test_cartridge().region, None);
+
+        raw_data[8] = 0x01;
+        assert_eq!(Cartridge::new(&raw_data).err().unwrap(), "Mapper 256 is not supported");
+        raw_data[8] = 0;
+        raw_data[9] = 0x01;
+        assert!(Cartridge::new(&raw_data).err().unwrap().starts_with("File is truncated"));
+    }
+
+    #[test]
+    fn test_arcade_flags() {
+        let mut raw_data = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0, 0, 0];
+        raw_data.extend(vec![0; 2 * PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE]);
+        let vs = Cartridge::new(&raw_data).unwrap();
+        assert_eq!((vs.console, vs.ppu_model, vs.mapper), (Console::VsSystem, PpuModel::Rp2c03, 0));
+
+        raw_data[7] = 0x02;
+        assert_eq!(Cartridge::new(&raw_data).unwrap().console, Console::PlayChoice10);
+        assert_eq!(create_test_cartridge().ppu_model, PpuModel::Rp2c02);
+    }
+
+    #[test]
+    fn test_short_and_truncated_files() {
+        assert_eq!(Cartridge::new(b"NES\x1a").err().unwrap(), "File is too short to be an iNES file");
+
+        let mut raw_data = vec![0x4E, 0x45, 0x53, 0x1A, 0x00, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(Cartridge::new(&raw_data).err().unwrap(), "Cartridge has no PRG-ROM");
+
+        raw_data[4] = 0x02;
+        raw_data.extend(vec![0; 2 * PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE - 1]);
+        assert_eq!(
+            Cartridge::new(&raw_data).err().unwrap(),
+            "File is truncated: the header promises 40976 bytes of ROM, but it has 40975"
+        );
+        raw_data.push(0);
+        assert!(Cartridge::new(&raw_data).is_ok());
+    }
+}
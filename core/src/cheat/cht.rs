@@ -0,0 +1,214 @@
+//! Cheat lists in the `.cht` formats other emulators use, so existing cheat libraries can be
+//! brought over.
+//!
+//! FCEUX writes one cheat per line, as `[S][C][:]AAAA:VV[:CC]:Name`: `S` makes it substitute reads
+//! of the address instead of writing RAM every frame, `C` adds a compare value, and a `:` in front
+//! of the address means it is switched off. Mesen imports the same files.
+//!
+//! libretro's cheat files (RetroArch's cheat database) are `key = "value"` lines numbering each
+//! cheat's description, code and whether it is on. NES codes are Game Genie codes, or raw
+//! `AAAA:VV`/`AAAA?CC:VV` read substitutions, joined by `+` when a cheat needs several.
+//!
+//! Reads substituted in ROM map onto Game Genie codes, and RAM writes onto freezes; read
+//! substitutions in RAM become freezes on write, which is as close as the engine gets.
+//!
+//! Reference: <https://fceux.com/web/help/CheatsFileFormat.html>,
+//! <https://docs.libretro.com/guides/cheat-codes/>
+
+use crate::cheat::game_genie::{GameGenieCode, LETTERS};
+use crate::cheat::{CheatCode, Cheats, Freeze};
+
+// The cheat engine's version of a patch to `addr`. `substitute` patches reads rather than writing
+// RAM.
+fn from_patch(addr: u16, value: u8, compare: Option<u8>, substitute: bool) -> Result<CheatCode, String> {
+    match (addr, compare) {
+        (0x8000..=0xffff, _) => Ok(CheatCode::GameGenie(GameGenieCode { addr, value, compare })),
+        (0x0000..=0x1fff | 0x6000..=0x7fff, None) => Ok(CheatCode::Freeze(Freeze {
+            addr: match addr {
+                0x0000..=0x1fff => addr & 0x07ff,
+                _ => addr,
+            },
+            value,
+            on_write: substitute,
+        })),
+        (0x0000..=0x1fff | 0x6000..=0x7fff, Some(_)) => Err("Compare values only work in ROM".to_string()),
+        _ => Err(format!("Can't patch ${:04X}, which isn't RAM or ROM", addr)),
+    }
+}
+
+fn hex(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text, 16).map_err(|_| format!("Bad hex number {}", text))
+}
+
+fn hex_byte(text: &str) -> Result<u8, String> {
+    u8::from_str_radix(text, 16).map_err(|_| format!("Bad hex byte {}", text))
+}
+
+// Loads either format, telling them apart by libretro's `cheats = <count>` line.
+pub fn parse(text: &str) -> Result<Cheats, String> {
+    let libretro = text.lines().any(|line| line.split('=').next().is_some_and(|key| key.trim() == "cheats"));
+    match libretro {
+        true => parse_libretro(text),
+        false => parse_fceux(text),
+    }
+}
+
+pub fn parse_fceux(text: &str) -> Result<Cheats, String> {
+    let mut cheats = Cheats::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut line = line.trim_end_matches('\r');
+        let substitute = line.starts_with('S');
+        line = line.strip_prefix('S').unwrap_or(line);
+        let has_compare = line.starts_with('C');
+        line = line.strip_prefix('C').unwrap_or(line);
+        let enabled = !line.starts_with(':');
+        line = line.strip_prefix(':').unwrap_or(line);
+
+        let fields = if has_compare { 4 } else { 3 };
+        let parts: Vec<&str> = line.splitn(fields, ':').collect();
+        let added = (|| {
+            if parts.len() != fields {
+                return Err(format!("Expected [S][C][:]AAAA:VV{}:Name", if has_compare { ":CC" } else { "" }));
+            }
+            let compare = match has_compare {
+                true => Some(hex_byte(parts[2])?),
+                false => None,
+            };
+            let code = from_patch(hex(parts[0])?, hex_byte(parts[1])?, compare, substitute)?;
+            cheats.add_named(code, parts[fields - 1].trim())
+        })()
+        .map_err(|e| format!("Line {}: {}", number + 1, e))?;
+        cheats.set_enabled(added, enabled);
+    }
+    Ok(cheats)
+}
+
+pub fn to_fceux(cheats: &Cheats) -> String {
+    let mut text = String::new();
+    for cheat in cheats.list() {
+        let off = if cheat.enabled { "" } else { ":" };
+        let line = match cheat.code {
+            CheatCode::GameGenie(GameGenieCode { addr, value, compare: None }) => {
+                format!("S{}{:04x}:{:02x}", off, addr, value)
+            }
+            CheatCode::GameGenie(GameGenieCode { addr, value, compare: Some(compare) }) => {
+                format!("SC{}{:04x}:{:02x}:{:02x}", off, addr, value, compare)
+            }
+            CheatCode::Freeze(Freeze { addr, value, on_write }) => {
+                format!("{}{}{:04x}:{:02x}", if on_write { "S" } else { "" }, off, addr, value)
+            }
+        };
+        text.push_str(&format!("{}:{}\n", line, cheat.name));
+    }
+    text
+}
+
+// One code of a libretro cheat: a Game Genie code, or `AAAA:VV` or `AAAA?CC:VV`.
+fn parse_libretro_code(code: &str) -> Result<CheatCode, String> {
+    if code.chars().all(|letter| LETTERS.contains(letter.to_ascii_uppercase())) {
+        return Ok(CheatCode::GameGenie(GameGenieCode::decode(code)?));
+    }
+    let (target, value) = code.split_once(':').ok_or_else(|| format!("Can't read the code {}", code))?;
+    let (addr, compare) = match target.split_once('?') {
+        Some((addr, compare)) => (addr, Some(hex_byte(compare)?)),
+        None => (target, None),
+    };
+    from_patch(hex(addr)?, hex_byte(value)?, compare, true)
+}
+
+pub fn parse_libretro(text: &str) -> Result<Cheats, String> {
+    // (description, code, enabled) by cheat number.
+    let mut entries: Vec<(String, String, bool)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim().trim_matches('"'));
+        let Some((index, field)) = key.strip_prefix("cheat").and_then(|key| key.split_once('_')) else {
+            continue;
+        };
+        let index: usize = index.parse().map_err(|_| format!("Line {}: Bad cheat number in {}", number + 1, key))?;
+        if entries.len() <= index {
+            entries.resize(index + 1, (String::new(), String::new(), false));
+        }
+        match field {
+            "desc" => entries[index].0 = value.to_string(),
+            "code" => entries[index].1 = value.to_string(),
+            "enable" => entries[index].2 = value == "true",
+            _ => {}
+        }
+    }
+
+    let mut cheats = Cheats::new();
+    for (index, (name, codes, enabled)) in entries.into_iter().enumerate() {
+        for code in codes.split('+').filter(|code| !code.is_empty()) {
+            let added = parse_libretro_code(code.trim())
+                .and_then(|code| cheats.add_named(code, &name))
+                .map_err(|e| format!("Cheat {}: {}", index, e))?;
+            cheats.set_enabled(added, enabled);
+        }
+    }
+    Ok(cheats)
+}
+
+// libretro's format has no way to write RAM every frame, so freezes are all written as read
+// substitutions, as if they were on write.
+pub fn to_libretro(cheats: &Cheats) -> String {
+    let mut text = format!("cheats = {}\n", cheats.list().len());
+    for (index, cheat) in cheats.list().iter().enumerate() {
+        let code = match cheat.code {
+            CheatCode::GameGenie(code) => code.encode(),
+            CheatCode::Freeze(Freeze { addr, value, .. }) => format!("{:04X}:{:02X}", addr, value),
+        };
+        text.push_str(&format!(
+            "\ncheat{0}_desc = \"{1}\"\ncheat{0}_code = \"{2}\"\ncheat{0}_enable = {3}\n",
+            index, cheat.name, code, cheat.enabled
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fceux() {
+        let text = "075a:09:Infinite lives\nS:91d9:ad:Lives (off)\nSC94a7:02:03:Compare\nS0075:03:Held\n";
+        let cheats = parse(text).unwrap();
+        let codes: Vec<(String, &str, bool)> = cheats
+            .list()
+            .iter()
+            .map(|cheat| (cheat.code.to_string(), cheat.name.as_str(), cheat.enabled))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("freeze $075A to $09".to_string(), "Infinite lives", true),
+                ("SXIOPO".to_string(), "Lives (off)", false),
+                ("ZEXPYGLA".to_string(), "Compare", true),
+                ("freeze $0075 to $03 on write".to_string(), "Held", true),
+            ]
+        );
+        assert_eq!(to_fceux(&cheats), text);
+
+        assert_eq!(parse("2000:01:PPU"), Err("Line 1: Can't patch $2000, which isn't RAM or ROM".to_string()));
+        assert_eq!(parse("C0075:01:02:x"), Err("Line 1: Compare values only work in ROM".to_string()));
+        assert!(parse("0075").is_err());
+    }
+
+    #[test]
+    fn test_libretro() {
+        let text = "cheats = 2\n\ncheat0_desc = \"Infinite lives\"\ncheat0_code = \"SXIOPO\"\ncheat0_enable = true\n\n\
+                    cheat1_desc = \"Two codes\"\ncheat1_code = \"075A:09+94A7?03:02\"\ncheat1_enable = false\n";
+        let cheats = parse(text).unwrap();
+        let codes: Vec<String> = cheats.list().iter().map(|cheat| cheat.code.to_string()).collect();
+        assert_eq!(codes, ["SXIOPO", "freeze $075A to $09 on write", "ZEXPYGLA"]);
+        assert_eq!(cheats.list()[1].name, "Two codes");
+        assert!(cheats.list()[0].enabled && !cheats.list()[2].enabled);
+        assert_eq!(parse(&to_libretro(&cheats)), Ok(cheats));
+    }
+}
@@ -0,0 +1,132 @@
+//! Game Genie codes. The Game Genie sat between the cartridge and the console and answered CPU
+//! reads of up to three ROM addresses with a value of its own. A code spells out the address, the
+//! new value and, in 8-letter codes, a compare value: the patch only applies while ROM holds that
+//! value, which keeps it from hitting other banks mapped to the same address.
+//!
+//! Reference: <https://www.nesdev.org/wiki/Game_Genie>
+
+// Each letter stands for the 4-bit value of its position.
+pub const LETTERS: &str = "APZLGITYEOXUKSVN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameGenieCode {
+    // $8000-$FFFF.
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl GameGenieCode {
+    // Decodes a 6- or 8-letter code, in either case.
+    pub fn decode(code: &str) -> Result<Self, String> {
+        let n = code
+            .chars()
+            .map(|letter| {
+                LETTERS
+                    .find(letter.to_ascii_uppercase())
+                    .map(|value| value as u16)
+                    .ok_or_else(|| format!("'{}' is not a Game Genie letter", letter))
+            })
+            .collect::<Result<Vec<u16>, String>>()?;
+        if n.len() != 6 && n.len() != 8 {
+            return Err(format!("Game Genie codes have 6 or 8 letters, not {}", n.len()));
+        }
+
+        let addr = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8)
+            | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4)
+            | ((n[1] & 8) << 4)
+            | (n[4] & 7)
+            | (n[3] & 8);
+        let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+        let (value, compare) = match n.len() {
+            6 => (value | (n[5] & 8), None),
+            _ => {
+                let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+                (value | (n[7] & 8), Some(compare as u8))
+            }
+        };
+        Ok(GameGenieCode {
+            addr,
+            value: value as u8,
+            compare,
+        })
+    }
+
+    // The code's letters, as the Game Genie would show them.
+    pub fn encode(&self) -> String {
+        let addr = self.addr;
+        let value = self.value as u16;
+        let mut n = [0u16; 8];
+        n[0] = ((value >> 4) & 8) | (value & 7);
+        n[1] = ((addr >> 4) & 8) | ((value >> 4) & 7);
+        n[2] = (addr >> 4) & 7;
+        n[3] = (addr & 8) | ((addr >> 12) & 7);
+        n[4] = ((addr >> 8) & 8) | (addr & 7);
+        n[5] = (addr >> 8) & 7;
+        let len = match self.compare {
+            None => {
+                n[5] |= value & 8;
+                6
+            }
+            Some(compare) => {
+                let compare = compare as u16;
+                // The third letter's top bit tells the Game Genie to read eight letters.
+                n[2] |= 8;
+                n[5] |= compare & 8;
+                n[6] = ((compare >> 4) & 8) | (compare & 7);
+                n[7] = (value & 8) | ((compare >> 4) & 7);
+                8
+            }
+        };
+        n[..len].iter().map(|&i| LETTERS.as_bytes()[i as usize] as char).collect()
+    }
+
+    // What a read of `addr` returns with the code on, where ROM holds `value`.
+    pub fn apply(&self, addr: u16, value: u8) -> u8 {
+        match self.compare {
+            _ if addr != self.addr => value,
+            Some(compare) if compare != value => value,
+            _ => self.value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // Super Mario Bros., infinite lives.
+        let code = GameGenieCode::decode("SXIOPO").unwrap();
+        assert_eq!(code, GameGenieCode { addr: 0x91d9, value: 0xad, compare: None });
+        assert_eq!(GameGenieCode::decode("sxiopo"), Ok(code));
+
+        let code = GameGenieCode::decode("ZEXPYGLA").unwrap();
+        assert_eq!(code, GameGenieCode { addr: 0x94a7, value: 0x02, compare: Some(0x03) });
+
+        assert_eq!(GameGenieCode::decode("SXIOP"), Err("Game Genie codes have 6 or 8 letters, not 5".to_string()));
+        assert_eq!(GameGenieCode::decode("SXIOPB"), Err("'B' is not a Game Genie letter".to_string()));
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        for code in ["SXIOPO", "ZEXPYGLA", "AAAAAA", "NNYNNN", "AAEAAAAA", "NNNNNNNN"] {
+            let decoded = GameGenieCode::decode(code).unwrap();
+            assert_eq!(decoded.encode(), code);
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let code = GameGenieCode { addr: 0x8000, value: 0x42, compare: None };
+        assert_eq!(code.apply(0x8000, 0x10), 0x42);
+        assert_eq!(code.apply(0x8001, 0x10), 0x10);
+        let code = GameGenieCode { compare: Some(0x10), ..code };
+        assert_eq!(code.apply(0x8000, 0x10), 0x42);
+        assert_eq!(code.apply(0x8000, 0x11), 0x11);
+    }
+}
@@ -0,0 +1,363 @@
+//! Cheats: codes that patch what the CPU sees, kept per game and switched on and off while it
+//! runs. Cheats are a host-side setting, so they aren't part of save states.
+//!
+//! There are two kinds. Game Genie codes patch ROM reads. RAM freezes hold a byte of work RAM or
+//! PRG-RAM at a value, either by writing it back after each frame, which is how most emulators
+//! freeze memory, or by replacing every write the game makes to it, which also covers reads
+//! within the frame.
+//!
+//! Each game's cheats can be listed in a text file next to the ROM (`balloon.nes.cheats` for
+//! `balloon.nes`), one per line. A comment after `#` names the cheat, and `off` in front of it
+//! loads it switched off:
+//!
+//! ```text
+//! SXIOPO                          # Infinite lives
+//! freeze $07FF to $09             # written back after each frame
+//! off freeze $0075 to 3 on write
+//! ```
+//!
+//! Cheat lists in FCEUX's and libretro's `.cht` formats can be loaded and saved too; see `cht`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub mod cht;
+pub mod game_genie;
+pub mod search;
+
+use game_genie::GameGenieCode;
+
+// Holds a byte of RAM at `value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Freeze {
+    // $0000-$07FF or $6000-$7FFF.
+    pub addr: u16,
+    pub value: u8,
+    // Replace the game's writes instead of writing the value back after each frame.
+    pub on_write: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatCode {
+    GameGenie(GameGenieCode),
+    Freeze(Freeze),
+}
+
+impl CheatCode {
+    // A line of a cheat file: a Game Genie code or "freeze <addr> to <value>[ on write]". Numbers
+    // are hex with a `$` or `0x` prefix, and decimal otherwise.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.as_slice() {
+            [code] => Ok(CheatCode::GameGenie(GameGenieCode::decode(code)?)),
+            ["freeze", addr, "to", value, rest @ ..] => {
+                let on_write = match rest {
+                    [] => false,
+                    ["on", "write"] => true,
+                    _ => return Err(format!("Expected \"on write\" or nothing after the value, found \"{}\"", rest.join(" "))),
+                };
+                let addr = parse_number(addr)?;
+                let addr = match addr {
+                    0x0000..=0x1fff => addr & 0x07ff,
+                    0x6000..=0x7fff => addr,
+                    _ => return Err(format!("Can only freeze RAM ($0000-$07FF or $6000-$7FFF), not ${:04X}", addr)),
+                };
+                let value = u8::try_from(parse_number(value)?).map_err(|_| format!("{} doesn't fit in a byte", value))?;
+                Ok(CheatCode::Freeze(Freeze { addr, value, on_write }))
+            }
+            _ => Err(format!("Expected a Game Genie code or \"freeze <addr> to <value>\", found \"{}\"", text.trim())),
+        }
+    }
+
+    // The code as a person reads it, e.g. "SXIOPO ($91D9 = AD)".
+    pub fn describe(&self) -> String {
+        match self {
+            CheatCode::GameGenie(code) => match code.compare {
+                Some(compare) => format!("{} (${:04X} = {:02X} if {:02X})", code.encode(), code.addr, code.value, compare),
+                None => format!("{} (${:04X} = {:02X})", code.encode(), code.addr, code.value),
+            },
+            CheatCode::Freeze(_) => self.to_string(),
+        }
+    }
+}
+
+// The code as a line of a cheat file.
+impl fmt::Display for CheatCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheatCode::GameGenie(code) => write!(f, "{}", code.encode()),
+            CheatCode::Freeze(freeze) => {
+                write!(f, "freeze ${:04X} to ${:02X}", freeze.addr, freeze.value)?;
+                if freeze.on_write {
+                    write!(f, " on write")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    let hex = text.strip_prefix('$').or_else(|| text.strip_prefix("0x"));
+    match hex {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    }
+    .map_err(|_| format!("Bad number {}", text))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: CheatCode,
+    // What it does, e.g. "Infinite lives". May be empty.
+    pub name: String,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    // The name and code, for lists.
+    pub fn label(&self) -> String {
+        match self.name.is_empty() {
+            true => self.code.describe(),
+            false => format!("{}: {}", self.name, self.code.describe()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cheats {
+    list: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats::default()
+    }
+
+    // The cheats in a cheat file's text.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut cheats = Cheats::new();
+        for (number, line) in text.lines().enumerate() {
+            let (line, name) = line.split_once('#').unwrap_or((line, ""));
+            let (line, enabled) = match line.trim().strip_prefix("off ") {
+                Some(line) => (line, false),
+                None => (line, true),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let index = CheatCode::parse(line)
+                .and_then(|code| cheats.add_named(code, name.trim()))
+                .map_err(|e| format!("Line {}: {}", number + 1, e))?;
+            cheats.set_enabled(index, enabled);
+        }
+        Ok(cheats)
+    }
+
+    // The list as a cheat file.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for cheat in &self.list {
+            let line = match cheat.enabled {
+                true => cheat.code.to_string(),
+                false => format!("off {}", cheat.code),
+            };
+            match cheat.name.is_empty() {
+                true => text.push_str(&line),
+                false => text.push_str(&format!("{:<32}# {}", line, cheat.name)),
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    // Loads a cheat file, or a `.cht` file in FCEUX's or libretro's format.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let cheats = match path.extension().is_some_and(|extension| extension == "cht") {
+            true => cht::parse(&text),
+            false => Cheats::parse(&text),
+        };
+        cheats.map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Saves the list as a cheat file, or as a `.cht` file in FCEUX's format.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = match path.extension().is_some_and(|extension| extension == "cht") {
+            true => cht::to_fceux(self),
+            false => self.to_text(),
+        };
+        std::fs::write(path, text).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    // The cheat file for the ROM at `rom_path`, which may not exist.
+    pub fn path_for_rom(rom_path: &Path) -> PathBuf {
+        let mut path = rom_path.as_os_str().to_owned();
+        path.push(".cheats");
+        PathBuf::from(path)
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    // Adds `code`, switched on. Returns its index.
+    pub fn add(&mut self, code: CheatCode) -> Result<usize, String> {
+        self.add_named(code, "")
+    }
+
+    pub fn add_named(&mut self, code: CheatCode, name: &str) -> Result<usize, String> {
+        if self.list.iter().any(|cheat| cheat.code == code) {
+            return Err(format!("{} is already on the list", code));
+        }
+        self.list.push(Cheat {
+            code,
+            name: name.to_string(),
+            enabled: true,
+        });
+        Ok(self.list.len() - 1)
+    }
+
+    // Decodes and adds a Game Genie code, switched on. Returns its index.
+    pub fn add_game_genie(&mut self, code: &str) -> Result<usize, String> {
+        self.add(CheatCode::GameGenie(GameGenieCode::decode(code)?))
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.list.len() {
+            self.list.remove(index);
+        }
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(cheat) = self.list.get_mut(index) {
+            cheat.enabled = enabled;
+        }
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = &CheatCode> {
+        self.list.iter().filter(|cheat| cheat.enabled).map(|cheat| &cheat.code)
+    }
+
+    // What a CPU read of ROM at `addr` returns, where ROM holds `value`. If two codes patch the
+    // same address, the first on the list wins.
+    pub fn patch_read(&self, addr: u16, value: u8) -> u8 {
+        for code in self.enabled() {
+            if let CheatCode::GameGenie(code) = code {
+                let patched = code.apply(addr, value);
+                if patched != value {
+                    return patched;
+                }
+            }
+        }
+        value
+    }
+
+    // What a CPU write of `value` to RAM at `addr` (mirrored down) stores.
+    pub fn patch_write(&self, addr: u16, value: u8) -> u8 {
+        self.enabled()
+            .find_map(|code| match code {
+                CheatCode::Freeze(freeze) if freeze.on_write && freeze.addr == addr => Some(freeze.value),
+                _ => None,
+            })
+            .unwrap_or(value)
+    }
+
+    // The (address, value) of each freeze written back after every frame. Freezes on write are
+    // included too, so switching one on takes hold straight away.
+    pub fn frozen(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.enabled().filter_map(|code| match code {
+            CheatCode::Freeze(freeze) => Some((freeze.addr, freeze.value)),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::cpu::Mem;
+
+    #[test]
+    fn test_cheat_list() {
+        let mut cheats = Cheats::new();
+        assert_eq!(cheats.add_game_genie("SXIOPO"), Ok(0));
+        assert_eq!(cheats.add_game_genie("sxiopo"), Err("SXIOPO is already on the list".to_string()));
+        assert!(cheats.add_game_genie("SXIO").is_err());
+        assert_eq!(cheats.patch_read(0x91d9, 0xce), 0xad);
+
+        cheats.set_enabled(0, false);
+        assert_eq!(cheats.patch_read(0x91d9, 0xce), 0xce);
+        cheats.set_enabled(0, true);
+        cheats.remove(0);
+        assert!(cheats.is_empty());
+        assert_eq!(cheats.patch_read(0x91d9, 0xce), 0xce);
+    }
+
+    #[test]
+    fn test_codes_patch_rom_reads() {
+        let mut bus = Bus::new(create_test_cartridge());
+        let rom = bus.mem_read(0x8123);
+        let code = GameGenieCode { addr: 0x8123, value: rom ^ 0xff, compare: None };
+        bus.cheats.add_game_genie(&code.encode()).unwrap();
+        assert_eq!(bus.mem_read(0x8123), rom ^ 0xff);
+        assert_eq!(bus.peek(0x8123), rom ^ 0xff);
+        assert_eq!(bus.read_prg_rom(0x8123), rom);
+    }
+
+    #[test]
+    fn test_parse_cheat_file() {
+        let text = "# Lives\nSXIOPO\n\nfreeze $07FF to 0x09  # stage\nfreeze 0x0875 to 3 on write\nfreeze $6000 to 255\n";
+        let cheats = Cheats::parse(text).unwrap();
+        let codes: Vec<String> = cheats.list().iter().map(|cheat| cheat.code.to_string()).collect();
+        assert_eq!(codes, ["SXIOPO", "freeze $07FF to $09", "freeze $0075 to $03 on write", "freeze $6000 to $FF"]);
+        assert_eq!(cheats.list()[1].name, "stage");
+        assert_eq!(Cheats::parse(&cheats.to_text()), Ok(cheats));
+
+        assert_eq!(
+            Cheats::parse("SXIOPO\nfreeze $2000 to 1"),
+            Err("Line 2: Can only freeze RAM ($0000-$07FF or $6000-$7FFF), not $2000".to_string())
+        );
+        assert_eq!(Cheats::parse("freeze $10 to 256"), Err("Line 1: 256 doesn't fit in a byte".to_string()));
+        assert!(Cheats::parse("freeze $10 to 1 on read").is_err());
+        assert!(Cheats::parse("unfreeze $10").is_err());
+        assert_eq!(Cheats::path_for_rom(Path::new("roms/balloon.nes")), Path::new("roms/balloon.nes.cheats"));
+    }
+
+    #[test]
+    fn test_names_and_switched_off_cheats() {
+        let text = "SXIOPO # Infinite lives\noff freeze $07FF to $09\n";
+        let cheats = Cheats::parse(text).unwrap();
+        assert_eq!(cheats.list()[0].name, "Infinite lives");
+        assert_eq!(cheats.list()[0].label(), "Infinite lives: SXIOPO ($91D9 = AD)");
+        assert!(cheats.list()[0].enabled);
+        assert!(!cheats.list()[1].enabled);
+        assert_eq!(Cheats::parse(&cheats.to_text()), Ok(cheats));
+    }
+
+    #[test]
+    fn test_freezes_hold_ram() {
+        let mut bus = Bus::new(create_test_cartridge());
+        bus.cheats = Cheats::parse("freeze $0010 to 5\nfreeze $0811 to 7 on write\nfreeze $6000 to 9 on write").unwrap();
+        bus.apply_freezes();
+        assert_eq!((bus.mem_read(0x0010), bus.mem_read(0x0011), bus.mem_read(0x6000)), (5, 7, 9));
+
+        // Writes go through until the next frame, unless the freeze is on write.
+        bus.mem_write(0x0010, 1);
+        bus.mem_write(0x0011, 1);
+        bus.mem_write(0x6000, 1);
+        assert_eq!((bus.mem_read(0x0010), bus.mem_read(0x0011), bus.mem_read(0x6000)), (1, 7, 9));
+        bus.apply_freezes();
+        assert_eq!(bus.mem_read(0x0010), 5);
+
+        bus.cheats.set_enabled(1, false);
+        bus.mem_write(0x0011, 1);
+        assert_eq!(bus.mem_read(0x0011), 1);
+    }
+}
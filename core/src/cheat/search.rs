@@ -0,0 +1,153 @@
+//! Cheat search: finds where a game keeps a number, such as lives or health, by comparing work
+//! RAM against a snapshot. Start a search, play until the number changes, and keep only the
+//! addresses that changed the same way; a few rounds usually narrow 2KB down to a handful.
+
+use crate::cheat::{CheatCode, Freeze};
+use crate::debugger::watch::{AccessKind, Watchpoint};
+
+const RAM_SIZE: usize = 0x0800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    // Compared with the snapshot.
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+    // Holds this value now.
+    EqualTo(u8),
+}
+
+impl Comparison {
+    pub fn name(&self) -> String {
+        match self {
+            Comparison::Unchanged => "Unchanged".to_string(),
+            Comparison::Changed => "Changed".to_string(),
+            Comparison::Increased => "Increased".to_string(),
+            Comparison::Decreased => "Decreased".to_string(),
+            Comparison::EqualTo(value) => format!("= ${:02X}", value),
+        }
+    }
+
+    fn matches(&self, previous: u8, current: u8) -> bool {
+        match self {
+            Comparison::Unchanged => current == previous,
+            Comparison::Changed => current != previous,
+            Comparison::Increased => current > previous,
+            Comparison::Decreased => current < previous,
+            Comparison::EqualTo(value) => current == *value,
+        }
+    }
+}
+
+// An address still in the running, with its value in the snapshot and now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchResult {
+    pub addr: u16,
+    pub previous: u8,
+    pub current: u8,
+}
+
+impl SearchResult {
+    // Freezes the address at its current value.
+    pub fn to_freeze(&self) -> CheatCode {
+        CheatCode::Freeze(Freeze {
+            addr: self.addr,
+            value: self.current,
+            on_write: false,
+        })
+    }
+
+    // Stops the debugger when the game writes the address.
+    pub fn to_watchpoint(&self) -> Watchpoint {
+        Watchpoint::new(self.addr, self.addr, AccessKind::Write)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheatSearch {
+    // Work RAM when the search started or was last narrowed.
+    snapshot: [u8; RAM_SIZE],
+    // Addresses that have matched every comparison so far, in order.
+    candidates: Vec<u16>,
+}
+
+impl CheatSearch {
+    // Starts a search over every address, taking `ram` (the CPU's 2KB work RAM) as the snapshot.
+    pub fn new(ram: &[u8; RAM_SIZE]) -> Self {
+        CheatSearch {
+            snapshot: *ram,
+            candidates: (0..RAM_SIZE as u16).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    // Keeps the addresses whose value in `ram` compares with the snapshot as asked, then takes
+    // `ram` as the new snapshot.
+    pub fn narrow(&mut self, ram: &[u8; RAM_SIZE], comparison: Comparison) {
+        let snapshot = &self.snapshot;
+        self.candidates
+            .retain(|&addr| comparison.matches(snapshot[addr as usize], ram[addr as usize]));
+        self.snapshot = *ram;
+    }
+
+    // The addresses left, with their values in the snapshot and in `ram`.
+    pub fn results<'a>(&'a self, ram: &'a [u8; RAM_SIZE]) -> impl Iterator<Item = SearchResult> + 'a {
+        self.candidates.iter().map(|&addr| SearchResult {
+            addr,
+            previous: self.snapshot[addr as usize],
+            current: ram[addr as usize],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_narrowing() {
+        let mut ram = [0; RAM_SIZE];
+        ram[0x10] = 3;
+        ram[0x20] = 3;
+        ram[0x30] = 3;
+        let mut search = CheatSearch::new(&ram);
+        assert_eq!(search.len(), RAM_SIZE);
+
+        search.narrow(&ram, Comparison::EqualTo(3));
+        assert_eq!(search.len(), 3);
+
+        // A life is lost: 0x10 and 0x20 go down, 0x30 stays.
+        ram[0x10] = 2;
+        ram[0x20] = 2;
+        search.narrow(&ram, Comparison::Decreased);
+        assert_eq!(search.len(), 2);
+        search.narrow(&ram, Comparison::Unchanged);
+        assert_eq!(search.len(), 2);
+
+        ram[0x20] = 9;
+        let results: Vec<SearchResult> = search.results(&ram).collect();
+        assert_eq!(
+            results,
+            vec![
+                SearchResult { addr: 0x10, previous: 2, current: 2 },
+                SearchResult { addr: 0x20, previous: 2, current: 9 },
+            ]
+        );
+        search.narrow(&ram, Comparison::Changed);
+        let result = search.results(&ram).next().unwrap();
+        assert_eq!(result.addr, 0x20);
+        assert_eq!(result.to_freeze().to_string(), "freeze $0020 to $09");
+        assert_eq!(result.to_watchpoint(), Watchpoint::new(0x20, 0x20, AccessKind::Write));
+
+        search.narrow(&ram, Comparison::Increased);
+        assert!(search.is_empty());
+    }
+}
@@ -0,0 +1,367 @@
+//! The 24C01 and 24C02 serial EEPROMs, 128 and 256 bytes, on an I²C bus the mapper drives: a
+//! clock line (SCL) and a data line (SDA) the EEPROM can pull low to answer.
+//! Reference: https://www.nesdev.org/wiki/Bandai_FCG_board#Serial_EEPROM
+//!
+//! A transfer begins with a start condition (SDA falling while SCL is high) and ends with a stop
+//! (SDA rising while SCL is high). Bits are read on SCL's rising edge, and each byte is followed
+//! by an acknowledge bit, sent low by whichever side received it. On the 24C02 the first byte
+//! addresses the chip (1010xxx, then 1 to read or 0 to write) and, when writing, the second the
+//! byte within it, most significant bit first. The 24C01 has no chip address: its first byte is a
+//! 7-bit address and the read bit, and every byte goes least significant bit first. Reads and
+//! writes then carry on through the following addresses, wrapping around, for as long as the
+//! other side keeps acknowledging or sending.
+
+use crate::chips::Chip;
+use crate::savestate::{StateReader, StateWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    C01,
+    C02,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Idle,
+    ChipAddress,
+    Address,
+    Read,
+    Write,
+    // Pulling SDA low to acknowledge the byte just received.
+    SendAck,
+    // Waiting for the other side to acknowledge the byte just sent.
+    WaitAck,
+}
+
+impl Mode {
+    const ALL: [Mode; 7] = [
+        Mode::Idle,
+        Mode::ChipAddress,
+        Mode::Address,
+        Mode::Read,
+        Mode::Write,
+        Mode::SendAck,
+        Mode::WaitAck,
+    ];
+
+    fn from_index(index: u8) -> Result<Mode, String> {
+        Mode::ALL.get(index as usize).copied().ok_or_else(|| format!("Invalid EEPROM mode {}", index))
+    }
+
+    fn index(self) -> u8 {
+        Mode::ALL.iter().position(|mode| *mode == self).unwrap() as u8
+    }
+}
+
+pub struct Eeprom {
+    model: Model,
+    data: Vec<u8>,
+    mode: Mode,
+    // Where `mode` goes after an acknowledge bit.
+    next_mode: Mode,
+    // Bits of `byte` moved so far.
+    bit: u8,
+    byte: u8,
+    address: u8,
+    // The level the EEPROM drives SDA to. High lets the line float, so a read sees the mapper's.
+    output: bool,
+    scl: bool,
+    sda: bool,
+}
+
+impl Eeprom {
+    pub fn new(model: Model) -> Self {
+        let size = match model {
+            Model::C01 => 128,
+            Model::C02 => 256,
+        };
+        Eeprom {
+            model,
+            data: vec![0; size],
+            mode: Mode::Idle,
+            next_mode: Mode::Idle,
+            bit: 0,
+            byte: 0,
+            address: 0,
+            output: true,
+            scl: false,
+            sda: false,
+        }
+    }
+
+    // SDA as the EEPROM drives it.
+    pub fn read(&self) -> bool {
+        self.output
+    }
+
+    // Sets both lines from the mapper's side.
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        if self.scl && scl && self.sda && !sda {
+            let first = match self.model {
+                Model::C01 => Mode::Address,
+                Model::C02 => Mode::ChipAddress,
+            };
+            self.start_byte(first);
+        } else if self.scl && scl && !self.sda && sda {
+            self.mode = Mode::Idle;
+            self.output = true;
+        } else if !self.scl && scl {
+            self.clock_rising(sda);
+        } else if self.scl && !scl {
+            self.clock_falling();
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    fn start_byte(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.bit = 0;
+        self.output = true;
+    }
+
+    // Which bit of a byte goes `bit`th on the wire.
+    fn bit_index(&self) -> u8 {
+        match self.model {
+            Model::C01 => self.bit,
+            Model::C02 => 7 - self.bit,
+        }
+    }
+
+    fn clock_rising(&mut self, sda: bool) {
+        match self.mode {
+            Mode::ChipAddress | Mode::Address | Mode::Write if self.bit < 8 => {
+                if self.bit == 0 {
+                    self.byte = 0;
+                }
+                self.byte |= (sda as u8) << self.bit_index();
+                self.bit += 1;
+            }
+            Mode::Read if self.bit < 8 => {
+                self.output = self.byte >> self.bit_index() & 1 == 1;
+                self.bit += 1;
+            }
+            Mode::SendAck => self.output = false,
+            Mode::WaitAck => {
+                self.next_mode = match sda {
+                    false => self.load_for_read(),
+                    true => Mode::Idle,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn clock_falling(&mut self) {
+        match self.mode {
+            Mode::SendAck | Mode::WaitAck => self.start_byte(self.next_mode),
+            _ if self.bit < 8 => {}
+            Mode::ChipAddress => match self.byte & 0xf0 == 0xa0 {
+                true => {
+                    self.next_mode = if self.byte & 1 == 1 { self.load_for_read() } else { Mode::Address };
+                    self.mode = Mode::SendAck;
+                }
+                // Another chip's address.
+                false => self.mode = Mode::Idle,
+            },
+            Mode::Address => {
+                self.next_mode = match self.model {
+                    Model::C01 if self.byte & 0x80 != 0 => {
+                        self.address = self.byte & 0x7f;
+                        self.load_for_read()
+                    }
+                    Model::C01 => {
+                        self.address = self.byte & 0x7f;
+                        Mode::Write
+                    }
+                    Model::C02 => {
+                        self.address = self.byte;
+                        Mode::Write
+                    }
+                };
+                self.mode = Mode::SendAck;
+            }
+            Mode::Write => {
+                let address = self.address as usize % self.data.len();
+                self.data[address] = self.byte;
+                self.advance();
+                self.next_mode = Mode::Write;
+                self.mode = Mode::SendAck;
+            }
+            Mode::Read => {
+                self.advance();
+                self.mode = Mode::WaitAck;
+            }
+            Mode::Idle => {}
+        }
+    }
+
+    // Fetches the byte at the current address to shift out, and returns the mode that does.
+    fn load_for_read(&mut self) -> Mode {
+        self.byte = self.data[self.address as usize % self.data.len()];
+        Mode::Read
+    }
+
+    fn advance(&mut self) {
+        self.address = ((self.address as usize + 1) % self.data.len()) as u8;
+    }
+}
+
+impl Chip for Eeprom {
+    fn name(&self) -> &'static str {
+        match self.model {
+            Model::C01 => "24c01",
+            Model::C02 => "24c02",
+        }
+    }
+
+    fn set_lines(&mut self, scl: bool, sda: bool) {
+        self.write(scl, sda);
+    }
+
+    fn sda(&self) -> bool {
+        self.read()
+    }
+
+    fn persistent(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    fn restore(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != self.data.len() {
+            return Err(format!("A {} holds {} bytes, not {}", self.name(), self.data.len(), data.len()));
+        }
+        self.data.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.vec(&self.data);
+        state.u8(self.mode.index());
+        state.u8(self.next_mode.index());
+        state.u8(self.bit);
+        state.u8(self.byte);
+        state.u8(self.address);
+        state.bool(self.output);
+        state.bool(self.scl);
+        state.bool(self.sda);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.vec(&mut self.data)?;
+        self.mode = Mode::from_index(state.u8()?)?;
+        self.next_mode = Mode::from_index(state.u8()?)?;
+        self.bit = state.u8()?;
+        self.byte = state.u8()?;
+        self.address = state.u8()?;
+        self.output = state.bool()?;
+        self.scl = state.bool()?;
+        self.sda = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Drives the bus the way a game's mapper writes would.
+    struct Bus {
+        eeprom: Eeprom,
+        msb_first: bool,
+    }
+
+    impl Bus {
+        fn new(model: Model) -> Self {
+            Bus { eeprom: Eeprom::new(model), msb_first: model == Model::C02 }
+        }
+
+        fn start(&mut self) {
+            self.eeprom.write(false, true);
+            self.eeprom.write(true, true);
+            self.eeprom.write(true, false);
+            self.eeprom.write(false, false);
+        }
+
+        fn stop(&mut self) {
+            self.eeprom.write(false, false);
+            self.eeprom.write(true, false);
+            self.eeprom.write(true, true);
+        }
+
+        // One clock pulse with SDA at `sda`, returning SDA while the clock is high.
+        fn clock(&mut self, sda: bool) -> bool {
+            self.eeprom.write(false, sda);
+            self.eeprom.write(true, sda);
+            let read = self.eeprom.read() && sda;
+            self.eeprom.write(false, sda);
+            read
+        }
+
+        // Sends a byte and returns whether it was acknowledged.
+        fn send(&mut self, byte: u8) -> bool {
+            for i in 0..8 {
+                let shift = if self.msb_first { 7 - i } else { i };
+                self.clock(byte >> shift & 1 == 1);
+            }
+            !self.clock(true)
+        }
+
+        fn receive(&mut self, ack: bool) -> u8 {
+            let mut byte = 0;
+            for i in 0..8 {
+                let shift = if self.msb_first { 7 - i } else { i };
+                byte |= (self.clock(true) as u8) << shift;
+            }
+            self.clock(!ack);
+            byte
+        }
+    }
+
+    #[test]
+    fn test_24c02_write_then_random_read() {
+        let mut bus = Bus::new(Model::C02);
+        bus.start();
+        assert!(bus.send(0xa0));
+        assert!(bus.send(0x10));
+        assert!(bus.send(0x12));
+        assert!(bus.send(0x34));
+        bus.stop();
+        assert_eq!(bus.eeprom.data[0x10..0x12], [0x12, 0x34]);
+
+        bus.start();
+        assert!(bus.send(0xa0));
+        assert!(bus.send(0x10));
+        bus.start();
+        assert!(bus.send(0xa1));
+        assert_eq!(bus.receive(true), 0x12);
+        assert_eq!(bus.receive(false), 0x34);
+        bus.stop();
+
+        // Nothing answers another chip's address.
+        bus.start();
+        assert!(!bus.send(0xb0));
+    }
+
+    #[test]
+    fn test_24c01_write_then_read() {
+        let mut bus = Bus::new(Model::C01);
+        bus.start();
+        assert!(bus.send(0x05));
+        assert!(bus.send(0xc3));
+        bus.stop();
+        assert_eq!(bus.eeprom.data[5], 0xc3);
+
+        bus.start();
+        assert!(bus.send(0x80 | 0x05));
+        assert_eq!(bus.receive(false), 0xc3);
+        bus.stop();
+
+        let mut state = StateWriter::new();
+        bus.eeprom.save_state(&mut state);
+        let bytes = state.into_bytes();
+        let mut restored = Eeprom::new(Model::C01);
+        restored.load_state(&mut StateReader::new(&bytes)).unwrap();
+        assert_eq!(restored.persistent(), bus.eeprom.persistent());
+    }
+}
@@ -0,0 +1,100 @@
+//! Chips on a cartridge board besides the ROMs and the mapper, like the serial EEPROMs Bandai's FCG
+//! boards save to, or a real-time clock. The mapper wires its registers to a chip's pins; the chip
+//! keeps what it stores while the power is off.
+//!
+//! What each chip stores is kept in a file of its own, `<name>.sav` in the game's save state
+//! directory: `save` writes them, when a game is closed, and `load` reads them back when it is
+//! loaded. Save states carry the chips too, contents included, in the "CHIP" section.
+//!
+//! Bandai's FCG boards (see `fcg`) are the only ones with a chip so far: a 24C02 on mapper 16 and
+//! a 24C01 on mapper 159. Another mapper with one lists it in `for_cartridge`.
+
+pub mod eeprom;
+
+use std::path::Path;
+
+use crate::cartridge::Cartridge;
+use crate::fcg;
+use eeprom::{Eeprom, Model};
+use crate::savestate::{StateReader, StateWriter};
+
+pub trait Chip: Send {
+    // A short name for messages, which also names its file ("24c02" saves to "24c02.sav").
+    fn name(&self) -> &'static str;
+
+    // The chip's end of a two-wire serial bus, for the mapper to set the clock (SCL) and data
+    // (SDA) lines and read back SDA as the chip drives it. A chip on no such bus ignores them.
+    fn set_lines(&mut self, _scl: bool, _sda: bool) {}
+    fn sda(&self) -> bool {
+        true
+    }
+
+    // What the chip keeps without power, and putting it back, as read from its file.
+    fn persistent(&self) -> Vec<u8>;
+    fn restore(&mut self, data: &[u8]) -> Result<(), String>;
+
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String>;
+}
+
+// The chips on `cartridge`'s board, blank.
+pub fn for_cartridge(cartridge: &Cartridge) -> Vec<Box<dyn Chip>> {
+    match cartridge.mapper {
+        fcg::MAPPER_24C02 => vec![Box::new(Eeprom::new(Model::C02))],
+        fcg::MAPPER_24C01 => vec![Box::new(Eeprom::new(Model::C01))],
+        _ => Vec::new(),
+    }
+}
+
+// Writes each chip's contents to its file in `dir`.
+pub fn save(dir: &Path, chips: &[Box<dyn Chip>]) -> Result<(), String> {
+    if chips.is_empty() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    for chip in chips {
+        let path = dir.join(format!("{}.sav", chip.name()));
+        std::fs::write(&path, chip.persistent()).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+// Reads each chip's contents back from its file in `dir`. A chip without a file stays blank.
+pub fn load(dir: &Path, chips: &mut [Box<dyn Chip>]) -> Result<(), String> {
+    for chip in chips {
+        let path = dir.join(format!("{}.sav", chip.name()));
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        };
+        chip.restore(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chips_persist_in_files() {
+        let dir = std::env::temp_dir().join(format!("nes_rs_chips_{}", std::process::id()));
+        let mut eeprom = Eeprom::new(Model::C02);
+        eeprom.restore(&[7; 256]).unwrap();
+        let chips: Vec<Box<dyn Chip>> = vec![Box::new(eeprom)];
+        save(&dir, &chips).unwrap();
+
+        let mut loaded: Vec<Box<dyn Chip>> = vec![Box::new(Eeprom::new(Model::C02))];
+        load(&dir, &mut loaded).unwrap();
+        assert_eq!(loaded[0].persistent(), [7; 256]);
+
+        // A file of the wrong size is refused, and a missing one leaves the chip blank.
+        let mut other: Vec<Box<dyn Chip>> = vec![Box::new(Eeprom::new(Model::C01))];
+        std::fs::rename(dir.join("24c02.sav"), dir.join("24c01.sav")).unwrap();
+        assert!(load(&dir, &mut other).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+        load(&dir, &mut other).unwrap();
+        assert_eq!(other[0].persistent(), [0; 128]);
+    }
+}
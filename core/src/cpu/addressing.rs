@@ -1,7 +1,7 @@
 use crate::cpu::CPU;
 use crate::cpu::Mem;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
     Immediate,
@@ -23,10 +23,26 @@ impl CPU {
     pub fn get_operand_address(&mut self, mode: &AddressingMode) -> (u16, bool) {
         match mode {
             AddressingMode::Immediate => (self.program_counter, false),
-            _ => self.get_absolute_address(mode, self.program_counter),
+            _ => {
+                let (addr, page_crossed) = self.get_absolute_address(mode, self.program_counter);
+                if page_crossed && self.bus.accuracy.dummy_accesses {
+                    self.dummy_read(mode, addr);
+                }
+                (addr, page_crossed)
+            }
         }
     }
 
+    // The read an indexed access makes before the carry reaches the high byte of the address,
+    // from the page below the right one.
+    fn dummy_read(&mut self, mode: &AddressingMode, addr: u16) {
+        let index = match mode {
+            AddressingMode::Absolute_X => self.register_x,
+            _ => self.register_y,
+        };
+        self.mem_read(addr.wrapping_sub(index as u16) & 0xff00 | addr & 0x00ff);
+    }
+
     // Returns whether or not a page was crossed when adding something to a that results in b.
     // Checks if the high byte is different.
     pub fn page_cross(a: u16, b: u16) -> bool {
@@ -0,0 +1,186 @@
+//! Experimental cached interpreter, built with the `experimental-jit` feature.
+//!
+//! Code is decoded a basic block at a time: from an entry point up to the first instruction that
+//! can jump (a branch, JMP, JSR, RTS, RTI or BRK), or to the end of its 256-byte page. After that
+//! the CPU takes each instruction's operation straight from the block instead of fetching and
+//! looking up its opcode. Instructions still run one at a time, with interrupts and debugger
+//! callbacks between them, so timing and behavior are exactly the interpreter's.
+//!
+//! The bus counts writes to every page of memory. A block remembers its page's count when it was
+//! decoded and is decoded again once that changes, which is how self-modifying code, code copied
+//! into RAM and loaded save states are caught. Anything that watches opcode fetches (a code/data
+//! log, watchpoints, the event viewer, Game Genie codes) turns the cache off while it is active.
+
+use std::collections::HashMap;
+
+use crate::bus::Bus;
+use crate::cpu::opcodes::{Dispatch, DISPATCH};
+use crate::cpu::operations::Operation;
+
+// Longest block, so a page of straight-line code doesn't become one huge block.
+const MAX_BLOCK: usize = 64;
+
+// Write counts for each page of CPU memory. RAM mirrors share their page's count.
+#[derive(Clone)]
+pub struct CodeWrites {
+    pages: [u32; 256],
+}
+
+impl Default for CodeWrites {
+    fn default() -> Self {
+        CodeWrites { pages: [0; 256] }
+    }
+}
+
+impl CodeWrites {
+    fn page(addr: u16) -> usize {
+        match addr {
+            0x0000..=0x1fff => ((addr & 0x07ff) >> 8) as usize,
+            _ => (addr >> 8) as usize,
+        }
+    }
+
+    pub fn written(&mut self, addr: u16) {
+        let page = &mut self.pages[Self::page(addr)];
+        *page = page.wrapping_add(1);
+    }
+
+    // For changes that can touch any page, like loading a state.
+    pub fn written_everywhere(&mut self) {
+        for page in &mut self.pages {
+            *page = page.wrapping_add(1);
+        }
+    }
+
+    fn generation(&self, addr: u16) -> u32 {
+        self.pages[Self::page(addr)]
+    }
+}
+
+struct Block {
+    // The page's write count when the block was decoded.
+    generation: u32,
+    instructions: Vec<(u16, &'static Dispatch)>,
+}
+
+impl Block {
+    fn decode(start: u16, generation: u32, bus: &Bus) -> Block {
+        let mut instructions = Vec::new();
+        let mut addr = start;
+        while instructions.len() < MAX_BLOCK && addr >> 8 == start >> 8 {
+            let Some(dispatch) = &DISPATCH[bus.peek(addr) as usize] else {
+                break;
+            };
+            instructions.push((addr, dispatch));
+            if ends_block(dispatch.opcode.op) {
+                break;
+            }
+            addr = addr.wrapping_add(dispatch.opcode.bytes as u16);
+        }
+        Block { generation, instructions }
+    }
+}
+
+fn ends_block(op: Operation) -> bool {
+    use Operation::*;
+    matches!(op, BCC | BCS | BEQ | BMI | BNE | BPL | BVC | BVS | JMP | JSR | RTS | RTI | BRK)
+}
+
+// Whether fetches from `addr` read plain memory: RAM, PRG-RAM, the FDS's RAM or ROM, but not
+// registers.
+fn cacheable(addr: u16) -> bool {
+    !(0x2000..0x6000).contains(&addr)
+}
+
+// Whether something wants to see every opcode fetch, or changes what it returns.
+fn watched(bus: &Bus) -> bool {
+    bus.cdl.is_some()
+        || bus.events.is_some()
+        || bus.access_log.is_some()
+        || bus.flat_ram.is_some()
+        || !bus.watchpoints.is_empty()
+        || !bus.cheats.list().is_empty()
+}
+
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: Vec<Block>,
+    starts: HashMap<u16, usize>,
+    // The block being run and the index of its next instruction.
+    cursor: Option<(usize, usize)>,
+    // Blocks decoded, including ones decoded again after a write.
+    pub decoded: u64,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        BlockCache::default()
+    }
+
+    // The operation of the instruction at `pc`, or None when the interpreter should fetch it
+    // itself (an uncacheable address, an opcode it won't run, or the cache being off).
+    pub fn fetch(&mut self, pc: u16, bus: &Bus) -> Option<&'static Dispatch> {
+        if !cacheable(pc) || watched(bus) {
+            self.cursor = None;
+            return None;
+        }
+        let generation = bus.code_writes.generation(pc);
+
+        // Most of the time the instruction is the next one in the block being run.
+        if let Some((block, index)) = self.cursor {
+            let block_ref = &self.blocks[block];
+            if let Some(&(addr, dispatch)) = block_ref.instructions.get(index) {
+                if addr == pc && block_ref.generation == generation {
+                    self.cursor = Some((block, index + 1));
+                    return Some(dispatch);
+                }
+            }
+        }
+
+        let block = match self.starts.get(&pc) {
+            Some(&block) if self.blocks[block].generation == generation => block,
+            Some(&block) => {
+                self.blocks[block] = Block::decode(pc, generation, bus);
+                self.decoded += 1;
+                block
+            }
+            None => {
+                self.blocks.push(Block::decode(pc, generation, bus));
+                self.starts.insert(pc, self.blocks.len() - 1);
+                self.decoded += 1;
+                self.blocks.len() - 1
+            }
+        };
+        self.cursor = Some((block, 1));
+        self.blocks[block].instructions.first().map(|&(_, dispatch)| dispatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::{Mem, CPU};
+    use crate::bus::Bus;
+    use crate::cartridge::test::create_test_cartridge;
+
+    #[test]
+    fn test_self_modifying_code_is_decoded_again() {
+        let mut cpu = CPU::new(Bus::new(create_test_cartridge()));
+        // INX, then a jump back to the start, twice; then the INX is patched into an INY.
+        cpu.load(vec![0xe8, 0x4c, 0x00, 0x06]);
+        cpu.program_counter = 0x0600;
+        for _ in 0..4 {
+            cpu.execute_instruction();
+        }
+        assert_eq!((cpu.register_x, cpu.block_cache.decoded), (2, 1));
+
+        cpu.mem_write(0x0600, 0xc8);
+        cpu.execute_instruction();
+        assert_eq!((cpu.register_x, cpu.register_y, cpu.block_cache.decoded), (2, 1, 2));
+
+        // A write to a mirror of the page counts too.
+        cpu.execute_instruction();
+        cpu.mem_write(0x1600, 0xe8);
+        cpu.execute_instruction();
+        assert_eq!((cpu.register_x, cpu.block_cache.decoded), (3, 3));
+    }
+}
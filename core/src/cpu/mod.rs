@@ -0,0 +1,332 @@
+//! 6502 CPU implementation
+//!
+//! <http://wiki.nesdev.com/w/index.php/CPU>
+
+use crate::cartridge::Cartridge;
+use crate::cpu::operations::Operation;
+use crate::bus::Bus;
+use crate::savestate::{StateReader, StateWriter};
+use crate::cpu::opcodes::{Dispatch, DISPATCH};
+use crate::cpu::addressing::AddressingMode;
+
+pub mod trace;
+pub mod operations;
+pub mod opcodes;
+pub mod addressing;
+#[cfg(feature = "experimental-jit")]
+pub mod block_cache;
+
+const NMI_VECTOR: u16 = 0xfffa;
+const IRQ_VECTOR: u16 = 0xfffe;
+
+// Status flags -- https://www.nesdev.org/wiki/Status_flags
+// 7654 3210
+// NV0B DIZC
+// |||| ||||
+// |||| |||+- Carry
+// |||| ||+-- Zero
+// |||| |+--- Interrupt Disable
+// |||| +---- Decimal
+// |||+------ (No CPU effect; see: the B flag)
+// ||+------- (No CPU effect; always pushed as 1)
+// |+-------- Overflow
+// +--------- Negative
+bitflags! {
+    #[derive(Clone)]
+    pub struct CPUFlags: u8 {
+        const CARRY             = 1 << 0;
+        const ZERO              = 1 << 1;
+        const INTERRUPT_DISABLE = 1 << 2;
+        const DECIMAL_MODE      = 1 << 3;
+        const BREAK             = 1 << 4;
+        const BREAK2            = 1 << 5; // not used, default = 1
+        const OVERFLOW          = 1 << 6;
+        const NEGATIVE          = 1 << 7;
+    }
+}
+
+pub struct CPU {
+    pub register_a: u8,
+    pub status: CPUFlags,
+    pub register_x: u8,
+    pub register_y: u8,
+    pub program_counter: u16,
+    pub stack_pointer: u8,
+    pub bus: Bus,
+    #[cfg(feature = "experimental-jit")]
+    pub block_cache: block_cache::BlockCache,
+}
+
+// Stack occupied 0x0100 -> 0x01FF
+const STACK: u16 = 0x0100;
+// STACK + STACK_RESET is "top" of stack
+const STACK_RESET: u8 = 0xfd;
+
+pub trait Mem {
+    fn mem_read(&mut self, addr: u16) -> u8;
+
+    fn mem_write(&mut self, addr: u16, data: u8);
+
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        let lo = self.mem_read(pos) as u16;
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    fn mem_write_u16(&mut self, pos: u16, data: u16) {
+        let hi = (data >> 8) as u8;
+        let lo = (data & 0xff) as u8;
+        self.mem_write(pos, lo);
+        self.mem_write(pos.wrapping_add(1), hi);
+    }
+}
+
+impl Mem for CPU {
+    // This is a mut self because we need to increment VRAM address in PPU
+    #[inline]
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.bus.mem_read(addr)
+    }
+
+    #[inline]
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.bus.mem_write(addr, data)
+    }
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        self.bus.mem_read_u16(pos)
+    }
+
+    fn mem_write_u16(&mut self, pos: u16, data: u16) {
+        self.bus.mem_write_u16(pos, data)
+    }
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        CPU {
+            register_a: 0,
+            register_x: 0,
+            register_y: 0,
+            bus: Bus::default(Cartridge::default()),
+            program_counter: 0,
+            stack_pointer: STACK_RESET,
+            // Interrupt disable (bit 2) and the unused (bit 5) initialized by default
+            status: CPUFlags::from_bits_truncate(0b100100),
+            #[cfg(feature = "experimental-jit")]
+            block_cache: block_cache::BlockCache::new(),
+        }
+    }
+}
+
+impl CPU {
+    pub fn new(bus: Bus) -> Self {
+        CPU {
+            register_a: 0,
+            register_x: 0,
+            register_y: 0,
+            bus,
+            program_counter: 0,
+            stack_pointer: STACK_RESET,
+            // Interrupt disable (bit 2) and the unused (bit 5) initialized by default
+            status: CPUFlags::from_bits_truncate(0b100100),
+            #[cfg(feature = "experimental-jit")]
+            block_cache: block_cache::BlockCache::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.register_a = 0;
+        self.register_x = 0;
+        self.register_y = 0;
+        self.stack_pointer = STACK_RESET;
+        self.status = CPUFlags::from_bits_truncate(0b100100);
+
+        self.program_counter = self.mem_read_u16(0xFFFC);
+    }
+
+    pub fn load(&mut self, program: Vec<u8>) {
+        // 0x8000 to 0xFFFF stores program ROM
+        for i in 0..(program.len() as u16) {
+            self.mem_write(0x0600 + i, program[i as usize]);
+        }
+        // self.mem_write_u16(0xFFFC, 0x8000);
+    }
+
+    pub fn load_and_run(&mut self, program: Vec<u8>) {
+        self.load(program);
+        self.reset();
+        self.run();
+    }
+
+    fn stack_pop(&mut self) -> u8 {
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.mem_read(STACK + self.stack_pointer as u16)
+    }
+
+    fn stack_push(&mut self, data: u8) {
+        self.mem_write(STACK + self.stack_pointer as u16, data);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1)
+    }
+
+    fn stack_push_u16(&mut self, data: u16) {
+        let hi = (data >> 8) as u8;
+        let lo = (data & 0xff) as u8;
+        self.stack_push(hi);
+        self.stack_push(lo);
+    }
+
+    fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop() as u16;
+        let hi = self.stack_pop() as u16;
+
+        hi << 8 | lo
+    }
+
+    fn set_register_a(&mut self, value: u8) {
+        self.register_a = value;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    /// note: NES ignores decimal mode, unlike most 6502 processors
+    /// http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
+    fn add_to_register_a(&mut self, data: u8) {
+        let sum = self.register_a as u16
+            + data as u16
+            + (if self.status.contains(CPUFlags::CARRY) {
+                1
+            } else {
+                0
+            }) as u16;
+
+        self.status.set(CPUFlags::CARRY, sum > 0xff);
+
+        let result = sum as u8;
+
+        self.status.set(
+            CPUFlags::OVERFLOW,
+            (data ^ result) & (result ^ self.register_a) & 0x80 != 0,
+        );
+
+        self.set_register_a(result);
+    }
+
+    fn update_zero_and_negative_flags(&mut self, result: u8) {
+        self.status.set(CPUFlags::ZERO, result == 0);
+        self.status
+            .set(CPUFlags::NEGATIVE, result & 0b1000_0000 != 0);
+    }
+
+    pub fn run(&mut self) {
+        self.run_with_callback(|_| {});
+    }
+
+    // Reference; https://www.nesdev.org/wiki/The_frame_and_NMIs
+    fn interrupt_nmi(&mut self) {
+        self.interrupt(NMI_VECTOR);
+    }
+
+    // Pushes the return address and the status, with B clear, and jumps through `vector`, as NMI
+    // and IRQ do.
+    fn interrupt(&mut self, vector: u16) {
+        self.stack_push_u16(self.program_counter);
+
+        let mut flag = self.status.clone();
+        flag.set(CPUFlags::BREAK, false);
+        flag.set(CPUFlags::BREAK2, true);
+
+        self.stack_push(flag.bits());
+        self.status.insert(CPUFlags::INTERRUPT_DISABLE);
+
+        self.bus.tick(2);
+        self.program_counter = self.mem_read_u16(vector);
+    }
+
+    pub fn run_with_callback<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&mut CPU),
+    {
+        while self.step_with_callback(&mut callback) {}
+    }
+
+    // Executes a single instruction, servicing a pending NMI first. Returns false on BRK or an unknown opcode, which we
+    // treat as program termination.
+    pub fn step_with_callback<F>(&mut self, mut callback: F) -> bool
+    where
+        F: FnMut(&mut CPU),
+    {
+        self.service_interrupts();
+        callback(self);
+        self.execute_instruction()
+    }
+
+    // Jumps to the NMI handler if the PPU raised one, returning whether it did, or otherwise to the
+    // IRQ handler if the IRQ line is held and interrupts are enabled. Afterwards the program
+    // counter points at the instruction that will actually run next.
+    pub fn service_interrupts(&mut self) -> bool {
+        let nmi = self.bus.pull_nmi_status().is_some();
+        if nmi {
+            tracing::trace!(target: "cpu", "NMI at ${:04x}", self.program_counter);
+            self.interrupt_nmi();
+        } else if self.bus.irq() && !self.status.contains(CPUFlags::INTERRUPT_DISABLE) {
+            tracing::trace!(target: "cpu", "IRQ at ${:04x}", self.program_counter);
+            self.interrupt(IRQ_VECTOR);
+        }
+        nmi
+    }
+
+    // Executes the instruction at the program counter, without checking for interrupts. Returns
+    // false on BRK, and on the opcodes we don't run (the JAMs, which lock the real CPU up, and a
+    // few unstable ones).
+    pub fn execute_instruction(&mut self) -> bool {
+        #[cfg(feature = "experimental-jit")]
+        if let Some(entry) = self.block_cache.fetch(self.program_counter, &self.bus) {
+            self.program_counter = self.program_counter.wrapping_add(1);
+            return self.execute(entry);
+        }
+
+        if self.bus.cdl.is_some() {
+            self.bus.log_instruction(self.program_counter);
+        }
+        let code = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+
+        // BRK isn't in the table, so it stops here too, leaving the CPU as it was.
+        let Some(entry) = &DISPATCH[code as usize] else {
+            return false;
+        };
+        self.execute(entry)
+    }
+
+    // Runs a fetched instruction, with the program counter just past its opcode.
+    fn execute(&mut self, entry: &Dispatch) -> bool {
+        let opcode = entry.opcode;
+
+        (entry.execute)(self, &opcode.addressing_mode);
+
+        // -1 because we already incremented program_counter to account for the instruction
+        self.program_counter = self.program_counter.wrapping_add((opcode.bytes - 1) as u16);
+
+        self.bus.tick(opcode.cycles);
+        true
+    }
+
+    // The registers. The bus saves its own section.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.register_a);
+        state.u8(self.register_x);
+        state.u8(self.register_y);
+        state.u8(self.status.bits());
+        state.u16(self.program_counter);
+        state.u8(self.stack_pointer);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.register_a = state.u8()?;
+        self.register_x = state.u8()?;
+        self.register_y = state.u8()?;
+        self.status = CPUFlags::from_bits_retain(state.u8()?);
+        self.program_counter = state.u16()?;
+        self.stack_pointer = state.u8()?;
+        Ok(())
+    }
+}
@@ -4,6 +4,7 @@
 
 use crate::cpu::AddressingMode;
 use crate::cpu::Operation;
+use crate::cpu::operations::Execute;
 use std::collections::HashMap;
 pub struct OpCode {
     pub code: u8,
@@ -31,6 +32,12 @@ impl OpCode {
     }
 }
 
+// An opcode with its operation's logic, ready to run.
+pub struct Dispatch {
+    pub opcode: &'static OpCode,
+    pub execute: Execute,
+}
+
 lazy_static! {
     pub static ref CPU_OPS_CODES: Vec<OpCode> = vec![
         OpCode::new(0x69, Operation::ADC, 2, 2, AddressingMode::Immediate),
@@ -345,6 +352,18 @@ lazy_static! {
         ];
 
 
+    // Indexed by opcode, so running an instruction is a lookup and a call. BRK and the opcodes we
+    // don't run are None.
+    pub static ref DISPATCH: Vec<Option<Dispatch>> = {
+        let mut table: Vec<Option<Dispatch>> = (0..256).map(|_| None).collect();
+        for opcode in &*CPU_OPS_CODES {
+            if let Some(execute) = opcode.op.execute() {
+                table[opcode.code as usize] = Some(Dispatch { opcode, execute });
+            }
+        }
+        table
+    };
+
     pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
         let mut map = HashMap::new();
         for cpuop in &*CPU_OPS_CODES {
@@ -8,7 +8,7 @@ use crate::cpu::addressing::AddressingMode;
 use crate::cpu::Mem;
 use crate::cpu::CPUFlags;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum Operation {
     ADC, AND, ASL, BCC, BCS, BEQ, BIT, BMI, BNE, BPL, BRK, BVC, BVS, CLC,
@@ -25,6 +25,113 @@ impl fmt::Display for Operation {
     }
 }
 
+// What an instruction does, given its addressing mode.
+pub type Execute = fn(&mut CPU, &AddressingMode);
+
+impl Operation {
+    // The logic of this operation, looked up once when the dispatch table is built rather than
+    // matched on every instruction. BRK has none: we treat it as the end of the program.
+    pub fn execute(self) -> Option<Execute> {
+        let execute: Execute = match self {
+            Operation::ADC => |cpu, mode| cpu.adc(mode, true),
+            Operation::ALR => |cpu, mode| {
+                cpu.and(mode, false);
+                cpu.lsr(mode);
+            },
+            Operation::ANC => |cpu, mode| cpu.anc(mode),
+            Operation::AND => |cpu, mode| cpu.and(mode, true),
+            Operation::ARR => |cpu, mode| cpu.arr(mode),
+            Operation::ASL => |cpu, mode| cpu.asl(mode),
+            Operation::BCC => |cpu, _| cpu.branch(!cpu.status.contains(CPUFlags::CARRY)),
+            Operation::BCS => |cpu, _| cpu.branch(cpu.status.contains(CPUFlags::CARRY)),
+            Operation::BEQ => |cpu, _| cpu.branch(cpu.status.contains(CPUFlags::ZERO)),
+            Operation::BIT => |cpu, mode| cpu.bit(mode),
+            Operation::BMI => |cpu, _| cpu.branch(cpu.status.contains(CPUFlags::NEGATIVE)),
+            Operation::BNE => |cpu, _| cpu.branch(!cpu.status.contains(CPUFlags::ZERO)),
+            Operation::BPL => |cpu, _| cpu.branch(!cpu.status.contains(CPUFlags::NEGATIVE)),
+            Operation::BRK => return None,
+            Operation::BVC => |cpu, _| cpu.branch(!cpu.status.contains(CPUFlags::OVERFLOW)),
+            Operation::BVS => |cpu, _| cpu.branch(cpu.status.contains(CPUFlags::OVERFLOW)),
+            Operation::CLC => |cpu, _| cpu.status.remove(CPUFlags::CARRY),
+            Operation::CLD => |cpu, _| cpu.status.remove(CPUFlags::DECIMAL_MODE),
+            Operation::CLI => |cpu, _| cpu.status.remove(CPUFlags::INTERRUPT_DISABLE),
+            Operation::CLV => |cpu, _| cpu.status.remove(CPUFlags::OVERFLOW),
+            Operation::CMP => |cpu, mode| cpu.compare(mode, cpu.register_a, true),
+            Operation::CPX => |cpu, mode| cpu.compare(mode, cpu.register_x, true),
+            Operation::CPY => |cpu, mode| cpu.compare(mode, cpu.register_y, true),
+            Operation::DCP => |cpu, mode| {
+                cpu.dec(mode);
+                cpu.compare(mode, cpu.register_a, false);
+            },
+            Operation::DEC => |cpu, mode| cpu.dec(mode),
+            Operation::DEX => |cpu, _| cpu.dex(),
+            Operation::DEY => |cpu, _| cpu.dey(),
+            Operation::EOR => |cpu, mode| cpu.eor(mode, true),
+            Operation::INC => |cpu, mode| cpu.inc(mode),
+            Operation::INX => |cpu, _| cpu.inx(),
+            Operation::INY => |cpu, _| cpu.iny(),
+            Operation::ISB => |cpu, mode| {
+                cpu.inc(mode);
+                cpu.sbc(mode, false);
+            },
+            Operation::JMP => |cpu, mode| cpu.jmp(mode),
+            Operation::JSR => |cpu, _| cpu.jsr(),
+            Operation::LAX => |cpu, mode| {
+                cpu.lda(mode);
+                cpu.tax();
+            },
+            Operation::LDA => |cpu, mode| cpu.lda(mode),
+            Operation::LDX => |cpu, mode| cpu.ldx(mode),
+            Operation::LDY => |cpu, mode| cpu.ldy(mode),
+            Operation::LSR => |cpu, mode| cpu.lsr(mode),
+            Operation::NOP => |cpu, mode| cpu.nop(mode),
+            Operation::ORA => |cpu, mode| cpu.ora(mode, true),
+            Operation::PHA => |cpu, _| cpu.stack_push(cpu.register_a),
+            Operation::PHP => |cpu, _| cpu.stack_push(cpu.status.bits() | 0b0011_0000), // set break flag and bit 5 to be 1
+            Operation::PLA => |cpu, _| cpu.pla(),
+            Operation::PLP => |cpu, _| cpu.plp(),
+            Operation::ROL => |cpu, mode| cpu.rol(mode),
+            Operation::ROR => |cpu, mode| cpu.ror(mode),
+            Operation::RLA => |cpu, mode| {
+                cpu.rol(mode);
+                cpu.and(mode, false);
+            },
+            Operation::RRA => |cpu, mode| {
+                cpu.ror(mode);
+                cpu.adc(mode, false);
+            },
+            Operation::RTI => |cpu, _| {
+                cpu.plp();
+                cpu.program_counter = cpu.stack_pop_u16();
+            },
+            Operation::RTS => |cpu, _| cpu.program_counter = cpu.stack_pop_u16().wrapping_add(1),
+            Operation::SAX => |cpu, mode| cpu.sax(mode),
+            Operation::SBC => |cpu, mode| cpu.sbc(mode, true),
+            Operation::SEC => |cpu, _| cpu.status.insert(CPUFlags::CARRY),
+            Operation::SED => |cpu, _| cpu.status.insert(CPUFlags::DECIMAL_MODE),
+            Operation::SEI => |cpu, _| cpu.sei(),
+            Operation::SLO => |cpu, mode| {
+                cpu.asl(mode);
+                cpu.ora(mode, false);
+            },
+            Operation::SRE => |cpu, mode| {
+                cpu.lsr(mode);
+                cpu.eor(mode, false);
+            },
+            Operation::STA => |cpu, mode| cpu.sta(mode),
+            Operation::STX => |cpu, mode| cpu.stx(mode),
+            Operation::STY => |cpu, mode| cpu.sty(mode),
+            Operation::TAX => |cpu, _| cpu.tax(),
+            Operation::TAY => |cpu, _| cpu.tay(),
+            Operation::TSX => |cpu, _| cpu.tsx(),
+            Operation::TXA => |cpu, _| cpu.txa(),
+            Operation::TXS => |cpu, _| cpu.stack_pointer = cpu.register_x,
+            Operation::TYA => |cpu, _| cpu.tya(),
+        };
+        Some(execute)
+    }
+}
+
 
 impl CPU {
     // Add with carry
@@ -54,6 +161,13 @@ impl CPU {
         }
     }
 
+    // Read-modify-write instructions write the value they read straight back, then the result.
+    fn dummy_write(&mut self, addr: u16, data: u8) {
+        if self.bus.accuracy.dummy_accesses {
+            self.mem_write(addr, data);
+        }
+    }
+
     // Arithmetic shift left
     pub fn asl(&mut self, mode: &AddressingMode) {
         let mut data;
@@ -65,6 +179,7 @@ impl CPU {
             _ => {
                 addr = Some(self.get_operand_address(mode).0);
                 data = self.mem_read(addr.unwrap());
+                self.dummy_write(addr.unwrap(), data);
             }
         }
         self.status.set(CPUFlags::CARRY, data >> 7 == 1);
@@ -150,7 +265,9 @@ impl CPU {
     // DECrement memory
     pub fn dec(&mut self, mode: &AddressingMode) {
         let (addr, _) = self.get_operand_address(mode);
-        let val = self.mem_read(addr).wrapping_sub(1);
+        let val = self.mem_read(addr);
+        self.dummy_write(addr, val);
+        let val = val.wrapping_sub(1);
 
         self.mem_write(addr, val);
         self.update_zero_and_negative_flags(val);
@@ -273,6 +390,7 @@ impl CPU {
             _ => {
                 addr = Some(self.get_operand_address(mode).0);
                 data = self.mem_read(addr.unwrap());
+                self.dummy_write(addr.unwrap(), data);
             }
         }
         self.status.set(CPUFlags::CARRY, data & 1 == 1);
@@ -367,6 +485,7 @@ impl CPU {
     pub fn inc(&mut self, mode: &AddressingMode) {
         let (addr, _) = self.get_operand_address(mode);
         let val = self.mem_read(addr);
+        self.dummy_write(addr, val);
 
         self.mem_write(addr, val.wrapping_add(1));
         self.update_zero_and_negative_flags(val.wrapping_add(1));
@@ -393,6 +512,7 @@ impl CPU {
             _ => {
                 addr = Some(self.get_operand_address(mode).0);
                 data = self.mem_read(addr.unwrap());
+                self.dummy_write(addr.unwrap(), data);
             }
         }
 
@@ -425,6 +545,7 @@ impl CPU {
             _ => {
                 addr = Some(self.get_operand_address(mode).0);
                 data = self.mem_read(addr.unwrap());
+                self.dummy_write(addr.unwrap(), data);
             }
         }
 
@@ -34,7 +34,7 @@ impl Bus {
 
             0x2005 => {
                 // TODO: implement scroll get
-                println!("dummy");
+                tracing::debug!(target: "cpu", "${:04x} can't be read for a trace", addr);
                 42
             },
 
@@ -42,24 +42,26 @@ impl Bus {
 
             // TODO: implement PPUDATA debug
             0x2007 => { 
-                println!("dummy");
+                tracing::debug!(target: "cpu", "${:04x} can't be read for a trace", addr);
                 42
             }
 
             // TODO: implement OAMDATA debug
             0x4014 => {
-                println!("dummy");
+                tracing::debug!(target: "cpu", "${:04x} can't be read for a trace", addr);
                 42
             }
 
             0x4016 => self.joypad.button_status.bits() as u16,
 
+            0x4017 => self.joypad2.button_status.bits() as u16,
+
             PPU_MIRRORS_START..=PPU_MIRRORS_END => {
                 // Mirrors $2008 - $4000 into $2000 - $2008
                 // let mirror_down_addr = addr & 0b00100000_00000111;
                 // self.mem_read(mirror_down_addr)
                 // TODO: fix this lol
-                println!("dummy");
+                tracing::debug!(target: "cpu", "${:04x} can't be read for a trace", addr);
                 42
       
             },
@@ -69,7 +71,7 @@ impl Bus {
             PRG_ROM_START..=PRG_ROM_END => self.read_prg_rom(addr) as u16,
 
             _ => {
-                println!("Ignoring mem_read at BUS address {}", addr);
+                tracing::debug!(target: "cpu", "Read from unmapped ${:04x} for a trace", addr);
                 0
             }
         }
@@ -81,9 +83,11 @@ pub fn trace(cpu: &mut CPU) -> String {
     let opscodes: &HashMap<u8, &'static opcodes::OpCode> = &opcodes::OPCODES_MAP;
 
     let code = cpu.mem_read(cpu.program_counter);
-    let ops = opscodes.get(&code).expect(&format!("no opcode found for {}", code));
-
     let begin = cpu.program_counter;
+    let Some(ops) = opscodes.get(&code) else {
+        return with_registers(cpu, format!("{:04x}  {:02x}        .db ${:02x}", begin, code, code));
+    };
+
     let mut hex_dump = vec![];
     hex_dump.push(code);
 
@@ -213,6 +217,11 @@ pub fn trace(cpu: &mut CPU) -> String {
     .trim()
     .to_string();
 
+    with_registers(cpu, asm_str)
+}
+
+// Pads the disassembly and adds the registers after it.
+fn with_registers(cpu: &CPU, asm_str: String) -> String {
     format!(
         "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x} PPU:{:>3},{:>3} CYC:{}",
         asm_str, cpu.register_a, cpu.register_x, cpu.register_y, cpu.status, cpu.stack_pointer, cpu.bus.ppu.scanline, cpu.bus.ppu.cycles, cpu.bus.cycles
@@ -286,4 +295,22 @@ mod trace_test {
             result[0]
         );
     }
+
+    #[test]
+    fn test_unknown_opcode_halts() {
+        let mut bus = Bus::default(create_test_cartridge());
+        // A JAM.
+        bus.mem_write(100, 0x02);
+
+        let mut cpu = CPU::new(bus);
+        cpu.program_counter = 0x64;
+        let mut result: Vec<String> = vec![];
+        cpu.run_with_callback(|cpu| {
+            result.push(trace(cpu));
+        });
+        assert_eq!(
+            vec!["0064  02        .DB $02                         A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"],
+            result
+        );
+    }
 }
@@ -0,0 +1,189 @@
+//! Shadow call stack: the subroutine calls and interrupts the CPU is inside of, for showing a
+//! backtrace when the debugger stops.
+//!
+//! Frames are pushed on JSR and NMI entry, and each remembers the stack pointer just below the
+//! return address it pushed. Rather than popping a frame on each RTS or RTI, the stack drops every
+//! frame whose return address the CPU has moved the stack pointer past. Games that pull a return
+//! address with PLA to jump elsewhere, reset the stack with TXS, or push an address to RTS into a
+//! jump table entry then don't leave stale frames behind, and unmatched returns pop nothing.
+
+use std::fmt;
+
+use crate::cpu::CPU;
+use crate::debugger::symbols::SymbolTable;
+
+// Frames kept at most. Code that calls without ever returning (or returns by other means the
+// stack pointer doesn't reveal) shouldn't grow the stack forever.
+const MAX_FRAMES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Subroutine,
+    Nmi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    // The subroutine or interrupt handler entered.
+    pub entry: u16,
+    // 16kB PRG-ROM bank of the entry, or None if it isn't in ROM.
+    pub bank: Option<usize>,
+    // The JSR that made the call, or the instruction the interrupt came before.
+    pub call_site: u16,
+    // Where execution continues after returning.
+    pub return_addr: u16,
+    // Stack pointer after the return address (and status, for interrupts) was pushed.
+    pub stack_pointer: u8,
+}
+
+impl Frame {
+    // "update_sprites ($C4F2) in bank 01, called from $C012", with names from `symbols`.
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let mut text = match (self.kind, symbols.name(self.entry)) {
+            (FrameKind::Subroutine, Some(name)) => format!("{} (${:04X})", name, self.entry),
+            (FrameKind::Subroutine, None) => format!("${:04X}", self.entry),
+            (FrameKind::Nmi, Some(name)) => format!("NMI {} (${:04X})", name, self.entry),
+            (FrameKind::Nmi, None) => format!("NMI ${:04X}", self.entry),
+        };
+        if let Some(bank) = self.bank {
+            text += &format!(" in bank {:02X}", bank);
+        }
+        text += &match self.kind {
+            FrameKind::Subroutine => format!(", called from ${:04X}", self.call_site),
+            FrameKind::Nmi => format!(", interrupting ${:04X}", self.call_site),
+        };
+        text
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.describe(&SymbolTable::new()))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Frames from the outermost call in.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    fn push(&mut self, frame: Frame) {
+        if self.frames.len() == MAX_FRAMES {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    // Records the CPU entering the NMI handler at `handler`, which left the stack pointer at
+    // `stack_pointer` after pushing the program counter and status.
+    pub fn enter_nmi(&mut self, cpu: &CPU, handler: u16, stack_pointer: u8) {
+        let stack = |offset: u8| cpu.bus.peek(0x0100 + stack_pointer.wrapping_add(offset) as u16);
+        let interrupted = u16::from_le_bytes([stack(2), stack(3)]);
+        self.push(Frame {
+            kind: FrameKind::Nmi,
+            entry: handler,
+            bank: cpu.bus.prg_rom_bank(handler),
+            call_site: interrupted,
+            return_addr: interrupted,
+            stack_pointer,
+        });
+    }
+
+    // Records the JSR at `pc` having run, leaving the CPU in the subroutine.
+    pub fn enter_subroutine(&mut self, cpu: &CPU, pc: u16) {
+        self.push(Frame {
+            kind: FrameKind::Subroutine,
+            entry: cpu.program_counter,
+            bank: cpu.bus.prg_rom_bank(cpu.program_counter),
+            call_site: pc,
+            return_addr: pc.wrapping_add(3),
+            stack_pointer: cpu.stack_pointer,
+        });
+    }
+
+    // Drops the frames whose return address is no longer on the stack. Call after every
+    // instruction.
+    pub fn unwind(&mut self, stack_pointer: u8) {
+        while self.frames.last().is_some_and(|frame| frame.stack_pointer < stack_pointer) {
+            self.frames.pop();
+        }
+    }
+
+    // One line per frame, innermost first, as in a debugger's backtrace.
+    pub fn backtrace(&self, symbols: &SymbolTable) -> Vec<String> {
+        self.frames.iter().rev().map(|frame| frame.describe(symbols)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::create_test_cartridge;
+
+    #[test]
+    fn test_nmi_frame_reads_the_interrupted_address() {
+        let mut cpu = CPU::new(Bus::new(create_test_cartridge()));
+        cpu.program_counter = 0x8123;
+        cpu.bus.ppu.nmi_interrupt = Some(1);
+        assert!(cpu.service_interrupts());
+        let mut stack = CallStack::new();
+        stack.enter_nmi(&cpu, cpu.program_counter, cpu.stack_pointer);
+
+        let frame = stack.frames()[0];
+        assert_eq!((frame.kind, frame.call_site, frame.return_addr), (FrameKind::Nmi, 0x8123, 0x8123));
+        // The test cartridge's NMI vector points at RAM.
+        assert_eq!(frame.bank, None);
+        assert!(frame.to_string().starts_with("NMI $"));
+
+        // RTI pulls three bytes.
+        stack.unwind(cpu.stack_pointer.wrapping_add(3));
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn test_unwind_drops_every_released_frame() {
+        let mut stack = CallStack::new();
+        let frame = |entry, stack_pointer| Frame {
+            kind: FrameKind::Subroutine,
+            entry,
+            bank: None,
+            call_site: 0x8000,
+            return_addr: 0x8003,
+            stack_pointer,
+        };
+        stack.push(frame(0x9000, 0xfb));
+        stack.push(frame(0xa000, 0xf9));
+        stack.push(frame(0xb000, 0xf7));
+        // Unchanged, or deeper: nothing returned.
+        stack.unwind(0xf5);
+        assert_eq!(stack.len(), 3);
+        // TXS back above two frames' return addresses.
+        stack.unwind(0xfa);
+        assert_eq!(stack.frames().iter().map(|frame| frame.entry).collect::<Vec<_>>(), vec![0x9000]);
+        assert_eq!(stack.frames()[0].describe(&SymbolTable::new()), "$9000, called from $8000");
+    }
+}
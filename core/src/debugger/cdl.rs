@@ -0,0 +1,212 @@
+//! Code/Data Logger: records which ROM bytes the game runs as code and which it reads as data.
+//!
+//! The log has one byte of flags per PRG-ROM byte followed by one per CHR-ROM byte, the `.cdl`
+//! format FCEUX and Mesen read and write, so logs can be shared with their tools. The disassembler
+//! uses it to list bytes only ever read as data as `.db` rather than decoding them.
+//!
+//! Reference: <https://fceux.com/web/help/CodeDataLogger.html>
+
+use std::path::Path;
+
+use crate::ppu::PPU;
+use crate::ppu::registers::controller::PPUCTRL;
+
+// PRG flags. Bits 2 and 3 hold which 8KB slot of $8000-$FFFF the byte was seen at.
+pub const CODE: u8 = 0x01;
+pub const DATA: u8 = 0x02;
+pub const INDIRECT_CODE: u8 = 0x10;
+pub const INDIRECT_DATA: u8 = 0x20;
+// DPCM sample data. There is no APU yet, so this is never set, but logs from other emulators keep
+// it.
+pub const PCM_DATA: u8 = 0x40;
+
+// CHR flags.
+pub const DRAWN: u8 = 0x01;
+pub const READ: u8 = 0x02;
+
+// JMP ($nnnn).
+const JMP_INDIRECT: u8 = 0x6c;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeDataLog {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    // Addresses of the instruction being run. Fetching its bytes isn't a data read.
+    instruction: Option<(u16, u16)>,
+    // Whether the instruction being run reads through a pointer, i.e. ($nn,X) or ($nn),Y.
+    indirect: bool,
+    // Whether the last instruction was an indirect jump, making the next one indirect code.
+    jumped_indirectly: bool,
+}
+
+impl CodeDataLog {
+    // An empty log for a cartridge with `prg_len` bytes of PRG-ROM and `chr_len` of CHR-ROM (0
+    // for CHR-RAM).
+    pub fn new(prg_len: usize, chr_len: usize) -> Self {
+        CodeDataLog {
+            prg: vec![0; prg_len],
+            chr: vec![0; chr_len],
+            instruction: None,
+            indirect: false,
+            jumped_indirectly: false,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8], prg_len: usize, chr_len: usize) -> Result<Self, String> {
+        if bytes.len() != prg_len + chr_len {
+            return Err(format!(
+                "Code/data log is {} bytes, but the cartridge has {} bytes of ROM",
+                bytes.len(),
+                prg_len + chr_len
+            ));
+        }
+        let mut log = CodeDataLog::new(prg_len, chr_len);
+        log.prg.copy_from_slice(&bytes[..prg_len]);
+        log.chr.copy_from_slice(&bytes[prg_len..]);
+        Ok(log)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.prg.as_slice(), self.chr.as_slice()].concat()
+    }
+
+    pub fn load(path: &Path, prg_len: usize, chr_len: usize) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        CodeDataLog::from_bytes(&bytes, prg_len, chr_len)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_bytes()).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    // Starts an instruction at `pc`, `len` bytes long. Returns the flags its bytes get.
+    pub fn begin_instruction(&mut self, pc: u16, len: u16, code: u8, mode_is_indirect: bool) -> u8 {
+        let flags = if self.jumped_indirectly { CODE | INDIRECT_CODE } else { CODE };
+        self.instruction = Some((pc, pc.wrapping_add(len - 1)));
+        self.indirect = mode_is_indirect;
+        self.jumped_indirectly = code == JMP_INDIRECT;
+        flags
+    }
+
+    // Ors `flags` into PRG-ROM byte `offset`, seen at CPU address `addr`.
+    pub fn log_prg(&mut self, offset: usize, addr: u16, flags: u8) {
+        let slot = ((addr >> 13) & 0b11) as u8;
+        self.prg[offset] |= flags | (slot << 2);
+    }
+
+    // Logs a CPU read of PRG-ROM byte `offset` at `addr`. Reads of the instruction's own bytes
+    // don't count.
+    pub fn log_read(&mut self, offset: usize, addr: u16) {
+        if let Some((start, end)) = self.instruction {
+            if (start..=end).contains(&addr) {
+                return;
+            }
+        }
+        let flags = if self.indirect { DATA | INDIRECT_DATA } else { DATA };
+        self.log_prg(offset, addr, flags);
+    }
+
+    // Logs the CHR-ROM byte a $2007 read is about to fetch.
+    pub fn log_chr_read(&mut self, ppu: &PPU) {
+        let addr = (ppu.ppu_addr.get() & 0x3fff) as usize;
+        if let Some(flags) = self.chr.get_mut(addr) {
+            *flags |= READ;
+        }
+    }
+
+    // Logs the tiles the frame just rendered drew: the first nametable's background tiles and
+    // every sprite, from the pattern tables PPUCTRL selects.
+    pub fn log_rendered_tiles(&mut self, ppu: &PPU) {
+        let background = ppu.controller.contains(PPUCTRL::BACKGROUND_PATTERN_ADDR) as usize * 0x1000;
+        let sprites = ppu.controller.contains(PPUCTRL::SPRITE_PATTERN_ADDR) as usize * 0x1000;
+        let tiles = ppu.vram[..960].iter().map(|&tile| background + tile as usize * 16);
+        let sprite_tiles = ppu.oam_data.chunks(4).map(|sprite| sprites + sprite[1] as usize * 16);
+        for tile in tiles.chain(sprite_tiles) {
+            if let Some(bytes) = self.chr.get_mut(tile..tile + 16) {
+                bytes.iter_mut().for_each(|flags| *flags |= DRAWN);
+            }
+        }
+    }
+
+    // Whether PRG-ROM byte `offset` has been read as data but never run.
+    pub fn is_data_only(&self, offset: usize) -> bool {
+        self.prg.get(offset).is_some_and(|flags| flags & (CODE | DATA) == DATA)
+    }
+
+    // Number of PRG-ROM bytes logged as code and as data.
+    pub fn prg_counts(&self) -> (usize, usize) {
+        let count = |flag: u8| self.prg.iter().filter(|flags| *flags & flag != 0).count();
+        (count(CODE), count(DATA))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::cpu::CPU;
+
+    #[test]
+    fn test_instruction_bytes_are_code_not_data() {
+        let mut log = CodeDataLog::new(0x8000, 0);
+        let flags = log.begin_instruction(0xc000, 3, 0xad, false);
+        for i in 0..3 {
+            log.log_prg(0x4000 + i, 0xc000 + i as u16, flags);
+            log.log_read(0x4000 + i, 0xc000 + i as u16);
+        }
+        log.log_read(0x4010, 0xc010);
+
+        // $C000 is in the third 8KB slot.
+        assert_eq!(log.prg[0x4000], CODE | 0b1000);
+        assert_eq!(log.prg[0x4010], DATA | 0b1000);
+        assert!(log.is_data_only(0x4010));
+        assert!(!log.is_data_only(0x4000));
+        assert_eq!(log.prg_counts(), (3, 1));
+    }
+
+    #[test]
+    fn test_indirect_flags() {
+        let mut log = CodeDataLog::new(0x8000, 0);
+        // LDA ($10),Y reads through a pointer.
+        log.begin_instruction(0x0600, 2, 0xb1, true);
+        log.log_read(0x10, 0x8010);
+        assert_eq!(log.prg[0x10], DATA | INDIRECT_DATA);
+
+        // The instruction after JMP ($0200) was reached indirectly.
+        log.begin_instruction(0x0602, 3, JMP_INDIRECT, false);
+        assert_eq!(log.begin_instruction(0x8000, 1, 0xea, false), CODE | INDIRECT_CODE);
+        assert_eq!(log.begin_instruction(0x8001, 1, 0xea, false), CODE);
+    }
+
+    #[test]
+    fn test_cpu_logs_code_and_data() {
+        let mut cpu = CPU::new(Bus::new(create_test_cartridge()));
+        // The pointer at $00 holds $8020.
+        crate::asm::patch(&mut cpu.bus, 0x8000, "LDA $8010\nLDA ($00),Y").unwrap();
+        cpu.bus.poke(0x0000, 0x20);
+        cpu.bus.poke(0x0001, 0x80);
+        cpu.bus.start_code_data_log();
+        cpu.program_counter = 0x8000;
+        cpu.execute_instruction();
+        cpu.execute_instruction();
+
+        let log = cpu.bus.cdl.as_ref().unwrap();
+        assert_eq!(&log.prg[..5], &[CODE; 5]);
+        assert_eq!(log.prg[0x10], DATA);
+        assert_eq!(log.prg[0x20], DATA | INDIRECT_DATA);
+        assert!(cpu.bus.is_logged_data(0x8010));
+        assert!(!cpu.bus.is_logged_data(0x8001));
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let mut log = CodeDataLog::new(4, 2);
+        log.prg[1] = CODE;
+        log.chr[1] = DRAWN | READ;
+        let bytes = log.to_bytes();
+        assert_eq!(bytes, vec![0, CODE, 0, 0, 0, DRAWN | READ]);
+        assert_eq!(CodeDataLog::from_bytes(&bytes, 4, 2), Ok(log));
+        assert!(CodeDataLog::from_bytes(&bytes, 4, 4).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! Breakpoint conditions: small expressions over the CPU and PPU state, like
+//! `A == $3F && scanline > 200`, that decide whether a breakpoint stops the CPU.
+//!
+//! Values are registers (`a`, `x`, `y`, `sp`, `pc`, `p`), status flags (`c`, `z`, `i`, `d`, `v`,
+//! `n`, each 0 or 1), PPU timing (`scanline`, `dot`, `frame`), CPU `cycles`, memory (`[$0300]`,
+//! `[$0300 + x]`), numbers (`$3F`, `0x3F`, `%0011`, `63`) and symbol names, which stand for their
+//! address. Names are case insensitive. Operators, loosest first: `||`, `&&`, comparisons
+//! (`==`, `!=`, `<`, `<=`, `>`, `>=`), `|`, `^`, `&`, `+` and `-`, then unary `!` and `-`.
+//! Anything non-zero is true.
+
+use std::fmt;
+
+use crate::cpu::CPU;
+use crate::debugger::symbols::SymbolTable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    A,
+    X,
+    Y,
+    Sp,
+    Pc,
+    P,
+    // A status flag, by its bit.
+    Flag(u8),
+    Scanline,
+    Dot,
+    Frame,
+    Cycles,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "a" => Variable::A,
+            "x" => Variable::X,
+            "y" => Variable::Y,
+            "sp" => Variable::Sp,
+            "pc" => Variable::Pc,
+            "p" => Variable::P,
+            "c" => Variable::Flag(0),
+            "z" => Variable::Flag(1),
+            "i" => Variable::Flag(2),
+            "d" => Variable::Flag(3),
+            "v" => Variable::Flag(6),
+            "n" => Variable::Flag(7),
+            "scanline" => Variable::Scanline,
+            "dot" => Variable::Dot,
+            "frame" => Variable::Frame,
+            "cycles" => Variable::Cycles,
+            _ => return None,
+        })
+    }
+
+    fn value(&self, cpu: &CPU) -> i64 {
+        match self {
+            Variable::A => cpu.register_a as i64,
+            Variable::X => cpu.register_x as i64,
+            Variable::Y => cpu.register_y as i64,
+            Variable::Sp => cpu.stack_pointer as i64,
+            Variable::Pc => cpu.program_counter as i64,
+            Variable::P => cpu.status.bits() as i64,
+            Variable::Flag(bit) => ((cpu.status.bits() >> bit) & 1) as i64,
+            Variable::Scanline => cpu.bus.ppu.scanline as i64,
+            Variable::Dot => cpu.bus.ppu.cycles as i64,
+            Variable::Frame => cpu.bus.ppu.frame_count as i64,
+            Variable::Cycles => cpu.bus.cycles as i64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+impl BinaryOp {
+    // Operators by precedence level, loosest first.
+    const LEVELS: [&'static [(&'static str, BinaryOp)]; 6] = [
+        &[("||", BinaryOp::Or)],
+        &[("&&", BinaryOp::And)],
+        &[
+            ("==", BinaryOp::Eq),
+            ("!=", BinaryOp::Ne),
+            ("<=", BinaryOp::Le),
+            (">=", BinaryOp::Ge),
+            ("<", BinaryOp::Lt),
+            (">", BinaryOp::Gt),
+        ],
+        &[("|", BinaryOp::BitOr)],
+        &[("^", BinaryOp::BitXor)],
+        &[("&", BinaryOp::BitAnd)],
+    ];
+    const SUMS: &'static [(&'static str, BinaryOp)] = &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)];
+
+    fn apply(&self, a: i64, b: i64) -> i64 {
+        match self {
+            BinaryOp::Or => (a != 0 || b != 0) as i64,
+            BinaryOp::And => (a != 0 && b != 0) as i64,
+            BinaryOp::Eq => (a == b) as i64,
+            BinaryOp::Ne => (a != b) as i64,
+            BinaryOp::Lt => (a < b) as i64,
+            BinaryOp::Le => (a <= b) as i64,
+            BinaryOp::Gt => (a > b) as i64,
+            BinaryOp::Ge => (a >= b) as i64,
+            BinaryOp::BitOr => a | b,
+            BinaryOp::BitXor => a ^ b,
+            BinaryOp::BitAnd => a & b,
+            BinaryOp::Add => a.wrapping_add(b),
+            BinaryOp::Sub => a.wrapping_sub(b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(i64),
+    Variable(Variable),
+    // The byte at an address, read without side effects.
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn evaluate(&self, cpu: &CPU) -> i64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Variable(variable) => variable.value(cpu),
+            Expr::Memory(addr) => cpu.bus.peek(addr.evaluate(cpu) as u16) as i64,
+            Expr::Not(e) => (e.evaluate(cpu) == 0) as i64,
+            Expr::Negate(e) => e.evaluate(cpu).wrapping_neg(),
+            Expr::Binary(op, a, b) => op.apply(a.evaluate(cpu), b.evaluate(cpu)),
+        }
+    }
+}
+
+// A recursive descent parser over the source text.
+struct Parser<'a> {
+    rest: &'a str,
+    symbols: &'a SymbolTable,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    // Consumes `token` if the input starts with it.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        match self.eat(token) {
+            true => Ok(()),
+            false => Err(format!("Expected {} at \"{}\"", token, self.rest)),
+        }
+    }
+
+    // Consumes one of `operators` if the input starts with it. `|` and `&` don't match the start of
+    // `||` and `&&`.
+    fn eat_operator(&mut self, operators: &[(&str, BinaryOp)]) -> Option<BinaryOp> {
+        self.skip_space();
+        let (token, op) = operators.iter().find(|(token, _)| self.rest.starts_with(token))?;
+        if token.len() == 1 && self.rest[1..].starts_with(*token) && matches!(*token, "|" | "&") {
+            return None;
+        }
+        self.rest = &self.rest[token.len()..];
+        Some(*op)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        let operators = match BinaryOp::LEVELS.get(level) {
+            Some(operators) => *operators,
+            None => BinaryOp::SUMS,
+        };
+        let next = |parser: &mut Self| match level < BinaryOp::LEVELS.len() {
+            true => parser.binary(level + 1),
+            false => parser.unary(),
+        };
+        let mut expr = next(self)?;
+        while let Some(op) = self.eat_operator(operators) {
+            expr = Expr::Binary(op, Box::new(expr), Box::new(next(self)?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            Ok(Expr::Not(Box::new(self.unary()?)))
+        } else if self.eat("-") {
+            Ok(Expr::Negate(Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        if self.eat("(") {
+            let expr = self.binary(0)?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if self.eat("[") {
+            let expr = self.binary(0)?;
+            self.expect("]")?;
+            return Ok(Expr::Memory(Box::new(expr)));
+        }
+
+        self.skip_space();
+        let len = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '@' || c == '$' || c == '%'))
+            .unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        if word.is_empty() {
+            return Err(format!("Expected a value at \"{}\"", rest));
+        }
+
+        let number = |digits: &str, radix| {
+            i64::from_str_radix(digits, radix).map_err(|_| format!("Invalid number {}", word))
+        };
+        if let Some(hex) = word.strip_prefix('$').or_else(|| word.strip_prefix("0x")) {
+            number(hex, 16).map(Expr::Number)
+        } else if let Some(binary) = word.strip_prefix('%') {
+            number(binary, 2).map(Expr::Number)
+        } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+            number(word, 10).map(Expr::Number)
+        } else if let Some(variable) = Variable::from_name(word) {
+            Ok(Expr::Variable(variable))
+        } else if let Some(addr) = self.symbols.addr(word) {
+            Ok(Expr::Number(addr as i64))
+        } else {
+            Err(format!("Unknown name {}", word))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    // Parses `source`, resolving symbol names through `symbols`.
+    pub fn parse(source: &str, symbols: &SymbolTable) -> Result<Self, String> {
+        let mut parser = Parser { rest: source, symbols };
+        let expr = parser.binary(0)?;
+        parser.skip_space();
+        if !parser.rest.is_empty() {
+            return Err(format!("Unexpected \"{}\" in condition {}", parser.rest, source));
+        }
+        Ok(Condition { source: source.trim().to_string(), expr })
+    }
+
+    // The condition's value for the CPU about to run its next instruction.
+    pub fn evaluate(&self, cpu: &CPU) -> i64 {
+        self.expr.evaluate(cpu)
+    }
+
+    pub fn is_met(&self, cpu: &CPU) -> bool {
+        self.evaluate(cpu) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::cpu::Mem;
+
+    fn evaluate(source: &str, cpu: &CPU) -> i64 {
+        Condition::parse(source, &SymbolTable::new()).unwrap().evaluate(cpu)
+    }
+
+    #[test]
+    fn test_operators_and_precedence() {
+        let cpu = CPU::new(Bus::new(create_test_cartridge()));
+        assert_eq!(evaluate("1 + 2 == 3", &cpu), 1);
+        assert_eq!(evaluate("$10 | 0x01 & %11", &cpu), 0x11);
+        assert_eq!(evaluate("1 || 0 && 0", &cpu), 1);
+        assert_eq!(evaluate("!(2 > 1) || -1 < 0", &cpu), 1);
+        assert_eq!(evaluate("5 - 3 - 1", &cpu), 1);
+        assert_eq!(evaluate("6 ^ 3 != 5", &cpu), 0);
+    }
+
+    #[test]
+    fn test_cpu_state_and_memory() {
+        let mut cpu = CPU::new(Bus::new(create_test_cartridge()));
+        cpu.register_a = 0x3f;
+        cpu.register_x = 2;
+        cpu.mem_write(0x0302, 0x99);
+        cpu.bus.ppu.scanline = 241;
+        assert_eq!(evaluate("A == 0x3F && scanline > 200", &cpu), 1);
+        assert_eq!(evaluate("[$0300 + x]", &cpu), 0x99);
+        // The interrupt flag is set at power-on, carry isn't.
+        assert_eq!(evaluate("i + c", &cpu), 1);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x0302, "lives");
+        let condition = Condition::parse(" [lives] >= $99 ", &symbols).unwrap();
+        assert!(condition.is_met(&cpu));
+        assert_eq!(condition.to_string(), "[lives] >= $99");
+    }
+
+    #[test]
+    fn test_errors() {
+        let symbols = SymbolTable::new();
+        assert!(Condition::parse("a ==", &symbols).is_err());
+        assert!(Condition::parse("(a", &symbols).is_err());
+        assert!(Condition::parse("a b", &symbols).is_err());
+        assert!(Condition::parse("lives > 1", &symbols).is_err());
+        assert!(Condition::parse("$xyz", &symbols).is_err());
+    }
+}
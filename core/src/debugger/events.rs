@@ -0,0 +1,166 @@
+//! Event viewer log: the PPU, APU, controller and mapper register accesses of a frame, and the
+//! NMI, each tagged with the scanline and dot the PPU was at. Laid out on the 341x262 grid of a
+//! frame's PPU cycles, they show where in the frame a game changes scroll, banks or palettes, the
+//! usual way to debug raster effects and interrupt timing.
+//!
+//! The PPU is caught up once per instruction, so an access is placed at the dot the instruction
+//! started on, up to a few dots early.
+//!
+//! Reference: <https://www.nesdev.org/wiki/PPU_rendering>
+
+use crate::debugger::watch::AccessKind;
+use crate::ppu::PPU;
+
+// The grid: dots per scanline and scanlines per frame.
+pub const DOTS: usize = 341;
+pub const SCANLINES: usize = 262;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    // $2000-$3FFF and OAM DMA at $4014.
+    Ppu,
+    // $4000-$4013, $4015 and writes to $4017.
+    Apu,
+    // Controller reads from $4016/$4017 and strobes at $4016.
+    Input,
+    // Writes to cartridge space, $4020-$FFFF.
+    Mapper,
+    // The CPU taking the NMI.
+    Nmi,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 5] = [EventKind::Ppu, EventKind::Apu, EventKind::Input, EventKind::Mapper, EventKind::Nmi];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Ppu => "PPU",
+            EventKind::Apu => "APU",
+            EventKind::Input => "Input",
+            EventKind::Mapper => "Mapper",
+            EventKind::Nmi => "NMI",
+        }
+    }
+
+    // The kind of event an access to `addr` is, or None if it isn't a register access worth
+    // logging.
+    pub fn of_access(addr: u16, access: AccessKind) -> Option<EventKind> {
+        let write = access == AccessKind::Write;
+        match addr {
+            0x2000..=0x3fff | 0x4014 => Some(EventKind::Ppu),
+            0x4016 => Some(EventKind::Input),
+            0x4017 if write => Some(EventKind::Apu),
+            0x4017 => Some(EventKind::Input),
+            0x4000..=0x4015 => Some(EventKind::Apu),
+            0x4020..=0xffff if write => Some(EventKind::Mapper),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub addr: u16,
+    pub value: u8,
+    // Read or Write. NMIs are recorded as reads of the NMI vector.
+    pub access: AccessKind,
+    pub scanline: u16,
+    pub dot: u16,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventLog {
+    // The frame being run, and the last one completed.
+    current: Vec<Event>,
+    previous: Vec<Event>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        EventLog::default()
+    }
+
+    // Records a CPU access, if it is to a register.
+    pub fn record_access(&mut self, ppu: &PPU, addr: u16, value: u8, access: AccessKind) {
+        if let Some(kind) = EventKind::of_access(addr, access) {
+            self.record(ppu, kind, addr, value, access);
+        }
+    }
+
+    pub fn record_nmi(&mut self, ppu: &PPU) {
+        self.record(ppu, EventKind::Nmi, 0xfffa, 0, AccessKind::Read);
+    }
+
+    fn record(&mut self, ppu: &PPU, kind: EventKind, addr: u16, value: u8, access: AccessKind) {
+        self.current.push(Event {
+            kind,
+            addr,
+            value,
+            access,
+            scanline: ppu.scanline,
+            dot: ppu.cycles as u16,
+        });
+    }
+
+    // Call when the PPU finishes a frame: its events become the ones shown.
+    pub fn end_frame(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    // Events of the last completed frame, in the order they happened.
+    pub fn events(&self) -> &[Event] {
+        &self.previous
+    }
+
+    // Events of the frame still running, e.g. while the debugger is stopped part way through.
+    pub fn current_events(&self) -> &[Event] {
+        &self.current
+    }
+}
+
+impl Event {
+    // "Write $2005 = $1F at scanline 32, dot 250".
+    pub fn describe(&self) -> String {
+        let access = match (self.kind, self.access) {
+            (EventKind::Nmi, _) => "NMI".to_string(),
+            (_, AccessKind::Write) => format!("Write ${:04X} = ${:02X}", self.addr, self.value),
+            _ => format!("Read ${:04X} = ${:02X}", self.addr, self.value),
+        };
+        format!("{} at scanline {}, dot {}", access, self.scanline, self.dot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::cpu::Mem;
+
+    #[test]
+    fn test_register_accesses_are_logged_per_frame() {
+        let mut bus = Bus::new(create_test_cartridge());
+        bus.events = Some(EventLog::new());
+        bus.tick(100);
+        let dot = bus.ppu.cycles as u16;
+        bus.mem_write(0x2005, 0x1f);
+        bus.mem_write(0x0300, 0x01);
+        bus.mem_read(0x4016);
+        bus.mem_write(0x4017, 0x40);
+        let log = bus.events.as_ref().unwrap();
+        assert_eq!(log.current_events().len(), 3);
+        assert!(log.events().is_empty());
+
+        // Run to the end of the frame.
+        while bus.ppu.frame_count == 0 {
+            bus.tick(100);
+        }
+        let log = bus.events.as_ref().unwrap();
+        let kinds: Vec<EventKind> = log.events().iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![EventKind::Ppu, EventKind::Input, EventKind::Apu]);
+        let scroll = log.events()[0];
+        assert_eq!((scroll.scanline, scroll.dot), (0, dot));
+        assert_eq!(scroll.describe(), format!("Write $2005 = $1F at scanline 0, dot {}", dot));
+    }
+}
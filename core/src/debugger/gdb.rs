@@ -0,0 +1,527 @@
+//! GDB remote serial protocol stub, so gdb, or an IDE that speaks the protocol, can attach over
+//! TCP to set breakpoints and watchpoints, step, and read and write registers and memory.
+//!
+//! The stub drives the same `Debugger` as the debugger window: a gdb breakpoint is a debugger
+//! breakpoint, continuing resumes it and a stop in the debugger is reported back to gdb. The
+//! server is polled once per host frame and never blocks the emulator.
+//!
+//! There is no 6502 in gdb's list of architectures, so the stub describes its registers with a
+//! target description (`qXfer:features:read`): `a`, `x`, `y`, `p` and `sp` of 8 bits, then `pc` of
+//! 16. Stock gdb builds can still talk to it for memory and run control; a build or front end with
+//! 6502 support is needed for disassembly.
+//!
+//! Reference: <https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html>
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use crate::debugger::watch::{AccessKind, Watchpoint};
+use crate::debugger::{BreakReason, Debugger};
+use crate::emulator::Emulator;
+
+// Ctrl-C from gdb, sent outside any packet.
+const INTERRUPT: u8 = 0x03;
+
+// The longest packet we take or send, as told to gdb in hex. A memory read fills at most half of
+// one, at two digits a byte.
+const PACKET_SIZE: usize = 0x4000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nesrs.6502.core">
+    <reg name="a" bitsize="8" regnum="0"/>
+    <reg name="x" bitsize="8"/>
+    <reg name="y" bitsize="8"/>
+    <reg name="p" bitsize="8"/>
+    <reg name="sp" bitsize="8" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+// Wraps `data` in a packet: "$data#cc".
+pub fn frame_packet(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum(data.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 16).map_err(|_| format!("Invalid number {}", s))
+}
+
+fn parse_hex_bytes(s: &str) -> Result<Vec<u8>, String> {
+    if !s.is_ascii() {
+        return Err(format!("Invalid hex digits in {}", s));
+    }
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("Odd number of hex digits in {}", s));
+    }
+    (0..s.len()).step_by(2).map(|i| parse_hex(&s[i..i + 2]).map(|byte| byte as u8)).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Packet(String),
+    Interrupt,
+    // A packet whose checksum didn't match, to be answered with '-' so gdb resends it.
+    Corrupt,
+}
+
+// Splits the byte stream from gdb into packets.
+#[derive(Debug, Default)]
+pub struct PacketReader {
+    buffer: Vec<u8>,
+}
+
+impl PacketReader {
+    pub fn new() -> Self {
+        PacketReader::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // The next complete input, if one has arrived. Acknowledgements are skipped.
+    pub fn next_input(&mut self) -> Option<Input> {
+        loop {
+            match *self.buffer.first()? {
+                INTERRUPT => {
+                    self.buffer.remove(0);
+                    return Some(Input::Interrupt);
+                }
+                b'$' => break,
+                _ => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+        let end = self.buffer.iter().position(|byte| *byte == b'#')?;
+        if self.buffer.len() < end + 3 {
+            return None;
+        }
+        let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+        let data = &packet[1..end];
+        let sum = std::str::from_utf8(&packet[end + 1..]).ok().and_then(|sum| u8::from_str_radix(sum, 16).ok());
+        if sum != Some(checksum(data)) {
+            return Some(Input::Corrupt);
+        }
+        Some(Input::Packet(String::from_utf8_lossy(data).into_owned()))
+    }
+}
+
+// The protocol, apart from the connection: answers packets by acting on the emulator and the
+// debugger.
+#[derive(Debug, Default)]
+pub struct GdbStub {
+    // Whether gdb continued or stepped and is waiting for the target to stop.
+    waiting: bool,
+    // Whether gdb turned acknowledgements off with QStartNoAckMode.
+    pub no_ack: bool,
+}
+
+impl GdbStub {
+    pub fn new() -> Self {
+        GdbStub::default()
+    }
+
+    // The stop reply for why the debugger is stopped.
+    fn stop_reply(debugger: &Debugger) -> String {
+        match debugger.break_reason() {
+            Some(BreakReason::Breakpoint(_)) => "T05swbreak:;".to_string(),
+            Some(BreakReason::Watchpoint(hit)) => {
+                let kind = match hit.kind {
+                    AccessKind::Read => "rwatch",
+                    AccessKind::Write => "watch",
+                    AccessKind::ReadWrite => "awatch",
+                };
+                format!("T05{}:{:x};", kind, hit.addr)
+            }
+            Some(BreakReason::Paused) => "S02".to_string(),
+            _ => "S05".to_string(),
+        }
+    }
+
+    fn read_registers(emulator: &Emulator) -> String {
+        let cpu = &emulator.cpu;
+        let pc = cpu.program_counter.to_le_bytes();
+        hex(&[cpu.register_a, cpu.register_x, cpu.register_y, cpu.status.bits(), cpu.stack_pointer, pc[0], pc[1]])
+    }
+
+    fn write_register(emulator: &mut Emulator, register: u32, value: &[u8]) -> Result<(), String> {
+        let cpu = &mut emulator.cpu;
+        let byte = *value.first().ok_or("Missing register value")?;
+        match register {
+            0 => cpu.register_a = byte,
+            1 => cpu.register_x = byte,
+            2 => cpu.register_y = byte,
+            3 => cpu.status = crate::cpu::CPUFlags::from_bits_truncate(byte),
+            4 => cpu.stack_pointer = byte,
+            5 if value.len() == 2 => cpu.program_counter = u16::from_le_bytes([value[0], value[1]]),
+            _ => return Err(format!("Invalid register {}", register)),
+        }
+        Ok(())
+    }
+
+    // "Z2,addr,len" and friends: (type, addr, len).
+    fn parse_point(args: &str) -> Result<(u32, u16, u16), String> {
+        let mut fields = args.split([',', ';']);
+        let mut next = || fields.next().ok_or("Missing breakpoint field").map(str::to_string);
+        let kind = parse_hex(&next()?)?;
+        let addr = parse_hex(&next()?)? as u16;
+        let len = match parse_hex(&next()?)? {
+            0 => 1,
+            len @ 1..=0xffff => len as u16,
+            len => return Err(format!("A watchpoint of {} bytes is larger than memory", len)),
+        };
+        Ok((kind, addr, len))
+    }
+
+    fn set_point(debugger: &mut Debugger, args: &str, insert: bool) -> Result<String, String> {
+        let (kind, addr, len) = GdbStub::parse_point(args)?;
+        let access = match kind {
+            0 | 1 => {
+                match insert {
+                    true => debugger.add_breakpoint(addr),
+                    false => {
+                        debugger.remove_breakpoint(addr);
+                    }
+                }
+                return Ok("OK".to_string());
+            }
+            2 => AccessKind::Write,
+            3 => AccessKind::Read,
+            4 => AccessKind::ReadWrite,
+            _ => return Ok(String::new()),
+        };
+        let watchpoint = Watchpoint::new(addr, addr.wrapping_add(len - 1), access);
+        match insert {
+            true => debugger.add_watchpoint(watchpoint),
+            false => {
+                debugger.remove_watchpoint(watchpoint);
+            }
+        }
+        Ok("OK".to_string())
+    }
+
+    // Handles a packet. Returns the reply, or None when there is none yet: after a continue, the
+    // reply comes from `poll_stop` once the debugger stops.
+    pub fn handle(&mut self, packet: &str, emulator: &mut Emulator, debugger: &mut Debugger) -> Option<String> {
+        match self.handle_command(packet, emulator, debugger) {
+            Ok(reply) => reply,
+            Err(_) => Some("E01".to_string()),
+        }
+    }
+
+    fn handle_command(
+        &mut self,
+        packet: &str,
+        emulator: &mut Emulator,
+        debugger: &mut Debugger,
+    ) -> Result<Option<String>, String> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => GdbStub::stop_reply(debugger),
+            "g" => GdbStub::read_registers(emulator),
+            "G" => {
+                let bytes = parse_hex_bytes(args)?;
+                for (register, value) in [(0, 0..1), (1, 1..2), (2, 2..3), (3, 3..4), (4, 4..5), (5, 5..7)] {
+                    GdbStub::write_register(emulator, register, bytes.get(value).ok_or("Too few registers")?)?;
+                }
+                "OK".to_string()
+            }
+            "p" => {
+                let registers = parse_hex_bytes(&GdbStub::read_registers(emulator))?;
+                match parse_hex(args)? {
+                    register @ 0..=4 => hex(&registers[register as usize..register as usize + 1]),
+                    5 => hex(&registers[5..7]),
+                    register => return Err(format!("Invalid register {}", register)),
+                }
+            }
+            "P" => {
+                let (register, value) = args.split_once('=').ok_or("Missing register value")?;
+                GdbStub::write_register(emulator, parse_hex(register)?, &parse_hex_bytes(value)?)?;
+                "OK".to_string()
+            }
+            "m" => {
+                let (addr, len) = args.split_once(',').ok_or("Missing length")?;
+                let (addr, len) = (parse_hex(addr)? as u16, parse_hex(len)? as usize);
+                if len > PACKET_SIZE / 2 {
+                    return Err(format!("Reading {} bytes doesn't fit in a packet", len));
+                }
+                let bytes: Vec<u8> = (0..len).map(|i| emulator.cpu.bus.peek(addr.wrapping_add(i as u16))).collect();
+                hex(&bytes)
+            }
+            "M" => {
+                let (range, data) = args.split_once(':').ok_or("Missing data")?;
+                let addr = parse_hex(range.split(',').next().unwrap_or_default())? as u16;
+                for (i, byte) in parse_hex_bytes(data)?.into_iter().enumerate() {
+                    emulator.cpu.bus.poke(addr.wrapping_add(i as u16), byte);
+                }
+                "OK".to_string()
+            }
+            "c" | "s" => {
+                if !args.is_empty() {
+                    emulator.cpu.program_counter = parse_hex(args)? as u16;
+                }
+                if command == "s" {
+                    debugger.step_instruction(emulator);
+                    return Ok(Some(GdbStub::stop_reply(debugger)));
+                }
+                debugger.resume();
+                self.waiting = true;
+                return Ok(None);
+            }
+            "Z" => GdbStub::set_point(debugger, args, true)?,
+            "z" => GdbStub::set_point(debugger, args, false)?,
+            "H" | "T" => "OK".to_string(),
+            // Detach or kill: let the game run on without gdb.
+            "D" | "k" => {
+                debugger.resume();
+                "OK".to_string()
+            }
+            _ => self.handle_query(packet)?,
+        };
+        Ok(Some(reply))
+    }
+
+    fn handle_query(&mut self, packet: &str) -> Result<String, String> {
+        Ok(if packet.starts_with("qSupported") {
+            format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;swbreak+", PACKET_SIZE)
+        } else if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            "OK".to_string()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, len) = range.split_once(',').ok_or("Missing length")?;
+            let (offset, len) = (parse_hex(offset)? as usize, parse_hex(len)? as usize);
+            let rest = TARGET_XML.get(offset.min(TARGET_XML.len())..).unwrap_or_default();
+            match rest.len() > len {
+                true => format!("m{}", &rest[..len]),
+                false => format!("l{}", rest),
+            }
+        } else {
+            match packet {
+                "qAttached" => "1".to_string(),
+                "qC" => "QC1".to_string(),
+                "qfThreadInfo" => "m1".to_string(),
+                "qsThreadInfo" => "l".to_string(),
+                // Unsupported: an empty reply tells gdb so.
+                _ => String::new(),
+            }
+        })
+    }
+
+    // Ctrl-C: stops the game. gdb gets its stop reply from `poll_stop`.
+    pub fn interrupt(&mut self, debugger: &mut Debugger) {
+        debugger.pause();
+    }
+
+    // The stop reply, once the debugger has stopped after a continue.
+    pub fn poll_stop(&mut self, debugger: &Debugger) -> Option<String> {
+        if self.waiting && debugger.is_paused() {
+            self.waiting = false;
+            return Some(GdbStub::stop_reply(debugger));
+        }
+        None
+    }
+}
+
+// Listens for gdb on a TCP port and serves one connection at a time.
+#[derive(Debug)]
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<(TcpStream, PacketReader)>,
+    stub: GdbStub,
+}
+
+impl GdbServer {
+    // Listens on `port` on localhost. Port 0 picks a free one.
+    pub fn listen(port: u16) -> Result<Self, String> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("Could not listen on {}: {}", port, e))?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(GdbServer { listener, client: None, stub: GdbStub::new() })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|e| e.to_string())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // Accepts a connection, answers whatever gdb has sent and reports stops. Never blocks. A new
+    // connection stops the game, since gdb expects to attach to a stopped target.
+    pub fn poll(&mut self, emulator: &mut Emulator, debugger: &mut Debugger) {
+        if self.client.is_none() {
+            if let Ok((stream, _)) = self.listener.accept() {
+                if stream.set_nonblocking(true).is_ok() {
+                    debugger.pause();
+                    self.stub = GdbStub::new();
+                    self.client = Some((stream, PacketReader::new()));
+                }
+            }
+        }
+        if self.client.is_some() && self.serve(emulator, debugger).is_err() {
+            // gdb went away: carry on without it.
+            self.client = None;
+            debugger.resume();
+        }
+    }
+
+    fn serve(&mut self, emulator: &mut Emulator, debugger: &mut Debugger) -> std::io::Result<()> {
+        let Some((stream, reader)) = &mut self.client else {
+            return Ok(());
+        };
+        let mut buffer = [0; 4096];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => reader.push(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        while let Some(input) = reader.next_input() {
+            match input {
+                Input::Interrupt => self.stub.interrupt(debugger),
+                Input::Corrupt => stream.write_all(b"-")?,
+                Input::Packet(packet) => {
+                    if !self.stub.no_ack {
+                        stream.write_all(b"+")?;
+                    }
+                    if let Some(reply) = self.stub.handle(&packet, emulator, debugger) {
+                        stream.write_all(frame_packet(&reply).as_bytes())?;
+                    }
+                    if packet == "k" || packet == "D" {
+                        return Err(ErrorKind::ConnectionAborted.into());
+                    }
+                }
+            }
+        }
+        if let Some(reply) = self.stub.poll_stop(debugger) {
+            stream.write_all(frame_packet(&reply).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::cpu::Mem;
+
+    fn emulator_with(source: &str) -> Emulator {
+        let mut emulator = Emulator::new(create_test_cartridge());
+        for (i, byte) in asm!(source).into_iter().enumerate() {
+            emulator.cpu.mem_write(0x0600 + i as u16, byte);
+        }
+        emulator.cpu.program_counter = 0x0600;
+        emulator
+    }
+
+    #[test]
+    fn test_packet_reader() {
+        let mut reader = PacketReader::new();
+        reader.push(b"+$g#67$m0");
+        assert_eq!(reader.next_input(), Some(Input::Packet("g".to_string())));
+        assert_eq!(reader.next_input(), None);
+        reader.push(b",2#fb\x03$g#00");
+        assert_eq!(reader.next_input(), Some(Input::Packet("m0,2".to_string())));
+        assert_eq!(reader.next_input(), Some(Input::Interrupt));
+        assert_eq!(reader.next_input(), Some(Input::Corrupt));
+        assert_eq!(frame_packet("OK"), "$OK#9a");
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let mut emulator = emulator_with("LDA #$05");
+        let mut debugger = Debugger::new();
+        let mut stub = GdbStub::new();
+        let mut handle = |packet: &str, emulator: &mut Emulator| stub.handle(packet, emulator, &mut debugger).unwrap();
+
+        emulator.cpu.register_a = 0x12;
+        assert_eq!(handle("g", &mut emulator), "12000024fd0006");
+        assert_eq!(handle("P5=0007", &mut emulator), "OK");
+        assert_eq!(emulator.cpu.program_counter, 0x0700);
+        assert_eq!(handle("p5", &mut emulator), "0007");
+        assert_eq!(handle("P1=7f", &mut emulator), "OK");
+        assert_eq!(handle("p1", &mut emulator), "7f");
+
+        assert_eq!(handle("M0010,2:beef", &mut emulator), "OK");
+        assert_eq!(handle("m10,3", &mut emulator), "beef00");
+        assert_eq!(handle("m0600,2", &mut emulator), "a905");
+        assert_eq!(handle("mzz,1", &mut emulator), "E01");
+        // Addresses wrap around the top of memory, and malformed packets are refused, not fatal.
+        let (top, bottom) = (emulator.cpu.bus.peek(0xffff), emulator.cpu.bus.peek(0));
+        assert_eq!(handle("mffff,2", &mut emulator), format!("{:02x}{:02x}", top, bottom));
+        assert_eq!(handle("mffffffff,2", &mut emulator), handle("mffff,2", &mut emulator));
+        assert_eq!(handle("m0,ffffffff", &mut emulator), "E01");
+        assert_eq!(handle("M0:a\u{e9}b", &mut emulator), "E01");
+        assert_eq!(handle("Z2,10,10000", &mut emulator), "E01");
+        assert_eq!(handle("Z2,10,0", &mut emulator), "OK");
+        assert_eq!(handle("vMustReplyEmpty", &mut emulator), "");
+        assert!(handle("qXfer:features:read:target.xml:0,ffff", &mut emulator).starts_with("l<?xml"));
+        assert!(handle("qXfer:features:read:target.xml:0,10", &mut emulator).starts_with("m<?xml"));
+    }
+
+    #[test]
+    fn test_breakpoints_continue_and_step() {
+        let mut emulator = emulator_with("loop: INX\nINX\nJMP loop");
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        let mut stub = GdbStub::new();
+
+        assert_eq!(stub.handle("Z0,601,1", &mut emulator, &mut debugger), Some("OK".to_string()));
+        assert_eq!(stub.handle("c", &mut emulator, &mut debugger), None);
+        debugger.run_frame(&mut emulator);
+        assert_eq!(stub.poll_stop(&debugger), Some("T05swbreak:;".to_string()));
+        assert_eq!(stub.poll_stop(&debugger), None);
+        assert_eq!(emulator.cpu.program_counter, 0x0601);
+
+        assert_eq!(stub.handle("z0,601,1", &mut emulator, &mut debugger), Some("OK".to_string()));
+        assert!(!debugger.has_breakpoint(0x0601));
+        assert_eq!(stub.handle("s", &mut emulator, &mut debugger), Some("S05".to_string()));
+        assert_eq!(emulator.cpu.program_counter, 0x0602);
+
+        // A write watchpoint on two bytes.
+        stub.handle("Z2,10,2", &mut emulator, &mut debugger);
+        assert_eq!(debugger.watchpoints(), &[Watchpoint::new(0x10, 0x11, AccessKind::Write)]);
+    }
+
+    #[test]
+    fn test_server_over_tcp() {
+        let mut emulator = emulator_with("loop: JMP loop");
+        let mut debugger = Debugger::new();
+        let mut server = GdbServer::listen(0).unwrap();
+        let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        client.write_all(frame_packet("m600,1").as_bytes()).unwrap();
+
+        let mut reply = Vec::new();
+        for _ in 0..100 {
+            server.poll(&mut emulator, &mut debugger);
+            client.set_read_timeout(Some(std::time::Duration::from_millis(10))).unwrap();
+            let mut buffer = [0; 64];
+            if let Ok(n) = client.read(&mut buffer) {
+                reply.extend_from_slice(&buffer[..n]);
+            }
+            if reply.ends_with(b"#") || reply.len() >= 8 {
+                break;
+            }
+        }
+        assert!(server.is_connected());
+        assert!(debugger.is_paused());
+        assert_eq!(String::from_utf8(reply).unwrap(), format!("+{}", frame_packet("4c")));
+    }
+}
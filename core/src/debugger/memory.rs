@@ -0,0 +1,138 @@
+//! Reading and poking memory for the hex viewer, in CPU address space, PPU address space or OAM.
+//!
+//! Reads never disturb the emulation. Pokes can go either way: bypassing side effects writes the
+//! byte straight into memory (even into ROM), while exercising them performs the write the way
+//! the CPU would, through the registers, so mappers and the PPU react as they would to the game.
+//!
+//! Reference: <https://www.nesdev.org/wiki/CPU_memory_map>, <https://www.nesdev.org/wiki/PPU_memory_map>
+
+use crate::bus::Bus;
+use crate::cpu::Mem;
+use crate::debugger::watch::canonical_addr;
+use crate::ppu::PPU;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemorySpace {
+    Cpu,
+    Ppu,
+    Oam,
+}
+
+impl MemorySpace {
+    pub const ALL: [MemorySpace; 3] = [MemorySpace::Cpu, MemorySpace::Ppu, MemorySpace::Oam];
+
+    // Number of addresses in the space.
+    pub fn size(&self) -> usize {
+        match self {
+            MemorySpace::Cpu => 0x10000,
+            MemorySpace::Ppu => 0x4000,
+            MemorySpace::Oam => 0x100,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MemorySpace::Cpu => "CPU",
+            MemorySpace::Ppu => "PPU",
+            MemorySpace::Oam => "OAM",
+        }
+    }
+
+    pub fn next(&self) -> MemorySpace {
+        match self {
+            MemorySpace::Cpu => MemorySpace::Ppu,
+            MemorySpace::Ppu => MemorySpace::Oam,
+            MemorySpace::Oam => MemorySpace::Cpu,
+        }
+    }
+
+    // Wraps `addr` into the space.
+    pub fn wrap(&self, addr: u16) -> u16 {
+        (addr as usize % self.size()) as u16
+    }
+}
+
+// Reads `addr` in `space` without side effects.
+pub fn peek(bus: &Bus, space: MemorySpace, addr: u16) -> u8 {
+    let addr = space.wrap(addr);
+    match space {
+        MemorySpace::Cpu => bus.peek(addr),
+        MemorySpace::Ppu => bus.ppu.peek(addr),
+        MemorySpace::Oam => bus.ppu.oam_data[addr as usize],
+    }
+}
+
+// Writes `value` to `addr` in `space`. With `side_effects` the write goes through the CPU bus:
+// straight to `addr` for CPU space, through $2006/$2007 for PPU space and through $2003/$2004 for
+// OAM. That moves the PPU's address registers, as it would for the game. The read-only PPU status
+// register can't be poked either way.
+pub fn poke(bus: &mut Bus, space: MemorySpace, addr: u16, value: u8, side_effects: bool) {
+    let addr = space.wrap(addr);
+    if !side_effects {
+        match space {
+            MemorySpace::Cpu => bus.poke(addr, value),
+            MemorySpace::Ppu => bus.ppu.poke(addr, value),
+            MemorySpace::Oam => bus.ppu.oam_data[addr as usize] = value,
+        }
+        return;
+    }
+
+    match space {
+        MemorySpace::Cpu if canonical_addr(addr) == 0x2002 => {}
+        MemorySpace::Cpu => bus.mem_write(addr, value),
+        MemorySpace::Ppu => {
+            // Reading PPUSTATUS resets the $2006 write latch, so the address goes in high byte
+            // first whatever the game left it in.
+            let addr = PPU::canonical_addr(addr);
+            bus.mem_read(0x2002);
+            bus.mem_write(0x2006, (addr >> 8) as u8);
+            bus.mem_write(0x2006, addr as u8);
+            bus.mem_write(0x2007, value);
+        }
+        MemorySpace::Oam => {
+            bus.mem_write(0x2003, addr as u8);
+            bus.mem_write(0x2004, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test::create_test_cartridge;
+
+    #[test]
+    fn test_poke_bypassing_side_effects() {
+        let mut bus = Bus::new(create_test_cartridge());
+        poke(&mut bus, MemorySpace::Cpu, 0x8000, 0xea, false);
+        assert_eq!(peek(&bus, MemorySpace::Cpu, 0x8000), 0xea);
+
+        poke(&mut bus, MemorySpace::Ppu, 0x2400, 0x12, false);
+        assert_eq!(peek(&bus, MemorySpace::Ppu, 0x2400), 0x12);
+        assert_eq!(bus.ppu.ppu_addr.get(), 0);
+
+        poke(&mut bus, MemorySpace::Oam, 0x10, 0x34, false);
+        assert_eq!(bus.ppu.oam_data[0x10], 0x34);
+        assert_eq!(bus.ppu.oam_addr, 0);
+    }
+
+    #[test]
+    fn test_poke_through_registers() {
+        let mut bus = Bus::new(create_test_cartridge());
+        poke(&mut bus, MemorySpace::Cpu, 0x0812, 0x56, true);
+        assert_eq!(peek(&bus, MemorySpace::Cpu, 0x0012), 0x56);
+
+        // The palette write lands and leaves the PPU address register just past it.
+        poke(&mut bus, MemorySpace::Ppu, 0x3f01, 0x21, true);
+        assert_eq!(peek(&bus, MemorySpace::Ppu, 0x3f01), 0x21);
+        assert_eq!(bus.ppu.ppu_addr.get(), 0x3f02);
+
+        poke(&mut bus, MemorySpace::Oam, 0x10, 0x34, true);
+        assert_eq!(bus.ppu.oam_data[0x10], 0x34);
+        assert_eq!(bus.ppu.oam_addr, 0x11);
+
+        // Pokes wrap around the space.
+        poke(&mut bus, MemorySpace::Oam, 0x105, 0x78, false);
+        assert_eq!(bus.ppu.oam_data[0x05], 0x78);
+    }
+}
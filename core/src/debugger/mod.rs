@@ -0,0 +1,605 @@
+//! Interactive debugger: breakpoints on the program counter, watchpoints on memory, pausing and
+//! single stepping.
+//!
+//! The debugger drives the emulator one instruction at a time through `Emulator::step` and
+//! checks each instruction before it runs, so a breakpoint stops the CPU with the program counter
+//! on the breakpoint address and nothing executed. Watchpoints stop after the instruction that
+//! made the access. Frontends call `Debugger::run_frame` in place of `Emulator::run_frame`.
+
+pub mod callstack;
+pub mod cdl;
+pub mod condition;
+pub mod events;
+pub mod gdb;
+pub mod memory;
+pub mod profiler;
+pub mod symbols;
+pub mod trace;
+pub mod watch;
+
+use std::collections::BTreeMap;
+
+use crate::emulator::{Emulator, StepResult};
+#[cfg(feature = "scripting")]
+use crate::script::Script;
+use callstack::CallStack;
+use condition::Condition;
+use profiler::Profiler;
+use symbols::SymbolTable;
+use trace::Tracer;
+use watch::{WatchHit, Watchpoint};
+
+// Opcodes that change the call depth.
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakReason {
+    // Paused from the frontend or with `Debugger::pause`.
+    Paused,
+    // The program counter reached a breakpoint.
+    Breakpoint(u16),
+    // A watched address was accessed.
+    Watchpoint(WatchHit),
+    // A single step finished.
+    Step,
+    // The CPU hit BRK or an unknown opcode.
+    Halted,
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    // Breakpoints, with the condition that has to hold for each to stop the CPU, if any.
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    watchpoints: Vec<Watchpoint>,
+    // Why the CPU is stopped, or None while running.
+    stopped: Option<BreakReason>,
+    // Set on resume, so that the instruction the CPU stopped on runs instead of breaking again.
+    skip_breakpoint: bool,
+    call_depth: i32,
+    // While stepping over or out: stop once the call depth is back down to this.
+    return_depth: Option<i32>,
+    call_stack: CallStack,
+    // Logs every instruction run through the debugger, when set.
+    pub tracer: Option<Tracer>,
+    // Attributes the cycles of every instruction run through the debugger to subroutines, when
+    // set.
+    pub profiler: Option<Profiler>,
+    // Names for addresses, shown in the disassembly and usable for breakpoints.
+    pub symbols: SymbolTable,
+    // Runs its instruction callbacks before every instruction run through the debugger, when set.
+    #[cfg(feature = "scripting")]
+    pub script: Option<Script>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            stopped: None,
+            skip_breakpoint: false,
+            call_depth: 0,
+            return_depth: None,
+            call_stack: CallStack::new(),
+            tracer: None,
+            profiler: None,
+            symbols: SymbolTable::new(),
+            #[cfg(feature = "scripting")]
+            script: None,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr, None);
+    }
+
+    // Sets a breakpoint that only stops the CPU when `condition` holds, replacing any breakpoint
+    // already at `addr`.
+    pub fn add_conditional_breakpoint(&mut self, addr: u16, condition: Condition) {
+        self.breakpoints.insert(addr, Some(condition));
+    }
+
+    // Sets a breakpoint from a symbol or hex address, optionally followed by "if" and a
+    // condition: "C000", "update_sprites" or "C000 if A == $3F". Returns the address.
+    pub fn add_breakpoint_at(&mut self, breakpoint: &str) -> Result<u16, String> {
+        let (symbol, condition) = match breakpoint.split_once(" if ") {
+            Some((symbol, condition)) => (symbol, Some(Condition::parse(condition, &self.symbols)?)),
+            None => (breakpoint, None),
+        };
+        let addr = self.symbols.resolve(symbol.trim())?;
+        self.breakpoints.insert(addr, condition);
+        Ok(addr)
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    // Returns whether the breakpoint is now set.
+    pub fn toggle_breakpoint(&mut self, addr: u16) -> bool {
+        if self.breakpoints.remove(&addr).is_none() {
+            self.breakpoints.insert(addr, None);
+        }
+        self.breakpoints.contains_key(&addr)
+    }
+
+    pub fn has_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains_key(&addr)
+    }
+
+    // Breakpoint addresses in ascending order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    // The condition on the breakpoint at `addr`, if it has one.
+    pub fn breakpoint_condition(&self, addr: u16) -> Option<&Condition> {
+        self.breakpoints.get(&addr)?.as_ref()
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|watch| *watch != watchpoint);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    // Hands the watchpoints to the bus, which does the checking.
+    fn sync_watchpoints(&self, emulator: &mut Emulator) {
+        let bus = &mut emulator.cpu.bus;
+        if bus.watchpoints != self.watchpoints {
+            bus.watchpoints = self.watchpoints.clone();
+        }
+        bus.watch_hits.clear();
+    }
+
+    // The first watchpoint hit by the instruction at `pc`, if any.
+    fn take_watch_hit(emulator: &mut Emulator, pc: u16) -> Option<WatchHit> {
+        let hits = std::mem::take(&mut emulator.cpu.bus.watch_hits);
+        hits.first().map(|hit| WatchHit { pc, ..*hit })
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.stopped.is_some()
+    }
+
+    // Why the debugger stopped, or None while running.
+    pub fn break_reason(&self) -> Option<BreakReason> {
+        self.stopped
+    }
+
+    pub fn pause(&mut self) {
+        if self.stopped.is_none() {
+            self.stopped = Some(BreakReason::Paused);
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.stopped.take().is_some() {
+            self.skip_breakpoint = true;
+        }
+    }
+
+    // Subroutine nesting of the code being run: JSR and entering the NMI handler go one level
+    // deeper, RTS and RTI come back out. Only instructions run through the debugger are counted,
+    // so the value is relative to wherever debugging started.
+    pub fn call_depth(&self) -> i32 {
+        self.call_depth
+    }
+
+    // The subroutines and interrupt handlers the CPU is inside of, as far as the debugger has seen
+    // them being entered.
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    // Runs one instruction, tracking the call depth and call stack, tracing and profiling it.
+    // Breakpoints veto it (conditional ones only when their condition holds) unless
+    // `skip_breakpoint` was set by a resume. Returns the step result, the address of the
+    // instruction and the first watchpoint it hit.
+    fn step_once(&mut self, emulator: &mut Emulator) -> (StepResult, u16, Option<WatchHit>) {
+        let (breakpoints, skip) = (&self.breakpoints, std::mem::take(&mut self.skip_breakpoint));
+        let tracer = &mut self.tracer;
+        #[cfg(feature = "scripting")]
+        let (script, mut script_error) = (&mut self.script, None);
+        let (mut pc, mut opcode, mut stack_pointer, mut trace_error) = (0, 0, 0, None);
+        let cycles = emulator.cpu.bus.cycles;
+        let result = emulator.step(|cpu| {
+            pc = cpu.program_counter;
+            opcode = cpu.bus.peek(pc);
+            stack_pointer = cpu.stack_pointer;
+            let run = skip
+                || match breakpoints.get(&pc) {
+                    Some(Some(condition)) => !condition.is_met(cpu),
+                    Some(None) => false,
+                    None => true,
+                };
+            if let (true, Some(tracer)) = (run, tracer.as_mut()) {
+                trace_error = tracer.log(cpu).err();
+            }
+            #[cfg(feature = "scripting")]
+            if let (true, Some(script)) = (run, script.as_mut()) {
+                script_error = script.before_instruction(cpu).err();
+            }
+            run
+        });
+        if let Some(e) = trace_error {
+            tracing::warn!(target: "debugger", "Trace stopped: {}", e);
+            self.tracer = None;
+        }
+        #[cfg(feature = "scripting")]
+        if let Some(e) = script_error {
+            tracing::warn!(target: "debugger", "Script stopped: {}", e);
+            self.script = None;
+        }
+
+        if emulator.entered_nmi() {
+            self.call_depth += 1;
+            self.call_stack.enter_nmi(&emulator.cpu, pc, stack_pointer);
+            if let Some(profiler) = &mut self.profiler {
+                profiler.enter_nmi(pc);
+            }
+        }
+        if result != StepResult::Interrupted {
+            if let Some(profiler) = &mut self.profiler {
+                let taken = emulator.cpu.bus.cycles - cycles;
+                profiler.record(opcode, taken as u64, emulator.cpu.program_counter);
+            }
+            match opcode {
+                JSR => {
+                    self.call_depth += 1;
+                    self.call_stack.enter_subroutine(&emulator.cpu, pc);
+                }
+                RTS | RTI => self.call_depth -= 1,
+                _ => {}
+            }
+            self.call_stack.unwind(emulator.cpu.stack_pointer);
+        }
+        (result, pc, Self::take_watch_hit(emulator, pc))
+    }
+
+    // Runs the rest of the current frame, stopping early on a breakpoint, a watchpoint, or when a
+    // step over or step out finishes. Does nothing while paused. Returns true if a frame was
+    // completed (and rendered).
+    pub fn run_frame(&mut self, emulator: &mut Emulator) -> bool {
+        if self.stopped.is_some() {
+            return false;
+        }
+
+        self.sync_watchpoints(emulator);
+        loop {
+            let (result, pc, hit) = self.step_once(emulator);
+            let frame_done = matches!(result, StepResult::FrameComplete | StepResult::Halted);
+            let reason = match (result, hit) {
+                (StepResult::Halted, _) => Some(BreakReason::Halted),
+                (StepResult::Interrupted, _) => Some(BreakReason::Breakpoint(pc)),
+                (_, Some(hit)) => Some(BreakReason::Watchpoint(hit)),
+                _ => match self.return_depth {
+                    Some(depth) if self.call_depth <= depth => Some(BreakReason::Step),
+                    _ => None,
+                },
+            };
+            if reason.is_some() {
+                self.stopped = reason;
+                self.return_depth = None;
+                return frame_done;
+            }
+            if frame_done {
+                return true;
+            }
+        }
+    }
+
+    // Executes exactly one instruction (plus any NMI entry before it) and stays paused, stepping
+    // into subroutines. Returns true if the instruction completed a frame.
+    pub fn step_instruction(&mut self, emulator: &mut Emulator) -> bool {
+        self.sync_watchpoints(emulator);
+        self.skip_breakpoint = true;
+        self.return_depth = None;
+        let (result, _, hit) = self.step_once(emulator);
+        self.stopped = Some(match (result, hit) {
+            (StepResult::Halted, _) => BreakReason::Halted,
+            (_, Some(hit)) => BreakReason::Watchpoint(hit),
+            _ => BreakReason::Step,
+        });
+        matches!(result, StepResult::FrameComplete | StepResult::Halted)
+    }
+
+    // Steps over a JSR by running until the subroutine returns; any other instruction is a single
+    // step. The subroutine runs over as many frames as it takes, through `run_frame`.
+    pub fn step_over(&mut self, emulator: &mut Emulator) -> bool {
+        if emulator.cpu.bus.peek(emulator.cpu.program_counter) != JSR {
+            return self.step_instruction(emulator);
+        }
+        self.run_until_depth(self.call_depth);
+        false
+    }
+
+    // Runs until the current subroutine (or interrupt handler) returns to its caller.
+    pub fn step_out(&mut self) {
+        self.run_until_depth(self.call_depth - 1);
+    }
+
+    fn run_until_depth(&mut self, depth: i32) {
+        self.return_depth = Some(depth);
+        self.stopped = None;
+        self.skip_breakpoint = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::cpu::Mem;
+
+    // An emulator running `program` from $0600 in RAM.
+    fn emulator_with(source: &str) -> Emulator {
+        let mut emulator = Emulator::new(create_test_cartridge());
+        for (i, byte) in asm!(source).into_iter().enumerate() {
+            emulator.cpu.mem_write(0x0600 + i as u16, byte);
+        }
+        emulator.cpu.program_counter = 0x0600;
+        emulator
+    }
+
+    const LOOP: &str = "loop: INX\nINX\nJMP loop";
+
+    #[test]
+    fn test_breakpoint_stops_before_instruction() {
+        let mut emulator = emulator_with(LOOP);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x0601);
+
+        assert!(!debugger.run_frame(&mut emulator));
+        assert_eq!(debugger.break_reason(), Some(BreakReason::Breakpoint(0x0601)));
+        assert_eq!(emulator.cpu.program_counter, 0x0601);
+        assert_eq!(emulator.cpu.register_x, 1);
+
+        // Paused: nothing runs until resumed.
+        assert!(!debugger.run_frame(&mut emulator));
+        assert_eq!(emulator.cpu.register_x, 1);
+
+        // Resuming runs past the breakpoint and stops on it again one loop later.
+        debugger.resume();
+        assert!(!debugger.run_frame(&mut emulator));
+        assert_eq!(emulator.cpu.program_counter, 0x0601);
+        assert_eq!(emulator.cpu.register_x, 3);
+    }
+
+    #[test]
+    fn test_run_frame_without_breakpoints_completes_frame() {
+        let mut emulator = emulator_with(LOOP);
+        let mut debugger = Debugger::new();
+        assert!(debugger.run_frame(&mut emulator));
+        assert_eq!(emulator.frame_count(), 1);
+        assert!(!debugger.is_paused());
+    }
+
+    #[test]
+    fn test_step_and_toggle() {
+        let mut emulator = emulator_with(LOOP);
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        debugger.step_instruction(&mut emulator);
+        debugger.step_instruction(&mut emulator);
+        assert_eq!(emulator.cpu.register_x, 2);
+        assert_eq!(debugger.break_reason(), Some(BreakReason::Step));
+
+        assert!(debugger.toggle_breakpoint(0x0602));
+        assert!(!debugger.toggle_breakpoint(0x0602));
+        assert_eq!(debugger.breakpoints().count(), 0);
+    }
+
+    #[test]
+    fn test_write_watchpoint_reports_values() {
+        let mut emulator = emulator_with("LDA #$07\nloop: STA $12\nINX\nJMP loop");
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint("12:w".parse().unwrap());
+
+        assert!(!debugger.run_frame(&mut emulator));
+        assert_eq!(
+            debugger.break_reason(),
+            Some(BreakReason::Watchpoint(WatchHit {
+                addr: 0x0012,
+                kind: watch::AccessKind::Write,
+                old: 0x00,
+                new: 0x07,
+                pc: 0x0602,
+            }))
+        );
+        // Stops after the store, before the next instruction.
+        assert_eq!(emulator.cpu.program_counter, 0x0604);
+
+        // Reads don't trigger a write watchpoint.
+        debugger.remove_watchpoint("12:w".parse().unwrap());
+        debugger.add_watchpoint("0600:w".parse().unwrap());
+        debugger.resume();
+        assert!(debugger.run_frame(&mut emulator));
+    }
+
+    fn nested_calls() -> Emulator {
+        emulator_with(
+            "
+            main:   JSR outer
+                    INX
+                    JMP main
+                    .org $0610
+            outer:  JSR inner
+                    INY
+                    RTS
+                    .org $0620
+            inner:  DEX
+                    RTS
+            ",
+        )
+    }
+
+    #[test]
+    fn test_step_into_tracks_depth() {
+        let mut emulator = nested_calls();
+        let mut debugger = Debugger::new();
+        debugger.step_instruction(&mut emulator);
+        debugger.step_instruction(&mut emulator);
+        assert_eq!(emulator.cpu.program_counter, 0x0620);
+        assert_eq!(debugger.call_depth(), 2);
+    }
+
+    #[test]
+    fn test_step_over_runs_whole_subroutine() {
+        let mut emulator = nested_calls();
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        debugger.step_over(&mut emulator);
+        debugger.run_frame(&mut emulator);
+
+        assert_eq!(debugger.break_reason(), Some(BreakReason::Step));
+        assert_eq!(emulator.cpu.program_counter, 0x0603);
+        assert_eq!((emulator.cpu.register_x, emulator.cpu.register_y), (0xff, 1));
+        assert_eq!(debugger.call_depth(), 0);
+
+        // Not a JSR: a plain step.
+        debugger.step_over(&mut emulator);
+        assert_eq!(emulator.cpu.program_counter, 0x0604);
+    }
+
+    #[test]
+    fn test_step_out_returns_to_caller() {
+        let mut emulator = nested_calls();
+        let mut debugger = Debugger::new();
+        debugger.step_instruction(&mut emulator);
+        debugger.step_instruction(&mut emulator);
+        debugger.step_out();
+        debugger.run_frame(&mut emulator);
+
+        assert_eq!(emulator.cpu.program_counter, 0x0613);
+        assert_eq!(debugger.call_depth(), 1);
+        assert_eq!(emulator.cpu.register_y, 0);
+    }
+
+    #[test]
+    fn test_breakpoint_interrupts_step_over() {
+        let mut emulator = nested_calls();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x0620);
+        debugger.step_over(&mut emulator);
+        debugger.run_frame(&mut emulator);
+        assert_eq!(debugger.break_reason(), Some(BreakReason::Breakpoint(0x0620)));
+    }
+
+    #[test]
+    fn test_brk_halts() {
+        let mut emulator = emulator_with("INX\nBRK");
+        let mut debugger = Debugger::new();
+        debugger.run_frame(&mut emulator);
+        assert_eq!(debugger.break_reason(), Some(BreakReason::Halted));
+    }
+
+    #[test]
+    fn test_profiler_attributes_cycles_to_subroutines() {
+        let mut emulator = nested_calls();
+        let mut debugger = Debugger::new();
+        debugger.profiler = Some(Profiler::new());
+        // One time round the loop.
+        for _ in 0..8 {
+            debugger.step_instruction(&mut emulator);
+        }
+
+        let report = debugger.profiler.as_ref().unwrap().report();
+        let stats = |routine| report.iter().find(|(r, _)| *r == routine).unwrap().1;
+        let outer = stats(profiler::Routine::Subroutine(0x0610));
+        assert_eq!((outer.calls, outer.exclusive, outer.inclusive), (1, 14, 22));
+        assert_eq!(stats(profiler::Routine::Subroutine(0x0620)).exclusive, 8);
+        assert_eq!(stats(profiler::Routine::Main).exclusive, 11);
+    }
+
+    #[test]
+    fn test_breakpoint_on_symbol() {
+        let mut emulator = nested_calls();
+        let mut debugger = Debugger::new();
+        debugger.symbols.insert(0x0620, "decrement");
+        assert_eq!(debugger.add_breakpoint_at("decrement"), Ok(0x0620));
+        assert!(debugger.add_breakpoint_at("nowhere").is_err());
+        debugger.run_frame(&mut emulator);
+        assert_eq!(debugger.break_reason(), Some(BreakReason::Breakpoint(0x0620)));
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let mut emulator = nested_calls();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x0620);
+        debugger.symbols.insert(0x0610, "outer");
+        debugger.run_frame(&mut emulator);
+        assert_eq!(
+            debugger.call_stack().backtrace(&debugger.symbols),
+            vec!["$0620, called from $0610", "outer ($0610), called from $0600"]
+        );
+        assert_eq!(debugger.call_stack().frames()[1].return_addr, 0x0613);
+
+        // Both frames are gone once the calls return.
+        debugger.remove_breakpoint(0x0620);
+        debugger.add_breakpoint(0x0603);
+        debugger.resume();
+        debugger.run_frame(&mut emulator);
+        assert!(debugger.call_stack().is_empty());
+    }
+
+    #[test]
+    fn test_call_stack_survives_discarded_return_address() {
+        // The subroutine drops its return address and jumps back instead of returning.
+        let mut emulator = emulator_with(
+            "
+            main:   JSR sub
+            sub:    PLA
+                    PLA
+                    JMP main
+            ",
+        );
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x0603);
+        debugger.run_frame(&mut emulator);
+        assert_eq!(debugger.call_stack().len(), 1);
+        for _ in 0..30 {
+            debugger.step_instruction(&mut emulator);
+        }
+        assert!(debugger.call_stack().len() <= 1);
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut emulator = emulator_with(LOOP);
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.add_breakpoint_at("0601 if x == 5"), Ok(0x0601));
+        assert!(debugger.add_breakpoint_at("0601 if x ==").is_err());
+        assert_eq!(debugger.breakpoint_condition(0x0601).unwrap().to_string(), "x == 5");
+
+        debugger.run_frame(&mut emulator);
+        assert_eq!(debugger.break_reason(), Some(BreakReason::Breakpoint(0x0601)));
+        assert_eq!(emulator.cpu.register_x, 5);
+        // Toggling clears the condition along with the breakpoint.
+        debugger.toggle_breakpoint(0x0601);
+        debugger.toggle_breakpoint(0x0601);
+        assert_eq!(debugger.breakpoint_condition(0x0601), None);
+    }
+}
@@ -0,0 +1,222 @@
+//! Cycle profiler: attributes the CPU cycles each instruction takes to the subroutine it ran in,
+//! so ROM developers can see where their game loop spends its time.
+//!
+//! Subroutines are identified by their JSR target, and the NMI handler counts as a routine of its
+//! own. Each routine gets the cycles spent in its own code (exclusive) and including everything it
+//! called (inclusive). Code outside any subroutine counts as "main". Calls are tracked by matching
+//! JSR with RTS and NMI entry with RTI, so code that juggles return addresses on the stack (jump
+//! tables built on RTS, for instance) can confuse the attribution.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::debugger::symbols::SymbolTable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Routine {
+    // Code outside any subroutine the profiler saw being called.
+    Main,
+    // The subroutine starting at this address, entered with JSR.
+    Subroutine(u16),
+    // The NMI handler starting at this address.
+    Nmi(u16),
+}
+
+impl Routine {
+    // The routine's symbol, if it has one, or what `Display` shows.
+    pub fn name(&self, symbols: &SymbolTable) -> String {
+        match self {
+            Routine::Subroutine(addr) => symbols.name(*addr).map_or(self.to_string(), str::to_string),
+            Routine::Nmi(addr) => match symbols.name(*addr) {
+                Some(name) => format!("NMI {}", name),
+                None => self.to_string(),
+            },
+            Routine::Main => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Routine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Routine::Main => write!(f, "main"),
+            Routine::Subroutine(addr) => write!(f, "${:04X}", addr),
+            Routine::Nmi(addr) => write!(f, "NMI ${:04X}", addr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RoutineStats {
+    pub calls: u64,
+    // Cycles spent in the routine's own instructions.
+    pub exclusive: u64,
+    // Cycles from entering the routine to returning from it, including callees. Recursive calls
+    // are only counted once.
+    pub inclusive: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Call {
+    routine: Routine,
+    // Total cycles when the routine was entered.
+    entered: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    stats: HashMap<Routine, RoutineStats>,
+    stack: Vec<Call>,
+    // Cycles attributed so far.
+    total: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Profiler::new();
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total
+    }
+
+    fn current(&self) -> Routine {
+        self.stack.last().map_or(Routine::Main, |call| call.routine)
+    }
+
+    fn enter(&mut self, routine: Routine) {
+        self.stats.entry(routine).or_default().calls += 1;
+        self.stack.push(Call { routine, entered: self.total });
+    }
+
+    fn leave(&mut self) {
+        // More returns than calls: the game returned through an address it pushed itself.
+        let Some(call) = self.stack.pop() else {
+            return;
+        };
+        if !self.stack.iter().any(|outer| outer.routine == call.routine) {
+            self.stats.entry(call.routine).or_default().inclusive += self.total - call.entered;
+        }
+    }
+
+    // Records the CPU jumping into the NMI handler at `handler`.
+    pub fn enter_nmi(&mut self, handler: u16) {
+        self.enter(Routine::Nmi(handler));
+    }
+
+    // Records an instruction that took `cycles`. `next_pc` is where it left the program counter,
+    // which is the subroutine's address after a JSR.
+    pub fn record(&mut self, opcode: u8, cycles: u64, next_pc: u16) {
+        self.stats.entry(self.current()).or_default().exclusive += cycles;
+        self.total += cycles;
+        match opcode {
+            super::JSR => self.enter(Routine::Subroutine(next_pc)),
+            super::RTS | super::RTI => self.leave(),
+            _ => {}
+        }
+    }
+
+    // Routines with their stats, in descending order of exclusive cycles. Routines still running
+    // have the time so far included.
+    pub fn report(&self) -> Vec<(Routine, RoutineStats)> {
+        let mut stats = self.stats.clone();
+        stats.entry(Routine::Main).or_default().inclusive = self.total;
+        let mut counted = Vec::new();
+        for call in &self.stack {
+            if !counted.contains(&call.routine) {
+                stats.entry(call.routine).or_default().inclusive += self.total - call.entered;
+                counted.push(call.routine);
+            }
+        }
+
+        let mut report: Vec<(Routine, RoutineStats)> = stats.into_iter().collect();
+        report.sort_by(|(a, a_stats), (b, b_stats)| b_stats.exclusive.cmp(&a_stats.exclusive).then(a.cmp(b)));
+        report
+    }
+
+    // The report as a table, with percentages of the total and routines named from `symbols`.
+    pub fn format_report(&self, symbols: &SymbolTable) -> String {
+        let percent = |cycles: u64| 100.0 * cycles as f64 / self.total.max(1) as f64;
+        let mut text = format!(
+            "{:<24} {:>8} {:>12} {:>7} {:>12} {:>7}\n",
+            "Routine", "Calls", "Exclusive", "%", "Inclusive", "%"
+        );
+        for (routine, stats) in self.report() {
+            text += &format!(
+                "{:<24} {:>8} {:>12} {:>6.2}% {:>12} {:>6.2}%\n",
+                routine.name(symbols),
+                stats.calls,
+                stats.exclusive,
+                percent(stats.exclusive),
+                stats.inclusive,
+                percent(stats.inclusive)
+            );
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{JSR, RTI, RTS};
+    use super::*;
+
+    const NOP: u8 = 0xea;
+
+    #[test]
+    fn test_cycles_go_to_the_called_routine() {
+        let mut profiler = Profiler::new();
+        profiler.record(NOP, 2, 0x8001);
+        profiler.record(JSR, 6, 0x9000);
+        profiler.record(NOP, 2, 0x9001);
+        profiler.record(JSR, 6, 0xa000);
+        profiler.record(RTS, 6, 0x9004);
+        profiler.record(RTS, 6, 0x8004);
+        profiler.record(NOP, 2, 0x8005);
+
+        let report: HashMap<Routine, RoutineStats> = profiler.report().into_iter().collect();
+        let sub = report[&Routine::Subroutine(0x9000)];
+        assert_eq!((sub.calls, sub.exclusive, sub.inclusive), (1, 14, 20));
+        let inner = report[&Routine::Subroutine(0xa000)];
+        assert_eq!((inner.calls, inner.exclusive, inner.inclusive), (1, 6, 6));
+        assert_eq!(report[&Routine::Main].exclusive, 10);
+        assert_eq!(profiler.total_cycles(), 30);
+        // Sorted by exclusive cycles.
+        assert_eq!(profiler.report()[0].0, Routine::Subroutine(0x9000));
+    }
+
+    #[test]
+    fn test_nmi_handler_and_unmatched_returns() {
+        let mut profiler = Profiler::new();
+        profiler.enter_nmi(0xc000);
+        profiler.record(NOP, 9, 0xc001);
+        profiler.record(RTI, 6, 0x8000);
+        // A return with nothing on the call stack stays in main.
+        profiler.record(RTS, 6, 0x8123);
+
+        let report: HashMap<Routine, RoutineStats> = profiler.report().into_iter().collect();
+        assert_eq!(report[&Routine::Nmi(0xc000)].inclusive, 15);
+        assert_eq!(report[&Routine::Main].exclusive, 6);
+        assert!(profiler.format_report(&SymbolTable::new()).contains("NMI $C000"));
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0xc000, "nmi");
+        assert!(profiler.format_report(&symbols).contains("NMI nmi "));
+    }
+
+    #[test]
+    fn test_recursion_counts_inclusive_once() {
+        let mut profiler = Profiler::new();
+        profiler.record(JSR, 6, 0x9000);
+        profiler.record(JSR, 6, 0x9000);
+        profiler.record(RTS, 6, 0x9003);
+        profiler.record(RTS, 6, 0x8003);
+
+        let report: HashMap<Routine, RoutineStats> = profiler.report().into_iter().collect();
+        let sub = report[&Routine::Subroutine(0x9000)];
+        assert_eq!((sub.calls, sub.exclusive, sub.inclusive), (2, 18, 18));
+    }
+}
@@ -0,0 +1,174 @@
+//! Symbol tables: names for addresses, loaded from the label files assemblers and other emulators
+//! write, so the debugger can show `JSR update_sprites` instead of `JSR $C4F2`.
+//!
+//! Two formats are read:
+//! - FCEUX name lists (`.nl`): one `$C4F2#update_sprites#comment` per line. FCEUX keeps one file
+//!   per PRG bank, named after the ROM (`game.nes.0.nl`, `game.nes.1.nl`, ...) plus
+//!   `game.nes.ram.nl` for RAM.
+//! - ca65/ld65 debug files (`.dbg`, from `ld65 --dbgfile`): the `sym` lines for labels.
+//!
+//! Reference: <https://fceux.com/web/help/NLFilesFormat.html>,
+//! <https://cc65.github.io/doc/debugging.html>
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::debugger::watch::parse_range;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    names: BTreeMap<u16, String>,
+    addrs: HashMap<String, u16>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    // Names `addr`. An address keeps the first name it is given.
+    pub fn insert(&mut self, addr: u16, name: &str) {
+        self.names.entry(addr).or_insert_with(|| name.to_string());
+        self.addrs.entry(name.to_string()).or_insert(addr);
+    }
+
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.names.get(&addr).map(String::as_str)
+    }
+
+    pub fn addr(&self, name: &str) -> Option<u16> {
+        self.addrs.get(name).copied()
+    }
+
+    // Symbols in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.names.iter().map(|(addr, name)| (*addr, name.as_str()))
+    }
+
+    // Resolves a symbol name, or failing that a hex address ("C000" or "$C000").
+    pub fn resolve(&self, s: &str) -> Result<u16, String> {
+        match self.addr(s) {
+            Some(addr) => Ok(addr),
+            None => match parse_range(s) {
+                Ok((start, end)) if start == end => Ok(start),
+                _ => Err(format!("Unknown symbol {}", s)),
+            },
+        }
+    }
+
+    // Adds the symbols of an FCEUX name list.
+    pub fn add_fceux(&mut self, text: &str) -> Result<(), String> {
+        for line in text.lines().map(str::trim).filter(|line| line.starts_with('$')) {
+            let mut fields = line.splitn(3, '#');
+            let addr = fields.next().unwrap_or_default();
+            let name = fields.next().unwrap_or_default().trim();
+            // "$0200/10" names a 16 byte array; the name goes on its first address.
+            let addr = addr.split('/').next().unwrap_or_default();
+            let addr = u16::from_str_radix(&addr[1..], 16).map_err(|_| format!("Invalid address in {}", line))?;
+            if !name.is_empty() {
+                self.insert(addr, name);
+            }
+        }
+        Ok(())
+    }
+
+    // Adds the labels of a ca65/ld65 debug file.
+    pub fn add_ca65(&mut self, text: &str) -> Result<(), String> {
+        for line in text.lines() {
+            let Some(fields) = line.strip_prefix("sym\t") else {
+                continue;
+            };
+            let field = |key: &str| {
+                fields
+                    .split(',')
+                    .find_map(|field| field.strip_prefix(key)?.strip_prefix('='))
+            };
+            if field("type") != Some("lab") {
+                continue;
+            }
+            let (Some(name), Some(val)) = (field("name"), field("val")) else {
+                continue;
+            };
+            let name = name.trim_matches('"');
+            let addr = u16::from_str_radix(val.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("Invalid value for symbol {}", name))?;
+            self.insert(addr, name);
+        }
+        Ok(())
+    }
+
+    // Loads a `.dbg` or `.nl` file, going by the extension.
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("dbg") => self.add_ca65(&text),
+            Some("nl") => self.add_fceux(&text),
+            _ => Err(format!("Unknown symbol file type {}, expected .dbg or .nl", path.display())),
+        }
+    }
+
+    // Loads whichever of FCEUX's name lists for the ROM at `rom_path` exist. Returns how many were
+    // found.
+    pub fn load_fceux_for_rom(&mut self, rom_path: &Path) -> Result<usize, String> {
+        let mut found = 0;
+        let suffixes = ["ram".to_string()].into_iter().chain((0..64).map(|bank| bank.to_string()));
+        for suffix in suffixes {
+            let mut path = rom_path.as_os_str().to_owned();
+            path.push(format!(".{}.nl", suffix));
+            let path = Path::new(&path);
+            if path.exists() {
+                self.load(path)?;
+                found += 1;
+            }
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fceux_name_list() {
+        let mut symbols = SymbolTable::new();
+        symbols
+            .add_fceux("$C4F2#update_sprites#Copies the sprites to OAM\n$0200/100#oam_buffer#\n$0010##unnamed\n")
+            .unwrap();
+        assert_eq!(symbols.name(0xc4f2), Some("update_sprites"));
+        assert_eq!(symbols.addr("oam_buffer"), Some(0x0200));
+        assert_eq!(symbols.len(), 2);
+        assert!(symbols.add_fceux("$ZZZZ#bad#").is_err());
+    }
+
+    #[test]
+    fn test_ca65_debug_file() {
+        let text = "version\tmajor=2,minor=0\n\
+            sym\tid=0,name=\"reset\",addrsize=absolute,scope=0,def=1,ref=5,val=0xC000,seg=0,type=lab\n\
+            sym\tid=1,name=\"PPUCTRL\",addrsize=absolute,scope=0,def=2,val=0x2000,type=equ\n\
+            sym\tid=2,name=\"@loop\",addrsize=absolute,parent=0,def=3,val=0xC004,seg=0,type=lab\n";
+        let mut symbols = SymbolTable::new();
+        symbols.add_ca65(text).unwrap();
+        assert_eq!(symbols.name(0xc000), Some("reset"));
+        assert_eq!(symbols.name(0xc004), Some("@loop"));
+        // Constants aren't addresses.
+        assert_eq!(symbols.addr("PPUCTRL"), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0xc4f2, "update_sprites");
+        assert_eq!(symbols.resolve("update_sprites"), Ok(0xc4f2));
+        assert_eq!(symbols.resolve("$8000"), Ok(0x8000));
+        assert!(symbols.resolve("nowhere").is_err());
+    }
+}
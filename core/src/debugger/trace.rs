@@ -0,0 +1,296 @@
+//! Trace logger: one line per instruction, in a chosen set of columns, for the instructions in a
+//! chosen set of address ranges.
+//!
+//! Lines go to a file or to an in-memory ring buffer holding the last few thousand. The ring is
+//! shared, so a frontend can keep a handle to it and dump it after a crash, when the tracer
+//! itself may be unreachable. For traces to compare against nestest's log, use `cpu::trace`.
+//!
+//! Reference: <https://www.qmtpro.com/~nes/misc/nestest.txt>
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use crate::cpu::CPU;
+use crate::debugger::symbols::SymbolTable;
+use crate::debugger::watch::parse_range;
+use crate::disasm;
+
+// Lines kept by a ring buffer when no size is given.
+pub const DEFAULT_RING_LINES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceColumn {
+    // Program counter: "C000".
+    Pc,
+    // The symbol at the program counter, if any.
+    Label,
+    // 16KB PRG-ROM bank the program counter is in: "01", or "--" outside ROM.
+    Bank,
+    // Instruction bytes: "4C F5 C5".
+    Bytes,
+    // "JMP $C5F5".
+    Disassembly,
+    // "A:00 X:00 Y:00 SP:FD".
+    Registers,
+    // Status register in hex: "P:24".
+    Status,
+    // Status flags as letters, upper case when set: "nvUbdIzc".
+    Flags,
+    // PPU scanline and dot: "PPU:241, 21".
+    Ppu,
+    // CPU cycles since power-on: "CYC:7".
+    Cycles,
+}
+
+impl TraceColumn {
+    pub const ALL: [TraceColumn; 10] = [
+        TraceColumn::Pc,
+        TraceColumn::Label,
+        TraceColumn::Bank,
+        TraceColumn::Bytes,
+        TraceColumn::Disassembly,
+        TraceColumn::Registers,
+        TraceColumn::Status,
+        TraceColumn::Flags,
+        TraceColumn::Ppu,
+        TraceColumn::Cycles,
+    ];
+
+    pub const DEFAULT: [TraceColumn; 6] = [
+        TraceColumn::Pc,
+        TraceColumn::Bytes,
+        TraceColumn::Disassembly,
+        TraceColumn::Registers,
+        TraceColumn::Flags,
+        TraceColumn::Ppu,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TraceColumn::Pc => "pc",
+            TraceColumn::Label => "label",
+            TraceColumn::Bank => "bank",
+            TraceColumn::Bytes => "bytes",
+            TraceColumn::Disassembly => "disasm",
+            TraceColumn::Registers => "regs",
+            TraceColumn::Status => "p",
+            TraceColumn::Flags => "flags",
+            TraceColumn::Ppu => "ppu",
+            TraceColumn::Cycles => "cycles",
+        }
+    }
+
+    // The column for the instruction the CPU is about to run.
+    fn format(&self, cpu: &CPU, symbols: &SymbolTable) -> String {
+        let pc = cpu.program_counter;
+        match self {
+            TraceColumn::Pc => format!("{:04X}", pc),
+            TraceColumn::Label => format!("{:16}", symbols.name(pc).unwrap_or_default()),
+            TraceColumn::Bank => match cpu.bus.prg_rom_bank(pc) {
+                Some(bank) => format!("{:02X}", bank),
+                None => "--".to_string(),
+            },
+            TraceColumn::Bytes => {
+                let instruction = disasm::decode(|addr| cpu.bus.peek(addr), pc);
+                let hex: Vec<String> = instruction.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                format!("{:8}", hex.join(" "))
+            }
+            TraceColumn::Disassembly => {
+                format!("{:12}", disasm::decode(|addr| cpu.bus.peek(addr), pc).with_symbols(symbols))
+            }
+            TraceColumn::Registers => format!(
+                "A:{:02X} X:{:02X} Y:{:02X} SP:{:02X}",
+                cpu.register_a, cpu.register_x, cpu.register_y, cpu.stack_pointer
+            ),
+            TraceColumn::Status => format!("P:{:02X}", cpu.status.bits()),
+            TraceColumn::Flags => "NVUBDIZC"
+                .chars()
+                .enumerate()
+                .map(|(i, letter)| match cpu.status.bits() & (0x80 >> i) {
+                    0 => letter.to_ascii_lowercase(),
+                    _ => letter,
+                })
+                .collect(),
+            TraceColumn::Ppu => format!("PPU:{:>3},{:>3}", cpu.bus.ppu.scanline, cpu.bus.ppu.cycles),
+            TraceColumn::Cycles => format!("CYC:{}", cpu.bus.cycles),
+        }
+    }
+}
+
+impl FromStr for TraceColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TraceColumn::ALL
+            .into_iter()
+            .find(|column| column.name() == s)
+            .ok_or_else(|| format!("Unknown trace column {}", s))
+    }
+}
+
+// Parses a comma-separated list of column names: "pc,disasm,regs".
+pub fn parse_columns(s: &str) -> Result<Vec<TraceColumn>, String> {
+    s.split(',').map(|name| name.trim().parse()).collect()
+}
+
+// The last lines traced, oldest first.
+pub type TraceRing = Arc<Mutex<VecDeque<String>>>;
+
+#[derive(Debug)]
+enum TraceOutput {
+    File(BufWriter<File>),
+    Ring { lines: TraceRing, capacity: usize },
+}
+
+#[derive(Debug)]
+pub struct Tracer {
+    pub columns: Vec<TraceColumn>,
+    // Only instructions in these ranges (inclusive) are traced. Empty traces everything.
+    pub ranges: Vec<(u16, u16)>,
+    // Names for the label column and for addresses in the disassembly.
+    pub symbols: SymbolTable,
+    output: TraceOutput,
+}
+
+impl Tracer {
+    // Traces to the file at `path`, replacing it.
+    pub fn to_file(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Could not create {}: {}", path.display(), e))?;
+        Ok(Tracer::with_output(TraceOutput::File(BufWriter::new(file))))
+    }
+
+    // Traces into a ring buffer that keeps the last `capacity` lines.
+    pub fn ring(capacity: usize) -> Self {
+        let lines = Arc::new(Mutex::new(VecDeque::with_capacity(capacity)));
+        Tracer::with_output(TraceOutput::Ring { lines, capacity })
+    }
+
+    fn with_output(output: TraceOutput) -> Self {
+        Tracer {
+            columns: TraceColumn::DEFAULT.to_vec(),
+            ranges: Vec::new(),
+            symbols: SymbolTable::new(),
+            output,
+        }
+    }
+
+    // Traces only instructions between `start` and `end`, in addition to any ranges already added.
+    pub fn add_range(&mut self, start: u16, end: u16) {
+        self.ranges.push((start.min(end), start.max(end)));
+    }
+
+    // Parses and adds a range like "8000-BFFF".
+    pub fn add_range_str(&mut self, s: &str) -> Result<(), String> {
+        let (start, end) = parse_range(s)?;
+        self.add_range(start, end);
+        Ok(())
+    }
+
+    // The ring buffer, for reading it after the tracer is gone. None when tracing to a file.
+    pub fn ring_buffer(&self) -> Option<TraceRing> {
+        match &self.output {
+            TraceOutput::Ring { lines, .. } => Some(lines.clone()),
+            TraceOutput::File(_) => None,
+        }
+    }
+
+    // The lines in the ring buffer, oldest first. Empty when tracing to a file.
+    pub fn lines(&self) -> Vec<String> {
+        match &self.output {
+            TraceOutput::Ring { lines, .. } => lines.lock().unwrap().iter().cloned().collect(),
+            TraceOutput::File(_) => Vec::new(),
+        }
+    }
+
+    fn traces(&self, pc: u16) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|(start, end)| (*start..=*end).contains(&pc))
+    }
+
+    // The line for the instruction the CPU is about to run.
+    pub fn format_line(&self, cpu: &CPU) -> String {
+        let columns: Vec<String> = self.columns.iter().map(|column| column.format(cpu, &self.symbols)).collect();
+        columns.join("  ").trim_end().to_string()
+    }
+
+    // Traces the instruction the CPU is about to run, if it is in range.
+    pub fn log(&mut self, cpu: &CPU) -> Result<(), String> {
+        if !self.traces(cpu.program_counter) {
+            return Ok(());
+        }
+        let line = self.format_line(cpu);
+        match &mut self.output {
+            TraceOutput::File(file) => writeln!(file, "{}", line).map_err(|e| e.to_string()),
+            TraceOutput::Ring { lines, capacity } => {
+                let mut lines = lines.lock().unwrap();
+                if lines.len() == *capacity {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        match &mut self.output {
+            TraceOutput::File(file) => file.flush().map_err(|e| e.to_string()),
+            TraceOutput::Ring { .. } => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::bus::Bus;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::cpu::Mem;
+
+    fn cpu_at(pc: u16, program: &[u8]) -> CPU {
+        let mut cpu = CPU::new(Bus::new(create_test_cartridge()));
+        for (i, byte) in program.iter().enumerate() {
+            cpu.mem_write(pc + i as u16, *byte);
+        }
+        cpu.program_counter = pc;
+        cpu
+    }
+
+    #[test]
+    fn test_columns() {
+        let mut cpu = cpu_at(0x0600, &asm!("LDA #$01"));
+        cpu.register_a = 0x80;
+        let mut tracer = Tracer::ring(4);
+        tracer.columns = parse_columns("pc,bank,bytes,disasm,p,flags").unwrap();
+        assert_eq!(tracer.format_line(&cpu), "0600  --  A9 01     LDA #$01      P:24  nvUbdIzc");
+
+        tracer.columns = parse_columns("regs,cycles").unwrap();
+        assert_eq!(tracer.format_line(&cpu), "A:80 X:00 Y:00 SP:FD  CYC:7");
+        assert!(parse_columns("pc,nope").is_err());
+
+        tracer.symbols.insert(0x0600, "start");
+        tracer.columns = parse_columns("label,disasm").unwrap();
+        assert_eq!(tracer.format_line(&cpu), "start             LDA #$01");
+    }
+
+    #[test]
+    fn test_ring_keeps_last_lines_in_range() {
+        let mut cpu = cpu_at(0x0600, &asm!("INX\nINX\nINX\nINX"));
+        let mut tracer = Tracer::ring(2);
+        tracer.columns = vec![TraceColumn::Pc];
+        tracer.add_range_str("0601-0603").unwrap();
+        let ring = tracer.ring_buffer().unwrap();
+        for _ in 0..4 {
+            tracer.log(&cpu).unwrap();
+            cpu.execute_instruction();
+        }
+        assert_eq!(tracer.lines(), vec!["0602", "0603"]);
+        // The handle outlives the tracer.
+        drop(tracer);
+        assert_eq!(ring.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,122 @@
+//! Watchpoints: stop when the CPU reads or writes an address range.
+//!
+//! The bus checks every CPU access against its watchpoint list and records hits, which the
+//! debugger turns into a break once the instruction that made the access has finished.
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl AccessKind {
+    fn includes(&self, access: AccessKind) -> bool {
+        *self == AccessKind::ReadWrite || *self == access
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: AccessKind,
+}
+
+impl Watchpoint {
+    pub fn new(start: u16, end: u16, kind: AccessKind) -> Self {
+        Watchpoint {
+            start: start.min(end),
+            end: start.max(end),
+            kind,
+        }
+    }
+
+    // Whether an access of `kind` to `addr` triggers this watchpoint. Mirrors count: watching
+    // $0012 also catches $0812, and watching $2002 also catches $200A.
+    pub fn matches(&self, addr: u16, kind: AccessKind) -> bool {
+        self.kind.includes(kind) && (self.contains(addr) || self.contains(canonical_addr(addr)))
+    }
+
+    fn contains(&self, addr: u16) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+}
+
+// Parses "0012", "$0300-$03FF" or "6000:w". The suffix is r, w or rw (the default).
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, kind) = match s.rsplit_once(':') {
+            Some((range, "r")) => (range, AccessKind::Read),
+            Some((range, "w")) => (range, AccessKind::Write),
+            Some((range, "rw")) => (range, AccessKind::ReadWrite),
+            Some((_, kind)) => return Err(format!("Unknown access kind {}, expected r, w or rw", kind)),
+            None => (s, AccessKind::ReadWrite),
+        };
+        let (start, end) = parse_range(range)?;
+        Ok(Watchpoint::new(start, end, kind))
+    }
+}
+
+// Parses a hex address ("0300", "$0300" or "0x0300") or range ("$0300-$03FF") into its first and
+// last address.
+pub fn parse_range(s: &str) -> Result<(u16, u16), String> {
+    let parse = |addr: &str| {
+        let digits = addr.trim().trim_start_matches('$').trim_start_matches("0x");
+        u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address {}", addr))
+    };
+    match s.split_once('-') {
+        Some((start, end)) => Ok((parse(start)?, parse(end)?)),
+        None => Ok((parse(s)?, parse(s)?)),
+    }
+}
+
+// A watched access. For reads `old` and `new` are both the value read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub kind: AccessKind,
+    pub old: u8,
+    pub new: u8,
+    // Address of the instruction that made the access. Filled in by the debugger.
+    pub pc: u16,
+}
+
+// Folds mirrored addresses onto the address they mirror: work RAM onto $0000-$07FF and the PPU
+// registers onto $2000-$2007.
+pub fn canonical_addr(addr: u16) -> u16 {
+    match addr {
+        0x0000..=0x1fff => addr & 0x07ff,
+        0x2000..=0x3fff => addr & 0x2007,
+        _ => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_watchpoint() {
+        assert_eq!("0012".parse(), Ok(Watchpoint::new(0x12, 0x12, AccessKind::ReadWrite)));
+        assert_eq!("$0300-$03FF:w".parse(), Ok(Watchpoint::new(0x300, 0x3ff, AccessKind::Write)));
+        assert_eq!("6000:r".parse(), Ok(Watchpoint::new(0x6000, 0x6000, AccessKind::Read)));
+        assert!("6000:x".parse::<Watchpoint>().is_err());
+        assert!("zz".parse::<Watchpoint>().is_err());
+    }
+
+    #[test]
+    fn test_matches_mirrors_and_kinds() {
+        let watch = Watchpoint::new(0x0012, 0x0012, AccessKind::Write);
+        assert!(watch.matches(0x0812, AccessKind::Write));
+        assert!(!watch.matches(0x0812, AccessKind::Read));
+        assert!(!watch.matches(0x0013, AccessKind::Write));
+
+        let status = Watchpoint::new(0x2002, 0x2002, AccessKind::ReadWrite);
+        assert!(status.matches(0x3ffa, AccessKind::Read));
+    }
+}
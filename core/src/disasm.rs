@@ -0,0 +1,316 @@
+//! 6502 disassembler built on the CPU's opcode table.
+//!
+//! Operands use the usual assembler syntax: `#$nn` immediate, `$nn` zero page, `$nnnn` absolute,
+//! `,X`/`,Y` indexed, `($nnnn)` indirect, `($nn,X)`/`($nn),Y` indexed indirect, and `A` for the
+//! accumulator. Branch operands are shown as their target address. Unofficial opcodes are
+//! prefixed with `*` like in nestest logs, and bytes that aren't an opcode disassemble as `.db`.
+//! So do bytes a code/data log has only seen read as data, when one is running.
+//!
+//! Reference: <https://www.nesdev.org/obelisk-6502-guide/addressing.html>
+
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::cpu::addressing::AddressingMode;
+use crate::cpu::opcodes::{OPCODES_MAP, UNOFFICIAL_OPCODES};
+use crate::cpu::operations::Operation;
+use crate::debugger::symbols::SymbolTable;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    // The opcode followed by its operand bytes.
+    pub bytes: Vec<u8>,
+    // None for bytes that aren't a known opcode.
+    pub op: Option<Operation>,
+    pub mode: AddressingMode,
+    pub unofficial: bool,
+    // Formatted operand, e.g. "$0200,X". Empty for implied instructions.
+    pub operand: String,
+}
+
+impl Instruction {
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    // Address of the instruction after this one.
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.len())
+    }
+
+    // The mnemonic as written in the listing: "LDA", "*NOP" or ".db".
+    pub fn mnemonic(&self) -> String {
+        match self.op {
+            Some(op) if self.unofficial => format!("*{}", op),
+            Some(op) => op.to_string(),
+            None => ".db".to_string(),
+        }
+    }
+
+    // 16-bit operand, for absolute and indirect modes and JSR.
+    fn word(&self) -> u16 {
+        u16::from_le_bytes([self.bytes[1], self.bytes[2]])
+    }
+
+    // Where a branch, JMP or JSR goes, if known without running the code. Indirect jumps depend
+    // on memory, so they have no static target.
+    pub fn target(&self) -> Option<u16> {
+        match (self.op?, self.mode) {
+            (Operation::JMP, AddressingMode::Absolute) | (Operation::JSR, _) => Some(self.word()),
+            (op, AddressingMode::NoneAddressing) if is_branch(op) => {
+                Some(self.next_addr().wrapping_add(self.bytes[1] as i8 as u16))
+            }
+            _ => None,
+        }
+    }
+
+    // The address in the operand as written, before indexing or indirection: $44 in "LDA
+    // ($44),Y", or the target of a branch or JSR. None for immediate and implied operands.
+    pub fn operand_addr(&self) -> Option<u16> {
+        self.op?;
+        match self.mode {
+            AddressingMode::ZeroPage
+            | AddressingMode::ZeroPage_X
+            | AddressingMode::ZeroPage_Y
+            | AddressingMode::Indirect_X
+            | AddressingMode::Indirect_Y => Some(self.bytes[1] as u16),
+            AddressingMode::Absolute
+            | AddressingMode::Absolute_X
+            | AddressingMode::Absolute_Y
+            | AddressingMode::Indirect => Some(self.word()),
+            AddressingMode::Immediate => None,
+            AddressingMode::NoneAddressing => self.target(),
+        }
+    }
+
+    // The instruction with its operand address replaced by its name, if it has one: "JSR
+    // update_sprites", "LDA oam_buffer,X".
+    pub fn with_symbols(&self, symbols: &SymbolTable) -> String {
+        let Some((addr, name)) = self.operand_addr().and_then(|addr| Some((addr, symbols.name(addr)?))) else {
+            return self.to_string();
+        };
+        let written = match self.mode {
+            AddressingMode::ZeroPage
+            | AddressingMode::ZeroPage_X
+            | AddressingMode::ZeroPage_Y
+            | AddressingMode::Indirect_X
+            | AddressingMode::Indirect_Y => format!("${:02X}", addr),
+            _ => format!("${:04X}", addr),
+        };
+        format!("{} {}", self.mnemonic(), self.operand.replacen(&written, name, 1))
+    }
+}
+
+// "LDA #$01", "*NOP $44" or ".db $02".
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.operand.is_empty() {
+            write!(f, "{}", self.mnemonic())
+        } else {
+            write!(f, "{} {}", self.mnemonic(), self.operand)
+        }
+    }
+}
+
+pub fn is_branch(op: Operation) -> bool {
+    matches!(
+        op,
+        Operation::BCC
+            | Operation::BCS
+            | Operation::BEQ
+            | Operation::BMI
+            | Operation::BNE
+            | Operation::BPL
+            | Operation::BVC
+            | Operation::BVS
+    )
+}
+
+// A single byte listed as data: ".db $nn".
+fn data_byte(addr: u16, byte: u8) -> Instruction {
+    Instruction {
+        addr,
+        bytes: vec![byte],
+        op: None,
+        mode: AddressingMode::NoneAddressing,
+        unofficial: false,
+        operand: format!("${:02X}", byte),
+    }
+}
+
+// Decodes the instruction at `addr`, reading memory through `read`.
+pub fn decode<F: Fn(u16) -> u8>(read: F, addr: u16) -> Instruction {
+    let code = read(addr);
+    let opcode = match OPCODES_MAP.get(&code) {
+        Some(opcode) => opcode,
+        None => return data_byte(addr, code),
+    };
+
+    let bytes: Vec<u8> = (0..opcode.bytes as u16).map(|i| read(addr.wrapping_add(i))).collect();
+    let mut instruction = Instruction {
+        addr,
+        bytes,
+        op: Some(opcode.op),
+        mode: opcode.addressing_mode,
+        unofficial: UNOFFICIAL_OPCODES.contains(&code),
+        operand: String::new(),
+    };
+
+    instruction.operand = match (opcode.addressing_mode, opcode.bytes) {
+        (AddressingMode::Immediate, _) => format!("#${:02X}", instruction.bytes[1]),
+        (AddressingMode::ZeroPage, _) => format!("${:02X}", instruction.bytes[1]),
+        (AddressingMode::ZeroPage_X, _) => format!("${:02X},X", instruction.bytes[1]),
+        (AddressingMode::ZeroPage_Y, _) => format!("${:02X},Y", instruction.bytes[1]),
+        (AddressingMode::Absolute, _) => format!("${:04X}", instruction.word()),
+        (AddressingMode::Absolute_X, _) => format!("${:04X},X", instruction.word()),
+        (AddressingMode::Absolute_Y, _) => format!("${:04X},Y", instruction.word()),
+        (AddressingMode::Indirect, _) => format!("(${:04X})", instruction.word()),
+        (AddressingMode::Indirect_X, _) => format!("(${:02X},X)", instruction.bytes[1]),
+        (AddressingMode::Indirect_Y, _) => format!("(${:02X}),Y", instruction.bytes[1]),
+        // Implied: shifts and rotates act on the accumulator.
+        (AddressingMode::NoneAddressing, 1) => match code {
+            0x0a | 0x4a | 0x2a | 0x6a => "A".to_string(),
+            _ => String::new(),
+        },
+        // Branches and JSR, which the opcode table lists without an addressing mode.
+        (AddressingMode::NoneAddressing, _) => match instruction.target() {
+            Some(target) => format!("${:04X}", target),
+            None => String::new(),
+        },
+    };
+    instruction
+}
+
+// Decodes `count` consecutive instructions starting at `addr`.
+pub fn disassemble_with<F: Fn(u16) -> u8>(read: F, addr: u16, count: usize) -> Vec<Instruction> {
+    disassemble_logged(read, |_| false, addr, count)
+}
+
+// Like `disassemble_with`, but bytes `is_data` picks out are listed as `.db` rather than decoded.
+pub fn disassemble_logged<F, D>(read: F, is_data: D, addr: u16, count: usize) -> Vec<Instruction>
+where
+    F: Fn(u16) -> u8,
+    D: Fn(u16) -> bool,
+{
+    let mut instructions = Vec::with_capacity(count);
+    let mut addr = addr;
+    for _ in 0..count {
+        let instruction = if is_data(addr) { data_byte(addr, read(addr)) } else { decode(&read, addr) };
+        addr = instruction.next_addr();
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+// Disassembles `count` instructions from CPU memory without disturbing the emulation. With a
+// code/data log running, bytes it has only seen read as data are listed as `.db`.
+pub fn disassemble(bus: &Bus, addr: u16, count: usize) -> Vec<Instruction> {
+    disassemble_logged(|addr| bus.peek(addr), |addr| bus.is_logged_data(addr), addr, count)
+}
+
+// Disassembles a whole buffer, as if it were loaded at `origin`. A trailing partial instruction
+// reads past the end as zeros.
+pub fn disassemble_bytes(bytes: &[u8], origin: u16) -> Vec<Instruction> {
+    let read = |addr: u16| bytes.get(addr.wrapping_sub(origin) as usize).copied().unwrap_or(0);
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let instruction = decode(read, origin.wrapping_add(offset as u16));
+        offset += instruction.bytes.len();
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+// One listing line: "C000  4C F5 C5  JMP $C5F5".
+pub fn format_line(instruction: &Instruction) -> String {
+    let hex: Vec<String> = instruction.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!("{:04X}  {:8}  {}", instruction.addr, hex.join(" "), instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8]) -> Vec<String> {
+        disassemble_bytes(bytes, 0x8000).iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn test_addressing_mode_syntax() {
+        assert_eq!(
+            text(&[
+                0xa9, 0x01, // LDA #$01
+                0xa5, 0x44, // LDA $44
+                0xb5, 0x44, // LDA $44,X
+                0xb6, 0x44, // LDX $44,Y
+                0xad, 0x00, 0x02, // LDA $0200
+                0xbd, 0x00, 0x02, // LDA $0200,X
+                0xb9, 0x00, 0x02, // LDA $0200,Y
+                0x6c, 0xfc, 0xff, // JMP ($FFFC)
+                0xa1, 0x44, // LDA ($44,X)
+                0xb1, 0x44, // LDA ($44),Y
+                0x0a, // ASL A
+                0xe8, // INX
+            ]),
+            vec![
+                "LDA #$01", "LDA $44", "LDA $44,X", "LDX $44,Y", "LDA $0200", "LDA $0200,X",
+                "LDA $0200,Y", "JMP ($FFFC)", "LDA ($44,X)", "LDA ($44),Y", "ASL A", "INX",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_branch_and_jump_targets() {
+        let instructions = disassemble_bytes(&[0xd0, 0xfe, 0x10, 0x02, 0x20, 0x34, 0x12, 0x4c, 0x00, 0x80], 0x8000);
+        assert_eq!(instructions[0].to_string(), "BNE $8000");
+        assert_eq!(instructions[0].target(), Some(0x8000));
+        assert_eq!(instructions[1].to_string(), "BPL $8006");
+        assert_eq!(instructions[2].to_string(), "JSR $1234");
+        assert_eq!(instructions[2].next_addr(), 0x8007);
+        assert_eq!(instructions[3].target(), Some(0x8000));
+    }
+
+    #[test]
+    fn test_unofficial_and_unknown_opcodes() {
+        let instructions = disassemble_bytes(&[0x04, 0x10, 0xa7, 0x20, 0x02], 0x8000);
+        assert_eq!(instructions[0].to_string(), "*NOP $10");
+        assert_eq!(instructions[1].to_string(), "*LAX $20");
+        assert_eq!(instructions[2].to_string(), ".db $02");
+        assert_eq!(instructions[2].len(), 1);
+    }
+
+    #[test]
+    fn test_logged_data_is_not_decoded() {
+        let bytes = [0xa9, 0x01, 0xa9, 0x02];
+        let read = |addr: u16| bytes[(addr - 0x8000) as usize];
+        let instructions = disassemble_logged(read, |addr| addr == 0x8002, 0x8000, 3);
+        let text: Vec<String> = instructions.iter().map(|i| i.to_string()).collect();
+        assert_eq!(text, vec!["LDA #$01", ".db $A9", ".db $02"]);
+    }
+
+    #[test]
+    fn test_symbols_replace_operand_addresses() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0xc4f2, "update_sprites");
+        symbols.insert(0x0200, "oam_buffer");
+        symbols.insert(0x0044, "pointer");
+        let instructions = disassemble_bytes(
+            &[0x20, 0xf2, 0xc4, 0xbd, 0x00, 0x02, 0xb1, 0x44, 0xa9, 0x44, 0xe8],
+            0x8000,
+        );
+        let text: Vec<String> = instructions.iter().map(|i| i.with_symbols(&symbols)).collect();
+        assert_eq!(text, vec!["JSR update_sprites", "LDA oam_buffer,X", "LDA (pointer),Y", "LDA #$44", "INX"]);
+    }
+
+    #[test]
+    fn test_format_line() {
+        let instruction = &disassemble_bytes(&[0x4c, 0xf5, 0xc5], 0xc000)[0];
+        assert_eq!(format_line(instruction), "C000  4C F5 C5  JMP $C5F5");
+    }
+}
@@ -0,0 +1,556 @@
+//! The whole console: owns the CPU (and through it the bus, PPU and joypads) together with the
+//! rendered frame, and runs the machine one video frame at a time.
+
+use std::path::Path;
+
+use crate::accuracy::AccuracyProfile;
+use crate::bus::Bus;
+use crate::cartridge::Cartridge;
+use crate::cheat::Cheats;
+use crate::cpu::CPU;
+use crate::fds::{self, disk, Fds};
+use crate::host::{AudioSink, InputProvider, VideoSink, SAMPLES_PER_FRAME};
+use crate::joypad::family_keyboard::FamilyKeyboard;
+use crate::joypad::four_score::FourScore;
+use crate::joypad::{JoypadButton, Port2Device};
+use crate::movie::{FrameInput, Movie, MovieState};
+use crate::ppu::model::PpuModel;
+use crate::render::blend::FrameBlender;
+use crate::render::constants::{NES_PIXEL_HEIGHT, NES_PIXEL_WIDTH};
+use crate::render::frame::Frame;
+use crate::render::Layers;
+use crate::render::screenshot::write_png;
+use crate::savestate::{self, StateFile, StateWriter, BUS_SECTION, CHIPS_SECTION, CPU_SECTION, EMULATOR_SECTION,
+    FCG_SECTION, FDS_SECTION, KEYBOARD_SECTION, POWER_PAD_SECTION, PPU_SECTION, VS_SYSTEM_SECTION};
+
+// Frames per second of an NTSC console: the 21.477272 MHz master clock (39375000 / 655171 Hz) over
+// 357366 master cycles per frame.
+// Reference: https://www.nesdev.org/wiki/Cycle_reference_chart
+pub const NTSC_FRAME_RATE: f64 = 60.0988;
+
+pub struct Emulator {
+    pub cpu: CPU,
+    // The most recently completed frame.
+    pub frame: Frame,
+    // Its sound: `SAMPLES_PER_FRAME` mono samples at `host::SAMPLE_RATE`. There is no APU yet, so
+    // only a disk system game's expansion channel is heard; everything else is silent.
+    pub sound: Vec<i16>,
+    // Blends each frame with the previous one to hide sprite flicker. Off by default.
+    pub blender: FrameBlender,
+    // While set, frames run but aren't drawn, and `frame` keeps the last picture that was. The
+    // picture is drawn from the PPU's state once the frame is over, so timing, sprite 0 hits and
+    // everything else the game sees are the same either way. Frontends set it to skip frames
+    // they won't show.
+    pub skip_render: bool,
+    cartridge: Cartridge,
+    movie: MovieState,
+    // Controller reads during the last frame, and the number of frames since power-on that never
+    // read the controllers.
+    input_reads: u32,
+    lag_count: u64,
+    // PPU frame count when the frame being run started, or None between frames.
+    frame_start: Option<u64>,
+    // Whether the last `step` began by entering the NMI handler.
+    entered_nmi: bool,
+    // RetroAchievements hardcore mode: no loading states and no cheats. See `set_hardcore`.
+    hardcore: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    // The instruction ran and the frame isn't finished yet.
+    Running,
+    // The instruction ran and finished the frame, which has been rendered.
+    FrameComplete,
+    // The `before` callback vetoed the instruction, so nothing ran.
+    Interrupted,
+    // The CPU hit BRK or an opcode we don't run, which we treat as a halt. The frame has been rendered as it stands.
+    Halted,
+}
+
+// Logged whenever a game is loaded. `Emulator::new` doesn't, since load_state and rewind build
+// scratch consoles with it; frontends call `Emulator::log_cartridge` for the first game instead.
+fn log_cartridge(cartridge: &Cartridge) {
+    tracing::info!(
+        target: "mapper",
+        "Mapper {} with {} KB PRG and {} KB CHR, {:?} mirroring",
+        cartridge.mapper,
+        cartridge.prg_rom.len() / 1024,
+        cartridge.chr_rom.len() / 1024,
+        cartridge.screen_mirroring
+    );
+}
+
+impl Emulator {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let mut cpu = CPU::new(Bus::new(cartridge.clone()));
+        cpu.reset();
+
+        Emulator {
+            cpu,
+            frame: Frame::new(),
+            sound: vec![0; SAMPLES_PER_FRAME],
+            blender: FrameBlender::new(),
+            skip_render: false,
+            cartridge,
+            movie: MovieState::Inactive,
+            input_reads: 0,
+            lag_count: 0,
+            frame_start: None,
+            entered_nmi: false,
+            hardcore: false,
+        }
+    }
+
+    // Power cycles the console. Whatever is plugged into the controller and expansion ports stays
+    // plugged in, the accuracy profile, overclocking and hidden layers stay, a code/data log keeps
+    // going and cheats stay on. A disk keeps what the game saved to it, as do the board's EEPROMs,
+    // and a VS System its DIP switch settings.
+    pub fn power_on(&mut self) {
+        let disk = self.cpu.bus.fds.take().map(|fds| fds.disk().to_vec());
+        let dip_switches = self.cpu.bus.vs_system.as_ref().map(|vs_system| vs_system.dip_switches);
+        let port2 = std::mem::replace(&mut self.cpu.bus.port2, Port2Device::Joypad);
+        let four_score = self.cpu.bus.four_score.is_some();
+        let keyboard = self.cpu.bus.keyboard.is_some();
+        let accuracy = self.cpu.bus.accuracy;
+        let layers = self.cpu.bus.ppu.layers;
+        let extra_scanlines = self.cpu.bus.ppu.extra_scanlines;
+        let cdl = self.cpu.bus.cdl.take();
+        let cheats = std::mem::take(&mut self.cpu.bus.cheats);
+        let chips: Vec<Vec<u8>> = self.cpu.bus.chips.iter().map(|chip| chip.persistent()).collect();
+
+        self.cpu = CPU::new(Bus::new(self.cartridge.clone()));
+        self.cpu.bus.port2 = port2;
+        self.cpu.bus.accuracy = accuracy;
+        self.cpu.bus.ppu.layers = layers;
+        self.cpu.bus.ppu.extra_scanlines = extra_scanlines;
+        self.cpu.bus.cdl = cdl;
+        self.cpu.bus.cheats = cheats;
+        for (chip, data) in self.cpu.bus.chips.iter_mut().zip(chips) {
+            chip.restore(&data).expect("a chip takes back its own contents");
+        }
+        if let Some(disk) = disk {
+            self.cpu.bus.fds = Some(Fds::new(disk));
+        }
+        if let (Some(vs_system), Some(dip_switches)) = (&mut self.cpu.bus.vs_system, dip_switches) {
+            vs_system.dip_switches = dip_switches;
+        }
+        self.set_four_score(four_score);
+        self.set_keyboard(keyboard);
+        self.cpu.reset();
+        self.frame = Frame::new();
+        self.blender.clear();
+        self.input_reads = 0;
+        self.lag_count = 0;
+        self.frame_start = None;
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    // Logs the mapper, ROM sizes and mirroring of the cartridge that's in, as `load_cartridge` does.
+    pub fn log_cartridge(&self) {
+        log_cartridge(&self.cartridge);
+    }
+
+    // Swaps in a new cartridge and power cycles the console, as if the old one had been pulled
+    // and the new one inserted. Any movie and code/data log are stopped; call `stop_movie` and take
+    // `cpu.bus.cdl` first to keep them.
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.movie = MovieState::Inactive;
+        self.cpu.bus.cdl = None;
+        // Cheats are for the old game.
+        self.cpu.bus.cheats = Cheats::new();
+        log_cartridge(&cartridge);
+        self.cartridge = cartridge;
+        self.power_on();
+    }
+
+    // Loads the iNES file at `path` with `load_cartridge`, or the .fds disk image there with the
+    // BIOS `fds::load_bios` finds. On error the current game keeps running.
+    pub fn open_rom(&mut self, path: &Path) -> Result<(), String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let cartridge = match disk::is_disk_image(&bytes) {
+            true => Cartridge::fds(&bytes, &fds::load_bios(path)?)?,
+            false => Cartridge::new(&bytes)?,
+        };
+        self.load_cartridge(cartridge);
+        Ok(())
+    }
+
+    // Swaps the PPU for `model`, which changes the palette and, for the RC2C05s, the registers.
+    // VS System and PlayChoice-10 games need the one their board had, which iNES headers don't
+    // record. It stays in when the console is power cycled, until another cartridge is loaded.
+    pub fn set_ppu_model(&mut self, model: PpuModel) {
+        self.cartridge.ppu_model = model;
+        self.cpu.bus.ppu.model = model;
+        Frame::render(&self.cpu.bus.ppu, &mut self.frame);
+    }
+
+    // Shows or hides the background and sprites in the picture, redrawn straight away so a paused
+    // game shows the change. The layers stay across power cycles and cartridge changes.
+    pub fn set_layers(&mut self, layers: Layers) {
+        self.cpu.bus.ppu.layers = layers;
+        Frame::render(&self.cpu.bus.ppu, &mut self.frame);
+    }
+
+    pub fn layers(&self) -> Layers {
+        self.cpu.bus.ppu.layers
+    }
+
+    // Overclocks the CPU by `scanlines` scanlines a frame, run after vblank while the PPU stands
+    // still, so games that slow down get more time each frame without the frame getting any
+    // shorter. 0 turns it off. It stays across power cycles and cartridge changes.
+    pub fn set_overclock(&mut self, scanlines: u16) {
+        self.cpu.bus.ppu.extra_scanlines = scanlines;
+    }
+
+    pub fn overclock(&self) -> u16 {
+        self.cpu.bus.ppu.extra_scanlines
+    }
+
+    // Turns hardcore mode on or off. While it is on, `load_state` (and so rewind) refuses and
+    // cheats are dropped at the start of every frame, so achievements can only be earned by
+    // playing. Usually set by `achievements::Achievements`.
+    pub fn set_hardcore(&mut self, hardcore: bool) {
+        self.hardcore = hardcore;
+    }
+
+    pub fn is_hardcore(&self) -> bool {
+        self.hardcore
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.cpu.bus.ppu.frame_count
+    }
+
+    // Writes the last frame, as the PPU produced it (after frame blending, if enabled), to `path`
+    // as a 256x240 PNG.
+    pub fn screenshot(&self, path: &Path) -> Result<(), String> {
+        write_png(path, NES_PIXEL_WIDTH as u32, NES_PIXEL_HEIGHT as u32, &self.frame.to_rgba8())
+    }
+
+    // True if the game did not poll the controllers during the last frame, so input held on that
+    // frame was never seen.
+    pub fn is_lag_frame(&self) -> bool {
+        self.input_reads == 0
+    }
+
+    // Number of $4016/$4017 reads during the last frame. A game reading one controller normally
+    // makes 8 reads per poll.
+    pub fn input_reads(&self) -> u32 {
+        self.input_reads
+    }
+
+    // Number of lag frames since power-on.
+    pub fn lag_count(&self) -> u64 {
+        self.lag_count
+    }
+
+    // Switches the timing details `profile` emulates, from the next instruction on. The profile
+    // stays across power cycles and cartridge changes.
+    pub fn set_accuracy(&mut self, profile: AccuracyProfile) {
+        self.cpu.bus.accuracy = profile.settings();
+    }
+
+    // Plugs the Four Score adapter in or out. Controllers 3 and 4 are only readable while it is in.
+    pub fn set_four_score(&mut self, enabled: bool) {
+        if enabled != self.cpu.bus.four_score.is_some() {
+            self.cpu.bus.four_score = enabled.then(FourScore::new);
+        }
+    }
+
+    // Plugs the Family BASIC keyboard in or out. The keys held are set on `cpu.bus.keyboard`.
+    pub fn set_keyboard(&mut self, enabled: bool) {
+        if enabled != self.cpu.bus.keyboard.is_some() {
+            self.cpu.bus.keyboard = enabled.then(FamilyKeyboard::new);
+        }
+    }
+
+    // Sets the buttons held on controller `port` (0 to 3) for the next frame. Ignored while a
+    // movie is playing, and for controllers 3 and 4 when no Four Score is plugged in.
+    pub fn set_buttons(&mut self, port: usize, buttons: JoypadButton) {
+        match port {
+            0 => self.cpu.bus.joypad.button_status = buttons,
+            1 => self.cpu.bus.joypad2.button_status = buttons,
+            2 | 3 => {
+                if let Some(four_score) = &mut self.cpu.bus.four_score {
+                    four_score.button_status[port - 2] = buttons;
+                }
+            }
+            _ => panic!("Invalid controller port {}", port),
+        }
+    }
+
+    fn buttons(&self) -> FrameInput {
+        let bus = &self.cpu.bus;
+        let extra = bus.four_score.map(|four_score| four_score.button_status).unwrap_or_default();
+        [bus.joypad.button_status, bus.joypad2.button_status, extra[0], extra[1]]
+    }
+
+    pub fn run_frame(&mut self) {
+        self.run_frame_with_callback(|_| {});
+    }
+
+    // Runs one frame for a frontend: takes the buttons from `input`, then hands the picture to
+    // `video` and the sound to `audio`.
+    pub fn run_frame_with_host(
+        &mut self,
+        input: &mut impl InputProvider,
+        video: &mut impl VideoSink,
+        audio: &mut impl AudioSink,
+    ) {
+        for (port, buttons) in input.buttons().into_iter().enumerate() {
+            self.set_buttons(port, buttons);
+        }
+        self.run_frame();
+        video.frame(&self.frame);
+        audio.samples(&self.sound);
+    }
+
+    // Runs the CPU until the PPU finishes the current frame, then renders it into `self.frame`.
+    // The callback is called before every instruction.
+    pub fn run_frame_with_callback<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&mut CPU),
+    {
+        let _frame = tracing::info_span!(target: "frame", "frame", number = self.frame_count()).entered();
+        while self.step(|cpu| {
+            callback(cpu);
+            true
+        }) == StepResult::Running
+        {}
+    }
+
+    // Runs a single instruction, servicing a pending NMI first. `before` sees the CPU about to
+    // execute it and can veto it by returning false, which is how the debugger stops on
+    // breakpoints. The frame is rendered when the instruction completes it.
+    pub fn step<F>(&mut self, before: F) -> StepResult
+    where
+        F: FnOnce(&mut CPU) -> bool,
+    {
+        if self.frame_start.is_none() {
+            if self.hardcore && !self.cpu.bus.cheats.list().is_empty() {
+                self.cpu.bus.cheats = Cheats::new();
+            }
+            self.apply_movie();
+            self.cpu.bus.input_reads = 0;
+            self.frame_start = Some(self.cpu.bus.ppu.frame_count);
+        }
+
+        self.entered_nmi = self.cpu.service_interrupts();
+        if !before(&mut self.cpu) {
+            return StepResult::Interrupted;
+        }
+
+        let running = self.cpu.execute_instruction();
+        if !running {
+            tracing::info!(target: "cpu", "Halted at ${:04x}", self.cpu.program_counter.wrapping_sub(1));
+            self.end_frame();
+            StepResult::Halted
+        } else if Some(self.cpu.bus.ppu.frame_count) != self.frame_start {
+            self.end_frame();
+            StepResult::FrameComplete
+        } else {
+            StepResult::Running
+        }
+    }
+
+    // Whether the last `step` jumped into the NMI handler before running its instruction (or
+    // before being vetoed).
+    pub fn entered_nmi(&self) -> bool {
+        self.entered_nmi
+    }
+
+    fn end_frame(&mut self) {
+        self.frame_start = None;
+        self.input_reads = self.cpu.bus.input_reads;
+        if self.is_lag_frame() {
+            self.lag_count += 1;
+        }
+        self.cpu.bus.apply_freezes();
+        if let Some(vs_system) = &mut self.cpu.bus.vs_system {
+            vs_system.end_frame();
+        }
+
+        if let Some(cdl) = &mut self.cpu.bus.cdl {
+            cdl.log_rendered_tiles(&self.cpu.bus.ppu);
+        }
+        // A frame's cycles only roughly make `SAMPLES_PER_FRAME` samples, so the last is repeated
+        // or dropped to fit.
+        self.sound.clear();
+        self.sound.append(&mut self.cpu.bus.samples);
+        let last = self.sound.last().copied().unwrap_or(0);
+        self.sound.resize(SAMPLES_PER_FRAME, last);
+        if !self.skip_render {
+            Frame::render(&self.cpu.bus.ppu, &mut self.frame);
+            self.blender.apply(&mut self.frame);
+        }
+    }
+
+    // Feeds or records the joypad state for the frame about to run.
+    fn apply_movie(&mut self) {
+        let buttons = self.buttons();
+        match &mut self.movie {
+            MovieState::Inactive => {}
+            MovieState::Recording(movie) => movie.inputs.push(buttons),
+            MovieState::Playing { movie, frame } => match movie.inputs.get(*frame).copied() {
+                Some(input) => {
+                    *frame += 1;
+                    for (port, buttons) in input.into_iter().enumerate() {
+                        self.set_buttons(port, buttons);
+                    }
+                }
+                // Input goes back to the player once the movie runs out.
+                None => self.movie = MovieState::Inactive,
+            },
+        }
+    }
+
+    // Power cycles the console and records input from the first frame on.
+    pub fn record_movie(&mut self) {
+        self.power_on();
+        let mut movie = Movie::new(self.cartridge.hash());
+        movie.four_score = self.cpu.bus.four_score.is_some();
+        self.movie = MovieState::Recording(movie);
+    }
+
+    // Power cycles the console and replays `movie` from the first frame on. The Four Score is
+    // plugged in or out to match the recording.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), String> {
+        if movie.rom_hash != self.cartridge.hash() {
+            return Err("Movie was recorded on a different ROM".to_string());
+        }
+        self.set_four_score(movie.four_score);
+        self.power_on();
+        self.movie = MovieState::Playing { movie, frame: 0 };
+        Ok(())
+    }
+
+    // Stops recording or playback and returns the movie, if any.
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        match std::mem::take(&mut self.movie) {
+            MovieState::Inactive => None,
+            MovieState::Recording(movie) => Some(movie),
+            MovieState::Playing { movie, .. } => Some(movie),
+        }
+    }
+
+    pub fn movie_state(&self) -> &MovieState {
+        &self.movie
+    }
+
+    // Snapshots the whole console. See `savestate` for the format.
+    pub fn save_state(&self) -> Vec<u8> {
+        savestate::save(self)
+    }
+
+    // Like `save_state`, but into `buffer`, which doesn't allocate once it has held a state.
+    pub fn save_state_into(&self, buffer: &mut Vec<u8>) {
+        savestate::save_into(self, buffer)
+    }
+
+    // Restores a snapshot from `save_state`, made on the same ROM. Movies aren't part of the state:
+    // one being recorded or played carries on where it was.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if self.hardcore {
+            return Err("Loading states is off in hardcore mode".to_string());
+        }
+        let rom_hash = self.cartridge.hash();
+        // Try the state on a scratch console first, so a bad one leaves the game alone.
+        Emulator::new(self.cartridge.clone()).load_sections(&mut savestate::open(bytes, rom_hash)?)?;
+        self.load_sections(&mut savestate::open(bytes, rom_hash)?)?;
+
+        Frame::render(&self.cpu.bus.ppu, &mut self.frame);
+        self.blender.clear();
+        Ok(())
+    }
+
+    // Restores a state this emulator just saved, quickly: without trying it on a scratch console
+    // first, and leaving the picture alone. For rolling back, as run-ahead does. A bad state can
+    // leave the console half restored.
+    pub fn restore_state(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.load_sections(&mut savestate::open(bytes, self.cartridge.hash())?)
+    }
+
+    pub(crate) fn save_sections(&self, state: &mut StateWriter) {
+        state.section(CPU_SECTION, |state| self.cpu.save_state(state));
+        state.section(BUS_SECTION, |state| self.cpu.bus.save_state(state));
+        state.section(PPU_SECTION, |state| self.cpu.bus.ppu.save_state(state));
+        state.section(EMULATOR_SECTION, |state| {
+            state.u32(self.input_reads);
+            state.u64(self.lag_count);
+            state.bool(self.frame_start.is_some());
+            state.u64(self.frame_start.unwrap_or_default());
+            state.bool(self.entered_nmi);
+        });
+        if let Some(fds) = &self.cpu.bus.fds {
+            state.section(FDS_SECTION, |state| fds.save_state(state));
+        }
+        if let Some(vs_system) = &self.cpu.bus.vs_system {
+            state.section(VS_SYSTEM_SECTION, |state| vs_system.save_state(state));
+        }
+        if let Some(fcg) = &self.cpu.bus.fcg {
+            state.section(FCG_SECTION, |state| fcg.save_state(state));
+        }
+        if let Some(keyboard) = &self.cpu.bus.keyboard {
+            state.section(KEYBOARD_SECTION, |state| keyboard.save_state(state));
+        }
+        if let Port2Device::PowerPad(power_pad) = &self.cpu.bus.port2 {
+            state.section(POWER_PAD_SECTION, |state| power_pad.save_state(state));
+        }
+        if !self.cpu.bus.chips.is_empty() {
+            state.section(CHIPS_SECTION, |state| self.cpu.bus.chips.iter().for_each(|chip| chip.save_state(state)));
+        }
+    }
+
+    fn load_sections(&mut self, file: &mut StateFile) -> Result<(), String> {
+        self.cpu.bus.samples.clear();
+        self.cpu.load_state(file.required(CPU_SECTION)?)?;
+        self.cpu.bus.load_state(file.required(BUS_SECTION)?)?;
+        self.cpu.bus.ppu.load_state(file.required(PPU_SECTION)?)?;
+        let state = file.required(EMULATOR_SECTION)?;
+        self.input_reads = state.u32()?;
+        self.lag_count = state.u64()?;
+        let started = state.bool()?;
+        let frame_start = state.u64()?;
+        self.frame_start = started.then_some(frame_start);
+        self.entered_nmi = state.bool()?;
+        if let Some(fds) = &mut self.cpu.bus.fds {
+            if let Some(state) = file.section(FDS_SECTION)? {
+                fds.load_state(state)?;
+            }
+            self.cpu.bus.ppu.mirroring = fds.mirroring();
+        }
+        if let Some(vs_system) = &mut self.cpu.bus.vs_system {
+            if let Some(state) = file.section(VS_SYSTEM_SECTION)? {
+                vs_system.load_state(state, &mut self.cpu.bus.ppu)?;
+            }
+        }
+        if let Some(fcg) = &mut self.cpu.bus.fcg {
+            if let Some(state) = file.section(FCG_SECTION)? {
+                fcg.load_state(state, &mut self.cpu.bus.ppu)?;
+                // The restored PRG bank may not be the one cached code came from.
+                #[cfg(feature = "experimental-jit")]
+                self.cpu.bus.code_writes.written_everywhere();
+            }
+        }
+        if let Some(keyboard) = &mut self.cpu.bus.keyboard {
+            if let Some(state) = file.section(KEYBOARD_SECTION)? {
+                keyboard.load_state(state)?;
+            }
+        }
+        if let Port2Device::PowerPad(power_pad) = &mut self.cpu.bus.port2 {
+            if let Some(state) = file.section(POWER_PAD_SECTION)? {
+                power_pad.load_state(state)?;
+            }
+        }
+        if let Some(state) = file.section(CHIPS_SECTION)? {
+            for chip in &mut self.cpu.bus.chips {
+                chip.load_state(state)?;
+            }
+        }
+        file.finish()
+    }
+}
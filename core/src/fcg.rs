@@ -0,0 +1,288 @@
+//! Bandai's FCG boards with the LZ93D50 mapper and a serial EEPROM to save to: mapper 16 with a
+//! 24C02 (Dragon Ball Z games, SD Gundam Gaiden) and mapper 159 with a 24C01. Registers sit at
+//! $8000-$FFFF, mirrored every 16 bytes:
+//!
+//! | $x0-$x7 | 1kB CHR bank at PPU $0000, $0400, ... $1C00 |
+//! | $x8     | 16kB PRG bank at $8000; $C000 always has the last |
+//! | $x9     | mirroring: 0 vertical, 1 horizontal, 2 and 3 single screen lower and upper |
+//! | $xA     | bit 0 enables the IRQ counter; any write loads it from the latch and acknowledges |
+//! | $xB/$xC | IRQ latch, low and high byte |
+//! | $xD     | EEPROM lines: bit 5 SCL, bit 6 SDA, bit 7 lets go of SDA for the EEPROM to answer |
+//!
+//! Reads of $6000-$7FFF have SDA in bit 4. The counter counts CPU cycles down, holding IRQ from
+//! the cycle it is 0 at until acknowledged. The older FCG-1 and FCG-2 boards, which have their
+//! registers at $6000-$7FFF and no EEPROM, aren't emulated.
+//!
+//! Reference: https://www.nesdev.org/wiki/Bandai_FCG_board
+
+use crate::cartridge::Mirroring;
+use crate::chips::Chip;
+use crate::ppu::PPU;
+use crate::savestate::{StateReader, StateWriter};
+
+pub const MAPPER_24C02: u8 = 16;
+pub const MAPPER_24C01: u8 = 159;
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const SCL: u8 = 0x20;
+const SDA: u8 = 0x40;
+const SDA_RELEASED: u8 = 0x80;
+
+pub struct Fcg {
+    // All of CHR-ROM; the selected banks are copied into the PPU. Empty with CHR-RAM, which isn't
+    // banked.
+    chr: Vec<u8>,
+    chr_banks: [u8; 8],
+    prg_bank: u8,
+    mirroring: u8,
+    irq_enabled: bool,
+    irq_latch: u16,
+    irq_counter: u16,
+    irq: bool,
+    // The last $xD write.
+    eeprom_lines: u8,
+}
+
+impl Fcg {
+    // Starts with CHR as the cartridge has it, banks 0 to 7, and the header's mirroring.
+    pub fn new(chr: Vec<u8>, mirroring: &Mirroring) -> Self {
+        Fcg {
+            chr,
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            prg_bank: 0,
+            mirroring: (*mirroring == Mirroring::Horizontal) as u8,
+            irq_enabled: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq: false,
+            eeprom_lines: SDA_RELEASED,
+        }
+    }
+
+    // Index into PRG-ROM, `len` bytes of it, of CPU address `addr` in $8000-$FFFF.
+    pub fn prg_offset(&self, addr: u16, len: usize) -> usize {
+        let banks = (len / PRG_BANK_SIZE).max(1);
+        let bank = match addr {
+            0x8000..=0xbfff => self.prg_bank as usize % banks,
+            _ => banks - 1,
+        };
+        (bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))) % len
+    }
+
+    // The 16kB bank at $8000-$BFFF.
+    pub fn prg_bank(&self) -> u8 {
+        self.prg_bank
+    }
+
+    // A read of $6000-$7FFF, which only has the EEPROM's SDA.
+    pub fn read(&self, chips: &[Box<dyn Chip>]) -> u8 {
+        let sda = self.eeprom_lines & (SDA | SDA_RELEASED) != 0 && chips.iter().all(|chip| chip.sda());
+        (sda as u8) << 4
+    }
+
+    // A write to a register at $8000-$FFFF.
+    pub fn write(&mut self, addr: u16, value: u8, ppu: &mut PPU, chips: &mut [Box<dyn Chip>]) {
+        match addr & 0x0f {
+            bank @ 0x0..=0x7 => {
+                self.chr_banks[bank as usize] = value;
+                self.map_chr(ppu, bank as usize);
+            }
+            0x8 => self.prg_bank = value & 0x0f,
+            0x9 => {
+                self.mirroring = value & 0b11;
+                ppu.mirroring = self.ppu_mirroring();
+            }
+            0xa => {
+                self.irq_enabled = value & 1 != 0;
+                self.irq_counter = self.irq_latch;
+                self.irq = false;
+            }
+            0xb => self.irq_latch = self.irq_latch & 0xff00 | value as u16,
+            0xc => self.irq_latch = self.irq_latch & 0x00ff | (value as u16) << 8,
+            0xd => {
+                self.eeprom_lines = value;
+                let sda = value & (SDA | SDA_RELEASED) != 0;
+                chips.iter_mut().for_each(|chip| chip.set_lines(value & SCL != 0, sda));
+            }
+            _ => tracing::debug!(target: "mapper", "Ignored write of ${:02x} to ${:04x}", value, addr),
+        }
+    }
+
+    pub fn ppu_mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn map_chr(&self, ppu: &mut PPU, bank: usize) {
+        if self.chr.is_empty() {
+            return;
+        }
+        let start = self.chr_banks[bank] as usize * CHR_BANK_SIZE % self.chr.len();
+        let at = bank * CHR_BANK_SIZE;
+        ppu.chr_rom[at..at + CHR_BANK_SIZE].copy_from_slice(&self.chr[start..start + CHR_BANK_SIZE]);
+        ppu.chr_changed();
+    }
+
+    // Counts `cycles` CPU cycles down.
+    pub fn tick(&mut self, cycles: usize) {
+        if !self.irq_enabled {
+            return;
+        }
+        if (self.irq_counter as usize) < cycles {
+            self.irq = true;
+        }
+        self.irq_counter = self.irq_counter.wrapping_sub(cycles as u16);
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.chr_banks);
+        state.u8(self.prg_bank);
+        state.u8(self.mirroring);
+        state.bool(self.irq_enabled);
+        state.u16(self.irq_latch);
+        state.u16(self.irq_counter);
+        state.bool(self.irq);
+        state.u8(self.eeprom_lines);
+    }
+
+    // Also maps the saved CHR banks and mirroring into the PPU.
+    pub fn load_state(&mut self, state: &mut StateReader, ppu: &mut PPU) -> Result<(), String> {
+        state.bytes(&mut self.chr_banks)?;
+        self.prg_bank = state.u8()? & 0x0f;
+        self.mirroring = state.u8()? & 0b11;
+        self.irq_enabled = state.bool()?;
+        self.irq_latch = state.u16()?;
+        self.irq_counter = state.u16()?;
+        self.irq = state.bool()?;
+        self.eeprom_lines = state.u8()?;
+        (0..self.chr_banks.len()).for_each(|bank| self.map_chr(ppu, bank));
+        ppu.mirroring = self.ppu_mirroring();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::Cartridge;
+    use crate::cpu::Mem;
+
+    // A mapper 16 game with four PRG banks and sixteen CHR banks, each filled with its number.
+    fn bus() -> Bus {
+        let mut raw = vec![0x4e, 0x45, 0x53, 0x1a, 4, 2, 0x00, 0x10, 0, 0, 0, 0, 0, 0, 0, 0];
+        raw.extend((0..4 * 0x4000).map(|i| (i / 0x4000) as u8));
+        raw.extend((0..2 * 0x2000).map(|i| (i / 0x400) as u8));
+        Bus::new(Cartridge::new(&raw).unwrap())
+    }
+
+    // Sets SCL and SDA the way a game does, letting go of SDA whenever it is high.
+    fn lines(bus: &mut Bus, scl: bool, sda: bool) {
+        bus.mem_write(0x800d, if scl { SCL } else { 0 } | if sda { SDA | SDA_RELEASED } else { 0 });
+    }
+
+    fn start(bus: &mut Bus) {
+        lines(bus, false, true);
+        lines(bus, true, true);
+        lines(bus, true, false);
+        lines(bus, false, false);
+    }
+
+    fn stop(bus: &mut Bus) {
+        lines(bus, false, false);
+        lines(bus, true, false);
+        lines(bus, true, true);
+    }
+
+    // One clock pulse with SDA at `sda`, returning SDA, through $6000, while the clock is high.
+    fn clock(bus: &mut Bus, sda: bool) -> bool {
+        lines(bus, false, sda);
+        lines(bus, true, sda);
+        let read = bus.mem_read(0x6000) & 0x10 != 0;
+        lines(bus, false, sda);
+        read
+    }
+
+    // Sends a byte, most significant bit first, and returns whether it was acknowledged.
+    fn send(bus: &mut Bus, byte: u8) -> bool {
+        (0..8).rev().for_each(|bit| {
+            clock(bus, byte >> bit & 1 == 1);
+        });
+        !clock(bus, true)
+    }
+
+    #[test]
+    fn test_eeprom_through_the_mapper() {
+        let mut bus = bus();
+        start(&mut bus);
+        assert!(send(&mut bus, 0xa0));
+        assert!(send(&mut bus, 0x42));
+        assert!(send(&mut bus, 0x5a));
+        stop(&mut bus);
+        assert_eq!(bus.chips[0].persistent()[0x42], 0x5a);
+
+        start(&mut bus);
+        assert!(send(&mut bus, 0xa0));
+        assert!(send(&mut bus, 0x42));
+        start(&mut bus);
+        assert!(send(&mut bus, 0xa1));
+        let byte = (0..8).fold(0, |byte, _| byte << 1 | clock(&mut bus, true) as u8);
+        clock(&mut bus, true);
+        stop(&mut bus);
+        assert_eq!(byte, 0x5a);
+    }
+
+    #[cfg(feature = "experimental-jit")]
+    #[test]
+    fn test_prg_bank_switch_drops_cached_code() {
+        use crate::cpu::CPU;
+        // LDA #$11 at $9000 in bank 0, and LDY #$22 in bank 1.
+        let mut raw = vec![0x4e, 0x45, 0x53, 0x1a, 4, 2, 0x00, 0x10, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0xea; 4 * PRG_BANK_SIZE];
+        prg[0x1000..0x1002].copy_from_slice(&[0xa9, 0x11]);
+        prg[PRG_BANK_SIZE + 0x1000..PRG_BANK_SIZE + 0x1002].copy_from_slice(&[0xa0, 0x22]);
+        raw.extend(prg);
+        raw.extend(vec![0; 2 * 0x2000]);
+        let mut cpu = CPU::new(Bus::new(Cartridge::new(&raw).unwrap()));
+        cpu.program_counter = 0x9000;
+        cpu.execute_instruction();
+        assert_eq!((cpu.register_a, cpu.block_cache.decoded), (0x11, 1));
+
+        cpu.mem_write(0x8008, 1);
+        cpu.program_counter = 0x9000;
+        cpu.execute_instruction();
+        assert_eq!((cpu.register_a, cpu.register_y, cpu.block_cache.decoded), (0x11, 0x22, 2));
+    }
+
+    #[test]
+    fn test_banks_mirroring_and_irq() {
+        let mut bus = bus();
+        assert_eq!((bus.mem_read(0x8000), bus.mem_read(0xc000)), (0, 3));
+        bus.mem_write(0x8008, 2);
+        assert_eq!((bus.mem_read(0x8000), bus.mem_read(0xffff)), (2, 3));
+        // Mirrored every 16 bytes.
+        bus.mem_write(0xfff3, 9);
+        assert_eq!((bus.ppu.chr_rom[0x0c00], bus.ppu.chr_rom[0x0fff], bus.ppu.chr_rom[0x1000]), (9, 9, 4));
+        bus.mem_write(0x8009, 3);
+        assert_eq!(bus.ppu.mirroring, Mirroring::SingleScreenUpper);
+
+        bus.mem_write(0x800b, 10);
+        bus.mem_write(0x800c, 0);
+        bus.mem_write(0x800a, 1);
+        bus.tick(10);
+        assert!(!bus.irq());
+        bus.tick(1);
+        assert!(bus.irq());
+        bus.mem_write(0x800a, 0);
+        bus.tick(100);
+        assert!(!bus.irq());
+    }
+}
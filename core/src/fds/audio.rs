@@ -0,0 +1,370 @@
+//! The FDS expansion sound channel: one 64-step wavetable voice with a volume envelope, whose pitch
+//! a second wavetable (the modulator) bends. Registers $4040-$4097.
+//!
+//! Reference: https://www.nesdev.org/wiki/FDS_audio
+
+use crate::savestate::{StateReader, StateWriter};
+
+// Output level scale for each master volume setting ($4089 bits 0-1): 2/2, 2/3, 2/4 and 2/5.
+const MASTER_VOLUMES: [u32; 4] = [36, 24, 17, 14];
+// Pitch change for each modulator table entry. 4 resets the counter instead.
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+// A volume or modulation gain envelope ($4080 or $4084).
+#[derive(Debug, Clone, Copy, Default)]
+struct Envelope {
+    speed: u8,
+    increase: bool,
+    // Off, the gain stays where the register set it.
+    off: bool,
+    gain: u8,
+    timer: u32,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8, master_speed: u8) {
+        self.speed = value & 0x3F;
+        self.increase = value & 0x40 != 0;
+        self.off = value & 0x80 != 0;
+        if self.off {
+            self.gain = self.speed;
+        }
+        self.reset_timer(master_speed);
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    // Counts down a CPU cycle, stepping the gain towards 0 or 32 when the timer runs out.
+    fn tick(&mut self, master_speed: u8) {
+        if self.off || master_speed == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.reset_timer(master_speed);
+            if self.increase && self.gain < 32 {
+                self.gain += 1;
+            } else if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.speed);
+        state.bool(self.increase);
+        state.bool(self.off);
+        state.u8(self.gain);
+        state.u32(self.timer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.speed = state.u8()?;
+        self.increase = state.bool()?;
+        self.off = state.bool()?;
+        self.gain = state.u8()?;
+        self.timer = state.u32()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Audio {
+    wave: [u8; 64],
+    // While set, the wavetable can be written and the output holds still.
+    wave_write: bool,
+    master_volume: u8,
+    frequency: u16,
+    wave_halted: bool,
+    envelopes_halted: bool,
+    // The wave steps each time this overflows.
+    wave_accumulator: u16,
+    wave_position: u8,
+    volume: Envelope,
+
+    modulator: Envelope,
+    mod_frequency: u16,
+    // While set, the modulator stops and its table can be written.
+    mod_halted: bool,
+    mod_table: [u8; 64],
+    mod_position: u8,
+    // 7-bit signed.
+    mod_counter: i8,
+    mod_accumulator: u16,
+
+    // $408A: scales both envelopes' speed.
+    envelope_speed: u8,
+    // Output level, 0 to 63.
+    level: u8,
+}
+
+impl Audio {
+    pub fn new() -> Self {
+        Audio {
+            wave: [0; 64],
+            wave_write: false,
+            master_volume: 0,
+            frequency: 0,
+            wave_halted: true,
+            envelopes_halted: true,
+            wave_accumulator: 0,
+            wave_position: 0,
+            volume: Envelope::default(),
+            modulator: Envelope::default(),
+            mod_frequency: 0,
+            mod_halted: true,
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_counter: 0,
+            mod_accumulator: 0,
+            envelope_speed: 0xE8,
+            level: 0,
+        }
+    }
+
+    // The channel's output, from 0 to 1, which the bus samples into the frame's sound.
+    pub fn output(&self) -> f32 {
+        self.level as f32 / 63.0
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x4040..=0x407F => self.wave[(addr & 0x3F) as usize] | 0x40,
+            0x4090 => self.volume.gain | 0x40,
+            0x4092 => self.modulator.gain | 0x40,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => self.wave[(addr & 0x3F) as usize] = value & 0x3F,
+            0x4080 => self.volume.write(value, self.envelope_speed),
+            0x4082 => self.frequency = (self.frequency & 0x0F00) | value as u16,
+            0x4083 => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.wave_halted = value & 0x80 != 0;
+                self.envelopes_halted = value & 0x40 != 0;
+                if self.wave_halted {
+                    self.wave_position = 0;
+                    self.wave_accumulator = 0;
+                }
+                if self.envelopes_halted {
+                    self.volume.reset_timer(self.envelope_speed);
+                    self.modulator.reset_timer(self.envelope_speed);
+                }
+            }
+            0x4084 => self.modulator.write(value, self.envelope_speed),
+            // Sign extends the 7-bit counter.
+            0x4085 => self.mod_counter = ((value & 0x7F) << 1) as i8 >> 1,
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0F00) | value as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.mod_halted = value & 0x80 != 0;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // Each write fills two entries.
+            0x4088 if self.mod_halted => {
+                for _ in 0..2 {
+                    self.mod_table[self.mod_position as usize] = value & 0x07;
+                    self.mod_position = (self.mod_position + 1) & 0x3F;
+                }
+            }
+            0x4089 => {
+                self.wave_write = value & 0x80 != 0;
+                self.master_volume = value & 0x03;
+            }
+            0x408A => self.envelope_speed = value,
+            _ => {}
+        }
+    }
+
+    // Runs one CPU cycle.
+    pub fn tick(&mut self) {
+        if !self.wave_halted && !self.envelopes_halted {
+            self.volume.tick(self.envelope_speed);
+            self.modulator.tick(self.envelope_speed);
+        }
+        if !self.mod_halted && self.mod_frequency > 0 {
+            let (accumulator, overflow) = self.mod_accumulator.overflowing_add(self.mod_frequency);
+            self.mod_accumulator = accumulator;
+            if overflow {
+                self.step_modulator();
+            }
+        }
+
+        if self.wave_halted {
+            self.level = 0;
+            return;
+        }
+        if !self.wave_write {
+            let gain = self.volume.gain.min(32) as u32;
+            self.level = (self.wave[self.wave_position as usize] as u32 * gain * MASTER_VOLUMES[self.master_volume as usize]
+                / 1152) as u8;
+            let pitch = self.pitch();
+            if pitch > 0 {
+                let (accumulator, overflow) = self.wave_accumulator.overflowing_add(pitch);
+                self.wave_accumulator = accumulator;
+                if overflow {
+                    self.wave_position = (self.wave_position + 1) & 0x3F;
+                }
+            }
+        }
+    }
+
+    fn step_modulator(&mut self) {
+        let step = self.mod_table[self.mod_position as usize];
+        self.mod_counter = match step {
+            4 => 0,
+            // Wraps within 7 bits.
+            _ => ((self.mod_counter.wrapping_add(MOD_STEPS[step as usize]) as u8) << 1) as i8 >> 1,
+        };
+        self.mod_position = (self.mod_position + 1) & 0x3F;
+    }
+
+    // The wave's pitch after modulation, worked out the way the hardware rounds it.
+    fn pitch(&self) -> u16 {
+        if self.mod_halted {
+            return self.frequency;
+        }
+        let counter = self.mod_counter as i32;
+        let mut temp = counter * self.modulator.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        let mut temp = self.frequency as i32 * temp;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        (self.frequency as i32 + temp).clamp(0, 0xFFFF) as u16
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.wave);
+        state.bool(self.wave_write);
+        state.u8(self.master_volume);
+        state.u16(self.frequency);
+        state.bool(self.wave_halted);
+        state.bool(self.envelopes_halted);
+        state.u16(self.wave_accumulator);
+        state.u8(self.wave_position);
+        self.volume.save_state(state);
+        self.modulator.save_state(state);
+        state.u16(self.mod_frequency);
+        state.bool(self.mod_halted);
+        state.bytes(&self.mod_table);
+        state.u8(self.mod_position);
+        state.u8(self.mod_counter as u8);
+        state.u16(self.mod_accumulator);
+        state.u8(self.envelope_speed);
+        state.u8(self.level);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.wave)?;
+        self.wave_write = state.bool()?;
+        self.master_volume = state.u8()? & 0x03;
+        self.frequency = state.u16()?;
+        self.wave_halted = state.bool()?;
+        self.envelopes_halted = state.bool()?;
+        self.wave_accumulator = state.u16()?;
+        self.wave_position = state.u8()? & 0x3F;
+        self.volume.load_state(state)?;
+        self.modulator.load_state(state)?;
+        self.mod_frequency = state.u16()?;
+        self.mod_halted = state.bool()?;
+        state.bytes(&mut self.mod_table)?;
+        self.mod_position = state.u8()? & 0x3F;
+        self.mod_counter = state.u8()? as i8;
+        self.mod_accumulator = state.u16()?;
+        self.envelope_speed = state.u8()?;
+        self.level = state.u8()?;
+        Ok(())
+    }
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Audio::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A square wave at full volume: 32 steps low, 32 high.
+    fn square() -> Audio {
+        let mut audio = Audio::new();
+        audio.write(0x4089, 0x80);
+        for i in 0..64 {
+            audio.write(0x4040 + i, if i < 32 { 0 } else { 63 });
+        }
+        audio.write(0x4089, 0x00);
+        audio.write(0x4080, 0x80 | 32);
+        audio
+    }
+
+    #[test]
+    fn test_wave_plays_at_its_pitch() {
+        let mut audio = square();
+        assert_eq!(audio.read(0x4060), 0x40 | 63);
+        assert_eq!(audio.read(0x4090), 0x40 | 32);
+
+        // A pitch of 0x400 steps the wave every 64 cycles, so a whole cycle of it is 4096.
+        audio.write(0x4082, 0x00);
+        audio.write(0x4083, 0x04);
+        let levels: Vec<u8> = (0..4096)
+            .map(|_| {
+                audio.tick();
+                audio.level
+            })
+            .collect();
+        assert!(levels[..2048].iter().all(|level| *level == 0));
+        assert!(levels[2048..].iter().all(|level| *level == 63));
+        assert_eq!(audio.output(), 1.0);
+
+        // Halting the wave silences it and sends it back to the start.
+        audio.write(0x4083, 0x84);
+        audio.tick();
+        assert_eq!((audio.level, audio.wave_position), (0, 0));
+    }
+
+    #[test]
+    fn test_modulator_bends_the_pitch() {
+        let mut audio = square();
+        audio.write(0x4082, 0x00);
+        audio.write(0x4083, 0x04);
+        assert_eq!(audio.pitch(), 0x400);
+
+        // The table is only writable while the modulator is halted.
+        audio.write(0x4087, 0x80);
+        for _ in 0..32 {
+            audio.write(0x4088, 1);
+        }
+        audio.write(0x4084, 0x80 | 32);
+        audio.write(0x4085, 0x08);
+        audio.write(0x4086, 0xFF);
+        audio.write(0x4087, 0x0F);
+        // Counter 8 at gain 32 raises the pitch by 8 * 32 / 16 / 64 of itself.
+        assert_eq!(audio.pitch(), 0x400 + 0x400 * 16 / 64);
+
+        // Each +1 step raises the counter by 1, wrapping at 7 bits.
+        audio.write(0x4085, 0x3F);
+        audio.step_modulator();
+        assert_eq!(audio.mod_counter, -64);
+    }
+}
@@ -0,0 +1,148 @@
+//! .fds disk images. The file keeps only the blocks of each disk side; the drive reads them off the
+//! disk with the gaps, start marks and CRCs in between, so those are put back in on loading.
+//!
+//! Reference: https://www.nesdev.org/wiki/FDS_file_format
+//! Reference: https://www.nesdev.org/wiki/FDS_disk_format
+
+const FDS_IDENTIFIER: [u8; 4] = [0x46, 0x44, 0x53, 0x1A];
+// Every side starts with the disk info block, whose first bytes are its block code and this.
+const DISK_VERIFICATION: &[u8] = b"\x01*NINTENDO-HVC*";
+pub const SIDE_SIZE: usize = 65500;
+
+// Gaps of zero bits, in bytes: before the first block, and after each block's CRC.
+const LEAD_IN: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+// The 1 bit ending a gap, which the drive reads as the byte before the block.
+pub const START_MARK: u8 = 0x80;
+
+// Whether `raw` looks like an .fds file, with or without the fwNES header.
+pub fn is_disk_image(raw: &[u8]) -> bool {
+    raw.starts_with(&FDS_IDENTIFIER) || raw.starts_with(DISK_VERIFICATION)
+}
+
+// Splits an .fds file into its sides, each as the drive reads it off the disk.
+pub fn parse(raw: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let data = match raw.starts_with(&FDS_IDENTIFIER) {
+        true => &raw[16.min(raw.len())..],
+        false => raw,
+    };
+    if data.is_empty() || data.len() % SIDE_SIZE != 0 {
+        return Err(format!("Disk image is {} bytes, not a whole number of {} byte sides", data.len(), SIDE_SIZE));
+    }
+    data.chunks(SIDE_SIZE)
+        .enumerate()
+        .map(|(i, side)| match side.starts_with(DISK_VERIFICATION) {
+            true => Ok(add_gaps(side)),
+            false => Err(format!("{} of the disk image has no disk info block", side_name(i))),
+        })
+        .collect()
+}
+
+// "Disk 1 Side A" for side 0, and so on.
+pub fn side_name(side: usize) -> String {
+    format!("Disk {} Side {}", side / 2 + 1, if side.is_multiple_of(2) { 'A' } else { 'B' })
+}
+
+// Lays the blocks of `side` out as they are on the disk, stopping at the first byte that doesn't
+// start a block: the rest of the side is unused.
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+    let mut track = vec![0; LEAD_IN];
+    let mut pos = 0;
+    // The size of the file whose header was the last block, which its data block holds.
+    let mut file_size = 0;
+    while pos < side.len() {
+        let len = match side[pos] {
+            1 => 56,
+            2 => 2,
+            3 => 16,
+            4 => 1 + file_size,
+            _ => break,
+        };
+        let Some(block) = side.get(pos..pos + len) else {
+            break;
+        };
+        if block[0] == 3 {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+        let crc = block_crc(block);
+        track.push(START_MARK);
+        track.extend_from_slice(block);
+        track.extend_from_slice(&crc.to_le_bytes());
+        track.resize(track.len() + BLOCK_GAP, 0);
+        pos += len;
+    }
+    track.resize(track.len().max(SIDE_SIZE), 0);
+    track
+}
+
+// Feeds `byte` into a CRC the way the drive does, least significant bit first.
+pub fn update_crc(crc: u16, byte: u8) -> u16 {
+    (0..8).fold(crc, |crc, bit| {
+        let crc = if crc & 1 != 0 { (crc >> 1) ^ 0x8408 } else { crc >> 1 };
+        if byte >> bit & 1 != 0 {
+            crc ^ 0x8000
+        } else {
+            crc
+        }
+    })
+}
+
+// The CRC stored after `block`: over its start mark and bytes, then 16 zero bits to flush it.
+fn block_crc(block: &[u8]) -> u16 {
+    [START_MARK].iter().chain(block).chain(&[0, 0]).fold(0, |crc, byte| update_crc(crc, *byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A side with the disk info block, a file count and one 3 byte file.
+    fn side() -> Vec<u8> {
+        let mut side = DISK_VERIFICATION.to_vec();
+        side.resize(56, 0);
+        side.extend([2, 1]);
+        let mut header = vec![3, 0, 0, b'F', b'I', b'L', b'E', b' ', b' ', b' ', b' ', 0x00, 0x60, 3, 0, 0];
+        side.append(&mut header);
+        side.extend([4, 0xA9, 0x01, 0x60]);
+        side.resize(SIDE_SIZE, 0);
+        side
+    }
+
+    #[test]
+    fn test_parse_adds_gaps_and_crcs() {
+        let mut raw = FDS_IDENTIFIER.to_vec();
+        raw.extend([2; 12]);
+        raw.extend(side());
+        raw.extend(side());
+        assert!(is_disk_image(&raw));
+
+        let sides = parse(&raw).unwrap();
+        assert_eq!(sides.len(), 2);
+        let track = &sides[0];
+        assert_eq!(track.len(), SIDE_SIZE);
+        assert!(track[..LEAD_IN].iter().all(|byte| *byte == 0));
+        assert_eq!(track[LEAD_IN], START_MARK);
+        assert_eq!(&track[LEAD_IN + 1..LEAD_IN + 1 + DISK_VERIFICATION.len()], DISK_VERIFICATION);
+
+        // Each block is followed by its CRC, then a gap. The file's data block is 1 + 3 bytes.
+        let data = LEAD_IN + 3 * (1 + 2 + BLOCK_GAP) + 56 + 2 + 16;
+        assert_eq!(&track[data..data + 5], &[START_MARK, 4, 0xA9, 0x01, 0x60]);
+        let crc = u16::from_le_bytes([track[data + 5], track[data + 6]]);
+        // Running the CRC over the block and its CRC leaves nothing, which is how the drive checks it.
+        let check = [START_MARK, 4, 0xA9, 0x01, 0x60].iter().fold(0, |crc, byte| update_crc(crc, *byte));
+        assert_eq!(update_crc(update_crc(check, crc as u8), (crc >> 8) as u8), 0);
+        assert!(track[data + 7..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_images() {
+        assert!(!is_disk_image(b"NES\x1a"));
+        assert_eq!(
+            parse(&side()[..1000]).err().unwrap(),
+            "Disk image is 1000 bytes, not a whole number of 65500 byte sides"
+        );
+        let mut raw = side();
+        raw.extend(vec![0; SIDE_SIZE]);
+        assert_eq!(parse(&raw).err().unwrap(), "Disk 1 Side B of the disk image has no disk info block");
+    }
+}
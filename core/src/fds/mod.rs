@@ -0,0 +1,652 @@
+//! Famicom Disk System: the RAM adapter that plugs into the cartridge slot, and the disk drive
+//! behind it.
+//!
+//! The adapter holds 32kB of RAM at $6000-$DFFF, 8kB of CHR-RAM, the BIOS at $E000-$FFFF (the
+//! cartridge's PRG-ROM), a timer IRQ, the drive's registers at $4020-$4033 and the expansion sound
+//! channel at $4040-$4097. The BIOS loads files off the disk a byte at a time, the drive raising an
+//! IRQ as each one goes past the head.
+//!
+//! Reference: https://www.nesdev.org/wiki/Family_Computer_Disk_System
+
+use std::path::{Path, PathBuf};
+
+use crate::cartridge::Mirroring;
+use crate::savestate::{StateReader, StateWriter};
+
+pub mod audio;
+pub mod disk;
+
+use audio::Audio;
+
+// The BIOS image, disksys.rom, is 8kB.
+pub const BIOS_SIZE: usize = 0x2000;
+const RAM_START: u16 = 0x6000;
+const RAM_SIZE: usize = 0x8000;
+// The environment variable naming the BIOS, when it isn't next to the disk image.
+pub const BIOS_VARIABLE: &str = "NES_RS_FDS_BIOS";
+const BIOS_FILE: &str = "disksys.rom";
+
+// CPU cycles for the head to go back to the start of the disk, and for each byte to pass under it.
+const REWIND_CYCLES: u32 = 50000;
+const BYTE_CYCLES: u32 = 150;
+// How long a disk stays out of the drive while switching sides: about a second, long enough for the
+// BIOS to see it go.
+const SWITCH_CYCLES: u32 = 1_789_773;
+
+pub struct Fds {
+    ram: Vec<u8>,
+    sides: Vec<Vec<u8>>,
+    // The side in the drive, or None with the drive empty.
+    inserted: Option<usize>,
+    // The side going in next, and the cycles until it does.
+    switching: Option<(usize, u32)>,
+    pub audio: Audio,
+
+    // $4023: whether the disk and sound registers are enabled.
+    disk_registers: bool,
+    sound_registers: bool,
+
+    // The timer IRQ ($4020-$4022).
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+
+    // The drive ($4024-$4026), as the BIOS last set it up.
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    mirroring: Mirroring,
+    crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+    write_data: u8,
+    external: u8,
+
+    // The drive's progress through the side.
+    read_data: u8,
+    // A byte went past the head since the BIOS last took one, raising an IRQ if they were on.
+    transfer_complete: bool,
+    disk_irq: bool,
+    end_of_head: bool,
+    scanning: bool,
+    position: usize,
+    delay: u32,
+    gap_ended: bool,
+    previous_crc_control: bool,
+    crc: u16,
+}
+
+impl Fds {
+    // The adapter with `sides` of a disk, side A of the first in the drive.
+    pub fn new(sides: Vec<Vec<u8>>) -> Self {
+        Fds {
+            ram: vec![0; RAM_SIZE],
+            inserted: (!sides.is_empty()).then_some(0),
+            sides,
+            switching: None,
+            audio: Audio::new(),
+            disk_registers: false,
+            sound_registers: false,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: false,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            mirroring: Mirroring::Horizontal,
+            crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            write_data: 0,
+            external: 0,
+            read_data: 0,
+            transfer_complete: false,
+            disk_irq: false,
+            end_of_head: true,
+            scanning: false,
+            position: 0,
+            delay: 0,
+            gap_ended: false,
+            previous_crc_control: false,
+            crc: 0,
+        }
+    }
+
+    pub fn sides(&self) -> usize {
+        self.sides.len()
+    }
+
+    // The disk sides, as the drive reads them, with whatever the game has written.
+    pub fn disk(&self) -> &[Vec<u8>] {
+        &self.sides
+    }
+
+    pub fn inserted(&self) -> Option<usize> {
+        self.inserted
+    }
+
+    // Ejects the disk, and puts `side` in a second later, as a player flipping it over would.
+    pub fn switch_side(&mut self, side: usize) {
+        if side < self.sides.len() {
+            self.inserted = None;
+            self.switching = Some((side, SWITCH_CYCLES));
+        }
+    }
+
+    // Switches to the side after the one in the drive (or going in), back to the first after the
+    // last, and returns it.
+    pub fn switch_to_next_side(&mut self) -> usize {
+        let current = self.switching.map(|(side, _)| side).or(self.inserted);
+        let next = current.map_or(0, |side| (side + 1) % self.sides.len().max(1));
+        self.switch_side(next);
+        next
+    }
+
+    // Set by the BIOS through $4025.
+    pub fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    // Whether the timer or the drive is holding the CPU's IRQ line.
+    pub fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    // Reads $4020-$DFFF.
+    pub fn read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4030 if self.disk_registers => {
+                let status = self.timer_irq as u8
+                    | (self.transfer_complete as u8) << 1
+                    | (self.end_of_head as u8) << 6;
+                self.timer_irq = false;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                status
+            }
+            0x4031 if self.disk_registers => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                self.read_data
+            }
+            0x4032 if self.disk_registers => {
+                let empty = self.inserted.is_none();
+                empty as u8 | ((empty || !self.scanning) as u8) << 1 | (empty as u8) << 2 | 0x40
+            }
+            // The expansion port's bit 7 reads as the battery being good.
+            0x4033 if self.disk_registers => self.external & 0x7F | 0x80,
+            0x4040..=0x4097 if self.sound_registers => self.audio.read(addr),
+            RAM_START..=0xDFFF => self.ram[(addr - RAM_START) as usize],
+            _ => 0,
+        }
+    }
+
+    // Reads `addr` without side effects: RAM, or 0 for a register.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM_START..=0xDFFF => self.ram[(addr - RAM_START) as usize],
+            _ => 0,
+        }
+    }
+
+    // Writes $4020-$DFFF.
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | value as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | (value as u16) << 8,
+            0x4022 => {
+                self.irq_repeat = value & 0x01 != 0;
+                self.irq_enabled = value & 0x02 != 0 && self.disk_registers;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_registers = value & 0x01 != 0;
+                self.sound_registers = value & 0x02 != 0;
+                if !self.disk_registers {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 if self.disk_registers => {
+                self.write_data = value;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 if self.disk_registers => {
+                self.motor_on = value & 0x01 != 0;
+                self.reset_transfer = value & 0x02 != 0;
+                self.read_mode = value & 0x04 != 0;
+                self.mirroring = if value & 0x08 != 0 { Mirroring::Horizontal } else { Mirroring::Vertical };
+                self.crc_control = value & 0x10 != 0;
+                self.disk_ready = value & 0x40 != 0;
+                self.disk_irq_enabled = value & 0x80 != 0;
+                self.disk_irq = false;
+            }
+            0x4026 if self.disk_registers => self.external = value,
+            0x4040..=0x4097 if self.sound_registers => self.audio.write(addr, value),
+            RAM_START..=0xDFFF => self.ram[(addr - RAM_START) as usize] = value,
+            _ => {}
+        }
+    }
+
+    // Writes `addr` straight into RAM. Registers are ignored.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        if let RAM_START..=0xDFFF = addr {
+            self.ram[(addr - RAM_START) as usize] = value;
+        }
+    }
+
+    // Runs `cycles` CPU cycles.
+    pub fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.tick_timer();
+            if let Some((side, delay)) = &mut self.switching {
+                *delay -= 1;
+                if *delay == 0 {
+                    self.inserted = Some(*side);
+                    self.switching = None;
+                }
+            }
+            self.tick_drive();
+            if self.sound_registers {
+                self.audio.tick();
+            }
+        }
+    }
+
+    fn tick_timer(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            self.irq_counter = self.irq_reload;
+            self.irq_enabled = self.irq_repeat;
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    // Moves the disk under the head, reading or writing a byte every `BYTE_CYCLES`. With the
+    // motor stopped or the drive empty, the head goes back to the start.
+    fn tick_drive(&mut self) {
+        let Some(side) = self.inserted.filter(|_| self.motor_on) else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let track = &mut self.sides[side];
+        if self.read_mode {
+            let byte = track[self.position];
+            if !self.previous_crc_control {
+                self.crc = disk::update_crc(self.crc, byte);
+            }
+            let mut irq = self.disk_irq_enabled;
+            if !self.disk_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if byte != 0 && !self.gap_ended {
+                // The start mark: the block begins with the next byte.
+                self.gap_ended = true;
+                irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = byte;
+                self.disk_irq |= irq;
+            }
+        } else {
+            let mut byte = self.write_data;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                self.disk_irq |= self.disk_irq_enabled;
+            }
+            if !self.disk_ready {
+                byte = 0;
+            }
+            if !self.crc_control {
+                self.crc = disk::update_crc(self.crc, byte);
+            } else {
+                if !self.previous_crc_control {
+                    // Flush the CRC, then write it out a byte at a time.
+                    self.crc = disk::update_crc(disk::update_crc(self.crc, 0), 0);
+                }
+                byte = self.crc as u8;
+                self.crc >>= 8;
+            }
+            track[self.position] = byte;
+            self.gap_ended = false;
+        }
+        self.previous_crc_control = self.crc_control;
+
+        self.position += 1;
+        if self.position >= track.len() {
+            // The end of the side stops the motor, and the head goes back to the start even if a
+            // write turns it on again before the next cycle.
+            self.motor_on = false;
+            self.end_of_head = true;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+
+    // RAM, the disk in its current state and every register. The BIOS and CHR-RAM are saved with
+    // the bus and PPU.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.vec(&self.ram);
+        state.u32(self.sides.len() as u32);
+        for side in &self.sides {
+            state.u32(side.len() as u32);
+            state.bytes(side);
+        }
+        state.u32(self.inserted.map_or(u32::MAX, |side| side as u32));
+        let (switching, delay) = self.switching.unwrap_or((usize::MAX, 0));
+        state.u32(switching as u32);
+        state.u32(delay);
+        self.audio.save_state(state);
+        state.bool(self.disk_registers);
+        state.bool(self.sound_registers);
+        state.u16(self.irq_reload);
+        state.u16(self.irq_counter);
+        state.bool(self.irq_repeat);
+        state.bool(self.irq_enabled);
+        state.bool(self.timer_irq);
+        state.bool(self.motor_on);
+        state.bool(self.reset_transfer);
+        state.bool(self.read_mode);
+        state.bool(self.mirroring == Mirroring::Horizontal);
+        state.bool(self.crc_control);
+        state.bool(self.disk_ready);
+        state.bool(self.disk_irq_enabled);
+        state.u8(self.write_data);
+        state.u8(self.external);
+        state.u8(self.read_data);
+        state.bool(self.transfer_complete);
+        state.bool(self.disk_irq);
+        state.bool(self.end_of_head);
+        state.bool(self.scanning);
+        state.u32(self.position as u32);
+        state.u32(self.delay);
+        state.bool(self.gap_ended);
+        state.bool(self.previous_crc_control);
+        state.u16(self.crc);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.vec(&mut self.ram)?;
+        if state.u32()? as usize != self.sides.len() {
+            return Err("Save state is for a disk with a different number of sides".to_string());
+        }
+        for side in &mut self.sides {
+            if state.u32()? as usize != side.len() {
+                return Err("Save state's disk doesn't match this one".to_string());
+            }
+            state.bytes(side)?;
+        }
+        let side = |side: u32| (side != u32::MAX).then_some(side as usize);
+        self.inserted = side(state.u32()?);
+        let switching = side(state.u32()?);
+        self.switching = switching.zip(Some(state.u32()?));
+        if self.inserted.into_iter().chain(switching).any(|side| side >= self.sides.len()) {
+            return Err("Save state has a disk side this disk doesn't".to_string());
+        }
+        self.audio.load_state(state)?;
+        self.disk_registers = state.bool()?;
+        self.sound_registers = state.bool()?;
+        self.irq_reload = state.u16()?;
+        self.irq_counter = state.u16()?;
+        self.irq_repeat = state.bool()?;
+        self.irq_enabled = state.bool()?;
+        self.timer_irq = state.bool()?;
+        self.motor_on = state.bool()?;
+        self.reset_transfer = state.bool()?;
+        self.read_mode = state.bool()?;
+        self.mirroring = if state.bool()? { Mirroring::Horizontal } else { Mirroring::Vertical };
+        self.crc_control = state.bool()?;
+        self.disk_ready = state.bool()?;
+        self.disk_irq_enabled = state.bool()?;
+        self.write_data = state.u8()?;
+        self.external = state.u8()?;
+        self.read_data = state.u8()?;
+        self.transfer_complete = state.bool()?;
+        self.disk_irq = state.bool()?;
+        self.end_of_head = state.bool()?;
+        self.scanning = state.bool()?;
+        self.position = state.u32()? as usize;
+        self.delay = state.u32()?;
+        self.gap_ended = state.bool()?;
+        self.previous_crc_control = state.bool()?;
+        self.crc = state.u16()?;
+        // A head at the very end of the side, as it is once a side has been read through, goes
+        // back to the start. Older builds saved that without saying so.
+        match self.inserted.map(|side| self.sides[side].len()) {
+            Some(len) if self.position > len => {
+                return Err("Save state's drive head is off the end of the disk".to_string());
+            }
+            Some(len) if self.position == len => self.end_of_head = true,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+// Reads the BIOS for the disk image at `image`: the file named by $NES_RS_FDS_BIOS, or else
+// disksys.rom next to the image or in the current directory.
+pub fn load_bios(image: &Path) -> Result<Vec<u8>, String> {
+    let candidates: Vec<PathBuf> = match std::env::var_os(BIOS_VARIABLE) {
+        Some(path) => vec![PathBuf::from(path)],
+        None => vec![image.with_file_name(BIOS_FILE), PathBuf::from(BIOS_FILE)],
+    };
+    let Some(path) = candidates.iter().find(|path| path.is_file()) else {
+        return Err(format!(
+            "Disk images need the FDS BIOS: put {} next to the image or set {} to its path",
+            BIOS_FILE, BIOS_VARIABLE
+        ));
+    };
+    let bios = std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    match bios.len() {
+        BIOS_SIZE => Ok(bios),
+        len => Err(format!("{} is {} bytes, but the FDS BIOS is {}", path.display(), len, BIOS_SIZE)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::emulator::Emulator;
+    use crate::host::{AudioSink, SAMPLES_PER_FRAME};
+
+    // A disk whose single side counts up from 1 after the lead-in, so bytes are easy to place.
+    fn fds() -> Fds {
+        let mut side = vec![0; 100];
+        side.extend((1..=50).map(|byte| byte as u8));
+        let mut fds = Fds::new(vec![side.clone(), side]);
+        fds.write(0x4023, 0x03);
+        fds
+    }
+
+    #[test]
+    fn test_timer_irq() {
+        let mut fds = fds();
+        fds.write(0x4020, 9);
+        fds.write(0x4021, 0);
+        fds.write(0x4022, 0x03);
+        fds.tick(9);
+        assert!(!fds.irq());
+        fds.tick(1);
+        assert!(fds.irq());
+        // Reading the status acknowledges it, and repeat reloads the counter.
+        assert_eq!(fds.read(0x4030) & 0x01, 0x01);
+        assert!(!fds.irq());
+        fds.tick(10);
+        assert!(fds.irq());
+
+        fds.write(0x4022, 0x00);
+        assert!(!fds.irq());
+    }
+
+    #[test]
+    fn test_drive_reads_the_disk_with_irqs() {
+        let mut fds = fds();
+        // Motor on, read mode, ready, IRQ on the transfers.
+        fds.write(0x4025, 0xC5);
+        fds.tick(1);
+        assert_eq!(fds.read(0x4032) & 0x03, 0x02);
+        fds.tick(REWIND_CYCLES as usize);
+
+        // The gap passes without IRQs; the start mark (here the first nonzero byte) ends it.
+        let mut bytes = Vec::new();
+        for _ in 0..120 * (BYTE_CYCLES as usize + 1) {
+            fds.tick(1);
+            if fds.irq() {
+                bytes.push(fds.read(0x4031));
+            }
+        }
+        assert_eq!(fds.read(0x4032) & 0x03, 0x00);
+        assert_eq!(bytes[..5], [2, 3, 4, 5, 6]);
+        assert_eq!(fds.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_head_rewinds_at_the_end_of_a_side() {
+        let mut fds = Fds::new(vec![vec![1; 10]]);
+        fds.write(0x4023, 0x01);
+        fds.write(0x4025, 0x45);
+        // The BIOS writes $4025 all the time, which can turn the motor straight back on.
+        for _ in 0..10 * REWIND_CYCLES as usize {
+            fds.tick(1);
+            fds.write(0x4025, 0x45);
+        }
+
+        // A state saved with the head at the end of a side loads, and rewinds it.
+        fds.position = 10;
+        fds.end_of_head = false;
+        let mut state = StateWriter::new();
+        fds.save_state(&mut state);
+        let mut loaded = Fds::new(vec![vec![1; 10]]);
+        loaded.load_state(&mut StateReader::new(&state.into_bytes())).unwrap();
+        loaded.tick(1);
+        assert_eq!(loaded.position, 0);
+    }
+
+    #[test]
+    fn test_switching_sides() {
+        let mut fds = fds();
+        assert_eq!(fds.inserted(), Some(0));
+        assert_eq!(fds.switch_to_next_side(), 1);
+        assert_eq!(fds.read(0x4032) & 0x01, 0x01);
+        fds.tick(SWITCH_CYCLES as usize);
+        assert_eq!(fds.inserted(), Some(1));
+        assert_eq!(fds.read(0x4032) & 0x01, 0x00);
+        assert_eq!(fds.switch_to_next_side(), 0);
+    }
+
+    #[test]
+    fn test_ram_and_save_state() {
+        let mut fds = fds();
+        fds.write(0x6000, 0x12);
+        fds.write(0xDFFF, 0x34);
+        assert_eq!((fds.read(0x6000), fds.peek(0xDFFF)), (0x12, 0x34));
+        fds.write(0x4025, 0x25);
+
+        let mut state = StateWriter::new();
+        fds.save_state(&mut state);
+        let bytes = state.into_bytes();
+        let mut loaded = Fds::new(fds.disk().to_vec());
+        loaded.load_state(&mut StateReader::new(&bytes)).unwrap();
+        assert_eq!((loaded.read(0x6000), loaded.read(0xDFFF)), (0x12, 0x34));
+        assert_eq!(loaded.mirroring(), Mirroring::Vertical);
+
+        let mut wrong_disk = Fds::new(vec![vec![0; 150]]);
+        assert!(wrong_disk.load_state(&mut StateReader::new(&bytes)).is_err());
+    }
+
+    #[test]
+    fn test_timer_irq_reaches_the_cpu() {
+        // The BIOS only has the vectors and an IRQ handler that acknowledges and counts.
+        let mut bios = vec![0; BIOS_SIZE];
+        bios[..7].copy_from_slice(&[0xAD, 0x30, 0x40, 0xE6, 0x00, 0x40, 0x00]);
+        bios[0x1FFC..].copy_from_slice(&[0x00, 0x60, 0x00, 0xE0]);
+        let mut image = b"\x01*NINTENDO-HVC*".to_vec();
+        image.resize(disk::SIDE_SIZE, 0);
+        let mut emulator = Emulator::new(Cartridge::fds(&image, &bios).unwrap());
+
+        // Enables the timer to fire every 0x1000 cycles, then waits.
+        let program = [
+            0xA9, 0x01, 0x8D, 0x23, 0x40, 0xA9, 0x00, 0x8D, 0x20, 0x40, 0xA9, 0x10, 0x8D, 0x21, 0x40, 0xA9, 0x03,
+            0x8D, 0x22, 0x40, 0x58, 0x4C, 0x15, 0x60,
+        ];
+        for (i, byte) in program.into_iter().enumerate() {
+            emulator.cpu.bus.poke(0x6000 + i as u16, byte);
+        }
+        emulator.run_frame();
+        // A frame is 29780 cycles, so seven or eight IRQs.
+        assert!((7..=8).contains(&emulator.cpu.bus.peek(0x0000)));
+    }
+
+    // Keeps the sound it is given.
+    struct Sound(Vec<i16>);
+
+    impl AudioSink for Sound {
+        fn samples(&mut self, samples: &[i16]) {
+            self.0.extend_from_slice(samples);
+        }
+    }
+
+    #[test]
+    fn test_sound_reaches_the_audio_sink() {
+        // The BIOS just loops.
+        let mut bios = vec![0; BIOS_SIZE];
+        bios[..3].copy_from_slice(&[0x4C, 0x00, 0xE0]);
+        bios[0x1FFC..].copy_from_slice(&[0x00, 0xE0, 0x00, 0xE0]);
+        let mut image = b"\x01*NINTENDO-HVC*".to_vec();
+        image.resize(disk::SIDE_SIZE, 0);
+        let mut emulator = Emulator::new(Cartridge::fds(&image, &bios).unwrap());
+        let mut sound = Sound(Vec::new());
+        emulator.run_frame_with_host(&mut (), &mut (), &mut sound);
+        assert!(sound.0.iter().all(|sample| *sample == 0));
+
+        // A square wave at full volume, a whole cycle every 4096 CPU cycles.
+        let fds = emulator.cpu.bus.fds.as_mut().unwrap();
+        fds.write(0x4023, 0x03);
+        fds.write(0x4089, 0x80);
+        for i in 0..64 {
+            fds.write(0x4040 + i, if i < 32 { 0 } else { 63 });
+        }
+        fds.write(0x4089, 0x00);
+        fds.write(0x4080, 0x80 | 32);
+        fds.write(0x4082, 0x00);
+        fds.write(0x4083, 0x04);
+        sound.0.clear();
+        emulator.run_frame_with_host(&mut (), &mut (), &mut sound);
+        assert_eq!(sound.0.len(), SAMPLES_PER_FRAME);
+        let loud = sound.0.iter().filter(|sample| **sample > 0).count();
+        assert!((SAMPLES_PER_FRAME / 3..SAMPLES_PER_FRAME * 2 / 3).contains(&loud), "{} loud samples", loud);
+    }
+}
@@ -0,0 +1,298 @@
+//! What a frontend plugs into the console: where controller input comes from and where the
+//! picture and sound go. `Emulator::run_frame_with_host` reads the input, runs one frame and hands
+//! the results over, so a frontend only implements these three traits. The desktop window, the
+//! libretro core and anything embedding `nes_rs_core` (an egui app, a game engine) drive the
+//! console the same way. `()` implements all three, for a frontend that doesn't need one of them.
+//! `SinkThread` runs a video or audio sink on a thread of its own, off the emulation thread.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::emulator::NTSC_FRAME_RATE;
+use crate::joypad::JoypadButton;
+use crate::render::frame::Frame;
+
+// Audio samples per video frame. The sample rate follows from it, so sound and picture stay in
+// step however fast the host runs the console.
+pub const SAMPLES_PER_FRAME: usize = 734;
+pub const SAMPLE_RATE: f64 = SAMPLES_PER_FRAME as f64 * NTSC_FRAME_RATE;
+
+pub trait InputProvider {
+    // The buttons held on controllers 1 to 4 for the next frame. Called once per frame, before it
+    // runs; controllers 3 and 4 only matter with a Four Score plugged in.
+    fn buttons(&mut self) -> [JoypadButton; 4];
+}
+
+pub trait VideoSink {
+    // Called with the finished picture after every frame. Only the top 256x240 pixels are shown.
+    fn frame(&mut self, frame: &Frame);
+}
+
+pub trait AudioSink {
+    // Called with the frame's sound after every frame: `SAMPLES_PER_FRAME` mono samples at
+    // `SAMPLE_RATE`. There is no APU yet, so it is silent except for the disk system's channel;
+    // frontends that pace themselves by audio still get the right amount of it.
+    fn samples(&mut self, samples: &[i16]);
+}
+
+impl InputProvider for () {
+    fn buttons(&mut self) -> [JoypadButton; 4] {
+        [JoypadButton::empty(); 4]
+    }
+}
+
+impl VideoSink for () {
+    fn frame(&mut self, _frame: &Frame) {}
+}
+
+impl AudioSink for () {
+    fn samples(&mut self, _samples: &[i16]) {}
+}
+
+// Fixed input, for tests and for frontends that set it themselves.
+impl InputProvider for [JoypadButton; 4] {
+    fn buttons(&mut self) -> [JoypadButton; 4] {
+        *self
+    }
+}
+
+// Frames of picture and of sound a `SinkThread` holds for a sink that has fallen behind. One
+// picture is enough, since an older one is never worth showing; sound gets more, to ride out an
+// audio callback that runs late without a gap.
+pub const VIDEO_QUEUE: usize = 1;
+pub const AUDIO_QUEUE: usize = 8;
+
+// What `SinkThread` gives up when its queue is full. A picture that is already out of date goes
+// in favour of the new one, while sound keeps what is queued, since skipping ahead would leave a
+// gap in the middle of it instead of at the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WhenFull {
+    DropOldest,
+    DropNewest,
+}
+
+// The buffers waiting for the worker, and whether the sending side has gone.
+struct Queue<T> {
+    buffers: VecDeque<T>,
+    closed: bool,
+}
+
+// Runs a sink on its own thread, so one that blocks (a slow texture upload, an audio device's
+// queue) never holds up emulation. Buffers go over a bounded queue; when it is full a buffer is
+// dropped, and counted, rather than waited for: the oldest picture, or the newest sound. The
+// worker hands buffers back to be refilled, so nothing is allocated once it is running. Dropping
+// it waits for the sink to take what is queued.
+pub struct SinkThread<T: Send + 'static> {
+    queue: Arc<(Mutex<Queue<T>>, Condvar)>,
+    depth: usize,
+    when_full: WhenFull,
+    returned: Receiver<T>,
+    spare: Vec<T>,
+    worker: Option<JoinHandle<()>>,
+    // Frames the sink was too far behind to get.
+    pub dropped: u64,
+}
+
+impl<T: Send + 'static> SinkThread<T> {
+    fn spawn<F: FnMut(&T) + Send + 'static>(depth: usize, when_full: WhenFull, mut consume: F) -> Self {
+        let queue = Arc::new((Mutex::new(Queue { buffers: VecDeque::with_capacity(depth), closed: false }), Condvar::new()));
+        let (give_back, returned) = channel();
+        let jobs = queue.clone();
+        let worker = thread::spawn(move || {
+            let (lock, ready) = &*jobs;
+            loop {
+                let mut queue = lock.lock().unwrap();
+                while queue.buffers.is_empty() && !queue.closed {
+                    queue = ready.wait(queue).unwrap();
+                }
+                let Some(buffer) = queue.buffers.pop_front() else { break };
+                drop(queue);
+                consume(&buffer);
+                let _ = give_back.send(buffer);
+            }
+        });
+        SinkThread {
+            queue,
+            depth,
+            when_full,
+            returned,
+            spare: Vec::new(),
+            worker: Some(worker),
+            dropped: 0,
+        }
+    }
+
+    fn buffer(&mut self) -> Option<T> {
+        self.spare.pop().or_else(|| self.returned.try_recv().ok())
+    }
+
+    fn send(&mut self, buffer: T) {
+        let (lock, ready) = &*self.queue;
+        // The sink panicked; its thread is gone and there is nobody left to show anything to.
+        let Ok(mut queue) = lock.lock() else { return };
+        if queue.buffers.len() < self.depth {
+            queue.buffers.push_back(buffer);
+        } else {
+            self.dropped += 1;
+            match self.when_full {
+                WhenFull::DropOldest => {
+                    self.spare.extend(queue.buffers.pop_front());
+                    queue.buffers.push_back(buffer);
+                }
+                WhenFull::DropNewest => self.spare.push(buffer),
+            }
+        }
+        ready.notify_one();
+    }
+}
+
+impl SinkThread<Frame> {
+    pub fn video<S: VideoSink + Send + 'static>(mut sink: S) -> Self {
+        SinkThread::spawn(VIDEO_QUEUE, WhenFull::DropOldest, move |frame| sink.frame(frame))
+    }
+}
+
+impl SinkThread<Vec<i16>> {
+    pub fn audio<S: AudioSink + Send + 'static>(mut sink: S) -> Self {
+        SinkThread::spawn(AUDIO_QUEUE, WhenFull::DropNewest, move |samples: &Vec<i16>| sink.samples(samples))
+    }
+}
+
+impl VideoSink for SinkThread<Frame> {
+    fn frame(&mut self, frame: &Frame) {
+        let mut buffer = self.buffer().unwrap_or_else(|| Frame { data: Vec::new() });
+        buffer.data.clone_from(&frame.data);
+        self.send(buffer);
+    }
+}
+
+impl AudioSink for SinkThread<Vec<i16>> {
+    fn samples(&mut self, samples: &[i16]) {
+        let mut buffer = self.buffer().unwrap_or_default();
+        buffer.clear();
+        buffer.extend_from_slice(samples);
+        self.send(buffer);
+    }
+}
+
+impl<T: Send + 'static> Drop for SinkThread<T> {
+    fn drop(&mut self) {
+        // Closing the queue ends the worker's loop once it has emptied it.
+        let (lock, ready) = &*self.queue;
+        if let Ok(mut queue) = lock.lock() {
+            queue.closed = true;
+        }
+        ready.notify_one();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::emulator::Emulator;
+
+    #[derive(Default)]
+    struct Recorder {
+        frames: u32,
+        samples: usize,
+    }
+
+    impl VideoSink for Recorder {
+        fn frame(&mut self, frame: &Frame) {
+            assert!(frame.data.len() >= 256 * 240);
+            self.frames += 1;
+        }
+    }
+
+    impl AudioSink for Recorder {
+        fn samples(&mut self, samples: &[i16]) {
+            self.samples += samples.len();
+        }
+    }
+
+    #[test]
+    fn test_host_is_driven_once_per_frame() {
+        let mut emulator = Emulator::new(create_test_cartridge());
+        let mut input = [JoypadButton::START, JoypadButton::BUTTON_A, JoypadButton::empty(), JoypadButton::empty()];
+        let (mut video, mut audio) = (Recorder::default(), Recorder::default());
+        for _ in 0..3 {
+            emulator.run_frame_with_host(&mut input, &mut video, &mut audio);
+        }
+        assert_eq!((video.frames, audio.samples), (3, 3 * SAMPLES_PER_FRAME));
+        assert_eq!(emulator.cpu.bus.joypad.button_status, JoypadButton::START);
+        assert_eq!(emulator.cpu.bus.joypad2.button_status, JoypadButton::BUTTON_A);
+
+        emulator.run_frame_with_host(&mut (), &mut (), &mut ());
+        assert_eq!(emulator.cpu.bus.joypad.button_status, JoypadButton::empty());
+    }
+
+    // Hands what it gets back to the test thread.
+    struct Forward(std::sync::mpsc::Sender<usize>);
+
+    impl VideoSink for Forward {
+        fn frame(&mut self, frame: &Frame) {
+            self.0.send(frame.data.len()).unwrap();
+        }
+    }
+
+    impl AudioSink for Forward {
+        fn samples(&mut self, samples: &[i16]) {
+            self.0.send(samples.len()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sink_threads_get_or_drop_every_frame() {
+        let mut emulator = Emulator::new(create_test_cartridge());
+        let (video_sent, video_got) = channel();
+        let (audio_sent, audio_got) = channel();
+        let mut video = SinkThread::video(Forward(video_sent));
+        let mut audio = SinkThread::audio(Forward(audio_sent));
+        for _ in 0..20 {
+            emulator.run_frame_with_host(&mut (), &mut video, &mut audio);
+        }
+        let (video_dropped, audio_dropped) = (video.dropped, audio.dropped);
+        drop((video, audio));
+
+        let frames: Vec<usize> = video_got.try_iter().collect();
+        assert_eq!(frames.len() as u64 + video_dropped, 20);
+        assert!(frames.iter().all(|&len| len == emulator.frame.data.len()));
+        let sounds: Vec<usize> = audio_got.try_iter().collect();
+        assert_eq!(sounds.len() as u64 + audio_dropped, 20);
+        assert!(sounds.iter().all(|&len| len == SAMPLES_PER_FRAME));
+    }
+
+    // Shows frames slowly, passing on the red of the first pixel of each.
+    struct Slow(std::sync::mpsc::Sender<f32>);
+
+    impl VideoSink for Slow {
+        fn frame(&mut self, frame: &Frame) {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            self.0.send(frame.data[0].r).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_lagging_video_sink_gets_the_newest_frame() {
+        let (shown, got) = channel();
+        let mut video = SinkThread::video(Slow(shown));
+        let mut frame = Frame::new();
+        for i in 0..10 {
+            frame.data[0].r = i as f32;
+            video.frame(&frame);
+        }
+        let dropped = video.dropped;
+        drop(video);
+
+        let frames: Vec<f32> = got.try_iter().collect();
+        assert!(dropped > 0);
+        assert_eq!(frames.len() as u64 + dropped, 10);
+        assert_eq!(frames.last(), Some(&9.0));
+    }
+}
@@ -0,0 +1,153 @@
+//! Implementation of the Family BASIC keyboard on the Famicom's expansion port ($4016/$4017)
+//! Reference: https://www.nesdev.org/wiki/Family_BASIC_Keyboard
+//!
+//! The 72 keys sit in a matrix of 9 rows by 2 columns of 4 keys. Writes to $4016 pick the row and
+//! column: bit 0 goes back to row 0, bit 1 selects the column, and the row moves on each time bit 1
+//! goes from 1 to 0. Bit 2 turns the matrix on; while it is off, reads return 0, which is how games
+//! tell whether a keyboard is there. $4017 bits 1-4 then read the selected keys, 0 for held.
+
+use crate::savestate::{StateReader, StateWriter};
+
+pub const ROWS: usize = 9;
+
+// Key names by row, column and $4017 bit, from bit 1 up.
+const MATRIX: [[[&str; 4]; 2]; ROWS] = [
+    [["F8", "RETURN", "[", "]"], ["KANA", "RSHIFT", "¥", "STOP"]],
+    [["F7", "@", ":", ";"], ["_", "/", "-", "^"]],
+    [["F6", "O", "L", "K"], [".", ",", "P", "0"]],
+    [["F5", "I", "U", "J"], ["M", "N", "9", "8"]],
+    [["F4", "Y", "G", "H"], ["B", "V", "7", "6"]],
+    [["F3", "T", "R", "D"], ["F", "C", "5", "4"]],
+    [["F2", "W", "S", "A"], ["X", "Z", "E", "3"]],
+    [["F1", "ESC", "Q", "CTR"], ["LSHIFT", "GRPH", "1", "2"]],
+    [["CLR HOME", "UP", "RIGHT", "LEFT"], ["DOWN", "SPACE", "DEL", "INS"]],
+];
+
+// Bits 1-4 of $4017.
+const KEY_BITS: u8 = 0b1_1110;
+
+// A key's place in the matrix: its row, its column, and which of $4017's bits 1-4 (0 to 3) it
+// drives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub row: u8,
+    pub column: u8,
+    pub bit: u8,
+}
+
+impl Key {
+    // The key labelled `name` ("A", "RETURN", "CLR HOME", ...).
+    pub fn named(name: &str) -> Option<Key> {
+        MATRIX.iter().enumerate().find_map(|(row, columns)| {
+            columns.iter().enumerate().find_map(|(column, keys)| {
+                keys.iter().position(|key| *key == name).map(|bit| Key {
+                    row: row as u8,
+                    column: column as u8,
+                    bit: bit as u8,
+                })
+            })
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        MATRIX[self.row as usize][self.column as usize][self.bit as usize]
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FamilyKeyboard {
+    // Keys held, as $4017 bits 1-4 set for each row and column.
+    held: [[u8; 2]; ROWS],
+    enabled: bool,
+    row: u8,
+    column: u8,
+}
+
+impl FamilyKeyboard {
+    pub fn new() -> Self {
+        FamilyKeyboard::default()
+    }
+
+    // Replaces the keys held with `keys`.
+    pub fn set_keys(&mut self, keys: &[Key]) {
+        self.held = [[0; 2]; ROWS];
+        for key in keys {
+            self.held[key.row as usize][key.column as usize] |= 2 << key.bit;
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        let column = (data >> 1) & 1;
+        if data & 1 == 1 {
+            self.row = 0;
+        } else if self.column == 1 && column == 0 {
+            self.row = self.row.saturating_add(1);
+        }
+        self.column = column;
+        self.enabled = data & 0b100 != 0;
+    }
+
+    // Bits 1-4 of a $4017 read. Past the last row nothing is held.
+    pub fn read(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let held = self.held.get(self.row as usize).map_or(0, |columns| columns[self.column as usize]);
+        !held & KEY_BITS
+    }
+
+    // Which row and column are selected. The keys held are input, not state, and are left alone.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.enabled);
+        state.u8(self.row);
+        state.u8(self.column);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.enabled = state.bool()?;
+        self.row = state.u8()?;
+        self.column = state.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Scans the matrix the way Family BASIC does and returns what each row and column read.
+    fn scan(keyboard: &mut FamilyKeyboard) -> Vec<u8> {
+        let mut reads = Vec::new();
+        keyboard.write(0b101);
+        for _ in 0..ROWS {
+            keyboard.write(0b100);
+            reads.push(keyboard.read());
+            keyboard.write(0b110);
+            reads.push(keyboard.read());
+        }
+        reads
+    }
+
+    #[test]
+    fn test_keyboard_scan() {
+        let a = Key::named("A").unwrap();
+        assert_eq!(a, Key { row: 6, column: 0, bit: 3 });
+        assert_eq!(Key::named("CLR HOME").unwrap().name(), "CLR HOME");
+        assert_eq!(Key::named("a"), None);
+
+        let mut keyboard = FamilyKeyboard::new();
+        keyboard.set_keys(&[a, Key::named("RETURN").unwrap(), Key::named("INS").unwrap()]);
+        let reads = scan(&mut keyboard);
+        let mut expected = vec![KEY_BITS; ROWS * 2];
+        expected[0] = KEY_BITS & !(1 << 2);
+        expected[12] = KEY_BITS & !(1 << 4);
+        expected[17] = KEY_BITS & !(1 << 4);
+        assert_eq!(reads, expected);
+
+        // One row past the end, then with the matrix off.
+        keyboard.write(0b100);
+        assert_eq!(keyboard.read(), KEY_BITS);
+        keyboard.write(0);
+        assert_eq!(keyboard.read(), 0);
+    }
+}
@@ -0,0 +1,96 @@
+//! Implementation of the NES Four Score adapter ($4016/$4017)
+//! Reference: https://www.nesdev.org/wiki/Four_player_adapters
+//!
+//! Each port shifts out 24 bits: the controller plugged into it (1 or 2), then the extra
+//! controller behind it (3 or 4), then a signature games use to detect the adapter.
+
+use crate::joypad::JoypadButton;
+use crate::savestate::{StateReader, StateWriter};
+
+// Bits 16-23 of each port, in read order: $4016 reads 0,0,0,1,0,0,0,0 and $4017 reads 0,0,1,0,0,0,0,0.
+const SIGNATURES: [u8; 2] = [1 << 3, 1 << 2];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FourScore {
+    // Buttons held on controllers 3 and 4.
+    pub button_status: [JoypadButton; 2],
+    strobe: bool,
+    // Number of bits read from each port since the last strobe.
+    read_index: [u8; 2],
+}
+
+impl FourScore {
+    pub fn new() -> Self {
+        FourScore::default()
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.read_index = [0, 0];
+        }
+    }
+
+    // Reads the next bit of `port` (0 for $4016, 1 for $4017). `first` is the state of the
+    // controller plugged directly into that port.
+    pub fn read(&mut self, port: usize, first: JoypadButton) -> u8 {
+        let index = self.read_index[port];
+        let bit = match index {
+            0..=7 => first.bits() >> index,
+            8..=15 => self.button_status[port].bits() >> (index - 8),
+            16..=23 => SIGNATURES[port] >> (index - 16),
+            _ => 1,
+        } & 1;
+
+        if !self.strobe && index < 24 {
+            self.read_index[port] += 1;
+        }
+        bit
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.strobe);
+        state.bytes(&self.read_index);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.strobe = state.bool()?;
+        state.bytes(&mut self.read_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_bits(four_score: &mut FourScore, port: usize, first: JoypadButton) -> Vec<u8> {
+        (0..26).map(|_| four_score.read(port, first)).collect()
+    }
+
+    #[test]
+    fn test_four_score_sequence() {
+        let mut four_score = FourScore::new();
+        four_score.button_status = [JoypadButton::BUTTON_B, JoypadButton::RIGHT];
+        four_score.write(1);
+        four_score.write(0);
+
+        let port1 = read_bits(&mut four_score, 0, JoypadButton::BUTTON_A);
+        assert_eq!(port1[0..8], [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(port1[8..16], [0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(port1[16..24], [0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(port1[24..], [1, 1]);
+
+        let port2 = read_bits(&mut four_score, 1, JoypadButton::empty());
+        assert_eq!(port2[0..8], [0; 8]);
+        assert_eq!(port2[8..16], [0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(port2[16..24], [0, 0, 1, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_strobe_holds_first_bit() {
+        let mut four_score = FourScore::new();
+        four_score.write(1);
+        assert_eq!(four_score.read(0, JoypadButton::BUTTON_A), 1);
+        assert_eq!(four_score.read(0, JoypadButton::BUTTON_A), 1);
+    }
+}
@@ -0,0 +1,92 @@
+//! Implementation of controller input ($4016)
+//! Reference: https://www.nesdev.org/wiki/Standard_controller
+
+pub mod family_keyboard;
+pub mod four_score;
+pub mod power_pad;
+pub mod turbo;
+pub mod zapper;
+
+use crate::savestate::{StateReader, StateWriter};
+use power_pad::PowerPad;
+use zapper::Zapper;
+
+bitflags! {
+    // https://wiki.nesdev.com/w/index.php/Controller_reading_code
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct JoypadButton: u8 {
+        const RIGHT             = 1 << 7;
+        const LEFT              = 1 << 6;
+        const DOWN              = 1 << 5;
+        const UP                = 1 << 4;
+        const START             = 1 << 3;
+        const SELECT            = 1 << 2;
+        const BUTTON_B          = 1 << 1;
+        const BUTTON_A          = 1 << 0;
+    }
+}
+
+// Device plugged into controller port 2 ($4017).
+pub enum Port2Device {
+    // Standard controller, `Bus::joypad2`.
+    Joypad,
+    Zapper(Zapper),
+    PowerPad(PowerPad),
+}
+
+#[derive(Clone, Copy)]
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    pub button_status: JoypadButton,
+}
+ 
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            button_index: 0,
+            button_status: JoypadButton::from_bits_truncate(0),
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        // Set strobe to last bit of data.
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        // Extracts the button_index-th bit.
+        let response = (self.button_status.bits() & (1 << self.button_index)) >> self.button_index;
+        if !self.strobe && self.button_index <= 7 {
+            self.button_index += 1;
+        }
+        response
+    }
+
+    // The shift register. The buttons held are input, not state, and are left alone.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.strobe);
+        state.u8(self.button_index);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.strobe = state.bool()?;
+        self.button_index = state.u8()?;
+        Ok(())
+    }
+}
+
+
@@ -0,0 +1,86 @@
+//! Implementation of the Power Pad (Family Trainer) mat on controller port 2 ($4017)
+//! Reference: https://www.nesdev.org/wiki/Power_Pad
+//!
+//! The mat has 12 buttons, numbered 1 to 12 along its rows on side B:
+//!
+//! | 1 | 2  | 3  | 4  |
+//! | 5 | 6  | 7  | 8  |
+//! | 9 | 10 | 11 | 12 |
+//!
+//! Side A, with 8 buttons, is the same mat turned over, so games read the same numbers. Two shift
+//! registers, strobed by $4016 like a controller's, shift out at once: $4017 bit 3 reads buttons 2,
+//! 1, 5, 9, 6, 10, 11 and 7, bit 4 reads 4, 3, 12 and 8 then four 1s, and both read 1 after that.
+
+use crate::savestate::{StateReader, StateWriter};
+
+const BIT3_ORDER: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const BIT4_ORDER: [u8; 4] = [4, 3, 12, 8];
+
+pub const BUTTONS: usize = 12;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PowerPad {
+    // Buttons stood on, bit n - 1 for button n.
+    pub buttons: u16,
+    strobe: bool,
+    read_index: u8,
+}
+
+impl PowerPad {
+    pub fn new() -> Self {
+        PowerPad::default()
+    }
+
+    pub fn held(&self, button: u8) -> bool {
+        self.buttons & (1 << (button - 1)) != 0
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.read_index = 0;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let index = self.read_index as usize;
+        let bit3 = BIT3_ORDER.get(index).is_none_or(|button| self.held(*button));
+        let bit4 = BIT4_ORDER.get(index).is_none_or(|button| self.held(*button));
+        if !self.strobe && index < BIT3_ORDER.len() {
+            self.read_index += 1;
+        }
+        (bit3 as u8) << 3 | (bit4 as u8) << 4
+    }
+
+    // The shift registers. The buttons held are input, not state, and are left alone.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.strobe);
+        state.u8(self.read_index);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.strobe = state.bool()?;
+        self.read_index = state.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_power_pad_sequence() {
+        let mut power_pad = PowerPad::new();
+        // Buttons 1, 8 and 11.
+        power_pad.buttons = 1 | 1 << 7 | 1 << 10;
+        power_pad.write(1);
+        power_pad.write(0);
+
+        let reads: Vec<u8> = (0..10).map(|_| power_pad.read()).collect();
+        let bit3: Vec<u8> = reads.iter().map(|read| read >> 3 & 1).collect();
+        let bit4: Vec<u8> = reads.iter().map(|read| read >> 4 & 1).collect();
+        assert_eq!(bit3, [0, 1, 0, 0, 0, 0, 1, 0, 1, 1]);
+        assert_eq!(bit4, [0, 0, 0, 1, 1, 1, 1, 1, 1, 1]);
+    }
+}
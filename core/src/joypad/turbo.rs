@@ -0,0 +1,87 @@
+//! Turbo (auto-fire) buttons.
+//!
+//! Turbo state advances once per emulated frame rather than on wall-clock time, so the buttons the
+//! game sees depend only on the frame number and what is held. This keeps recorded input replayable.
+
+use crate::joypad::JoypadButton;
+
+// Frames each on/off phase lasts. 2 gives 15 presses per second at 60 FPS.
+pub const DEFAULT_TURBO_PERIOD: u8 = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Turbo {
+    pub period: u8,
+    // Frames since a turbo button was first held.
+    frame: u32,
+}
+
+impl Default for Turbo {
+    fn default() -> Self {
+        Self::new(DEFAULT_TURBO_PERIOD)
+    }
+}
+
+impl Turbo {
+    pub fn new(period: u8) -> Self {
+        Turbo {
+            period: period.max(1),
+            frame: 0,
+        }
+    }
+
+    // Combines normally held buttons with turbo-held buttons for the next frame. Turbo buttons
+    // start pressed so a quick tap still registers.
+    pub fn next_frame(&mut self, held: JoypadButton, turbo_held: JoypadButton) -> JoypadButton {
+        if turbo_held.is_empty() {
+            self.frame = 0;
+            return held;
+        }
+
+        let on = (self.frame / self.period.max(1) as u32).is_multiple_of(2);
+        self.frame = self.frame.wrapping_add(1);
+
+        if on {
+            held | turbo_held
+        } else {
+            held
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turbo_toggles_every_period() {
+        let mut turbo = Turbo::new(2);
+        let pressed: Vec<bool> = (0..8)
+            .map(|_| {
+                turbo
+                    .next_frame(JoypadButton::empty(), JoypadButton::BUTTON_A)
+                    .contains(JoypadButton::BUTTON_A)
+            })
+            .collect();
+        assert_eq!(pressed, [true, true, false, false, true, true, false, false]);
+    }
+
+    #[test]
+    fn test_turbo_keeps_held_buttons_and_restarts_on_release() {
+        let mut turbo = Turbo::new(1);
+        assert_eq!(
+            turbo.next_frame(JoypadButton::UP, JoypadButton::BUTTON_B),
+            JoypadButton::UP | JoypadButton::BUTTON_B
+        );
+        assert_eq!(
+            turbo.next_frame(JoypadButton::UP, JoypadButton::BUTTON_B),
+            JoypadButton::UP
+        );
+
+        // Releasing turbo resets the phase so the next press fires immediately.
+        turbo.next_frame(JoypadButton::empty(), JoypadButton::empty());
+        assert_eq!(
+            turbo.next_frame(JoypadButton::empty(), JoypadButton::BUTTON_B),
+            JoypadButton::BUTTON_B
+        );
+    }
+}
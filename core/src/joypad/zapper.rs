@@ -0,0 +1,159 @@
+//! Implementation of the Zapper light gun on controller port 2 ($4017)
+//! Reference: https://www.nesdev.org/wiki/Zapper
+//!
+//! The photodiode only sees light while the CRT beam is near it, so the light bit reports the
+//! brightness under the cursor only during the scanlines just after the beam has passed it.
+
+use crate::ppu::PPU;
+use crate::render::constants::{NES_PIXEL_HEIGHT, NES_PIXEL_WIDTH};
+use crate::render::frame::Frame;
+
+// Bits of $4017 driven by the Zapper.
+const LIGHT_NOT_DETECTED: u8 = 1 << 3;
+const TRIGGER_PULLED: u8 = 1 << 4;
+
+// Number of scanlines the sensor stays lit after the beam passes the cursor.
+pub const LIGHT_SCANLINES: u16 = 20;
+// Radius (in NES pixels) of the area the sensor sees around the cursor.
+const SENSOR_RADIUS: i32 = 2;
+// Average luminance (0.0 - 1.0) needed to register as light.
+const BRIGHTNESS_THRESHOLD: f32 = 0.5;
+
+pub struct Zapper {
+    // Cursor position in NES pixels. Positions outside the screen never see light.
+    pub x: i32,
+    pub y: i32,
+    pub trigger: bool,
+
+    frame: Frame,
+    // PPU frame the current brightness sample was taken from.
+    sampled_frame: Option<u64>,
+    brightness: f32,
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper {
+            x: -1,
+            y: -1,
+            trigger: false,
+            frame: Frame::new(),
+            sampled_frame: None,
+            brightness: 0.0,
+        }
+    }
+
+    pub fn on_screen(&self) -> bool {
+        (0..NES_PIXEL_WIDTH).contains(&self.x) && (0..NES_PIXEL_HEIGHT).contains(&self.y)
+    }
+
+    pub fn read(&mut self, ppu: &PPU) -> u8 {
+        let mut data = 0;
+        if self.trigger {
+            data |= TRIGGER_PULLED;
+        }
+        if !self.light_detected(ppu) {
+            data |= LIGHT_NOT_DETECTED;
+        }
+        data
+    }
+
+    fn light_detected(&mut self, ppu: &PPU) -> bool {
+        if !self.on_screen() {
+            return false;
+        }
+
+        let beam_passed = ppu.scanline >= self.y as u16 && ppu.scanline < self.y as u16 + LIGHT_SCANLINES;
+        if !beam_passed {
+            return false;
+        }
+
+        // Games change the screen between frames (e.g. Duck Hunt's white target boxes), so the
+        // frame is rendered from the current PPU state at most once per frame.
+        if self.sampled_frame != Some(ppu.frame_count) {
+            Frame::render(ppu, &mut self.frame);
+            self.brightness = self.sample_brightness();
+            self.sampled_frame = Some(ppu.frame_count);
+        }
+
+        self.brightness >= BRIGHTNESS_THRESHOLD
+    }
+
+    // Average luminance of the pixels the sensor sees.
+    fn sample_brightness(&self) -> f32 {
+        let mut total = 0.0;
+        let mut count = 0;
+        for y in (self.y - SENSOR_RADIUS)..=(self.y + SENSOR_RADIUS) {
+            for x in (self.x - SENSOR_RADIUS)..=(self.x + SENSOR_RADIUS) {
+                if (0..NES_PIXEL_WIDTH).contains(&x) && (0..NES_PIXEL_HEIGHT).contains(&y) {
+                    let color = self.frame.data[(y * NES_PIXEL_WIDTH + x) as usize];
+                    total += 0.299 * color.r + 0.587 * color.g + 0.114 * color.b;
+                    count += 1;
+                }
+            }
+        }
+        if count == 0 {
+            0.0
+        } else {
+            total / count as f32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Mirroring;
+
+    // A PPU whose whole screen is the universal background color.
+    fn solid_ppu(color: u8) -> PPU {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.palette_table[0] = color;
+        ppu
+    }
+
+    #[test]
+    fn test_trigger_bit() {
+        let mut zapper = Zapper::new();
+        let ppu = solid_ppu(0x0d);
+        assert_eq!(zapper.read(&ppu) & TRIGGER_PULLED, 0);
+        zapper.trigger = true;
+        assert_eq!(zapper.read(&ppu) & TRIGGER_PULLED, TRIGGER_PULLED);
+    }
+
+    #[test]
+    fn test_light_only_after_beam_passes_cursor() {
+        let mut zapper = Zapper::new();
+        zapper.x = 128;
+        zapper.y = 100;
+
+        let mut ppu = solid_ppu(0x30);
+        ppu.scanline = 50;
+        assert_eq!(zapper.read(&ppu) & LIGHT_NOT_DETECTED, LIGHT_NOT_DETECTED);
+        ppu.scanline = 105;
+        assert_eq!(zapper.read(&ppu) & LIGHT_NOT_DETECTED, 0);
+        ppu.scanline = 100 + LIGHT_SCANLINES;
+        assert_eq!(zapper.read(&ppu) & LIGHT_NOT_DETECTED, LIGHT_NOT_DETECTED);
+    }
+
+    #[test]
+    fn test_dark_screen_and_off_screen_see_no_light() {
+        let mut zapper = Zapper::new();
+        zapper.x = 128;
+        zapper.y = 100;
+        let mut ppu = solid_ppu(0x0d);
+        ppu.scanline = 105;
+        assert_eq!(zapper.read(&ppu) & LIGHT_NOT_DETECTED, LIGHT_NOT_DETECTED);
+
+        let mut ppu = solid_ppu(0x30);
+        ppu.scanline = 105;
+        zapper.x = -1;
+        assert_eq!(zapper.read(&ppu) & LIGHT_NOT_DETECTED, LIGHT_NOT_DETECTED);
+    }
+}
@@ -0,0 +1,46 @@
+//! The emulated console, with no window, audio or host input: the CPU, PPU, bus, cartridges, the
+//! Famicom Disk System, the VS System and controllers, plus save states, movies, cheats, frame
+//! streaming, the debugger and headless test runners. The `nes_rs` frontend, the libretro core, the
+//! C API and the Python module are all built on it.
+//!
+//! Diagnostics go through `tracing`, with one target per subsystem so they can be filtered
+//! separately: `cpu`, `ppu`, `mapper` and `debugger`, plus `apu`, whose only event is a trace of
+//! each write to the sound registers being ignored, since there is no APU yet. Frames run inside a
+//! `frame` span (target `frame`, level info) carrying the frame number, which tags the events
+//! logged during it. Nothing is printed unless the program using the core installs a subscriber, as
+//! the `nes_rs` frontend does for `--log`.
+
+pub mod accuracy;
+#[cfg(feature = "achievements")]
+pub mod achievements;
+pub mod asm;
+pub mod bench;
+pub mod bus;
+pub mod cartridge;
+pub mod cheat;
+pub mod chips;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod emulator;
+pub mod fcg;
+pub mod fds;
+pub mod host;
+pub mod movie;
+pub mod netplay;
+pub mod ppu;
+pub mod region;
+pub mod render;
+pub mod savestate;
+pub mod stream;
+pub mod testrom;
+pub mod vs_system;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod joypad;
+
+#[macro_use]
+extern crate lazy_static;
+
+#[macro_use]
+extern crate bitflags;
@@ -0,0 +1,383 @@
+//! TAS editor: a movie whose input can be edited frame by frame while it plays.
+//!
+//! The editor owns the console's input. It keeps a save state every `GREENZONE_INTERVAL` frames
+//! of the run with the current input (the "greenzone"), so an edit only throws away the states
+//! after the edited frame and re-simulates from the last state before it, and seeking anywhere
+//! already played is a state load and a few frames.
+//!
+//! Markers label frames, and up to `BRANCHES` branches keep other versions of the input to go back
+//! to. A movie saved as `run.nmv` keeps its markers in `run.nmv.markers`, one "<frame> <note>" per
+//! line, and branch 3 in `run.nmv.branch3`, which is a movie itself.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::emulator::Emulator;
+use crate::joypad::JoypadButton;
+use crate::movie::{FrameInput, Movie};
+
+pub const GREENZONE_INTERVAL: usize = 10;
+// About 2000 states of a few kB each. Past that, the ones furthest from the cursor go first.
+pub const MAX_GREENZONE: usize = 2000;
+pub const BRANCHES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub inputs: Vec<FrameInput>,
+    // Where the cursor was when the branch was saved.
+    pub frame: usize,
+}
+
+pub struct Editor {
+    movie: Movie,
+    // Frames run with the current input: the console is at the start of this frame.
+    frame: usize,
+    // States at the start of frames, each made with the input as it is now.
+    greenzone: BTreeMap<usize, Vec<u8>>,
+    markers: BTreeMap<usize, String>,
+    branches: [Option<Branch>; BRANCHES],
+}
+
+impl Editor {
+    // Takes over `emulator`'s input, power cycling it and stopping any movie, to edit `movie` from
+    // the first frame.
+    pub fn new(emulator: &mut Emulator, movie: Movie) -> Result<Self, String> {
+        if movie.rom_hash != emulator.cartridge().hash() {
+            return Err("Movie was recorded on a different ROM".to_string());
+        }
+        emulator.stop_movie();
+        emulator.set_four_score(movie.four_score);
+        emulator.power_on();
+        Ok(Editor {
+            movie,
+            frame: 0,
+            greenzone: BTreeMap::from([(0, emulator.save_state())]),
+            markers: BTreeMap::new(),
+            branches: Default::default(),
+        })
+    }
+
+    // Opens the movie at `path` with its markers and branches, or starts a new one if there is
+    // no file there yet.
+    pub fn open(emulator: &mut Emulator, path: &str) -> Result<Self, String> {
+        if !Path::new(path).exists() {
+            let mut movie = Movie::new(emulator.cartridge().hash());
+            movie.four_score = emulator.cpu.bus.four_score.is_some();
+            return Editor::new(emulator, movie);
+        }
+        let mut editor = Editor::new(emulator, Movie::load(path)?)?;
+        if let Ok(text) = std::fs::read_to_string(format!("{}.markers", path)) {
+            for line in text.lines() {
+                let (frame, note) = line.split_once(' ').unwrap_or((line, ""));
+                let frame = frame.parse().map_err(|_| format!("Bad marker \"{}\" in {}.markers", line, path))?;
+                editor.markers.insert(frame, note.to_string());
+            }
+        }
+        for (slot, branch) in editor.branches.iter_mut().enumerate() {
+            let branch_path = format!("{}.branch{}", path, slot);
+            if Path::new(&branch_path).exists() {
+                let inputs = Movie::load(&branch_path)?.inputs;
+                *branch = Some(Branch { frame: inputs.len(), inputs });
+            }
+        }
+        Ok(editor)
+    }
+
+    // Writes the movie to `path`, and its markers and branches next to it.
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let write_error = |e: std::io::Error| format!("Could not write {}: {}", path, e);
+        self.movie.save(path).map_err(write_error)?;
+        let markers: String = self.markers.iter().map(|(frame, note)| format!("{} {}\n", frame, note)).collect();
+        std::fs::write(format!("{}.markers", path), markers).map_err(write_error)?;
+        for (slot, branch) in self.branches.iter().enumerate() {
+            let branch_path = format!("{}.branch{}", path, slot);
+            match branch {
+                Some(branch) => {
+                    let movie = Movie {
+                        rom_hash: self.movie.rom_hash,
+                        four_score: self.movie.four_score,
+                        inputs: branch.inputs.clone(),
+                    };
+                    movie.save(&branch_path).map_err(write_error)?;
+                }
+                None => {
+                    let _ = std::fs::remove_file(&branch_path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn len(&self) -> usize {
+        self.movie.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.movie.is_empty()
+    }
+
+    // The frame the console is about to run.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    // Nothing is pressed on frames past the end of the movie.
+    pub fn input(&self, frame: usize) -> FrameInput {
+        self.movie.inputs.get(frame).copied().unwrap_or_default()
+    }
+
+    // Whether `frame` has been run with the input as it is now, so seeking to it is quick.
+    pub fn is_green(&self, frame: usize) -> bool {
+        frame <= self.frame || self.greenzone.range(frame..).next().is_some()
+    }
+
+    // Runs the frame at the cursor. `live` is input from the controllers, which replaces the
+    // movie's for the frame when recording; otherwise the movie's input is played.
+    pub fn advance(&mut self, emulator: &mut Emulator, live: Option<FrameInput>) -> Result<(), String> {
+        let frame = self.frame;
+        if let Some(input) = live.filter(|&input| input != self.input(frame) || frame >= self.len()) {
+            self.edit(emulator, frame, |inputs| inputs[frame] = input)?;
+        }
+        self.run_frame(emulator);
+        Ok(())
+    }
+
+    // Moves the cursor to `frame`, from the nearest greenzone state before it, or by running on
+    // from the cursor if that is nearer.
+    pub fn seek(&mut self, emulator: &mut Emulator, frame: usize) -> Result<(), String> {
+        let (&start, state) = self.greenzone.range(..=frame).next_back().expect("frame 0 is always green");
+        if self.frame > frame || self.frame < start {
+            emulator.load_state(state)?;
+            self.frame = start;
+        }
+        while self.frame < frame {
+            self.run_frame(emulator);
+        }
+        Ok(())
+    }
+
+    // Sets the buttons of controller `port` on `frame`, lengthening the movie if it is past the
+    // end.
+    pub fn set_buttons(&mut self, emulator: &mut Emulator, frame: usize, port: usize, buttons: JoypadButton) -> Result<(), String> {
+        self.edit(emulator, frame, |inputs| inputs[frame][port] = buttons)
+    }
+
+    pub fn toggle(&mut self, emulator: &mut Emulator, frame: usize, port: usize, button: JoypadButton) -> Result<(), String> {
+        self.set_buttons(emulator, frame, port, self.input(frame)[port] ^ button)
+    }
+
+    // Inserts `count` frames with nothing pressed before `frame`. Markers after it move with their
+    // frames.
+    pub fn insert_frames(&mut self, emulator: &mut Emulator, frame: usize, count: usize) -> Result<(), String> {
+        self.markers = std::mem::take(&mut self.markers)
+            .into_iter()
+            .map(|(marker, note)| (if marker >= frame { marker + count } else { marker }, note))
+            .collect();
+        self.edit(emulator, frame, |inputs| {
+            inputs.splice(frame..frame, std::iter::repeat_n(FrameInput::default(), count));
+        })
+    }
+
+    // Deletes up to `count` frames from `frame` on, with their markers.
+    pub fn delete_frames(&mut self, emulator: &mut Emulator, frame: usize, count: usize) -> Result<(), String> {
+        if frame >= self.len() {
+            return Ok(());
+        }
+        let end = (frame + count).min(self.len());
+        self.markers = std::mem::take(&mut self.markers)
+            .into_iter()
+            .filter(|(marker, _)| !(frame..end).contains(marker))
+            .map(|(marker, note)| (if marker >= end { marker - (end - frame) } else { marker }, note))
+            .collect();
+        self.edit(emulator, frame, |inputs| {
+            inputs.drain(frame..end);
+        })
+    }
+
+    pub fn markers(&self) -> &BTreeMap<usize, String> {
+        &self.markers
+    }
+
+    pub fn set_marker(&mut self, frame: usize, note: &str) {
+        self.markers.insert(frame, note.to_string());
+    }
+
+    pub fn remove_marker(&mut self, frame: usize) {
+        self.markers.remove(&frame);
+    }
+
+    // The nearest marker after, or before, the cursor.
+    pub fn next_marker(&self) -> Option<usize> {
+        self.markers.range(self.frame + 1..).next().map(|(&frame, _)| frame)
+    }
+
+    pub fn previous_marker(&self) -> Option<usize> {
+        self.markers.range(..self.frame).next_back().map(|(&frame, _)| frame)
+    }
+
+    pub fn branch(&self, slot: usize) -> Option<&Branch> {
+        self.branches[slot].as_ref()
+    }
+
+    // Keeps the input as it is now in branch `slot`, replacing what was there.
+    pub fn save_branch(&mut self, slot: usize) {
+        self.branches[slot] = Some(Branch {
+            inputs: self.movie.inputs.clone(),
+            frame: self.frame,
+        });
+    }
+
+    // Switches to the input in branch `slot` and seeks to where it was saved. Only what comes
+    // after the first frame the two differ on is re-simulated.
+    pub fn load_branch(&mut self, emulator: &mut Emulator, slot: usize) -> Result<(), String> {
+        let Some(branch) = self.branches[slot].clone() else {
+            return Err(format!("Branch {} is empty", slot));
+        };
+        if branch.inputs != self.movie.inputs {
+            let same = self.movie.inputs.iter().zip(&branch.inputs).take_while(|(a, b)| a == b).count();
+            self.edit(emulator, same, |inputs| *inputs = branch.inputs.clone())?;
+        }
+        self.seek(emulator, branch.frame)
+    }
+
+    // Changes the input from `frame` on, with the movie made long enough to hold it, and drops
+    // the states that depended on the old input. If the console has already run past `frame`, it
+    // goes back to the last state before it and runs the new input up to the cursor again.
+    fn edit<F: FnOnce(&mut Vec<FrameInput>)>(&mut self, emulator: &mut Emulator, frame: usize, change: F) -> Result<(), String> {
+        if self.movie.inputs.len() <= frame {
+            self.movie.inputs.resize(frame + 1, FrameInput::default());
+        }
+        change(&mut self.movie.inputs);
+        self.greenzone.split_off(&(frame + 1));
+        if self.frame > frame {
+            let cursor = self.frame;
+            let (&start, state) = self.greenzone.range(..=frame).next_back().expect("frame 0 is always green");
+            emulator.load_state(state)?;
+            self.frame = start;
+            self.seek(emulator, cursor)?;
+        }
+        Ok(())
+    }
+
+    fn run_frame(&mut self, emulator: &mut Emulator) {
+        for (port, buttons) in self.input(self.frame).into_iter().enumerate() {
+            emulator.set_buttons(port, buttons);
+        }
+        emulator.run_frame();
+        self.frame += 1;
+        if self.frame.is_multiple_of(GREENZONE_INTERVAL) {
+            self.greenzone.insert(self.frame, emulator.save_state());
+            if self.greenzone.len() > MAX_GREENZONE {
+                let cursor = self.frame;
+                let furthest = self.greenzone.keys().skip(1).max_by_key(|frame| frame.abs_diff(cursor)).copied();
+                if let Some(frame) = furthest {
+                    self.greenzone.remove(&frame);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    fn nestest() -> Emulator {
+        Emulator::new(Cartridge::new(&std::fs::read("tests/nestest/nestest.nes").unwrap()).unwrap())
+    }
+
+    fn input(buttons: JoypadButton) -> FrameInput {
+        [buttons, JoypadButton::empty(), JoypadButton::empty(), JoypadButton::empty()]
+    }
+
+    // What a console that played `inputs` from power-on looks like.
+    fn played(inputs: &[FrameInput]) -> Vec<u8> {
+        let mut emulator = nestest();
+        for frame in inputs {
+            for (port, buttons) in frame.iter().enumerate() {
+                emulator.set_buttons(port, *buttons);
+            }
+            emulator.run_frame();
+        }
+        emulator.save_state()
+    }
+
+    #[test]
+    fn test_edits_resimulate_from_the_edit() {
+        let mut emulator = nestest();
+        let movie = Movie::new(emulator.cartridge().hash());
+        let mut editor = Editor::new(&mut emulator, movie).unwrap();
+        for frame in 0..45 {
+            let buttons = if frame % 8 < 2 { JoypadButton::DOWN } else { JoypadButton::empty() };
+            editor.advance(&mut emulator, Some(input(buttons))).unwrap();
+        }
+        assert_eq!(editor.len(), 45);
+        assert_eq!(emulator.save_state(), played(&editor.movie().inputs));
+
+        editor.toggle(&mut emulator, 25, 0, JoypadButton::START).unwrap();
+        assert_eq!(editor.frame(), 45);
+        assert_eq!(editor.greenzone.keys().copied().collect::<Vec<_>>(), [0, 10, 20, 30, 40]);
+        assert_eq!(emulator.save_state(), played(&editor.movie().inputs));
+
+        editor.seek(&mut emulator, 12).unwrap();
+        assert_eq!(emulator.save_state(), played(&editor.movie().inputs[..12]));
+        assert!(editor.is_green(40));
+        assert!(!editor.is_green(41));
+    }
+
+    #[test]
+    fn test_branches_and_markers() {
+        let mut emulator = nestest();
+        let movie = Movie::new(emulator.cartridge().hash());
+        let mut editor = Editor::new(&mut emulator, movie).unwrap();
+        for _ in 0..30 {
+            editor.advance(&mut emulator, Some(input(JoypadButton::empty()))).unwrap();
+        }
+        editor.set_marker(5, "Start");
+        editor.set_marker(20, "Menu");
+        editor.save_branch(1);
+        let saved = emulator.save_state();
+
+        editor.set_buttons(&mut emulator, 10, 0, JoypadButton::SELECT).unwrap();
+        editor.insert_frames(&mut emulator, 8, 3).unwrap();
+        assert_eq!(editor.len(), 33);
+        assert_eq!(editor.input(13)[0], JoypadButton::SELECT);
+        assert_eq!(editor.markers().keys().copied().collect::<Vec<_>>(), [5, 23]);
+        editor.delete_frames(&mut emulator, 4, 2).unwrap();
+        assert_eq!(editor.markers().keys().copied().collect::<Vec<_>>(), [21]);
+        assert_eq!(editor.previous_marker(), Some(21));
+        assert_eq!(editor.next_marker(), None);
+
+        editor.load_branch(&mut emulator, 1).unwrap();
+        assert_eq!(editor.frame(), 30);
+        assert_eq!(emulator.save_state(), saved);
+        assert!(editor.load_branch(&mut emulator, 2).is_err());
+    }
+
+    #[test]
+    fn test_save_and_open() {
+        let dir = std::env::temp_dir().join(format!("nes_rs_tas_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("run.nmv").to_string_lossy().into_owned();
+
+        let mut emulator = nestest();
+        let mut editor = Editor::open(&mut emulator, &path).unwrap();
+        assert!(editor.is_empty());
+        editor.set_buttons(&mut emulator, 3, 0, JoypadButton::BUTTON_A).unwrap();
+        editor.set_marker(3, "Jump here");
+        editor.save_branch(4);
+        editor.save(&path).unwrap();
+
+        let reopened = Editor::open(&mut emulator, &path).unwrap();
+        assert_eq!(reopened.movie(), editor.movie());
+        assert_eq!(reopened.markers(), editor.markers());
+        assert_eq!(reopened.branch(4).map(|branch| &branch.inputs), Some(&editor.movie().inputs));
+        assert!(reopened.branch(0).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
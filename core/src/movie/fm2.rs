@@ -0,0 +1,301 @@
+//! FCEUX's FM2 movie format.
+//! Reference: https://fceux.com/web/FM2.html
+//!
+//! An FM2 file is a list of `key value` header lines followed by one input line per frame:
+//! `|commands|port0|port1|port2|`, where each gamepad is written as "RLDUTSBA" with '.' for
+//! released buttons. Four Score movies have four gamepad fields before port2 instead of two.
+//! Only the text format with standard gamepads is supported.
+
+use crate::cartridge::Cartridge;
+use crate::joypad::JoypadButton;
+use crate::movie::{FrameInput, Movie};
+
+// Gamepad columns, left to right. Column i is bit 7 - i of `JoypadButton`.
+const BUTTON_CHARS: [char; 8] = ['R', 'L', 'D', 'U', 'T', 'S', 'B', 'A'];
+
+// FCEUX input device numbers for the port0/port1 header keys.
+const SI_NONE: &str = "0";
+const SI_GAMEPAD: &str = "1";
+
+impl Movie {
+    pub fn to_fm2(&self, cartridge: &Cartridge, rom_filename: &str) -> String {
+        let checksum = rom_checksum(cartridge);
+        let mut fm2 = String::new();
+        fm2.push_str("version 3\n");
+        fm2.push_str("emuVersion 22020\n");
+        fm2.push_str("rerecordCount 0\n");
+        fm2.push_str("palFlag 0\n");
+        fm2.push_str(&format!("romFilename {}\n", rom_filename));
+        fm2.push_str(&format!("romChecksum base64:{}\n", base64_encode(&checksum)));
+        fm2.push_str(&format!("guid {}\n", guid(&checksum)));
+        fm2.push_str(&format!("fourscore {}\n", self.four_score as u8));
+        fm2.push_str("microphone 0\n");
+        // The port0/port1 devices are ignored when a Four Score is used.
+        let device = if self.four_score { SI_NONE } else { SI_GAMEPAD };
+        fm2.push_str(&format!("port0 {}\n", device));
+        fm2.push_str(&format!("port1 {}\n", device));
+        fm2.push_str("port2 0\n");
+        fm2.push_str("FDS 0\n");
+        fm2.push_str("NewPPU 0\n");
+
+        let ports = if self.four_score { 4 } else { 2 };
+        for input in &self.inputs {
+            fm2.push_str("|0|");
+            for buttons in &input[..ports] {
+                fm2.push_str(&encode_gamepad(*buttons));
+                fm2.push('|');
+            }
+            fm2.push_str("|\n");
+        }
+        fm2
+    }
+
+    // Parses an FM2 movie recorded on `cartridge`.
+    pub fn from_fm2(text: &str, cartridge: &Cartridge) -> Result<Movie, String> {
+        let mut movie = Movie::new(cartridge.hash());
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.starts_with('|') {
+                let first_frame = movie.inputs.is_empty();
+                movie.inputs.push(parse_input_line(line, first_frame, movie.four_score)
+                    .map_err(|e| format!("Line {}: {}", line_number + 1, e))?);
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "binary" if value == "1" => return Err("Binary FM2 movies are not supported".to_string()),
+                "fourscore" => movie.four_score = value == "1",
+                "FDS" if value != "0" => return Err("FDS movies are not supported".to_string()),
+                "port0" | "port1" if value != SI_GAMEPAD && value != SI_NONE => {
+                    return Err(format!("Unsupported input device {} on {}", value, key))
+                }
+                "romChecksum" => {
+                    let expected = value.strip_prefix("base64:").and_then(base64_decode);
+                    if expected.as_deref() != Some(&rom_checksum(cartridge)[..]) {
+                        return Err("Movie was recorded on a different ROM".to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(movie)
+    }
+}
+
+fn encode_gamepad(buttons: JoypadButton) -> String {
+    BUTTON_CHARS
+        .iter()
+        .enumerate()
+        .map(|(i, c)| if buttons.bits() & (1 << (7 - i)) != 0 { *c } else { '.' })
+        .collect()
+}
+
+fn decode_gamepad(field: &str) -> Result<JoypadButton, String> {
+    // An empty field means nothing is plugged in.
+    if field.is_empty() {
+        return Ok(JoypadButton::empty());
+    }
+    if field.chars().count() != 8 {
+        return Err(format!("Malformed gamepad field \"{}\"", field));
+    }
+    let mut bits = 0;
+    for (i, c) in field.chars().enumerate() {
+        if c != '.' && c != ' ' {
+            bits |= 1 << (7 - i);
+        }
+    }
+    Ok(JoypadButton::from_bits_truncate(bits))
+}
+
+fn parse_input_line(line: &str, first_frame: bool, four_score: bool) -> Result<FrameInput, String> {
+    let ports = if four_score { 4 } else { 2 };
+    let fields: Vec<&str> = line.split('|').collect();
+    if fields.len() < ports + 2 {
+        return Err("Malformed input line".to_string());
+    }
+
+    // Movies always start from power-on, so a reset on the first frame is a no-op.
+    let commands: u8 = fields[1].parse().map_err(|_| "Malformed command field".to_string())?;
+    if commands != 0 && !first_frame {
+        return Err("Resets during a movie are not supported".to_string());
+    }
+
+    let mut input = FrameInput::default();
+    for (buttons, field) in input.iter_mut().zip(&fields[2..ports + 2]) {
+        *buttons = decode_gamepad(field)?;
+    }
+    Ok(input)
+}
+
+// FCEUX identifies iNES games by the MD5 of their PRG ROM followed by their CHR ROM.
+fn rom_checksum(cartridge: &Cartridge) -> [u8; 16] {
+    let mut data = cartridge.prg_rom.clone();
+    data.extend_from_slice(&cartridge.chr_rom);
+    md5(&data)
+}
+
+// FCEUX only needs the GUID to be unique per movie, so it is derived from the checksum.
+fn guid(checksum: &[u8; 16]) -> String {
+    let hex: String = checksum.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in text.bytes().filter(|c| *c != b'=') {
+        let value = BASE64_ALPHABET.iter().position(|a| *a == c)? as u32;
+        buffer = buffer << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+// Reference: RFC 1321
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+        5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+        4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+        6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+    let constants: Vec<u32> = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32)
+        .collect();
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in message.chunks_exact(64) {
+        let words: Vec<u32> = block
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect();
+
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(constants[i])
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[i]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+
+    let mut digest = [0; 16];
+    for (i, word) in state.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test::create_test_cartridge;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_md5_and_base64() {
+        assert_eq!(hex(&md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(&md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(base64_encode(b"Man"), "TWFu");
+        assert_eq!(base64_encode(b"Ma"), "TWE=");
+        assert_eq!(base64_decode("TWE=").unwrap(), b"Ma");
+    }
+
+    #[test]
+    fn test_gamepad_columns() {
+        assert_eq!(encode_gamepad(JoypadButton::RIGHT | JoypadButton::BUTTON_A), "R......A");
+        assert_eq!(
+            decode_gamepad("...UT...").unwrap(),
+            JoypadButton::UP | JoypadButton::START
+        );
+        assert!(decode_gamepad("RL").is_err());
+    }
+
+    #[test]
+    fn test_fm2_round_trip() {
+        let cartridge = create_test_cartridge();
+        let mut movie = Movie::new(cartridge.hash());
+        movie.inputs.push(FrameInput::default());
+        movie.inputs.push([JoypadButton::BUTTON_B | JoypadButton::LEFT, JoypadButton::SELECT, JoypadButton::empty(), JoypadButton::empty()]);
+
+        let fm2 = movie.to_fm2(&cartridge, "test.nes");
+        assert!(fm2.contains("|0|.L....B.|.....S..||\n"));
+        assert_eq!(Movie::from_fm2(&fm2, &cartridge), Ok(movie));
+    }
+
+    #[test]
+    fn test_fm2_four_score_round_trip() {
+        let cartridge = create_test_cartridge();
+        let mut movie = Movie::new(cartridge.hash());
+        movie.four_score = true;
+        movie.inputs.push([JoypadButton::BUTTON_A, JoypadButton::empty(), JoypadButton::UP, JoypadButton::START]);
+
+        let fm2 = movie.to_fm2(&cartridge, "test.nes");
+        assert!(fm2.contains("fourscore 1\n"));
+        assert!(fm2.contains("|0|.......A|........|...U....|....T...||\n"));
+        assert_eq!(Movie::from_fm2(&fm2, &cartridge), Ok(movie));
+    }
+
+    #[test]
+    fn test_fm2_rejects_unsupported_movies() {
+        let cartridge = create_test_cartridge();
+        let movie = Movie::new(cartridge.hash()).to_fm2(&cartridge, "test.nes");
+
+        let wrong_rom = movie.replace("romChecksum base64:", "romChecksum base64:AAAA");
+        assert!(Movie::from_fm2(&wrong_rom, &cartridge).is_err());
+        assert!(Movie::from_fm2(&movie.replace("port1 1", "port1 2"), &cartridge).is_err());
+        assert!(Movie::from_fm2("|0|........|........||\n|1|........|........||", &cartridge).is_err());
+    }
+}
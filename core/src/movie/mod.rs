@@ -0,0 +1,180 @@
+//! Input movies.
+//!
+//! A movie is the controller state for every frame since power-on. The emulator has no other
+//! source of nondeterminism, so replaying the inputs from power-on reproduces the run exactly.
+//!
+//! File layout (little endian):
+//! | magic "NMV\x1a" | version u8 | ROM hash u64 | flags u8 | frame count u32 | per frame: one u8 per port |
+//!
+//! Bit 0 of flags marks a Four Score movie, which stores 4 ports per frame instead of 2. Version 1
+//! files have no flags byte and always store 2 ports.
+
+use crate::joypad::JoypadButton;
+
+pub mod editor;
+pub mod fm2;
+
+const MOVIE_MAGIC: [u8; 4] = [0x4E, 0x4D, 0x56, 0x1A];
+const MOVIE_VERSION: u8 = 2;
+const FLAG_FOUR_SCORE: u8 = 1 << 0;
+
+// Buttons held on controllers 1-4 for one frame. Controllers 3 and 4 need a Four Score.
+pub type FrameInput = [JoypadButton; 4];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    // `Cartridge::hash` of the game the movie was recorded on.
+    pub rom_hash: u64,
+    pub four_score: bool,
+    pub inputs: Vec<FrameInput>,
+}
+
+impl Movie {
+    pub fn new(rom_hash: u64) -> Self {
+        Movie {
+            rom_hash,
+            four_score: false,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    fn ports(&self) -> usize {
+        if self.four_score {
+            4
+        } else {
+            2
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(18 + self.inputs.len() * self.ports());
+        bytes.extend_from_slice(&MOVIE_MAGIC);
+        bytes.push(MOVIE_VERSION);
+        bytes.extend_from_slice(&self.rom_hash.to_le_bytes());
+        bytes.push(if self.four_score { FLAG_FOUR_SCORE } else { 0 });
+        bytes.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        for input in &self.inputs {
+            for buttons in &input[..self.ports()] {
+                bytes.push(buttons.bits());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Result<Movie, String> {
+        if raw.len() < 5 || raw[0..4] != MOVIE_MAGIC {
+            return Err("File is not a movie".to_string());
+        }
+
+        let version = raw[4];
+        let header_size = match version {
+            1 => 17,
+            2 => 18,
+            _ => return Err(format!("Unsupported movie version {}", version)),
+        };
+        if raw.len() < header_size {
+            return Err("Movie is truncated".to_string());
+        }
+
+        let rom_hash = u64::from_le_bytes(raw[5..13].try_into().unwrap());
+        let flags = if version == 1 { 0 } else { raw[13] };
+        let frames = u32::from_le_bytes(raw[header_size - 4..header_size].try_into().unwrap()) as usize;
+
+        let mut movie = Movie {
+            rom_hash,
+            four_score: flags & FLAG_FOUR_SCORE != 0,
+            inputs: Vec::with_capacity(frames),
+        };
+
+        let ports = movie.ports();
+        let data = &raw[header_size..];
+        if data.len() != frames * ports {
+            return Err("Movie is truncated".to_string());
+        }
+        for frame in data.chunks_exact(ports) {
+            let mut input = FrameInput::default();
+            for (buttons, bits) in input.iter_mut().zip(frame) {
+                *buttons = JoypadButton::from_bits_truncate(*bits);
+            }
+            movie.inputs.push(input);
+        }
+
+        Ok(movie)
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn load(path: &str) -> Result<Movie, String> {
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        Movie::from_bytes(&bytes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum MovieState {
+    #[default]
+    Inactive,
+    Recording(Movie),
+    // `frame` is the index of the next input to play back.
+    Playing { movie: Movie, frame: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_movie_round_trip() {
+        let mut movie = Movie::new(0x1234_5678_9abc_def0);
+        movie.inputs.push([JoypadButton::BUTTON_A, JoypadButton::empty(), JoypadButton::empty(), JoypadButton::empty()]);
+        movie.inputs.push([JoypadButton::UP | JoypadButton::START, JoypadButton::LEFT, JoypadButton::empty(), JoypadButton::empty()]);
+
+        let bytes = movie.to_bytes();
+        assert_eq!(bytes.len(), 18 + 4);
+        assert_eq!(Movie::from_bytes(&bytes), Ok(movie));
+    }
+
+    #[test]
+    fn test_four_score_movie_round_trip() {
+        let mut movie = Movie::new(7);
+        movie.four_score = true;
+        movie.inputs.push([JoypadButton::BUTTON_A, JoypadButton::UP, JoypadButton::DOWN, JoypadButton::SELECT]);
+
+        let bytes = movie.to_bytes();
+        assert_eq!(bytes.len(), 18 + 4);
+        assert_eq!(Movie::from_bytes(&bytes), Ok(movie));
+    }
+
+    #[test]
+    fn test_loads_version_1_movies() {
+        let mut bytes = vec![0x4E, 0x4D, 0x56, 0x1A, 1];
+        bytes.extend_from_slice(&42u64.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&[0x01, 0x80]);
+
+        let movie = Movie::from_bytes(&bytes).unwrap();
+        assert_eq!(movie.rom_hash, 42);
+        assert!(!movie.four_score);
+        assert_eq!(movie.inputs, [[JoypadButton::BUTTON_A, JoypadButton::RIGHT, JoypadButton::empty(), JoypadButton::empty()]]);
+    }
+
+    #[test]
+    fn test_movie_rejects_bad_input() {
+        assert!(Movie::from_bytes(&[0; 3]).is_err());
+        assert!(Movie::from_bytes(&[0; 18]).is_err());
+
+        let mut bytes = Movie::new(0).to_bytes();
+        bytes[14] = 5;
+        assert_eq!(Movie::from_bytes(&bytes), Err("Movie is truncated".to_string()));
+    }
+}
@@ -0,0 +1,471 @@
+//! Two-player netplay over UDP, in lockstep.
+//!
+//! Both consoles run the same game from power-on and only exchange controller inputs: `start`
+//! power cycles each side's console once the players connect, whatever it was doing before. Each
+//! player's input is scheduled `delay` frames ahead and sent to the other, and a frame only runs
+//! once both inputs for it are in, so the two consoles see identical inputs on identical frames
+//! and stay in step. With a delay longer than the one-way latency in frames, neither side ever
+//! waits. The host drives controller 1 and the player who joins controller 2.
+//!
+//! Every `HASH_INTERVAL` frames each side sends a hash of its save state. A hash that differs from
+//! the peer's for the same frame means the consoles have diverged (a desync), which is reported
+//! rather than fixed.
+//!
+//! Datagrams can be lost or reordered: every input the peer hasn't acknowledged is sent again
+//! with each datagram, and the session is polled once per host frame.
+
+pub mod protocol;
+
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::accuracy::Accuracy;
+use crate::emulator::Emulator;
+use crate::joypad::JoypadButton;
+use protocol::{Message, VERSION};
+
+// Frames between a button press and the frame it takes effect on. 2 frames hide about 30 ms of
+// one-way latency.
+pub const DEFAULT_DELAY: u64 = 2;
+
+// Frames between state hashes.
+pub const HASH_INTERVAL: u64 = 60;
+
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+// Largest datagram we expect. Input messages are the largest, at 18 bytes plus the inputs.
+const MAX_DATAGRAM: usize = 512;
+
+// What the two consoles must agree on for the same inputs to play out the same way: the game, and
+// the settings that change how it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Setup {
+    // The game's `Cartridge::hash`.
+    pub rom_hash: u64,
+    // Overclocked scanlines per frame, as `Emulator::overclock` has them.
+    pub overclock: u16,
+    pub accuracy: Accuracy,
+}
+
+impl Setup {
+    pub fn of(emulator: &Emulator) -> Setup {
+        Setup {
+            rom_hash: emulator.cartridge().hash(),
+            overclock: emulator.overclock(),
+            accuracy: emulator.cpu.bus.accuracy,
+        }
+    }
+
+    fn accuracy_bits(&self) -> u8 {
+        self.accuracy.dummy_accesses as u8 | (self.accuracy.dma_timing as u8) << 1
+    }
+
+    fn hello(&self) -> Message {
+        Message::Hello {
+            version: VERSION,
+            rom_hash: self.rom_hash,
+            overclock: self.overclock,
+            accuracy: self.accuracy_bits(),
+        }
+    }
+}
+
+pub struct Session {
+    socket: UdpSocket,
+    // Where the peer is. The host learns it from the first Hello.
+    peer: Option<SocketAddr>,
+    // The controller this side drives: 0 for the host, 1 for the player who joined.
+    pub player: usize,
+    pub delay: u64,
+    setup: Setup,
+    // Whether the peer has said Hello, so inputs can flow.
+    connected: bool,
+    // Whether `start` has power cycled the console since connecting.
+    started: bool,
+    // The next frame to run.
+    frame: u64,
+    // Inputs by frame. Local ones are kept until the peer acknowledges them, remote ones until
+    // their frame runs.
+    local: BTreeMap<u64, JoypadButton>,
+    remote: BTreeMap<u64, JoypadButton>,
+    // The next frame the peer needs from us.
+    peer_ack: u64,
+    local_hashes: BTreeMap<u64, u64>,
+    remote_hashes: BTreeMap<u64, u64>,
+    // The first frame whose hashes differed.
+    desync: Option<u64>,
+    clock: Instant,
+    last_ping: Option<Instant>,
+    rtt: Option<Duration>,
+}
+
+// FNV-1a, as for ROM hashes.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+impl Session {
+    fn new(socket: UdpSocket, peer: Option<SocketAddr>, player: usize, setup: Setup) -> Result<Session, String> {
+        socket.set_nonblocking(true).map_err(|e| format!("Could not set up netplay: {}", e))?;
+        Ok(Session {
+            socket,
+            peer,
+            player,
+            delay: DEFAULT_DELAY,
+            setup,
+            connected: false,
+            started: false,
+            frame: 0,
+            local: BTreeMap::new(),
+            remote: BTreeMap::new(),
+            peer_ack: 0,
+            local_hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            desync: None,
+            clock: Instant::now(),
+            last_ping: None,
+            rtt: None,
+        })
+    }
+
+    // Waits on UDP `port` for a player to join, as player 1. A player with another game, or other
+    // overclocking or accuracy settings, is turned away.
+    pub fn host(port: u16, setup: Setup) -> Result<Session, String> {
+        let socket = UdpSocket::bind(("0.0.0.0", port)).map_err(|e| format!("Could not listen on port {}: {}", port, e))?;
+        Session::new(socket, None, 0, setup)
+    }
+
+    // Joins the game hosted at `addr` ("host:port") as player 2.
+    pub fn join(addr: &str, setup: Setup) -> Result<Session, String> {
+        let peer = addr
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| format!("Could not find {}", addr))?;
+        let local: SocketAddr = match peer {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local).map_err(|e| format!("Could not set up netplay: {}", e))?;
+        Session::new(socket, Some(peer), 1, setup)
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.socket.local_addr().map_err(|e| e.to_string())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    // The next frame to run.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // The last measured round trip time.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    // The first frame the consoles were seen to differ after, if they have.
+    pub fn desync(&self) -> Option<u64> {
+        self.desync
+    }
+
+    fn send(&self, message: &Message) -> Result<(), String> {
+        let Some(peer) = self.peer else {
+            return Ok(());
+        };
+        match self.socket.send_to(&message.encode(), peer) {
+            Ok(_) => Ok(()),
+            // A full buffer is as good as a lost datagram: it'll be sent again.
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(format!("Netplay connection lost: {}", e)),
+        }
+    }
+
+    fn send_inputs(&self) -> Result<(), String> {
+        let inputs: Vec<u8> = self.local.range(self.peer_ack..).map(|(_, buttons)| buttons.bits()).collect();
+        self.send(&Message::Input {
+            ack: self.frame,
+            first: self.peer_ack,
+            inputs,
+        })
+    }
+
+    fn receive(&mut self, message: Message, from: SocketAddr) -> Result<(), String> {
+        match message {
+            Message::Hello { version, rom_hash, overclock, accuracy } => {
+                if version != VERSION {
+                    return Err(format!("The other player runs netplay version {}, and this is {}", version, VERSION));
+                }
+                if rom_hash != self.setup.rom_hash {
+                    return Err("The other player is running a different game".to_string());
+                }
+                if overclock != self.setup.overclock {
+                    return Err(format!(
+                        "The other player overclocks by {} scanlines, and this by {}",
+                        overclock, self.setup.overclock
+                    ));
+                }
+                if accuracy != self.setup.accuracy_bits() {
+                    return Err("The other player has another accuracy profile".to_string());
+                }
+                if self.peer.is_none() {
+                    self.peer = Some(from);
+                }
+                if !self.connected {
+                    self.connected = true;
+                    // The joiner keeps saying Hello until the host answers.
+                    self.send(&self.setup.hello())?;
+                }
+            }
+            Message::Input { ack, first, inputs } => {
+                self.peer_ack = self.peer_ack.max(ack);
+                for (frame, bits) in (first..).zip(inputs) {
+                    if frame >= self.frame {
+                        self.remote.insert(frame, JoypadButton::from_bits_truncate(bits));
+                    }
+                }
+            }
+            Message::Hash { frame, hash } => {
+                self.remote_hashes.insert(frame, hash);
+            }
+            Message::Ping { sent } => self.send(&Message::Pong { sent })?,
+            Message::Pong { sent } => {
+                let now = self.clock.elapsed().as_micros() as u64;
+                self.rtt = Some(Duration::from_micros(now.saturating_sub(sent)));
+            }
+        }
+        Ok(())
+    }
+
+    // Handles everything the peer has sent and sends what it is waiting for. Call once per host
+    // frame, whether or not a frame runs. Errors end the session.
+    pub fn poll(&mut self) -> Result<(), String> {
+        let mut buffer = [0; MAX_DATAGRAM];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, from)) => {
+                    // Stray datagrams from anyone but the peer are ignored, as are garbled ones.
+                    if self.peer.is_some_and(|peer| peer != from) {
+                        continue;
+                    }
+                    if let Ok(message) = Message::decode(&buffer[..len]) {
+                        self.receive(message, from)?;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // Windows reports an ICMP port unreachable from an earlier send this way. The
+                // peer may just not be up yet.
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(format!("Netplay connection lost: {}", e)),
+            }
+        }
+
+        if !self.connected {
+            return self.send(&self.setup.hello());
+        }
+        if self.last_ping.is_none_or(|last| last.elapsed() >= PING_INTERVAL) {
+            self.last_ping = Some(Instant::now());
+            self.send(&Message::Ping { sent: self.clock.elapsed().as_micros() as u64 })?;
+        }
+        self.check_hashes();
+        self.send_inputs()
+    }
+
+    // Power cycles `emulator` the first time it is called after the peer connects, so both
+    // consoles start from the same state. Call after each `poll`.
+    pub fn start(&mut self, emulator: &mut Emulator) {
+        if self.connected && !self.started {
+            self.started = true;
+            emulator.power_on();
+        }
+    }
+
+    // Schedules `local` (this player's buttons now) and returns the buttons of both controllers
+    // for the next frame, if both are in. None means wait: run no frame and try again after the
+    // next `poll` and `start`.
+    pub fn next_inputs(&mut self, local: JoypadButton) -> Option<[JoypadButton; 2]> {
+        if !self.started {
+            return None;
+        }
+        let scheduled = self.frame + self.delay;
+        if let Entry::Vacant(entry) = self.local.entry(scheduled) {
+            entry.insert(local);
+            // Best effort: `poll` sends again if this is lost.
+            let _ = self.send_inputs();
+        }
+
+        // Nobody pressed anything on the frames before the first scheduled input.
+        let input = |inputs: &BTreeMap<u64, JoypadButton>| match self.frame < self.delay {
+            true => Some(JoypadButton::empty()),
+            false => inputs.get(&self.frame).copied(),
+        };
+        let (mine, theirs) = (input(&self.local)?, input(&self.remote)?);
+        let mut buttons = [theirs; 2];
+        buttons[self.player] = mine;
+        Some(buttons)
+    }
+
+    // Call after running the frame `next_inputs` gave inputs for.
+    pub fn frame_done(&mut self, emulator: &Emulator) {
+        self.remote.remove(&self.frame);
+        self.local.retain(|frame, _| *frame >= self.peer_ack);
+        self.frame += 1;
+        if self.frame.is_multiple_of(HASH_INTERVAL) {
+            let state_hash = hash(&emulator.save_state());
+            self.local_hashes.insert(self.frame, state_hash);
+            let _ = self.send(&Message::Hash { frame: self.frame, hash: state_hash });
+        }
+        self.check_hashes();
+    }
+
+    fn check_hashes(&mut self) {
+        let matched: Vec<u64> = self
+            .local_hashes
+            .iter()
+            .filter_map(|(frame, hash)| self.remote_hashes.get(frame).map(|remote| (*frame, remote == hash)))
+            .map(|(frame, same)| {
+                if !same && self.desync.is_none() {
+                    self.desync = Some(frame);
+                }
+                frame
+            })
+            .collect();
+        for frame in matched {
+            self.local_hashes.remove(&frame);
+            self.remote_hashes.remove(&frame);
+        }
+        // The peer's hash for these was lost.
+        let frame = self.frame;
+        self.local_hashes.retain(|hashed, _| hashed + 10 * HASH_INTERVAL > frame);
+    }
+
+    // One line for the OSD: who we are, the latency and any desync.
+    pub fn status(&self) -> String {
+        if !self.connected {
+            return match self.player {
+                0 => format!("Netplay: waiting for player 2 on port {}", self.local_addr().map_or(0, |addr| addr.port())),
+                _ => "Netplay: connecting".to_string(),
+            };
+        }
+        let mut status = format!("Netplay P{}", self.player + 1);
+        if let Some(rtt) = self.rtt {
+            status += &format!(" {} ms", rtt.as_millis());
+        }
+        if let Some(frame) = self.desync {
+            status += &format!(" DESYNC at frame {}", frame);
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    fn nestest() -> Cartridge {
+        Cartridge::new(&std::fs::read("tests/nestest/nestest.nes").unwrap()).unwrap()
+    }
+
+    fn connect(setup: Setup) -> (Session, Session) {
+        let host = Session::host(0, setup).unwrap();
+        let addr = format!("127.0.0.1:{}", host.local_addr().unwrap().port());
+        (host, Session::join(&addr, setup).unwrap())
+    }
+
+    fn setup(rom_hash: u64) -> Setup {
+        Setup { rom_hash, overclock: 0, accuracy: Accuracy::default() }
+    }
+
+    // Runs both sides for `frames` frames, with each player pressing something different.
+    // `tamper` runs on player 2's console just before `frame`.
+    fn play(frames: u64, tamper: Option<u64>) -> (Session, Session, Emulator, Emulator) {
+        let (mut host, mut guest) = connect(setup(nestest().hash()));
+        let (mut a, mut b) = (Emulator::new(nestest()), Emulator::new(nestest()));
+        // Player 2's console has been running before connecting. `start` evens that out.
+        for _ in 0..10 {
+            b.run_frame();
+        }
+        let mut rounds = 0;
+        while host.frame() < frames || guest.frame() < frames {
+            rounds += 1;
+            assert!(rounds < 100 * frames, "Netplay stalled at frames {} and {}", host.frame(), guest.frame());
+            host.poll().unwrap();
+            guest.poll().unwrap();
+            host.start(&mut a);
+            guest.start(&mut b);
+            let press = |frame: u64, button: JoypadButton| match frame % 7 < 3 {
+                true => button,
+                false => JoypadButton::empty(),
+            };
+            if host.frame() < frames {
+                if let Some(buttons) = host.next_inputs(press(host.frame(), JoypadButton::START)) {
+                    a.set_buttons(0, buttons[0]);
+                    a.set_buttons(1, buttons[1]);
+                    a.run_frame();
+                    host.frame_done(&a);
+                }
+            }
+            if guest.frame() < frames {
+                if let Some(buttons) = guest.next_inputs(press(guest.frame(), JoypadButton::BUTTON_A)) {
+                    if tamper == Some(guest.frame()) {
+                        b.cpu.bus.poke(0x0700, 0x55);
+                    }
+                    b.set_buttons(0, buttons[0]);
+                    b.set_buttons(1, buttons[1]);
+                    b.run_frame();
+                    guest.frame_done(&b);
+                }
+            }
+        }
+        // Let the last hashes arrive.
+        for _ in 0..10 {
+            host.poll().unwrap();
+            guest.poll().unwrap();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        (host, guest, a, b)
+    }
+
+    #[test]
+    fn test_lockstep_stays_in_sync() {
+        let (host, guest, a, b) = play(2 * HASH_INTERVAL, None);
+        assert_eq!(a.save_state(), b.save_state());
+        assert_eq!((host.desync(), guest.desync()), (None, None));
+        assert!(host.status().starts_with("Netplay P1"));
+        assert!(guest.status().starts_with("Netplay P2"));
+    }
+
+    #[test]
+    fn test_detects_desync() {
+        let (host, guest, _, _) = play(3 * HASH_INTERVAL, Some(HASH_INTERVAL + 5));
+        assert_eq!(host.desync(), Some(2 * HASH_INTERVAL));
+        assert_eq!(guest.desync(), Some(2 * HASH_INTERVAL));
+        assert!(host.status().ends_with("DESYNC at frame 120"));
+    }
+
+    #[test]
+    fn test_refuses_another_game_or_settings() {
+        let refusal = |guest_setup: Setup| {
+            let mut host = Session::host(0, setup(1)).unwrap();
+            let addr = format!("127.0.0.1:{}", host.local_addr().unwrap().port());
+            let mut guest = Session::join(&addr, guest_setup).unwrap();
+            guest.poll().unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+            host.poll().unwrap_err()
+        };
+        assert_eq!(refusal(setup(2)), "The other player is running a different game");
+        assert_eq!(
+            refusal(Setup { overclock: 100, ..setup(1) }),
+            "The other player overclocks by 100 scanlines, and this by 0"
+        );
+        let accurate = crate::accuracy::AccuracyProfile::Accurate.settings();
+        assert_eq!(refusal(Setup { accuracy: accurate, ..setup(1) }), "The other player has another accuracy profile");
+    }
+}
@@ -0,0 +1,159 @@
+// Netplay datagrams. Each is a tag byte followed by little-endian fields.
+
+// Bumped whenever a message changes, so mismatched builds refuse to play instead of desyncing.
+pub const VERSION: u8 = 2;
+
+// Most inputs one datagram carries. Far more than are ever unacknowledged at once.
+pub const MAX_INPUTS: usize = 255;
+
+const HELLO: u8 = 0;
+const INPUT: u8 = 1;
+const HASH: u8 = 2;
+const PING: u8 = 3;
+const PONG: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    // Sent until the peer answers, to connect and to check both sides run the same game the same
+    // way: with as many overclocked scanlines, and the same accuracy switches, one per bit.
+    Hello { version: u8, rom_hash: u64, overclock: u16, accuracy: u8 },
+    // The sender's inputs for `first` onwards, and the next frame it needs from the receiver.
+    // Everything the receiver hasn't acknowledged is sent again each time, so a lost datagram
+    // costs nothing but the wait for the next one.
+    Input { ack: u64, first: u64, inputs: Vec<u8> },
+    // A hash of the sender's state after running `frame` frames, to catch desyncs.
+    Hash { frame: u64, hash: u64 },
+    // Round trip timing. `sent` is in the sender's clock and only means anything to it.
+    Ping { sent: u64 },
+    Pong { sent: u64 },
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Message::Hello { version, rom_hash, overclock, accuracy } => {
+                bytes.push(HELLO);
+                bytes.push(*version);
+                bytes.extend_from_slice(&rom_hash.to_le_bytes());
+                bytes.extend_from_slice(&overclock.to_le_bytes());
+                bytes.push(*accuracy);
+            }
+            Message::Input { ack, first, inputs } => {
+                bytes.push(INPUT);
+                bytes.extend_from_slice(&ack.to_le_bytes());
+                bytes.extend_from_slice(&first.to_le_bytes());
+                bytes.push(inputs.len().min(MAX_INPUTS) as u8);
+                bytes.extend_from_slice(&inputs[..inputs.len().min(MAX_INPUTS)]);
+            }
+            Message::Hash { frame, hash } => {
+                bytes.push(HASH);
+                bytes.extend_from_slice(&frame.to_le_bytes());
+                bytes.extend_from_slice(&hash.to_le_bytes());
+            }
+            Message::Ping { sent } => {
+                bytes.push(PING);
+                bytes.extend_from_slice(&sent.to_le_bytes());
+            }
+            Message::Pong { sent } => {
+                bytes.push(PONG);
+                bytes.extend_from_slice(&sent.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Message, String> {
+        let mut reader = Reader { bytes, position: 0 };
+        let message = match reader.u8()? {
+            HELLO => {
+                let version = reader.u8()?;
+                let rom_hash = reader.u64()?;
+                // Version 1 ended there. Its Hello still decodes, to be turned away by version.
+                let (overclock, accuracy) = match version {
+                    1 => (0, 0),
+                    _ => (reader.u16()?, reader.u8()?),
+                };
+                Message::Hello { version, rom_hash, overclock, accuracy }
+            }
+            INPUT => {
+                let ack = reader.u64()?;
+                let first = reader.u64()?;
+                let count = reader.u8()? as usize;
+                Message::Input {
+                    ack,
+                    first,
+                    inputs: reader.take(count)?.to_vec(),
+                }
+            }
+            HASH => Message::Hash {
+                frame: reader.u64()?,
+                hash: reader.u64()?,
+            },
+            PING => Message::Ping { sent: reader.u64()? },
+            PONG => Message::Pong { sent: reader.u64()? },
+            tag => return Err(format!("Unknown netplay message {}", tag)),
+        };
+        match reader.position == bytes.len() {
+            true => Ok(message),
+            false => Err("Netplay message is too long".to_string()),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or("Netplay message is truncated")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let messages = [
+            Message::Hello { version: VERSION, rom_hash: 0x0123_4567_89ab_cdef, overclock: 300, accuracy: 0b10 },
+            Message::Input { ack: 7, first: 5, inputs: vec![0x01, 0x80, 0x00] },
+            Message::Hash { frame: 60, hash: u64::MAX },
+            Message::Ping { sent: 12345 },
+            Message::Pong { sent: 12345 },
+        ];
+        for message in messages {
+            assert_eq!(Message::decode(&message.encode()), Ok(message));
+        }
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert_eq!(Message::decode(&[]), Err("Netplay message is truncated".to_string()));
+        assert_eq!(Message::decode(&[9]), Err("Unknown netplay message 9".to_string()));
+        assert_eq!(Message::decode(&[INPUT, 0, 0]), Err("Netplay message is truncated".to_string()));
+        let mut bytes = Message::Ping { sent: 1 }.encode();
+        bytes.push(0);
+        assert_eq!(Message::decode(&bytes), Err("Netplay message is too long".to_string()));
+    }
+}
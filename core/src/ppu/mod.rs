@@ -3,12 +3,17 @@
 //! https://www.nesdev.org/wiki/PPU_memory_map
 
 use crate::cartridge::Mirroring;
+use crate::render::tiles::TileCache;
+use crate::render::Layers;
+use crate::savestate::{StateReader, StateWriter};
+use model::PpuModel;
 use registers::controller::PPUCTRL;
 use registers::mask::PPUMASK;
 use registers::addr::PPUADDR;
 use registers::scroll::PPUSCROLL;
 use registers::status::PPUSTATUS;
 
+pub mod model;
 pub mod registers;
 
 // Memory map constants.
@@ -48,11 +53,28 @@ pub struct PPU {
 
     pub scanline: u16,
     pub cycles: usize,
+    // Number of completed frames since power-on.
+    pub frame_count: u64,
 
     pub nmi_interrupt: Option<u8>,
 
     pub chr_ram: Option<Vec<u8>>,
 
+    // The chip, which picks the palette. Like mirroring, it comes from the cartridge.
+    pub model: PpuModel,
+
+    // The pattern tables decoded for rendering. Call `chr_changed` after changing CHR directly.
+    pub tiles: TileCache,
+
+    // The layers drawn into the frame. A host setting, not part of the console's state.
+    pub layers: Layers,
+
+    // Overclocking: scanlines the PPU stands still for after each vblank, at the top of the next
+    // frame, so the CPU gets their time on top of the frame's. A host setting like `layers`.
+    pub extra_scanlines: u16,
+    // PPU cycles of this frame's extra scanlines still to go.
+    overclock_cycles: usize,
+
     // For PPUDATA
     internal_data_buffer: u8,
 }
@@ -60,14 +82,13 @@ pub struct PPU {
 impl PPU {
     pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
 
-        let chr_ram;
-
-        if chr_rom.len() == 0 {
-            chr_ram = Some(vec![0; 0x2000]);
+        let chr_ram = if chr_rom.is_empty() {
+            Some(vec![0; 0x2000])
         } else {
-            chr_ram = None;
-        }
+            None
+        };
 
+        let tiles = TileCache::new(chr_ram.as_deref().unwrap_or(&chr_rom));
         PPU {
             chr_rom,
             mirroring,
@@ -83,6 +104,7 @@ impl PPU {
 
             scanline: 0,
             cycles: 21,
+            frame_count: 0,
 
             // Simplification of NMI_occurred and NMI_output
             nmi_interrupt: None,
@@ -90,10 +112,28 @@ impl PPU {
             internal_data_buffer: 0,
 
             chr_ram,
+            model: PpuModel::Rp2c02,
+            tiles,
+            layers: Layers::default(),
+            extra_scanlines: 0,
+            overclock_cycles: 0,
         }
     }
 
-    pub fn default() -> Self {
+    // The pattern tables as they are now: CHR-RAM if the cartridge has it, otherwise CHR-ROM.
+    pub fn chr(&self) -> &[u8] {
+        self.chr_ram.as_deref().unwrap_or(&self.chr_rom)
+    }
+
+    // Decodes the tile cache again after CHR was changed other than through the PPU.
+    pub fn chr_changed(&mut self) {
+        let chr = self.chr_ram.as_deref().unwrap_or(&self.chr_rom);
+        self.tiles.rebuild(chr);
+    }
+}
+
+impl Default for PPU {
+    fn default() -> Self {
         PPU {
             chr_rom: vec![0; 1],
             mirroring: Mirroring::Horizontal,
@@ -109,6 +149,7 @@ impl PPU {
 
             scanline: 0,
             cycles: 21,
+            frame_count: 0,
 
             // Simplification of NMI_occurred and NMI_output
             nmi_interrupt: None,
@@ -116,12 +157,33 @@ impl PPU {
             internal_data_buffer: 0,
 
             chr_ram: None,
+            model: PpuModel::Rp2c02,
+            tiles: TileCache::new(&[]),
+            layers: Layers::default(),
+            extra_scanlines: 0,
+            overclock_cycles: 0,
         }
     }
+}
+
+impl PPU {
+    // Whether the PPU is standing still for an extra scanline.
+    pub fn overclocking(&self) -> bool {
+        self.overclock_cycles > 0
+    }
+
+    // How many of the next `ppu_cycles` the PPU will stand still for.
+    pub fn stalled_cycles(&self, ppu_cycles: usize) -> usize {
+        ppu_cycles.min(self.overclock_cycles)
+    }
 
     // Progresses PPU cycles and sets up NMI + VBLANK.
     pub fn tick(&mut self, ppu_cycles: usize) -> bool {
-        self.cycles += ppu_cycles;
+        // Nothing moves during the extra scanlines: the scanline, the dot and the status flags read
+        // as they did when vblank ended, so code timing itself against the PPU doesn't see them.
+        let stalled = self.stalled_cycles(ppu_cycles);
+        self.overclock_cycles -= stalled;
+        self.cycles += ppu_cycles - stalled;
 
         if self.cycles >= 341 {
             self.cycles -= 341;
@@ -131,11 +193,9 @@ impl PPU {
             if self.scanline == 241 {
                 self.status.set(PPUSTATUS::VBLANK_STARTED, true);
 
-                // println!("SCANLINE 241");
-
                 if self.controller.contains(PPUCTRL::GENERATE_NMI)  {
                     self.nmi_interrupt = Some(1);
-                    // println!("NMI_INTERRUPT SET");
+                    tracing::trace!(target: "ppu", "Vblank NMI");
                 }
             };
 
@@ -145,6 +205,8 @@ impl PPU {
                 self.status.set(PPUSTATUS::SPRITE_ZERO_HIT, false);
                 self.status.set(PPUSTATUS::VBLANK_STARTED, false);
                 self.nmi_interrupt = None;
+                self.frame_count += 1;
+                self.overclock_cycles = self.extra_scanlines as usize * 341;
                 return true;
             }
         };
@@ -202,7 +264,7 @@ impl PPU {
     }
 
     pub fn write_to_data(&mut self, value: u8) {
-        let addr = self.ppu_addr.get();
+        let addr = PPU::canonical_addr(self.ppu_addr.get());
 
         self.increment_vram_addr();
 
@@ -210,28 +272,19 @@ impl PPU {
             CHR_ROM_START..=CHR_ROM_END => {
                 if let Some(chr_ram) = &mut self.chr_ram {
                     chr_ram[addr as usize] = value;
+                    self.tiles.update(chr_ram, addr);
                 } else {
-                    println!("Ignoring write into PPU CHR-ROM space at addr {}", addr);
+                    tracing::debug!(target: "ppu", "Ignored write of ${:02x} to CHR-ROM at ${:04x}", value, addr);
                 }
             },
 
             VRAM_START..=VRAM_END => {
                 self.vram[self.mirror_vram_addr(addr) as usize] = value;
-                // println!("writing {} to {}", value, self.mirror_vram_addr(addr))
             },
-            UNUSED_START..=UNUSED_END => panic!("Attempting to write to unused space {}", addr),
-            
-            // $3f10, $3f14, $3f18, $3f1c are mirrors of $3f00, $3f04, $3f08, $3f0c respectively
-            // Reference: https://www.nesdev.org/wiki/PPU_palettes
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                self.palette_table[(addr - 0x10 - PALETTE_TABLE_START) as usize] = value;
-            }
-
-            PALETTE_TABLE_START..=PALETTE_TABLE_END => {
-                self.palette_table[(addr - PALETTE_TABLE_START) as usize] = value;
-            }
 
-            _ => panic!("Unexpected access to {}", addr),
+            // `canonical_addr` has folded the mirrors, $3000-$3EFF and the palette's, away. Palette
+            // entries are 6 bits.
+            _ => self.palette_table[(addr - PALETTE_TABLE_START) as usize] = value & 0x3f,
         }
     }
 
@@ -315,7 +368,7 @@ impl PPU {
     }
 
     pub fn read_data(&mut self) -> u8 {
-        let addr = self.ppu_addr.get();
+        let addr = PPU::canonical_addr(self.ppu_addr.get());
 
         self.increment_vram_addr();
 
@@ -334,17 +387,57 @@ impl PPU {
                 self.internal_data_buffer = self.vram[self.mirror_vram_addr(addr) as usize];
                 result
             }
-            UNUSED_START..=UNUSED_END => panic!("addr space 0x3000 ~ 0x3eff should not be read from, requested = {}", addr),
-            
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                self.palette_table[(addr - 0x10 - PALETTE_TABLE_START) as usize]
-            }
+            _ => self.palette_table[(addr - PALETTE_TABLE_START) as usize],
+        }
+    }
 
-            PALETTE_TABLE_START..=PALETTE_TABLE_END => {
-                self.palette_table[(addr - PALETTE_TABLE_START) as usize]
-            }
+    // Folds PPU address space mirrors onto the address that is actually stored: $3000-$3EFF onto
+    // the nametables, and the palette mirrors onto $3F00-$3F1F (with the sprite backdrop entries
+    // onto the background ones).
+    // Reference: https://www.nesdev.org/wiki/PPU_memory_map
+    pub fn canonical_addr(addr: u16) -> u16 {
+        let addr = addr & 0x3fff;
+        match addr {
+            UNUSED_START..=UNUSED_END => addr - 0x1000,
+            PALETTE_TABLE_START..=PALETTE_TABLE_END => match addr & 0x3f1f {
+                0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => (addr & 0x3f1f) - 0x10,
+                palette => palette,
+            },
+            _ => addr,
+        }
+    }
 
-            _ => panic!("unexpected access to mirrored space {}", addr)
+    // Reads PPU address space without touching the read buffer or the address register.
+    pub fn peek(&self, addr: u16) -> u8 {
+        let addr = PPU::canonical_addr(addr);
+        match addr {
+            CHR_ROM_START..=CHR_ROM_END => match &self.chr_ram {
+                Some(chr_ram) => chr_ram[addr as usize],
+                None => self.chr_rom.get(addr as usize).copied().unwrap_or(0),
+            },
+            VRAM_START..=VRAM_END => self.vram[self.mirror_vram_addr(addr) as usize],
+            _ => self.palette_table[(addr - PALETTE_TABLE_START) as usize],
+        }
+    }
+
+    // Writes PPU address space directly. Unlike writes through $2007 this also patches CHR-ROM.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        let addr = PPU::canonical_addr(addr);
+        match addr {
+            CHR_ROM_START..=CHR_ROM_END => {
+                match &mut self.chr_ram {
+                    Some(chr_ram) => chr_ram[addr as usize] = value,
+                    None => {
+                        if let Some(byte) = self.chr_rom.get_mut(addr as usize) {
+                            *byte = value;
+                        }
+                    }
+                }
+                let chr = self.chr_ram.as_deref().unwrap_or(&self.chr_rom);
+                self.tiles.update(chr, addr);
+            }
+            VRAM_START..=VRAM_END => self.vram[self.mirror_vram_addr(addr) as usize] = value,
+            _ => self.palette_table[(addr - PALETTE_TABLE_START) as usize] = value & 0x3f,
         }
     }
 
@@ -353,7 +446,7 @@ impl PPU {
     }
 
     pub fn read_status(&mut self) -> u8 {
-        let data = self.status.bits();
+        let data = self.status.bits() | self.model.status_id();
         self.status.set(PPUSTATUS::VBLANK_STARTED, false);
         self.ppu_addr.reset_write_latch();
         self.ppu_scroll.reset_latch();
@@ -383,9 +476,67 @@ impl PPU {
             (Mirroring::Horizontal, 2) => vram_index - NAMETABLE_SIZE,
             (Mirroring::Horizontal, 1) => vram_index - NAMETABLE_SIZE,
             (Mirroring::Horizontal, 3) => vram_index - (2 * NAMETABLE_SIZE),
+            (Mirroring::SingleScreenLower, _) => vram_index % NAMETABLE_SIZE,
+            (Mirroring::SingleScreenUpper, _) => vram_index % NAMETABLE_SIZE + NAMETABLE_SIZE,
             _ => vram_index,
         }
     }
+
+    // Memories, registers, latches and the beam position. CHR-ROM and mirroring come from the
+    // cartridge.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        if let Some(chr_ram) = &self.chr_ram {
+            state.vec(chr_ram);
+        }
+        state.bytes(&self.vram);
+        state.bytes(&self.palette_table);
+        state.bytes(&self.oam_data);
+        state.u8(self.controller.bits());
+        self.ppu_addr.save_state(state);
+        state.u8(self.ppu_mask.bits());
+        state.u8(self.oam_addr);
+        state.u8(self.ppu_scroll.scroll_x);
+        state.u8(self.ppu_scroll.scroll_y);
+        state.bool(self.ppu_scroll.latch);
+        state.u8(self.status.bits());
+        state.u16(self.scanline);
+        state.u32(self.cycles as u32);
+        state.u64(self.frame_count);
+        state.bool(self.nmi_interrupt.is_some());
+        state.u8(self.internal_data_buffer);
+        state.u32(self.overclock_cycles as u32);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        if let Some(chr_ram) = &mut self.chr_ram {
+            state.vec(chr_ram)?;
+            self.chr_changed();
+        }
+        state.bytes(&mut self.vram)?;
+        state.bytes(&mut self.palette_table)?;
+        // Entries are 6 bits, as writes leave them; more would index past the system palette.
+        self.palette_table.iter_mut().for_each(|entry| *entry &= 0x3f);
+        state.bytes(&mut self.oam_data)?;
+        self.controller = PPUCTRL::from_bits_retain(state.u8()?);
+        self.ppu_addr.load_state(state)?;
+        self.ppu_mask = PPUMASK::from_bits_retain(state.u8()?);
+        self.oam_addr = state.u8()?;
+        self.ppu_scroll.scroll_x = state.u8()?;
+        self.ppu_scroll.scroll_y = state.u8()?;
+        self.ppu_scroll.latch = state.bool()?;
+        self.status = PPUSTATUS::from_bits_retain(state.u8()?);
+        self.scanline = state.u16()?;
+        self.cycles = state.u32()? as usize;
+        self.frame_count = state.u64()?;
+        self.nmi_interrupt = state.bool()?.then_some(1);
+        self.internal_data_buffer = state.u8()?;
+        // Version 2 added overclocking.
+        self.overclock_cycles = match state.version() >= 2 {
+            true => state.u32()? as usize,
+            false => 0,
+        };
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -403,4 +554,56 @@ mod tests {
         assert_eq!(ppu.status.bits() >> 7, 0);
     }
 
+    #[test]
+    fn test_peek_and_poke_fold_mirrors() {
+        let mut ppu = PPU::default();
+        ppu.poke(0x3f10, 0x21);
+        assert_eq!(ppu.palette_table[0], 0x21);
+        assert_eq!(ppu.peek(0x3f30), 0x21);
+
+        ppu.poke(0x3005, 0x42);
+        assert_eq!(ppu.peek(0x2005), 0x42);
+        // Poking CHR-ROM patches it.
+        ppu.poke(0x0000, 0x99);
+        assert_eq!(ppu.peek(0x0000), 0x99);
+    }
+
+    #[test]
+    fn test_data_port_folds_mirrors() {
+        let mut ppu = PPU::default();
+        // $3005 is $2005, and $7f30 is $3f30 is $3f10 is $3f00.
+        ppu.write_to_ppu_addr(0x30);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x42);
+        ppu.write_to_ppu_addr(0x7f);
+        ppu.write_to_ppu_addr(0x30);
+        ppu.write_to_data(0xe1);
+        assert_eq!(ppu.peek(0x2005), 0x42);
+        assert_eq!(ppu.palette_table[0], 0x21);
+
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x10);
+        assert_eq!(ppu.read_data(), 0x21);
+    }
+
+    #[test]
+    fn test_extra_scanlines_stall_after_vblank() {
+        let mut ppu = PPU { extra_scanlines: 10, ..PPU::default() };
+        while !ppu.tick(1) {}
+        assert_eq!((ppu.scanline, ppu.overclocking()), (0, true));
+        // A tick running past the stall is only stalled for its part.
+        assert_eq!(ppu.stalled_cycles(10 * 341 + 5), 10 * 341);
+
+        // The next frame takes ten scanlines longer, all spent at the top of scanline 0.
+        let mut cycles = 1;
+        while ppu.overclocking() {
+            assert_eq!(ppu.scanline, 0);
+            ppu.tick(1);
+            cycles += 1;
+        }
+        while !ppu.tick(1) {
+            cycles += 1;
+        }
+        assert_eq!(cycles, 262 * 341 + 10 * 341);
+    }
 }
\ No newline at end of file
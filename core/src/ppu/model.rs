@@ -0,0 +1,105 @@
+//! Which PPU chip the console has. The NES has the RP2C02; VS System and PlayChoice-10 boards
+//! have RGB PPUs with their own palettes, some scrambled or with registers moved around so a game
+//! only runs on the board it was sold with.
+//!
+//! Reference: https://www.nesdev.org/wiki/Vs._System#PPU_variants
+
+use std::str::FromStr;
+
+use crate::render::color::Color;
+use crate::render::palette::{RGB_PALETTE, RP2C04_PALETTES, SYSTEM_PALETTE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PpuModel {
+    #[default]
+    Rp2c02,
+    // The RGB PPU of most VS System games and the PlayChoice-10.
+    Rp2c03,
+    // RP2C04-0001 to -0004: the RP2C03's colors in four different orders.
+    Rp2c04(u8),
+    // RC2C05-01 to -05: the RP2C03's palette, with $2000 and $2001 swapped and an ID in the low
+    // bits of $2002 that games check.
+    Rc2c05(u8),
+}
+
+impl PpuModel {
+    pub fn palette(&self) -> &'static [Color; 64] {
+        match self {
+            PpuModel::Rp2c02 => &SYSTEM_PALETTE,
+            PpuModel::Rp2c03 | PpuModel::Rc2c05(_) => &RGB_PALETTE,
+            PpuModel::Rp2c04(n) => &RP2C04_PALETTES[(*n as usize - 1) % 4],
+        }
+    }
+
+    pub fn swaps_control_registers(&self) -> bool {
+        matches!(self, PpuModel::Rc2c05(_))
+    }
+
+    // What reads of $2002 show in bits 0-4, where other PPUs leave the data bus.
+    pub fn status_id(&self) -> u8 {
+        match self {
+            PpuModel::Rc2c05(1) | PpuModel::Rc2c05(4) => 0x1B,
+            PpuModel::Rc2c05(2) => 0x3D,
+            PpuModel::Rc2c05(3) => 0x1C,
+            _ => 0x00,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            PpuModel::Rp2c02 => "RP2C02".to_string(),
+            PpuModel::Rp2c03 => "RP2C03".to_string(),
+            PpuModel::Rp2c04(n) => format!("RP2C04-{:04}", n),
+            PpuModel::Rc2c05(n) => format!("RC2C05-{:02}", n),
+        }
+    }
+}
+
+impl FromStr for PpuModel {
+    type Err = String;
+
+    // "2c02", "2c03", "2c04-0001" to "2c04-0004" or "2c05-01" to "2c05-05", with or without the
+    // leading letters.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let lower = s.to_ascii_lowercase();
+        let name = lower.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+        let (chip, variant) = name.split_once('-').unwrap_or((name, ""));
+        let variant = variant.parse::<u8>();
+        match (chip, variant) {
+            ("2c02", _) => Ok(PpuModel::Rp2c02),
+            ("2c03", _) => Ok(PpuModel::Rp2c03),
+            ("2c04", Ok(n @ 1..=4)) => Ok(PpuModel::Rp2c04(n)),
+            ("2c05", Ok(n @ 1..=5)) => Ok(PpuModel::Rc2c05(n)),
+            _ => Err(format!(
+                "Unknown PPU {}: use 2c02, 2c03, 2c04-0001 to 2c04-0004 or 2c05-01 to 2c05-05",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_name() {
+        for name in ["RP2C02", "RP2C03", "RP2C04-0003", "RC2C05-02"] {
+            assert_eq!(name.parse::<PpuModel>().unwrap().name(), name);
+        }
+        assert_eq!("2c04-0001".parse(), Ok(PpuModel::Rp2c04(1)));
+        assert!("2c04-0005".parse::<PpuModel>().is_err());
+        assert!("2c07".parse::<PpuModel>().is_err());
+    }
+
+    #[test]
+    fn test_rp2c04_palettes_reorder_the_rgb_colors() {
+        // Every RP2C04 shows white at a different index, but the same white.
+        let white = RGB_PALETTE[0x20];
+        assert_eq!(PpuModel::Rp2c04(1).palette()[0x08], white);
+        assert_eq!(PpuModel::Rp2c04(2).palette()[0x0C], white);
+        assert_eq!(PpuModel::Rp2c03.palette()[0x20], white);
+        assert_ne!(PpuModel::Rp2c02.palette()[0x00], PpuModel::Rp2c03.palette()[0x00]);
+        assert_eq!(PpuModel::Rc2c05(2).status_id(), 0x3D);
+    }
+}
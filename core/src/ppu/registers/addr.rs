@@ -2,6 +2,8 @@
 //! Reference: https://www.nesdev.org/wiki/PPU_registers#PPUADDR
 //! Note that the PPU data register ($2007) is implemented as `PPU::write_data()`
 
+use crate::savestate::{StateReader, StateWriter};
+
 pub struct PPUADDR {
     // high byte, then low byte
     value: (u8, u8),
@@ -10,6 +12,12 @@ pub struct PPUADDR {
 }
 
 // Address register corresponds to 0x2006.
+impl Default for PPUADDR {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PPUADDR {
     pub fn new() -> Self {
         PPUADDR {
@@ -34,7 +42,7 @@ impl PPUADDR {
 
         // Mirrors down in case result is greater than the valid address range.
         if self.get() > 0x3fff {
-            self.set(self.get() & 0x4000);
+            self.set(self.get() & 0x3fff);
         }
 
         self.write_latch = !self.write_latch;
@@ -51,7 +59,7 @@ impl PPUADDR {
 
         // Mirrors down in case result is greater than the valid address range.
         if self.get() > 0x3fff {
-            self.set(self.get() & 0x4000);
+            self.set(self.get() & 0x3fff);
         }
     }
 
@@ -62,6 +70,17 @@ impl PPUADDR {
     pub fn get(&self) -> u16 {
         ((self.value.0 as u16) << 8) | (self.value.1 as u16)
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u16(self.get());
+        state.bool(self.write_latch);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.set(state.u16()?);
+        self.write_latch = state.bool()?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -31,6 +31,12 @@ bitflags! {
     }
 }
 
+impl Default for PPUCTRL {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PPUCTRL {
     pub fn new() -> Self {
         PPUCTRL::from_bits_truncate(0b0000_0000)
@@ -8,6 +8,12 @@ pub struct PPUSCROLL {
     pub latch: bool,
 }
 
+impl Default for PPUSCROLL {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PPUSCROLL {
     pub fn new() -> Self {
         PPUSCROLL {
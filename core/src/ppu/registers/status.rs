@@ -33,6 +33,12 @@ bitflags! {
     }
 }
 
+impl Default for PPUSTATUS {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PPUSTATUS {
     pub fn new() -> Self {
         PPUSTATUS::from_bits_truncate(0b0000_0000)
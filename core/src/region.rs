@@ -0,0 +1,153 @@
+//! Which console a game was made for. NTSC consoles (North America and Japan) draw 60 frames a
+//! second; PAL ones (Europe and Australia) draw 50 with a slower CPU, and the Dendy, the Famicom
+//! clone sold in Russia, draws 50 with close to NTSC's CPU speed. Only NTSC consoles are emulated
+//! so far, so the region is reported rather than acted on.
+//!
+//! `detect` takes the region from the NES 2.0 header's timing byte when the ROM has one, then from
+//! the region tags GoodNES and No-Intro put in file names ("(E)", "(Europe)", "(USA)", ...), and
+//! falls back on NTSC. There is no game database; per-game settings can say what the header
+//! doesn't.
+//!
+//! Reference: https://www.nesdev.org/wiki/NES_2.0#CPU/PPU_Timing
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::cartridge::Cartridge;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+// Where `detect` found the region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionSource {
+    Header,
+    FileName,
+    Default,
+}
+
+impl Region {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::Ntsc => "ntsc",
+            Region::Pal => "pal",
+            Region::Dendy => "dendy",
+        }
+    }
+
+    // For messages: "NTSC", "PAL" or "Dendy".
+    pub fn label(&self) -> &'static str {
+        match self {
+            Region::Ntsc => "NTSC",
+            Region::Pal => "PAL",
+            Region::Dendy => "Dendy",
+        }
+    }
+
+    // Reads the low two bits of a NES 2.0 header's byte 12. Games that run on either kind of
+    // console are counted as NTSC.
+    pub fn from_timing_byte(byte: u8) -> Region {
+        match byte & 0b11 {
+            1 => Region::Pal,
+            3 => Region::Dendy,
+            _ => Region::Ntsc,
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("Unknown region {} (expected ntsc, pal or dendy)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl fmt::Display for RegionSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RegionSource::Header => "the NES 2.0 header",
+            RegionSource::FileName => "the file name",
+            RegionSource::Default => "the default",
+        })
+    }
+}
+
+// The region of `cartridge`, loaded from a file called `file_name`, and where it came from.
+pub fn detect(cartridge: &Cartridge, file_name: &str) -> (Region, RegionSource) {
+    if let Some(region) = cartridge.region {
+        return (region, RegionSource::Header);
+    }
+    match from_file_name(file_name) {
+        Some(region) => (region, RegionSource::FileName),
+        None => (Region::Ntsc, RegionSource::Default),
+    }
+}
+
+// The region the tags in parentheses in `name` point to. A game tagged with both an NTSC and a
+// PAL country, like "(USA, Europe)", runs on either, so it is NTSC.
+fn from_file_name(name: &str) -> Option<Region> {
+    const NTSC: &[&str] = &["u", "j", "ju", "ue", "usa", "japan", "canada", "korea", "ntsc", "world"];
+    const PAL: &[&str] = &[
+        "e", "a", "g", "f", "s", "i", "europe", "australia", "germany", "france", "spain", "italy", "sweden",
+        "netherlands", "uk", "scandinavia", "pal",
+    ];
+    const DENDY: &[&str] = &["r", "russia", "dendy"];
+
+    let mut found = None;
+    for tag in name.split('(').skip(1).filter_map(|rest| rest.split_once(')')).map(|(tag, _)| tag) {
+        for country in tag.split(',').map(|country| country.trim().to_ascii_lowercase()) {
+            let region = if NTSC.contains(&country.as_str()) {
+                Region::Ntsc
+            } else if PAL.contains(&country.as_str()) {
+                Region::Pal
+            } else if DENDY.contains(&country.as_str()) {
+                Region::Dendy
+            } else {
+                continue;
+            };
+            if region == Region::Ntsc {
+                return Some(Region::Ntsc);
+            }
+            found.get_or_insert(region);
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_from_file_name() {
+        assert_eq!(from_file_name("Super Mario Bros. (E) [!].nes"), Some(Region::Pal));
+        assert_eq!(from_file_name("Tetris (USA, Europe).nes"), Some(Region::Ntsc));
+        assert_eq!(from_file_name("Elite (Europe) (En,Fr,De).nes"), Some(Region::Pal));
+        assert_eq!(from_file_name("Contra (Russia) (Unl).nes"), Some(Region::Dendy));
+        assert_eq!(from_file_name("balloon.nes"), None);
+        assert_eq!(from_file_name("Unclosed (E.nes"), None);
+
+        let cartridge = Cartridge::default();
+        assert_eq!(detect(&cartridge, "game (PAL).nes"), (Region::Pal, RegionSource::FileName));
+        assert_eq!(detect(&cartridge, "game.nes"), (Region::Ntsc, RegionSource::Default));
+        let header = Cartridge { region: Some(Region::Dendy), ..Cartridge::default() };
+        assert_eq!(detect(&header, "game (U).nes"), (Region::Dendy, RegionSource::Header));
+    }
+}
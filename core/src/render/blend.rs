@@ -0,0 +1,208 @@
+//! Frame blending, to hide the flicker games produce by drawing some sprites only on alternate
+//! frames (usually to get around the 8 sprites per scanline limit).
+//!
+//! Blending happens on the frame buffer itself, before any frontend sees it, so every backend,
+//! screenshot and recording gets the same picture.
+//!
+//! Reference: <https://www.nesdev.org/wiki/Sprite_overflow_games>
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::render::color::Color;
+
+use crate::render::constants::*;
+use crate::render::frame::Frame;
+
+// How much of the previous picture survives each frame in `BlendMode::Phosphor`.
+pub const DEFAULT_PHOSPHOR_DECAY: f32 = 0.6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    Off,
+    // Each pixel is the average of this frame and the last one.
+    Average,
+    // Each pixel is the brighter of this frame and the previous picture faded by the given
+    // factor, like the afterglow of a CRT's phosphors.
+    Phosphor(f32),
+}
+
+impl BlendMode {
+    pub fn next(&self) -> BlendMode {
+        match self {
+            BlendMode::Off => BlendMode::Average,
+            BlendMode::Average => BlendMode::Phosphor(DEFAULT_PHOSPHOR_DECAY),
+            BlendMode::Phosphor(_) => BlendMode::Off,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlendMode::Off => "off",
+            BlendMode::Average => "average",
+            BlendMode::Phosphor(_) => "phosphor",
+        }
+    }
+}
+
+// Accepts "off", "average" (or "50") and "phosphor", optionally with a decay: "phosphor:0.8".
+impl FromStr for BlendMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("phosphor", decay)) => match decay.parse::<f32>() {
+                Ok(decay) if (0.0..1.0).contains(&decay) => Ok(BlendMode::Phosphor(decay)),
+                _ => Err(format!("Invalid phosphor decay {}, expected 0 to 1", decay)),
+            },
+            None => match s {
+                "off" => Ok(BlendMode::Off),
+                "average" | "50" => Ok(BlendMode::Average),
+                "phosphor" => Ok(BlendMode::Phosphor(DEFAULT_PHOSPHOR_DECAY)),
+                _ => Err(format!("Unknown blend mode {}", s)),
+            },
+            _ => Err(format!("Unknown blend mode {}", s)),
+        }
+    }
+}
+
+// Writes the mode the way `from_str` reads it, leaving out the default decay.
+impl fmt::Display for BlendMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlendMode::Phosphor(decay) if *decay != DEFAULT_PHOSPHOR_DECAY => write!(f, "phosphor:{}", decay),
+            mode => f.write_str(mode.name()),
+        }
+    }
+}
+
+pub struct FrameBlender {
+    pub mode: BlendMode,
+    // What the next frame is blended with: the last unblended frame for `Average`, the last
+    // blended picture for `Phosphor`. Empty until the first frame.
+    history: Vec<Color>,
+}
+
+impl Default for FrameBlender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameBlender {
+    pub fn new() -> Self {
+        FrameBlender {
+            mode: BlendMode::Off,
+            history: Vec::new(),
+        }
+    }
+
+    // Forgets the previous frame, e.g. after a reset.
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+
+    // Blends a freshly rendered `frame` with the previous one, in place.
+    pub fn apply(&mut self, frame: &mut Frame) {
+        let visible = &mut frame.data[..(NES_PIXEL_WIDTH * NES_PIXEL_HEIGHT) as usize];
+        if self.mode == BlendMode::Off {
+            self.history.clear();
+            return;
+        }
+        if self.history.len() != visible.len() {
+            self.history = visible.to_vec();
+            return;
+        }
+
+        for (pixel, previous) in visible.iter_mut().zip(self.history.iter_mut()) {
+            match self.mode {
+                BlendMode::Off => unreachable!(),
+                BlendMode::Average => {
+                    let current = *pixel;
+                    *pixel = Color::new(
+                        (current.r + previous.r) / 2.0,
+                        (current.g + previous.g) / 2.0,
+                        (current.b + previous.b) / 2.0,
+                        1.0,
+                    );
+                    *previous = current;
+                }
+                BlendMode::Phosphor(decay) => {
+                    *pixel = Color::new(
+                        pixel.r.max(previous.r * decay),
+                        pixel.g.max(previous.g * decay),
+                        pixel.b.max(previous.b * decay),
+                        1.0,
+                    );
+                    *previous = *pixel;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(color: Color) -> Frame {
+        let mut frame = Frame::new();
+        frame.data.iter_mut().for_each(|pixel| *pixel = color);
+        frame
+    }
+
+    #[test]
+    fn test_average_blends_with_unblended_previous_frame() {
+        let mut blender = FrameBlender::new();
+        blender.mode = BlendMode::Average;
+
+        let white = Color::new(1.0, 1.0, 1.0, 1.0);
+        let black = Color::new(0.0, 0.0, 0.0, 1.0);
+        let mut frame = filled(white);
+        blender.apply(&mut frame);
+        assert_eq!(frame.data[0], white);
+
+        let mut frame = filled(black);
+        blender.apply(&mut frame);
+        assert_eq!(frame.data[0], Color::new(0.5, 0.5, 0.5, 1.0));
+
+        // Alternating frames settle at the average rather than drifting.
+        let mut frame = filled(white);
+        blender.apply(&mut frame);
+        assert_eq!(frame.data[0], Color::new(0.5, 0.5, 0.5, 1.0));
+    }
+
+    #[test]
+    fn test_phosphor_decays() {
+        let mut blender = FrameBlender::new();
+        blender.mode = BlendMode::Phosphor(0.5);
+
+        blender.apply(&mut filled(Color::new(1.0, 0.0, 0.0, 1.0)));
+        let mut frame = filled(Color::new(0.0, 0.2, 0.0, 1.0));
+        blender.apply(&mut frame);
+        assert_eq!(frame.data[0], Color::new(0.5, 0.2, 0.0, 1.0));
+        let mut frame = filled(Color::new(0.0, 0.0, 0.0, 1.0));
+        blender.apply(&mut frame);
+        assert_eq!(frame.data[0], Color::new(0.25, 0.1, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_off_leaves_frame_untouched() {
+        let mut blender = FrameBlender::new();
+        let red = Color::new(1.0, 0.0, 0.0, 1.0);
+        blender.apply(&mut filled(red));
+        let mut frame = filled(Color::new(0.0, 1.0, 0.0, 1.0));
+        blender.apply(&mut frame);
+        assert_eq!(frame.data[0], Color::new(0.0, 1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_parse_blend_mode() {
+        assert_eq!("off".parse(), Ok(BlendMode::Off));
+        assert_eq!("50".parse(), Ok(BlendMode::Average));
+        assert_eq!("phosphor".parse(), Ok(BlendMode::Phosphor(DEFAULT_PHOSPHOR_DECAY)));
+        assert_eq!("phosphor:0.8".parse(), Ok(BlendMode::Phosphor(0.8)));
+        assert!("phosphor:2".parse::<BlendMode>().is_err());
+        assert!("smear".parse::<BlendMode>().is_err());
+    }
+}
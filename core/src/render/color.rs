@@ -0,0 +1,35 @@
+// An RGBA color with components from 0.0 to 1.0. Laid out and converted like macroquad's `Color`,
+// which the renderer used before the core was split from the frontend, so pictures are unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Color {
+        Color { r, g, b, a }
+    }
+
+    // Builds a color from components from 0 to 255.
+    pub const fn from_rgba(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, a as f32 / 255.0)
+    }
+}
+
+impl From<Color> for [u8; 4] {
+    fn from(color: Color) -> Self {
+        [
+            (color.r * 255.0) as u8,
+            (color.g * 255.0) as u8,
+            (color.b * 255.0) as u8,
+            (color.a * 255.0) as u8,
+        ]
+    }
+}
+
+pub const BLACK: Color = Color::new(0.0, 0.0, 0.0, 1.0);
+// Fills a frame before anything is drawn, so undrawn pixels stand out.
+pub const PINK: Color = Color::new(1.0, 0.43, 0.76, 1.0);
@@ -0,0 +1,285 @@
+//! Pictures of the PPU and CPU state for the debug viewers: pattern tables, nametables, palettes,
+//! a memory dump and the event grid. Each view is rebuilt from scratch, so it can be refreshed every frame.
+//!
+//! Reference: <https://www.nesdev.org/wiki/PPU_pattern_tables>,
+//! <https://www.nesdev.org/wiki/PPU_nametables>, <https://www.nesdev.org/wiki/PPU_attribute_tables>
+
+use crate::debugger::events::{self, Event, EventKind};
+use crate::debugger::watch::AccessKind;
+use crate::ppu::{registers::controller::PPUCTRL, PPU};
+use crate::render::filters::{darken, Picture};
+use crate::render::frame::Frame;
+use crate::render::osd::{draw_text, text_width, GLYPH_HEIGHT};
+
+// Both pattern tables side by side, 16x16 tiles each.
+pub const PATTERN_TABLES_WIDTH: usize = 256;
+pub const PATTERN_TABLES_HEIGHT: usize = 128;
+// All four nametables in a 2x2 grid.
+pub const NAMETABLES_WIDTH: usize = 512;
+pub const NAMETABLES_HEIGHT: usize = 480;
+// 8 palettes of 4 colors, one swatch per color.
+pub const SWATCH_SIZE: usize = 16;
+pub const PALETTES_WIDTH: usize = 4 * SWATCH_SIZE;
+pub const PALETTES_HEIGHT: usize = 8 * SWATCH_SIZE;
+// 16 bytes per row of the memory dump.
+pub const MEMORY_ROW_BYTES: usize = 16;
+const MEMORY_LINE_HEIGHT: usize = GLYPH_HEIGHT + 3;
+const CURSOR_COLOR: [u8; 4] = [0x30, 0x50, 0xa0, 0xff];
+// The event grid: the visible picture is lighter than blanking.
+pub const EVENTS_WIDTH: usize = events::DOTS;
+pub const EVENTS_HEIGHT: usize = events::SCANLINES;
+const VISIBLE_COLOR: [u8; 4] = [0x30, 0x30, 0x30, 0xff];
+const BLANK_COLOR: [u8; 4] = [0x10, 0x10, 0x10, 0xff];
+const SELECTED_COLOR: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+
+// Colors come from the palette of the PPU being viewed, which differs on arcade boards.
+fn rgba(ppu: &PPU, palette_index: u8) -> [u8; 4] {
+    ppu.model.palette()[(palette_index & 0x3f) as usize].into()
+}
+
+// Draws the 8x8 tile at (x, y) with the 2-bit color indices mapped through `palette`.
+fn draw_tile(picture: &mut Picture, ppu: &PPU, tile: &[u8], x: usize, y: usize, palette: [u8; 4]) {
+    for row in 0..8 {
+        let (lower, upper) = (tile[row], tile[row + 8]);
+        for column in 0..8 {
+            let bit = 7 - column;
+            let value = ((upper >> bit) & 1) << 1 | ((lower >> bit) & 1);
+            picture.set_pixel(x + column, y + row, rgba(ppu, palette[value as usize]));
+        }
+    }
+}
+
+// The 8 palettes in palette RAM: 0-3 are background, 4-7 sprites. Entry 0 of every palette shows
+// the shared backdrop color.
+pub fn palette(ppu: &PPU, index: usize) -> [u8; 4] {
+    let start = index * 4;
+    [
+        ppu.palette_table[0],
+        ppu.palette_table[start + 1],
+        ppu.palette_table[start + 2],
+        ppu.palette_table[start + 3],
+    ]
+}
+
+// Both pattern tables ($0000 on the left, $1000 on the right), colored with `palette_index`.
+pub fn pattern_tables(ppu: &PPU, palette_index: usize) -> Picture {
+    let mut picture = Picture::new(PATTERN_TABLES_WIDTH, PATTERN_TABLES_HEIGHT);
+    let colors = palette(ppu, palette_index);
+    for table in 0..2 {
+        for tile_index in 0..256 {
+            let tile = Frame::fetch_tile(ppu, table * 0x1000, tile_index);
+            let x = table * 128 + (tile_index % 16) * 8;
+            let y = (tile_index / 16) * 8;
+            draw_tile(&mut picture, ppu, tile, x, y, colors);
+        }
+    }
+    picture
+}
+
+// The four logical nametables ($2000, $2400, $2800, $2C00) as the PPU sees them through the
+// cartridge's mirroring, using the current background pattern table.
+pub fn nametables(ppu: &PPU) -> Picture {
+    let mut picture = Picture::new(NAMETABLES_WIDTH, NAMETABLES_HEIGHT);
+    let bank = ppu.controller.contains(PPUCTRL::BACKGROUND_PATTERN_ADDR) as usize * 0x1000;
+    for table in 0..4u16 {
+        let base = 0x2000 + table * 0x400;
+        let (left, top) = ((table as usize % 2) * 256, (table as usize / 2) * 240);
+        for i in 0..960u16 {
+            let (tile_x, tile_y) = ((i % 32) as usize, (i / 32) as usize);
+            let tile_index = ppu.vram[ppu.mirror_vram_addr(base + i) as usize] as usize;
+
+            let attr_addr = base + 0x3c0 + (tile_y as u16 / 4) * 8 + tile_x as u16 / 4;
+            let attr_byte = ppu.vram[ppu.mirror_vram_addr(attr_addr) as usize];
+            let shift = ((tile_y % 4) / 2) * 4 + ((tile_x % 4) / 2) * 2;
+            let colors = palette(ppu, ((attr_byte >> shift) & 0b11) as usize);
+
+            let tile = Frame::fetch_tile(ppu, bank, tile_index);
+            draw_tile(&mut picture, ppu, tile, left + tile_x * 8, top + tile_y * 8, colors);
+        }
+    }
+    picture
+}
+
+// One row of swatches per palette, background palettes first.
+pub fn palettes(ppu: &PPU) -> Picture {
+    let mut picture = Picture::new(PALETTES_WIDTH, PALETTES_HEIGHT);
+    for index in 0..8 {
+        for (entry, color) in palette(ppu, index).into_iter().enumerate() {
+            for y in 0..SWATCH_SIZE {
+                for x in 0..SWATCH_SIZE {
+                    picture.set_pixel(entry * SWATCH_SIZE + x, index * SWATCH_SIZE + y, rgba(ppu, color));
+                }
+            }
+        }
+    }
+    picture
+}
+
+// One line of the memory dump: "0200: 00 01 ... 0F", with the bytes read through `read`.
+pub fn memory_line<F: Fn(u16) -> u8>(read: F, addr: u16) -> String {
+    let bytes: Vec<String> = (0..MEMORY_ROW_BYTES as u16)
+        .map(|offset| format!("{:02X}", read(addr.wrapping_add(offset))))
+        .collect();
+    format!("{:04X}: {}", addr, bytes.join(" "))
+}
+
+// `rows` lines of `memory_line` starting at `start`. `read` should be side-effect free (e.g.
+// `Bus::peek`) so viewing memory never disturbs the emulation. The byte at `cursor`, if shown, is
+// highlighted.
+pub fn memory<F: Fn(u16) -> u8>(read: F, start: u16, rows: usize, cursor: Option<u16>) -> Picture {
+    let width = text_width(&memory_line(&read, 0)) + 4;
+    let mut picture = Picture::new(width, rows * MEMORY_LINE_HEIGHT + 2);
+
+    if let Some(offset) = cursor.map(|cursor| cursor.wrapping_sub(start) as usize) {
+        if offset < rows * MEMORY_ROW_BYTES {
+            // Skip the "0200: " prefix and three characters per byte before the cursor.
+            let advance = text_width("00") - text_width("0");
+            let column = 6 + 3 * (offset % MEMORY_ROW_BYTES);
+            let (left, top) = (column * advance, (offset / MEMORY_ROW_BYTES) * MEMORY_LINE_HEIGHT);
+            for y in top..top + MEMORY_LINE_HEIGHT {
+                for x in left..left + 2 * advance + 1 {
+                    picture.set_pixel(x, y, CURSOR_COLOR);
+                }
+            }
+        }
+    }
+
+    for row in 0..rows {
+        let addr = start.wrapping_add((row * MEMORY_ROW_BYTES) as u16);
+        draw_text(&mut picture, 1, 1 + row * MEMORY_LINE_HEIGHT, &memory_line(&read, addr), 1.0);
+    }
+    picture
+}
+
+// Color of an event on the grid. Reads are drawn darker than writes.
+pub fn event_color(kind: EventKind) -> [u8; 4] {
+    match kind {
+        EventKind::Ppu => [0xf0, 0x40, 0x40, 0xff],
+        EventKind::Apu => [0xf0, 0xd0, 0x30, 0xff],
+        EventKind::Input => [0x40, 0xe0, 0x40, 0xff],
+        EventKind::Mapper => [0x40, 0x90, 0xf0, 0xff],
+        EventKind::Nmi => [0xe0, 0x60, 0xf0, 0xff],
+    }
+}
+
+// A frame's events at their scanline (down) and dot (across), over the visible area and blanking.
+// The event at `selected` is boxed.
+pub fn events(events: &[Event], selected: Option<usize>) -> Picture {
+    let mut picture = Picture::new(EVENTS_WIDTH, EVENTS_HEIGHT);
+    for y in 0..EVENTS_HEIGHT {
+        for x in 0..EVENTS_WIDTH {
+            let visible = (1..=256).contains(&x) && y < 240;
+            picture.set_pixel(x, y, if visible { VISIBLE_COLOR } else { BLANK_COLOR });
+        }
+    }
+    for event in events {
+        let color = match event.access {
+            AccessKind::Write => event_color(event.kind),
+            _ => darken(event_color(event.kind), 0.6),
+        };
+        picture.set_pixel(event.dot as usize % EVENTS_WIDTH, event.scanline as usize % EVENTS_HEIGHT, color);
+    }
+    if let Some(event) = selected.and_then(|index| events.get(index)) {
+        let (x, y) = (event.dot as usize % EVENTS_WIDTH, event.scanline as usize % EVENTS_HEIGHT);
+        for offset in -2..=2isize {
+            for (dx, dy) in [(offset, -2), (offset, 2), (-2, offset), (2, offset)] {
+                let (x, y) = (x as isize + dx, y as isize + dy);
+                if (0..EVENTS_WIDTH as isize).contains(&x) && (0..EVENTS_HEIGHT as isize).contains(&y) {
+                    picture.set_pixel(x as usize, y as usize, SELECTED_COLOR);
+                }
+            }
+        }
+    }
+    picture
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::cartridge::Mirroring;
+    use crate::cpu::Mem;
+
+    fn test_ppu() -> PPU {
+        PPU::new(vec![0; 0x2000], Mirroring::Horizontal)
+    }
+
+    #[test]
+    fn test_pattern_tables_use_selected_palette() {
+        let mut ppu = test_ppu();
+        // Tile 1 of the right table: top row is color 3, the rest color 0.
+        ppu.chr_rom[0x1010] = 0xff;
+        ppu.chr_rom[0x1018] = 0xff;
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[7] = 0x16;
+
+        let picture = pattern_tables(&ppu, 1);
+        assert_eq!(picture.pixel(128 + 8, 0), rgba(&ppu, 0x16));
+        assert_eq!(picture.pixel(128 + 8, 1), rgba(&ppu, 0x0f));
+    }
+
+    #[test]
+    fn test_nametables_follow_mirroring() {
+        let mut ppu = test_ppu();
+        ppu.chr_rom[0x10] = 0xff;
+        ppu.palette_table[1] = 0x21;
+        ppu.vram[0] = 1;
+
+        // The test PPU mirrors horizontally, so $2400 shows the same tile as $2000.
+        let picture = nametables(&ppu);
+        assert_eq!(picture.pixel(0, 0), rgba(&ppu, 0x21));
+        assert_eq!(picture.pixel(256, 0), rgba(&ppu, 0x21));
+        assert_eq!(picture.pixel(0, 240), rgba(&ppu, 0x00));
+    }
+
+    #[test]
+    fn test_palettes_show_backdrop_in_every_row() {
+        let mut ppu = test_ppu();
+        ppu.palette_table[0] = 0x30;
+        ppu.palette_table[0x12] = 0x2a;
+        let picture = palettes(&ppu);
+        assert_eq!(picture.pixel(0, 7 * SWATCH_SIZE), rgba(&ppu, 0x30));
+        assert_eq!(picture.pixel(2 * SWATCH_SIZE, 4 * SWATCH_SIZE), rgba(&ppu, 0x2a));
+    }
+
+    #[test]
+    fn test_memory_line() {
+        let mut bus = Bus::new(create_test_cartridge());
+        bus.mem_write(0x0201, 0xab);
+        assert_eq!(
+            memory_line(|addr| bus.peek(addr), 0x0200),
+            "0200: 00 AB 00 00 00 00 00 00 00 00 00 00 00 00 00 00"
+        );
+        let picture = memory(|addr| bus.peek(addr), 0x0200, 4, None);
+        assert_eq!(picture.height, 4 * MEMORY_LINE_HEIGHT + 2);
+    }
+
+    #[test]
+    fn test_memory_highlights_cursor() {
+        let bus = Bus::new(create_test_cartridge());
+        let advance = text_width("00") - text_width("0");
+        // The cursor on $0211 is the second byte of the second row.
+        let picture = memory(|addr| bus.peek(addr), 0x0200, 4, Some(0x0211));
+        assert_eq!(picture.pixel(9 * advance, MEMORY_LINE_HEIGHT), CURSOR_COLOR);
+        assert_ne!(picture.pixel(6 * advance, MEMORY_LINE_HEIGHT), CURSOR_COLOR);
+        // Off the page, nothing is highlighted.
+        let picture = memory(|addr| bus.peek(addr), 0x0200, 4, Some(0x0300));
+        assert!((0..picture.width).all(|x| picture.pixel(x, 0) != CURSOR_COLOR));
+    }
+
+    #[test]
+    fn test_events_are_placed_by_scanline_and_dot() {
+        let event = |kind, access, scanline, dot| Event { kind, addr: 0x2005, value: 0, access, scanline, dot };
+        let log = [
+            event(EventKind::Ppu, AccessKind::Write, 30, 250),
+            event(EventKind::Input, AccessKind::Read, 250, 10),
+        ];
+        let picture = events(&log, Some(0));
+        assert_eq!(picture.pixel(250, 30), event_color(EventKind::Ppu));
+        assert_eq!(picture.pixel(10, 250), darken(event_color(EventKind::Input), 0.6));
+        assert_eq!(picture.pixel(100, 100), VISIBLE_COLOR);
+        assert_eq!(picture.pixel(300, 100), BLANK_COLOR);
+        assert_eq!(picture.pixel(252, 30), SELECTED_COLOR);
+    }
+}
@@ -0,0 +1,125 @@
+//! A simple CRT look: barrel distortion for the curved glass, scanline gaps and a vignette.
+
+use crate::render::filters::{simd, Filter, Picture};
+
+#[derive(Debug, Clone)]
+pub struct Crt {
+    // How strongly the picture bulges outward. 0.0 is flat.
+    pub curvature: f32,
+    // Brightness of the gaps between scanlines.
+    pub scanline_intensity: f32,
+    // How much the corners darken.
+    pub vignette: f32,
+    // Where each output pixel comes from and how much it darkens. Only changes with the settings and
+    // the picture size, so it is worked out once.
+    map: CrtMap,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct CrtMap {
+    // The settings and input size the map is for.
+    key: (f32, f32, f32, usize, usize),
+    // The input pixel for each output pixel, or None off the curved screen.
+    sources: Vec<Option<u32>>,
+    factors: Vec<f32>,
+}
+
+impl Default for Crt {
+    fn default() -> Self {
+        Crt {
+            curvature: 0.06,
+            scanline_intensity: 0.7,
+            vignette: 0.25,
+            map: CrtMap::default(),
+        }
+    }
+}
+
+impl Filter for Crt {
+    fn name(&self) -> &str {
+        "crt"
+    }
+
+    // The output is twice the input size so scanlines and the curved edges have room.
+    fn apply(&mut self, input: &Picture, output: &mut Picture) {
+        output.resize(input.width * 2, input.height * 2);
+        let key = (self.curvature, self.scanline_intensity, self.vignette, input.width, input.height);
+        if self.map.key != key || self.map.sources.is_empty() {
+            self.map = self.build_map(input.width, input.height);
+        }
+
+        for (pixel, source) in output.pixels.chunks_exact_mut(4).zip(&self.map.sources) {
+            match source {
+                Some(i) => pixel.copy_from_slice(&input.pixels[*i as usize * 4..*i as usize * 4 + 4]),
+                None => pixel.copy_from_slice(&[0, 0, 0, 255]),
+            }
+        }
+        simd::darken_pixels(&mut output.pixels, &self.map.factors);
+    }
+}
+
+impl Crt {
+    fn build_map(&self, width: usize, height: usize) -> CrtMap {
+        let (out_width, out_height) = (width * 2, height * 2);
+        let mut map = CrtMap {
+            key: (self.curvature, self.scanline_intensity, self.vignette, width, height),
+            sources: Vec::with_capacity(out_width * out_height),
+            factors: Vec::with_capacity(out_width * out_height),
+        };
+        for y in 0..out_height {
+            for x in 0..out_width {
+                // Centered coordinates in [-1, 1].
+                let u = (x as f32 + 0.5) / out_width as f32 * 2.0 - 1.0;
+                let v = (y as f32 + 0.5) / out_height as f32 * 2.0 - 1.0;
+                let r2 = u * u + v * v;
+                let warp = 1.0 + self.curvature * r2;
+                let (su, sv) = (u * warp, v * warp);
+
+                if su.abs() > 1.0 || sv.abs() > 1.0 {
+                    map.sources.push(None);
+                    map.factors.push(1.0);
+                    continue;
+                }
+
+                let source_x = (((su + 1.0) / 2.0 * width as f32) as usize).min(width - 1);
+                let source_y = (((sv + 1.0) / 2.0 * height as f32) as usize).min(height - 1);
+                let mut factor = 1.0 - self.vignette * r2 / 2.0;
+                if y % 2 == 1 {
+                    factor *= self.scanline_intensity;
+                }
+                map.sources.push(Some((source_y * width + source_x) as u32));
+                map.factors.push(factor);
+            }
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crt_curves_edges_and_keeps_center() {
+        let mut input = Picture::new(16, 16);
+        for pixel in input.pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[200, 200, 200, 255]);
+        }
+
+        let mut output = Picture::default();
+        Crt::default().apply(&input, &mut output);
+        assert_eq!((output.width, output.height), (32, 32));
+        // The corners fall outside the curved screen.
+        assert_eq!(output.pixel(0, 0), [0, 0, 0, 255]);
+        // The center is barely touched; the row below it is a scanline gap.
+        assert_eq!(output.pixel(16, 16), [200, 200, 200, 255]);
+        assert_eq!(output.pixel(16, 17)[0], 140);
+
+        // Changing a setting redraws the map.
+        let mut crt = Crt::default();
+        crt.apply(&input, &mut output);
+        crt.scanline_intensity = 0.5;
+        crt.apply(&input, &mut output);
+        assert_eq!(output.pixel(16, 17)[0], 100);
+    }
+}
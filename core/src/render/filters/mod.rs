@@ -0,0 +1,246 @@
+//! Post-processing filters applied between the PPU frame buffer and the window texture.
+//!
+//! A filter turns one RGBA8 picture into another, possibly larger one. Filters are chained in a
+//! `FilterChain`, so custom filters only need to implement `Filter` and be pushed onto the chain.
+
+pub mod crt;
+pub mod scale2x;
+pub mod scanlines;
+pub mod simd;
+
+use crate::render::constants::*;
+use crate::render::frame::Frame;
+
+use crt::Crt;
+use scale2x::Scale2x;
+use scanlines::Scanlines;
+
+// An RGBA8 image, row by row.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Picture {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Picture {
+    pub fn new(width: usize, height: usize) -> Self {
+        Picture {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+        }
+    }
+
+    pub fn from_frame(frame: &Frame) -> Self {
+        Picture {
+            width: NES_PIXEL_WIDTH as usize,
+            height: NES_PIXEL_HEIGHT as usize,
+            pixels: frame.to_rgba8(),
+        }
+    }
+
+    // Resizes the picture, keeping the allocation when possible. The contents are unspecified.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.pixels.resize(width * height * 4, 0);
+    }
+
+    // Keeps only the `width` x `height` rectangle with its top left corner at (`x`, `y`), in place.
+    pub fn crop(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        for row in 0..height {
+            let from = ((y + row) * self.width + x) * 4;
+            self.pixels.copy_within(from..from + width * 4, row * width * 4);
+        }
+        self.resize(width, height);
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * self.width + x) * 4;
+        self.pixels[i..i + 4].try_into().unwrap()
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        let i = (y * self.width + x) * 4;
+        self.pixels[i..i + 4].copy_from_slice(&rgba);
+    }
+}
+
+// Filters are Send so a chain can run on a presentation thread.
+pub trait Filter: Send {
+    fn name(&self) -> &str;
+
+    // Writes the filtered version of `input` into `output`, resizing `output` as needed.
+    fn apply(&mut self, input: &Picture, output: &mut Picture);
+}
+
+// Multiplies the color channels of a pixel by `factor`, leaving alpha alone. Rounds by adding a half
+// and truncating, the way `simd` does it four pixels at a time.
+pub fn darken(rgba: [u8; 4], factor: f32) -> [u8; 4] {
+    let scale = |c: u8| ((c as f32 * factor).clamp(0.0, 255.0) + 0.5) as u8;
+    [scale(rgba[0]), scale(rgba[1]), scale(rgba[2]), rgba[3]]
+}
+
+// Built-in filter chains, selectable at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterPreset {
+    None,
+    Scanlines,
+    Crt,
+    Scale2x,
+}
+
+impl FilterPreset {
+    pub const ALL: [FilterPreset; 4] = [
+        FilterPreset::None,
+        FilterPreset::Scanlines,
+        FilterPreset::Crt,
+        FilterPreset::Scale2x,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterPreset::None => "none",
+            FilterPreset::Scanlines => "scanlines",
+            FilterPreset::Crt => "crt",
+            FilterPreset::Scale2x => "scale2x",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FilterPreset> {
+        FilterPreset::ALL.into_iter().find(|preset| preset.name() == name)
+    }
+
+    pub fn next(&self) -> FilterPreset {
+        let index = FilterPreset::ALL.iter().position(|preset| preset == self).unwrap();
+        FilterPreset::ALL[(index + 1) % FilterPreset::ALL.len()]
+    }
+
+    pub fn chain(&self) -> FilterChain {
+        let mut chain = FilterChain::new();
+        match self {
+            FilterPreset::None => {}
+            FilterPreset::Scanlines => chain.push(Box::new(Scanlines::default())),
+            FilterPreset::Crt => chain.push(Box::new(Crt::default())),
+            FilterPreset::Scale2x => chain.push(Box::new(Scale2x)),
+        }
+        chain
+    }
+}
+
+impl std::str::FromStr for FilterPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FilterPreset::from_name(s).ok_or_else(|| format!("Unknown filter {}", s))
+    }
+}
+
+impl std::fmt::Display for FilterPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Runs filters in order, each one reading the previous one's output.
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn Filter>>,
+    buffers: [Picture; 2],
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        FilterChain::default()
+    }
+
+    pub fn push(&mut self, filter: Box<dyn Filter>) {
+        self.filters.push(filter);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.filters.iter().map(|filter| filter.name()).collect()
+    }
+
+    // Returns `input` run through every filter. With no filters this is `input` itself.
+    pub fn apply<'a>(&'a mut self, input: &'a Picture) -> &'a Picture {
+        let [even, odd] = &mut self.buffers;
+        for (i, filter) in self.filters.iter_mut().enumerate() {
+            // Filter i writes into buffer i % 2, reading the other buffer (or the input).
+            match i {
+                0 => filter.apply(input, even),
+                _ if i % 2 == 1 => filter.apply(even, odd),
+                _ => filter.apply(odd, even),
+            }
+        }
+        match self.filters.len() {
+            0 => input,
+            n => &self.buffers[(n - 1) % 2],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Invert;
+
+    impl Filter for Invert {
+        fn name(&self) -> &str {
+            "invert"
+        }
+
+        fn apply(&mut self, input: &Picture, output: &mut Picture) {
+            output.resize(input.width, input.height);
+            for (out, pixel) in output.pixels.chunks_exact_mut(4).zip(input.pixels.chunks_exact(4)) {
+                out.copy_from_slice(&[255 - pixel[0], 255 - pixel[1], 255 - pixel[2], pixel[3]]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_crop() {
+        let mut picture = Picture::new(3, 3);
+        for i in 0..9 {
+            picture.set_pixel(i % 3, i / 3, [i as u8; 4]);
+        }
+        picture.crop(1, 1, 2, 1);
+        assert_eq!((picture.width, picture.height), (2, 1));
+        assert_eq!(picture.pixels, [4, 4, 4, 4, 5, 5, 5, 5]);
+    }
+
+    #[test]
+    fn test_chain_runs_filters_in_order() {
+        let mut input = Picture::new(2, 1);
+        input.set_pixel(0, 0, [10, 20, 30, 255]);
+
+        let mut chain = FilterChain::new();
+        assert_eq!(chain.apply(&input), &input);
+
+        chain.push(Box::new(Invert));
+        chain.push(Box::new(Scanlines::default()));
+        let output = chain.apply(&input).clone();
+        assert_eq!((output.width, output.height), (2, 2));
+        assert_eq!(output.pixel(0, 0), [245, 235, 225, 255]);
+        assert_eq!(chain.names(), ["invert", "scanlines"]);
+
+        // Two inverts cancel out.
+        chain = FilterChain::new();
+        chain.push(Box::new(Invert));
+        chain.push(Box::new(Invert));
+        assert_eq!(chain.apply(&input), &input);
+    }
+
+    #[test]
+    fn test_presets_cycle() {
+        assert_eq!(FilterPreset::from_name("crt"), Some(FilterPreset::Crt));
+        assert_eq!(FilterPreset::Scale2x.next(), FilterPreset::None);
+        assert!(FilterPreset::None.chain().is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+//! Doubles the picture vertically and darkens every other line, like the gaps between a CRT's
+//! scanlines.
+
+use crate::render::filters::{simd, Filter, Picture};
+
+#[derive(Debug, Clone)]
+pub struct Scanlines {
+    // Brightness of the gap lines, from 0.0 (black) to 1.0 (no effect).
+    pub intensity: f32,
+}
+
+impl Default for Scanlines {
+    fn default() -> Self {
+        Scanlines { intensity: 0.6 }
+    }
+}
+
+impl Filter for Scanlines {
+    fn name(&self) -> &str {
+        "scanlines"
+    }
+
+    fn apply(&mut self, input: &Picture, output: &mut Picture) {
+        output.resize(input.width, input.height * 2);
+        let row_bytes = input.width * 4;
+        for y in 0..input.height {
+            let row = &input.pixels[y * row_bytes..(y + 1) * row_bytes];
+            let (bright, dark) = output.pixels[2 * y * row_bytes..(2 * y + 2) * row_bytes].split_at_mut(row_bytes);
+            bright.copy_from_slice(row);
+            simd::darken_row(row, dark, self.intensity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scanlines_darken_odd_rows() {
+        let mut input = Picture::new(1, 2);
+        input.set_pixel(0, 0, [100, 200, 50, 255]);
+        input.set_pixel(0, 1, [10, 10, 10, 255]);
+
+        let mut output = Picture::default();
+        Scanlines { intensity: 0.5 }.apply(&input, &mut output);
+        assert_eq!((output.width, output.height), (1, 4));
+        assert_eq!(output.pixel(0, 0), [100, 200, 50, 255]);
+        assert_eq!(output.pixel(0, 1), [50, 100, 25, 255]);
+        assert_eq!(output.pixel(0, 2), [10, 10, 10, 255]);
+        assert_eq!(output.pixel(0, 3), [5, 5, 5, 255]);
+    }
+}
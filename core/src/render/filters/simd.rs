@@ -0,0 +1,255 @@
+//! The filters' per-pixel inner loops, four pixels at a time with SSE2 where the CPU has it and
+//! one at a time otherwise. Support is checked once, at runtime, so one build runs everywhere;
+//! both paths give exactly the same pixels. Rows are RGBA8, as in `Picture`.
+
+use std::sync::OnceLock;
+
+// Whether the vector paths are used. Always on x86-64, which requires SSE2.
+pub fn enabled() -> bool {
+    static SSE2: OnceLock<bool> = OnceLock::new();
+    *SSE2.get_or_init(detect_sse2)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn detect_sse2() -> bool {
+    std::is_x86_feature_detected!("sse2")
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn detect_sse2() -> bool {
+    false
+}
+
+// Writes `input` darkened by `factor` (see `darken`) into `output`, which is as long.
+pub fn darken_row(input: &[u8], output: &mut [u8], factor: f32) {
+    assert_eq!(input.len(), output.len());
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    if enabled() {
+        // Safe: SSE2 is there, and the lengths match.
+        return unsafe { sse2::darken_row(input, output, factor) };
+    }
+    scalar::darken_row(input, output, factor);
+}
+
+// Darkens each pixel by its own factor, in place.
+pub fn darken_pixels(pixels: &mut [u8], factors: &[f32]) {
+    assert_eq!(pixels.len(), factors.len() * 4);
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    if enabled() {
+        // Safe: SSE2 is there, and the lengths match.
+        return unsafe { sse2::darken_pixels(pixels, factors) };
+    }
+    scalar::darken_pixels(pixels, factors);
+}
+
+// Scale2x of one row: `row` between `above` and `below` (the row itself at the picture's edges)
+// becomes the `top` and `bottom` output rows, each twice as long.
+pub fn scale2x_row(above: &[u8], row: &[u8], below: &[u8], top: &mut [u8], bottom: &mut [u8]) {
+    assert!(above.len() == row.len() && below.len() == row.len());
+    assert!(top.len() == row.len() * 2 && bottom.len() == row.len() * 2);
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    if enabled() {
+        // Safe: SSE2 is there, and the lengths match.
+        return unsafe { sse2::scale2x_row(above, row, below, top, bottom) };
+    }
+    scalar::scale2x_row(above, row, below, top, bottom, 0..row.len() / 4);
+}
+
+mod scalar {
+    use std::ops::Range;
+
+    use crate::render::filters::darken;
+
+    fn pixel(row: &[u8], x: usize) -> [u8; 4] {
+        row[x * 4..x * 4 + 4].try_into().unwrap()
+    }
+
+    pub fn darken_row(input: &[u8], output: &mut [u8], factor: f32) {
+        for (out, pixel) in output.chunks_exact_mut(4).zip(input.chunks_exact(4)) {
+            out.copy_from_slice(&darken(pixel.try_into().unwrap(), factor));
+        }
+    }
+
+    pub fn darken_pixels(pixels: &mut [u8], factors: &[f32]) {
+        for (pixel, &factor) in pixels.chunks_exact_mut(4).zip(factors) {
+            let darkened = darken((&*pixel).try_into().unwrap(), factor);
+            pixel.copy_from_slice(&darkened);
+        }
+    }
+
+    // Only the pixels in `xs`.
+    pub fn scale2x_row(above: &[u8], row: &[u8], below: &[u8], top: &mut [u8], bottom: &mut [u8], xs: Range<usize>) {
+        let width = row.len() / 4;
+        for x in xs {
+            // B is above, D left, F right and H below E. Edges repeat the border pixel.
+            let e = pixel(row, x);
+            let b = pixel(above, x);
+            let d = pixel(row, x.saturating_sub(1));
+            let f = pixel(row, (x + 1).min(width - 1));
+            let h = pixel(below, x);
+
+            let (e0, e1, e2, e3) = if b != h && d != f {
+                (
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e },
+                )
+            } else {
+                (e, e, e, e)
+            };
+            top[x * 8..x * 8 + 4].copy_from_slice(&e0);
+            top[x * 8 + 4..x * 8 + 8].copy_from_slice(&e1);
+            bottom[x * 8..x * 8 + 4].copy_from_slice(&e2);
+            bottom[x * 8 + 4..x * 8 + 8].copy_from_slice(&e3);
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod sse2 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    use super::scalar;
+
+    // Four channels, widened to 32 bits, times `factor`, rounded the way `darken` rounds.
+    #[target_feature(enable = "sse2")]
+    fn scale(channels: __m128i, factor: __m128) -> __m128i {
+        let x = _mm_mul_ps(_mm_cvtepi32_ps(channels), factor);
+        let x = _mm_min_ps(_mm_max_ps(x, _mm_setzero_ps()), _mm_set1_ps(255.0));
+        _mm_cvttps_epi32(_mm_add_ps(x, _mm_set1_ps(0.5)))
+    }
+
+    // Four pixels, each times its own factor vector, alpha untouched.
+    #[target_feature(enable = "sse2")]
+    fn darken4(pixels: __m128i, factors: [__m128; 4]) -> __m128i {
+        let zero = _mm_setzero_si128();
+        let (low, high) = (_mm_unpacklo_epi8(pixels, zero), _mm_unpackhi_epi8(pixels, zero));
+        let p0 = scale(_mm_unpacklo_epi16(low, zero), factors[0]);
+        let p1 = scale(_mm_unpackhi_epi16(low, zero), factors[1]);
+        let p2 = scale(_mm_unpacklo_epi16(high, zero), factors[2]);
+        let p3 = scale(_mm_unpackhi_epi16(high, zero), factors[3]);
+        _mm_packus_epi16(_mm_packs_epi32(p0, p1), _mm_packs_epi32(p2, p3))
+    }
+
+    // The color channels times `factor` and alpha times 1.
+    #[target_feature(enable = "sse2")]
+    fn factor(factor: f32) -> __m128 {
+        _mm_set_ps(1.0, factor, factor, factor)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn darken_row(input: &[u8], output: &mut [u8], factor: f32) {
+        let vector = self::factor(factor);
+        let end = input.len() / 16 * 16;
+        for i in (0..end).step_by(16) {
+            let pixels = _mm_loadu_si128(input.as_ptr().add(i) as *const __m128i);
+            let darkened = darken4(pixels, [vector; 4]);
+            _mm_storeu_si128(output.as_mut_ptr().add(i) as *mut __m128i, darkened);
+        }
+        scalar::darken_row(&input[end..], &mut output[end..], factor);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn darken_pixels(pixels: &mut [u8], factors: &[f32]) {
+        let end = pixels.len() / 16 * 16;
+        for i in (0..end).step_by(16) {
+            let f = &factors[i / 4..i / 4 + 4];
+            let vectors = [factor(f[0]), factor(f[1]), factor(f[2]), factor(f[3])];
+            let pointer = pixels.as_mut_ptr().add(i) as *mut __m128i;
+            _mm_storeu_si128(pointer, darken4(_mm_loadu_si128(pointer), vectors));
+        }
+        scalar::darken_pixels(&mut pixels[end..], &factors[end / 4..]);
+    }
+
+    // `mask ? a : b`, lane by lane.
+    #[target_feature(enable = "sse2")]
+    fn select(mask: __m128i, a: __m128i, b: __m128i) -> __m128i {
+        _mm_or_si128(_mm_and_si128(mask, a), _mm_andnot_si128(mask, b))
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn scale2x_row(above: &[u8], row: &[u8], below: &[u8], top: &mut [u8], bottom: &mut [u8]) {
+        let width = row.len() / 4;
+        // Four pixels at a time from x = 1, while the right neighbors are all inside the row. The
+        // first pixel and whatever is left over take the scalar path.
+        scalar::scale2x_row(above, row, below, top, bottom, 0..width.min(1));
+        let mut x = 1;
+        let load = |row: &[u8], x: usize| _mm_loadu_si128(row.as_ptr().add(x * 4) as *const __m128i);
+        while x + 5 <= width {
+            let e = load(row, x);
+            let b = load(above, x);
+            let d = load(row, x - 1);
+            let f = load(row, x + 1);
+            let h = load(below, x);
+
+            let flat = _mm_or_si128(_mm_cmpeq_epi32(b, h), _mm_cmpeq_epi32(d, f));
+            let edge = _mm_andnot_si128(flat, _mm_set1_epi32(-1));
+            let e0 = select(_mm_and_si128(edge, _mm_cmpeq_epi32(d, b)), d, e);
+            let e1 = select(_mm_and_si128(edge, _mm_cmpeq_epi32(b, f)), f, e);
+            let e2 = select(_mm_and_si128(edge, _mm_cmpeq_epi32(d, h)), d, e);
+            let e3 = select(_mm_and_si128(edge, _mm_cmpeq_epi32(h, f)), f, e);
+
+            let store = |out: &mut [u8], i: usize, pixels: __m128i| {
+                _mm_storeu_si128(out.as_mut_ptr().add(i) as *mut __m128i, pixels)
+            };
+            store(top, x * 8, _mm_unpacklo_epi32(e0, e1));
+            store(top, x * 8 + 16, _mm_unpackhi_epi32(e0, e1));
+            store(bottom, x * 8, _mm_unpacklo_epi32(e2, e3));
+            store(bottom, x * 8 + 16, _mm_unpackhi_epi32(e2, e3));
+            x += 4;
+        }
+        scalar::scale2x_row(above, row, below, top, bottom, x.min(width)..width);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pixels from a few colors, so neighbors often match the way Scale2x looks for.
+    fn row(seed: u32, width: usize) -> Vec<u8> {
+        let colors = [[0, 0, 0, 255], [255, 255, 255, 255], [200, 76, 12, 255], [3, 128, 250, 0]];
+        let mut state = seed;
+        (0..width)
+            .flat_map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                colors[(state >> 16) as usize % colors.len()]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_vector_paths_match_scalar() {
+        if !enabled() {
+            return;
+        }
+        // Every channel value, and widths that leave the vector loops a remainder.
+        let all: Vec<u8> = (0..=255).collect();
+        for factor in [0.0, 0.25, 0.5, 0.6, 0.7, 0.999, 1.0, 1.5, -1.0] {
+            let (mut vector, mut expected) = (vec![0; 256], vec![0; 256]);
+            darken_row(&all, &mut vector, factor);
+            scalar::darken_row(&all, &mut expected, factor);
+            assert_eq!(vector, expected, "factor {}", factor);
+        }
+        let factors: Vec<f32> = (0..64).map(|i| i as f32 / 50.0).collect();
+        let (mut vector, mut expected) = (all.clone(), all.clone());
+        darken_pixels(&mut vector, &factors);
+        scalar::darken_pixels(&mut expected, &factors);
+        assert_eq!(vector, expected);
+
+        for width in [1, 2, 5, 6, 9, 13, 256] {
+            let (above, middle, below) = (row(1, width), row(2, width), row(3, width));
+            let mut vector = [vec![0; width * 8], vec![0; width * 8]];
+            let mut expected = vector.clone();
+            let [top, bottom] = &mut vector;
+            scale2x_row(&above, &middle, &below, top, bottom);
+            let [top, bottom] = &mut expected;
+            scalar::scale2x_row(&above, &middle, &below, top, bottom, 0..width);
+            assert_eq!(vector, expected, "width {}", width);
+        }
+    }
+}
@@ -1,5 +1,4 @@
-use macroquad::color::Color;
-use macroquad::color::colors::*;
+use crate::render::color::{Color, PINK};
 use crate::render::constants::*;
 use crate::render::palette::SYSTEM_PALETTE;
 
@@ -21,35 +20,22 @@ impl Frame {
         self.data[base] = color;
     }
     
-    // Reference: https://www.nesdev.org/wiki/PPU_memory_map
-    fn show_tile(chr_rom: &Vec<u8>, bank: usize, tile_n: usize) -> Frame {
-        assert!(bank <= 1);
-     
-        let mut frame = Frame::new();
-        let bank = (bank * PATTERN_TABLE_SIZE) as usize;
-    
-        let tile = &chr_rom[(bank + tile_n * 16)..=(bank + tile_n * 16 + 15)];
-     
-        for y in 0..=7 {
-            let mut lower = tile[y];
-            let mut upper = tile[y + 8];
-     
-            for x in (0..=7).rev() {
-                // If neither bit is set to 1: The pixel is background/transparent.
-                // If only the bit in the first plane is set to 1: The pixel's color index is 1.
-                // If only the bit in the second plane is set to 1: The pixel's color index is 2.
-                // If both bits are set to 1: The pixel's color index is 3.
-                let value = (1 & upper) << 1 | (1 & lower);
-                upper >>= 1;
-                lower >>= 1;
-                frame.set_pixel(x, y, SYSTEM_PALETTE[value as usize])
-            }
+    // Copies the visible 256x240 picture into `out` as RGBA8, row by row.
+    pub fn write_rgba8(&self, out: &mut [u8]) {
+        let visible = (NES_PIXEL_WIDTH * NES_PIXEL_HEIGHT) as usize;
+        for (pixel, color) in out.chunks_exact_mut(4).zip(&self.data[..visible]) {
+            let rgba: [u8; 4] = (*color).into();
+            pixel.copy_from_slice(&rgba);
         }
-     
-        frame
     }
-    
-    pub fn show_tile_bank(chr_rom: &Vec<u8>, bank: usize) -> Frame {
+
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let mut rgba = vec![0; (NES_PIXEL_WIDTH * NES_PIXEL_HEIGHT) as usize * 4];
+        self.write_rgba8(&mut rgba);
+        rgba
+    }
+
+    pub fn show_tile_bank(chr_rom: &[u8], bank: usize) -> Frame {
 
         assert!(bank <= 1);
     
@@ -0,0 +1,107 @@
+use crate::ppu::{registers::controller::PPUCTRL, PPU};
+use constants::{NES_PIXEL_HEIGHT, NES_PIXEL_WIDTH};
+use frame::Frame;
+
+pub mod palette;
+pub mod color;
+pub mod frame;
+pub mod blend;
+pub mod osd;
+pub mod debug_views;
+pub mod constants;
+pub mod filters;
+pub mod recorder;
+pub mod screenshot;
+pub mod tiles;
+
+// Which layers `Frame::render` draws, for debugging rendering and ripping graphics. The game can't
+// tell: only the picture changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layers {
+    pub background: bool,
+    pub sprites: bool,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Layers { background: true, sprites: true }
+    }
+}
+
+impl Frame {
+
+    pub fn fetch_tile(ppu: &PPU, bank: usize, tile_index: usize) -> &[u8] {
+        &ppu.chr()[(bank + tile_index * 16)..=(bank + tile_index * 16 + 15)]
+    }
+    // Reads PPU to mutate frame object.
+    pub fn render(ppu: &PPU, frame: &mut Frame) {
+        let palette = ppu.model.palette();
+
+        // Draw background =========================================================
+
+        // Without the background, the backdrop color shows through.
+        if !ppu.layers.background {
+            let backdrop = palette[ppu.palette_table[0] as usize];
+            let visible = (NES_PIXEL_WIDTH * NES_PIXEL_HEIGHT) as usize;
+            frame.data[..visible].fill(backdrop);
+        }
+
+        let bank: usize = ppu.controller.contains(PPUCTRL::BACKGROUND_PATTERN_ADDR) as usize * 0x1000;
+    
+        for i in (0..960).filter(|_| ppu.layers.background) { // just for now, lets use the first nametable
+            let tile_index = ppu.vram[i] as usize;
+            // println!("tile: {}", tile);
+            let tile_x = i % 32;
+            let tile_y = i / 32;
+
+            let bg_palette = ppu.bg_palette(tile_x, tile_y);
+
+            // println!("bank: {}, tile: {}", bank, tile);
+            // println!("{}", ppu.chr_rom.len());
+
+            let tile = ppu.tiles.tile(bank, tile_index);
+
+            for y in 0..=7 {
+                for x in 0..=7 {
+                    let rgb = palette[bg_palette[tile[y * 8 + x] as usize] as usize];
+                    frame.set_pixel(tile_x * 8 + x, tile_y * 8 + y, rgb)
+                }
+            }
+        }  
+
+        let bank: usize = ppu.controller.contains(PPUCTRL::SPRITE_PATTERN_ADDR) as usize * 0x1000;
+    
+        // Draw foreground (sprites) ====================================================
+        // Reference: https://www.nesdev.org/wiki/PPU_OAM
+        for i in (0..ppu.oam_data.len()).step_by(4).filter(|_| ppu.layers.sprites) {
+            let tile_y = ppu.oam_data[i] as usize;
+            let tile_index = ppu.oam_data[i + 1] as usize;
+            let attr_byte: u8 = ppu.oam_data[i + 2];
+            let tile_x = ppu.oam_data[i + 3] as usize;
+
+            let flip_vertical = (attr_byte >> 7 & 1) == 1;
+            let flip_horizontal = (attr_byte >> 6 & 1) == 1;
+
+            let palette_idx = attr_byte & 0b11;
+            let sprite_palette = ppu.sprite_palette(palette_idx);
+
+            let tile = ppu.tiles.tile(bank, tile_index);
+
+            for y in 0..=7 {
+                for x in 0..=7 {
+                    let rgb = match tile[y * 8 + x] {
+                        0 => continue, // skip coloring the pixel
+                        value => palette[sprite_palette[value as usize] as usize],
+                    };
+
+                    match (flip_horizontal, flip_vertical) {
+                        (false, false) => frame.set_pixel(tile_x + x, tile_y + y, rgb),
+                        (true, false) => frame.set_pixel(tile_x + 7 - x, tile_y + y, rgb),
+                        (false, true) => frame.set_pixel(tile_x + x, tile_y + 7 - y, rgb),
+                        (true, true) => frame.set_pixel(tile_x + 7 - x, tile_y + 7 - y, rgb),
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! On-screen display: a frame counter and short status messages drawn over the picture with a
+//! built-in 5x7 bitmap font.
+//!
+//! Anything that wants to tell the player something ("State saved", "Paused") posts a message;
+//! messages stack up in the bottom-left corner and fade out after a few seconds.
+
+use std::collections::VecDeque;
+
+use crate::render::filters::{darken, Picture};
+
+pub const GLYPH_WIDTH: usize = 5;
+pub const GLYPH_HEIGHT: usize = 7;
+// Pixels between characters and between lines.
+const SPACING: usize = 1;
+const MARGIN: usize = 4;
+
+// How long a message stays up, in seconds, and how many are shown at once.
+pub const MESSAGE_DURATION: f64 = 2.5;
+pub const MAX_MESSAGES: usize = 4;
+
+const TEXT_COLOR: [u8; 4] = [255, 255, 255, 255];
+const SHADOW_COLOR: [u8; 4] = [0, 0, 0, 255];
+
+// Rows of a glyph, top to bottom. Bit 4 is the leftmost pixel.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        ' ' => [0x00; GLYPH_HEIGHT],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '=' => [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '#' => [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '\'' => [0x0C, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '$' => [0x04, 0x0F, 0x14, 0x0E, 0x05, 0x1E, 0x04],
+        // Anything else, including '?'.
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+// Width in pixels of `text` drawn with `draw_text`.
+pub fn text_width(text: &str) -> usize {
+    let chars = text.chars().count();
+    (chars * (GLYPH_WIDTH + SPACING)).saturating_sub(SPACING)
+}
+
+// Draws `text` with its top-left corner at (x, y), with a one-pixel drop shadow. `brightness`
+// (0.0 to 1.0) fades the text. Pixels off the picture are clipped.
+pub fn draw_text(picture: &mut Picture, x: usize, y: usize, text: &str, brightness: f32) {
+    draw_text_color(picture, x, y, text, darken(TEXT_COLOR, brightness));
+}
+
+// `draw_text` in `color`.
+pub fn draw_text_color(picture: &mut Picture, x: usize, y: usize, text: &str, color: [u8; 4]) {
+    // An ellipsis is drawn as three dots.
+    let text = text.replace('…', "...");
+    for (color, offset) in [(SHADOW_COLOR, 1), (color, 0)] {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + offset + i * (GLYPH_WIDTH + SPACING);
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    let (px, py) = (left + column, y + offset + row);
+                    if bits & (0x10 >> column) != 0 && px < picture.width && py < picture.height {
+                        picture.set_pixel(px, py, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Something drawn over the picture on behalf of a script, in NES pixel coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Text { x: i32, y: i32, text: String, color: [u8; 4] },
+    // An outline, or a filled box when `filled`.
+    Rect { x: i32, y: i32, width: i32, height: i32, color: [u8; 4], filled: bool },
+}
+
+impl Shape {
+    pub fn draw(&self, picture: &mut Picture) {
+        match self {
+            Shape::Text { x, y, text, color } => {
+                if *x >= 0 && *y >= 0 {
+                    draw_text_color(picture, *x as usize, *y as usize, text, *color);
+                }
+            }
+            Shape::Rect { x, y, width, height, color, filled } => {
+                let (right, bottom) = (x + width - 1, y + height - 1);
+                for py in (*y).max(0)..=bottom.min(picture.height as i32 - 1) {
+                    for px in (*x).max(0)..=right.min(picture.width as i32 - 1) {
+                        if *filled || px == *x || px == right || py == *y || py == bottom {
+                            picture.set_pixel(px as usize, py as usize, *color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Message {
+    text: String,
+    // Seconds left on screen.
+    remaining: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Osd {
+    messages: VecDeque<Message>,
+    pub show_fps: bool,
+    fps: f64,
+    // Drawn under the messages every frame until replaced.
+    pub overlay: Vec<Shape>,
+    // A line kept up in the top-left corner until cleared, e.g. netplay's latency.
+    pub status: Option<String>,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Osd::default()
+    }
+
+    // Shows `text` for a few seconds. The oldest message makes room when too many are up.
+    pub fn post(&mut self, text: impl Into<String>) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(Message {
+            text: text.into(),
+            remaining: MESSAGE_DURATION,
+        });
+    }
+
+    pub fn set_fps(&mut self, fps: f64) {
+        self.fps = fps;
+    }
+
+    pub fn messages(&self) -> impl Iterator<Item = &str> {
+        self.messages.iter().map(|message| message.text.as_str())
+    }
+
+    // Ages messages by `elapsed` seconds of wall-clock time.
+    pub fn tick(&mut self, elapsed: f64) {
+        for message in self.messages.iter_mut() {
+            message.remaining -= elapsed;
+        }
+        self.messages.retain(|message| message.remaining > 0.0);
+    }
+
+    pub fn draw(&self, picture: &mut Picture) {
+        for shape in &self.overlay {
+            shape.draw(picture);
+        }
+
+        for shape in &self.overlay {
+            shape.draw(picture);
+        }
+
+        if let Some(status) = &self.status {
+            draw_text(picture, MARGIN, MARGIN, status, 1.0);
+        }
+
+        if self.show_fps {
+            let text = format!("{:.0} FPS", self.fps);
+            let x = picture.width.saturating_sub(MARGIN + text_width(&text));
+            draw_text(picture, x, MARGIN, &text, 1.0);
+        }
+
+        // Newest message at the bottom. Messages fade out over their last half second.
+        let line_height = GLYPH_HEIGHT + SPACING + 1;
+        let bottom = picture.height.saturating_sub(MARGIN + GLYPH_HEIGHT);
+        for (i, message) in self.messages.iter().rev().enumerate() {
+            let y = bottom.saturating_sub(i * line_height);
+            let brightness = (message.remaining / 0.5).min(1.0) as f32;
+            draw_text(picture, MARGIN, y, &message.text, brightness);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_text_sets_glyph_pixels() {
+        let mut picture = Picture::new(16, 10);
+        draw_text(&mut picture, 0, 0, "1", 1.0);
+        // The top row of '1' is a single pixel in the middle column.
+        assert_eq!(picture.pixel(2, 0), TEXT_COLOR);
+        assert_eq!(picture.pixel(0, 0), [0, 0, 0, 0]);
+        // Shadow one pixel down and right.
+        assert_eq!(picture.pixel(3, 1), SHADOW_COLOR);
+        assert_eq!(text_width("10"), 11);
+    }
+
+    #[test]
+    fn test_messages_expire_and_stack() {
+        let mut osd = Osd::new();
+        osd.post("State saved");
+        osd.tick(2.0);
+        osd.post("Paused");
+        assert_eq!(osd.messages().collect::<Vec<_>>(), ["State saved", "Paused"]);
+        osd.tick(1.0);
+        assert_eq!(osd.messages().collect::<Vec<_>>(), ["Paused"]);
+
+        for i in 0..MAX_MESSAGES + 1 {
+            osd.post(format!("{}", i));
+        }
+        assert_eq!(osd.messages().count(), MAX_MESSAGES);
+        assert_eq!(osd.messages().next(), Some("1"));
+    }
+
+    #[test]
+    fn test_overlay_shapes() {
+        let mut picture = Picture::new(8, 8);
+        let red = [255, 0, 0, 255];
+        Shape::Rect { x: -2, y: 1, width: 5, height: 3, color: red, filled: false }.draw(&mut picture);
+        // The left edge is off the picture; the right edge and the corners are drawn.
+        assert_eq!(picture.pixel(2, 2), red);
+        assert_eq!(picture.pixel(0, 1), red);
+        assert_eq!(picture.pixel(1, 2), [0, 0, 0, 0]);
+        Shape::Rect { x: 6, y: 6, width: 4, height: 4, color: red, filled: true }.draw(&mut picture);
+        assert_eq!(picture.pixel(7, 7), red);
+    }
+
+    #[test]
+    fn test_draw_clips_at_edges() {
+        let mut picture = Picture::new(256, 240);
+        let mut osd = Osd::new();
+        osd.show_fps = true;
+        osd.set_fps(60.0);
+        osd.post("A message much too long to fit on one line of the NES screen at all");
+        osd.draw(&mut picture);
+        assert_ne!(picture.pixels, Picture::new(256, 240).pixels);
+    }
+}
@@ -0,0 +1,69 @@
+//! Storing the 2C02 system palette, and the RGB palettes of the arcade PPUs.
+//! Rererence: https://www.nesdev.org/wiki/PPU_palettes#Palettes
+//! https://www.nesdev.org/wiki/PPU_palettes#2C03_and_2C05
+
+use crate::render::color::Color;
+
+lazy_static! {
+    pub static ref SYSTEM_PALETTE: [Color; 64] = [
+    Color::from_rgba(0x80, 0x80, 0x80, 255), Color::from_rgba(0x00, 0x3D, 0xA6, 255), Color::from_rgba(0x00, 0x12, 0xB0, 255), Color::from_rgba(0x44, 0x00, 0x96, 255), Color::from_rgba(0xA1, 0x00, 0x5E, 255),
+    Color::from_rgba(0xC7, 0x00, 0x28, 255), Color::from_rgba(0xBA, 0x06, 0x00, 255), Color::from_rgba(0x8C, 0x17, 0x00, 255), Color::from_rgba(0x5C, 0x2F, 0x00, 255), Color::from_rgba(0x10, 0x45, 0x00, 255),
+    Color::from_rgba(0x05, 0x4A, 0x00, 255), Color::from_rgba(0x00, 0x47, 0x2E, 255), Color::from_rgba(0x00, 0x41, 0x66, 255), Color::from_rgba(0x00, 0x00, 0x00, 255), Color::from_rgba(0x05, 0x05, 0x05, 255),
+    Color::from_rgba(0x05, 0x05, 0x05, 255), Color::from_rgba(0xC7, 0xC7, 0xC7, 255), Color::from_rgba(0x00, 0x77, 0xFF, 255), Color::from_rgba(0x21, 0x55, 0xFF, 255), Color::from_rgba(0x82, 0x37, 0xFA, 255),
+    Color::from_rgba(0xEB, 0x2F, 0xB5, 255), Color::from_rgba(0xFF, 0x29, 0x50, 255), Color::from_rgba(0xFF, 0x22, 0x00, 255), Color::from_rgba(0xD6, 0x32, 0x00, 255), Color::from_rgba(0xC4, 0x62, 0x00, 255),
+    Color::from_rgba(0x35, 0x80, 0x00, 255), Color::from_rgba(0x05, 0x8F, 0x00, 255), Color::from_rgba(0x00, 0x8A, 0x55, 255), Color::from_rgba(0x00, 0x99, 0xCC, 255), Color::from_rgba(0x21, 0x21, 0x21, 255),
+    Color::from_rgba(0x09, 0x09, 0x09, 255), Color::from_rgba(0x09, 0x09, 0x09, 255), Color::from_rgba(0xFF, 0xFF, 0xFF, 255), Color::from_rgba(0x0F, 0xD7, 0xFF, 255), Color::from_rgba(0x69, 0xA2, 0xFF, 255),
+    Color::from_rgba(0xD4, 0x80, 0xFF, 255), Color::from_rgba(0xFF, 0x45, 0xF3, 255), Color::from_rgba(0xFF, 0x61, 0x8B, 255), Color::from_rgba(0xFF, 0x88, 0x33, 255), Color::from_rgba(0xFF, 0x9C, 0x12, 255),
+    Color::from_rgba(0xFA, 0xBC, 0x20, 255), Color::from_rgba(0x9F, 0xE3, 0x0E, 255), Color::from_rgba(0x2B, 0xF0, 0x35, 255), Color::from_rgba(0x0C, 0xF0, 0xA4, 255), Color::from_rgba(0x05, 0xFB, 0xFF, 255),
+    Color::from_rgba(0x5E, 0x5E, 0x5E, 255), Color::from_rgba(0x0D, 0x0D, 0x0D, 255), Color::from_rgba(0x0D, 0x0D, 0x0D, 255), Color::from_rgba(0xFF, 0xFF, 0xFF, 255), Color::from_rgba(0xA6, 0xFC, 0xFF, 255),
+    Color::from_rgba(0xB3, 0xEC, 0xFF, 255), Color::from_rgba(0xDA, 0xAB, 0xEB, 255), Color::from_rgba(0xFF, 0xA8, 0xF9, 255), Color::from_rgba(0xFF, 0xAB, 0xB3, 255), Color::from_rgba(0xFF, 0xD2, 0xB0, 255),
+    Color::from_rgba(0xFF, 0xEF, 0xA6, 255), Color::from_rgba(0xFF, 0xF7, 0x9C, 255), Color::from_rgba(0xD7, 0xE8, 0x95, 255), Color::from_rgba(0xA6, 0xED, 0xAF, 255), Color::from_rgba(0xA2, 0xF2, 0xDA, 255),
+    Color::from_rgba(0x99, 0xFF, 0xFC, 255), Color::from_rgba(0xDD, 0xDD, 0xDD, 255), Color::from_rgba(0x11, 0x11, 0x11, 255), Color::from_rgba(0x11, 0x11, 0x11, 255)
+];
+}
+
+// The RP2C03 and RC2C05 palette, 3 bits per channel as the PPU outputs it.
+const RGB_PALETTE_333: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+// The RP2C04 PPUs have the same colors in a scrambled order, different on each of the four, so a
+// game only looks right on the PPU it was made for. Each entry is the RP2C03 color it shows.
+const RP2C04_ORDERS: [[u8; 64]; 4] = [
+    [
+        0x35, 0x23, 0x16, 0x22, 0x1C, 0x09, 0x1D, 0x15, 0x20, 0x00, 0x27, 0x05, 0x04, 0x28, 0x08, 0x20,
+        0x21, 0x3E, 0x1F, 0x29, 0x3C, 0x32, 0x36, 0x12, 0x3F, 0x2B, 0x2E, 0x1E, 0x3D, 0x2D, 0x24, 0x01,
+        0x0E, 0x31, 0x33, 0x2A, 0x2C, 0x0C, 0x1B, 0x14, 0x2E, 0x07, 0x34, 0x06, 0x13, 0x02, 0x26, 0x2E,
+        0x2E, 0x19, 0x10, 0x0A, 0x39, 0x03, 0x37, 0x17, 0x0F, 0x11, 0x0B, 0x0D, 0x38, 0x25, 0x18, 0x3A,
+    ],
+    [
+        0x2E, 0x27, 0x18, 0x39, 0x3A, 0x25, 0x1C, 0x31, 0x16, 0x13, 0x38, 0x34, 0x20, 0x23, 0x3C, 0x0B,
+        0x0F, 0x21, 0x06, 0x3D, 0x1B, 0x29, 0x1E, 0x22, 0x1D, 0x24, 0x0E, 0x2B, 0x32, 0x08, 0x2E, 0x03,
+        0x04, 0x36, 0x26, 0x33, 0x11, 0x1F, 0x10, 0x02, 0x14, 0x3F, 0x00, 0x09, 0x12, 0x2E, 0x28, 0x20,
+        0x3E, 0x0D, 0x2A, 0x17, 0x0C, 0x01, 0x15, 0x19, 0x2E, 0x2C, 0x07, 0x37, 0x35, 0x05, 0x0A, 0x2D,
+    ],
+    [
+        0x14, 0x25, 0x3A, 0x10, 0x0B, 0x20, 0x31, 0x09, 0x01, 0x2E, 0x36, 0x08, 0x15, 0x3D, 0x3E, 0x3C,
+        0x22, 0x1C, 0x05, 0x12, 0x19, 0x18, 0x17, 0x1B, 0x00, 0x03, 0x2E, 0x02, 0x16, 0x06, 0x34, 0x35,
+        0x23, 0x0F, 0x0E, 0x37, 0x0D, 0x27, 0x26, 0x20, 0x29, 0x04, 0x21, 0x24, 0x11, 0x2D, 0x2E, 0x1F,
+        0x2C, 0x1E, 0x39, 0x33, 0x07, 0x2A, 0x28, 0x1D, 0x0A, 0x2E, 0x32, 0x38, 0x13, 0x2B, 0x3F, 0x0C,
+    ],
+    [
+        0x18, 0x03, 0x1C, 0x28, 0x2E, 0x35, 0x01, 0x17, 0x10, 0x1F, 0x2A, 0x0E, 0x36, 0x37, 0x0B, 0x39,
+        0x25, 0x1E, 0x12, 0x34, 0x2E, 0x1D, 0x06, 0x26, 0x3E, 0x1B, 0x22, 0x19, 0x04, 0x2E, 0x3A, 0x21,
+        0x05, 0x0A, 0x07, 0x02, 0x13, 0x14, 0x00, 0x15, 0x0C, 0x3D, 0x11, 0x0F, 0x0D, 0x38, 0x2D, 0x24,
+        0x33, 0x20, 0x08, 0x16, 0x3F, 0x2B, 0x20, 0x3C, 0x2E, 0x27, 0x23, 0x31, 0x29, 0x32, 0x2C, 0x09,
+    ],
+];
+
+lazy_static! {
+    pub static ref RGB_PALETTE: [Color; 64] = RGB_PALETTE_333.map(|rgb| {
+        let level = |shift: u16| (((rgb >> shift) & 0o7) * 255 / 7) as u8;
+        Color::from_rgba(level(6), level(3), level(0), 255)
+    });
+
+    pub static ref RP2C04_PALETTES: [[Color; 64]; 4] = RP2C04_ORDERS.map(|order| order.map(|index| RGB_PALETTE[index as usize]));
+}
@@ -0,0 +1,127 @@
+//! Records gameplay to an animated GIF.
+//!
+//! Every NES color is one of the 64 system palette entries, or of the 64 colors of the arcade RGB
+//! PPUs, so frames are stored losslessly as indices into a single global palette of both.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::emulator::NTSC_FRAME_RATE;
+use crate::render::constants::*;
+use crate::render::frame::Frame;
+use crate::render::palette::{RGB_PALETTE, SYSTEM_PALETTE};
+
+// Palette index used for pixels that aren't system palette colors (black).
+const FALLBACK_INDEX: u8 = 0x0F;
+
+pub struct GifRecorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    palette_index: HashMap<[u8; 4], u8>,
+    // Frames dropped between recorded ones. 0 records every frame.
+    frame_skip: u32,
+    frames_seen: u64,
+    // GIF delays are in whole centiseconds; the rounding error carries over to the next frame.
+    delay_error: f64,
+}
+
+impl GifRecorder {
+    pub fn new(path: &Path, frame_skip: u32) -> Result<Self, String> {
+        let mut palette = Vec::with_capacity((SYSTEM_PALETTE.len() + RGB_PALETTE.len()) * 3);
+        let mut palette_index = HashMap::new();
+        for (index, color) in SYSTEM_PALETTE.iter().chain(RGB_PALETTE.iter()).enumerate() {
+            let rgba: [u8; 4] = (*color).into();
+            palette.extend_from_slice(&rgba[..3]);
+            palette_index.entry(rgba).or_insert(index as u8);
+        }
+
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut encoder = gif::Encoder::new(
+            BufWriter::new(file),
+            NES_PIXEL_WIDTH as u16,
+            NES_PIXEL_HEIGHT as u16,
+            &palette,
+        )
+        .map_err(|e| e.to_string())?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+
+        Ok(GifRecorder {
+            encoder,
+            palette_index,
+            frame_skip,
+            frames_seen: 0,
+            delay_error: 0.0,
+        })
+    }
+
+    // Call once per emulated frame; frames dropped by `frame_skip` are folded into the delay of
+    // the recorded ones so playback runs at the right speed.
+    pub fn add_frame(&mut self, frame: &Frame) -> Result<(), String> {
+        let recorded = self.frames_seen.is_multiple_of(self.frame_skip as u64 + 1);
+        self.frames_seen += 1;
+        if !recorded {
+            return Ok(());
+        }
+
+        let pixels: Vec<u8> = frame.data[..(NES_PIXEL_WIDTH * NES_PIXEL_HEIGHT) as usize]
+            .iter()
+            .map(|color| {
+                let rgba: [u8; 4] = (*color).into();
+                *self.palette_index.get(&rgba).unwrap_or(&FALLBACK_INDEX)
+            })
+            .collect();
+
+        let delay = (self.frame_skip + 1) as f64 * 100.0 / NTSC_FRAME_RATE + self.delay_error;
+        let rounded = delay.round();
+        self.delay_error = delay - rounded;
+
+        let mut gif_frame = gif::Frame::from_indexed_pixels(
+            NES_PIXEL_WIDTH as u16,
+            NES_PIXEL_HEIGHT as u16,
+            pixels,
+            None,
+        );
+        gif_frame.delay = rounded as u16;
+        self.encoder.write_frame(&gif_frame).map_err(|e| e.to_string())
+    }
+
+    // Number of frames written to the file so far.
+    pub fn frames_recorded(&self) -> u64 {
+        self.frames_seen.div_ceil(self.frame_skip as u64 + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_every_other_frame() {
+        let path = std::env::temp_dir().join(format!("nes_rs_recorder_{}.gif", std::process::id()));
+        let mut frame = Frame::new();
+        for (i, pixel) in frame.data.iter_mut().enumerate() {
+            *pixel = SYSTEM_PALETTE[i % 64];
+        }
+
+        let mut recorder = GifRecorder::new(&path, 1).unwrap();
+        for _ in 0..5 {
+            recorder.add_frame(&frame).unwrap();
+        }
+        assert_eq!(recorder.frames_recorded(), 3);
+        drop(recorder);
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        let mut delays = Vec::new();
+        while let Some(gif_frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(gif_frame.buffer[..3], [0, 1, 2]);
+            delays.push(gif_frame.delay);
+        }
+        // Two NES frames are 3.33 centiseconds.
+        assert_eq!(delays, [3, 4, 3]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+//! Writes pictures out as PNG files.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+// Writes `rgba` (width * height RGBA8 pixels, row by row) to `path` as a PNG.
+pub fn write_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(rgba).map_err(|e| e.to_string())
+}
+
+// Reads the PNG at `path` as (width, height, RGBA8 pixels). Greyscale, RGB and palette images are
+// expanded to RGBA.
+pub fn read_png(path: &Path) -> Result<(u32, u32, Vec<u8>), String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).map_err(|e| format!("{}: {}", path.display(), e))?;
+    pixels.truncate(info.buffer_size());
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels,
+        png::ColorType::Rgb => pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+        png::ColorType::Indexed => return Err(format!("{}: Unexpected indexed color", path.display())),
+    };
+    Ok((info.width, info.height, rgba))
+}
+
+// Returns the first `dir/<stem>-NNNN.<extension>` that doesn't exist yet, creating `dir` if needed.
+pub fn numbered_path(dir: &Path, stem: &str, extension: &str) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    (0..10000)
+        .map(|n| dir.join(format!("{}-{:04}.{}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .ok_or_else(|| format!("{} is full", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png_round_trip_and_numbering() {
+        let dir = std::env::temp_dir().join(format!("nes_rs_screenshot_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let first = numbered_path(&dir, "shot", "png").unwrap();
+        assert_eq!(first, dir.join("shot-0000.png"));
+        let rgba = [255, 0, 0, 255, 0, 0, 255, 255];
+        write_png(&first, 2, 1, &rgba).unwrap();
+        assert_eq!(numbered_path(&dir, "shot", "png").unwrap(), dir.join("shot-0001.png"));
+
+        assert_eq!(read_png(&first).unwrap(), (2, 1, rgba.to_vec()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+//! Decoded pattern tables. A tile is stored as two bit planes, so reading a pixel means picking a
+//! bit out of each; the cache keeps all 512 tiles as palette indices instead, one byte per pixel,
+//! so rendering a frame doesn't interleave the planes again for every pixel.
+//!
+//! The PPU keeps its cache in step with the pattern tables: a CHR-RAM write re-decodes the row it
+//! touched, and anything that swaps CHR wholesale (a bank switch, loading a state) calls
+//! `PPU::chr_changed`.
+
+// Two pattern tables of 256 tiles, 16 bytes each.
+pub const TILES: usize = 512;
+const TILE_BYTES: usize = 16;
+
+pub struct TileCache {
+    // Each tile's 8x8 palette indices (0-3), row by row.
+    tiles: Vec<[u8; 64]>,
+}
+
+impl TileCache {
+    pub fn new(chr: &[u8]) -> Self {
+        let mut cache = TileCache { tiles: vec![[0; 64]; TILES] };
+        cache.rebuild(chr);
+        cache
+    }
+
+    // Decodes every tile again. CHR shorter than the pattern tables reads as 0 past its end.
+    pub fn rebuild(&mut self, chr: &[u8]) {
+        for addr in (0..TILES * TILE_BYTES).step_by(TILE_BYTES) {
+            for row in 0..8 {
+                self.update(chr, (addr + row) as u16);
+            }
+        }
+    }
+
+    // Re-decodes the row of pixels that the byte at pattern table address `addr` belongs to.
+    pub fn update(&mut self, chr: &[u8], addr: u16) {
+        let addr = addr as usize & 0x1fff;
+        let (tile, row) = (addr / TILE_BYTES, addr % 8);
+        let base = tile * TILE_BYTES + row;
+        let lower = chr.get(base).copied().unwrap_or(0);
+        let upper = chr.get(base + 8).copied().unwrap_or(0);
+        for x in 0..8 {
+            let bit = 7 - x;
+            self.tiles[tile][row * 8 + x] = (upper >> bit & 1) << 1 | (lower >> bit & 1);
+        }
+    }
+
+    // Tile `index` of the pattern table at `bank` (0 or 0x1000).
+    pub fn tile(&self, bank: usize, index: usize) -> &[u8; 64] {
+        &self.tiles[bank / TILE_BYTES + index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::ppu::PPU;
+
+    #[test]
+    fn test_tiles_follow_chr_writes() {
+        // Tile 1 of the right pattern table: the top row has the low plane set on the left half
+        // and the high plane on the even pixels.
+        let mut chr = vec![0; 0x2000];
+        chr[0x1010] = 0xf0;
+        chr[0x1018] = 0xaa;
+        let cache = TileCache::new(&chr);
+        assert_eq!(cache.tile(0x1000, 1)[..8], [3, 1, 3, 1, 2, 0, 2, 0]);
+        assert_eq!(cache.tile(0x1000, 1)[8..], [0; 56]);
+        assert_eq!(cache.tile(0, 1), &[0; 64]);
+
+        // CHR-RAM written through $2006/$2007.
+        let mut ppu = PPU::new(Vec::new(), Mirroring::Horizontal);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_ppu_addr(0x27);
+        ppu.write_to_data(0x01);
+        assert_eq!(ppu.tiles.tile(0, 2)[7 * 8..], [0, 0, 0, 0, 0, 0, 0, 1]);
+        ppu.poke(0x002f, 0x01);
+        assert_eq!(ppu.tiles.tile(0, 2)[7 * 8 + 7], 3);
+    }
+}
@@ -0,0 +1,126 @@
+//! Checks that the emulator is deterministic: two consoles powered on with the same ROM and fed the
+//! same input must agree after every frame. Movies, rewind, run-ahead and netplay all depend on it,
+//! and a difference is much easier to track down on the frame it appears than when a movie desyncs
+//! minutes later.
+//!
+//! The two runs go in lockstep, and after each frame their save states and pictures are compared.
+//! Optionally the second run is rebuilt from its own save state on a fresh console every frame,
+//! which also catches anything that affects the game but is missing from save states.
+
+use std::fmt;
+
+use crate::cartridge::Cartridge;
+use crate::emulator::Emulator;
+use crate::movie::Movie;
+use crate::savestate;
+
+// Frames run when there is no movie to set the length.
+pub const DEFAULT_FRAMES: u64 = 600;
+
+// The first frame after which the two runs disagreed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    // Frames run, counting from 1.
+    pub frame: u64,
+    // The save state sections that differ ("CPU", "PPU", ...), and "picture" if the frames do.
+    pub differences: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Runs diverge at frame {}: {} differ", self.frame, self.differences.join(", "))
+    }
+}
+
+// What differs between two consoles running the ROM with hash `rom_hash`: save state sections by
+// tag, and the picture.
+fn differences(a: &Emulator, b: &Emulator, rom_hash: u64) -> Result<Vec<String>, String> {
+    let (state_a, state_b) = (a.save_state(), b.save_state());
+    let mut differences = Vec::new();
+    if state_a != state_b {
+        let (file_a, file_b) = (savestate::open(&state_a, rom_hash)?, savestate::open(&state_b, rom_hash)?);
+        let (sections_a, sections_b) = (file_a.raw_sections(), file_b.raw_sections());
+        for (tag, payload) in &sections_a {
+            if sections_b.iter().find(|(other, _)| other == tag).map(|(_, other)| other) != Some(payload) {
+                differences.push(String::from_utf8_lossy(tag).trim_end().to_string());
+            }
+        }
+        if differences.is_empty() {
+            differences.push("section layout".to_string());
+        }
+    }
+    if a.frame.data != b.frame.data {
+        differences.push("picture".to_string());
+    }
+    Ok(differences)
+}
+
+// Plays `movie`, or no input, on two consoles running `cartridge` for `frames` frames and compares
+// them after every frame. With `reload`, the second console is replaced every frame by a fresh one
+// loaded from its save state. Returns the first frame they differ on, if any.
+pub fn verify(cartridge: &Cartridge, movie: Option<&Movie>, frames: u64, reload: bool) -> Result<Option<Divergence>, String> {
+    let rom_hash = cartridge.hash();
+    if movie.is_some_and(|movie| movie.rom_hash != rom_hash) {
+        return Err("Movie was recorded on a different ROM".to_string());
+    }
+    let four_score = movie.is_some_and(|movie| movie.four_score);
+    let new_console = || {
+        let mut emulator = Emulator::new(cartridge.clone());
+        emulator.set_four_score(four_score);
+        emulator
+    };
+    let (mut a, mut b) = (new_console(), new_console());
+
+    for frame in 0..frames {
+        let input = movie.and_then(|movie| movie.inputs.get(frame as usize)).copied().unwrap_or_default();
+        for emulator in [&mut a, &mut b] {
+            for (port, buttons) in input.into_iter().enumerate() {
+                emulator.set_buttons(port, buttons);
+            }
+            emulator.run_frame();
+        }
+
+        let differences = differences(&a, &b, rom_hash)?;
+        if !differences.is_empty() {
+            return Ok(Some(Divergence { frame: frame + 1, differences }));
+        }
+
+        if reload {
+            let mut fresh = new_console();
+            fresh.load_state(&b.save_state())?;
+            fresh.frame.data.clone_from(&b.frame.data);
+            b = fresh;
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test::create_test_cartridge;
+
+    #[test]
+    fn test_identical_runs_agree() {
+        let cartridge = create_test_cartridge();
+        assert_eq!(verify(&cartridge, None, 3, false), Ok(None));
+        assert_eq!(verify(&cartridge, None, 3, true), Ok(None));
+
+        let movie = Movie::new(cartridge.hash() ^ 1);
+        assert_eq!(verify(&cartridge, Some(&movie), 3, false), Err("Movie was recorded on a different ROM".to_string()));
+    }
+
+    #[test]
+    fn test_differences_are_named() {
+        let cartridge = create_test_cartridge();
+        let a = Emulator::new(cartridge.clone());
+        let mut b = Emulator::new(cartridge.clone());
+        assert_eq!(differences(&a, &b, cartridge.hash()), Ok(vec![]));
+
+        b.cpu.bus.cpu_wram[0x10] = 1;
+        b.frame.data[0] = crate::render::color::BLACK;
+        assert_eq!(differences(&a, &b, cartridge.hash()), Ok(vec!["BUS".to_string(), "picture".to_string()]));
+        let divergence = Divergence { frame: 7, differences: vec!["BUS".to_string(), "picture".to_string()] };
+        assert_eq!(divergence.to_string(), "Runs diverge at frame 7: BUS, picture differ");
+    }
+}
@@ -0,0 +1,414 @@
+//! Save states: a snapshot of the whole console that can be restored later, mid-frame included.
+//!
+//! File layout (little endian):
+//! | magic "NSS\x1a" | version u8 | ROM hash u64 | sections |
+//!
+//! and each section:
+//! | tag [u8; 4] | section version u16 | length u32 | payload |
+//!
+//! Each component writes its own section with `save_state` and reads it back with `load_state`:
+//! "CPU " the registers, "BUS " RAM and the controller ports, "PPU " and "EMU " the emulator's
+//! frame bookkeeping, with a disk image loaded "FDS " the RAM adapter and the disk itself, and
+//! on a VS System game "VS  " its DIP switches, coins and CHR bank, and with the Family BASIC
+//! keyboard plugged in "KBD " the row and column it has selected, and with a Power Pad in port 2
+//! "PPAD" its shift registers. On Bandai's FCG boards "FCG " has the mapper's registers. "CHIP"
+//! has the board's EEPROMs and clocks, if it has any. There is no APU yet, and NROM has no mapper
+//! registers, so neither has a section. Host-side settings, like what is plugged into port 2 or
+//! the frame blender, aren't part of the state.
+//!
+//! The formats are kept forward compatible so states from older releases keep loading:
+//! - A new field goes at the end of its section, with the section's version bumped. The loader
+//!   reads it only when `StateReader::version` is new enough, and uses a default otherwise.
+//! - A new component gets a new section. Readers skip sections they don't know, and a loader that
+//!   finds its section missing keeps its defaults.
+//! - A section newer than this build understands is an error rather than a half-read state.
+//!
+//! Version 1 files have no section headers: the same payloads, all at section version 1, follow
+//! each other in the order above. `tests/savestate/nestest-v1.nss` keeps one around.
+
+use crate::emulator::Emulator;
+
+pub mod determinism;
+pub mod rewind;
+pub mod run_ahead;
+pub mod slots;
+
+const STATE_MAGIC: [u8; 4] = [0x4E, 0x53, 0x53, 0x1A];
+pub const STATE_VERSION: u8 = 2;
+
+// One component's part of the file: its tag, and the newest version of its payload, which this
+// build writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+    pub tag: [u8; 4],
+    pub version: u16,
+}
+
+impl Section {
+    pub fn name(&self) -> &str {
+        std::str::from_utf8(&self.tag).unwrap_or("?").trim_end()
+    }
+}
+
+pub const CPU_SECTION: Section = Section { tag: *b"CPU ", version: 1 };
+pub const BUS_SECTION: Section = Section { tag: *b"BUS ", version: 1 };
+pub const PPU_SECTION: Section = Section { tag: *b"PPU ", version: 2 };
+pub const EMULATOR_SECTION: Section = Section { tag: *b"EMU ", version: 1 };
+pub const FDS_SECTION: Section = Section { tag: *b"FDS ", version: 1 };
+pub const VS_SYSTEM_SECTION: Section = Section { tag: *b"VS  ", version: 1 };
+pub const KEYBOARD_SECTION: Section = Section { tag: *b"KBD ", version: 1 };
+pub const POWER_PAD_SECTION: Section = Section { tag: *b"PPAD", version: 1 };
+pub const FCG_SECTION: Section = Section { tag: *b"FCG ", version: 1 };
+pub const CHIPS_SECTION: Section = Section { tag: *b"CHIP", version: 1 };
+
+// Appends state to a byte buffer.
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::default()
+    }
+
+    // Writes into `buffer`, replacing its contents but keeping its allocation.
+    pub fn with_buffer(mut buffer: Vec<u8>) -> Self {
+        buffer.clear();
+        StateWriter { bytes: buffer }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // A block of bytes whose length the reader knows, e.g. a fixed-size RAM.
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    // A block of bytes preceded by its length.
+    pub fn vec(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+
+    // A section header, then the payload `write` writes, at `section.version`.
+    pub fn section<F>(&mut self, section: Section, write: F)
+    where
+        F: FnOnce(&mut StateWriter),
+    {
+        self.bytes(&section.tag);
+        self.u16(section.version);
+        let len_pos = self.bytes.len();
+        self.u32(0);
+        write(self);
+        let len = (self.bytes.len() - len_pos - 4) as u32;
+        self.bytes[len_pos..len_pos + 4].copy_from_slice(&len.to_le_bytes());
+    }
+}
+
+// Reads state back in the order it was written.
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        StateReader { bytes, pos: 0, version: 1 }
+    }
+
+    // The version the section being read was written at. Fields added in later versions are only
+    // there if it is new enough.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + len;
+        let bytes = self.bytes.get(self.pos..end).ok_or("Save state is truncated")?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // Fills `bytes`, which must be as long as the block written.
+    pub fn bytes(&mut self, bytes: &mut [u8]) -> Result<(), String> {
+        bytes.copy_from_slice(self.take(bytes.len())?);
+        Ok(())
+    }
+
+    // Fills `bytes` from a block written with `StateWriter::vec`, which must be as long, since the
+    // sizes of RAMs come from the cartridge rather than the state.
+    pub fn vec(&mut self, bytes: &mut [u8]) -> Result<(), String> {
+        let len = self.u32()? as usize;
+        if len != bytes.len() {
+            return Err(format!("Save state has a {} byte block where {} bytes were expected", len, bytes.len()));
+        }
+        self.bytes(bytes)
+    }
+}
+
+// The whole console as a save state.
+pub fn save(emulator: &Emulator) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    save_into(emulator, &mut bytes);
+    bytes
+}
+
+// Like `save`, but reusing `bytes`, which doesn't allocate once it has held a state.
+pub fn save_into(emulator: &Emulator, bytes: &mut Vec<u8>) {
+    let mut state = StateWriter::with_buffer(std::mem::take(bytes));
+    state.bytes(&STATE_MAGIC);
+    state.u8(STATE_VERSION);
+    state.u64(emulator.cartridge().hash());
+    emulator.save_sections(&mut state);
+    *bytes = state.into_bytes();
+}
+
+// An opened save state, read a section at a time.
+#[derive(Debug)]
+pub struct StateFile<'a> {
+    version: u8,
+    // Everything after the header.
+    sections: &'a [u8],
+    // The section being read. In a version 1 file, the rest of the file.
+    reader: StateReader<'a>,
+}
+
+impl<'a> StateFile<'a> {
+    // A reader for `section`'s payload, or None if the file doesn't have one (it was saved before
+    // the section existed). Sections are read one after another, each to its end.
+    pub fn section(&mut self, section: Section) -> Result<Option<&mut StateReader<'a>>, String> {
+        if self.version == 1 {
+            return Ok(Some(&mut self.reader));
+        }
+        self.finish()?;
+        let Some((version, payload)) = find_section(self.sections, section.tag) else {
+            return Ok(None);
+        };
+        if version > section.version {
+            return Err(format!(
+                "Save state's {} section is version {}, newer than the {} this build reads",
+                section.name(),
+                version,
+                section.version
+            ));
+        }
+        self.reader = StateReader {
+            bytes: payload,
+            pos: 0,
+            version,
+        };
+        Ok(Some(&mut self.reader))
+    }
+
+    // Like `section`, for a section every state has.
+    pub fn required(&mut self, section: Section) -> Result<&mut StateReader<'a>, String> {
+        self.section(section)?
+            .ok_or_else(|| format!("Save state has no {} section", section.name()))
+    }
+
+    // The tag and payload of every section, in file order, for comparing states. A version 1 file
+    // has no section headers, so it comes back as a single block tagged "    ".
+    pub fn raw_sections(&self) -> Vec<([u8; 4], &'a [u8])> {
+        if self.version == 1 {
+            return vec![(*b"    ", self.sections)];
+        }
+        let mut rest = self.sections;
+        let mut sections = Vec::new();
+        while let Ok((tag, _, payload)) = next_section(&mut rest) {
+            sections.push((tag, payload));
+        }
+        sections
+    }
+
+    // Checks the last section read was read to its end.
+    pub fn finish(&self) -> Result<(), String> {
+        match self.reader.is_at_end() {
+            true => Ok(()),
+            false => Err("Save state has trailing data".to_string()),
+        }
+    }
+}
+
+// The (tag, version, payload) of the section at the start of `sections`, which is moved past it.
+fn next_section<'a>(sections: &mut &'a [u8]) -> Result<([u8; 4], u16, &'a [u8]), String> {
+    let mut header = StateReader::new(sections);
+    let mut tag = [0; 4];
+    header.bytes(&mut tag)?;
+    let version = header.u16()?;
+    let len = header.u32()? as usize;
+    let payload = header.take(len)?;
+    *sections = &sections[header.pos..];
+    Ok((tag, version, payload))
+}
+
+// The version and payload of the first section tagged `tag`. `sections` must have been checked by
+// `open`.
+fn find_section(mut sections: &[u8], tag: [u8; 4]) -> Option<(u16, &[u8])> {
+    while let Ok((found, version, payload)) = next_section(&mut sections) {
+        if found == tag {
+            return Some((version, payload));
+        }
+    }
+    None
+}
+
+// Checks the header of `bytes`, and that its sections are all there, and returns it ready to be
+// read a section at a time.
+pub fn open<'a>(bytes: &'a [u8], rom_hash: u64) -> Result<StateFile<'a>, String> {
+    if bytes.len() < 5 || bytes[0..4] != STATE_MAGIC {
+        return Err("File is not a save state".to_string());
+    }
+    let mut state = StateReader::new(bytes);
+    state.take(4)?;
+    let version = state.u8()?;
+    if version == 0 || version > STATE_VERSION {
+        return Err(format!("Unsupported save state version {}", version));
+    }
+    if state.u64()? != rom_hash {
+        return Err("Save state was made on a different ROM".to_string());
+    }
+    let sections = &bytes[state.pos..];
+    if version > 1 {
+        let mut rest = sections;
+        while !rest.is_empty() {
+            next_section(&mut rest)?;
+        }
+    }
+    Ok(StateFile {
+        version,
+        sections,
+        reader: StateReader::new(match version {
+            1 => sections,
+            _ => &[],
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader_reads_back_what_was_written() {
+        let mut writer = StateWriter::new();
+        writer.u8(0x12);
+        writer.bool(true);
+        writer.u16(0x3456);
+        writer.u32(0x789a_bcde);
+        writer.u64(u64::MAX - 1);
+        writer.vec(&[1, 2, 3]);
+        let bytes = writer.into_bytes();
+
+        let mut reader = StateReader::new(&bytes);
+        assert_eq!(reader.u8(), Ok(0x12));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u16(), Ok(0x3456));
+        assert_eq!(reader.u32(), Ok(0x789a_bcde));
+        assert_eq!(reader.u64(), Ok(u64::MAX - 1));
+        let mut block = [0; 3];
+        assert_eq!(reader.vec(&mut block), Ok(()));
+        assert_eq!(block, [1, 2, 3]);
+        assert!(reader.is_at_end());
+        assert!(reader.u8().is_err());
+
+        let mut reader = StateReader::new(&bytes[..bytes.len() - 3]);
+        reader.bytes(&mut [0; 16]).unwrap();
+        assert_eq!(reader.vec(&mut [0; 4]), Err("Save state has a 3 byte block where 4 bytes were expected".to_string()));
+    }
+
+    fn sections(sections: &[(Section, &[u8])]) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.bytes(&STATE_MAGIC);
+        writer.u8(STATE_VERSION);
+        writer.u64(7);
+        for (section, payload) in sections {
+            writer.section(*section, |writer| writer.bytes(payload));
+        }
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn test_sections_are_found_by_tag() {
+        let extra = Section { tag: *b"NEW ", version: 9 };
+        let bytes = sections(&[(extra, &[1, 2, 3]), (CPU_SECTION, &[4]), (PPU_SECTION, &[5, 6])]);
+        let mut file = open(&bytes, 7).unwrap();
+        // Out of order, and skipping the one this build doesn't know.
+        let ppu = file.required(PPU_SECTION).unwrap();
+        assert_eq!((ppu.version(), ppu.u16()), (PPU_SECTION.version, Ok(0x0605)));
+        let cpu = file.required(CPU_SECTION).unwrap();
+        assert_eq!(cpu.u8(), Ok(4));
+        assert!(file.section(BUS_SECTION).unwrap().is_none());
+        assert_eq!(file.required(BUS_SECTION).unwrap_err(), "Save state has no BUS section");
+        assert_eq!(file.finish(), Ok(()));
+
+        // A section must be read to its end.
+        let mut file = open(&bytes, 7).unwrap();
+        file.required(PPU_SECTION).unwrap().u8().unwrap();
+        assert_eq!(file.required(CPU_SECTION).unwrap_err(), "Save state has trailing data");
+
+        assert_eq!(open(&bytes[..bytes.len() - 1], 7).unwrap_err(), "Save state is truncated");
+    }
+
+    #[test]
+    fn test_older_sections_load_and_newer_ones_are_rejected() {
+        let old = Section { tag: CPU_SECTION.tag, version: 0 };
+        let bytes = sections(&[(old, &[])]);
+        assert_eq!(open(&bytes, 7).unwrap().required(CPU_SECTION).unwrap().version(), 0);
+
+        let new = Section { tag: CPU_SECTION.tag, version: CPU_SECTION.version + 1 };
+        let bytes = sections(&[(new, &[])]);
+        assert_eq!(
+            open(&bytes, 7).unwrap().required(CPU_SECTION).unwrap_err(),
+            "Save state's CPU section is version 2, newer than the 1 this build reads"
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Rewind: a rolling history of save states taken every few frames, which the player can step
+//! back through.
+//!
+//! Only the newest state is kept whole. Each older one is stored as its difference from the state
+//! after it (XOR), with the runs of unchanged bytes squeezed out, since a couple of frames only
+//! touch a few hundred bytes of the console. Stepping back restores the newest state and rebuilds
+//! the one before it from its difference; the oldest differences are dropped as the history fills.
+
+use std::collections::VecDeque;
+
+use crate::emulator::Emulator;
+
+// A snapshot every other frame, for a minute at 60fps.
+pub const DEFAULT_INTERVAL: u32 = 2;
+pub const DEFAULT_SECONDS: u32 = 60;
+
+#[derive(Debug, Clone)]
+pub struct Rewind {
+    // Frames between snapshots, and how many snapshots are kept.
+    interval: u32,
+    capacity: usize,
+    frames_since_snapshot: u32,
+    newest: Option<Vec<u8>>,
+    // Encoded differences, oldest first. The last one turns `newest` into the snapshot before it.
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Rewind::new(DEFAULT_INTERVAL, DEFAULT_SECONDS)
+    }
+}
+
+impl Rewind {
+    // Keeps a snapshot every `interval` frames, going back `seconds` seconds at 60fps.
+    pub fn new(interval: u32, seconds: u32) -> Self {
+        let interval = interval.max(1);
+        Rewind {
+            interval,
+            capacity: (seconds * 60 / interval).max(1) as usize,
+            frames_since_snapshot: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.frames_since_snapshot = 0;
+    }
+
+    // Snapshots that can be stepped back to.
+    pub fn len(&self) -> usize {
+        self.newest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    // Bytes held by the history.
+    pub fn memory_used(&self) -> usize {
+        self.newest.as_ref().map_or(0, Vec::len) + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+
+    // Call after each emulated frame. Takes a snapshot every `interval` frames.
+    pub fn record(&mut self, emulator: &Emulator) {
+        self.frames_since_snapshot += 1;
+        if self.frames_since_snapshot < self.interval {
+            return;
+        }
+        self.frames_since_snapshot = 0;
+
+        let state = emulator.save_state();
+        match self.newest.take() {
+            Some(previous) if previous.len() == state.len() => {
+                if self.deltas.len() + 1 == self.capacity {
+                    self.deltas.pop_front();
+                }
+                self.deltas.push_back(encode(&xor(&previous, &state)));
+            }
+            // The state changed size (e.g. the Four Score was plugged in), so older snapshots
+            // can't be rebuilt from it.
+            Some(_) => self.deltas.clear(),
+            None => {}
+        }
+        self.newest = Some(state);
+    }
+
+    // Restores the newest snapshot and drops it from the history, so the next call goes further
+    // back. Returns false once the history is used up.
+    pub fn step_back(&mut self, emulator: &mut Emulator) -> Result<bool, String> {
+        let Some(state) = self.newest.take() else {
+            return Ok(false);
+        };
+        self.newest = self.deltas.pop_back().map(|delta| xor(&state, &decode(&delta, state.len())));
+        self.frames_since_snapshot = 0;
+        emulator.load_state(&state)?;
+        Ok(true)
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(a, b)| a ^ b).collect()
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(byte) = bytes.get(*pos) {
+        *pos += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+// Packs a difference, which is mostly zeros, as pairs of (zero run length, literal count, literal
+// bytes).
+fn encode(delta: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < delta.len() {
+        let zeros = delta[pos..].iter().take_while(|byte| **byte == 0).count();
+        pos += zeros;
+        // A literal run ends at the next pair of zeros; a lone zero is cheaper to copy.
+        let start = pos;
+        while pos < delta.len() && !(delta[pos] == 0 && delta.get(pos + 1).is_none_or(|byte| *byte == 0)) {
+            pos += 1;
+        }
+        push_varint(&mut out, zeros);
+        push_varint(&mut out, pos - start);
+        out.extend_from_slice(&delta[start..pos]);
+    }
+    out
+}
+
+fn decode(encoded: &[u8], len: usize) -> Vec<u8> {
+    let mut delta = Vec::with_capacity(len);
+    let mut pos = 0;
+    while pos < encoded.len() {
+        let zeros = read_varint(encoded, &mut pos);
+        delta.resize(delta.len() + zeros, 0);
+        let literals = read_varint(encoded, &mut pos);
+        delta.extend_from_slice(&encoded[pos..pos + literals]);
+        pos += literals;
+    }
+    delta.resize(len, 0);
+    delta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::cpu::Mem;
+
+    #[test]
+    fn test_encode_round_trip() {
+        let mut delta = vec![0; 1000];
+        delta[3] = 1;
+        delta[4] = 2;
+        delta[6] = 3;
+        delta[999] = 4;
+        for delta in [delta, vec![], vec![5; 300], vec![0, 0, 7]] {
+            let encoded = encode(&delta);
+            assert_eq!(decode(&encoded, delta.len()), delta);
+        }
+        assert!(encode(&[0; 1000]).len() <= 4);
+    }
+
+    #[test]
+    fn test_step_back_through_history() {
+        let mut emulator = Emulator::new(create_test_cartridge());
+        for (i, byte) in asm!("loop: INC $10\nJMP loop").into_iter().enumerate() {
+            emulator.cpu.mem_write(0x0600 + i as u16, byte);
+        }
+        emulator.cpu.program_counter = 0x0600;
+
+        // Keeps 3 snapshots, one every 2 frames.
+        let mut rewind = Rewind::new(2, 0);
+        rewind.capacity = 3;
+        let mut states = Vec::new();
+        for frame in 1..=10 {
+            emulator.run_frame();
+            rewind.record(&emulator);
+            if frame % 2 == 0 {
+                states.push(emulator.save_state());
+            }
+        }
+        assert_eq!(rewind.len(), 3);
+        assert!(rewind.memory_used() < 2 * states[0].len());
+
+        for expected in states.iter().rev().take(3) {
+            assert_eq!(rewind.step_back(&mut emulator), Ok(true));
+            assert_eq!(&emulator.save_state(), expected);
+        }
+        assert_eq!(rewind.step_back(&mut emulator), Ok(false));
+        assert!(rewind.is_empty());
+    }
+}
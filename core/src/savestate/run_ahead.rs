@@ -0,0 +1,60 @@
+//! Run-ahead: hides the frames of input lag a game has by showing a frame from the near future.
+//!
+//! Most games react to a button a frame or two after it is pressed. With run-ahead, each frame is
+//! run for real, saved, then followed by a few more frames with the same input; the last of those
+//! is what gets shown, and the console is rolled back to the saved state. The player sees the
+//! game's reaction that many frames sooner. Set it no higher than the game's own lag, or the
+//! picture will jump when input changes.
+//!
+//! Reference: <https://docs.libretro.com/guides/runahead/>
+
+use crate::emulator::Emulator;
+use crate::movie::MovieState;
+
+#[derive(Debug, Clone, Default)]
+pub struct RunAhead {
+    // Frames run ahead of the real one. 0 turns run-ahead off.
+    pub frames: u32,
+    // The state rolled back to, reused from frame to frame so nothing is allocated.
+    state: Vec<u8>,
+}
+
+impl RunAhead {
+    pub fn new(frames: u32) -> Self {
+        RunAhead { frames, state: Vec::new() }
+    }
+
+    // Runs the real frame with `run_frame`, which returns false if it didn't run (e.g. the
+    // debugger is stopped), then runs ahead and rolls back. Afterwards the console is in the state
+    // after the real frame, but `emulator.frame` shows the last frame run ahead.
+    //
+    // Frames run ahead go straight to `Emulator::run_frame`, so they don't stop on breakpoints.
+    // Run-ahead is skipped while a movie is recording or playing, since those frames would be
+    // recorded or use up its input.
+    pub fn run_frame<F>(&mut self, emulator: &mut Emulator, run_frame: F) -> bool
+    where
+        F: FnOnce(&mut Emulator) -> bool,
+    {
+        if self.frames == 0 || *emulator.movie_state() != MovieState::Inactive {
+            return run_frame(emulator);
+        }
+
+        // Only the last frame run ahead is shown, so the others needn't be drawn.
+        let skip_render = emulator.skip_render;
+        emulator.skip_render = true;
+        if !run_frame(emulator) {
+            emulator.skip_render = skip_render;
+            return false;
+        }
+        emulator.save_state_into(&mut self.state);
+        for i in 1..=self.frames {
+            emulator.skip_render = skip_render || i < self.frames;
+            emulator.run_frame();
+        }
+        emulator.skip_render = skip_render;
+        emulator.restore_state(&self.state).expect("run-ahead restores a state it just saved");
+        // Watchpoint hits from the future would confuse the debugger.
+        emulator.cpu.bus.watch_hits.clear();
+        true
+    }
+}
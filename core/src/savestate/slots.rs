@@ -0,0 +1,214 @@
+//! Numbered save state slots on disk, ten per game, plus an automatic one for resuming where the
+//! player left off.
+//!
+//! Each game gets a directory named after the ROM and its hash, so two ROMs with the same file name
+//! don't share slots. A slot is a file named after the slot and the time it was saved (UTC), e.g.
+//! `slot3-20261015-142211.nss` or `autosave-20261015-142211.nss`; saving again replaces it.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::emulator::Emulator;
+
+pub const SLOTS: usize = 10;
+const EXTENSION: &str = "nss";
+const AUTOSAVE: &str = "autosave";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateSlots {
+    dir: PathBuf,
+    // Slot the save and load hotkeys use.
+    pub selected: usize,
+}
+
+impl StateSlots {
+    // Slots for the game `rom_stem` with `Cartridge::hash` `rom_hash`, kept under `root`.
+    pub fn new(root: &Path, rom_stem: &str, rom_hash: u64) -> Self {
+        StateSlots {
+            dir: root.join(format!("{}-{:016x}", rom_stem, rom_hash)),
+            selected: 0,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Files saved under `name`, oldest first. Normally there is at most one.
+    fn files(&self, name: &str) -> Vec<PathBuf> {
+        let prefix = format!("{}-", name);
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                name.starts_with(&prefix) && path.extension().is_some_and(|extension| extension == EXTENSION)
+            })
+            .collect();
+        // The timestamps sort as text.
+        files.sort();
+        files
+    }
+
+    // The file holding `slot`, if anything was saved to it.
+    pub fn path(&self, slot: usize) -> Option<PathBuf> {
+        self.files(&slot_name(slot)).pop()
+    }
+
+    // When `slot` was saved, as "2026-10-15 14:22:11 UTC".
+    pub fn saved_at(&self, slot: usize) -> Option<String> {
+        saved_at(&self.path(slot)?)
+    }
+
+    // Saves the console to `slot`, replacing what was there.
+    pub fn save(&self, slot: usize, emulator: &Emulator) -> Result<PathBuf, String> {
+        self.save_as(&slot_name(slot), emulator)
+    }
+
+    // Restores the console from `slot`.
+    pub fn load(&self, slot: usize, emulator: &mut Emulator) -> Result<(), String> {
+        let path = self.path(slot).ok_or_else(|| format!("Slot {} is empty", slot))?;
+        load(&path, emulator)
+    }
+
+    // The automatic save, made on exit and every so often, if there is one.
+    pub fn autosave_path(&self) -> Option<PathBuf> {
+        self.files(AUTOSAVE).pop()
+    }
+
+    pub fn autosaved_at(&self) -> Option<String> {
+        saved_at(&self.autosave_path()?)
+    }
+
+    pub fn autosave(&self, emulator: &Emulator) -> Result<PathBuf, String> {
+        self.save_as(AUTOSAVE, emulator)
+    }
+
+    // Picks up where the automatic save left off.
+    pub fn resume(&self, emulator: &mut Emulator) -> Result<(), String> {
+        let path = self.autosave_path().ok_or("Nothing to resume")?;
+        load(&path, emulator)
+    }
+
+    fn save_as(&self, name: &str, emulator: &Emulator) -> Result<PathBuf, String> {
+        std::fs::create_dir_all(&self.dir).map_err(|e| format!("{}: {}", self.dir.display(), e))?;
+        let old = self.files(name);
+        let path = self.dir.join(format!("{}-{}.{}", name, timestamp(SystemTime::now()), EXTENSION));
+        std::fs::write(&path, emulator.save_state()).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for file in old.into_iter().filter(|file| *file != path) {
+            let _ = std::fs::remove_file(file);
+        }
+        Ok(path)
+    }
+}
+
+fn slot_name(slot: usize) -> String {
+    format!("slot{}", slot)
+}
+
+fn load(path: &Path, emulator: &mut Emulator) -> Result<(), String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    emulator.load_state(&bytes)
+}
+
+// The time in a slot's file name, as "2026-10-15 14:22:11 UTC".
+fn saved_at(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_string_lossy().into_owned();
+    let (_, stamp) = stem.split_once('-')?;
+    let (date, time) = stamp.split_once('-')?;
+    if date.len() != 8 || time.len() != 6 {
+        return None;
+    }
+    Some(format!(
+        "{}-{}-{} {}:{}:{} UTC",
+        &date[..4],
+        &date[4..6],
+        &date[6..],
+        &time[..2],
+        &time[2..4],
+        &time[4..]
+    ))
+}
+
+// "20261015-142211" for `time` in UTC.
+pub fn timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let seconds = seconds % 86400;
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// The Gregorian date `days` after 1970-01-01.
+// Reference: <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test::create_test_cartridge;
+    use std::time::Duration;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(UNIX_EPOCH), "19700101-000000");
+        assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(1_700_000_000)), "20231114-221320");
+        assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)), "20000229-000000");
+    }
+
+    #[test]
+    fn test_save_and_load_slots() {
+        let root = std::env::temp_dir().join(format!("nes_rs_slots_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let mut emulator = Emulator::new(create_test_cartridge());
+        let slots = StateSlots::new(&root, "game", 0xabc);
+        assert_eq!(slots.dir(), root.join("game-0000000000000abc"));
+        assert_eq!(slots.path(3), None);
+        assert_eq!(slots.load(3, &mut emulator), Err("Slot 3 is empty".to_string()));
+
+        emulator.cpu.bus.cpu_wram[0x10] = 0x42;
+        let path = slots.save(3, &emulator).unwrap();
+        // An older save of the same slot is replaced.
+        std::fs::write(slots.dir().join("slot3-20000101-000000.nss"), b"old").unwrap();
+        assert_eq!(slots.save(3, &emulator).unwrap(), path);
+        assert_eq!(slots.files("slot3"), vec![path.clone()]);
+        assert!(slots.saved_at(3).unwrap().ends_with(" UTC"));
+        assert_eq!(slots.path(30), None);
+
+        emulator.cpu.bus.cpu_wram[0x10] = 0;
+        slots.load(3, &mut emulator).unwrap();
+        assert_eq!(emulator.cpu.bus.cpu_wram[0x10], 0x42);
+
+        // The automatic save is separate from the numbered slots.
+        assert_eq!(slots.resume(&mut emulator), Err("Nothing to resume".to_string()));
+        emulator.cpu.bus.cpu_wram[0x10] = 0x99;
+        slots.autosave(&emulator).unwrap();
+        assert!(slots.autosaved_at().is_some());
+        emulator.cpu.bus.cpu_wram[0x10] = 0;
+        slots.resume(&mut emulator).unwrap();
+        assert_eq!(emulator.cpu.bus.cpu_wram[0x10], 0x99);
+        slots.load(3, &mut emulator).unwrap();
+        assert_eq!(emulator.cpu.bus.cpu_wram[0x10], 0x42);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,403 @@
+//! Rhai scripting: scripts that watch and poke the running game, draw over the picture and press
+//! buttons, for practice hacks, auto-splitters and bots that don't need changes to the crate.
+//!
+//! A script runs once when loaded, registering callbacks:
+//!
+//! ```text
+//! let deaths = 0;
+//! on_frame(|| {
+//!     text(8, 8, `Lives: ${read(0x0075)}`);
+//! });
+//! on_exec(0xC123, || { deaths += 1; print(`Died ${deaths} times`); });
+//! ```
+//!
+//! - `on_frame(f)`: after every emulated frame.
+//! - `on_instruction(f)`: before every instruction, with the program counter. Slow.
+//! - `on_exec(addr, f)`: before the instruction at `addr` runs.
+//!
+//! Callbacks can use `read(addr)`, `read16(addr)`, `write(addr, value)`, `cpu()` (a map of `a`,
+//! `x`, `y`, `sp`, `pc` and `p`), `frame()` and `scanline()`; `text(x, y, s[, color])`,
+//! `rect(x, y, w, h, color)` and `fill(x, y, w, h, color)` to draw, with colors as `0xRRGGBB`;
+//! and `press([port, ]button)` to hold "A", "B", "Select", "Start", "Up", "Down", "Left" or
+//! "Right" through the next frame. `print` posts an on-screen message.
+//!
+//! Callbacks see memory as it was when they were called: RAM and registers are current, the rest
+//! of the address space as of the end of the last frame. Writes go straight into memory (ROM
+//! included) once the callback returns, without side effects.
+//!
+//! Reference: <https://rhai.rs/book/>
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, Map, AST};
+
+use crate::cpu::CPU;
+use crate::emulator::Emulator;
+use crate::joypad::JoypadButton;
+use crate::render::osd::Shape;
+
+// Controller ports scripts can press buttons on.
+const PORTS: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Registers {
+    a: u8,
+    x: u8,
+    y: u8,
+    sp: u8,
+    pc: u16,
+    p: u8,
+}
+
+// What the script's functions read and write, shared between the engine and the `Script`.
+#[derive(Debug)]
+struct State {
+    // The CPU address space as of the last frame, with RAM kept current.
+    memory: Vec<u8>,
+    registers: Registers,
+    frame: u64,
+    scanline: u16,
+    writes: Vec<(u16, u8)>,
+    shapes: Vec<Shape>,
+    buttons: [JoypadButton; PORTS],
+    messages: Vec<String>,
+    frame_hooks: Vec<FnPtr>,
+    instruction_hooks: Vec<FnPtr>,
+    exec_hooks: Vec<(u16, FnPtr)>,
+}
+
+impl State {
+    fn new() -> Self {
+        State {
+            memory: vec![0; 0x10000],
+            registers: Registers::default(),
+            frame: 0,
+            scanline: 0,
+            writes: Vec::new(),
+            shapes: Vec::new(),
+            buttons: [JoypadButton::empty(); PORTS],
+            messages: Vec::new(),
+            frame_hooks: Vec::new(),
+            instruction_hooks: Vec::new(),
+            exec_hooks: Vec::new(),
+        }
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // RAM and its mirrors.
+            0x0000..=0x1fff => self.memory[(addr & 0x07ff) as usize],
+            _ => self.memory[addr as usize],
+        }
+    }
+
+    // Refreshes the registers and RAM, the parts that change with every instruction.
+    fn sync_cpu(&mut self, cpu: &CPU) {
+        self.registers = Registers {
+            a: cpu.register_a,
+            x: cpu.register_x,
+            y: cpu.register_y,
+            sp: cpu.stack_pointer,
+            pc: cpu.program_counter,
+            p: cpu.status.bits(),
+        };
+        self.memory[..0x0800].copy_from_slice(&cpu.bus.cpu_wram);
+        self.frame = cpu.bus.ppu.frame_count;
+        self.scanline = cpu.bus.ppu.scanline;
+    }
+
+    // Refreshes the whole address space.
+    fn sync_all(&mut self, cpu: &CPU) {
+        for addr in 0x0800..=0xffff {
+            self.memory[addr] = cpu.bus.peek(addr as u16);
+        }
+        self.sync_cpu(cpu);
+    }
+
+    // Applies the writes the script made to the real memory.
+    fn flush_writes(&mut self, cpu: &mut CPU) {
+        for (addr, value) in self.writes.drain(..) {
+            cpu.bus.poke(addr, value);
+        }
+    }
+}
+
+fn button(name: &str) -> Result<JoypadButton, Box<EvalAltResult>> {
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "a" => JoypadButton::BUTTON_A,
+        "b" => JoypadButton::BUTTON_B,
+        "select" => JoypadButton::SELECT,
+        "start" => JoypadButton::START,
+        "up" => JoypadButton::UP,
+        "down" => JoypadButton::DOWN,
+        "left" => JoypadButton::LEFT,
+        "right" => JoypadButton::RIGHT,
+        _ => return Err(format!("Unknown button {}", name).into()),
+    })
+}
+
+// 0xRRGGBB to RGBA.
+fn color(rgb: i64) -> [u8; 4] {
+    [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 255]
+}
+
+const WHITE: i64 = 0xffffff;
+
+#[derive(Debug)]
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    state: Rc<RefCell<State>>,
+}
+
+impl Script {
+    // Compiles and runs `source`, which registers its callbacks.
+    pub fn new(source: &str) -> Result<Self, String> {
+        let state = Rc::new(RefCell::new(State::new()));
+        let engine = Script::engine(&state);
+        let ast = engine.compile(source).map_err(|e| format!("Script error: {}", e))?;
+        engine.run_ast(&ast).map_err(|e| format!("Script error: {}", e))?;
+        Ok(Script { engine, ast, state })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        Script::new(&source)
+    }
+
+    fn engine(state: &Rc<RefCell<State>>) -> Engine {
+        let mut engine = Engine::new();
+        let s = state.clone();
+        engine.register_fn("on_frame", move |f: FnPtr| s.borrow_mut().frame_hooks.push(f));
+        let s = state.clone();
+        engine.register_fn("on_instruction", move |f: FnPtr| s.borrow_mut().instruction_hooks.push(f));
+        let s = state.clone();
+        engine.register_fn("on_exec", move |addr: i64, f: FnPtr| s.borrow_mut().exec_hooks.push((addr as u16, f)));
+
+        let s = state.clone();
+        engine.register_fn("read", move |addr: i64| s.borrow().read(addr as u16) as i64);
+        let s = state.clone();
+        engine.register_fn("read16", move |addr: i64| {
+            let state = s.borrow();
+            u16::from_le_bytes([state.read(addr as u16), state.read((addr as u16).wrapping_add(1))]) as i64
+        });
+        let s = state.clone();
+        engine.register_fn("write", move |addr: i64, value: i64| {
+            let mut state = s.borrow_mut();
+            let addr = addr as u16;
+            let index = if addr < 0x2000 { addr & 0x07ff } else { addr };
+            state.memory[index as usize] = value as u8;
+            state.writes.push((addr, value as u8));
+        });
+        let s = state.clone();
+        engine.register_fn("cpu", move || {
+            let registers = s.borrow().registers;
+            let mut map = Map::new();
+            map.insert("a".into(), (registers.a as i64).into());
+            map.insert("x".into(), (registers.x as i64).into());
+            map.insert("y".into(), (registers.y as i64).into());
+            map.insert("sp".into(), (registers.sp as i64).into());
+            map.insert("pc".into(), (registers.pc as i64).into());
+            map.insert("p".into(), (registers.p as i64).into());
+            map
+        });
+        let s = state.clone();
+        engine.register_fn("frame", move || s.borrow().frame as i64);
+        let s = state.clone();
+        engine.register_fn("scanline", move || s.borrow().scanline as i64);
+
+        let s = state.clone();
+        engine.register_fn("text", move |x: i64, y: i64, text: &str| {
+            let shape = Shape::Text { x: x as i32, y: y as i32, text: text.to_string(), color: color(WHITE) };
+            s.borrow_mut().shapes.push(shape);
+        });
+        let s = state.clone();
+        engine.register_fn("text", move |x: i64, y: i64, text: &str, rgb: i64| {
+            let shape = Shape::Text { x: x as i32, y: y as i32, text: text.to_string(), color: color(rgb) };
+            s.borrow_mut().shapes.push(shape);
+        });
+        for (name, filled) in [("rect", false), ("fill", true)] {
+            let s = state.clone();
+            engine.register_fn(name, move |x: i64, y: i64, width: i64, height: i64, rgb: i64| {
+                let (x, y, width, height) = (x as i32, y as i32, width as i32, height as i32);
+                let shape = Shape::Rect { x, y, width, height, color: color(rgb), filled };
+                s.borrow_mut().shapes.push(shape);
+            });
+        }
+
+        let s = state.clone();
+        engine.register_fn("press", move |port: i64, name: &str| -> Result<(), Box<EvalAltResult>> {
+            let mut state = s.borrow_mut();
+            let buttons = state.buttons.get_mut(port as usize).ok_or("No such controller port")?;
+            buttons.insert(button(name)?);
+            Ok(())
+        });
+        let s = state.clone();
+        engine.register_fn("press", move |name: &str| -> Result<(), Box<EvalAltResult>> {
+            s.borrow_mut().buttons[0].insert(button(name)?);
+            Ok(())
+        });
+
+        let s = state.clone();
+        engine.on_print(move |text| s.borrow_mut().messages.push(text.to_string()));
+        engine
+    }
+
+    fn call(&self, hooks: &[FnPtr], args: impl Fn() -> Vec<Dynamic>) -> Result<(), String> {
+        for hook in hooks {
+            let _: Dynamic = hook
+                .call(&self.engine, &self.ast, args())
+                .map_err(|e| format!("Script error: {}", e))?;
+        }
+        Ok(())
+    }
+
+    // Whether `before_instruction` has anything to do for the instruction at `pc`.
+    pub fn hooks_instruction(&self, pc: u16) -> bool {
+        let state = self.state.borrow();
+        !state.instruction_hooks.is_empty() || state.exec_hooks.iter().any(|(addr, _)| *addr == pc)
+    }
+
+    // Runs the instruction callbacks for the instruction the CPU is about to execute.
+    pub fn before_instruction(&mut self, cpu: &mut CPU) -> Result<(), String> {
+        let pc = cpu.program_counter;
+        if !self.hooks_instruction(pc) {
+            return Ok(());
+        }
+        let (instruction_hooks, exec_hooks): (Vec<FnPtr>, Vec<FnPtr>) = {
+            let mut state = self.state.borrow_mut();
+            state.sync_cpu(cpu);
+            let exec = state.exec_hooks.iter().filter(|(addr, _)| *addr == pc);
+            (state.instruction_hooks.clone(), exec.map(|(_, f)| f.clone()).collect())
+        };
+        let result = self
+            .call(&instruction_hooks, || vec![(pc as i64).into()])
+            .and_then(|()| self.call(&exec_hooks, Vec::new));
+        self.state.borrow_mut().flush_writes(cpu);
+        result
+    }
+
+    // Runs the frame callbacks after a frame, replacing what they drew.
+    pub fn after_frame(&mut self, emulator: &mut Emulator) -> Result<(), String> {
+        let hooks = {
+            let mut state = self.state.borrow_mut();
+            if state.frame_hooks.is_empty() {
+                return Ok(());
+            }
+            state.sync_all(&emulator.cpu);
+            state.shapes.clear();
+            state.frame_hooks.clone()
+        };
+        let result = self.call(&hooks, Vec::new);
+        self.state.borrow_mut().flush_writes(&mut emulator.cpu);
+        result
+    }
+
+    // Adds the buttons the script pressed to the ones held on each controller, for the frame about
+    // to run. Presses last one frame.
+    pub fn apply_input(&mut self, held: &mut [JoypadButton]) {
+        let mut state = self.state.borrow_mut();
+        for (buttons, pressed) in held.iter_mut().zip(state.buttons.iter_mut()) {
+            *buttons |= std::mem::take(pressed);
+        }
+    }
+
+    // What the frame callbacks drew.
+    pub fn shapes(&self) -> Vec<Shape> {
+        self.state.borrow().shapes.clone()
+    }
+
+    // Messages printed since the last call.
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.state.borrow_mut().messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::cpu::Mem;
+    use crate::debugger::Debugger;
+
+    fn emulator_with(source: &str) -> Emulator {
+        let mut emulator = Emulator::new(create_test_cartridge());
+        for (i, byte) in asm!(source).into_iter().enumerate() {
+            emulator.cpu.mem_write(0x0600 + i as u16, byte);
+        }
+        emulator.cpu.program_counter = 0x0600;
+        emulator
+    }
+
+    #[test]
+    fn test_frame_callbacks_read_write_and_draw() {
+        let mut emulator = emulator_with("loop: JMP loop");
+        emulator.cpu.mem_write(0x0010, 0x41);
+        let mut script = Script::new(
+            r#"
+            let frames = 0;
+            on_frame(|| {
+                frames += 1;
+                write(0x0811, read(0x0010) + frames);
+                text(8, 8, `frame ${frames}`, 0xff0000);
+                fill(0, 0, 4, 4, 0x00ff00);
+                press("Start");
+                press(1, "a");
+                if frames == 2 { print("two"); }
+            });
+            "#,
+        )
+        .unwrap();
+
+        for _ in 0..2 {
+            emulator.run_frame();
+            script.after_frame(&mut emulator).unwrap();
+        }
+        // Writes land in RAM through the mirror.
+        assert_eq!(emulator.cpu.mem_read(0x0011), 0x43);
+        assert_eq!(script.take_messages(), vec!["two"]);
+        let shapes = script.shapes();
+        assert_eq!(shapes.len(), 2);
+        assert!(matches!(&shapes[0], Shape::Text { text, color: [255, 0, 0, 255], .. } if text == "frame 2"));
+
+        let mut held = [JoypadButton::UP, JoypadButton::empty()];
+        script.apply_input(&mut held);
+        assert_eq!(held, [JoypadButton::UP | JoypadButton::START, JoypadButton::BUTTON_A]);
+        // Presses last a single frame.
+        script.apply_input(&mut held[1..]);
+        assert_eq!(held[1], JoypadButton::BUTTON_A);
+    }
+
+    #[test]
+    fn test_exec_callbacks_run_through_the_debugger() {
+        let mut emulator = emulator_with("loop: INX\nhere: INY\nJMP loop");
+        let script = Script::new(
+            r#"
+            let seen = 0;
+            on_exec(0x0601, || {
+                seen += 1;
+                if cpu().x == 3 { write(0x00, seen); }
+            });
+            "#,
+        )
+        .unwrap();
+        let mut debugger = Debugger::new();
+        debugger.script = Some(script);
+        for _ in 0..9 {
+            debugger.step_instruction(&mut emulator);
+        }
+        assert_eq!(emulator.cpu.mem_read(0x0000), 3);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Script::new("on_frame(|| {").is_err());
+        let mut script = Script::new(r#"on_frame(|| press("turbo"));"#).unwrap();
+        let mut emulator = emulator_with("loop: JMP loop");
+        emulator.run_frame();
+        assert!(script.after_frame(&mut emulator).unwrap_err().contains("Unknown button turbo"));
+    }
+}
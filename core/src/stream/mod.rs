@@ -0,0 +1,255 @@
+//! Streams the console to remote clients over TCP: frames go out, controller input comes in.
+//!
+//! A client connects, reads Hello, then gets a Frame after every emulated frame and sends Input
+//! whenever a button changes. Any number of clients can watch; each can drive any controller. A
+//! client that can't keep up misses frames rather than falling behind, since only one frame is
+//! ever queued for it. There is no APU yet, so no audio is streamed.
+
+pub mod protocol;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use crate::emulator::Emulator;
+use crate::joypad::JoypadButton;
+use crate::render::constants::{NES_PIXEL_HEIGHT, NES_PIXEL_WIDTH};
+use crate::render::frame::Frame;
+use protocol::{FrameEncoding, Message, VERSION};
+
+pub const DEFAULT_PORT: u16 = 4280;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Connected(SocketAddr),
+    // A client left, or broke the protocol and was dropped.
+    Disconnected(SocketAddr, String),
+}
+
+struct Client {
+    stream: TcpStream,
+    addr: SocketAddr,
+    incoming: Vec<u8>,
+    // The message being written, and how much of it has gone out.
+    outgoing: Vec<u8>,
+    written: usize,
+}
+
+impl Client {
+    // Writes as much of the queued message as the socket takes without blocking.
+    fn flush(&mut self) -> Result<(), String> {
+        while self.written < self.outgoing.len() {
+            match self.stream.write(&self.outgoing[self.written..]) {
+                Ok(0) => return Err("Connection closed".to_string()),
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        self.outgoing.clear();
+        self.written = 0;
+        Ok(())
+    }
+
+    // Reads what the client sent and hands each whole message to `receive`.
+    fn receive<F: FnMut(Message)>(&mut self, mut receive: F) -> Result<(), String> {
+        let mut buffer = [0; 512];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err("Connection closed".to_string()),
+                Ok(n) => self.incoming.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        while let Some(message) = Message::take(&mut self.incoming)? {
+            receive(message);
+        }
+        Ok(())
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    pub encoding: FrameEncoding,
+}
+
+impl Server {
+    // Listens on `addr` ("host:port").
+    pub fn bind(addr: &str, encoding: FrameEncoding) -> Result<Server, String> {
+        let listener = TcpListener::bind(addr).map_err(|e| format!("Could not listen on {}: {}", addr, e))?;
+        listener.set_nonblocking(true).map_err(|e| format!("Could not listen on {}: {}", addr, e))?;
+        Ok(Server {
+            listener,
+            clients: Vec::new(),
+            encoding,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|e| e.to_string())
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    // Lets new clients in and applies the input everyone has sent to `emulator`. Call once per
+    // frame, before running it.
+    pub fn poll(&mut self, emulator: &mut Emulator) -> Vec<Event> {
+        let mut events = Vec::new();
+        while let Ok((stream, addr)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_err() {
+                continue;
+            }
+            // Frames are written whole as soon as they are ready; batching them only adds latency.
+            let _ = stream.set_nodelay(true);
+            let hello = Message::Hello {
+                version: VERSION,
+                width: NES_PIXEL_WIDTH as u16,
+                height: NES_PIXEL_HEIGHT as u16,
+                encoding: self.encoding,
+            };
+            self.clients.push(Client {
+                stream,
+                addr,
+                incoming: Vec::new(),
+                outgoing: hello.encode(),
+                written: 0,
+            });
+            events.push(Event::Connected(addr));
+        }
+
+        self.clients.retain_mut(|client| {
+            let result = client.receive(|message| {
+                // Clients only send Input; anything else, or a port that doesn't exist, is ignored.
+                if let Message::Input { port: port @ 0..=3, buttons } = message {
+                    emulator.set_buttons(port as usize, JoypadButton::from_bits_truncate(buttons));
+                }
+            });
+            let result = result.and_then(|()| client.flush());
+            match result {
+                Ok(()) => true,
+                Err(e) => {
+                    events.push(Event::Disconnected(client.addr, e));
+                    false
+                }
+            }
+        });
+        events
+    }
+
+    // Sends `frame`, the picture after emulated frame `number`, to every client that has taken
+    // the last one. Encodes it only if someone will get it.
+    pub fn send_frame(&mut self, number: u64, frame: &Frame) -> Result<(), String> {
+        if self.clients.iter().all(|client| !client.outgoing.is_empty()) {
+            return Ok(());
+        }
+        let message = Message::Frame {
+            frame: number,
+            data: encode_frame(frame, self.encoding)?,
+        }
+        .encode();
+        for client in self.clients.iter_mut().filter(|client| client.outgoing.is_empty()) {
+            client.outgoing.extend_from_slice(&message);
+            // Errors show up, and drop the client, on the next poll.
+            let _ = client.flush();
+        }
+        Ok(())
+    }
+}
+
+// The visible picture as RGB8 rows, or as a PNG of them.
+pub fn encode_frame(frame: &Frame, encoding: FrameEncoding) -> Result<Vec<u8>, String> {
+    let rgb: Vec<u8> = frame.to_rgba8().chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect();
+    match encoding {
+        FrameEncoding::Raw => Ok(rgb),
+        FrameEncoding::Png => {
+            let mut png = Vec::new();
+            let mut encoder = png::Encoder::new(&mut png, NES_PIXEL_WIDTH as u32, NES_PIXEL_HEIGHT as u32);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+            writer.write_image_data(&rgb).map_err(|e| e.to_string())?;
+            writer.finish().map_err(|e| e.to_string())?;
+            Ok(png)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test::create_test_cartridge;
+    use std::time::{Duration, Instant};
+
+    // Reads from `stream` until a whole message is in.
+    fn read_message(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> Message {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(message) = Message::take(buffer).unwrap() {
+                return message;
+            }
+            assert!(Instant::now() < deadline, "No message from the server");
+            let mut bytes = [0; 4096];
+            match stream.read(&mut bytes) {
+                Ok(n) => buffer.extend_from_slice(&bytes[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_streams_frames_and_takes_input() {
+        let mut emulator = Emulator::new(create_test_cartridge());
+        let mut server = Server::bind("127.0.0.1:0", FrameEncoding::Raw).unwrap();
+        let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        client.write_all(&Message::Input { port: 1, buttons: JoypadButton::START.bits() }.encode()).unwrap();
+
+        let mut buffer = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while emulator.cpu.bus.joypad2.button_status != JoypadButton::START {
+            assert!(Instant::now() < deadline, "The server never took the input");
+            let events = server.poll(&mut emulator);
+            assert!(events.iter().all(|event| matches!(event, Event::Connected(_))));
+        }
+        assert_eq!(server.client_count(), 1);
+        assert!(matches!(
+            read_message(&mut client, &mut buffer),
+            Message::Hello { version: VERSION, width: 256, height: 240, encoding: FrameEncoding::Raw }
+        ));
+
+        emulator.run_frame();
+        server.send_frame(emulator.frame_count(), &emulator.frame).unwrap();
+        match read_message(&mut client, &mut buffer) {
+            Message::Frame { frame, data } => {
+                assert_eq!(frame, emulator.frame_count());
+                assert_eq!(data, encode_frame(&emulator.frame, FrameEncoding::Raw).unwrap());
+                assert_eq!(data.len(), 256 * 240 * 3);
+            }
+            message => panic!("Expected a frame, got {:?}", message),
+        }
+
+        drop(client);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.client_count() > 0 {
+            assert!(Instant::now() < deadline, "The server never saw the client leave");
+            server.poll(&mut emulator);
+        }
+    }
+
+    #[test]
+    fn test_png_frames_decode() {
+        let mut emulator = Emulator::new(create_test_cartridge());
+        emulator.run_frame();
+        let png = encode_frame(&emulator.frame, FrameEncoding::Png).unwrap();
+        let mut reader = png::Decoder::new(&png[..]).read_info().unwrap();
+        let mut rgb = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut rgb).unwrap();
+        assert_eq!(rgb, encode_frame(&emulator.frame, FrameEncoding::Raw).unwrap());
+    }
+}
@@ -0,0 +1,156 @@
+// Streaming messages. On the wire each is a little-endian u32 length, then a tag byte and
+// little-endian fields.
+
+// Sent in Hello, and bumped whenever a message changes.
+pub const VERSION: u8 = 1;
+
+// Larger messages than this are a broken or hostile client. Frames are far smaller.
+pub const MAX_MESSAGE: usize = 1 << 20;
+
+const HELLO: u8 = 0;
+const FRAME: u8 = 1;
+const INPUT: u8 = 2;
+
+// How frames are sent: raw RGB8 rows, or each frame as a PNG file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrameEncoding {
+    #[default]
+    Raw,
+    Png,
+}
+
+impl std::str::FromStr for FrameEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "raw" => Ok(FrameEncoding::Raw),
+            "png" => Ok(FrameEncoding::Png),
+            _ => Err(format!("Unknown frame encoding \"{}\": use raw or png", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    // The server's first message: the picture size and how frames will come.
+    Hello { version: u8, width: u16, height: u16, encoding: FrameEncoding },
+    // The picture after emulated frame `frame`. Frames a client is too slow for are skipped, so
+    // the numbers can jump.
+    Frame { frame: u64, data: Vec<u8> },
+    // From a client: hold `buttons` (JoypadButton bits) on controller `port` (0 to 3) until the
+    // next Input for that port.
+    Input { port: u8, buttons: u8 },
+}
+
+impl Message {
+    // The message with its length in front, ready to write to the socket.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![0; 4];
+        match self {
+            Message::Hello { version, width, height, encoding } => {
+                bytes.push(HELLO);
+                bytes.push(*version);
+                bytes.extend_from_slice(&width.to_le_bytes());
+                bytes.extend_from_slice(&height.to_le_bytes());
+                bytes.push(*encoding as u8);
+            }
+            Message::Frame { frame, data } => {
+                bytes.push(FRAME);
+                bytes.extend_from_slice(&frame.to_le_bytes());
+                bytes.extend_from_slice(data);
+            }
+            Message::Input { port, buttons } => {
+                bytes.push(INPUT);
+                bytes.push(*port);
+                bytes.push(*buttons);
+            }
+        }
+        let len = (bytes.len() - 4) as u32;
+        bytes[..4].copy_from_slice(&len.to_le_bytes());
+        bytes
+    }
+
+    // Takes the first whole message off the front of `buffer`, if it has arrived.
+    pub fn take(buffer: &mut Vec<u8>) -> Result<Option<Message>, String> {
+        let Some(len) = buffer.get(..4).map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize) else {
+            return Ok(None);
+        };
+        if len > MAX_MESSAGE {
+            return Err(format!("Stream message of {} bytes is too long", len));
+        }
+        if buffer.len() < 4 + len {
+            return Ok(None);
+        }
+        let message = Message::decode(&buffer[4..4 + len]);
+        buffer.drain(..4 + len);
+        message.map(Some)
+    }
+
+    // Decodes one message, without its length.
+    pub fn decode(bytes: &[u8]) -> Result<Message, String> {
+        let field = |range: std::ops::Range<usize>| bytes.get(range).ok_or("Stream message is truncated");
+        let (message, len) = match field(0..1)?[0] {
+            HELLO => {
+                let encoding = match field(6..7)?[0] {
+                    0 => FrameEncoding::Raw,
+                    1 => FrameEncoding::Png,
+                    encoding => return Err(format!("Unknown frame encoding {}", encoding)),
+                };
+                let message = Message::Hello {
+                    version: field(1..2)?[0],
+                    width: u16::from_le_bytes(field(2..4)?.try_into().unwrap()),
+                    height: u16::from_le_bytes(field(4..6)?.try_into().unwrap()),
+                    encoding,
+                };
+                (message, 7)
+            }
+            FRAME => {
+                let frame = u64::from_le_bytes(field(1..9)?.try_into().unwrap());
+                (Message::Frame { frame, data: bytes[9..].to_vec() }, bytes.len())
+            }
+            INPUT => (Message::Input { port: field(1..2)?[0], buttons: field(2..3)?[0] }, 3),
+            tag => return Err(format!("Unknown stream message {}", tag)),
+        };
+        match len == bytes.len() {
+            true => Ok(message),
+            false => Err("Stream message is too long".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let messages = [
+            Message::Hello { version: VERSION, width: 256, height: 240, encoding: FrameEncoding::Png },
+            Message::Frame { frame: 61, data: vec![1, 2, 3] },
+            Message::Input { port: 1, buttons: 0x88 },
+        ];
+        let mut buffer = Vec::new();
+        for message in &messages {
+            buffer.extend(message.encode());
+        }
+        // The last message hasn't all arrived yet.
+        let last = buffer.pop().unwrap();
+        assert_eq!(Message::take(&mut buffer), Ok(Some(messages[0].clone())));
+        assert_eq!(Message::take(&mut buffer), Ok(Some(messages[1].clone())));
+        assert_eq!(Message::take(&mut buffer), Ok(None));
+        buffer.push(last);
+        assert_eq!(Message::take(&mut buffer), Ok(Some(messages[2].clone())));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert_eq!(Message::decode(&[]), Err("Stream message is truncated".to_string()));
+        assert_eq!(Message::decode(&[9]), Err("Unknown stream message 9".to_string()));
+        assert_eq!(Message::decode(&[INPUT, 0]), Err("Stream message is truncated".to_string()));
+        assert_eq!(Message::decode(&[INPUT, 0, 0, 0]), Err("Stream message is too long".to_string()));
+        let mut huge = (MAX_MESSAGE as u32 + 1).to_le_bytes().to_vec();
+        assert!(Message::take(&mut huge).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! Runs blargg's test ROMs. Most of them report through PRG-RAM as well as on screen: once
+//! $6001-$6003 hold the signature DE B0 61, $6000 is the status ($80 while running, $81 when the
+//! console should be reset, and the result code when done, 0 meaning passed) and $6004 on holds
+//! the text shown on screen, ending in a zero byte.
+//!
+//! Reference: <https://github.com/christopherpow/nes-test-roms/blob/master/instr_test-v5/readme.txt>
+
+use std::path::Path;
+
+use super::run_frame;
+use crate::cartridge::Cartridge;
+use crate::emulator::{Emulator, StepResult};
+
+const STATUS: u16 = 0x6000;
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const TEXT: u16 = 0x6004;
+const RUNNING: u8 = 0x80;
+const NEEDS_RESET: u8 = 0x81;
+// The ROMs ask for the reset button to be held at least 100ms later.
+const RESET_DELAY_FRAMES: u64 = 10;
+// A minute of emulated time, which covers the longest of the suites.
+pub const DEFAULT_MAX_FRAMES: u64 = 60 * 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Passed,
+    // The ROM's result code.
+    Failed(u8),
+    // Still running after the frame limit.
+    TimedOut,
+    // Never wrote the signature, so it doesn't report through $6000 (or never got that far).
+    NoSignature,
+    // The CPU hit BRK or an opcode the emulator doesn't run, which it treats as a halt.
+    Halted,
+    // The emulator panicked, e.g. on an opcode it doesn't know.
+    Crashed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub status: Status,
+    // The text the ROM left at $6004.
+    pub text: String,
+    pub frames: u64,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.status == Status::Passed
+    }
+
+    // "passed", "failed (code 3)", ...
+    pub fn summary(&self) -> String {
+        match &self.status {
+            Status::Passed => "passed".to_string(),
+            Status::Failed(code) => format!("failed (code {})", code),
+            Status::TimedOut => format!("timed out after {} frames", self.frames),
+            Status::NoSignature => "never reported a result at $6000".to_string(),
+            Status::Halted => "halted on BRK or an unknown opcode".to_string(),
+            Status::Crashed(message) => format!("crashed: {}", message),
+        }
+    }
+}
+
+fn has_signature(emulator: &Emulator) -> bool {
+    (0..3).all(|i| emulator.cpu.bus.peek(STATUS + 1 + i) == SIGNATURE[i as usize])
+}
+
+fn text(emulator: &Emulator) -> String {
+    let bytes: Vec<u8> = (TEXT..=0x7fff)
+        .map(|addr| emulator.cpu.bus.peek(addr))
+        .take_while(|byte| *byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).trim_end().to_string()
+}
+
+// Runs `emulator` until the ROM reports a result, for at most `max_frames` frames.
+pub fn run(emulator: &mut Emulator, max_frames: u64) -> Outcome {
+    let mut reset_at = None;
+    let mut signed = false;
+    let mut frames = 0;
+    let status = loop {
+        if frames == max_frames {
+            break if signed { Status::TimedOut } else { Status::NoSignature };
+        }
+        match run_frame(emulator) {
+            Ok(StepResult::Halted) => break Status::Halted,
+            Ok(_) => {}
+            Err(message) => break Status::Crashed(message),
+        }
+        frames += 1;
+
+        if !has_signature(emulator) {
+            continue;
+        }
+        signed = true;
+        match emulator.cpu.bus.peek(STATUS) {
+            RUNNING => {}
+            NEEDS_RESET => match reset_at {
+                None => reset_at = Some(frames + RESET_DELAY_FRAMES),
+                Some(at) if frames >= at => {
+                    emulator.cpu.reset();
+                    reset_at = None;
+                }
+                Some(_) => {}
+            },
+            0 => break Status::Passed,
+            code => break Status::Failed(code),
+        }
+    };
+    Outcome {
+        status,
+        text: text(emulator),
+        frames,
+    }
+}
+
+// Loads the ROM at `path` and runs it.
+pub fn run_rom(path: &Path, max_frames: u64) -> Result<Outcome, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let cartridge = Cartridge::new(&bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(run(&mut Emulator::new(cartridge), max_frames))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::test::create_test_cartridge;
+    use crate::cpu::Mem;
+
+    // An emulator running `source` from $0600 in place of the cartridge's program.
+    fn emulator_running(source: &str) -> Emulator {
+        let mut emulator = Emulator::new(create_test_cartridge());
+        for (i, byte) in asm!(source).into_iter().enumerate() {
+            emulator.cpu.mem_write(0x0600 + i as u16, byte);
+        }
+        emulator.cpu.program_counter = 0x0600;
+        emulator
+    }
+
+    const SIGN: &str = "LDA #$DE\nSTA $6001\nLDA #$B0\nSTA $6002\nLDA #$61\nSTA $6003\n";
+
+    #[test]
+    fn test_passing_and_failing() {
+        let source = format!("{}LDA #$4F\nSTA $6004\nLDA #$4B\nSTA $6005\nLDA #0\nSTA $6006\nSTA $6000\nloop: JMP loop", SIGN);
+        let outcome = run(&mut emulator_running(&source), 10);
+        assert_eq!(outcome, Outcome { status: Status::Passed, text: "OK".to_string(), frames: 1 });
+
+        let source = format!("{}LDA #3\nSTA $6000\nloop: JMP loop", SIGN);
+        let outcome = run(&mut emulator_running(&source), 10);
+        assert_eq!(outcome.status, Status::Failed(3));
+        assert_eq!(outcome.summary(), "failed (code 3)");
+    }
+
+    #[test]
+    fn test_unfinished_runs() {
+        let source = format!("{}LDA #$80\nSTA $6000\nloop: JMP loop", SIGN);
+        assert_eq!(run(&mut emulator_running(&source), 5).status, Status::TimedOut);
+        assert_eq!(run(&mut emulator_running("loop: JMP loop"), 5).status, Status::NoSignature);
+        assert_eq!(run(&mut emulator_running("BRK"), 5).status, Status::Halted);
+    }
+}
@@ -0,0 +1,177 @@
+//! Golden-frame tests: run a ROM for some frames and compare the picture with a PNG checked in
+//! next to the test, so a change that alters rendering shows up as a failing test with the new
+//! picture written beside the old one.
+//!
+//! Set `NES_RS_BLESS=1` to write the current frames as the golden images instead of comparing,
+//! after checking the change in rendering is the intended one.
+
+use std::path::{Path, PathBuf};
+
+use crate::cartridge::Cartridge;
+use crate::emulator::Emulator;
+use crate::render::constants::{NES_PIXEL_HEIGHT, NES_PIXEL_WIDTH};
+use crate::render::screenshot::{read_png, write_png};
+
+pub const BLESS_VAR: &str = "NES_RS_BLESS";
+
+// How far a frame may stray from its golden image and still match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tolerance {
+    // Largest difference allowed in any one color channel for a pixel to count as the same.
+    pub channel: u8,
+    // Number of pixels allowed to differ by more than `channel`.
+    pub pixels: usize,
+}
+
+impl Tolerance {
+    pub const EXACT: Tolerance = Tolerance { channel: 0, pixels: 0 };
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance::EXACT
+    }
+}
+
+// How two frames differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Difference {
+    // Pixels differing by more than the tolerance allows in some channel.
+    pub pixels: usize,
+    // The largest difference in any channel of any pixel.
+    pub largest: u8,
+    // The first differing pixel, as (x, y).
+    pub first: Option<(usize, usize)>,
+}
+
+// Compares two 256x240 RGBA8 frames, counting pixels with a channel more than `channel` apart.
+pub fn compare(actual: &[u8], expected: &[u8], channel: u8) -> Difference {
+    let mut difference = Difference { pixels: 0, largest: 0, first: None };
+    for (i, (a, b)) in actual.chunks_exact(4).zip(expected.chunks_exact(4)).enumerate() {
+        let largest = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0);
+        difference.largest = difference.largest.max(largest);
+        if largest > channel {
+            difference.pixels += 1;
+            let width = NES_PIXEL_WIDTH as usize;
+            difference.first.get_or_insert((i % width, i / width));
+        }
+    }
+    difference
+}
+
+// A 64-bit FNV-1a hash of a frame, for tests that would rather pin a number than check in a PNG.
+// It only changes when a pixel does.
+// Reference: <http://www.isthe.com/chongo/tech/comp/fnv/>
+pub fn frame_hash(rgba: &[u8]) -> u64 {
+    rgba.iter()
+        .fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// Loads the ROM at `path` and runs it headlessly for `frames` frames with no input.
+pub fn run_rom(path: &Path, frames: u64) -> Result<Emulator, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let mut emulator = Emulator::new(Cartridge::new(&bytes).map_err(|e| format!("{}: {}", path.display(), e))?);
+    for _ in 0..frames {
+        emulator.run_frame();
+    }
+    Ok(emulator)
+}
+
+// Where a frame that didn't match `golden` is written: `name.png` becomes `name.actual.png`.
+pub fn actual_path(golden: &Path) -> PathBuf {
+    golden.with_extension("actual.png")
+}
+
+// Checks `emulator`'s last frame against the PNG at `golden`. On a mismatch the frame is written
+// to `actual_path(golden)` and the error says how far off it was. With NES_RS_BLESS set, the frame
+// is written to `golden` instead.
+pub fn check(emulator: &Emulator, golden: &Path, tolerance: Tolerance) -> Result<(), String> {
+    let (width, height) = (NES_PIXEL_WIDTH as u32, NES_PIXEL_HEIGHT as u32);
+    let actual = emulator.frame.to_rgba8();
+    if std::env::var_os(BLESS_VAR).is_some() {
+        return write_png(golden, width, height, &actual);
+    }
+    if !golden.exists() {
+        return Err(format!("{} is missing; run with {}=1 to create it", golden.display(), BLESS_VAR));
+    }
+
+    let (golden_width, golden_height, expected) = read_png(golden)?;
+    let error = if (golden_width, golden_height) != (width, height) {
+        format!("{} is {}x{}, not {}x{}", golden.display(), golden_width, golden_height, width, height)
+    } else {
+        let difference = compare(&actual, &expected, tolerance.channel);
+        if difference.pixels <= tolerance.pixels {
+            let _ = std::fs::remove_file(actual_path(golden));
+            return Ok(());
+        }
+        let (x, y) = difference.first.unwrap_or_default();
+        format!(
+            "Frame {} differs from {} in {} pixels (allowed {}), first at ({}, {}), by up to {}",
+            emulator.frame_count(),
+            golden.display(),
+            difference.pixels,
+            tolerance.pixels,
+            x,
+            y,
+            difference.largest
+        )
+    };
+    let actual_path = actual_path(golden);
+    write_png(&actual_path, width, height, &actual)?;
+    Err(format!("{}; the frame is in {}", error, actual_path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test::create_test_cartridge;
+
+    #[test]
+    fn test_compare_with_tolerance() {
+        let expected = vec![10; (NES_PIXEL_WIDTH * NES_PIXEL_HEIGHT) as usize * 4];
+        let mut actual = expected.clone();
+        assert_eq!(compare(&actual, &expected, 0), Difference { pixels: 0, largest: 0, first: None });
+
+        // (3, 1) is off by 2 in green, (5, 2) by 9 in blue.
+        actual[(256 + 3) * 4 + 1] = 12;
+        actual[(512 + 5) * 4 + 2] = 1;
+        assert_eq!(compare(&actual, &expected, 0), Difference { pixels: 2, largest: 9, first: Some((3, 1)) });
+        assert_eq!(compare(&actual, &expected, 2), Difference { pixels: 1, largest: 9, first: Some((5, 2)) });
+        assert_eq!(compare(&actual, &expected, 9).pixels, 0);
+
+        assert_ne!(frame_hash(&actual), frame_hash(&expected));
+        assert_eq!(frame_hash(&[]), 0xcbf29ce484222325);
+        assert_eq!(frame_hash(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn test_check() {
+        if std::env::var_os(BLESS_VAR).is_some() {
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("nes_rs_golden_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let golden = dir.join("frame.png");
+        let emulator = Emulator::new(create_test_cartridge());
+        assert!(check(&emulator, &golden, Tolerance::EXACT).unwrap_err().contains("is missing"));
+
+        let mut rgba = emulator.frame.to_rgba8();
+        write_png(&golden, 256, 240, &rgba).unwrap();
+        assert_eq!(check(&emulator, &golden, Tolerance::EXACT), Ok(()));
+
+        rgba[0] ^= 0xff;
+        write_png(&golden, 256, 240, &rgba).unwrap();
+        let error = check(&emulator, &golden, Tolerance::EXACT).unwrap_err();
+        assert!(error.contains("in 1 pixels (allowed 0), first at (0, 0)"), "{}", error);
+        assert!(actual_path(&golden).exists());
+        assert_eq!(check(&emulator, &golden, Tolerance { channel: 0, pixels: 1 }), Ok(()));
+        assert!(!actual_path(&golden).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_actual_path() {
+        assert_eq!(actual_path(Path::new("tests/golden/title.png")), Path::new("tests/golden/title.actual.png"));
+    }
+}
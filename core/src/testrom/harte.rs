@@ -0,0 +1,305 @@
+//! Runs the 6502 SingleStepTests (Tom Harte's processor tests): a JSON file per opcode of 10,000
+//! cases, each giving the registers and memory before and after one instruction and every bus
+//! cycle the instruction makes. The CPU runs on flat 64kB RAM, so no PPU or cartridge is involved,
+//! and every opcode, unofficial ones included, can be checked on its own.
+//!
+//! Reference: <https://github.com/SingleStepTests/65x02/tree/main/nes6502>
+
+use std::fmt;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::cpu::{CPUFlags, CPU};
+use crate::debugger::watch::AccessKind;
+
+// Registers and the memory the case cares about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    pub pc: u16,
+    pub s: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub ram: Vec<(u16, u8)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    // The instruction's bytes, e.g. "b1 28 b5".
+    pub name: String,
+    pub initial: State,
+    pub expected: State,
+    // The bus activity of every cycle: (address, value, read or write).
+    pub cycles: Vec<(u16, u8, AccessKind)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    // The CPU halted instead of running the instruction: BRK, or an opcode it doesn't run.
+    Halted,
+    // Registers or memory ended up wrong, one description per difference.
+    State(Vec<String>),
+    CycleCount { expected: usize, actual: usize },
+    // The reads and writes differ from the listed cycles, first at `index`. None past the end.
+    BusActivity {
+        index: usize,
+        expected: Option<(u16, u8, AccessKind)>,
+        actual: Option<(u16, u8, AccessKind)>,
+    },
+}
+
+fn access(access: Option<(u16, u8, AccessKind)>) -> String {
+    match access {
+        Some((addr, value, AccessKind::Write)) => format!("write ${:02x} to ${:04x}", value, addr),
+        Some((addr, value, _)) => format!("read ${:02x} from ${:04x}", value, addr),
+        None => "nothing".to_string(),
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Halted => write!(f, "halted"),
+            Mismatch::State(differences) => write!(f, "{}", differences.join(", ")),
+            Mismatch::CycleCount { expected, actual } => write!(f, "took {} cycles, expected {}", actual, expected),
+            Mismatch::BusActivity { index, expected, actual } => write!(
+                f,
+                "access {} was {}, expected {}",
+                index,
+                access(*actual),
+                access(*expected)
+            ),
+        }
+    }
+}
+
+fn number(value: &Value, what: &str) -> Result<u64, String> {
+    value.as_u64().ok_or_else(|| format!("{} is not a number", what))
+}
+
+impl State {
+    fn from_json(value: &Value) -> Result<State, String> {
+        let register = |name: &str| number(&value[name], name);
+        let ram = value["ram"]
+            .as_array()
+            .ok_or("ram is not a list")?
+            .iter()
+            .map(|pair| Ok((number(&pair[0], "ram address")? as u16, number(&pair[1], "ram value")? as u8)))
+            .collect::<Result<_, String>>()?;
+        Ok(State {
+            pc: register("pc")? as u16,
+            s: register("s")? as u8,
+            a: register("a")? as u8,
+            x: register("x")? as u8,
+            y: register("y")? as u8,
+            p: register("p")? as u8,
+            ram,
+        })
+    }
+}
+
+impl Case {
+    pub fn from_json(value: &Value) -> Result<Case, String> {
+        let name = value["name"].as_str().ok_or("name is not a string")?.to_string();
+        let cycles = value["cycles"]
+            .as_array()
+            .ok_or("cycles is not a list")?
+            .iter()
+            .map(|cycle| {
+                let kind = match cycle[2].as_str() {
+                    Some("read") => AccessKind::Read,
+                    Some("write") => AccessKind::Write,
+                    _ => return Err(format!("{}: cycle kind is not read or write", name)),
+                };
+                Ok((number(&cycle[0], "cycle address")? as u16, number(&cycle[1], "cycle value")? as u8, kind))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Case {
+            initial: State::from_json(&value["initial"]).map_err(|e| format!("{}: initial {}", name, e))?,
+            expected: State::from_json(&value["final"]).map_err(|e| format!("{}: final {}", name, e))?,
+            name,
+            cycles,
+        })
+    }
+
+    // Runs the instruction and lists how the result differs from the expected one.
+    pub fn run(&self) -> Vec<Mismatch> {
+        let mut cpu = CPU::default();
+        cpu.bus.flat_ram = Some(vec![0; 0x10000]);
+        for (addr, value) in &self.initial.ram {
+            cpu.bus.poke(*addr, *value);
+        }
+        cpu.program_counter = self.initial.pc;
+        cpu.stack_pointer = self.initial.s;
+        cpu.register_a = self.initial.a;
+        cpu.register_x = self.initial.x;
+        cpu.register_y = self.initial.y;
+        cpu.status = CPUFlags::from_bits_retain(self.initial.p);
+
+        cpu.bus.access_log = Some(Vec::new());
+        let start = cpu.bus.cycles;
+        if !cpu.execute_instruction() {
+            return vec![Mismatch::Halted];
+        }
+        let cycles = cpu.bus.cycles - start;
+        let accesses = cpu.bus.access_log.take().unwrap_or_default();
+
+        let mut mismatches = Vec::new();
+        let mut differences = Vec::new();
+        let expected = &self.expected;
+        let registers = [
+            ("PC", cpu.program_counter, expected.pc),
+            ("S", cpu.stack_pointer as u16, expected.s as u16),
+            ("A", cpu.register_a as u16, expected.a as u16),
+            ("X", cpu.register_x as u16, expected.x as u16),
+            ("Y", cpu.register_y as u16, expected.y as u16),
+            ("P", cpu.status.bits() as u16, expected.p as u16),
+        ];
+        for (name, actual, expected) in registers {
+            if actual != expected {
+                differences.push(format!("{} is ${:02x}, expected ${:02x}", name, actual, expected));
+            }
+        }
+        for (addr, value) in &expected.ram {
+            let actual = cpu.bus.peek(*addr);
+            if actual != *value {
+                differences.push(format!("${:04x} is ${:02x}, expected ${:02x}", addr, actual, value));
+            }
+        }
+        if !differences.is_empty() {
+            mismatches.push(Mismatch::State(differences));
+        }
+
+        if cycles != self.cycles.len() {
+            mismatches.push(Mismatch::CycleCount { expected: self.cycles.len(), actual: cycles });
+        }
+        if let Some(index) = (0..accesses.len().max(self.cycles.len())).find(|i| accesses.get(*i) != self.cycles.get(*i)) {
+            mismatches.push(Mismatch::BusActivity {
+                index,
+                expected: self.cycles.get(index).copied(),
+                actual: accesses.get(index).copied(),
+            });
+        }
+        mismatches
+    }
+}
+
+// Reads a file of cases, e.g. `nes6502/v1/a9.json`.
+pub fn load(path: &Path) -> Result<Vec<Case>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let value: Value = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    value
+        .as_array()
+        .ok_or_else(|| format!("{}: not a list of cases", path.display()))?
+        .iter()
+        .map(|case| Case::from_json(case).map_err(|e| format!("{}: {}", path.display(), e)))
+        .collect()
+}
+
+// How many of a file's cases failed in each way, with the first failure of each kind.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub cases: usize,
+    pub halted: usize,
+    pub state: usize,
+    pub cycle_count: usize,
+    pub bus_activity: usize,
+    // "b1 28 b5: A is $12, expected $13", ...
+    pub examples: Vec<String>,
+}
+
+impl Report {
+    pub fn new(cases: &[Case]) -> Self {
+        let mut report = Report { cases: cases.len(), ..Report::default() };
+        for case in cases {
+            for mismatch in case.run() {
+                let count = match mismatch {
+                    Mismatch::Halted => &mut report.halted,
+                    Mismatch::State(_) => &mut report.state,
+                    Mismatch::CycleCount { .. } => &mut report.cycle_count,
+                    Mismatch::BusActivity { .. } => &mut report.bus_activity,
+                };
+                *count += 1;
+                if *count == 1 {
+                    report.examples.push(format!("{}: {}", case.name, mismatch));
+                }
+            }
+        }
+        report
+    }
+
+    // Whether every case ended in the right state after the right number of cycles. Bus
+    // activity is checked separately, as the CPU doesn't make the dummy reads the real one does.
+    pub fn passed(&self) -> bool {
+        self.halted == 0 && self.state == 0 && self.cycle_count == 0
+    }
+
+    // "10000 cases: 12 wrong state, 3 wrong cycle count, 10000 different bus activity"
+    pub fn summary(&self) -> String {
+        let counts = [
+            (self.halted, "halted"),
+            (self.state, "wrong state"),
+            (self.cycle_count, "wrong cycle count"),
+            (self.bus_activity, "different bus activity"),
+        ];
+        let failures: Vec<String> = counts
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, what)| format!("{} {}", count, what))
+            .collect();
+        match failures.is_empty() {
+            true => format!("{} cases: all passed", self.cases),
+            false => format!("{} cases: {}", self.cases, failures.join(", ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LDA #$42 at $1000, with the cycles listed.
+    const LDA: &str = r#"{
+        "name": "a9 42 00",
+        "initial": {"pc": 4096, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[4096, 169], [4097, 66], [4098, 0]]},
+        "final": {"pc": 4098, "s": 253, "a": 66, "x": 0, "y": 0, "p": 36, "ram": [[4096, 169], [4097, 66], [4098, 0]]},
+        "cycles": [[4096, 169, "read"], [4097, 66, "read"]]
+    }"#;
+
+    #[test]
+    fn test_passing_case() {
+        let case = Case::from_json(&serde_json::from_str(LDA).unwrap()).unwrap();
+        assert_eq!(case.name, "a9 42 00");
+        assert_eq!(case.cycles[1], (0x1001, 0x42, AccessKind::Read));
+        assert_eq!(case.run(), vec![]);
+        assert_eq!(Report::new(&[case]).summary(), "1 cases: all passed");
+    }
+
+    #[test]
+    fn test_mismatches() {
+        // Claim LDA loads $43 in three cycles, writing to $2000 on the way.
+        let json = LDA
+            .replace(r#""a": 66"#, r#""a": 67"#)
+            .replace(r#"[4097, 66, "read"]]"#, r#"[4097, 66, "read"], [8192, 1, "write"]]"#);
+        let case = Case::from_json(&serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(
+            case.run(),
+            vec![
+                Mismatch::State(vec!["A is $42, expected $43".to_string()]),
+                Mismatch::CycleCount { expected: 3, actual: 2 },
+                Mismatch::BusActivity { index: 2, expected: Some((0x2000, 1, AccessKind::Write)), actual: None },
+            ]
+        );
+        let report = Report::new(&[case]);
+        assert!(!report.passed());
+        assert_eq!(report.summary(), "1 cases: 1 wrong state, 1 wrong cycle count, 1 different bus activity");
+        assert_eq!(report.examples[2], "a9 42 00: access 2 was nothing, expected write $01 to $2000");
+
+        // JAM.
+        let json = LDA.replace("[4096, 169]", "[4096, 2]");
+        let case = Case::from_json(&serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(case.run(), vec![Mismatch::Halted]);
+    }
+}
@@ -0,0 +1,243 @@
+//! Runs test ROMs and CPU test suites headlessly and reports whether they passed, for tracking
+//! accuracy as the emulator grows.
+//!
+//! `run_rom` judges any ROM: by the picture if a `.hash` file sits next to it, and otherwise by
+//! the result blargg's ROMs (and many others since) report at $6000. A `.hash` file holds the
+//! number of frames to run and the `golden::frame_hash` of the picture after them, e.g.
+//! `120 9c2f0e5d1a7b3c48`.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::cartridge::Cartridge;
+use crate::emulator::{Emulator, StepResult};
+
+pub mod blargg;
+pub mod golden;
+pub mod harte;
+pub mod report;
+
+// How a ROM was judged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    // The result code at $6000.
+    Status,
+    // The picture's hash after the frames in the ROM's .hash file.
+    FrameHash,
+    // Neither was available.
+    None,
+}
+
+impl Check {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Check::Status => "$6000",
+            Check::FrameHash => "frame hash",
+            Check::None => "none",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Passed,
+    Failed(String),
+    // There was nothing to judge the ROM by.
+    Skipped(String),
+    // The ROM couldn't be loaded, or the emulator panicked.
+    Error(String),
+}
+
+impl Verdict {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Verdict::Passed => "passed",
+            Verdict::Failed(_) => "failed",
+            Verdict::Skipped(_) => "skipped",
+            Verdict::Error(_) => "error",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Verdict::Passed => "",
+            Verdict::Failed(message) | Verdict::Skipped(message) | Verdict::Error(message) => message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomResult {
+    pub path: PathBuf,
+    pub check: Check,
+    pub verdict: Verdict,
+    pub frames: u64,
+    pub elapsed: Duration,
+}
+
+// Runs one frame, turning a panic into an error with its message.
+pub fn run_frame(emulator: &mut Emulator) -> Result<StepResult, String> {
+    panic::catch_unwind(AssertUnwindSafe(|| loop {
+        match emulator.step(|_| true) {
+            StepResult::Running => {}
+            result => return result,
+        }
+    }))
+    .map_err(|payload| {
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|message| message.to_string()))
+            .unwrap_or_default()
+    })
+}
+
+// The .hash file for `rom`: `name.nes` has `name.hash`.
+pub fn hash_path(rom: &Path) -> PathBuf {
+    rom.with_extension("hash")
+}
+
+// Reads a .hash file as (frames, hash).
+fn read_hash(path: &Path) -> Result<(u64, u64), String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let mut fields = text.split_whitespace();
+    let frames = fields.next().and_then(|frames| frames.parse().ok());
+    let hash = fields.next().and_then(|hash| u64::from_str_radix(hash.trim_start_matches("0x"), 16).ok());
+    match (frames, hash) {
+        (Some(frames), Some(hash)) => Ok((frames, hash)),
+        _ => Err(format!("{}: expected the number of frames and a hash", path.display())),
+    }
+}
+
+// Runs `frames` frames and hashes the picture.
+fn hash_after(emulator: &mut Emulator, frames: u64) -> Result<u64, String> {
+    for _ in 0..frames {
+        if run_frame(emulator)? == StepResult::Halted {
+            break;
+        }
+    }
+    Ok(golden::frame_hash(&emulator.frame.to_rgba8()))
+}
+
+// Runs the ROM at `rom` headlessly and judges it, giving a blargg-style ROM up to `max_frames`
+// frames to report. With `write_hash`, a ROM with nothing to judge it by instead gets a .hash file
+// recording its picture after `max_frames` frames, for later runs to compare against.
+pub fn run_rom(rom: &Path, max_frames: u64, write_hash: bool) -> RomResult {
+    let start = Instant::now();
+    let mut result = RomResult {
+        path: rom.to_path_buf(),
+        check: Check::None,
+        verdict: Verdict::Passed,
+        frames: 0,
+        elapsed: Duration::ZERO,
+    };
+    let loaded = std::fs::read(rom)
+        .map_err(|e| format!("Could not read {}: {}", rom.display(), e))
+        .and_then(|bytes| Cartridge::new(&bytes));
+    let mut emulator = match loaded {
+        Ok(cartridge) => Emulator::new(cartridge),
+        Err(e) => {
+            result.verdict = Verdict::Error(e);
+            return result;
+        }
+    };
+
+    let hash_file = hash_path(rom);
+    result.verdict = if hash_file.exists() {
+        result.check = Check::FrameHash;
+        match read_hash(&hash_file) {
+            Ok((frames, expected)) => {
+                result.frames = frames;
+                match hash_after(&mut emulator, frames) {
+                    Ok(hash) if hash == expected => Verdict::Passed,
+                    Ok(hash) => Verdict::Failed(format!("frame hash is {:016x}, expected {:016x}", hash, expected)),
+                    Err(e) => Verdict::Error(e),
+                }
+            }
+            Err(e) => Verdict::Error(e),
+        }
+    } else {
+        result.check = Check::Status;
+        let outcome = blargg::run(&mut emulator, max_frames);
+        result.frames = outcome.frames;
+        let message = match outcome.text.is_empty() {
+            true => outcome.summary(),
+            false => format!("{}: {}", outcome.summary(), outcome.text),
+        };
+        match outcome.status {
+            blargg::Status::Passed => Verdict::Passed,
+            blargg::Status::Crashed(_) => Verdict::Error(message),
+            blargg::Status::NoSignature => {
+                result.check = Check::None;
+                match write_hash {
+                    true => {
+                        let hash = golden::frame_hash(&emulator.frame.to_rgba8());
+                        let written = std::fs::write(&hash_file, format!("{} {:016x}\n", outcome.frames, hash));
+                        match written {
+                            Ok(()) => Verdict::Skipped(format!("no result at $6000; wrote {}", hash_file.display())),
+                            Err(e) => Verdict::Error(format!("{}: {}", hash_file.display(), e)),
+                        }
+                    }
+                    false => Verdict::Skipped("no result at $6000 and no .hash file".to_string()),
+                }
+            }
+            _ => Verdict::Failed(message),
+        }
+    };
+    result.elapsed = start.elapsed();
+    result
+}
+
+// The .nes files under `dir` and its subdirectories, sorted by path.
+pub fn find_roms(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut roms = Vec::new();
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("Could not read {}: {}", dir.display(), e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| format!("Could not read {}: {}", dir.display(), e))?.path();
+        if path.is_dir() {
+            roms.extend(find_roms(&path)?);
+        } else if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("nes")) {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    Ok(roms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_rom_by_frame_hash() {
+        let dir = std::env::temp_dir().join(format!("nes_rs_testrom_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // NOPs from $C000 and a JMP back, so it never reports at $6000.
+        let rom = dir.join("nops.nes");
+        let mut bytes = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0xea; 0x4000];
+        prg[0x3ff7..].copy_from_slice(&[0x4c, 0x00, 0xc0, 0x00, 0xc0, 0x00, 0xc0, 0x00, 0xc0]);
+        bytes.extend(prg);
+        bytes.extend(vec![0; 0x2000]);
+        std::fs::write(&rom, bytes).unwrap();
+
+        let result = run_rom(&rom, 3, false);
+        assert_eq!(result.verdict.name(), "skipped");
+        assert_eq!((result.check, result.verdict), (Check::None, Verdict::Skipped("no result at $6000 and no .hash file".to_string())));
+        assert!(!hash_path(&rom).exists());
+
+        let result = run_rom(&rom, 3, true);
+        assert_eq!(result.verdict, Verdict::Skipped(format!("no result at $6000; wrote {}", hash_path(&rom).display())));
+        let result = run_rom(&rom, 3, false);
+        assert_eq!((result.check, result.verdict, result.frames), (Check::FrameHash, Verdict::Passed, 3));
+
+        std::fs::write(hash_path(&rom), "3 0").unwrap();
+        assert!(matches!(run_rom(&rom, 3, false).verdict, Verdict::Failed(message) if message.ends_with("expected 0000000000000000")));
+        std::fs::write(hash_path(&rom), "three").unwrap();
+        assert_eq!(run_rom(&rom, 3, false).verdict.name(), "error");
+        assert_eq!(run_rom(&dir.join("missing.nes"), 3, false).verdict.name(), "error");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
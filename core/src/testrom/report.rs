@@ -0,0 +1,135 @@
+//! Machine-readable results of a test ROM run: JSON for tracking compatibility over time, and
+//! JUnit XML for CI systems that show test results.
+
+use serde_json::json;
+
+use super::{RomResult, Verdict};
+
+// Passed, failed, skipped and errored counts.
+pub fn counts(results: &[RomResult]) -> (usize, usize, usize, usize) {
+    let count = |wanted: fn(&Verdict) -> bool| results.iter().filter(|result| wanted(&result.verdict)).count();
+    (
+        count(|verdict| matches!(verdict, Verdict::Passed)),
+        count(|verdict| matches!(verdict, Verdict::Failed(_))),
+        count(|verdict| matches!(verdict, Verdict::Skipped(_))),
+        count(|verdict| matches!(verdict, Verdict::Error(_))),
+    )
+}
+
+pub fn to_json(results: &[RomResult]) -> String {
+    let (passed, failed, skipped, errors) = counts(results);
+    let roms: Vec<_> = results
+        .iter()
+        .map(|result| {
+            json!({
+                "path": result.path.display().to_string(),
+                "check": result.check.name(),
+                "result": result.verdict.name(),
+                "message": result.verdict.message(),
+                "frames": result.frames,
+                "seconds": result.elapsed.as_secs_f64(),
+            })
+        })
+        .collect();
+    let report = json!({
+        "passed": passed,
+        "failed": failed,
+        "skipped": skipped,
+        "errors": errors,
+        "roms": roms,
+    });
+    serde_json::to_string_pretty(&report).unwrap()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// One test suite, with a test case per ROM named after its file and classed by its directory.
+pub fn to_junit(results: &[RomResult]) -> String {
+    let (_, failed, skipped, errors) = counts(results);
+    let time: f64 = results.iter().map(|result| result.elapsed.as_secs_f64()).sum();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites>\n  <testsuite name=\"test-roms\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        results.len(),
+        failed,
+        errors,
+        skipped,
+        time
+    ));
+    for result in results {
+        let name = result.path.file_name().unwrap_or_default().to_string_lossy();
+        let class = result.path.parent().map(|dir| dir.display().to_string()).unwrap_or_default();
+        xml.push_str(&format!(
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+            escape(&class),
+            escape(&name),
+            result.elapsed.as_secs_f64()
+        ));
+        let element = match result.verdict {
+            Verdict::Passed => None,
+            Verdict::Failed(_) => Some("failure"),
+            Verdict::Skipped(_) => Some("skipped"),
+            Verdict::Error(_) => Some("error"),
+        };
+        match element {
+            None => xml.push_str("/>\n"),
+            Some(element) => xml.push_str(&format!(
+                ">\n      <{} message=\"{}\"/>\n    </testcase>\n",
+                element,
+                escape(result.verdict.message())
+            )),
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testrom::Check;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn results() -> Vec<RomResult> {
+        let result = |path: &str, check, verdict| RomResult {
+            path: PathBuf::from(path),
+            check,
+            verdict,
+            frames: 60,
+            elapsed: Duration::from_millis(250),
+        };
+        vec![
+            result("roms/cpu/01-basics.nes", Check::Status, Verdict::Passed),
+            result("roms/cpu/02-implied.nes", Check::Status, Verdict::Failed("failed (code 2): <BRK> & co".to_string())),
+            result("roms/demo.nes", Check::None, Verdict::Skipped("nothing to check".to_string())),
+        ]
+    }
+
+    #[test]
+    fn test_json() {
+        let report: serde_json::Value = serde_json::from_str(&to_json(&results())).unwrap();
+        assert_eq!(report["passed"], 1);
+        assert_eq!(report["failed"], 1);
+        assert_eq!(report["skipped"], 1);
+        assert_eq!(report["roms"][1]["path"], "roms/cpu/02-implied.nes");
+        assert_eq!(report["roms"][1]["check"], "$6000");
+        assert_eq!(report["roms"][1]["result"], "failed");
+        assert_eq!(report["roms"][1]["message"], "failed (code 2): <BRK> & co");
+        assert_eq!(report["roms"][0]["seconds"], 0.25);
+    }
+
+    #[test]
+    fn test_junit() {
+        let xml = to_junit(&results());
+        assert!(xml.contains("<testsuite name=\"test-roms\" tests=\"3\" failures=\"1\" errors=\"0\" skipped=\"1\" time=\"0.750\">"));
+        assert!(xml.contains("<testcase classname=\"roms/cpu\" name=\"01-basics.nes\" time=\"0.250\"/>"));
+        assert!(xml.contains("<failure message=\"failed (code 2): &lt;BRK&gt; &amp; co\"/>"));
+        assert!(xml.contains("<skipped message=\"nothing to check\"/>"));
+    }
+}
@@ -0,0 +1,157 @@
+//! The VS UniSystem's extra inputs and its cartridge board (mapper 99): eight DIP switches, two
+//! coin slots and a service button read through the controller ports, and an 8kB CHR bank switched
+//! by $4016 bit 2.
+//!
+//! | $4016 read | bit 2 service button, bits 3-4 DIP switches 1-2, bits 5-6 coin slots 1-2 |
+//! | $4017 read | bits 2-7 DIP switches 3-8 |
+//!
+//! Reference: https://www.nesdev.org/wiki/Vs._System
+
+use crate::ppu::PPU;
+use crate::savestate::{StateReader, StateWriter};
+
+pub const COIN_SLOTS: usize = 2;
+// Frames a coin holds its switch closed as it drops, long enough for every game to count it.
+const COIN_FRAMES: u8 = 4;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+pub struct VsSystem {
+    // Switch 1 in bit 0 to switch 8 in bit 7, set for on.
+    pub dip_switches: u8,
+    // Frames left that each coin switch, and the service button, stay closed.
+    coins: [u8; COIN_SLOTS],
+    service: u8,
+    // All of CHR-ROM; the selected bank is copied into the PPU.
+    chr: Vec<u8>,
+    chr_bank: usize,
+}
+
+impl VsSystem {
+    pub fn new(chr: Vec<u8>) -> Self {
+        VsSystem {
+            dip_switches: 0,
+            coins: [0; COIN_SLOTS],
+            service: 0,
+            chr,
+            chr_bank: 0,
+        }
+    }
+
+    // Drops a coin into `slot` (0 or 1).
+    pub fn insert_coin(&mut self, slot: usize) {
+        self.coins[slot] = COIN_FRAMES;
+    }
+
+    // Presses the service button, which most games count as a credit.
+    pub fn press_service(&mut self) {
+        self.service = COIN_FRAMES;
+    }
+
+    // The bits this board adds to a $4016 or $4017 read, next to the controller's.
+    pub fn read(&self, port: usize) -> u8 {
+        match port {
+            0 => {
+                ((self.service > 0) as u8) << 2
+                    | (self.dip_switches & 0b11) << 3
+                    | ((self.coins[0] > 0) as u8) << 5
+                    | ((self.coins[1] > 0) as u8) << 6
+            }
+            _ => self.dip_switches & 0b1111_1100,
+        }
+    }
+
+    // A $4016 write: bit 2 picks the CHR bank on boards with 16kB of CHR-ROM.
+    pub fn write(&mut self, value: u8, ppu: &mut PPU) {
+        let bank = (value >> 2 & 1) as usize;
+        if bank != self.chr_bank && self.chr.len() >= 2 * CHR_BANK_SIZE {
+            self.chr_bank = bank;
+            self.map_chr(ppu);
+        }
+    }
+
+    fn map_chr(&self, ppu: &mut PPU) {
+        let start = self.chr_bank * CHR_BANK_SIZE;
+        if let Some(bank) = self.chr.get(start..start + CHR_BANK_SIZE) {
+            ppu.chr_rom[..CHR_BANK_SIZE].copy_from_slice(bank);
+            ppu.chr_changed();
+        }
+    }
+
+    // Lets go of coin switches and the service button once their time is up. The emulator calls
+    // this after each frame.
+    pub fn end_frame(&mut self) {
+        for frames in self.coins.iter_mut().chain([&mut self.service]) {
+            *frames = frames.saturating_sub(1);
+        }
+    }
+
+    // The DIP switches are part of the state, since games read them at any time.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.dip_switches);
+        state.bytes(&self.coins);
+        state.u8(self.service);
+        state.u8(self.chr_bank as u8);
+    }
+
+    // Also switches the PPU to the saved CHR bank.
+    pub fn load_state(&mut self, state: &mut StateReader, ppu: &mut PPU) -> Result<(), String> {
+        self.dip_switches = state.u8()?;
+        state.bytes(&mut self.coins)?;
+        self.service = state.u8()?;
+        self.chr_bank = (state.u8()? & 1) as usize;
+        self.map_chr(ppu);
+        Ok(())
+    }
+}
+
+// Parses DIP switch settings written as eight 0s and 1s, switch 1 first, the way arcade manuals
+// list them.
+pub fn parse_dip_switches(s: &str) -> Result<u8, String> {
+    if s.len() != 8 || !s.bytes().all(|c| c == b'0' || c == b'1') {
+        return Err(format!("DIP switches are eight 0s and 1s, switch 1 first, not {}", s));
+    }
+    Ok(s.bytes().enumerate().fold(0, |switches, (i, c)| switches | ((c - b'0') << i)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Mirroring;
+
+    #[test]
+    fn test_dip_switches_and_coins_read_through_the_ports() {
+        let mut vs = VsSystem::new(Vec::new());
+        vs.dip_switches = parse_dip_switches("10000011").unwrap();
+        assert_eq!(vs.dip_switches, 0b1100_0001);
+        assert_eq!(vs.read(0), 0b0000_1000);
+        assert_eq!(vs.read(1), 0b1100_0000);
+
+        vs.insert_coin(1);
+        vs.press_service();
+        for _ in 0..COIN_FRAMES {
+            assert_eq!(vs.read(0) & 0b0110_0100, 0b0100_0100);
+            vs.end_frame();
+        }
+        assert_eq!(vs.read(0) & 0b0110_0100, 0);
+
+        assert!(parse_dip_switches("1000001").is_err());
+        assert!(parse_dip_switches("1000001x").is_err());
+    }
+
+    #[test]
+    fn test_chr_bank_switching() {
+        let chr: Vec<u8> = (0..2 * CHR_BANK_SIZE).map(|i| (i / CHR_BANK_SIZE) as u8 + 1).collect();
+        let mut ppu = PPU::new(chr.clone(), Mirroring::Horizontal);
+        let mut vs = VsSystem::new(chr);
+        assert_eq!(ppu.chr_rom[0], 1);
+        vs.write(0b100, &mut ppu);
+        assert_eq!((ppu.chr_rom[0], ppu.chr_rom[CHR_BANK_SIZE - 1]), (2, 2));
+
+        let mut state = StateWriter::new();
+        vs.save_state(&mut state);
+        vs.write(0, &mut ppu);
+        assert_eq!(ppu.chr_rom[0], 1);
+        vs.load_state(&mut StateReader::new(&state.into_bytes()), &mut ppu).unwrap();
+        assert_eq!(ppu.chr_rom[0], 2);
+    }
+}
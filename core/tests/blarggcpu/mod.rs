@@ -0,0 +1,31 @@
+//! Runs blargg's test ROMs through `nes_rs_core::testrom::blargg` and checks each reports a pass at
+//! $6000. The ROMs aren't in the repository: put any of blargg's suites (instr_test-v5's
+//! rom_singles, ppu_vbl_nmi, apu_test, ...) anywhere under tests/blarggcpu and run
+//! `cargo test -- --ignored`. Every ROM found is run, and all failures are listed together.
+//!
+//! Reference: <https://github.com/christopherpow/nes-test-roms>
+
+#[cfg(test)]
+mod blarggcpu {
+    use std::path::Path;
+
+    use nes_rs_core::testrom::blargg::{self, DEFAULT_MAX_FRAMES};
+    use nes_rs_core::testrom::find_roms;
+
+    #[test]
+    #[ignore = "requires blargg's test ROMs under tests/blarggcpu"]
+    fn blargg_roms() {
+        let roms = find_roms(Path::new("tests/blarggcpu")).unwrap();
+        assert!(!roms.is_empty(), "No .nes files under tests/blarggcpu");
+
+        let mut failures = Vec::new();
+        for rom in &roms {
+            let outcome = blargg::run_rom(rom, DEFAULT_MAX_FRAMES).unwrap();
+            println!("{}: {}", rom.display(), outcome.summary());
+            if !outcome.passed() {
+                failures.push(format!("{}: {}\n{}", rom.display(), outcome.summary(), outcome.text));
+            }
+        }
+        assert!(failures.is_empty(), "{} of {} ROMs failed:\n{}", failures.len(), roms.len(), failures.join("\n\n"));
+    }
+}
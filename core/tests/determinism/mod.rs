@@ -0,0 +1,34 @@
+//! Runs nestest twice in lockstep through its menu, starting some of its tests, and checks the two
+//! runs agree after every frame, including when one is rebuilt from its save state each frame.
+
+#[cfg(test)]
+mod determinism {
+    use nes_rs_core::cartridge::Cartridge;
+    use nes_rs_core::joypad::JoypadButton;
+    use nes_rs_core::movie::Movie;
+    use nes_rs_core::savestate::determinism::verify;
+
+    const FRAMES: u64 = 120;
+
+    fn movie(cartridge: &Cartridge) -> Movie {
+        let mut movie = Movie::new(cartridge.hash());
+        for frame in 0..FRAMES {
+            let buttons = match frame % 40 {
+                0..=3 => JoypadButton::DOWN,
+                20..=23 => JoypadButton::START,
+                _ => JoypadButton::empty(),
+            };
+            movie.inputs.push([buttons, JoypadButton::empty(), JoypadButton::empty(), JoypadButton::empty()]);
+        }
+        movie
+    }
+
+    #[test]
+    fn nestest_is_deterministic() {
+        let bytes: Vec<u8> = std::fs::read("tests/nestest/nestest.nes").unwrap();
+        let cartridge = Cartridge::new(&bytes).unwrap();
+        let movie = movie(&cartridge);
+        assert_eq!(verify(&cartridge, Some(&movie), FRAMES, false), Ok(None));
+        assert_eq!(verify(&cartridge, Some(&movie), FRAMES, true), Ok(None));
+    }
+}
@@ -0,0 +1,32 @@
+//! Golden-frame tests, locking in what the PPU draws. Each runs a ROM headlessly and compares a
+//! frame with a PNG in this directory; a frame that no longer matches is written next to it as
+//! `<name>.actual.png`. After a deliberate change in rendering, run
+//! `NES_RS_BLESS=1 cargo test golden` to replace the images, and look at them before committing.
+
+#[cfg(test)]
+mod golden {
+    use std::path::Path;
+
+    use nes_rs_core::render::constants::{NES_PIXEL_HEIGHT, NES_PIXEL_WIDTH};
+    use nes_rs_core::render::Layers;
+    use nes_rs_core::testrom::golden::{self, Tolerance};
+
+    #[test]
+    fn nestest_menu() {
+        let emulator = golden::run_rom(Path::new("tests/nestest/nestest.nes"), 30).unwrap();
+        golden::check(&emulator, Path::new("tests/golden/nestest-menu.png"), Tolerance::EXACT).unwrap();
+    }
+
+    // The menu is all background, so hiding it leaves only the backdrop color.
+    #[test]
+    fn nestest_menu_without_background() {
+        let mut emulator = golden::run_rom(Path::new("tests/nestest/nestest.nes"), 30).unwrap();
+        emulator.set_layers(Layers { background: false, ..emulator.layers() });
+        let visible = (NES_PIXEL_WIDTH * NES_PIXEL_HEIGHT) as usize;
+        let backdrop = emulator.frame.data[0];
+        assert!(emulator.frame.data[..visible].iter().all(|color| *color == backdrop));
+
+        emulator.set_layers(Layers::default());
+        golden::check(&emulator, Path::new("tests/golden/nestest-menu.png"), Tolerance::EXACT).unwrap();
+    }
+}
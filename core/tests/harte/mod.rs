@@ -0,0 +1,55 @@
+//! Tom Harte's per-opcode CPU tests, from https://github.com/SingleStepTests/65x02/tree/main/nes6502.
+//! They aren't in the repository: put the JSON files at tests/harte/nes6502/v1/[opcode].json and
+//! run `cargo test harte -- --ignored --nocapture`. Each opcode's file is run through
+//! `nes_rs_core::testrom::harte`, which prints a line per opcode and lists the first failure of each kind.
+
+#[cfg(test)]
+mod harte {
+    use std::path::{Path, PathBuf};
+
+    use nes_rs_core::testrom::harte::{self, Report};
+
+    const DIR: &str = "tests/harte/nes6502/v1";
+
+    fn path(opcode: u8) -> PathBuf {
+        Path::new(DIR).join(format!("{:02x}.json", opcode))
+    }
+
+    fn run_opcode(opcode: u8) -> Report {
+        let report = Report::new(&harte::load(&path(opcode)).unwrap());
+        println!("{:02x}: {}", opcode, report.summary());
+        for example in &report.examples {
+            println!("    {}", example);
+        }
+        report
+    }
+
+    // Runs every opcode with a file, and fails if any ended in the wrong state or took the wrong
+    // number of cycles. BRK, which the emulator treats as a halt, and the opcodes it doesn't run
+    // are listed but not failed.
+    #[test]
+    #[ignore = "requires the SingleStepTests JSON files in tests/harte/nes6502/v1"]
+    fn run_all_opcodes() {
+        let mut failed = Vec::new();
+        let mut halted = Vec::new();
+        let mut bus_activity = 0;
+        for opcode in (0..=255).filter(|opcode| path(*opcode).exists()) {
+            let report = run_opcode(opcode);
+            bus_activity += report.bus_activity;
+            if report.halted == report.cases {
+                halted.push(format!("{:02x}", opcode));
+            } else if !report.passed() {
+                failed.push(format!("{:02x}", opcode));
+            }
+        }
+        println!("Not run (halts): {}", halted.join(" "));
+        println!("Cases with different bus activity: {}", bus_activity);
+        assert!(failed.is_empty(), "Opcodes failing: {}", failed.join(" "));
+    }
+
+    #[test]
+    #[ignore = "requires the SingleStepTests JSON files in tests/harte/nes6502/v1"]
+    fn test_opcode() {
+        assert!(run_opcode(0x01).passed());
+    }
+}
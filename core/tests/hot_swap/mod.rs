@@ -0,0 +1,46 @@
+//! Checks that a ROM can be swapped in while the emulator is running.
+
+#[cfg(test)]
+mod hot_swap {
+    use std::path::Path;
+
+    use nes_rs_core::cartridge::{test::create_test_cartridge, Cartridge};
+    use nes_rs_core::emulator::Emulator;
+    use nes_rs_core::movie::MovieState;
+
+    const NESTEST: &str = "tests/nestest/nestest.nes";
+
+    #[test]
+    fn open_rom_replaces_cartridge_and_resets() {
+        let mut emulator = Emulator::new(Cartridge::new(&std::fs::read(NESTEST).unwrap()).unwrap());
+        for _ in 0..5 {
+            emulator.run_frame();
+        }
+        emulator.record_movie();
+
+        emulator.load_cartridge(create_test_cartridge());
+        assert_eq!(emulator.frame_count(), 0);
+        assert!(matches!(emulator.movie_state(), MovieState::Inactive));
+
+        emulator.open_rom(Path::new(NESTEST)).unwrap();
+        let nestest = Cartridge::new(&std::fs::read(NESTEST).unwrap()).unwrap();
+        assert_eq!(emulator.cartridge().hash(), nestest.hash());
+        assert_eq!(emulator.frame_count(), 0);
+        for _ in 0..5 {
+            emulator.run_frame();
+        }
+        assert_eq!(emulator.frame_count(), 5);
+    }
+
+    #[test]
+    fn open_rom_error_keeps_current_game() {
+        let mut emulator = Emulator::new(Cartridge::new(&std::fs::read(NESTEST).unwrap()).unwrap());
+        emulator.run_frame();
+        let hash = emulator.cartridge().hash();
+
+        assert!(emulator.open_rom(Path::new("tests/missing.nes")).is_err());
+        assert!(emulator.open_rom(Path::new("tests/nestest/nestestmaster.log")).is_err());
+        assert_eq!(emulator.cartridge().hash(), hash);
+        assert_eq!(emulator.frame_count(), 1);
+    }
+}
@@ -0,0 +1,26 @@
+//! Checks lag frame detection on nestest, which only polls the controllers once its menu is up.
+
+#[cfg(test)]
+mod lag {
+    use nes_rs_core::cartridge::Cartridge;
+    use nes_rs_core::emulator::Emulator;
+
+    #[test]
+    fn lag_frames_counted_until_game_polls_input() {
+        let bytes: Vec<u8> = std::fs::read("tests/nestest/nestest.nes").unwrap();
+        let mut emulator = Emulator::new(Cartridge::new(&bytes).unwrap());
+
+        let mut lag = Vec::new();
+        for _ in 0..10 {
+            emulator.run_frame();
+            lag.push(emulator.is_lag_frame());
+        }
+        let polled_from = lag.iter().position(|lagged| !lagged).unwrap();
+        assert!(lag[polled_from..].iter().all(|lagged| !lagged));
+        assert_eq!(emulator.lag_count(), polled_from as u64);
+        assert!(emulator.input_reads() >= 8);
+
+        emulator.power_on();
+        assert_eq!(emulator.lag_count(), 0);
+    }
+}
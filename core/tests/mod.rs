@@ -0,0 +1,11 @@
+#![allow(clippy::module_inception)]
+
+mod nestest;
+mod harte;
+mod blarggcpu;
+mod movie;
+mod lag;
+mod hot_swap;
+mod savestate;
+mod golden;
+mod determinism;
@@ -0,0 +1,86 @@
+//! Records a movie on nestest's interactive menu and checks that replaying it from power-on ends
+//! in exactly the same machine state.
+
+#[cfg(test)]
+mod movie {
+    use nes_rs_core::cartridge::Cartridge;
+    use nes_rs_core::emulator::Emulator;
+    use nes_rs_core::joypad::JoypadButton;
+    use nes_rs_core::movie::{Movie, MovieState};
+
+    const FRAMES: u64 = 180;
+
+    fn scripted_input(frame: u64) -> JoypadButton {
+        match frame % 40 {
+            0..=3 => JoypadButton::DOWN,
+            20..=23 => JoypadButton::START,
+            _ => JoypadButton::empty(),
+        }
+    }
+
+    fn snapshot(emulator: &Emulator) -> (Vec<u8>, u16, u8, u8, u8) {
+        let cpu = &emulator.cpu;
+        (
+            cpu.bus.cpu_wram.to_vec(),
+            cpu.program_counter,
+            cpu.register_a,
+            cpu.register_x,
+            cpu.register_y,
+        )
+    }
+
+    #[test]
+    fn movie_replays_deterministically() {
+        let bytes: Vec<u8> = std::fs::read("tests/nestest/nestest.nes").unwrap();
+        let mut emulator = Emulator::new(Cartridge::new(&bytes).unwrap());
+
+        emulator.record_movie();
+        for frame in 0..FRAMES {
+            emulator.set_buttons(0, scripted_input(frame));
+            emulator.run_frame();
+        }
+        assert_eq!(emulator.frame_count(), FRAMES);
+        let recorded_state = snapshot(&emulator);
+        let movie = emulator.stop_movie().unwrap();
+        assert_eq!(movie.len() as u64, FRAMES);
+
+        // Round trip through the file format, and ignore live input during playback.
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        emulator.play_movie(movie).unwrap();
+        for _ in 0..FRAMES {
+            emulator.set_buttons(0, JoypadButton::BUTTON_A);
+            emulator.run_frame();
+        }
+        assert_eq!(snapshot(&emulator), recorded_state);
+
+        // The movie is used up on the next frame.
+        emulator.run_frame();
+        assert_eq!(emulator.movie_state(), &MovieState::Inactive);
+    }
+
+    #[test]
+    fn movie_rejects_other_roms() {
+        let bytes: Vec<u8> = std::fs::read("tests/nestest/nestest.nes").unwrap();
+        let mut emulator = Emulator::new(Cartridge::new(&bytes).unwrap());
+        assert!(emulator.play_movie(Movie::new(0)).is_err());
+    }
+
+    #[test]
+    fn movie_restores_four_score() {
+        let bytes: Vec<u8> = std::fs::read("tests/nestest/nestest.nes").unwrap();
+        let mut emulator = Emulator::new(Cartridge::new(&bytes).unwrap());
+
+        emulator.set_four_score(true);
+        emulator.record_movie();
+        emulator.set_buttons(3, JoypadButton::START);
+        emulator.run_frame();
+        let movie = emulator.stop_movie().unwrap();
+        assert!(movie.four_score);
+        assert_eq!(movie.inputs[0][3], JoypadButton::START);
+
+        emulator.set_four_score(false);
+        emulator.play_movie(movie).unwrap();
+        emulator.run_frame();
+        assert_eq!(emulator.cpu.bus.four_score.unwrap().button_status[1], JoypadButton::START);
+    }
+}
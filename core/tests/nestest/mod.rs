@@ -0,0 +1,88 @@
+//! This is a test based on Kevin Horton's NES CPU test here: https://www.qmtpro.com/~nes/misc/nestest.txt
+//! nestestmaster.log is the expected output
+//! The last few lines seem to deal with the I/O register and have been removed. We've also moved cycles "down" one slot.
+//!
+//! The ROM runs in automation mode from $C000, and every line of the trace is compared with the
+//! log. The test stops at the first line that differs and reports it with the lines leading up to
+//! it, since the instruction before a divergence is usually the one that is wrong. It passes
+//! quietly if the ROM isn't there, e.g. in a checkout without test ROMs.
+
+#[cfg(test)]
+mod nestest {
+    use std::fs;
+    use std::path::Path;
+
+    use nes_rs_core::cartridge::Cartridge;
+    use nes_rs_core::bus::Bus;
+    use nes_rs_core::cpu::{trace, CPU};
+
+    const ROM: &str = "tests/nestest/nestest.nes";
+    const LOG: &str = "tests/nestest/nestestmaster.log";
+    // Lines of the log shown before a divergence.
+    const CONTEXT: usize = 8;
+
+    // A report of where `actual` first differs from line `index` of `expected`, or None if it
+    // doesn't. `actual` is None if the CPU halted before the log ended.
+    fn divergence(expected: &[String], index: usize, actual: Option<&str>) -> Option<String> {
+        let want = &expected[index];
+        if actual == Some(want.as_str()) {
+            return None;
+        }
+        let mut report = format!("Trace diverges from {} at line {}:\n", LOG, index + 1);
+        for line in &expected[index.saturating_sub(CONTEXT)..index] {
+            report.push_str(&format!("           {}\n", line));
+        }
+        report.push_str(&format!("  expected {}\n", want));
+        match actual {
+            Some(actual) => {
+                let column = want.chars().zip(actual.chars()).take_while(|(a, b)| a == b).count();
+                report.push_str(&format!("  actual   {}\n", actual));
+                report.push_str(&format!("           {}^", " ".repeat(column)));
+            }
+            None => report.push_str("  actual   (the CPU halted)"),
+        }
+        Some(report)
+    }
+
+    #[test]
+    fn nestest() {
+        if !Path::new(ROM).exists() {
+            println!("Skipping nestest: {} is missing", ROM);
+            return;
+        }
+        let bytes: Vec<u8> = fs::read(ROM).unwrap();
+        let rom = Cartridge::new(&bytes).unwrap();
+
+        let bus = Bus::default(rom);
+
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        cpu.program_counter = 0xC000;
+
+        let expected: Vec<String> = fs::read_to_string(LOG).unwrap().lines().map(str::to_string).collect();
+        let mut running = true;
+        for index in 0..expected.len() {
+            let mut actual = None;
+            if running {
+                running = cpu.step_with_callback(|cpu| actual = Some(trace::trace(cpu)));
+            }
+            if let Some(report) = divergence(&expected, index, actual.as_deref()) {
+                panic!("{}", report);
+            }
+        }
+    }
+
+    #[test]
+    fn divergence_report() {
+        let expected: Vec<String> = ["C000  A", "C002  B", "C004  C"].iter().map(|line| line.to_string()).collect();
+        assert_eq!(divergence(&expected, 1, Some("C002  B")), None);
+        assert_eq!(
+            divergence(&expected, 2, Some("C004  D")).unwrap(),
+            format!(
+                "Trace diverges from {} at line 3:\n           C000  A\n           C002  B\n  expected C004  C\n  actual   C004  D\n                 ^",
+                LOG
+            )
+        );
+        assert!(divergence(&expected, 0, None).unwrap().ends_with("(the CPU halted)"));
+    }
+}
@@ -0,0 +1,186 @@
+//! Saves a state part way through nestest's menu and checks that loading it and replaying the
+//! same input ends in exactly the same machine state, that states saved by earlier versions of the
+//! format still load, that run-ahead shows future frames without changing the run, that frames
+//! skipped for speed run the same, and that hardcore mode refuses states and cheats.
+
+#[cfg(test)]
+mod savestate {
+    use nes_rs_core::cartridge::Cartridge;
+    use nes_rs_core::cheat::CheatCode;
+    use nes_rs_core::emulator::Emulator;
+    use nes_rs_core::joypad::JoypadButton;
+    use nes_rs_core::savestate::run_ahead::RunAhead;
+
+    fn scripted_input(frame: u64) -> JoypadButton {
+        match frame % 30 {
+            0..=3 => JoypadButton::DOWN,
+            15..=17 => JoypadButton::START,
+            _ => JoypadButton::empty(),
+        }
+    }
+
+    fn run(emulator: &mut Emulator, frames: std::ops::Range<u64>) {
+        for frame in frames {
+            emulator.set_buttons(0, scripted_input(frame));
+            emulator.run_frame();
+        }
+    }
+
+    #[test]
+    fn load_state_restores_the_machine() {
+        let bytes: Vec<u8> = std::fs::read("tests/nestest/nestest.nes").unwrap();
+        let mut emulator = Emulator::new(Cartridge::new(&bytes).unwrap());
+        run(&mut emulator, 0..60);
+        // Stop mid-frame, so the beam position and latches matter.
+        for _ in 0..1000 {
+            emulator.step(|_| true);
+        }
+        let state = emulator.save_state();
+
+        run(&mut emulator, 60..120);
+        let expected = emulator.save_state();
+        let frame = emulator.frame.to_rgba8();
+
+        emulator.load_state(&state).unwrap();
+        assert_eq!(emulator.save_state(), state);
+        run(&mut emulator, 60..120);
+        assert_eq!(emulator.save_state(), expected);
+        assert_eq!(emulator.frame.to_rgba8(), frame);
+    }
+
+    // The fixtures were saved 1000 instructions into frame 60 of `run`, one in each version of the
+    // format, and in each later version of a section (-ppu2 for PPU section version 2). Each must
+    // load into the state a fresh run reaches, and carry on the same way.
+    #[test]
+    fn states_from_every_version_load() {
+        let bytes: Vec<u8> = std::fs::read("tests/nestest/nestest.nes").unwrap();
+        let mut emulator = Emulator::new(Cartridge::new(&bytes).unwrap());
+        run(&mut emulator, 0..60);
+        for _ in 0..1000 {
+            emulator.step(|_| true);
+        }
+        let state = emulator.save_state();
+        run(&mut emulator, 60..120);
+        let expected = emulator.save_state();
+
+        // Anything that changes what is written must bump a version and add a fixture.
+        assert_eq!(state, std::fs::read("tests/savestate/nestest-v2-ppu2.nss").unwrap());
+        for fixture in [
+            "tests/savestate/nestest-v1.nss",
+            "tests/savestate/nestest-v2.nss",
+            "tests/savestate/nestest-v2-ppu2.nss",
+        ] {
+            let mut emulator = Emulator::new(Cartridge::new(&bytes).unwrap());
+            emulator.load_state(&std::fs::read(fixture).unwrap()).unwrap();
+            assert_eq!(emulator.save_state(), state, "{}", fixture);
+            run(&mut emulator, 60..120);
+            assert_eq!(emulator.save_state(), expected, "{}", fixture);
+        }
+    }
+
+    #[test]
+    fn bad_states_are_rejected() {
+        let bytes: Vec<u8> = std::fs::read("tests/nestest/nestest.nes").unwrap();
+        let mut emulator = Emulator::new(Cartridge::new(&bytes).unwrap());
+        run(&mut emulator, 0..10);
+        let state = emulator.save_state();
+        run(&mut emulator, 10..20);
+        let before = emulator.save_state();
+
+        assert!(emulator.load_state(&state[..state.len() - 1]).is_err());
+        assert!(emulator.load_state(&[state.as_slice(), &[0]].concat()).is_err());
+        assert!(emulator.load_state(b"not a state").is_err());
+        // Another ROM's hash.
+        let mut other = state.clone();
+        other[5] ^= 0xff;
+        assert_eq!(emulator.load_state(&other), Err("Save state was made on a different ROM".to_string()));
+        // A format from the future.
+        let mut other = state.clone();
+        other[4] = 3;
+        assert_eq!(emulator.load_state(&other), Err("Unsupported save state version 3".to_string()));
+        assert_eq!(emulator.save_state(), before);
+
+        // A palette entry out of range, past the tag, version, length and 2kB of VRAM, is cut
+        // down to the 6 bits the PPU keeps rather than read past the system palette.
+        let mut other = state.clone();
+        let ppu = other.windows(4).position(|tag| tag == b"PPU ").unwrap();
+        other[ppu + 10 + 0x800] = 0xd0;
+        emulator.load_state(&other).unwrap();
+        assert_eq!(emulator.cpu.bus.ppu.palette_table[0], 0x10);
+        emulator.run_frame();
+    }
+
+    #[test]
+    fn run_ahead_shows_the_future_without_changing_the_run() {
+        let bytes: Vec<u8> = std::fs::read("tests/nestest/nestest.nes").unwrap();
+        let run_frame = |emulator: &mut Emulator| {
+            emulator.run_frame();
+            true
+        };
+        // Input only changes every 4 frames, so frames run ahead can match what really happens.
+        let input = |frame: u64| scripted_input(frame / 4 * 4);
+
+        let mut plain = Emulator::new(Cartridge::new(&bytes).unwrap());
+        let mut ahead = Emulator::new(Cartridge::new(&bytes).unwrap());
+        let mut run_ahead = RunAhead::new(2);
+        let mut pictures = Vec::new();
+        let mut shown = Vec::new();
+        for frame in 0..60 {
+            plain.set_buttons(0, input(frame));
+            plain.run_frame();
+            pictures.push(plain.frame.to_rgba8());
+
+            ahead.set_buttons(0, input(frame));
+            assert!(run_ahead.run_frame(&mut ahead, run_frame));
+            shown.push(ahead.frame.to_rgba8());
+            assert_eq!(ahead.save_state(), plain.save_state());
+        }
+
+        // Where the input holds for two more frames, what was shown is the frame two ahead. Some
+        // of those frames must differ from the real one for this to mean anything.
+        let steady: Vec<usize> = (0..58).filter(|frame| frame % 4 < 2).collect();
+        assert!(steady.iter().all(|&frame| shown[frame] == pictures[frame + 2]));
+        assert!(steady.iter().any(|&frame| shown[frame] != pictures[frame]));
+    }
+
+    #[test]
+    fn skipped_frames_run_the_same() {
+        let bytes: Vec<u8> = std::fs::read("tests/nestest/nestest.nes").unwrap();
+        let mut drawn = Emulator::new(Cartridge::new(&bytes).unwrap());
+        let mut skipped = Emulator::new(Cartridge::new(&bytes).unwrap());
+        run(&mut drawn, 0..30);
+        run(&mut skipped, 0..30);
+        let picture = skipped.frame.to_rgba8();
+
+        skipped.skip_render = true;
+        run(&mut drawn, 30..90);
+        run(&mut skipped, 30..90);
+        assert_eq!(skipped.save_state(), drawn.save_state());
+        assert_eq!(skipped.frame.to_rgba8(), picture);
+        assert_ne!(drawn.frame.to_rgba8(), picture);
+
+        skipped.skip_render = false;
+        run(&mut drawn, 90..91);
+        run(&mut skipped, 90..91);
+        assert_eq!(skipped.frame.to_rgba8(), drawn.frame.to_rgba8());
+    }
+
+    #[test]
+    fn hardcore_refuses_states_and_cheats() {
+        let bytes: Vec<u8> = std::fs::read("tests/nestest/nestest.nes").unwrap();
+        let mut emulator = Emulator::new(Cartridge::new(&bytes).unwrap());
+        run(&mut emulator, 0..10);
+        let state = emulator.save_state();
+
+        emulator.set_hardcore(true);
+        assert!(emulator.load_state(&state).is_err());
+        emulator.cpu.bus.cheats.add(CheatCode::parse("freeze $0700 to 3").unwrap()).unwrap();
+        run(&mut emulator, 10..11);
+        assert!(emulator.cpu.bus.cheats.list().is_empty());
+        assert_ne!(emulator.cpu.bus.peek(0x0700), 3);
+
+        emulator.set_hardcore(false);
+        emulator.load_state(&state).unwrap();
+        assert_eq!(emulator.frame_count(), 10);
+    }
+}
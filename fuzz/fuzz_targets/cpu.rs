@@ -0,0 +1,31 @@
+//! Runs arbitrary bytes as a program: the input is tiled over a 32kB PRG-ROM whose reset vector
+//! points at $8000, and the console runs until it halts or a step limit, whichever comes first.
+//! Anything the program does, to RAM, the PPU or the controllers, must not panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nes_rs_core::cartridge::Cartridge;
+use nes_rs_core::emulator::{Emulator, StepResult};
+
+// About two frames' worth of instructions, to keep each run short.
+const MAX_STEPS: usize = 60_000;
+
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+    let mut rom = vec![b'N', b'E', b'S', 0x1a, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let prg_start = rom.len();
+    rom.extend(data.iter().cycle().take(0x8000));
+    // NMI, reset and IRQ vectors, all at $8000.
+    rom[prg_start + 0x7ffa..prg_start + 0x8000].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+    rom.extend(std::iter::repeat_n(0, 0x2000));
+
+    let mut emulator = Emulator::new(Cartridge::new(&rom).unwrap());
+    for _ in 0..MAX_STEPS {
+        if emulator.step(|_| true) == StepResult::Halted {
+            break;
+        }
+    }
+});
@@ -0,0 +1,14 @@
+//! Feeds arbitrary bytes to the iNES parser, which must turn anything that isn't a ROM it can run
+//! into an error rather than a panic. Whatever it accepts is powered on and run for a frame.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nes_rs_core::cartridge::Cartridge;
+use nes_rs_core::emulator::Emulator;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(cartridge) = Cartridge::new(data) {
+        Emulator::new(cartridge).run_frame();
+    }
+});
@@ -0,0 +1,477 @@
+//! libretro core: lets RetroArch and other libretro frontends load nes_rs.
+//!
+//! `cargo build --release -p nes_rs_libretro` builds `libnes_rs_libretro.so` (`.dll` on Windows,
+//! `.dylib` on macOS), which frontends load as a core. The frontend owns the window, audio and
+//! input: the callbacks implement the core's `host` traits, so each `retro_run` reads the two
+//! joypads through the input callback, runs one frame and hands the picture to the video callback
+//! as XRGB8888. There is no APU yet, so the audio callback gets a frame's worth of the Famicom
+//! Disk System's channel, or of silence for a cartridge, which keeps frontends that sync to audio
+//! running at the right speed. States go through
+//! `Emulator::save_state`, so they are the same as the desktop's save states, and cheats take the
+//! codes of the desktop's cheat files. A game or cheat that won't load is reported through the
+//! frontend's log interface.
+//!
+//! Reference: https://docs.libretro.com/development/cores/developing-cores/
+
+// The unsafe functions are only called by the frontend, with the pointers libretro.h promises.
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_uint, c_void, CStr, CString};
+
+use libretro_sys::{
+    AudioSampleBatchFn, AudioSampleFn, EnvironmentFn, GameGeometry, GameInfo, InputPollFn, InputStateFn, PixelFormat,
+    SystemAvInfo, SystemInfo, SystemTiming, VideoRefreshFn,
+};
+use nes_rs_core::cartridge::Cartridge;
+use nes_rs_core::cheat::{CheatCode, Cheats};
+use nes_rs_core::emulator::Emulator;
+use nes_rs_core::emulator::NTSC_FRAME_RATE;
+use nes_rs_core::host::{AudioSink, InputProvider, VideoSink, SAMPLE_RATE};
+use nes_rs_core::joypad::JoypadButton;
+use nes_rs_core::render::constants::{NES_PIXEL_HEIGHT, NES_PIXEL_WIDTH};
+use nes_rs_core::render::frame::Frame;
+
+const WIDTH: usize = NES_PIXEL_WIDTH as usize;
+const HEIGHT: usize = NES_PIXEL_HEIGHT as usize;
+
+// Controller ports the frontend can map joypads to.
+const PORTS: usize = 2;
+
+// libretro's joypad buttons and the NES buttons they press.
+const BUTTON_MAP: [(c_uint, JoypadButton); 8] = [
+    (libretro_sys::DEVICE_ID_JOYPAD_A, JoypadButton::BUTTON_A),
+    (libretro_sys::DEVICE_ID_JOYPAD_B, JoypadButton::BUTTON_B),
+    (libretro_sys::DEVICE_ID_JOYPAD_SELECT, JoypadButton::SELECT),
+    (libretro_sys::DEVICE_ID_JOYPAD_START, JoypadButton::START),
+    (libretro_sys::DEVICE_ID_JOYPAD_UP, JoypadButton::UP),
+    (libretro_sys::DEVICE_ID_JOYPAD_DOWN, JoypadButton::DOWN),
+    (libretro_sys::DEVICE_ID_JOYPAD_LEFT, JoypadButton::LEFT),
+    (libretro_sys::DEVICE_ID_JOYPAD_RIGHT, JoypadButton::RIGHT),
+];
+
+// retro_log_printf_t and struct retro_log_callback, which libretro-sys doesn't declare.
+type LogFn = unsafe extern "C" fn(level: c_uint, fmt: *const c_char, ...);
+#[repr(C)]
+struct LogCallback {
+    log: Option<LogFn>,
+}
+const LOG_WARN: c_uint = 2;
+
+// The input callbacks the frontend registers with the retro_set_* functions, and the environment.
+#[derive(Default)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    // The frontend's log, from ENVIRONMENT_GET_LOG_INTERFACE, if it has one.
+    log: Option<LogFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+impl Callbacks {
+    // Shows `message` in the frontend's log, or on stderr without one, as libretro.h suggests.
+    fn warn(&self, message: &str) {
+        match (self.log, CString::new(message)) {
+            (Some(log), Ok(message)) => unsafe { log(LOG_WARN, c"%s\n".as_ptr(), message.as_ptr()) },
+            _ => eprintln!("{}", message),
+        }
+    }
+
+    fn port_buttons(&self, port: usize) -> JoypadButton {
+        let Some(input_state) = self.input_state else {
+            return JoypadButton::empty();
+        };
+        BUTTON_MAP
+            .iter()
+            .filter(|(id, _)| unsafe { input_state(port as c_uint, libretro_sys::DEVICE_JOYPAD, 0, *id) } != 0)
+            .fold(JoypadButton::empty(), |held, (_, button)| held | *button)
+    }
+}
+
+impl InputProvider for Callbacks {
+    fn buttons(&mut self) -> [JoypadButton; 4] {
+        if let Some(input_poll) = self.input_poll {
+            unsafe { input_poll() };
+        }
+        let mut buttons = [JoypadButton::empty(); 4];
+        for (port, held) in buttons.iter_mut().enumerate().take(PORTS) {
+            *held = self.port_buttons(port);
+        }
+        buttons
+    }
+}
+
+// The video callback, with the picture converted to XRGB8888 for it.
+#[derive(Default)]
+struct Video {
+    refresh: Option<VideoRefreshFn>,
+    rgba: Vec<u8>,
+    pixels: Vec<u32>,
+}
+
+impl VideoSink for Video {
+    fn frame(&mut self, frame: &Frame) {
+        frame.write_rgba8(&mut self.rgba);
+        for (pixel, rgba) in self.pixels.iter_mut().zip(self.rgba.chunks_exact(4)) {
+            *pixel = u32::from_be_bytes([0, rgba[0], rgba[1], rgba[2]]);
+        }
+        if let Some(refresh) = self.refresh {
+            unsafe { refresh(self.pixels.as_ptr() as *const c_void, WIDTH as c_uint, HEIGHT as c_uint, WIDTH * 4) };
+        }
+    }
+}
+
+// The audio callback, which takes stereo.
+#[derive(Default)]
+struct Audio {
+    sample_batch: Option<AudioSampleBatchFn>,
+    stereo: Vec<i16>,
+}
+
+impl AudioSink for Audio {
+    fn samples(&mut self, samples: &[i16]) {
+        self.stereo.clear();
+        self.stereo.extend(samples.iter().flat_map(|sample| [*sample, *sample]));
+        if let Some(sample_batch) = self.sample_batch {
+            unsafe { sample_batch(self.stereo.as_ptr(), samples.len()) };
+        }
+    }
+}
+
+#[derive(Default)]
+struct Core {
+    callbacks: Callbacks,
+    video: Video,
+    audio: Audio,
+    // None until a game is loaded.
+    emulator: Option<Emulator>,
+}
+
+impl Core {
+    fn run(&mut self) {
+        if let Some(emulator) = &mut self.emulator {
+            emulator.run_frame_with_host(&mut self.callbacks, &mut self.video, &mut self.audio);
+        }
+    }
+
+    fn load_game(&mut self, rom: &[u8]) -> Result<(), String> {
+        let cartridge = Cartridge::new(rom)?;
+        if let Some(environment) = self.callbacks.environment {
+            let mut format = PixelFormat::ARGB8888;
+            let accepted = unsafe {
+                environment(libretro_sys::ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut PixelFormat as *mut c_void)
+            };
+            if !accepted {
+                return Err("The frontend doesn't support XRGB8888".to_string());
+            }
+        }
+        self.emulator = Some(Emulator::new(cartridge));
+        self.video.rgba = vec![0; WIDTH * HEIGHT * 4];
+        self.video.pixels = vec![0; WIDTH * HEIGHT];
+        Ok(())
+    }
+
+    // A cheat from the frontend may hold several codes joined with `+`, as in RetroArch's cheat
+    // files. Each is a Game Genie code or a freeze, as in `cheat::CheatCode::parse`.
+    fn set_cheat(&mut self, code: &str) -> Result<(), String> {
+        let Some(emulator) = &mut self.emulator else {
+            return Ok(());
+        };
+        for code in code.split('+') {
+            emulator.cpu.bus.cheats.add(CheatCode::parse(code.trim())?)?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    // Frontends call into the core from one thread.
+    static CORE: RefCell<Core> = RefCell::new(Core::default());
+}
+
+fn with_core<T>(f: impl FnOnce(&mut Core) -> T) -> T {
+    CORE.with(|core| f(&mut core.borrow_mut()))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    libretro_sys::API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    let mut log = LogCallback { log: None };
+    let has_log = unsafe {
+        callback(libretro_sys::ENVIRONMENT_GET_LOG_INTERFACE, &mut log as *mut LogCallback as *mut c_void)
+    };
+    with_core(|core| {
+        core.callbacks.environment = Some(callback);
+        core.callbacks.log = log.log.filter(|_| has_log);
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    with_core(|core| core.video.refresh = Some(callback));
+}
+
+// Audio goes through the batch callback.
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    with_core(|core| core.audio.sample_batch = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    with_core(|core| core.callbacks.input_poll = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    with_core(|core| core.callbacks.input_state = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    with_core(|core| *core = Core::default());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: c"nes_rs".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"nes".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            // 8:7 pixels, as on a TV.
+            aspect_ratio: (WIDTH as f32 * 8.0 / 7.0) / HEIGHT as f32,
+        },
+        timing: SystemTiming {
+            fps: NTSC_FRAME_RATE,
+            sample_rate: SAMPLE_RATE,
+        },
+    };
+}
+
+// Both ports always have a standard controller.
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core(|core| {
+        if let Some(emulator) = &mut core.emulator {
+            emulator.power_on();
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    with_core(Core::run);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(|core| core.emulator.as_ref().map_or(0, |emulator| emulator.save_state().len()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let Some(state) = with_core(|core| core.emulator.as_ref().map(Emulator::save_state)) else {
+        return false;
+    };
+    if state.len() > size {
+        return false;
+    }
+    std::slice::from_raw_parts_mut(data as *mut u8, state.len()).copy_from_slice(&state);
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let state = std::slice::from_raw_parts(data as *const u8, size);
+    with_core(|core| core.emulator.as_mut().is_some_and(|emulator| emulator.load_state(state).is_ok()))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_core(|core| {
+        if let Some(emulator) = &mut core.emulator {
+            emulator.cpu.bus.cheats = Cheats::new();
+        }
+    });
+}
+
+// Frontends switch a cheat off by resetting and setting the others again, so `enabled` is always
+// true in practice.
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(_index: c_uint, enabled: bool, code: *const c_char) {
+    if !enabled || code.is_null() {
+        return;
+    }
+    let code = CStr::from_ptr(code).to_string_lossy();
+    with_core(|core| {
+        if let Err(e) = core.set_cheat(&code) {
+            core.callbacks.warn(&format!("Ignoring cheat {}: {}", code, e));
+        }
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = std::slice::from_raw_parts((*game).data as *const u8, (*game).size);
+    with_core(|core| match core.load_game(rom) {
+        Ok(()) => true,
+        Err(e) => {
+            core.callbacks.warn(&format!("Could not load game: {}", e));
+            false
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const GameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    with_core(|core| core.emulator = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    libretro_sys::Region::NTSC as c_uint
+}
+
+// Only the 2kB of work RAM is exposed, for achievements and the frontend's memory tools.
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    with_core(|core| match (&mut core.emulator, id & libretro_sys::MEMORY_MASK) {
+        (Some(emulator), libretro_sys::MEMORY_SYSTEM_RAM) => emulator.cpu.bus.cpu_wram.as_mut_ptr() as *mut c_void,
+        _ => std::ptr::null_mut(),
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    with_core(|core| match (&core.emulator, id & libretro_sys::MEMORY_MASK) {
+        (Some(emulator), libretro_sys::MEMORY_SYSTEM_RAM) => emulator.cpu.bus.cpu_wram.len(),
+        _ => 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes_rs_core::host::SAMPLES_PER_FRAME;
+    use std::cell::Cell;
+
+    thread_local! {
+        static FRAMES_SHOWN: Cell<u32> = const { Cell::new(0) };
+        static SAMPLES_PLAYED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe extern "C" fn environment(cmd: c_uint, _data: *mut c_void) -> bool {
+        cmd == libretro_sys::ENVIRONMENT_SET_PIXEL_FORMAT
+    }
+
+    unsafe extern "C" fn video_refresh(_data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+        assert_eq!((width, height, pitch), (256, 240, 1024));
+        FRAMES_SHOWN.with(|frames| frames.set(frames.get() + 1));
+    }
+
+    unsafe extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+        SAMPLES_PLAYED.with(|samples| samples.set(samples.get() + frames));
+        frames
+    }
+
+    unsafe extern "C" fn input_poll() {}
+
+    // Start held on controller 1.
+    unsafe extern "C" fn input_state(port: c_uint, _device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+        (port == 0 && id == libretro_sys::DEVICE_ID_JOYPAD_START) as i16
+    }
+
+    #[test]
+    fn test_runs_as_a_core() {
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+
+        let rom = std::fs::read("../core/tests/nestest/nestest.nes").unwrap();
+        let game = GameInfo {
+            path: std::ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: std::ptr::null(),
+        };
+        assert!(unsafe { retro_load_game(&game) });
+        for _ in 0..10 {
+            retro_run();
+        }
+        assert_eq!(FRAMES_SHOWN.with(Cell::get), 10);
+        assert_eq!(SAMPLES_PLAYED.with(Cell::get), 10 * SAMPLES_PER_FRAME);
+        assert!(with_core(|core| core.video.pixels.iter().any(|&pixel| pixel != core.video.pixels[0])));
+        assert_eq!(retro_get_memory_size(libretro_sys::MEMORY_SYSTEM_RAM), 2048);
+
+        // A state taken now brings back the same picture after the game has moved on.
+        let mut state = vec![0u8; retro_serialize_size()];
+        assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+        retro_run();
+        let expected = with_core(|core| core.video.pixels.clone());
+        for _ in 0..30 {
+            retro_run();
+        }
+        assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+        retro_run();
+        assert_eq!(with_core(|core| core.video.pixels.clone()), expected);
+
+        assert!(!unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, 16) });
+        unsafe { retro_cheat_set(0, true, c"SXIOPO".as_ptr()) };
+        assert_eq!(with_core(|core| core.emulator.as_ref().unwrap().cpu.bus.cheats.list().len()), 1);
+        retro_cheat_reset();
+        assert!(with_core(|core| core.emulator.as_ref().unwrap().cpu.bus.cheats.is_empty()));
+
+        retro_unload_game();
+        retro_deinit();
+    }
+
+    #[test]
+    fn test_rejects_a_bad_rom() {
+        let rom = [0u8; 8];
+        let game = GameInfo {
+            path: std::ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: std::ptr::null(),
+        };
+        assert!(!unsafe { retro_load_game(&game) });
+        assert_eq!(retro_serialize_size(), 0);
+    }
+}
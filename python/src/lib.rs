@@ -0,0 +1,183 @@
+//! `nestalgia` Python module: the headless emulator, for scripts and reinforcement learning.
+//!
+//! ```python
+//! import nestalgia
+//!
+//! nes = nestalgia.Emulator.from_file("game.nes")
+//! nes.step(60, buttons=nestalgia.START)
+//! screen = nes.frame()   # numpy uint8 array, 240 x 256 x 3
+//! lives = nes.read(0x075A)
+//! ```
+//!
+//! Build and install it into the current virtualenv with `maturin develop --release` from
+//! `python/`. Nothing is drawn or played: a step only runs the console, so thousands of frames a
+//! second are possible. The `///` comments on the class and its methods are its Python
+//! docstrings.
+
+// Raised on the code `#[pymethods]` generates for methods returning `PyResult`.
+#![allow(clippy::useless_conversion)]
+
+use numpy::{PyArray1, PyArray3, PyArrayMethods};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use nes_rs_core::cartridge::Cartridge;
+use nes_rs_core::emulator::Emulator as Console;
+use nes_rs_core::joypad::JoypadButton;
+use nes_rs_core::render::constants::{NES_PIXEL_HEIGHT, NES_PIXEL_WIDTH};
+
+const WIDTH: usize = NES_PIXEL_WIDTH as usize;
+const HEIGHT: usize = NES_PIXEL_HEIGHT as usize;
+
+// Controller ports `step` and `set_buttons` take.
+const PORTS: usize = 2;
+
+/// A console running one game. Not shared between threads, so each environment of a vectorized
+/// gym gets its own.
+#[pyclass(unsendable, module = "nestalgia")]
+pub struct Emulator {
+    console: Console,
+    rgba: Vec<u8>,
+}
+
+impl Emulator {
+    fn new(cartridge: Cartridge) -> Self {
+        Emulator {
+            console: Console::new(cartridge),
+            rgba: vec![0; WIDTH * HEIGHT * 4],
+        }
+    }
+
+    fn port(port: usize) -> PyResult<usize> {
+        match port < PORTS {
+            true => Ok(port),
+            false => Err(PyValueError::new_err(format!("There is no controller port {}", port))),
+        }
+    }
+}
+
+#[pymethods]
+impl Emulator {
+    /// Loads an iNES file from its bytes.
+    #[new]
+    fn from_bytes(rom: &[u8]) -> PyResult<Self> {
+        Cartridge::new(rom).map(Emulator::new).map_err(PyValueError::new_err)
+    }
+
+    /// Loads an iNES file from disk.
+    #[staticmethod]
+    fn from_file(path: &str) -> PyResult<Self> {
+        let rom = std::fs::read(path).map_err(|e| PyIOError::new_err(format!("Could not read {}: {}", path, e)))?;
+        Emulator::from_bytes(&rom)
+    }
+
+    /// Presses the reset button.
+    fn reset(&mut self) {
+        self.console.power_on();
+    }
+
+    /// Holds `buttons`, a mask of the button constants, on controller `port` until changed.
+    #[pyo3(signature = (buttons, port = 0))]
+    fn set_buttons(&mut self, buttons: u8, port: usize) -> PyResult<()> {
+        self.console.set_buttons(Emulator::port(port)?, JoypadButton::from_bits_truncate(buttons));
+        Ok(())
+    }
+
+    /// Runs `frames` frames, holding `buttons` on controller `port` if given. The usual action
+    /// step of an environment.
+    #[pyo3(signature = (frames = 1, buttons = None, port = 0))]
+    fn step(&mut self, frames: u32, buttons: Option<u8>, port: usize) -> PyResult<()> {
+        if let Some(buttons) = buttons {
+            self.set_buttons(buttons, port)?;
+        }
+        for _ in 0..frames {
+            self.console.run_frame();
+        }
+        Ok(())
+    }
+
+    /// Frames run since power-on.
+    #[getter]
+    fn frame_count(&self) -> u64 {
+        self.console.frame_count()
+    }
+
+    /// The byte the CPU would read at `addr`, without the side effects of reading registers.
+    fn read(&self, addr: u16) -> u8 {
+        self.console.cpu.bus.peek(addr)
+    }
+
+    /// Writes `value` at `addr`, bypassing registers and their side effects. Also patches PRG-ROM.
+    fn write(&mut self, addr: u16, value: u8) {
+        self.console.cpu.bus.poke(addr, value);
+    }
+
+    /// The 2kB of work RAM, as a uint8 array. A copy: writing to it doesn't change the console.
+    fn ram<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<u8>> {
+        PyArray1::from_slice_bound(py, &self.console.cpu.bus.cpu_wram)
+    }
+
+    /// The last frame as a uint8 array of shape (240, 256, 3), rows first, RGB.
+    fn frame<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyArray3<u8>> {
+        self.console.frame.write_rgba8(&mut self.rgba);
+        let rgb: Vec<u8> = self.rgba.chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect();
+        PyArray1::from_vec_bound(py, rgb).reshape([HEIGHT, WIDTH, 3]).unwrap()
+    }
+
+    /// Snapshots the whole console as bytes.
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.console.save_state())
+    }
+
+    /// Restores a state from `save_state` made on the same ROM. States are the same as the desktop
+    /// emulator's, so a state saved there can start an episode.
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        self.console.load_state(state).map_err(PyValueError::new_err)
+    }
+}
+
+#[pymodule]
+fn nestalgia(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Emulator>()?;
+    let buttons = [
+        ("A", JoypadButton::BUTTON_A),
+        ("B", JoypadButton::BUTTON_B),
+        ("SELECT", JoypadButton::SELECT),
+        ("START", JoypadButton::START),
+        ("UP", JoypadButton::UP),
+        ("DOWN", JoypadButton::DOWN),
+        ("LEFT", JoypadButton::LEFT),
+        ("RIGHT", JoypadButton::RIGHT),
+    ];
+    for (name, button) in buttons {
+        module.add(name, button.bits())?;
+    }
+    module.add("WIDTH", WIDTH)?;
+    module.add("HEIGHT", HEIGHT)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps_and_reads() {
+        let rom = std::fs::read("../core/tests/nestest/nestest.nes").unwrap();
+        let mut nes = Emulator::from_bytes(&rom).unwrap();
+        nes.step(10, Some(JoypadButton::START.bits()), 0).unwrap();
+        assert_eq!(nes.frame_count(), 10);
+        assert!(nes.set_buttons(0, 2).is_err());
+
+        nes.write(0x0010, 0x42);
+        assert_eq!(nes.read(0x0010), 0x42);
+        assert_eq!(nes.read(0x0810), 0x42);
+
+        let state = nes.console.save_state();
+        nes.write(0x0010, 0);
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.read(0x0010), 0x42);
+        assert!(nes.load_state(&state[..8]).is_err());
+    }
+}
@@ -0,0 +1,430 @@
+//! The command line, parsed once with clap. `nes_rs [rom] [options]` plays a game; the
+//! subcommands run test ROMs, check determinism and measure speed without a window. Options that
+//! only make sense with a feature (--script, --ra-user) are accepted either way and ignored
+//! without it, so the help text stays the same across builds.
+//!
+//! Playing a game also reads the config file (see `config`): options not given on the command
+//! line are filled in from it, so the rest of the program only looks at `PlayArgs`.
+
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use nes_rs::accuracy::AccuracyProfile;
+use nes_rs::config::{self, Config};
+use nes_rs::debugger::watch::Watchpoint;
+use nes_rs::frontend::scaling::AspectRatio;
+use nes_rs::frontend::timing::Speed;
+use nes_rs::ppu::model::PpuModel;
+use nes_rs::region::Region;
+use nes_rs::render::blend::BlendMode;
+use nes_rs::render::filters::FilterPreset;
+use nes_rs::stream::protocol::FrameEncoding;
+use nes_rs::testrom::blargg::DEFAULT_MAX_FRAMES;
+use nes_rs::vs_system::parse_dip_switches;
+
+use crate::ROM_PATH;
+
+// The arguments the program was started with, with the config file's settings filled in. Parsing
+// them the first time exits with a message if they or the config file are wrong (or with the help
+// text for --help).
+pub fn args() -> &'static Cli {
+    static ARGS: OnceLock<Cli> = OnceLock::new();
+    ARGS.get_or_init(|| {
+        let mut cli = Cli::parse();
+        if cli.command.is_none() {
+            if let Err(e) = cli.apply_config() {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        }
+        cli
+    })
+}
+
+/// A Nintendo Entertainment System emulator.
+#[derive(Parser, Debug)]
+#[command(name = "nes_rs", version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub play: PlayArgs,
+    /// What the core logs to stderr, e.g. "ppu=debug,apu=off" (targets: cpu, ppu, apu, mapper,
+    /// debugger, frame). Defaults to $NES_RS_LOG, or else warnings only.
+    #[arg(long, global = true, value_name = "FILTER")]
+    pub log: Option<String>,
+    // The settings in effect: the config file's, overridden by the command line.
+    #[arg(skip)]
+    pub config: Config,
+}
+
+impl Cli {
+    // Loads the config file (--config, or the platform's, created with the defaults if missing),
+    // fills in the options not given on the command line from it, and with --save-config writes
+    // the result back.
+    fn apply_config(&mut self) -> Result<(), String> {
+        let Some(path) = self.play.config.clone().or_else(config::default_path) else {
+            return Ok(());
+        };
+        let mut config = Config::load_or_create(&path)?;
+        self.play.merge(&mut config);
+        if self.play.save_config {
+            config.save(&path)?;
+            println!("Saved the settings to {}", path.display());
+        }
+        self.config = config;
+        Ok(())
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run ROMs without a window and judge each by its .hash file or its result at $6000.
+    TestRoms(TestRomsArgs),
+    /// Run blargg's test ROMs without a window and print how each did.
+    Blargg(BlarggArgs),
+    /// Run a ROM twice in lockstep and stop at the first frame where the runs differ.
+    Determinism(DeterminismArgs),
+    /// Time the CPU, the whole console and each filter without a window.
+    Bench(BenchArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct TestRomsArgs {
+    /// ROMs, or directories to search for .nes files.
+    #[arg(required = true)]
+    pub roms: Vec<PathBuf>,
+    /// How long each ROM may run.
+    #[arg(long, default_value_t = DEFAULT_MAX_FRAMES)]
+    pub frames: u64,
+    /// Also write the results as JSON.
+    #[arg(long, value_name = "FILE")]
+    pub json: Option<String>,
+    /// Also write the results as JUnit XML.
+    #[arg(long, value_name = "FILE")]
+    pub junit: Option<String>,
+    /// Record .hash files for ROMs with nothing to judge them by.
+    #[arg(long)]
+    pub write_hashes: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct BlarggArgs {
+    /// ROMs, or directories to search for .nes files.
+    #[arg(required = true)]
+    pub roms: Vec<PathBuf>,
+    /// How long each ROM may run before it counts as failed.
+    #[arg(long, default_value_t = DEFAULT_MAX_FRAMES)]
+    pub frames: u64,
+}
+
+#[derive(Args, Debug)]
+pub struct DeterminismArgs {
+    #[arg(default_value = ROM_PATH)]
+    pub rom: String,
+    /// Feed both runs this movie's input.
+    #[arg(long, value_name = "FILE")]
+    pub movie: Option<String>,
+    /// How many frames to compare (the movie's length, or 600 without one).
+    #[arg(long)]
+    pub frames: Option<u64>,
+    /// Rebuild the second run from its save state every frame.
+    #[arg(long)]
+    pub reload: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    #[arg(default_value = ROM_PATH)]
+    pub rom: String,
+    /// Frames to run the console and each filter for.
+    #[arg(long, default_value_t = 600)]
+    pub frames: u64,
+    /// Instructions to run the CPU alone for.
+    #[arg(long, default_value_t = 10_000_000)]
+    pub instructions: u64,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Macroquad,
+    Wgpu,
+    Terminal,
+}
+
+#[derive(Args, Debug)]
+pub struct PlayArgs {
+    /// The game to play. Without one the window shows a list of games to choose from.
+    pub rom: Option<String>,
+
+    /// Run as this region (ntsc, pal or dendy) instead of detecting it from the header and file name.
+    #[arg(long, value_name = "REGION")]
+    pub region: Option<Region>,
+    /// Emulate the timing details games rarely need (accurate), or skip them (fast). The game's
+    /// settings in the config file come before [emulation] accuracy.
+    #[arg(long, value_name = "fast|accurate")]
+    pub accuracy: Option<AccuracyProfile>,
+    /// Run without a window for --frames frames, then exit.
+    #[arg(long, requires = "frames", conflicts_with_all = ["serve", "backend"])]
+    pub headless: bool,
+    /// How many frames to run with --headless.
+    #[arg(long, requires = "headless")]
+    pub frames: Option<u64>,
+    /// Read settings from this file instead of the usual config file.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Save this run's settings to the config file.
+    #[arg(long)]
+    pub save_config: bool,
+
+    // Video.
+    /// Window size as a multiple of 256x240.
+    #[arg(long, help_heading = "Video")]
+    pub scale: Option<u32>,
+    /// Only scale by whole multiples.
+    #[arg(long, help_heading = "Video")]
+    pub integer_scaling: bool,
+    /// Bilinear instead of nearest-neighbor filtering.
+    #[arg(long, help_heading = "Video")]
+    pub smooth: bool,
+    /// Start in fullscreen.
+    #[arg(long, help_heading = "Video")]
+    pub fullscreen: bool,
+    /// Pixel aspect ratio: square, 8:7 or any w:h.
+    #[arg(long, help_heading = "Video")]
+    pub aspect: Option<AspectRatio>,
+    /// Post-processing filter: none, scanlines, crt or scale2x.
+    #[arg(long, help_heading = "Video")]
+    pub filter: Option<FilterPreset>,
+    /// Run the filters on a thread of their own.
+    #[arg(long, help_heading = "Video")]
+    pub filter_thread: bool,
+    /// Frame blending: off, average or phosphor[:<decay>].
+    #[arg(long, help_heading = "Video")]
+    pub blend: Option<BlendMode>,
+    /// Renderer. wgpu and terminal need the features of the same names.
+    #[arg(long, value_enum, default_value_t = Backend::Macroquad, help_heading = "Video")]
+    pub backend: Backend,
+    /// Frames dropped after each one recorded to a GIF.
+    #[arg(long, help_heading = "Video")]
+    pub gif_frame_skip: Option<u32>,
+
+    // Speed.
+    /// Emulation speed, e.g. 0.5 for half speed.
+    #[arg(long, help_heading = "Speed")]
+    pub speed: Option<Speed>,
+    /// Speed while Tab is held, or max.
+    #[arg(long, help_heading = "Speed")]
+    pub fast_forward: Option<Speed>,
+    /// Lock to the display's refresh when it is close to 60 Hz.
+    #[arg(long, help_heading = "Speed")]
+    pub vsync: bool,
+    /// Frames left undrawn after each drawn one.
+    #[arg(long, help_heading = "Speed")]
+    pub frame_skip: Option<u32>,
+    /// Show the emulation speed.
+    #[arg(long, help_heading = "Speed")]
+    pub show_fps: bool,
+    /// Show each frame this many frames early, to hide input lag.
+    #[arg(long, help_heading = "Speed")]
+    pub run_ahead: Option<u32>,
+    /// Give the CPU this many extra scanlines of time each frame, to cut slowdown. 0 is off.
+    #[arg(long, value_name = "SCANLINES", value_parser = clap::value_parser!(u16).range(..=1000), help_heading = "Speed")]
+    pub overclock: Option<u16>,
+
+    // Input.
+    /// Plug a Zapper into port 2.
+    #[arg(long, help_heading = "Input")]
+    pub zapper: bool,
+    /// Plug a Power Pad into port 2, stood on with the keys under [input] power_pad.
+    #[arg(long, conflicts_with = "zapper", help_heading = "Input")]
+    pub power_pad: bool,
+    /// Plug in a Four Score for controllers 3 and 4.
+    #[arg(long, help_heading = "Input")]
+    pub four_score: bool,
+    /// Blow into controller 2's microphone through the host's (needs the microphone feature). M
+    /// works either way.
+    #[arg(long, help_heading = "Input")]
+    pub microphone: bool,
+    /// How loud, from 0 to 1, the host's microphone has to hear something to count [default: 0.25].
+    #[arg(long, value_name = "LEVEL", requires = "microphone", help_heading = "Input")]
+    pub microphone_level: Option<f32>,
+    /// Plug in the Family BASIC keyboard. Scroll Lock switches the host keyboard between typing on it
+    /// and the hotkeys.
+    #[arg(long, help_heading = "Input")]
+    pub keyboard: bool,
+    /// The PPU of an arcade game's board: 2c03, 2c04-0001 to 2c04-0004, 2c05-01 to 2c05-05.
+    #[arg(long, help_heading = "Input")]
+    pub vs_ppu: Option<PpuModel>,
+    /// An arcade game's eight DIP switches as 0s and 1s, switch 1 first.
+    #[arg(long, value_parser = parse_dip_switches, help_heading = "Input")]
+    pub vs_dip: Option<u8>,
+
+    // Movies, saves and cheats.
+    /// Record a movie from power-on (.fm2 for FCEUX's format).
+    #[arg(long, value_name = "FILE", help_heading = "Saves")]
+    pub record: Option<String>,
+    /// Play a movie back.
+    #[arg(long, value_name = "FILE", help_heading = "Saves")]
+    pub play: Option<String>,
+    /// Edit a movie in the TAS editor.
+    #[arg(long, value_name = "MOVIE", help_heading = "Saves")]
+    pub tas: Option<String>,
+    /// Save the game on exit.
+    #[arg(long, help_heading = "Saves")]
+    pub autosave: bool,
+    /// Also save the game every so many seconds.
+    #[arg(long, value_name = "SECONDS", help_heading = "Saves")]
+    pub autosave_every: Option<u64>,
+    /// Resume from the automatic save without asking.
+    #[arg(long, help_heading = "Saves")]
+    pub resume: bool,
+    /// How many seconds rewind goes back; 0 turns it off.
+    #[arg(long, value_name = "SECONDS", help_heading = "Saves")]
+    pub rewind: Option<u32>,
+    /// Load cheats from a cheat file or a .cht file (repeatable).
+    #[arg(long, value_name = "FILE", help_heading = "Saves")]
+    pub cheats: Vec<String>,
+    /// Add a Game Genie code or "freeze <addr> to <value>" (repeatable).
+    #[arg(long, value_name = "CODE", help_heading = "Saves")]
+    pub cheat: Vec<String>,
+    /// Save the cheat list on exit.
+    #[arg(long, value_name = "FILE", help_heading = "Saves")]
+    pub save_cheats: Option<String>,
+
+    // Debugging.
+    /// Open every debug window at startup.
+    #[arg(long, help_heading = "Debugging")]
+    pub debug: bool,
+    /// Load labels from a ca65 .dbg file or an FCEUX .nl name list (repeatable).
+    #[arg(long, value_name = "FILE", help_heading = "Debugging")]
+    pub symbols: Vec<String>,
+    /// Set a breakpoint: <symbol|addr>[ if <condition>] (repeatable).
+    #[arg(long = "break", value_name = "BREAKPOINT", help_heading = "Debugging")]
+    pub breakpoints: Vec<String>,
+    /// Set a watchpoint: <addr[-addr]>[:r|w|rw] (repeatable).
+    #[arg(long = "watch", value_name = "WATCHPOINT", help_heading = "Debugging")]
+    pub watchpoints: Vec<Watchpoint>,
+    /// Log every instruction to a file, or keep the last lines with ring[:<lines>].
+    #[arg(long, value_name = "FILE", help_heading = "Debugging")]
+    pub trace: Option<String>,
+    /// The trace's columns, comma-separated.
+    #[arg(long, value_name = "COLUMNS", help_heading = "Debugging")]
+    pub trace_format: Option<String>,
+    /// Only trace instructions in <addr-addr> (repeatable).
+    #[arg(long, value_name = "RANGE", help_heading = "Debugging")]
+    pub trace_range: Vec<String>,
+    /// Write a cycle profile to the file on exit.
+    #[arg(long, value_name = "FILE", help_heading = "Debugging")]
+    pub profile: Option<String>,
+    /// Log which ROM bytes are code and which are data.
+    #[arg(long, value_name = "FILE", help_heading = "Debugging")]
+    pub cdl: Option<String>,
+    /// Run a Rhai script (needs the scripting feature).
+    #[arg(long, value_name = "FILE", help_heading = "Debugging")]
+    pub script: Option<String>,
+    /// Let gdb attach on this port.
+    #[arg(long, value_name = "PORT", help_heading = "Debugging")]
+    pub gdb: Option<u16>,
+
+    // Network.
+    /// Stream the console to TCP clients on <host:port>, without a window.
+    #[arg(long, value_name = "ADDR", help_heading = "Network")]
+    pub serve: Option<String>,
+    /// How streamed frames are encoded: raw or png.
+    #[arg(long, default_value = "raw", help_heading = "Network")]
+    pub serve_encoding: FrameEncoding,
+    /// Wait for a second player on this UDP port.
+    #[arg(long, value_name = "PORT", conflicts_with = "netplay_join", help_heading = "Network")]
+    pub netplay_host: Option<u16>,
+    /// Join a netplay game at <host:port> as player 2.
+    #[arg(long, value_name = "ADDR", help_heading = "Network")]
+    pub netplay_join: Option<String>,
+    /// Netplay input delay in frames.
+    #[arg(long, value_name = "FRAMES", help_heading = "Network")]
+    pub netplay_delay: Option<u64>,
+    /// Log in to RetroAchievements (needs the achievements feature).
+    #[arg(long, value_name = "NAME", help_heading = "Network")]
+    pub ra_user: Option<String>,
+    /// RetroAchievements token, instead of NES_RS_RA_PASSWORD.
+    #[arg(long, value_name = "TOKEN", help_heading = "Network")]
+    pub ra_token: Option<String>,
+    /// RetroAchievements hardcore mode.
+    #[arg(long, help_heading = "Network")]
+    pub hardcore: bool,
+}
+
+impl PlayArgs {
+    // Fills in each option not given on the command line from `config`, and sets `config` to the
+    // options in effect. Switches given on the command line can only turn things on.
+    fn merge(&mut self, config: &mut Config) {
+        let video = &mut config.video;
+        video.scale = *self.scale.get_or_insert(video.scale);
+        video.aspect = *self.aspect.get_or_insert(video.aspect);
+        video.filter = *self.filter.get_or_insert(video.filter);
+        video.blend = *self.blend.get_or_insert(video.blend);
+        video.frame_skip = *self.frame_skip.get_or_insert(video.frame_skip);
+        video.gif_frame_skip = *self.gif_frame_skip.get_or_insert(video.gif_frame_skip);
+        for (flag, setting) in [
+            (&mut self.integer_scaling, &mut video.integer_scaling),
+            (&mut self.smooth, &mut video.smooth),
+            (&mut self.fullscreen, &mut video.fullscreen),
+            (&mut self.filter_thread, &mut video.filter_thread),
+            (&mut self.vsync, &mut video.vsync),
+            (&mut self.show_fps, &mut video.show_fps),
+        ] {
+            *flag |= *setting;
+            *setting = *flag;
+        }
+
+        let emulation = &mut config.emulation;
+        emulation.speed = *self.speed.get_or_insert(emulation.speed);
+        emulation.fast_forward = *self.fast_forward.get_or_insert(emulation.fast_forward);
+        emulation.rewind = *self.rewind.get_or_insert(emulation.rewind);
+        emulation.run_ahead = *self.run_ahead.get_or_insert(emulation.run_ahead);
+        emulation.overclock = *self.overclock.get_or_insert(emulation.overclock);
+        // Left unset so a game's own setting can still apply; see `configure_game`.
+        if let Some(accuracy) = self.accuracy {
+            emulation.accuracy = accuracy;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_command_line_parses() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::parse_from(["nes_rs", "game.nes", "--scale", "3", "--watch", "0300-03ff:w", "--watch", "12"]);
+        assert!(cli.command.is_none());
+        assert_eq!((cli.play.rom.as_deref(), cli.play.scale), (Some("game.nes"), Some(3)));
+        assert_eq!(cli.play.watchpoints.len(), 2);
+
+        let cli = Cli::parse_from(["nes_rs", "--headless", "--frames", "60"]);
+        assert_eq!((cli.play.rom.as_deref(), cli.play.frames), (None, Some(60)));
+        assert!(Cli::try_parse_from(["nes_rs", "--headless"]).is_err());
+        assert!(Cli::try_parse_from(["nes_rs", "--filter", "sepia"]).is_err());
+
+        let mut cli = Cli::parse_from(["nes_rs", "--scale", "2", "--vsync"]);
+        let mut config = Config::default();
+        config.video.scale = 3;
+        config.video.filter = FilterPreset::Crt;
+        config.video.smooth = true;
+        cli.play.merge(&mut config);
+        assert_eq!((cli.play.scale, cli.play.filter), (Some(2), Some(FilterPreset::Crt)));
+        assert!(cli.play.smooth && cli.play.vsync);
+        assert_eq!((config.video.scale, config.video.vsync), (2, true));
+
+        let cli = Cli::parse_from(["nes_rs", "bench", "nestest.nes", "--frames", "10", "--log", "ppu=debug"]);
+        assert_eq!(cli.log.as_deref(), Some("ppu=debug"));
+        match cli.command {
+            Some(Command::Bench(bench)) => assert_eq!((bench.rom.as_str(), bench.frames), ("nestest.nes", 10)),
+            command => panic!("{:?}", command),
+        }
+    }
+}
@@ -0,0 +1,386 @@
+//! The settings file, `config.toml` in the platform's config directory (`~/.config/nes_rs` on
+//! Linux, `~/Library/Application Support/nes_rs` on macOS, `%APPDATA%\nes_rs` on Windows). It is
+//! written with the defaults the first time the emulator starts, to be edited by hand; anything
+//! left out of it keeps its default. Command-line options override it for one run, and
+//! `--save-config` writes the settings of that run back.
+//!
+//! A `[games.<hash>]` table overrides some settings for one game, applied whenever it loads. The
+//! hash is `Cartridge::hash` in hex, the one in the name of the game's save state directory:
+//!
+//! ```toml
+//! [games.0123456789abcdef]
+//! name = "Duck Hunt"
+//! controller = "zapper"
+//! overscan = { top = 8, bottom = 8 }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::accuracy::AccuracyProfile;
+use crate::frontend::scaling::{AspectRatio, Overscan, VideoSettings};
+use crate::frontend::timing::Speed;
+use crate::region::Region;
+use crate::render::blend::BlendMode;
+use crate::render::filters::FilterPreset;
+use crate::savestate::rewind::DEFAULT_SECONDS;
+
+pub const FILE_NAME: &str = "config.toml";
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub video: VideoConfig,
+    pub audio: AudioConfig,
+    pub input: InputConfig,
+    pub paths: PathConfig,
+    pub emulation: EmulationConfig,
+    // Keyed by ROM hash, see `Config::game`.
+    pub games: BTreeMap<String, GameConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    pub scale: u32,
+    pub integer_scaling: bool,
+    pub smooth: bool,
+    pub fullscreen: bool,
+    #[serde(with = "as_text")]
+    pub aspect: AspectRatio,
+    pub overscan: Overscan,
+    #[serde(with = "as_text")]
+    pub filter: FilterPreset,
+    pub filter_thread: bool,
+    #[serde(with = "as_text")]
+    pub blend: BlendMode,
+    pub vsync: bool,
+    pub frame_skip: u32,
+    pub show_fps: bool,
+    pub gif_frame_skip: u32,
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        let video = VideoSettings::default();
+        VideoConfig {
+            scale: video.scale,
+            integer_scaling: video.integer_scaling,
+            smooth: !video.nearest_filter,
+            fullscreen: video.fullscreen,
+            aspect: video.aspect,
+            overscan: video.overscan,
+            filter: FilterPreset::None,
+            filter_thread: false,
+            blend: BlendMode::Off,
+            vsync: false,
+            frame_skip: 0,
+            show_fps: false,
+            gif_frame_skip: 1,
+        }
+    }
+}
+
+// There is no APU yet, so nothing is played; these are kept for when there is.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    // 0 to 100.
+    pub volume: u8,
+    pub mute: bool,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig { volume: 100, mute: false }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    pub keyboard: KeyBindings,
+    // The keys for the Power Pad's buttons 1 to 12, in the mat's rows of four. The numeric keypad
+    // by default, laid out like the mat.
+    pub power_pad: Vec<String>,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        let power_pad = ["Kp7", "Kp8", "Kp9", "KpSubtract", "Kp4", "Kp5", "Kp6", "KpAdd", "Kp1", "Kp2", "Kp3", "KpEnter"];
+        InputConfig {
+            keyboard: KeyBindings::default(),
+            power_pad: power_pad.map(str::to_owned).to_vec(),
+        }
+    }
+}
+
+// The keys for controller 1, by macroquad's key names ("A", "Space", "Left", "Key1", ...).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    pub up: String,
+    pub down: String,
+    pub left: String,
+    pub right: String,
+    pub select: String,
+    pub start: String,
+    pub a: String,
+    pub b: String,
+    pub turbo_a: String,
+    pub turbo_b: String,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            up: "Up".to_owned(),
+            down: "Down".to_owned(),
+            left: "Left".to_owned(),
+            right: "Right".to_owned(),
+            select: "Space".to_owned(),
+            start: "Q".to_owned(),
+            a: "A".to_owned(),
+            b: "S".to_owned(),
+            turbo_a: "Z".to_owned(),
+            turbo_b: "X".to_owned(),
+        }
+    }
+}
+
+// Where files the emulator writes go. Relative paths are from the directory it was started in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathConfig {
+    // Where the launcher looks for games.
+    pub roms: PathBuf,
+    pub states: PathBuf,
+    pub screenshots: PathBuf,
+    pub recordings: PathBuf,
+}
+
+impl Default for PathConfig {
+    fn default() -> Self {
+        PathConfig {
+            roms: PathBuf::from("roms"),
+            states: PathBuf::from("states"),
+            screenshots: PathBuf::from("screenshots"),
+            recordings: PathBuf::from("recordings"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmulationConfig {
+    #[serde(with = "as_text")]
+    pub speed: Speed,
+    #[serde(with = "as_text")]
+    pub fast_forward: Speed,
+    // Seconds of rewind history; 0 turns rewind off.
+    pub rewind: u32,
+    pub run_ahead: u32,
+    // Extra scanlines of CPU time a frame; 0 turns overclocking off.
+    pub overclock: u16,
+    // "fast" or "accurate"; see `accuracy`.
+    #[serde(with = "as_text")]
+    pub accuracy: AccuracyProfile,
+}
+
+impl Default for EmulationConfig {
+    fn default() -> Self {
+        EmulationConfig {
+            speed: Speed::Scaled(1.0),
+            fast_forward: Speed::Uncapped,
+            rewind: DEFAULT_SECONDS,
+            run_ahead: 0,
+            overclock: 0,
+            accuracy: AccuracyProfile::Fast,
+        }
+    }
+}
+
+// What is plugged into controller port 2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Controller {
+    Joypad,
+    Zapper,
+    PowerPad,
+}
+
+// Settings for one game. Those left out follow the rest of the config, and the command line still
+// overrides them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    // Which game this is, for whoever edits the file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // What the header and the file name don't say; see `region::detect`.
+    #[serde(skip_serializing_if = "Option::is_none", with = "optional_text")]
+    pub region: Option<Region>,
+    #[serde(skip_serializing_if = "Option::is_none", with = "optional_text")]
+    pub accuracy: Option<AccuracyProfile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub controller: Option<Controller>,
+    pub four_score: bool,
+    // Family BASIC and the other keyboard games.
+    pub keyboard: bool,
+    // Whether the cheat file next to the ROM is loaded.
+    pub cheats: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overscan: Option<Overscan>,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            name: None,
+            region: None,
+            accuracy: None,
+            controller: None,
+            four_score: false,
+            keyboard: false,
+            cheats: true,
+            overscan: None,
+        }
+    }
+}
+
+impl Config {
+    // The overrides for the game with `Cartridge::hash` `rom_hash`, if it has any.
+    pub fn game(&self, rom_hash: u64) -> Option<&GameConfig> {
+        self.games.get(&format!("{:016x}", rom_hash))
+    }
+
+    // Reads the file at `path`. Errors name the file and the line.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Writes the whole config to `path`, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    // The config at `path`, or the defaults if there is no file there yet, in which case they are
+    // written to it.
+    pub fn load_or_create(path: &Path) -> Result<Config, String> {
+        if path.exists() {
+            return Config::load(path);
+        }
+        let config = Config::default();
+        config.save(path)?;
+        Ok(config)
+    }
+}
+
+// The platform's directory for the config file and the launcher's list of recent games, or None
+// when the environment doesn't say (no home directory).
+pub fn dir() -> Option<PathBuf> {
+    let var = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let dir = if cfg!(windows) {
+        var("APPDATA")?
+    } else if cfg!(target_os = "macos") {
+        var("HOME")?.join("Library/Application Support")
+    } else {
+        var("XDG_CONFIG_HOME").or_else(|| Some(var("HOME")?.join(".config")))?
+    };
+    Some(dir.join("nes_rs"))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    Some(dir()?.join(FILE_NAME))
+}
+
+// (De)serializes a value as the text its `FromStr` and `Display` use, e.g. "8:7" or "phosphor:0.8".
+mod as_text {
+    use super::*;
+    use serde::{de::Error, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr<Err = String>,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
+
+// `as_text` for optional values, which are left out when None.
+mod optional_text {
+    use super::*;
+    use serde::{de::Error, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.collect_str(value),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr<Err = String>,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?.parse().map(Some).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_round_trips_and_fills_in_defaults() {
+        let mut config = Config::default();
+        config.video.aspect = AspectRatio::Custom(1.25);
+        config.video.filter = FilterPreset::Crt;
+        config.video.blend = BlendMode::Phosphor(0.8);
+        config.emulation.speed = Speed::Scaled(0.5);
+        config.input.keyboard.start = "Enter".to_owned();
+        let text = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&text).unwrap(), config);
+
+        let config: Config = toml::from_str("[video]\nscale = 2\naspect = \"8:7\"\n").unwrap();
+        assert_eq!((config.video.scale, config.video.aspect), (2, AspectRatio::Ntsc));
+        assert_eq!((config.paths, config.emulation), (PathConfig::default(), EmulationConfig::default()));
+
+        let error = toml::from_str::<Config>("[video]\nfilter = \"sepia\"\n").unwrap_err();
+        assert!(error.to_string().contains("Unknown filter sepia"));
+        assert!(toml::from_str::<Config>("[video]\nscael = 2\n").is_err());
+    }
+
+    #[test]
+    fn test_game_overrides() {
+        let text = "[games.00000000000000ff]\nname = \"Duck Hunt\"\nregion = \"pal\"\naccuracy = \"accurate\"\ncontroller = \"zapper\"\ncheats = false\nkeyboard = true\n\
+                    overscan = { top = 8, bottom = 8 }\n";
+        let config: Config = toml::from_str(text).unwrap();
+        let game = config.game(0xff).unwrap();
+        assert_eq!((game.controller, game.four_score, game.cheats), (Some(Controller::Zapper), false, false));
+        assert!(game.keyboard);
+        assert_eq!(game.overscan, Some(Overscan { top: 8, bottom: 8, left: 0, right: 0 }));
+        assert_eq!((game.region, game.accuracy), (Some(Region::Pal), Some(AccuracyProfile::Accurate)));
+        assert_eq!(config.game(0xfe), None);
+        assert_eq!(toml::from_str::<Config>(&toml::to_string(&config).unwrap()).unwrap(), config);
+
+        let config: Config = toml::from_str("[games.00000000000000ff]\ncontroller = \"power_pad\"\n").unwrap();
+        assert_eq!(config.game(0xff).unwrap().controller, Some(Controller::PowerPad));
+        assert!(toml::from_str::<Config>("[games.00000000000000ff]\ncontroller = \"mouse\"\n").is_err());
+    }
+}
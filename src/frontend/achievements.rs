@@ -0,0 +1,107 @@
+//! RetroAchievements for the windowed frontend (the `achievements` cargo feature): sends the
+//! calls `nes_rs_core::achievements` queues to the server over HTTPS, each on its own thread so
+//! the game never waits on the network, and shows what happens on the OSD.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use nes_rs_core::achievements::{Achievements, Event, Request};
+
+use crate::render::osd::Osd;
+
+pub struct Client {
+    pub achievements: Achievements,
+    user_agent: String,
+    replies_in: Sender<(u64, u16, Vec<u8>)>,
+    replies: Receiver<(u64, u16, Vec<u8>)>,
+    // Leaderboard values being played for, by leaderboard.
+    trackers: BTreeMap<u32, String>,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        let achievements = Achievements::new();
+        let user_agent = format!("nes_rs/{} {}", env!("CARGO_PKG_VERSION"), achievements.user_agent());
+        let (replies_in, replies) = channel();
+        Client {
+            achievements,
+            user_agent,
+            replies_in,
+            replies,
+            trackers: BTreeMap::new(),
+        }
+    }
+
+    // Sends new requests, passes back replies that have come in and posts events to the OSD. Call
+    // it once per host frame. A reset rcheevos asks for happens in the next `Achievements::do_frame`.
+    pub fn update(&mut self, osd: &mut Osd) {
+        for request in self.achievements.take_requests() {
+            self.send(request);
+        }
+        while let Ok((id, status, body)) = self.replies.try_recv() {
+            self.achievements.respond(id, status, &body);
+        }
+        for event in self.achievements.take_events() {
+            match event {
+                Event::LoggedIn { name, token } => {
+                    osd.post(format!("Logged in to RetroAchievements as {}", name));
+                    println!("Logged in as {}. Next time, --ra-token {} logs in without the password.", name, token);
+                }
+                Event::LoginFailed(e) => osd.post(format!("Could not log in: {}", e)),
+                Event::GameLoaded { title } => osd.post(format!("Achievements for {}", title)),
+                Event::GameLoadFailed(e) => osd.post(format!("No achievements: {}", e)),
+                Event::Unlocked { title, points, .. } => osd.post(format!("Unlocked {} ({} points)", title, points)),
+                Event::LeaderboardStarted(title) => osd.post(format!("Leaderboard attempt: {}", title)),
+                Event::LeaderboardFailed(title) => osd.post(format!("Leaderboard attempt failed: {}", title)),
+                Event::LeaderboardSubmitted { title, score } => osd.post(format!("Submitted {} to {}", score, title)),
+                Event::TrackerShown { id, display } => {
+                    self.trackers.insert(id, display);
+                }
+                Event::TrackerHidden(id) => {
+                    self.trackers.remove(&id);
+                }
+                Event::GameCompleted => osd.post("Every achievement unlocked!"),
+                Event::ServerError(e) => osd.post(e),
+                Event::Disconnected => osd.post("RetroAchievements unreachable, retrying unlocks"),
+                Event::Reconnected => osd.post("RetroAchievements reconnected"),
+            }
+        }
+    }
+
+    // The leaderboard values being played for, as an OSD status line.
+    pub fn trackers(&self) -> Option<String> {
+        (!self.trackers.is_empty()).then(|| self.trackers.values().cloned().collect::<Vec<_>>().join("  "))
+    }
+
+    fn send(&self, request: Request) {
+        let user_agent = self.user_agent.clone();
+        let replies = self.replies_in.clone();
+        std::thread::spawn(move || {
+            let reply = match &request.post_data {
+                Some(body) => ureq::post(&request.url)
+                    .set("User-Agent", &user_agent)
+                    .set("Content-Type", request.content_type.as_deref().unwrap_or("application/x-www-form-urlencoded"))
+                    .send_string(body),
+                None => ureq::get(&request.url).set("User-Agent", &user_agent).call(),
+            };
+            let mut body = Vec::new();
+            let status = match reply {
+                Ok(response) | Err(ureq::Error::Status(_, response)) => {
+                    let status = response.status();
+                    response.into_reader().read_to_end(&mut body).map_or(0, |_| status)
+                }
+                // Couldn't connect. rcheevos tries again later.
+                Err(ureq::Error::Transport(_)) => 0,
+            };
+            // Only fails once the client is gone.
+            let _ = replies.send((request.id, status, body));
+        });
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Client::new()
+    }
+}
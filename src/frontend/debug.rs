@@ -0,0 +1,643 @@
+//! Debug viewers for the pattern tables, nametables, palettes, memory and register events, plus
+//! the debugger, the cheat list and cheat search, shown as movable windows over the game. They are rebuilt from the emulator state once per host
+//! frame, after the emulated frames have run, so they never hold up emulation.
+
+use macroquad::prelude::*;
+use macroquad::ui::{hash, root_ui, widgets, Id};
+
+use crate::bus::Bus;
+use crate::cheat::game_genie::LETTERS;
+use crate::cheat::search::{CheatSearch, Comparison};
+use crate::debugger::events::{EventKind, EventLog};
+use crate::debugger::memory::{self, MemorySpace};
+use crate::debugger::watch::AccessKind;
+use crate::debugger::{BreakReason, Debugger};
+use crate::disasm;
+use crate::emulator::Emulator;
+use crate::render::debug_views::{self, *};
+use crate::render::filters::Picture;
+
+// Rows shown by the memory viewer; one page is 256 bytes.
+const MEMORY_ROWS: usize = 16;
+const MEMORY_PAGE: u16 = (MEMORY_ROWS * MEMORY_ROW_BYTES) as u16;
+// Room for the title bar and a row of buttons around each picture.
+const TITLE_HEIGHT: f32 = 20.0;
+const BUTTON_ROW_HEIGHT: f32 = 24.0;
+const PADDING: f32 = 8.0;
+// Instructions listed by the debugger window.
+const DISASSEMBLY_LINES: usize = 16;
+const DEBUGGER_SIZE: (f32, f32) = (300.0, 520.0);
+const CHEATS_SIZE: (f32, f32) = (320.0, 360.0);
+const CHEAT_SEARCH_SIZE: (f32, f32) = (320.0, 480.0);
+// Addresses listed by the cheat search window.
+const SEARCH_RESULTS: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugView {
+    PatternTables,
+    Nametables,
+    Palettes,
+    Memory,
+    Debugger,
+    Events,
+    Cheats,
+    CheatSearch,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 8] = [
+        DebugView::PatternTables,
+        DebugView::Nametables,
+        DebugView::Palettes,
+        DebugView::Memory,
+        DebugView::Debugger,
+        DebugView::Events,
+        DebugView::Cheats,
+        DebugView::CheatSearch,
+    ];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            DebugView::PatternTables => "Pattern tables",
+            DebugView::Nametables => "Nametables",
+            DebugView::Palettes => "Palettes",
+            DebugView::Memory => "Memory",
+            DebugView::Debugger => "Debugger",
+            DebugView::Events => "Events",
+            DebugView::Cheats => "Cheats",
+            DebugView::CheatSearch => "Cheat search",
+        }
+    }
+
+    // How much each view is magnified in its window.
+    fn zoom(&self) -> f32 {
+        match self {
+            DebugView::Nametables => 1.0,
+            _ => 2.0,
+        }
+    }
+
+    fn id(&self) -> Id {
+        match self {
+            DebugView::PatternTables => hash!(),
+            DebugView::Nametables => hash!(),
+            DebugView::Palettes => hash!(),
+            DebugView::Memory => hash!(),
+            DebugView::Debugger => hash!(),
+            DebugView::Events => hash!(),
+            DebugView::Cheats => hash!(),
+            DebugView::CheatSearch => hash!(),
+        }
+    }
+
+    // Windows open in a cascade from the top-left; after that they stay where they are dragged.
+    fn position(&self) -> Vec2 {
+        let offset = 16.0 + 24.0 * self.index() as f32;
+        vec2(offset, offset)
+    }
+
+    fn index(&self) -> usize {
+        DebugView::ALL.iter().position(|view| view == self).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StepKind {
+    Into,
+    Over,
+    Out,
+}
+
+struct ViewWindow {
+    open: bool,
+    texture: Option<Texture2D>,
+}
+
+pub struct DebugWindows {
+    windows: [ViewWindow; 8],
+    // Palette used to color the pattern tables (0-3 background, 4-7 sprites).
+    pub pattern_palette: usize,
+    // Address space shown by the memory viewer, its first address and the selected byte.
+    pub memory_space: MemorySpace,
+    pub memory_start: u16,
+    pub memory_cursor: u16,
+    // Whether pokes from the memory viewer go through the registers like a CPU write.
+    pub memory_side_effects: bool,
+    // First address disassembled by the debugger window, or None to follow the program counter.
+    pub disassembly_start: Option<u16>,
+    // Event of the last frame picked out in the event viewer.
+    pub event_cursor: usize,
+    // Letters of the Game Genie code being entered in the cheats window, and why the last one was
+    // turned down.
+    pub cheat_entry: String,
+    pub cheat_error: Option<String>,
+    // The cheat search, started when its window first opens, and the value "=" compares with.
+    pub cheat_search: Option<CheatSearch>,
+    pub search_value: u8,
+}
+
+impl Default for DebugWindows {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DebugWindows {
+    pub fn new() -> Self {
+        DebugWindows {
+            windows: std::array::from_fn(|_| ViewWindow { open: false, texture: None }),
+            pattern_palette: 0,
+            memory_space: MemorySpace::Cpu,
+            memory_start: 0,
+            memory_cursor: 0,
+            memory_side_effects: false,
+            disassembly_start: None,
+            event_cursor: 0,
+            cheat_entry: String::new(),
+            cheat_error: None,
+            cheat_search: None,
+            search_value: 0,
+        }
+    }
+
+    pub fn is_open(&self, view: DebugView) -> bool {
+        self.windows[view.index()].open
+    }
+
+    pub fn set_open(&mut self, view: DebugView, open: bool) {
+        self.windows[view.index()].open = open;
+    }
+
+    pub fn any_open(&self) -> bool {
+        self.windows.iter().any(|window| window.open)
+    }
+
+    // F1 to F4 toggle the pattern table, nametable, palette and memory viewers, ` toggles the
+    // debugger, Shift+` the event viewer, C the cheats and Shift+C cheat search.
+    pub fn handle_hotkeys(&mut self) {
+        let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+        let keys = [
+            (KeyCode::F1, false),
+            (KeyCode::F2, false),
+            (KeyCode::F3, false),
+            (KeyCode::F4, false),
+            (KeyCode::GraveAccent, false),
+            (KeyCode::GraveAccent, true),
+            (KeyCode::C, false),
+            (KeyCode::C, true),
+        ];
+        for (view, (key, with_shift)) in DebugView::ALL.into_iter().zip(keys) {
+            if is_key_pressed(key) && shift == with_shift {
+                self.set_open(view, !self.is_open(view));
+            }
+        }
+    }
+
+    fn picture(&self, view: DebugView, bus: &Bus) -> Picture {
+        match view {
+            DebugView::PatternTables => debug_views::pattern_tables(&bus.ppu, self.pattern_palette),
+            DebugView::Nametables => debug_views::nametables(&bus.ppu),
+            DebugView::Palettes => debug_views::palettes(&bus.ppu),
+            DebugView::Memory => debug_views::memory(
+                |addr| memory::peek(bus, self.memory_space, addr),
+                self.memory_start,
+                MEMORY_ROWS,
+                Some(self.memory_cursor),
+            ),
+            DebugView::Events => {
+                let events = bus.events.as_ref().map_or(&[][..], EventLog::events);
+                debug_views::events(events, Some(self.event_cursor))
+            }
+            DebugView::Debugger | DebugView::Cheats | DebugView::CheatSearch => unreachable!(),
+        }
+    }
+
+    // Refreshes and draws the open viewers. Call once per host frame, after `Frontend::present`.
+    pub fn show(&mut self, emulator: &mut Emulator, debugger: &mut Debugger) {
+        // Register accesses are only logged while someone is looking at them.
+        let events = &mut emulator.cpu.bus.events;
+        match (self.is_open(DebugView::Events), events.is_some()) {
+            (true, false) => *events = Some(EventLog::new()),
+            (false, true) => *events = None,
+            _ => {}
+        }
+
+        let mut edit = None;
+        for view in DebugView::ALL {
+            if !self.is_open(view) || matches!(view, DebugView::Debugger | DebugView::Cheats | DebugView::CheatSearch) {
+                continue;
+            }
+
+            let picture = self.picture(view, &emulator.cpu.bus);
+            let window = &mut self.windows[view.index()];
+            let texture = match &window.texture {
+                Some(texture) if texture.width() as usize == picture.width
+                    && texture.height() as usize == picture.height => {
+                    texture.update_from_bytes(picture.width as u32, picture.height as u32, &picture.pixels);
+                    texture.clone()
+                }
+                _ => {
+                    let texture = Texture2D::from_rgba8(picture.width as u16, picture.height as u16, &picture.pixels);
+                    texture.set_filter(FilterMode::Nearest);
+                    window.texture = Some(texture.clone());
+                    texture
+                }
+            };
+
+            let (width, height) = (picture.width as f32 * view.zoom(), picture.height as f32 * view.zoom());
+            let button_rows = match view {
+                DebugView::PatternTables => 1.0,
+                DebugView::Memory | DebugView::Events => 2.0,
+                _ => 0.0,
+            };
+            let size = vec2(
+                width + 2.0 * PADDING,
+                height + TITLE_HEIGHT + 2.0 * PADDING + button_rows * BUTTON_ROW_HEIGHT,
+            );
+
+            let mut pattern_palette = self.pattern_palette;
+            let mut memory_space = self.memory_space;
+            let mut memory_start = self.memory_start;
+            let mut cursor = self.memory_cursor;
+            let mut side_effects = self.memory_side_effects;
+            let mut event_cursor = self.event_cursor;
+            let frame_events = emulator.cpu.bus.events.as_ref().map_or(&[][..], EventLog::events);
+            let event_counts: Vec<String> = EventKind::ALL
+                .iter()
+                .map(|kind| {
+                    let count = frame_events.iter().filter(|event| event.kind == *kind).count();
+                    format!("{} {}", kind.name(), count)
+                })
+                .collect();
+            let cursor_value = memory::peek(&emulator.cpu.bus, memory_space, cursor);
+            let open = widgets::Window::new(view.id(), view.position(), size)
+                .label(view.title())
+                .close_button(true)
+                .ui(&mut root_ui(), |ui| {
+                    ui.texture(texture, width, height);
+                    if view == DebugView::PatternTables
+                        && ui.button(None, format!("Palette {}", pattern_palette).as_str())
+                    {
+                        pattern_palette = (pattern_palette + 1) % 8;
+                    }
+                    if view == DebugView::Memory {
+                        if ui.button(None, memory_space.name()) {
+                            memory_space = memory_space.next();
+                            memory_start = memory_space.wrap(memory_start);
+                            cursor = memory_start;
+                        }
+                        for (label, delta) in [("Prev", MEMORY_PAGE.wrapping_neg()), ("Next", MEMORY_PAGE)] {
+                            ui.same_line(0.0);
+                            if ui.button(None, label) {
+                                memory_start = memory_space.wrap(memory_start.wrapping_add(delta));
+                                cursor = memory_space.wrap(cursor.wrapping_add(delta));
+                            }
+                        }
+                        ui.same_line(0.0);
+                        ui.checkbox(hash!(), "Side effects", &mut side_effects);
+
+                        let row = MEMORY_ROW_BYTES as u16;
+                        for (label, delta) in [("<", 0xffff), (">", 1), ("^", row.wrapping_neg()), ("v", row)] {
+                            if ui.button(None, label) {
+                                cursor = memory_space.wrap(cursor.wrapping_add(delta));
+                            }
+                            ui.same_line(0.0);
+                        }
+                        ui.label(None, &format!("${:04X} = {:02X}", cursor, cursor_value));
+                        for (label, delta) in [("-", 0xff), ("+", 1)] {
+                            ui.same_line(0.0);
+                            if ui.button(None, label) {
+                                edit = Some((memory_space, cursor, cursor_value.wrapping_add(delta), side_effects));
+                            }
+                        }
+                    }
+                    if view == DebugView::Events {
+                        ui.label(None, &event_counts.join("  "));
+                        for (label, delta) in [("<", frame_events.len().saturating_sub(1)), (">", 1)] {
+                            if ui.button(None, label) && !frame_events.is_empty() {
+                                event_cursor = (event_cursor + delta) % frame_events.len();
+                            }
+                            ui.same_line(0.0);
+                        }
+                        match frame_events.get(event_cursor) {
+                            Some(event) => ui.label(None, &format!("{}: {}", event.kind.name(), event.describe())),
+                            None => ui.label(None, "No events"),
+                        }
+                    }
+                });
+            self.pattern_palette = pattern_palette;
+            self.memory_space = memory_space;
+            self.memory_side_effects = side_effects;
+            self.event_cursor = event_cursor;
+            // Keep the cursor on screen, turning the page if it moved off it.
+            if cursor.wrapping_sub(memory_start) >= MEMORY_PAGE {
+                memory_start = memory_space.wrap(cursor & !(MEMORY_ROW_BYTES as u16 - 1));
+            }
+            self.memory_start = memory_start;
+            self.memory_cursor = cursor;
+            self.windows[view.index()].open = open;
+        }
+
+        if let Some((space, addr, value, side_effects)) = edit {
+            memory::poke(&mut emulator.cpu.bus, space, addr, value, side_effects);
+        }
+
+        if self.is_open(DebugView::Debugger) {
+            self.show_debugger(emulator, debugger);
+        }
+        if self.is_open(DebugView::Cheats) {
+            self.show_cheats(emulator);
+        }
+        if self.is_open(DebugView::CheatSearch) {
+            self.show_cheat_search(emulator, debugger);
+        }
+    }
+
+    // Narrows the search down with the comparison buttons, and lists what is left with each
+    // address's value at the last comparison and now. Freeze adds a cheat holding the address at
+    // its value now; Watch stops the debugger when the game writes it.
+    fn show_cheat_search(&mut self, emulator: &mut Emulator, debugger: &mut Debugger) {
+        let view = DebugView::CheatSearch;
+        let size = vec2(CHEAT_SEARCH_SIZE.0, CHEAT_SEARCH_SIZE.1);
+
+        let ram = &emulator.cpu.bus.cpu_wram;
+        let search = self.cheat_search.get_or_insert_with(|| CheatSearch::new(ram));
+        let results: Vec<_> = search.results(ram).take(SEARCH_RESULTS).collect();
+        let count = search.len();
+        let mut value = self.search_value;
+        let (mut narrow, mut restart, mut freeze, mut watch) = (None, false, None, None);
+        let open = widgets::Window::new(view.id(), view.position(), size)
+            .label(view.title())
+            .close_button(true)
+            .ui(&mut root_ui(), |ui| {
+                let comparisons = [Comparison::Unchanged, Comparison::Changed, Comparison::Increased, Comparison::Decreased];
+                for (i, comparison) in comparisons.into_iter().enumerate() {
+                    if i > 0 {
+                        ui.same_line(0.0);
+                    }
+                    if ui.button(None, comparison.name().as_str()) {
+                        narrow = Some(comparison);
+                    }
+                }
+                if ui.button(None, Comparison::EqualTo(value).name().as_str()) {
+                    narrow = Some(Comparison::EqualTo(value));
+                }
+                for (label, delta) in [("-", 0xff), ("+", 1)] {
+                    ui.same_line(0.0);
+                    if ui.button(None, label) {
+                        value = value.wrapping_add(delta);
+                    }
+                }
+                ui.same_line(0.0);
+                if ui.button(None, "Restart") {
+                    restart = true;
+                }
+                ui.label(None, &format!("{} addresses", count));
+                ui.separator();
+                for result in &results {
+                    ui.label(None, &format!("${:04X}: {:02X} -> {:02X}", result.addr, result.previous, result.current));
+                    ui.same_line(0.0);
+                    if ui.button(None, "Freeze") {
+                        freeze = Some(*result);
+                    }
+                    ui.same_line(0.0);
+                    if ui.button(None, "Watch") {
+                        watch = Some(*result);
+                    }
+                }
+                if count > results.len() {
+                    ui.label(None, &format!("... and {} more", count - results.len()));
+                }
+            });
+
+        let ram = &emulator.cpu.bus.cpu_wram;
+        match (narrow, restart) {
+            (_, true) => self.cheat_search = Some(CheatSearch::new(ram)),
+            (Some(comparison), false) => search.narrow(ram, comparison),
+            (None, false) => {}
+        }
+        if let Some(result) = freeze {
+            // Already frozen is fine.
+            let _ = emulator.cpu.bus.cheats.add(result.to_freeze());
+        }
+        if let Some(result) = watch {
+            debugger.add_watchpoint(result.to_watchpoint());
+        }
+        self.search_value = value;
+        self.windows[view.index()].open = open;
+    }
+
+    // The game's cheats, from its cheat file and entered here, each with a checkbox to switch it on
+    // and off, and a Game Genie letter pad to enter new codes with. Entering codes by clicking keeps the letters from setting off
+    // hotkeys.
+    fn show_cheats(&mut self, emulator: &mut Emulator) {
+        let view = DebugView::Cheats;
+        let size = vec2(CHEATS_SIZE.0, CHEATS_SIZE.1);
+
+        let cheats = &emulator.cpu.bus.cheats;
+        let mut enabled: Vec<bool> = cheats.list().iter().map(|cheat| cheat.enabled).collect();
+        let mut entry = std::mem::take(&mut self.cheat_entry);
+        let (mut add, mut removed) = (false, None);
+        let open = widgets::Window::new(view.id(), view.position(), size)
+            .label(view.title())
+            .close_button(true)
+            .ui(&mut root_ui(), |ui| {
+                for (i, letter) in LETTERS.chars().enumerate() {
+                    if !i.is_multiple_of(4) {
+                        ui.same_line(0.0);
+                    }
+                    if ui.button(None, letter.to_string().as_str()) && entry.len() < 8 {
+                        entry.push(letter);
+                    }
+                }
+                ui.label(None, &format!("Code: {:_<6}", entry));
+                if ui.button(None, "Del") {
+                    entry.pop();
+                }
+                ui.same_line(0.0);
+                if ui.button(None, "Add") {
+                    add = true;
+                }
+                if let Some(error) = &self.cheat_error {
+                    ui.label(None, error);
+                }
+                ui.separator();
+                for (i, cheat) in cheats.list().iter().enumerate() {
+                    ui.checkbox(hash!("cheat", i), &cheat.label(), &mut enabled[i]);
+                    ui.same_line(0.0);
+                    if ui.button(None, "Remove") {
+                        removed = Some(i);
+                    }
+                }
+            });
+
+        let cheats = &mut emulator.cpu.bus.cheats;
+        for (i, enabled) in enabled.into_iter().enumerate() {
+            cheats.set_enabled(i, enabled);
+        }
+        if let Some(i) = removed {
+            cheats.remove(i);
+        }
+        if add {
+            match cheats.add_game_genie(&entry) {
+                Ok(_) => {
+                    entry.clear();
+                    self.cheat_error = None;
+                }
+                Err(e) => self.cheat_error = Some(e),
+            }
+        }
+        self.cheat_entry = entry;
+        self.windows[view.index()].open = open;
+    }
+
+    // Registers, a disassembly from the program counter (with labels from the debugger's symbols)
+    // and the run controls. Clicking an instruction toggles a breakpoint on it.
+    fn show_debugger(&mut self, emulator: &mut Emulator, debugger: &mut Debugger) {
+        let view = DebugView::Debugger;
+        let size = vec2(DEBUGGER_SIZE.0, DEBUGGER_SIZE.1);
+
+        let cpu = &emulator.cpu;
+        let status = match debugger.break_reason() {
+            None => "Running".to_string(),
+            Some(BreakReason::Paused) => "Paused".to_string(),
+            Some(BreakReason::Breakpoint(addr)) => format!("Breakpoint at ${:04X}", addr),
+            Some(BreakReason::Watchpoint(hit)) => match hit.kind {
+                AccessKind::Read => format!("${:04X} read ({:02X}) at ${:04X}", hit.addr, hit.new, hit.pc),
+                _ => format!("${:04X} written {:02X} -> {:02X} at ${:04X}", hit.addr, hit.old, hit.new, hit.pc),
+            },
+            Some(BreakReason::Step) => "Stepped".to_string(),
+            Some(BreakReason::Halted) => "Halted on BRK or an unknown opcode".to_string(),
+        };
+        let registers = format!(
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} Depth:{}",
+            cpu.program_counter,
+            cpu.register_a,
+            cpu.register_x,
+            cpu.register_y,
+            cpu.status.bits(),
+            cpu.stack_pointer,
+            debugger.call_depth()
+        );
+        let start = self.disassembly_start.unwrap_or(cpu.program_counter);
+        let instructions = disasm::disassemble(&cpu.bus, start, DISASSEMBLY_LINES);
+        let symbols = &debugger.symbols;
+        let breakpoints: Vec<String> = debugger
+            .breakpoints()
+            .map(|addr| {
+                let name = symbols.name(addr).map_or(format!("${:04X}", addr), str::to_string);
+                match debugger.breakpoint_condition(addr) {
+                    Some(condition) => format!("{} if {}", name, condition),
+                    None => name,
+                }
+            })
+            .collect();
+        let watchpoints: Vec<String> = debugger
+            .watchpoints()
+            .iter()
+            .map(|watch| {
+                let kind = match watch.kind {
+                    AccessKind::Read => "r",
+                    AccessKind::Write => "w",
+                    AccessKind::ReadWrite => "rw",
+                };
+                if watch.start == watch.end {
+                    format!("${:04X}:{}", watch.start, kind)
+                } else {
+                    format!("${:04X}-${:04X}:{}", watch.start, watch.end, kind)
+                }
+            })
+            .collect();
+        let code_data_log = cpu.bus.cdl.as_ref().map(|log| {
+            let (code, data) = log.prg_counts();
+            let percent = |count: usize| 100.0 * count as f32 / log.prg.len() as f32;
+            format!("CDL: {:.1}% code, {:.1}% data", percent(code), percent(data))
+        });
+
+        // Innermost call first, shown while stopped.
+        let backtrace = match debugger.is_paused() {
+            true => debugger.call_stack().backtrace(symbols),
+            false => Vec::new(),
+        };
+
+        let mut toggled = None;
+        let (mut run, mut step, mut page) = (false, None, None);
+        let open = widgets::Window::new(view.id(), view.position(), size)
+            .label(view.title())
+            .close_button(true)
+            .ui(&mut root_ui(), |ui| {
+                ui.label(None, &status);
+                ui.label(None, &registers);
+                if ui.button(None, if debugger.is_paused() { "Continue" } else { "Pause" }) {
+                    run = true;
+                }
+                ui.same_line(0.0);
+                for (label, kind) in [("Into", StepKind::Into), ("Over", StepKind::Over), ("Out", StepKind::Out)] {
+                    ui.same_line(0.0);
+                    if ui.button(None, label) {
+                        step = Some(kind);
+                    }
+                }
+                ui.separator();
+                for instruction in &instructions {
+                    if let Some(label) = symbols.name(instruction.addr) {
+                        ui.label(None, &format!("{}:", label));
+                    }
+                    let marker = match (debugger.has_breakpoint(instruction.addr), instruction.addr == cpu.program_counter) {
+                        (true, true) => "*>",
+                        (true, false) => "* ",
+                        (false, true) => " >",
+                        (false, false) => "  ",
+                    };
+                    if ui.button(None, format!("{} {:04X}  {}", marker, instruction.addr, instruction.with_symbols(symbols)).as_str()) {
+                        toggled = Some(instruction.addr);
+                    }
+                }
+                if ui.button(None, "Follow PC") {
+                    page = Some(None);
+                }
+                ui.same_line(0.0);
+                if ui.button(None, "Next") {
+                    page = instructions.last().map(|last| Some(last.next_addr()));
+                }
+                ui.separator();
+                ui.label(None, &format!("Breakpoints: {}", breakpoints.join(" ")));
+                ui.label(None, &format!("Watchpoints: {}", watchpoints.join(" ")));
+                if let Some(line) = &code_data_log {
+                    ui.label(None, line);
+                }
+                if !backtrace.is_empty() {
+                    ui.separator();
+                    ui.label(None, "Call stack:");
+                    for line in &backtrace {
+                        ui.label(None, &format!("  {}", line));
+                    }
+                }
+            });
+
+        if let Some(addr) = toggled {
+            debugger.toggle_breakpoint(addr);
+        }
+        if let Some(start) = page {
+            self.disassembly_start = start;
+        }
+        if run {
+            if debugger.is_paused() {
+                debugger.resume();
+            } else {
+                debugger.pause();
+            }
+        }
+        match step {
+            Some(StepKind::Into) => {
+                debugger.step_instruction(emulator);
+            }
+            Some(StepKind::Over) => {
+                debugger.step_over(emulator);
+            }
+            Some(StepKind::Out) => debugger.step_out(),
+            None => {}
+        }
+        self.windows[view.index()].open = open;
+    }
+}
@@ -0,0 +1,210 @@
+//! The launcher shown when the emulator starts without a ROM: the games played most recently, then
+//! the .nes and .fds files in the ROM directory (`[paths] roms` in the config file, searched with
+//! its subdirectories). Up and down on the keyboard or controller 1 pick a game, and Enter, Start
+//! or A starts it; dropping a file onto the window starts that one instead.
+//!
+//! There is no game database, so what is shown about each game comes from its header: the mapper,
+//! the sizes of PRG and CHR ROM, and whether it is a VS System game or a disk.
+
+use std::path::{Path, PathBuf};
+
+use macroquad::prelude::*;
+
+use crate::cartridge::{Cartridge, Console};
+use crate::fds::disk;
+use crate::joypad::controller::HostInput;
+use crate::joypad::JoypadButton;
+
+// Games kept in the recent list.
+pub const MAX_RECENT: usize = 10;
+const LINE_HEIGHT: f32 = 26.0;
+const FONT_SIZE: f32 = 24.0;
+const MARGIN: f32 = 24.0;
+
+// The games played most recently, newest first, kept one path per line in a text file.
+#[derive(Debug, Default)]
+pub struct RecentGames {
+    file: Option<PathBuf>,
+    pub games: Vec<PathBuf>,
+}
+
+impl RecentGames {
+    // Reads the list from `file`. A missing or unreadable file is an empty list, and without a
+    // file nothing is kept.
+    pub fn load(file: Option<PathBuf>) -> Self {
+        let games = file
+            .as_ref()
+            .and_then(|file| std::fs::read_to_string(file).ok())
+            .map(|text| text.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect())
+            .unwrap_or_default();
+        RecentGames { file, games }
+    }
+
+    // Puts `game` at the top of the list and writes the list out.
+    pub fn add(&mut self, game: &Path) -> Result<(), String> {
+        let game = game.canonicalize().unwrap_or_else(|_| game.to_path_buf());
+        self.games.retain(|recent| *recent != game);
+        self.games.insert(0, game);
+        self.games.truncate(MAX_RECENT);
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+        let text: String = self.games.iter().map(|game| format!("{}\n", game.display())).collect();
+        std::fs::write(file, text).map_err(|e| format!("Could not write {}: {}", file.display(), e))
+    }
+}
+
+pub struct Entry {
+    pub path: PathBuf,
+    pub recent: bool,
+    // What the header says about the game, or why it couldn't be read.
+    pub details: String,
+}
+
+// The recent games that still exist, then the rest of the games in `rom_dir`.
+pub fn entries(recent: &RecentGames, rom_dir: &Path) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = recent
+        .games
+        .iter()
+        .filter(|path| path.exists())
+        .map(|path| Entry { path: path.clone(), recent: true, details: describe(path) })
+        .collect();
+    let mut found = Vec::new();
+    find_games(rom_dir, &mut found);
+    found.sort();
+    for path in found {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if !recent.games.contains(&canonical) {
+            entries.push(Entry { details: describe(&path), path, recent: false });
+        }
+    }
+    entries
+}
+
+// .nes and .fds files under `dir`. A missing directory has none.
+fn find_games(dir: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(listing) = std::fs::read_dir(dir) else { return };
+    for path in listing.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        if path.is_dir() {
+            find_games(&path, found);
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("nes") || extension.eq_ignore_ascii_case("fds"))
+        {
+            found.push(path);
+        }
+    }
+}
+
+// A line about the game at `path` from its header, e.g. "Mapper 1, 128 KB PRG, 8 KB CHR RAM".
+pub fn describe(path: &Path) -> String {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => return e.to_string(),
+    };
+    if disk::is_disk_image(&bytes) {
+        return match disk::parse(&bytes) {
+            Ok(sides) if sides.len() == 1 => "Famicom Disk System, 1 side".to_string(),
+            Ok(sides) => format!("Famicom Disk System, {} sides", sides.len()),
+            Err(e) => e,
+        };
+    }
+    match Cartridge::new(&bytes) {
+        Ok(cartridge) => {
+            let chr = match cartridge.chr_rom.is_empty() {
+                true => "8 KB CHR RAM".to_string(),
+                false => format!("{} KB CHR", cartridge.chr_rom.len() / 1024),
+            };
+            let console = match cartridge.console {
+                Console::Nes => "",
+                _ => ", VS System",
+            };
+            format!("Mapper {}, {} KB PRG, {}{}", cartridge.mapper, cartridge.prg_rom.len() / 1024, chr, console)
+        }
+        Err(e) => e,
+    }
+}
+
+// Shows the list until a game is picked or dropped onto the window, and returns its path.
+pub async fn choose(entries: &[Entry], input: &mut HostInput) -> PathBuf {
+    let mut selected = 0;
+    let mut held = JoypadButton::empty();
+    loop {
+        if let Some(path) = get_dropped_files().into_iter().filter_map(|file| file.path).next_back() {
+            return path;
+        }
+
+        // Buttons count when they go down, so holding one moves a single line.
+        let buttons = input.poll()[0];
+        let pressed = buttons - held;
+        held = buttons;
+        if !entries.is_empty() {
+            if pressed.contains(JoypadButton::DOWN) || is_key_pressed(KeyCode::Down) {
+                selected = (selected + 1) % entries.len();
+            }
+            if pressed.contains(JoypadButton::UP) || is_key_pressed(KeyCode::Up) {
+                selected = (selected + entries.len() - 1) % entries.len();
+            }
+            if pressed.intersects(JoypadButton::START | JoypadButton::BUTTON_A) || is_key_pressed(KeyCode::Enter) {
+                return entries[selected].path.clone();
+            }
+        }
+
+        clear_background(BLACK);
+        draw_text("Choose a game", MARGIN, MARGIN + FONT_SIZE, FONT_SIZE * 1.5, WHITE);
+        let top = MARGIN * 2.0 + FONT_SIZE * 1.5;
+        if entries.is_empty() {
+            let message = "No games found. Put some in the ROM directory, or drop a .nes file here.";
+            draw_text(message, MARGIN, top + FONT_SIZE, FONT_SIZE, GRAY);
+        }
+        // Two lines per game, scrolled so the selected one is in view.
+        let rows = (((screen_height() - top) / (LINE_HEIGHT * 2.0)) as usize).max(1);
+        let first = selected.saturating_sub(rows - 1);
+        for (i, entry) in entries.iter().enumerate().skip(first).take(rows) {
+            let y = top + (i - first) as f32 * LINE_HEIGHT * 2.0 + FONT_SIZE;
+            let color = if i == selected { YELLOW } else { WHITE };
+            let name = entry.path.file_name().unwrap_or_default().to_string_lossy();
+            let marker = if i == selected { ">" } else { " " };
+            let recent = if entry.recent { " (recent)" } else { "" };
+            draw_text(format!("{} {}{}", marker, name, recent), MARGIN, y, FONT_SIZE, color);
+            draw_text(&entry.details, MARGIN * 2.0, y + LINE_HEIGHT, FONT_SIZE * 0.75, GRAY);
+        }
+        next_frame().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_games_come_first() {
+        let dir = std::env::temp_dir().join(format!("nes_rs_launcher_{}", std::process::id()));
+        let roms = dir.join("roms");
+        std::fs::create_dir_all(roms.join("more")).unwrap();
+        let rom = std::fs::read("core/tests/nestest/nestest.nes").unwrap();
+        for name in ["b.nes", "a.NES", "more/c.nes", "notes.txt"] {
+            std::fs::write(roms.join(name), &rom).unwrap();
+        }
+
+        let file = dir.join("recent.txt");
+        let mut recent = RecentGames::load(Some(file.clone()));
+        assert!(recent.games.is_empty());
+        recent.add(&roms.join("b.nes")).unwrap();
+        recent.add(&roms.join("more/c.nes")).unwrap();
+        recent.add(&roms.join("b.nes")).unwrap();
+
+        let recent = RecentGames::load(Some(file));
+        let names: Vec<String> = entries(&recent, &roms)
+            .iter()
+            .map(|entry| format!("{} {}", entry.path.file_name().unwrap().to_string_lossy(), entry.recent))
+            .collect();
+        assert_eq!(names, ["b.nes true", "c.nes true", "a.NES false"]);
+        assert_eq!(describe(&roms.join("a.NES")), "Mapper 0, 16 KB PRG, 8 KB CHR");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
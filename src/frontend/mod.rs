@@ -0,0 +1,309 @@
+//! Windowed frontend: uploads each finished frame to a texture and paces emulation in real time.
+//!
+//! macroquad owns the window and the event loop. Each host frame the main loop runs the emulated
+//! frames that are due through `Frontend::run_frames`, then calls `Frontend::present` before
+//! `next_frame().await`.
+
+#[cfg(feature = "achievements")]
+pub mod achievements;
+pub mod debug;
+pub mod launcher;
+pub mod present_thread;
+pub mod scaling;
+pub mod tas;
+pub mod terminal;
+pub mod timing;
+pub mod web;
+#[cfg(feature = "wgpu")]
+pub mod wgpu_backend;
+
+use macroquad::prelude::*;
+
+use crate::render::constants::*;
+use crate::render::filters::{FilterChain, FilterPreset, Picture};
+use crate::render::frame::Frame;
+use crate::render::osd::Osd;
+use crate::render::screenshot;
+use present_thread::PresentThread;
+use scaling::{dest_rect, AspectRatio, VideoSettings, MAX_SCALE, MIN_SCALE};
+use timing::{FpsCounter, FrameTimer, Speed};
+
+// At uncapped speed, spend this many seconds emulating per host frame and leave the rest for
+// presenting. Measured with macroquad's clock, since `std::time::Instant` panics in the browser.
+const UNCAPPED_BUDGET: f64 = 0.012;
+
+pub struct Frontend {
+    texture: Texture2D,
+    picture: Picture,
+    // Post-processing between the frame and the texture. Custom filters can be pushed onto it
+    // before `filter_on_thread` moves it to the worker.
+    pub filters: FilterChain,
+    present_thread: Option<PresentThread>,
+    filter_preset: FilterPreset,
+    pub timer: FrameTimer,
+    video: VideoSettings,
+    // The window's size when it went fullscreen, to go back to afterwards.
+    windowed_size: Option<(f32, f32)>,
+    // Drawn over the picture before filtering. Post messages with `osd.post`.
+    pub osd: Osd,
+    fps: FpsCounter,
+    // Emulated frames run since the last `present`.
+    frames_run: u32,
+}
+
+impl Default for Frontend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Frontend {
+    // Must be called from inside the macroquad main loop, once the window exists.
+    pub fn new() -> Self {
+        let picture = Picture::new(NES_PIXEL_WIDTH as usize, NES_PIXEL_HEIGHT as usize);
+        let texture = Texture2D::from_rgba8(NES_PIXEL_WIDTH as u16, NES_PIXEL_HEIGHT as u16, &picture.pixels);
+
+        let mut frontend = Frontend {
+            texture,
+            picture,
+            filters: FilterChain::new(),
+            present_thread: None,
+            filter_preset: FilterPreset::None,
+            timer: FrameTimer::new(),
+            video: VideoSettings::default(),
+            windowed_size: None,
+            osd: Osd::new(),
+            fps: FpsCounter::new(),
+            frames_run: 0,
+        };
+        frontend.set_video(VideoSettings::default());
+        frontend
+    }
+
+    pub fn video(&self) -> VideoSettings {
+        self.video
+    }
+
+    pub fn filter_preset(&self) -> FilterPreset {
+        self.filter_preset
+    }
+
+    // Replaces the filter chain with one of the built-in presets.
+    pub fn set_filter_preset(&mut self, preset: FilterPreset) {
+        match &mut self.present_thread {
+            Some(thread) => thread.set_filters(preset.chain()),
+            None => self.filters = preset.chain(),
+        }
+        self.filter_preset = preset;
+    }
+
+    // Runs the filters on a thread of their own from now on (see `present_thread`). Not available
+    // in the browser, which has no threads.
+    pub fn filter_on_thread(&mut self) {
+        if self.present_thread.is_none() {
+            self.present_thread = Some(PresentThread::new(std::mem::take(&mut self.filters)));
+        }
+    }
+
+    fn post_speed(&mut self) {
+        match self.timer.speed {
+            Speed::Scaled(multiplier) => self.osd.post(format!("Speed {}%", (multiplier * 100.0).round())),
+            Speed::Uncapped => self.osd.post("Speed max"),
+        }
+    }
+
+    // Applies new video settings, resizing the window if the scale changed. Leaving fullscreen
+    // restores the window's size from before, resized by hand or not, unless the scale changed
+    // meanwhile.
+    pub fn set_video(&mut self, video: VideoSettings) {
+        let video = VideoSettings {
+            scale: video.scale.clamp(MIN_SCALE, MAX_SCALE),
+            overscan: video.overscan.clamped(),
+            ..video
+        };
+        if video.fullscreen && !self.video.fullscreen {
+            self.windowed_size = Some((screen_width(), screen_height()));
+        }
+        if video.fullscreen != self.video.fullscreen {
+            set_fullscreen(video.fullscreen);
+        }
+        let leaving_fullscreen = self.video.fullscreen && !video.fullscreen;
+        let resized = video.scale != self.video.scale
+            || video.aspect != self.video.aspect
+            || video.overscan != self.video.overscan;
+        if resized {
+            self.windowed_size = None;
+        }
+        if !video.fullscreen && (resized || leaving_fullscreen) {
+            let (width, height) = self.windowed_size.take().unwrap_or_else(|| video.window_size());
+            request_new_screen_size(width, height);
+        }
+        self.texture.set_filter(if video.nearest_filter {
+            FilterMode::Nearest
+        } else {
+            FilterMode::Linear
+        });
+        self.video = video;
+    }
+
+    // Video hotkeys: - and = shrink and grow the window, F5 switches between square and 8:7
+    // pixels, F6 toggles integer scaling, F7 toggles
+    // nearest-neighbor filtering, F8 cycles the filter presets and F11 or Alt+Enter toggles
+    // fullscreen.
+    // Speed hotkeys: hold Tab to fast-forward, [ and ] slow down and speed up, P pauses and
+    // backslash advances a single frame.
+    // F10 toggles the FPS counter.
+    pub fn handle_hotkeys(&mut self) {
+        if is_key_pressed(KeyCode::P) {
+            self.timer.toggle_pause();
+            self.osd.post(if self.timer.paused { "Paused" } else { "Resumed" });
+        }
+        if is_key_pressed(KeyCode::Backslash) {
+            self.timer.step_frame();
+        }
+        self.timer.fast_forwarding = is_key_down(KeyCode::Tab);
+        if is_key_pressed(KeyCode::LeftBracket) {
+            self.timer.step_speed(false);
+            self.post_speed();
+        }
+        if is_key_pressed(KeyCode::RightBracket) {
+            self.timer.step_speed(true);
+            self.post_speed();
+        }
+        if is_key_pressed(KeyCode::F10) {
+            self.osd.show_fps = !self.osd.show_fps;
+        }
+
+        let mut video = self.video;
+        let alt = is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt);
+        if is_key_pressed(KeyCode::F11) || (alt && is_key_pressed(KeyCode::Enter)) {
+            video.fullscreen = !video.fullscreen;
+        }
+        if is_key_pressed(KeyCode::Minus) {
+            video.scale = video.scale.saturating_sub(1);
+        }
+        if is_key_pressed(KeyCode::Equal) {
+            video.scale += 1;
+        }
+        if is_key_pressed(KeyCode::F5) {
+            video.aspect = video.aspect.next();
+            self.osd.post(format!("Aspect ratio {}", if video.aspect == AspectRatio::Ntsc { "8:7" } else { "1:1" }));
+        }
+        if is_key_pressed(KeyCode::F6) {
+            video.integer_scaling = !video.integer_scaling;
+        }
+        if is_key_pressed(KeyCode::F7) {
+            video.nearest_filter = !video.nearest_filter;
+        }
+        if is_key_pressed(KeyCode::F8) {
+            self.set_filter_preset(self.filter_preset.next());
+            self.osd.post(format!("Filter: {}", self.filter_preset.name()));
+        }
+        if video != self.video {
+            self.set_video(video);
+        }
+    }
+
+    // Calls `run_frame` once for every emulated frame due before the next `present`, with
+    // whether the frame can go undrawn (see `FrameTimer::skip_next_frame`).
+    pub fn run_frames<F: FnMut(bool)>(&mut self, mut run_frame: F) {
+        match self.timer.current_speed() {
+            Speed::Uncapped if !self.timer.paused => {
+                let start = get_time();
+                while get_time() - start < UNCAPPED_BUDGET {
+                    run_frame(self.timer.skip_next_frame());
+                    self.frames_run += 1;
+                }
+            }
+            _ => {
+                for _ in 0..self.timer.advance(get_frame_time() as f64) {
+                    run_frame(self.timer.skip_next_frame());
+                    self.frames_run += 1;
+                }
+            }
+        }
+    }
+
+    // Where the picture goes in the current window. Recomputed every frame, so it follows resizes.
+    pub fn dest_rect(&self) -> Rect {
+        dest_rect(screen_width(), screen_height(), &self.video)
+    }
+
+    // Draws the OSD over `frame`, runs it through the filters and draws it scaled to fit the window.
+    pub fn present(&mut self, frame: &Frame) {
+        let elapsed = get_frame_time() as f64;
+        self.fps.add(std::mem::take(&mut self.frames_run), elapsed);
+        self.osd.set_fps(self.fps.fps());
+        self.osd.tick(elapsed);
+
+        self.picture.resize(NES_PIXEL_WIDTH as usize, NES_PIXEL_HEIGHT as usize);
+        frame.write_rgba8(&mut self.picture.pixels);
+        self.video.overscan.crop(&mut self.picture);
+        self.osd.draw(&mut self.picture);
+        match &mut self.present_thread {
+            // Show what the worker finished since last time, then give it this picture.
+            Some(thread) => {
+                if let Some(output) = thread.finished() {
+                    upload(&mut self.texture, output, self.video.nearest_filter);
+                }
+                thread.submit(&self.picture);
+            }
+            None => upload(&mut self.texture, self.filters.apply(&self.picture), self.video.nearest_filter),
+        }
+
+        let dest = self.dest_rect();
+        clear_background(BLACK);
+        draw_texture_ex(
+            &self.texture,
+            dest.x,
+            dest.y,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(vec2(dest.w, dest.h)),
+                ..Default::default()
+            },
+        );
+    }
+
+    // Writes the picture as currently displayed (scaled and filtered, without the borders) to
+    // `path` as a PNG. Call after `present`.
+    pub fn screenshot(&self, path: &std::path::Path) -> Result<(), String> {
+        let screen = get_screen_data();
+        let dest = self.dest_rect();
+        let (x0, y0) = (dest.x.max(0.0) as usize, dest.y.max(0.0) as usize);
+        let width = (dest.w as usize).min(screen.width() - x0);
+        let height = (dest.h as usize).min(screen.height() - y0);
+
+        // OpenGL hands the rows back bottom-up.
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for y in y0..y0 + height {
+            let row = screen.height() - 1 - y;
+            let start = (row * screen.width() + x0) * 4;
+            rgba.extend_from_slice(&screen.bytes[start..start + width * 4]);
+        }
+        screenshot::write_png(path, width as u32, height as u32, &rgba)
+    }
+
+    // Converts a window position (e.g. the mouse) to NES pixel coordinates. Positions outside the
+    // picture, including the cropped overscan, map outside 0..256 x 0..240.
+    pub fn screen_to_nes(&self, x: f32, y: f32) -> (i32, i32) {
+        let dest = self.dest_rect();
+        let overscan = self.video.overscan;
+        let (width, height) = overscan.visible_size();
+        (
+            ((x - dest.x) * width as f32 / dest.w).floor() as i32 + overscan.left as i32,
+            ((y - dest.y) * height as f32 / dest.h).floor() as i32 + overscan.top as i32,
+        )
+    }
+}
+
+// Copies `picture` into `texture`. Filters like Scale2x change the picture size; the texture follows.
+fn upload(texture: &mut Texture2D, picture: &Picture, nearest_filter: bool) {
+    let size = (picture.width as u32, picture.height as u32);
+    if size != (texture.width() as u32, texture.height() as u32) {
+        *texture = Texture2D::from_rgba8(size.0 as u16, size.1 as u16, &picture.pixels);
+        texture.set_filter(if nearest_filter { FilterMode::Nearest } else { FilterMode::Linear });
+    } else {
+        texture.update_from_bytes(size.0, size.1, &picture.pixels);
+    }
+}
@@ -0,0 +1,136 @@
+//! Runs the filter chain on a thread of its own, so a heavy filter doesn't eat into the time left
+//! for emulation. The main thread hands over each picture with the OSD drawn on it and uploads
+//! whichever filtered picture has come back; the upload itself has to stay on the main thread,
+//! which owns the GL context. The picture shown is one host frame older than without it.
+//!
+//! Only one picture is ever out at a time. One that arrives while the worker is still busy is
+//! dropped rather than waited for, so a slow filter costs frames, never emulation time.
+
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::render::filters::{FilterChain, Picture};
+
+enum Job {
+    // Filter `input` into `output`, both handed back with the result.
+    Filter { input: Picture, output: Picture },
+    Filters(FilterChain),
+}
+
+pub struct PresentThread {
+    jobs: Option<SyncSender<Job>>,
+    results: Receiver<(Picture, Picture)>,
+    // The input and output buffers while the worker isn't using them.
+    buffers: Option<(Picture, Picture)>,
+    // Whether `buffers` holds a filtered picture not yet handed out.
+    fresh: bool,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl PresentThread {
+    // Starts the worker with `filters`, which it owns from then on.
+    pub fn new(filters: FilterChain) -> Self {
+        let (jobs, incoming) = sync_channel::<Job>(1);
+        let (finished, results) = channel();
+        let worker = thread::spawn(move || {
+            let mut filters = filters;
+            for job in incoming {
+                match job {
+                    Job::Filter { input, mut output } => {
+                        let filtered = filters.apply(&input);
+                        output.resize(filtered.width, filtered.height);
+                        output.pixels.copy_from_slice(&filtered.pixels);
+                        if finished.send((input, output)).is_err() {
+                            return;
+                        }
+                    }
+                    Job::Filters(chain) => filters = chain,
+                }
+            }
+        });
+        PresentThread {
+            jobs: Some(jobs),
+            results,
+            buffers: Some((Picture::default(), Picture::default())),
+            fresh: false,
+            worker: Some(worker),
+        }
+    }
+
+    // Replaces the worker's filter chain, starting with the next picture.
+    pub fn set_filters(&mut self, filters: FilterChain) {
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(Job::Filters(filters));
+        }
+    }
+
+    // The filtered picture that came back since the last call, if one did.
+    pub fn finished(&mut self) -> Option<&Picture> {
+        if let Ok(buffers) = self.results.try_recv() {
+            self.buffers = Some(buffers);
+            self.fresh = true;
+        }
+        match std::mem::take(&mut self.fresh) {
+            true => self.buffers.as_ref().map(|(_, output)| output),
+            false => None,
+        }
+    }
+
+    // Hands `picture` to the worker, or drops it if the worker hasn't finished the last one.
+    // Returns whether it went.
+    pub fn submit(&mut self, picture: &Picture) -> bool {
+        let (Some(jobs), Some((mut input, output))) = (&self.jobs, self.buffers.take()) else {
+            return false;
+        };
+        input.clone_from(picture);
+        // With the channel holding one job and one picture out at a time, there is always room.
+        let _ = jobs.send(Job::Filter { input, output });
+        self.fresh = false;
+        true
+    }
+
+    // Waits for the picture being filtered, if any. For tests and for screenshots that have to
+    // match what was submitted.
+    pub fn wait(&mut self) -> Option<&Picture> {
+        if self.buffers.is_none() {
+            self.buffers = self.results.recv().ok();
+            self.fresh = self.buffers.is_some();
+        }
+        self.finished()
+    }
+}
+
+impl Drop for PresentThread {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::filters::FilterPreset;
+
+    #[test]
+    fn test_filters_off_thread_like_on_it() {
+        let mut picture = Picture::new(4, 3);
+        picture.set_pixel(1, 1, [200, 100, 50, 255]);
+        let mut chain = FilterPreset::Scale2x.chain();
+        let expected = chain.apply(&picture).clone();
+
+        let mut thread = PresentThread::new(FilterPreset::Scale2x.chain());
+        assert_eq!(thread.finished(), None);
+        assert!(thread.submit(&picture));
+        // Still out, so the next picture is dropped.
+        assert!(!thread.submit(&picture));
+        assert_eq!(thread.wait(), Some(&expected));
+        assert_eq!(thread.finished(), None);
+
+        thread.set_filters(FilterChain::new());
+        assert!(thread.submit(&picture));
+        assert_eq!(thread.wait(), Some(&picture));
+    }
+}
@@ -0,0 +1,242 @@
+//! Fits the 256x240 picture into a window of any size.
+//! Reference: https://www.nesdev.org/wiki/Overscan#Pixel_aspect_ratio
+//!
+//! On an NTSC television NES pixels are 8/7 as wide as they are tall. Square pixels are the
+//! default because they keep pixel art crisp with nearest-neighbor scaling.
+//!
+//! Televisions hid a few lines and columns around the edge of the picture behind the bezel, and
+//! some games leave garbage there. `Overscan` crops them off; nothing is cropped by default.
+
+use macroquad::math::Rect;
+use serde::{Deserialize, Serialize};
+
+use crate::render::constants::*;
+use crate::render::filters::Picture;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VideoSettings {
+    // Window size as a multiple of the NES resolution.
+    pub scale: u32,
+    // Only scale the picture by whole multiples, leaving a border rather than uneven pixels.
+    pub integer_scaling: bool,
+    // Nearest-neighbor filtering keeps pixels sharp; turning it off blurs them bilinearly.
+    pub nearest_filter: bool,
+    // Borderless fullscreen at the desktop resolution. miniquad cannot change the display mode, so
+    // there is no exclusive fullscreen; the picture is letterboxed like in a window.
+    pub fullscreen: bool,
+    pub aspect: AspectRatio,
+    pub overscan: Overscan,
+}
+
+// NES pixels cropped off each edge of the picture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Overscan {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+// The most that can be cropped off one edge.
+pub const MAX_OVERSCAN: u32 = 64;
+
+impl Overscan {
+    pub fn clamped(&self) -> Overscan {
+        Overscan {
+            top: self.top.min(MAX_OVERSCAN),
+            bottom: self.bottom.min(MAX_OVERSCAN),
+            left: self.left.min(MAX_OVERSCAN),
+            right: self.right.min(MAX_OVERSCAN),
+        }
+    }
+
+    // The size of the picture left after cropping.
+    pub fn visible_size(&self) -> (u32, u32) {
+        (
+            NES_PIXEL_WIDTH as u32 - self.left - self.right,
+            NES_PIXEL_HEIGHT as u32 - self.top - self.bottom,
+        )
+    }
+
+    // Crops a 256x240 picture.
+    pub fn crop(&self, picture: &mut Picture) {
+        if *self != Overscan::default() {
+            let (width, height) = self.visible_size();
+            picture.crop(self.left as usize, self.top as usize, width as usize, height as usize);
+        }
+    }
+}
+
+// Shape of one NES pixel on screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AspectRatio {
+    Square,
+    // 8:7, as on an NTSC CRT.
+    Ntsc,
+    // Pixel width divided by pixel height.
+    Custom(f32),
+}
+
+impl AspectRatio {
+    pub fn pixel_aspect(&self) -> f32 {
+        match self {
+            AspectRatio::Square => 1.0,
+            AspectRatio::Ntsc => 8.0 / 7.0,
+            AspectRatio::Custom(ratio) => *ratio,
+        }
+    }
+
+    // Cycles between square and 8:7 pixels.
+    pub fn next(&self) -> AspectRatio {
+        match self {
+            AspectRatio::Square => AspectRatio::Ntsc,
+            _ => AspectRatio::Square,
+        }
+    }
+}
+
+impl std::str::FromStr for AspectRatio {
+    type Err = String;
+
+    // Parses "square", "8:7", or any "w:h" or decimal ratio.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ratio = match s {
+            "square" | "1:1" => return Ok(AspectRatio::Square),
+            "ntsc" | "8:7" => return Ok(AspectRatio::Ntsc),
+            _ => match s.split_once(':') {
+                Some((w, h)) => w.parse::<f32>().ok().zip(h.parse::<f32>().ok()).map(|(w, h)| w / h),
+                None => s.parse().ok(),
+            },
+        };
+        match ratio {
+            Some(ratio) if ratio.is_finite() && ratio > 0.0 => Ok(AspectRatio::Custom(ratio)),
+            _ => Err(format!("Invalid aspect ratio \"{}\"", s)),
+        }
+    }
+}
+
+// Writes the ratio the way `from_str` reads it.
+impl std::fmt::Display for AspectRatio {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AspectRatio::Square => f.write_str("square"),
+            AspectRatio::Ntsc => f.write_str("8:7"),
+            AspectRatio::Custom(ratio) => write!(f, "{}", ratio),
+        }
+    }
+}
+
+pub const MIN_SCALE: u32 = 1;
+pub const MAX_SCALE: u32 = 8;
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        VideoSettings {
+            scale: PIXEL_RATIO as u32,
+            integer_scaling: false,
+            nearest_filter: true,
+            fullscreen: false,
+            aspect: AspectRatio::Square,
+            overscan: Overscan::default(),
+        }
+    }
+}
+
+impl VideoSettings {
+    pub fn window_size(&self) -> (f32, f32) {
+        let (width, height) = self.overscan.visible_size();
+        (
+            (width as f32 * self.aspect.pixel_aspect() * self.scale as f32).round(),
+            (height * self.scale) as f32,
+        )
+    }
+}
+
+// Returns the largest rectangle with the picture's aspect ratio that fits in the window, centered.
+// Integer scaling applies to the height; with non-square pixels the width can't be whole multiples.
+pub fn dest_rect(window_width: f32, window_height: f32, video: &VideoSettings) -> Rect {
+    let (visible_width, visible_height) = video.overscan.visible_size();
+    let picture_width = visible_width as f32 * video.aspect.pixel_aspect();
+    let mut scale = (window_width / picture_width).min(window_height / visible_height as f32);
+    if video.integer_scaling && scale >= 1.0 {
+        scale = scale.floor();
+    }
+
+    let width = picture_width * scale;
+    let height = visible_height as f32 * scale;
+    Rect::new(
+        ((window_width - width) / 2.0).floor(),
+        ((window_height - height) / 2.0).floor(),
+        width,
+        height,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(integer_scaling: bool, aspect: AspectRatio) -> VideoSettings {
+        VideoSettings {
+            integer_scaling,
+            aspect,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_dest_rect_letterboxes() {
+        let square = video(false, AspectRatio::Square);
+        assert_eq!(dest_rect(1024.0, 960.0, &square), Rect::new(0.0, 0.0, 1024.0, 960.0));
+        // A wide window gets bars on the sides.
+        assert_eq!(dest_rect(1000.0, 480.0, &square), Rect::new(244.0, 0.0, 512.0, 480.0));
+    }
+
+    #[test]
+    fn test_dest_rect_integer_scaling() {
+        let square = video(false, AspectRatio::Square);
+        let integer = video(true, AspectRatio::Square);
+        assert_eq!(dest_rect(800.0, 800.0, &square).w, 800.0);
+        assert_eq!(dest_rect(800.0, 800.0, &integer), Rect::new(16.0, 40.0, 768.0, 720.0));
+        // Windows smaller than 1x still show the whole picture.
+        assert_eq!(dest_rect(128.0, 120.0, &integer).w, 128.0);
+    }
+
+    #[test]
+    fn test_ntsc_pixel_aspect() {
+        let ntsc = video(false, AspectRatio::Ntsc);
+        // 256 * 8/7 = 292.57 pixels wide per 240 high.
+        let rect = dest_rect(2000.0, 480.0, &ntsc);
+        assert_eq!(rect.h, 480.0);
+        assert!((rect.w - 585.142_9).abs() < 0.01);
+
+        let ntsc_integer = video(true, AspectRatio::Ntsc);
+        assert_eq!(dest_rect(700.0, 1000.0, &ntsc_integer).h, 480.0);
+        assert_eq!(ntsc.window_size(), (1170.0, 960.0));
+    }
+
+    #[test]
+    fn test_overscan_crops_the_picture() {
+        let video = VideoSettings {
+            overscan: Overscan { top: 8, bottom: 8, left: 0, right: 0 },
+            ..Default::default()
+        };
+        assert_eq!(video.window_size(), (1024.0, 896.0));
+        assert_eq!(dest_rect(512.0, 448.0, &video), Rect::new(0.0, 0.0, 512.0, 448.0));
+
+        let mut picture = Picture::new(NES_PIXEL_WIDTH as usize, NES_PIXEL_HEIGHT as usize);
+        picture.set_pixel(0, 8, [1, 2, 3, 4]);
+        video.overscan.crop(&mut picture);
+        assert_eq!((picture.width, picture.height, picture.pixel(0, 0)), (256, 224, [1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_parse_aspect_ratio() {
+        assert_eq!("8:7".parse(), Ok(AspectRatio::Ntsc));
+        assert_eq!("square".parse(), Ok(AspectRatio::Square));
+        assert_eq!("4:3".parse(), Ok(AspectRatio::Custom(4.0 / 3.0)));
+        assert_eq!("1.25".parse(), Ok(AspectRatio::Custom(1.25)));
+        assert!("0:1".parse::<AspectRatio>().is_err());
+    }
+}
@@ -0,0 +1,194 @@
+//! TAS editor window (--tas <movie>): a piano roll over `nes_rs_core::movie::editor`.
+//!
+//! Each row is a frame and each column a button of controller 1 or 2 (and 3 and 4 with a Four
+//! Score). Clicking a button toggles it on that frame, and clicking a frame number seeks there.
+//! Frame numbers are marked '>' at the cursor, '+' where the greenzone reaches and '*' where there
+//! is a marker. The movie plays with the usual P and \ hotkeys, taking controller input only while
+//! "Record" is ticked.
+
+use macroquad::prelude::*;
+use macroquad::ui::{hash, root_ui, widgets};
+
+use crate::emulator::Emulator;
+use crate::joypad::JoypadButton;
+use crate::movie::editor::{Editor, BRANCHES};
+use crate::movie::FrameInput;
+use crate::render::osd::Osd;
+
+// Frames listed at once.
+const ROWS: usize = 20;
+const SIZE: (f32, f32) = (460.0, 640.0);
+const POSITION: (f32, f32) = (40.0, 40.0);
+// Frames inserted or deleted by one click.
+const EDIT_FRAMES: usize = 1;
+// Columns, in the order TAS editors usually show them.
+const COLUMNS: [(JoypadButton, char); 8] = [
+    (JoypadButton::BUTTON_A, 'A'),
+    (JoypadButton::BUTTON_B, 'B'),
+    (JoypadButton::SELECT, 'S'),
+    (JoypadButton::START, 'T'),
+    (JoypadButton::UP, 'U'),
+    (JoypadButton::DOWN, 'D'),
+    (JoypadButton::LEFT, 'L'),
+    (JoypadButton::RIGHT, 'R'),
+];
+
+enum Action {
+    Seek(usize),
+    Toggle(usize, usize, JoypadButton),
+    Insert,
+    Delete,
+    Marker,
+    Scroll(isize),
+    SaveBranch(usize),
+    LoadBranch(usize),
+    Save,
+}
+
+pub struct TasWindow {
+    pub path: String,
+    pub editor: Editor,
+    // Whether controller input replaces the movie's on the frames that run.
+    pub recording: bool,
+    // First frame listed, or None to keep the cursor in the middle.
+    scroll: Option<usize>,
+}
+
+impl TasWindow {
+    pub fn open(path: &str, emulator: &mut Emulator) -> Result<Self, String> {
+        Ok(TasWindow {
+            path: path.to_string(),
+            editor: Editor::open(emulator, path)?,
+            recording: false,
+            scroll: None,
+        })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        self.editor.save(&self.path)
+    }
+
+    // Runs the frame at the cursor in place of the normal frame, with `held` if recording.
+    pub fn advance(&mut self, emulator: &mut Emulator, held: FrameInput) -> Result<(), String> {
+        self.editor.advance(emulator, self.recording.then_some(held))
+    }
+
+    // Draws the piano roll and applies what was clicked. Call once per host frame.
+    pub fn show(&mut self, emulator: &mut Emulator, osd: &mut Osd) {
+        let editor = &self.editor;
+        let cursor = editor.frame();
+        let ports = if editor.movie().four_score { 4 } else { 2 };
+        let first = self.scroll.unwrap_or(cursor.saturating_sub(ROWS / 2));
+        let mut recording = self.recording;
+        let mut action = None;
+        widgets::Window::new(hash!(), vec2(POSITION.0, POSITION.1), vec2(SIZE.0, SIZE.1))
+            .label("TAS editor")
+            .ui(&mut root_ui(), |ui| {
+                ui.label(None, &format!("Frame {} of {}", cursor, editor.len()));
+                ui.same_line(0.0);
+                ui.checkbox(hash!(), "Record", &mut recording);
+                ui.same_line(0.0);
+                if ui.button(None, "Save") {
+                    action = Some(Action::Save);
+                }
+                for (label, edit) in [("Insert", Action::Insert), ("Delete", Action::Delete), ("Marker", Action::Marker)] {
+                    if ui.button(None, label) {
+                        action = Some(edit);
+                    }
+                    ui.same_line(0.0);
+                }
+                let markers = [("Prev marker", editor.previous_marker()), ("Next marker", editor.next_marker())];
+                for (label, marker) in markers {
+                    if ui.button(None, label) {
+                        action = marker.map(Action::Seek);
+                    }
+                    ui.same_line(0.0);
+                }
+                ui.label(None, editor.markers().get(&cursor).map_or("", String::as_str));
+
+                for (label, rows) in [("^^", -(ROWS as isize)), ("^", -1), ("v", 1), ("vv", ROWS as isize)] {
+                    if ui.button(None, label) {
+                        action = Some(Action::Scroll(rows));
+                    }
+                    ui.same_line(0.0);
+                }
+                if ui.button(None, "Follow") {
+                    action = Some(Action::Scroll(0));
+                }
+                ui.separator();
+                for frame in first..first + ROWS {
+                    let mark = match (frame == cursor, editor.is_green(frame)) {
+                        (true, _) => '>',
+                        (false, true) => '+',
+                        (false, false) => ' ',
+                    };
+                    let marker = if editor.markers().contains_key(&frame) { '*' } else { ' ' };
+                    if ui.button(None, format!("{}{:>6}{}", mark, frame, marker).as_str()) {
+                        action = Some(Action::Seek(frame));
+                    }
+                    let input = editor.input(frame);
+                    for (port, buttons) in input.iter().enumerate().take(ports) {
+                        for (i, (button, letter)) in COLUMNS.into_iter().enumerate() {
+                            ui.same_line(0.0);
+                            let pressed = if buttons.contains(button) { letter } else { '.' };
+                            // A gap between controllers.
+                            let label = if i == 0 && port > 0 { format!(" {}", pressed) } else { pressed.to_string() };
+                            if ui.button(None, label.as_str()) {
+                                action = Some(Action::Toggle(frame, port, button));
+                            }
+                        }
+                    }
+                }
+                ui.separator();
+                ui.label(None, "Branches: save to / load from");
+                for (save, row) in [(true, "Save"), (false, "Load")] {
+                    ui.label(None, row);
+                    for slot in 0..BRANCHES {
+                        ui.same_line(0.0);
+                        let label = match (save, self.editor.branch(slot)) {
+                            (false, None) => "-".to_string(),
+                            _ => slot.to_string(),
+                        };
+                        if ui.button(None, label.as_str()) {
+                            action = Some(if save { Action::SaveBranch(slot) } else { Action::LoadBranch(slot) });
+                        }
+                    }
+                }
+            });
+        self.recording = recording;
+
+        let editor = &mut self.editor;
+        let result = match action {
+            None => Ok(()),
+            Some(Action::Seek(frame)) => editor.seek(emulator, frame),
+            Some(Action::Toggle(frame, port, button)) => editor.toggle(emulator, frame, port, button),
+            Some(Action::Insert) => editor.insert_frames(emulator, cursor, EDIT_FRAMES),
+            Some(Action::Delete) => editor.delete_frames(emulator, cursor, EDIT_FRAMES),
+            Some(Action::Marker) => {
+                match editor.markers().contains_key(&cursor) {
+                    true => editor.remove_marker(cursor),
+                    false => editor.set_marker(cursor, &format!("Frame {}", cursor)),
+                }
+                Ok(())
+            }
+            Some(Action::Scroll(0)) => {
+                self.scroll = None;
+                Ok(())
+            }
+            Some(Action::Scroll(rows)) => {
+                self.scroll = Some(first.saturating_add_signed(rows));
+                Ok(())
+            }
+            Some(Action::SaveBranch(slot)) => {
+                editor.save_branch(slot);
+                osd.post(format!("Saved branch {}", slot));
+                Ok(())
+            }
+            Some(Action::LoadBranch(slot)) => editor.load_branch(emulator, slot),
+            Some(Action::Save) => self.save().map(|()| osd.post(format!("Saved {}", self.path))),
+        };
+        if let Err(e) = result {
+            osd.post(e);
+        }
+    }
+}
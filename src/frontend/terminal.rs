@@ -0,0 +1,229 @@
+//! Terminal frontend (the `terminal` cargo feature): draws frames with Unicode half blocks and
+//! 24-bit ANSI colors, and reads the keyboard through crossterm.
+//!
+//! Each character cell shows two pixels: '▀' in the top pixel's color over a background in the
+//! bottom pixel's color. The picture is shrunk to fit the terminal.
+
+use crate::render::filters::Picture;
+
+// Builds the escape sequences that draw `picture` into a `columns` x `rows` area at the top left
+// of the terminal, keeping the aspect ratio.
+pub fn render_half_blocks(picture: &Picture, columns: usize, rows: usize) -> String {
+    let scale = (columns as f32 / picture.width as f32).min(rows as f32 * 2.0 / picture.height as f32);
+    let out_columns = (picture.width as f32 * scale) as usize;
+    let out_rows = (picture.height as f32 * scale / 2.0) as usize;
+
+    let sample = |x: usize, y: usize| {
+        let source_x = ((x as f32 / scale) as usize).min(picture.width - 1);
+        let source_y = ((y as f32 / scale) as usize).min(picture.height - 1);
+        picture.pixel(source_x, source_y)
+    };
+
+    let mut out = String::new();
+    for row in 0..out_rows {
+        out.push_str(&format!("\x1b[{};1H", row + 1));
+        // Only emit a color when it changes from the previous cell.
+        let mut last: Option<([u8; 4], [u8; 4])> = None;
+        for column in 0..out_columns {
+            let top = sample(column, 2 * row);
+            let bottom = sample(column, 2 * row + 1);
+            if last.map(|(fg, _)| fg) != Some(top) {
+                out.push_str(&format!("\x1b[38;2;{};{};{}m", top[0], top[1], top[2]));
+            }
+            if last.map(|(_, bg)| bg) != Some(bottom) {
+                out.push_str(&format!("\x1b[48;2;{};{};{}m", bottom[0], bottom[1], bottom[2]));
+            }
+            out.push('▀');
+            last = Some((top, bottom));
+        }
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+#[cfg(feature = "terminal")]
+pub use app::run;
+
+#[cfg(feature = "terminal")]
+mod app {
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    use crossterm::event::{
+        self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    };
+    use crossterm::{cursor, execute, terminal};
+
+    use super::render_half_blocks;
+    use crate::emulator::Emulator;
+    use crate::frontend::timing::FrameTimer;
+    use crate::joypad::controller::HostInput;
+    use crate::joypad::JoypadButton;
+    use crate::render::filters::Picture;
+    use crate::render::constants::*;
+
+    // Most terminals only report key presses (and auto-repeat), never releases, so a button stays
+    // held for this many frames after its key was last reported.
+    const HOLD_FRAMES: u32 = 8;
+
+    fn joypad_button(key: KeyCode) -> Option<(JoypadButton, bool)> {
+        let button = match key {
+            KeyCode::Down => JoypadButton::DOWN,
+            KeyCode::Up => JoypadButton::UP,
+            KeyCode::Right => JoypadButton::RIGHT,
+            KeyCode::Left => JoypadButton::LEFT,
+            KeyCode::Char(' ') => JoypadButton::SELECT,
+            KeyCode::Char('q') => JoypadButton::START,
+            KeyCode::Char('a') => JoypadButton::BUTTON_A,
+            KeyCode::Char('s') => JoypadButton::BUTTON_B,
+            KeyCode::Char('z') => return Some((JoypadButton::BUTTON_A, true)),
+            KeyCode::Char('x') => return Some((JoypadButton::BUTTON_B, true)),
+            _ => return None,
+        };
+        Some((button, false))
+    }
+
+    #[derive(Default)]
+    struct Keyboard {
+        // Frames left for each held button, with the turbo flag.
+        held: Vec<(JoypadButton, bool, u32)>,
+        // With release events, keys stay held until released instead of timing out.
+        reports_release: bool,
+    }
+
+    impl Keyboard {
+        fn handle(&mut self, key: KeyEvent) {
+            let Some((button, turbo)) = joypad_button(key.code) else {
+                return;
+            };
+            self.held.retain(|(b, t, _)| (*b, *t) != (button, turbo));
+            if key.kind != KeyEventKind::Release {
+                let frames = if self.reports_release { u32::MAX } else { HOLD_FRAMES };
+                self.held.push((button, turbo, frames));
+            }
+        }
+
+        // Returns the held buttons and turbo buttons for this frame.
+        fn next_frame(&mut self) -> (JoypadButton, JoypadButton) {
+            let mut buttons = (JoypadButton::empty(), JoypadButton::empty());
+            for (button, turbo, frames) in self.held.iter_mut() {
+                if *turbo {
+                    buttons.1 |= *button;
+                } else {
+                    buttons.0 |= *button;
+                }
+                *frames = frames.saturating_sub(1);
+            }
+            self.held.retain(|(_, _, frames)| *frames > 0);
+            buttons
+        }
+    }
+
+    // Takes over the terminal and runs `emulator` until Esc or Ctrl+C.
+    pub fn run(emulator: Emulator) -> Result<(), String> {
+        let mut stdout = std::io::stdout();
+        terminal::enable_raw_mode().map_err(|e| e.to_string())?;
+        execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide).map_err(|e| e.to_string())?;
+        let reports_release = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_release {
+            let flags = KeyboardEnhancementFlags::REPORT_EVENT_TYPES;
+            execute!(stdout, PushKeyboardEnhancementFlags(flags)).map_err(|e| e.to_string())?;
+        }
+
+        let result = main_loop(emulator, reports_release);
+
+        // Put the terminal back even if the loop failed.
+        if reports_release {
+            let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+        result
+    }
+
+    fn main_loop(mut emulator: Emulator, reports_release: bool) -> Result<(), String> {
+        let mut stdout = std::io::stdout();
+        let mut keyboard = Keyboard {
+            reports_release,
+            ..Default::default()
+        };
+        let mut input = HostInput::new();
+        let mut timer = FrameTimer::new();
+        let mut picture = Picture::new(NES_PIXEL_WIDTH as usize, NES_PIXEL_HEIGHT as usize);
+        let mut last_update = Instant::now();
+
+        loop {
+            while event::poll(Duration::ZERO).map_err(|e| e.to_string())? {
+                if let Event::Key(key) = event::read().map_err(|e| e.to_string())? {
+                    let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                    if key.code == KeyCode::Esc || ctrl_c {
+                        return Ok(());
+                    }
+                    if key.code == KeyCode::Char('p') && key.kind == KeyEventKind::Press {
+                        timer.toggle_pause();
+                    }
+                    keyboard.handle(key);
+                }
+            }
+
+            let elapsed = last_update.elapsed().as_secs_f64();
+            last_update = Instant::now();
+            let frames = timer.advance(elapsed);
+            for _ in 0..frames {
+                let (held, turbo_held) = keyboard.next_frame();
+                for (port, buttons) in input.poll_with_keyboard(held, turbo_held).into_iter().enumerate() {
+                    emulator.set_buttons(port, buttons);
+                }
+                emulator.skip_render = timer.skip_next_frame();
+                emulator.run_frame();
+            }
+
+            if frames > 0 {
+                // Keep the bottom line for the status bar.
+                let (columns, rows) = terminal::size().map_err(|e| e.to_string())?;
+                let rows = (rows as usize).saturating_sub(1);
+                emulator.frame.write_rgba8(&mut picture.pixels);
+                let mut out = render_half_blocks(&picture, columns as usize, rows);
+                out.push_str(&format!(
+                    "\x1b[{};1H\x1b[2Kframe {}  arrows/A/S/Q/space  P pause  Esc quit",
+                    rows + 1,
+                    emulator.frame_count()
+                ));
+                stdout.write_all(out.as_bytes()).map_err(|e| e.to_string())?;
+                stdout.flush().map_err(|e| e.to_string())?;
+            }
+
+            std::thread::sleep(Duration::from_millis(2));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_blocks_pack_two_rows_per_cell() {
+        let mut picture = Picture::new(2, 2);
+        picture.set_pixel(0, 0, [255, 0, 0, 255]);
+        picture.set_pixel(0, 1, [0, 0, 255, 255]);
+        picture.set_pixel(1, 0, [255, 0, 0, 255]);
+        picture.set_pixel(1, 1, [0, 255, 0, 255]);
+
+        let out = render_half_blocks(&picture, 2, 1);
+        assert_eq!(
+            out,
+            "\x1b[1;1H\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀\x1b[48;2;0;255;0m▀\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_half_blocks_fit_terminal() {
+        let picture = Picture::new(256, 240);
+        // 80 columns limit the width: 80 pixels wide, 75 pixels (37 rows) high.
+        let out = render_half_blocks(&picture, 80, 50);
+        assert_eq!(out.matches("\x1b[0m").count(), 37);
+        assert_eq!(out.matches('▀').count(), 80 * 37);
+    }
+}
@@ -0,0 +1,331 @@
+//! Paces emulation against wall-clock time.
+//! Reference: https://www.nesdev.org/wiki/Cycle_reference_chart
+//!
+//! The NTSC NES does not run at the host's refresh rate, so each displayed host frame runs however
+//! many emulated frames are due since the last one. Speed changes only change how many frames are
+//! due; presentation always happens once per host frame.
+
+pub use nes_rs_core::emulator::NTSC_FRAME_RATE;
+
+// Never run more than this many emulated frames per host frame at normal speed, so a long stall
+// (dragging the window, a breakpoint) doesn't make the emulator try to catch up all at once.
+pub const MAX_FRAMES_PER_UPDATE: u32 = 4;
+
+// With vsync pacing, host refresh rates within this fraction of the NES rate run exactly one
+// emulated frame per host frame.
+pub const VSYNC_TOLERANCE: f64 = 0.01;
+
+// At uncapped speed dozens of frames run per host frame; drawing one in this many is plenty.
+pub const UNCAPPED_FRAME_SKIP: u32 = 15;
+
+// Speed steps for the slow-motion/speed-up hotkeys.
+pub const SPEED_PRESETS: [f64; 7] = [0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 4.0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    // A multiple of the NES frame rate: 0.5 is slow motion, 2.0 is double speed.
+    Scaled(f64),
+    // As fast as the host can go.
+    Uncapped,
+}
+
+impl std::str::FromStr for Speed {
+    type Err = String;
+
+    // Parses "max" or a multiplier such as "2" or "0.5".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "max" {
+            return Ok(Speed::Uncapped);
+        }
+        match s.parse::<f64>() {
+            Ok(multiplier) if multiplier > 0.0 => Ok(Speed::Scaled(multiplier)),
+            _ => Err(format!("Invalid speed \"{}\"", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Speed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Speed::Scaled(multiplier) => write!(f, "{}", multiplier),
+            Speed::Uncapped => f.write_str("max"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FrameTimer {
+    pub frame_rate: f64,
+    pub speed: Speed,
+    // Used instead of `speed` while the fast-forward key is held.
+    pub fast_forward: Speed,
+    pub fast_forwarding: bool,
+    // Lock to the host's refresh when it is close enough to the NES rate, trading a tiny speed
+    // error for perfectly even frame pacing. Otherwise frames are timed to exactly 60.0988 Hz.
+    pub vsync: bool,
+    // While paused no frames are due, except for one after each `step_frame`.
+    pub paused: bool,
+    step_requested: bool,
+    // Frames left undrawn after each drawn one, for hosts too slow to draw every frame. Above
+    // real time more are skipped anyway; see `skip_next_frame`.
+    pub frame_skip: u32,
+    skipped: u32,
+    // Wall-clock seconds not yet spent on an emulated frame.
+    accumulator: f64,
+}
+
+impl Default for FrameTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameTimer {
+    pub fn new() -> Self {
+        FrameTimer {
+            frame_rate: NTSC_FRAME_RATE,
+            speed: Speed::Scaled(1.0),
+            fast_forward: Speed::Uncapped,
+            fast_forwarding: false,
+            vsync: false,
+            paused: false,
+            step_requested: false,
+            frame_skip: 0,
+            skipped: 0,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn current_speed(&self) -> Speed {
+        if self.fast_forwarding {
+            self.fast_forward
+        } else {
+            self.speed
+        }
+    }
+
+    // Moves the base speed one preset slower (`faster == false`) or faster.
+    pub fn step_speed(&mut self, faster: bool) {
+        let current = match self.speed {
+            Speed::Scaled(multiplier) => multiplier,
+            Speed::Uncapped => f64::INFINITY,
+        };
+        let next = if faster {
+            SPEED_PRESETS.iter().find(|preset| **preset > current)
+        } else {
+            SPEED_PRESETS.iter().rev().find(|preset| **preset < current)
+        };
+        if let Some(next) = next {
+            self.speed = Speed::Scaled(*next);
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.step_requested = false;
+    }
+
+    // Pauses (if running) and lets exactly one frame run.
+    pub fn step_frame(&mut self) {
+        self.paused = true;
+        self.step_requested = true;
+    }
+
+    // Adds `elapsed` seconds of wall-clock time and returns the number of emulated frames to run.
+    // Always 0 at uncapped speed, where the caller runs frames for as long as it can afford.
+    pub fn advance(&mut self, elapsed: f64) -> u32 {
+        if self.paused {
+            // Time spent paused is not made up afterwards.
+            self.accumulator = 0.0;
+            return std::mem::take(&mut self.step_requested) as u32;
+        }
+
+        let multiplier = match self.current_speed() {
+            Speed::Scaled(multiplier) => multiplier,
+            Speed::Uncapped => {
+                self.accumulator = 0.0;
+                return 0;
+            }
+        };
+
+        let frame_time = 1.0 / (self.frame_rate * multiplier);
+        if self.vsync && ((elapsed / frame_time) - 1.0).abs() < VSYNC_TOLERANCE {
+            self.accumulator = 0.0;
+            return 1;
+        }
+
+        self.accumulator += elapsed;
+        let frames = (self.accumulator / frame_time).floor();
+        self.accumulator -= frames * frame_time;
+
+        let max_frames = (MAX_FRAMES_PER_UPDATE as f64 * multiplier).ceil();
+        if frames > max_frames {
+            self.accumulator = 0.0;
+            return max_frames as u32;
+        }
+        frames as u32
+    }
+}
+
+impl FrameTimer {
+    // Whether the next emulated frame can go undrawn (see `Emulator::skip_render`). Call once per
+    // emulated frame. At n times real time only about one frame in n can be shown, so n - 1 of
+    // every n are skipped, or `frame_skip` if that is more. A frame run while paused is always
+    // drawn, so frame advance shows every frame.
+    pub fn skip_next_frame(&mut self) -> bool {
+        if self.paused {
+            self.skipped = 0;
+            return false;
+        }
+        let skip = match self.current_speed() {
+            Speed::Scaled(multiplier) => multiplier.ceil() as u32 - 1,
+            Speed::Uncapped => UNCAPPED_FRAME_SKIP,
+        };
+        if self.skipped < skip.max(self.frame_skip) {
+            self.skipped += 1;
+            true
+        } else {
+            self.skipped = 0;
+            false
+        }
+    }
+}
+
+// Measures emulated frames per second, averaged over about half a second.
+#[derive(Debug, Clone, Default)]
+pub struct FpsCounter {
+    frames: u32,
+    elapsed: f64,
+    fps: f64,
+}
+
+impl FpsCounter {
+    const WINDOW: f64 = 0.5;
+
+    pub fn new() -> Self {
+        FpsCounter::default()
+    }
+
+    // Records that `frames` emulated frames ran in `elapsed` seconds of wall-clock time.
+    pub fn add(&mut self, frames: u32, elapsed: f64) {
+        self.frames += frames;
+        self.elapsed += elapsed;
+        if self.elapsed >= Self::WINDOW {
+            self.fps = self.frames as f64 / self.elapsed;
+            self.frames = 0;
+            self.elapsed = 0.0;
+        }
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_ntsc_rate_over_time() {
+        let mut timer = FrameTimer::new();
+        // One second of 144 Hz host frames.
+        let frames: u32 = (0..144).map(|_| timer.advance(1.0 / 144.0)).sum();
+        assert_eq!(frames, 60);
+        // The leftover 0.0988 frames carry over.
+        let frames: u32 = (0..144 * 9).map(|_| timer.advance(1.0 / 144.0)).sum();
+        assert_eq!(frames, 540);
+    }
+
+    #[test]
+    fn test_caps_catch_up() {
+        let mut timer = FrameTimer::new();
+        assert_eq!(timer.advance(2.0), MAX_FRAMES_PER_UPDATE);
+        assert_eq!(timer.advance(0.0), 0);
+    }
+
+    #[test]
+    fn test_speed_scales_frames() {
+        let mut timer = FrameTimer::new();
+        timer.speed = Speed::Scaled(0.5);
+        let frames: u32 = (0..120).map(|_| timer.advance(1.0 / 60.0)).sum();
+        assert_eq!(frames, 60);
+
+        timer.fast_forward = Speed::Scaled(3.0);
+        timer.fast_forwarding = true;
+        let frames: u32 = (0..60).map(|_| timer.advance(1.0 / 60.0)).sum();
+        assert_eq!(frames, 180);
+
+        timer.fast_forward = Speed::Uncapped;
+        assert_eq!(timer.advance(1.0 / 60.0), 0);
+    }
+
+    #[test]
+    fn test_vsync_locks_to_host_refresh() {
+        let mut timer = FrameTimer::new();
+        timer.vsync = true;
+        // A 60 Hz display runs one frame per refresh, where exact pacing would drop one every ~10s.
+        let frames: u32 = (0..600).map(|_| timer.advance(1.0 / 60.0)).sum();
+        assert_eq!(frames, 600);
+        // Other refresh rates fall back to exact timing.
+        assert_eq!(timer.advance(1.0 / 30.0), 2);
+    }
+
+    #[test]
+    fn test_pause_and_frame_advance() {
+        let mut timer = FrameTimer::new();
+        timer.toggle_pause();
+        assert_eq!(timer.advance(1.0), 0);
+
+        timer.step_frame();
+        assert_eq!(timer.advance(0.0), 1);
+        assert_eq!(timer.advance(1.0 / 60.0), 0);
+
+        timer.toggle_pause();
+        assert!(!timer.paused);
+        assert_eq!(timer.advance(1.0 / 60.0), 1);
+    }
+
+    #[test]
+    fn test_frame_skip_follows_speed() {
+        let mut timer = FrameTimer::new();
+        let drawn = |timer: &mut FrameTimer| (0..60).filter(|_| !timer.skip_next_frame()).count();
+        assert_eq!(drawn(&mut timer), 60);
+
+        timer.frame_skip = 1;
+        assert_eq!(drawn(&mut timer), 30);
+        // 4x draws one frame in four, more than `frame_skip` asks for.
+        timer.fast_forward = Speed::Scaled(4.0);
+        timer.fast_forwarding = true;
+        assert_eq!(drawn(&mut timer), 15);
+        timer.fast_forward = Speed::Uncapped;
+        assert_eq!(drawn(&mut timer), 60 / (UNCAPPED_FRAME_SKIP as usize + 1));
+
+        timer.step_frame();
+        assert!(!timer.skip_next_frame());
+    }
+
+    #[test]
+    fn test_fps_counter_averages() {
+        let mut counter = FpsCounter::new();
+        // 120 Hz host frames, one emulated frame every other host frame.
+        for i in 0..80 {
+            counter.add(i % 2, 1.0 / 120.0);
+        }
+        assert!((counter.fps() - 60.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_speed_presets_and_parsing() {
+        let mut timer = FrameTimer::new();
+        timer.step_speed(false);
+        assert_eq!(timer.speed, Speed::Scaled(0.75));
+        timer.speed = Speed::Scaled(4.0);
+        timer.step_speed(true);
+        assert_eq!(timer.speed, Speed::Scaled(4.0));
+
+        assert_eq!("max".parse(), Ok(Speed::Uncapped));
+        assert_eq!("2".parse(), Ok(Speed::Scaled(2.0)));
+        assert!("-1".parse::<Speed>().is_err());
+    }
+}
@@ -0,0 +1,72 @@
+//! Browser frontend for the wasm32-unknown-unknown build.
+//!
+//! The desktop main loop reads ROMs, save states and movies from disk and can open a gdb socket,
+//! none of which exist in the browser. This loop only needs what macroquad provides on every
+//! platform: the ROM is fetched from next to the page (or dropped onto the canvas), and the
+//! picture, timing and keyboard go through the same `Frontend` and `HostInput` as the desktop.
+//! macroquad's JS loader (`mq_js_bundle.js`) sets up the canvas and WebAudio. See web/index.html.
+
+use macroquad::prelude::*;
+
+use crate::cartridge::Cartridge;
+use crate::emulator::Emulator;
+use crate::frontend::Frontend;
+use crate::joypad::controller::HostInput;
+
+// Fetched relative to the page on startup. Without it the page waits for a dropped ROM.
+pub const ROM_URL: &str = "rom.nes";
+
+// Bytes of the last file dropped onto the canvas, if any. Browsers hand over the contents rather
+// than a path.
+fn dropped_rom() -> Option<Vec<u8>> {
+    get_dropped_files().into_iter().filter_map(|file| file.bytes).next_back()
+}
+
+async fn first_cartridge() -> Cartridge {
+    if let Ok(cartridge) = load_file(ROM_URL).await.map_err(|e| e.to_string()).and_then(|bytes| Cartridge::new(&bytes)) {
+        return cartridge;
+    }
+    let mut message = format!("Drop a .nes file here ({} was not found)", ROM_URL);
+    loop {
+        if let Some(bytes) = dropped_rom() {
+            match Cartridge::new(&bytes) {
+                Ok(cartridge) => return cartridge,
+                Err(e) => message = e,
+            }
+        }
+        clear_background(BLACK);
+        draw_text(&message, 16.0, screen_height() / 2.0, 24.0, WHITE);
+        next_frame().await;
+    }
+}
+
+// The browser main loop. Controls and video hotkeys are the same as on the desktop.
+pub async fn run() {
+    let mut emulator = Emulator::new(first_cartridge().await);
+    emulator.log_cartridge();
+    let mut frontend = Frontend::new();
+    let mut input = HostInput::new();
+
+    loop {
+        // Dropping another ROM swaps it in and resets the console.
+        if let Some(bytes) = dropped_rom() {
+            match Cartridge::new(&bytes) {
+                Ok(cartridge) => {
+                    emulator.load_cartridge(cartridge);
+                    frontend.osd.post("Loaded ROM");
+                }
+                Err(e) => frontend.osd.post(e),
+            }
+        }
+
+        frontend.handle_hotkeys();
+        // The picture is presented once per host frame rather than per emulated frame.
+        frontend.run_frames(|skip_render| {
+            emulator.skip_render = skip_render;
+            emulator.run_frame_with_host(&mut input, &mut (), &mut ());
+        });
+        frontend.present(&emulator.frame);
+
+        next_frame().await;
+    }
+}
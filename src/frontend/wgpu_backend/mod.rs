@@ -0,0 +1,471 @@
+//! Alternative frontend that draws through wgpu in a winit window (the `wgpu` cargo feature).
+//!
+//! The frame goes through the same filter chain as the macroquad frontend, is uploaded to a
+//! texture and scaled into the letterboxed picture rectangle by a shader, so GPU-side effects can
+//! be added to `shader.wgsl`. Only the keyboard and gamepads are supported; movies, the Zapper and
+//! screenshots need the default frontend.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use winit::application::ApplicationHandler;
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window::{Window, WindowId};
+
+use crate::emulator::Emulator;
+use crate::frontend::scaling::{dest_rect, VideoSettings};
+use crate::frontend::timing::{FrameTimer, Speed};
+use crate::joypad::controller::HostInput;
+use crate::joypad::JoypadButton;
+use crate::render::constants::*;
+use crate::render::filters::{FilterChain, FilterPreset, Picture};
+
+lazy_static! {
+    // Same layout as `joypad::controller::KEY_MAP`.
+    static ref KEY_MAP: HashMap<KeyCode, JoypadButton> = {
+        let mut key_map = HashMap::new();
+        key_map.insert(KeyCode::ArrowDown, JoypadButton::DOWN);
+        key_map.insert(KeyCode::ArrowUp, JoypadButton::UP);
+        key_map.insert(KeyCode::ArrowRight, JoypadButton::RIGHT);
+        key_map.insert(KeyCode::ArrowLeft, JoypadButton::LEFT);
+        key_map.insert(KeyCode::Space, JoypadButton::SELECT);
+        key_map.insert(KeyCode::KeyQ, JoypadButton::START);
+        key_map.insert(KeyCode::KeyA, JoypadButton::BUTTON_A);
+        key_map.insert(KeyCode::KeyS, JoypadButton::BUTTON_B);
+        key_map
+    };
+
+    static ref TURBO_KEY_MAP: HashMap<KeyCode, JoypadButton> = {
+        let mut key_map = HashMap::new();
+        key_map.insert(KeyCode::KeyZ, JoypadButton::BUTTON_A);
+        key_map.insert(KeyCode::KeyX, JoypadButton::BUTTON_B);
+        key_map
+    };
+}
+
+// Opens a window and runs `emulator` until it is closed.
+pub fn run(emulator: Emulator, video: VideoSettings, filter: FilterPreset) -> Result<(), String> {
+    let event_loop = EventLoop::new().map_err(|e| e.to_string())?;
+    let mut app = App {
+        emulator,
+        video,
+        filters: filter.chain(),
+        filter_preset: filter,
+        picture: Picture::new(NES_PIXEL_WIDTH as usize, NES_PIXEL_HEIGHT as usize),
+        timer: FrameTimer::new(),
+        input: HostInput::new(),
+        keys_held: Vec::new(),
+        last_update: Instant::now(),
+        gpu: None,
+        error: None,
+    };
+    event_loop.run_app(&mut app).map_err(|e| e.to_string())?;
+    app.error.map_or(Ok(()), Err)
+}
+
+struct App {
+    emulator: Emulator,
+    video: VideoSettings,
+    filters: FilterChain,
+    filter_preset: FilterPreset,
+    picture: Picture,
+    timer: FrameTimer,
+    input: HostInput,
+    keys_held: Vec<KeyCode>,
+    last_update: Instant,
+    // Created once the event loop is running.
+    gpu: Option<Gpu>,
+    error: Option<String>,
+}
+
+impl App {
+    fn keys(&self, key_map: &HashMap<KeyCode, JoypadButton>) -> JoypadButton {
+        self.keys_held
+            .iter()
+            .filter_map(|key| key_map.get(key))
+            .fold(JoypadButton::empty(), |held, button| held | *button)
+    }
+
+    fn run_frame(&mut self) {
+        let ports = self.input.poll_with_keyboard(self.keys(&KEY_MAP), self.keys(&TURBO_KEY_MAP));
+        for (port, buttons) in ports.into_iter().enumerate() {
+            self.emulator.set_buttons(port, buttons);
+        }
+        self.emulator.skip_render = self.timer.skip_next_frame();
+        self.emulator.run_frame();
+    }
+
+    // Runs the emulated frames that are due, like `Frontend::run_frames`.
+    fn update(&mut self) {
+        let elapsed = self.last_update.elapsed();
+        self.last_update = Instant::now();
+        match self.timer.current_speed() {
+            Speed::Uncapped if !self.timer.paused => {
+                let start = Instant::now();
+                while start.elapsed() < std::time::Duration::from_millis(12) {
+                    self.run_frame();
+                }
+            }
+            _ => {
+                for _ in 0..self.timer.advance(elapsed.as_secs_f64()) {
+                    self.run_frame();
+                }
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyCode, pressed: bool, repeat: bool) {
+        self.keys_held.retain(|held| *held != key);
+        if pressed {
+            self.keys_held.push(key);
+        }
+        if key == KeyCode::Tab {
+            self.timer.fast_forwarding = pressed;
+        }
+        if !pressed || repeat {
+            return;
+        }
+        match key {
+            KeyCode::KeyP => self.timer.toggle_pause(),
+            KeyCode::Backslash => self.timer.step_frame(),
+            KeyCode::BracketLeft => self.timer.step_speed(false),
+            KeyCode::BracketRight => self.timer.step_speed(true),
+            KeyCode::F8 => {
+                self.filter_preset = self.filter_preset.next();
+                self.filters = self.filter_preset.chain();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.gpu.is_some() {
+            return;
+        }
+        let (width, height) = self.video.window_size();
+        let attributes = Window::default_attributes()
+            .with_title("nes_rs")
+            .with_inner_size(LogicalSize::new(width, height));
+        let gpu = event_loop
+            .create_window(attributes)
+            .map_err(|e| e.to_string())
+            .and_then(|window| pollster::block_on(Gpu::new(Arc::new(window), self.video.nearest_filter)));
+        match gpu {
+            Ok(gpu) => self.gpu = Some(gpu),
+            Err(e) => {
+                self.error = Some(e);
+                event_loop.exit();
+            }
+        }
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::Resized(size) => {
+                if let Some(gpu) = &mut self.gpu {
+                    gpu.resize(size.width, size.height);
+                }
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                if let PhysicalKey::Code(key) = event.physical_key {
+                    self.handle_key(key, event.state == ElementState::Pressed, event.repeat);
+                }
+            }
+            WindowEvent::RedrawRequested => {
+                self.update();
+                self.picture.resize(NES_PIXEL_WIDTH as usize, NES_PIXEL_HEIGHT as usize);
+                self.emulator.frame.write_rgba8(&mut self.picture.pixels);
+                self.video.overscan.crop(&mut self.picture);
+                let output = self.filters.apply(&self.picture);
+                if let Some(gpu) = &mut self.gpu {
+                    if let Err(e) = gpu.render(output, &self.video) {
+                        tracing::warn!("Frame dropped: {}", e);
+                    }
+                    gpu.window.request_redraw();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Gpu {
+    window: Arc<Window>,
+    surface: wgpu::Surface<'static>,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    viewport: wgpu::Buffer,
+    // The frame texture and its bind group, recreated when the filtered picture changes size.
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Gpu {
+    async fn new(window: Arc<Window>, nearest_filter: bool) -> Result<Gpu, String> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let surface = instance.create_surface(window.clone()).map_err(|e| e.to_string())?;
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                compatible_surface: Some(&surface),
+                ..Default::default()
+            })
+            .await
+            .ok_or_else(|| "No suitable GPU adapter".to_string())?;
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor::default(), None)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let size = window.inner_size();
+        let mut config = surface
+            .get_default_config(&adapter, size.width.max(1), size.height.max(1))
+            .ok_or_else(|| "Window surface is not supported by the GPU".to_string())?;
+        config.present_mode = wgpu::PresentMode::AutoVsync;
+        surface.configure(&device, &config);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("frame shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("frame bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("frame pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("frame pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let filter_mode = if nearest_filter {
+            wgpu::FilterMode::Nearest
+        } else {
+            wgpu::FilterMode::Linear
+        };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: filter_mode,
+            min_filter: filter_mode,
+            ..Default::default()
+        });
+        let viewport = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("viewport"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let (texture, bind_group) = create_frame_texture(
+            &device,
+            &bind_group_layout,
+            &sampler,
+            &viewport,
+            NES_PIXEL_WIDTH as u32,
+            NES_PIXEL_HEIGHT as u32,
+        );
+
+        Ok(Gpu {
+            window,
+            surface,
+            device,
+            queue,
+            config,
+            pipeline,
+            bind_group_layout,
+            sampler,
+            viewport,
+            texture,
+            bind_group,
+        })
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.config.width = width;
+            self.config.height = height;
+            self.surface.configure(&self.device, &self.config);
+        }
+    }
+
+    fn render(&mut self, picture: &Picture, video: &VideoSettings) -> Result<(), String> {
+        let (width, height) = (picture.width as u32, picture.height as u32);
+        if (width, height) != (self.texture.width(), self.texture.height()) {
+            (self.texture, self.bind_group) = create_frame_texture(
+                &self.device,
+                &self.bind_group_layout,
+                &self.sampler,
+                &self.viewport,
+                width,
+                height,
+            );
+        }
+
+        let extent = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        self.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &picture.pixels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            extent,
+        );
+
+        // The picture rectangle in clip space: scale is half its size, offset its center.
+        let (surface_width, surface_height) = (self.config.width as f32, self.config.height as f32);
+        let dest = dest_rect(surface_width, surface_height, video);
+        let viewport = [
+            dest.w / surface_width,
+            dest.h / surface_height,
+            (dest.x + dest.w / 2.0) / surface_width * 2.0 - 1.0,
+            1.0 - (dest.y + dest.h / 2.0) / surface_height * 2.0,
+        ];
+        let bytes: Vec<u8> = viewport.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.queue.write_buffer(&self.viewport, 0, &bytes);
+
+        let output = self.surface.get_current_texture().map_err(|e| e.to_string())?;
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("frame pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.draw(0..6, 0..1);
+        }
+        self.queue.submit(Some(encoder.finish()));
+        output.present();
+        Ok(())
+    }
+}
+
+fn create_frame_texture(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    viewport: &wgpu::Buffer,
+    width: u32,
+    height: u32,
+) -> (wgpu::Texture, wgpu::BindGroup) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("frame"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        // Frame colors are sRGB, like the palette they come from.
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("frame bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: viewport.as_entire_binding(),
+            },
+        ],
+    });
+    (texture, bind_group)
+}
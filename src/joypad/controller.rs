@@ -0,0 +1,252 @@
+//! Maps host input (keyboard and gamepads) onto the emulated joypads.
+
+use std::collections::HashMap;
+
+use macroquad::input::{is_key_down, KeyCode};
+
+use crate::config::KeyBindings;
+use crate::host::InputProvider;
+
+use crate::joypad::family_keyboard::Key;
+use crate::joypad::gamepad::{Gamepads, MAX_PADS};
+use crate::joypad::power_pad::BUTTONS;
+use crate::joypad::turbo::Turbo;
+use crate::joypad::JoypadButton;
+
+lazy_static! {
+    pub static ref KEY_MAP: HashMap<KeyCode, JoypadButton> = {
+        let mut key_map = HashMap::new();
+        key_map.insert(KeyCode::Down, JoypadButton::DOWN);
+        key_map.insert(KeyCode::Up, JoypadButton::UP);
+        key_map.insert(KeyCode::Right, JoypadButton::RIGHT);
+        key_map.insert(KeyCode::Left, JoypadButton::LEFT);
+        key_map.insert(KeyCode::Space, JoypadButton::SELECT);
+        key_map.insert(KeyCode::Q, JoypadButton::START);
+        key_map.insert(KeyCode::A, JoypadButton::BUTTON_A);
+        key_map.insert(KeyCode::S, JoypadButton::BUTTON_B);
+        key_map
+    };
+
+    pub static ref TURBO_KEY_MAP: HashMap<KeyCode, JoypadButton> = {
+        let mut key_map = HashMap::new();
+        key_map.insert(KeyCode::Z, JoypadButton::BUTTON_A);
+        key_map.insert(KeyCode::X, JoypadButton::BUTTON_B);
+        key_map
+    };
+
+    // Host keys for the Family BASIC keyboard's, by the labels in `family_keyboard`. The keys
+    // missing from a PC keyboard go where they sit on the Famicom's: ¥ on backslash, @ and : right
+    // of P and L, ^ after -, and STOP on End, KANA on Right Alt, GRPH on Left Alt and _ on Right Ctrl.
+    pub static ref FAMILY_KEYBOARD_MAP: HashMap<KeyCode, Key> = {
+        let keys = [
+            (KeyCode::F1, "F1"), (KeyCode::F2, "F2"), (KeyCode::F3, "F3"), (KeyCode::F4, "F4"),
+            (KeyCode::F5, "F5"), (KeyCode::F6, "F6"), (KeyCode::F7, "F7"), (KeyCode::F8, "F8"),
+            (KeyCode::Key1, "1"), (KeyCode::Key2, "2"), (KeyCode::Key3, "3"), (KeyCode::Key4, "4"),
+            (KeyCode::Key5, "5"), (KeyCode::Key6, "6"), (KeyCode::Key7, "7"), (KeyCode::Key8, "8"),
+            (KeyCode::Key9, "9"), (KeyCode::Key0, "0"), (KeyCode::Minus, "-"), (KeyCode::Equal, "^"),
+            (KeyCode::Backslash, "¥"), (KeyCode::End, "STOP"), (KeyCode::Escape, "ESC"),
+            (KeyCode::Q, "Q"), (KeyCode::W, "W"), (KeyCode::E, "E"), (KeyCode::R, "R"), (KeyCode::T, "T"),
+            (KeyCode::Y, "Y"), (KeyCode::U, "U"), (KeyCode::I, "I"), (KeyCode::O, "O"), (KeyCode::P, "P"),
+            (KeyCode::LeftBracket, "@"), (KeyCode::RightBracket, "["), (KeyCode::Enter, "RETURN"),
+            (KeyCode::KpEnter, "RETURN"), (KeyCode::LeftControl, "CTR"), (KeyCode::A, "A"),
+            (KeyCode::S, "S"), (KeyCode::D, "D"), (KeyCode::F, "F"), (KeyCode::G, "G"), (KeyCode::H, "H"),
+            (KeyCode::J, "J"), (KeyCode::K, "K"), (KeyCode::L, "L"), (KeyCode::Semicolon, ";"),
+            (KeyCode::Apostrophe, ":"), (KeyCode::GraveAccent, "]"), (KeyCode::RightAlt, "KANA"),
+            (KeyCode::LeftShift, "LSHIFT"), (KeyCode::Z, "Z"), (KeyCode::X, "X"), (KeyCode::C, "C"),
+            (KeyCode::V, "V"), (KeyCode::B, "B"), (KeyCode::N, "N"), (KeyCode::M, "M"),
+            (KeyCode::Comma, ","), (KeyCode::Period, "."), (KeyCode::Slash, "/"),
+            (KeyCode::RightControl, "_"), (KeyCode::RightShift, "RSHIFT"), (KeyCode::LeftAlt, "GRPH"),
+            (KeyCode::Space, "SPACE"), (KeyCode::Home, "CLR HOME"), (KeyCode::Insert, "INS"),
+            (KeyCode::Delete, "DEL"), (KeyCode::Backspace, "DEL"), (KeyCode::Up, "UP"),
+            (KeyCode::Down, "DOWN"), (KeyCode::Left, "LEFT"), (KeyCode::Right, "RIGHT"),
+        ];
+        keys.into_iter().map(|(keycode, name)| (keycode, Key::named(name).unwrap())).collect()
+    };
+}
+
+// The Family BASIC keyboard's keys held on the host keyboard.
+pub fn family_keys_held() -> Vec<Key> {
+    FAMILY_KEYBOARD_MAP.iter().filter(|(keycode, _)| is_key_down(**keycode)).map(|(_, key)| *key).collect()
+}
+
+// Every key that can be bound, named as in `KeyCode`'s Debug output.
+const BINDABLE_KEYS: [KeyCode; 81] = [
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G, KeyCode::H,
+    KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N, KeyCode::O, KeyCode::P,
+    KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U, KeyCode::V, KeyCode::W, KeyCode::X,
+    KeyCode::Y, KeyCode::Z, KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9, KeyCode::Up, KeyCode::Down,
+    KeyCode::Left, KeyCode::Right, KeyCode::Space, KeyCode::Enter, KeyCode::Tab, KeyCode::Backspace,
+    KeyCode::Escape, KeyCode::Insert, KeyCode::Delete, KeyCode::Home, KeyCode::End, KeyCode::PageUp,
+    KeyCode::PageDown, KeyCode::LeftShift, KeyCode::RightShift, KeyCode::LeftControl, KeyCode::RightControl,
+    KeyCode::LeftAlt, KeyCode::RightAlt, KeyCode::Comma, KeyCode::Period, KeyCode::Slash, KeyCode::Semicolon,
+    KeyCode::Apostrophe, KeyCode::Minus, KeyCode::Equal, KeyCode::LeftBracket, KeyCode::RightBracket,
+    KeyCode::Backslash, KeyCode::GraveAccent, KeyCode::Kp0, KeyCode::Kp1, KeyCode::Kp2, KeyCode::Kp3,
+    KeyCode::Kp4, KeyCode::Kp5, KeyCode::Kp6, KeyCode::Kp7, KeyCode::Kp8, KeyCode::Kp9, KeyCode::KpEnter,
+    KeyCode::KpSubtract, KeyCode::KpAdd,
+];
+
+// The key called `name` ("A", "Space", "Key1", "LeftShift", ...), ignoring case.
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    BINDABLE_KEYS.into_iter().find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+}
+
+fn keys_held(key_map: &HashMap<KeyCode, JoypadButton>) -> JoypadButton {
+    let mut state = JoypadButton::empty();
+    for (keycode, joypad_button) in key_map.iter() {
+        if is_key_down(*keycode) {
+            state.insert(*joypad_button);
+        }
+    }
+    state
+}
+
+// Collects keyboard and gamepad state into per-port joypad states. The keyboard always drives
+// controller 1.
+pub struct HostInput {
+    pub gamepads: Gamepads,
+    pub turbo: [Turbo; MAX_PADS],
+    // Controller 1's keys, `KEY_MAP` and `TURBO_KEY_MAP` unless rebound.
+    keys: HashMap<KeyCode, JoypadButton>,
+    turbo_keys: HashMap<KeyCode, JoypadButton>,
+    // The Power Pad's buttons 1 to 12, when one is bound.
+    power_pad_keys: Vec<KeyCode>,
+}
+
+impl Default for HostInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HostInput {
+    pub fn new() -> Self {
+        HostInput {
+            gamepads: Gamepads::new(),
+            turbo: Default::default(),
+            keys: KEY_MAP.clone(),
+            turbo_keys: TURBO_KEY_MAP.clone(),
+            power_pad_keys: Vec::new(),
+        }
+    }
+
+    // Replaces controller 1's keys with `bindings`. Nothing changes if a key name is unknown.
+    pub fn bind_keys(&mut self, bindings: &KeyBindings) -> Result<(), String> {
+        let key = |name: &str| key_from_name(name).ok_or_else(|| format!("Unknown key {}", name));
+        let keys = [
+            (&bindings.up, JoypadButton::UP),
+            (&bindings.down, JoypadButton::DOWN),
+            (&bindings.left, JoypadButton::LEFT),
+            (&bindings.right, JoypadButton::RIGHT),
+            (&bindings.select, JoypadButton::SELECT),
+            (&bindings.start, JoypadButton::START),
+            (&bindings.a, JoypadButton::BUTTON_A),
+            (&bindings.b, JoypadButton::BUTTON_B),
+        ];
+        let turbo_keys = [(&bindings.turbo_a, JoypadButton::BUTTON_A), (&bindings.turbo_b, JoypadButton::BUTTON_B)];
+        // A key bound to two buttons presses both.
+        let map = |bound: &[(&String, JoypadButton)]| -> Result<HashMap<KeyCode, JoypadButton>, String> {
+            let mut map: HashMap<KeyCode, JoypadButton> = HashMap::new();
+            for (name, button) in bound {
+                *map.entry(key(name)?).or_insert(JoypadButton::empty()) |= *button;
+            }
+            Ok(map)
+        };
+        let keys = map(&keys)?;
+        self.turbo_keys = map(&turbo_keys)?;
+        self.keys = keys;
+        Ok(())
+    }
+
+    // Binds the Power Pad's buttons 1 to 12 to the keys named in `names`. Nothing changes if a key
+    // name is unknown or there aren't 12.
+    pub fn bind_power_pad(&mut self, names: &[String]) -> Result<(), String> {
+        if names.len() != BUTTONS {
+            return Err(format!("The Power Pad needs {} keys, not {}", BUTTONS, names.len()));
+        }
+        self.power_pad_keys = names
+            .iter()
+            .map(|name| key_from_name(name).ok_or_else(|| format!("Unknown key {}", name)))
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    // The Power Pad buttons held, bit n - 1 for button n.
+    pub fn power_pad_held(&self) -> u16 {
+        self.power_pad_keys.iter().enumerate().filter(|(_, key)| is_key_down(**key)).fold(0, |held, (i, _)| held | 1 << i)
+    }
+
+    // Reads the host devices and returns the joypad state for each port. Call exactly once per
+    // emulated frame so turbo buttons advance with the emulation.
+    pub fn poll(&mut self) -> [JoypadButton; MAX_PADS] {
+        self.poll_with_keyboard(keys_held(&self.keys), keys_held(&self.turbo_keys))
+    }
+
+    // Like `poll`, for frontends that read the keyboard themselves. `keyboard` and
+    // `keyboard_turbo` are the buttons and turbo buttons held on the keyboard.
+    pub fn poll_with_keyboard(
+        &mut self,
+        keyboard: JoypadButton,
+        keyboard_turbo: JoypadButton,
+    ) -> [JoypadButton; MAX_PADS] {
+        self.gamepads.poll();
+
+        let mut ports = [JoypadButton::empty(); MAX_PADS];
+        for (port, state) in ports.iter_mut().enumerate() {
+            let mut held = self.gamepads.state(port);
+            let mut turbo_held = self.gamepads.turbo_state(port);
+            if port == 0 {
+                held |= keyboard;
+                turbo_held |= keyboard_turbo;
+            }
+            *state = self.turbo[port].next_frame(held, turbo_held);
+        }
+        ports
+    }
+}
+
+// The window frontends' input, for `Emulator::run_frame_with_host`.
+impl InputProvider for HostInput {
+    fn buttons(&mut self) -> [JoypadButton; MAX_PADS] {
+        self.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_family_keyboard_key_has_a_host_key() {
+        let keys: std::collections::HashSet<Key> = FAMILY_KEYBOARD_MAP.values().copied().collect();
+        assert_eq!(keys.len(), 72);
+    }
+
+    #[test]
+    fn test_bind_keys() {
+        assert_eq!(key_from_name("space"), Some(KeyCode::Space));
+        assert_eq!(key_from_name("Key1"), Some(KeyCode::Key1));
+        assert_eq!(key_from_name("Hyper"), None);
+
+        let mut input = HostInput::new();
+        let mut bindings = KeyBindings::default();
+        assert!(input.bind_keys(&bindings).is_ok());
+        assert_eq!(input.keys, *KEY_MAP);
+        assert_eq!(input.turbo_keys, *TURBO_KEY_MAP);
+
+        bindings.start = "Enter".to_owned();
+        bindings.a = "Enter".to_owned();
+        input.bind_keys(&bindings).unwrap();
+        assert_eq!(input.keys[&KeyCode::Enter], JoypadButton::START | JoypadButton::BUTTON_A);
+        assert!(!input.keys.contains_key(&KeyCode::Q));
+
+        let power_pad = crate::config::InputConfig::default().power_pad;
+        assert!(input.bind_power_pad(&power_pad).is_ok());
+        assert_eq!(input.power_pad_keys[3], KeyCode::KpSubtract);
+        assert!(input.bind_power_pad(&power_pad[..11]).is_err());
+
+        bindings.b = "Hyper".to_owned();
+        assert_eq!(input.bind_keys(&bindings), Err("Unknown key Hyper".to_owned()));
+        assert_eq!(input.keys[&KeyCode::Enter], JoypadButton::START | JoypadButton::BUTTON_A);
+    }
+}
@@ -0,0 +1,330 @@
+//! Host gamepad support.
+//!
+//! Physical pads are read through gilrs (behind the `gamepad` cargo feature) and translated into
+//! `JoypadButton` states. Pads are assigned to emulated controller ports in the order they are
+//! plugged in, and each port has its own button mapping.
+
+use std::collections::HashMap;
+
+use crate::joypad::JoypadButton;
+
+// Number of emulated controller ports a host pad can drive. Ports 3 and 4 are only read when a
+// Four Score is plugged in.
+pub const MAX_PADS: usize = 4;
+
+// Sticks rarely rest at exactly 0.0, so ignore anything below this magnitude.
+pub const DEFAULT_DEADZONE: f32 = 0.35;
+
+// Host gamepad buttons, named by position (south = bottom face button).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PadButton {
+    South,
+    East,
+    North,
+    West,
+    Select,
+    Start,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PadAxis {
+    LeftStickX,
+    LeftStickY,
+}
+
+// Backend-independent gamepad events. `id` in `Gamepads::handle_event` identifies the host pad.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PadEvent {
+    Connected,
+    Disconnected,
+    ButtonPressed(PadButton),
+    ButtonReleased(PadButton),
+    AxisChanged(PadAxis, f32),
+}
+
+#[derive(Debug, Clone)]
+pub struct GamepadMapping {
+    pub buttons: HashMap<PadButton, JoypadButton>,
+    // Buttons that auto-fire while held (see `joypad::turbo`).
+    pub turbo_buttons: HashMap<PadButton, JoypadButton>,
+    pub deadzone: f32,
+}
+
+impl Default for GamepadMapping {
+    // Positional NES layout: the right face button is A, the bottom one is B. The other two face
+    // buttons are turbo A and turbo B.
+    fn default() -> Self {
+        let mut buttons = HashMap::new();
+        buttons.insert(PadButton::East, JoypadButton::BUTTON_A);
+        buttons.insert(PadButton::South, JoypadButton::BUTTON_B);
+        buttons.insert(PadButton::Select, JoypadButton::SELECT);
+        buttons.insert(PadButton::Start, JoypadButton::START);
+        buttons.insert(PadButton::DPadUp, JoypadButton::UP);
+        buttons.insert(PadButton::DPadDown, JoypadButton::DOWN);
+        buttons.insert(PadButton::DPadLeft, JoypadButton::LEFT);
+        buttons.insert(PadButton::DPadRight, JoypadButton::RIGHT);
+
+        let mut turbo_buttons = HashMap::new();
+        turbo_buttons.insert(PadButton::North, JoypadButton::BUTTON_A);
+        turbo_buttons.insert(PadButton::West, JoypadButton::BUTTON_B);
+
+        GamepadMapping {
+            buttons,
+            turbo_buttons,
+            deadzone: DEFAULT_DEADZONE,
+        }
+    }
+}
+
+// Converts an analog stick position into d-pad directions. Positive y points up.
+pub fn axis_to_dpad(x: f32, y: f32, deadzone: f32) -> JoypadButton {
+    let mut dpad = JoypadButton::empty();
+    dpad.set(JoypadButton::RIGHT, x > deadzone);
+    dpad.set(JoypadButton::LEFT, x < -deadzone);
+    dpad.set(JoypadButton::UP, y > deadzone);
+    dpad.set(JoypadButton::DOWN, y < -deadzone);
+    dpad
+}
+
+#[derive(Debug, Clone)]
+struct PadSlot {
+    id: usize,
+    pressed: JoypadButton,
+    turbo_pressed: JoypadButton,
+    stick: (f32, f32),
+}
+
+pub struct Gamepads {
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<gilrs::Gilrs>,
+    // slots[i] is the host pad driving controller port i.
+    slots: [Option<PadSlot>; MAX_PADS],
+    pub mappings: [GamepadMapping; MAX_PADS],
+}
+
+impl Default for Gamepads {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        #[allow(unused_mut)]
+        let mut gamepads = Gamepads {
+            #[cfg(feature = "gamepad")]
+            gilrs: None,
+            slots: Default::default(),
+            mappings: Default::default(),
+        };
+
+        #[cfg(feature = "gamepad")]
+        match gilrs::Gilrs::new() {
+            Ok(gilrs) => {
+                // Pads plugged in before startup don't generate Connected events.
+                let ids: Vec<usize> = gilrs.gamepads().map(|(id, _)| id.into()).collect();
+                for id in ids {
+                    gamepads.handle_event(id, PadEvent::Connected);
+                }
+                gamepads.gilrs = Some(gilrs);
+            }
+            Err(e) => tracing::warn!("Gamepad support unavailable: {}", e),
+        }
+
+        gamepads
+    }
+
+    // Drains pending host events. Call once per frame.
+    pub fn poll(&mut self) {
+        #[cfg(feature = "gamepad")]
+        if let Some(gilrs) = self.gilrs.as_mut() {
+            let mut events: Vec<(usize, PadEvent)> = Vec::new();
+            while let Some(event) = gilrs.next_event() {
+                if let Some(pad_event) = translate_event(event.event) {
+                    events.push((event.id.into(), pad_event));
+                }
+            }
+            for (id, pad_event) in events {
+                self.handle_event(id, pad_event);
+            }
+        }
+    }
+
+    pub fn handle_event(&mut self, id: usize, event: PadEvent) {
+        if event == PadEvent::Disconnected {
+            if let Some(port) = self.port_of(id) {
+                self.slots[port] = None;
+            }
+            return;
+        }
+
+        let port = match self.port_of(id) {
+            Some(port) => port,
+            // Unknown pads (including ones that just connected) take the first free port.
+            None => match self.slots.iter().position(|slot| slot.is_none()) {
+                Some(port) => {
+                    self.slots[port] = Some(PadSlot {
+                        id,
+                        pressed: JoypadButton::empty(),
+                        turbo_pressed: JoypadButton::empty(),
+                        stick: (0.0, 0.0),
+                    });
+                    port
+                }
+                None => return,
+            },
+        };
+
+        let mapping = &self.mappings[port];
+        let slot = self.slots[port].as_mut().unwrap();
+        match event {
+            PadEvent::ButtonPressed(button) => {
+                if let Some(joypad_button) = mapping.buttons.get(&button) {
+                    slot.pressed.insert(*joypad_button);
+                }
+                if let Some(joypad_button) = mapping.turbo_buttons.get(&button) {
+                    slot.turbo_pressed.insert(*joypad_button);
+                }
+            }
+            PadEvent::ButtonReleased(button) => {
+                if let Some(joypad_button) = mapping.buttons.get(&button) {
+                    slot.pressed.remove(*joypad_button);
+                }
+                if let Some(joypad_button) = mapping.turbo_buttons.get(&button) {
+                    slot.turbo_pressed.remove(*joypad_button);
+                }
+            }
+            PadEvent::AxisChanged(PadAxis::LeftStickX, value) => slot.stick.0 = value,
+            PadEvent::AxisChanged(PadAxis::LeftStickY, value) => slot.stick.1 = value,
+            PadEvent::Connected | PadEvent::Disconnected => {}
+        }
+    }
+
+    // Returns the buttons held on the pad assigned to `port`.
+    pub fn state(&self, port: usize) -> JoypadButton {
+        match self.slots.get(port) {
+            Some(Some(slot)) => {
+                let (x, y) = slot.stick;
+                slot.pressed | axis_to_dpad(x, y, self.mappings[port].deadzone)
+            }
+            _ => JoypadButton::empty(),
+        }
+    }
+
+    // Returns the turbo buttons held on the pad assigned to `port`.
+    pub fn turbo_state(&self, port: usize) -> JoypadButton {
+        match self.slots.get(port) {
+            Some(Some(slot)) => slot.turbo_pressed,
+            _ => JoypadButton::empty(),
+        }
+    }
+
+    pub fn is_connected(&self, port: usize) -> bool {
+        matches!(self.slots.get(port), Some(Some(_)))
+    }
+
+    fn port_of(&self, id: usize) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| matches!(slot, Some(slot) if slot.id == id))
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn translate_event(event: gilrs::EventType) -> Option<PadEvent> {
+    use gilrs::{Axis, Button, EventType};
+
+    let button = |button: Button| match button {
+        Button::South => Some(PadButton::South),
+        Button::East => Some(PadButton::East),
+        Button::North => Some(PadButton::North),
+        Button::West => Some(PadButton::West),
+        Button::Select => Some(PadButton::Select),
+        Button::Start => Some(PadButton::Start),
+        Button::DPadUp => Some(PadButton::DPadUp),
+        Button::DPadDown => Some(PadButton::DPadDown),
+        Button::DPadLeft => Some(PadButton::DPadLeft),
+        Button::DPadRight => Some(PadButton::DPadRight),
+        _ => None,
+    };
+
+    match event {
+        EventType::Connected => Some(PadEvent::Connected),
+        EventType::Disconnected => Some(PadEvent::Disconnected),
+        EventType::ButtonPressed(b, _) => button(b).map(PadEvent::ButtonPressed),
+        EventType::ButtonReleased(b, _) => button(b).map(PadEvent::ButtonReleased),
+        EventType::AxisChanged(Axis::LeftStickX, value, _) => {
+            Some(PadEvent::AxisChanged(PadAxis::LeftStickX, value))
+        }
+        EventType::AxisChanged(Axis::LeftStickY, value, _) => {
+            Some(PadEvent::AxisChanged(PadAxis::LeftStickY, value))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_axis_to_dpad_deadzone() {
+        assert_eq!(axis_to_dpad(0.2, -0.2, 0.35), JoypadButton::empty());
+        assert_eq!(axis_to_dpad(0.9, 0.0, 0.35), JoypadButton::RIGHT);
+        assert_eq!(
+            axis_to_dpad(-0.5, 0.8, 0.35),
+            JoypadButton::LEFT | JoypadButton::UP
+        );
+        assert_eq!(axis_to_dpad(0.0, -1.0, 0.35), JoypadButton::DOWN);
+    }
+
+    #[test]
+    fn test_hot_plug_assigns_ports_in_order() {
+        let mut pads = Gamepads::default();
+        pads.handle_event(7, PadEvent::Connected);
+        pads.handle_event(3, PadEvent::Connected);
+        pads.handle_event(3, PadEvent::ButtonPressed(PadButton::Start));
+        pads.handle_event(7, PadEvent::ButtonPressed(PadButton::East));
+
+        assert_eq!(pads.state(0), JoypadButton::BUTTON_A);
+        assert_eq!(pads.state(1), JoypadButton::START);
+
+        // Unplugging the first pad frees port 0 for the next one.
+        pads.handle_event(7, PadEvent::Disconnected);
+        assert!(!pads.is_connected(0));
+        assert_eq!(pads.state(0), JoypadButton::empty());
+        pads.handle_event(9, PadEvent::Connected);
+        assert!(pads.is_connected(0));
+        assert_eq!(pads.state(1), JoypadButton::START);
+    }
+
+    #[test]
+    fn test_per_pad_mapping_and_release() {
+        let mut pads = Gamepads::default();
+        pads.mappings[0]
+            .buttons
+            .insert(PadButton::South, JoypadButton::BUTTON_A);
+        pads.handle_event(0, PadEvent::ButtonPressed(PadButton::South));
+        pads.handle_event(1, PadEvent::ButtonPressed(PadButton::South));
+        assert_eq!(pads.state(0), JoypadButton::BUTTON_A);
+        assert_eq!(pads.state(1), JoypadButton::BUTTON_B);
+
+        pads.handle_event(0, PadEvent::ButtonReleased(PadButton::South));
+        pads.handle_event(0, PadEvent::AxisChanged(PadAxis::LeftStickX, -1.0));
+        assert_eq!(pads.state(0), JoypadButton::LEFT);
+    }
+
+    #[test]
+    fn test_turbo_buttons_reported_separately() {
+        let mut pads = Gamepads::default();
+        pads.handle_event(0, PadEvent::ButtonPressed(PadButton::North));
+        assert_eq!(pads.state(0), JoypadButton::empty());
+        assert_eq!(pads.turbo_state(0), JoypadButton::BUTTON_A);
+        pads.handle_event(0, PadEvent::ButtonReleased(PadButton::North));
+        assert_eq!(pads.turbo_state(0), JoypadButton::empty());
+    }
+}
@@ -0,0 +1,101 @@
+//! The Famicom's microphone, on controller 2, from the host.
+//!
+//! Holding M always counts as blowing into it. With the `microphone` cargo feature and
+//! `--microphone`, so does sound from the host's default input device louder than a threshold;
+//! the level is the loudest sample since the last frame, from 0 to 1.
+
+#[cfg(feature = "microphone")]
+use std::sync::atomic::{AtomicU32, Ordering};
+#[cfg(feature = "microphone")]
+use std::sync::Arc;
+
+use macroquad::input::{is_key_down, KeyCode};
+
+// Input louder than this counts as blowing, unless --microphone-level says otherwise.
+pub const DEFAULT_LEVEL: f32 = 0.25;
+
+pub struct HostMicrophone {
+    // The open input stream, the loudest sample it has seen since the last frame as f32 bits, and
+    // the level that counts. Non-negative floats order the same as their bits, so `fetch_max` works
+    // on them.
+    #[cfg(feature = "microphone")]
+    stream: Option<(cpal::Stream, Arc<AtomicU32>, f32)>,
+}
+
+impl Default for HostMicrophone {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HostMicrophone {
+    // The M key alone, until `listen` opens the host's microphone.
+    pub fn new() -> Self {
+        HostMicrophone {
+            #[cfg(feature = "microphone")]
+            stream: None,
+        }
+    }
+
+    // Starts listening to the host's default input device, counting anything louder than `level`.
+    #[cfg(feature = "microphone")]
+    pub fn listen(&mut self, level: f32) -> Result<(), String> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+        use cpal::SampleFormat;
+
+        let device = cpal::default_host().default_input_device().ok_or("No microphone found")?;
+        let config = device.default_input_config().map_err(|e| format!("Could not open the microphone: {}", e))?;
+        let peak = Arc::new(AtomicU32::new(0));
+        let stream = match config.sample_format() {
+            SampleFormat::F32 => input_stream::<f32>(&device, &config.into(), peak.clone()),
+            SampleFormat::I16 => input_stream::<i16>(&device, &config.into(), peak.clone()),
+            SampleFormat::U16 => input_stream::<u16>(&device, &config.into(), peak.clone()),
+            format => return Err(format!("The microphone's sample format {} isn't supported", format)),
+        }?;
+        stream.play().map_err(|e| format!("Could not start the microphone: {}", e))?;
+        self.stream = Some((stream, peak, level));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "microphone"))]
+    pub fn listen(&mut self, _level: f32) -> Result<(), String> {
+        Err("This build can't listen to a microphone; rebuild with --features microphone".to_string())
+    }
+
+    // Whether the player is blowing into the microphone. Call once per frame. M only counts when
+    // `key` is set, since it is a letter while typing on the Family BASIC keyboard.
+    pub fn poll(&mut self, key: bool) -> bool {
+        #[cfg(feature = "microphone")]
+        if let Some((_, peak, level)) = &self.stream {
+            if f32::from_bits(peak.swap(0, Ordering::Relaxed)) > *level {
+                return true;
+            }
+        }
+        key && is_key_down(KeyCode::M)
+    }
+}
+
+#[cfg(feature = "microphone")]
+fn input_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    peak: Arc<AtomicU32>,
+) -> Result<cpal::Stream, String>
+where
+    T: cpal::SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    use cpal::traits::DeviceTrait;
+
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                let loudest = data.iter().map(|sample| sample.to_sample::<f32>().abs()).fold(0.0, f32::max);
+                peak.fetch_max(loudest.to_bits(), Ordering::Relaxed);
+            },
+            |e| tracing::warn!("Microphone: {}", e),
+            None,
+        )
+        .map_err(|e| format!("Could not open the microphone: {}", e))
+}
@@ -1,12 +1,13 @@
-pub mod bus;
-pub mod cartridge;
-pub mod cpu;
-pub mod ppu;
-pub mod render;
+//! The desktop and browser frontends. The console itself lives in `nes_rs_core`, re-exported here
+//! so `nes_rs::emulator` and friends keep working.
+
+pub use nes_rs_core::{accuracy, asm, bench, bus, cartridge, cheat, chips, cpu, debugger, disasm, emulator, fds, host, movie, netplay, ppu, region, render, savestate, stream, testrom, vs_system};
+#[cfg(feature = "scripting")]
+pub use nes_rs_core::script;
+
+pub mod config;
+pub mod frontend;
 pub mod joypad;
 
 #[macro_use]
 extern crate lazy_static;
-
-#[macro_use]
-extern crate bitflags;
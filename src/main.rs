@@ -1,46 +1,1178 @@
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
 use macroquad::prelude::*;
-use nes_rs::{bus::Bus, cartridge::Cartridge, cpu::CPU, render::constants::*};
+use nes_rs::bench;
+use nes_rs::cheat::{CheatCode, Cheats};
+use nes_rs::chips;
+use nes_rs::{cartridge::Cartridge, cartridge::Console, emulator::Emulator, frontend::Frontend, movie::Movie, movie::MovieState};
+use nes_rs::config::{self, Controller, GameConfig};
+use nes_rs::fds::disk::{is_disk_image, side_name};
+use nes_rs::fds::load_bios;
+use nes_rs::frontend::launcher::{self, RecentGames};
+use nes_rs::ppu::model::PpuModel;
+use nes_rs::region::{self, Region, RegionSource};
+use nes_rs::debugger::trace::{parse_columns, Tracer, DEFAULT_RING_LINES};
+use nes_rs::debugger::{cdl::CodeDataLog, gdb::GdbServer, profiler::Profiler, symbols::SymbolTable, Debugger};
+use nes_rs::frontend::debug::{DebugView, DebugWindows};
+use nes_rs::frontend::scaling::VideoSettings;
+use nes_rs::frontend::tas::TasWindow;
+use nes_rs::frontend::timing::NTSC_FRAME_RATE;
+use nes_rs::render::filters::{simd, FilterPreset};
+use nes_rs::render::recorder::GifRecorder;
+use nes_rs::render::osd::Osd;
+use nes_rs::render::screenshot::numbered_path;
+use nes_rs::savestate::determinism;
+use nes_rs::savestate::rewind::{Rewind, DEFAULT_INTERVAL, DEFAULT_SECONDS};
+use nes_rs::savestate::run_ahead::RunAhead;
+use nes_rs::savestate::slots::StateSlots;
+use nes_rs::stream::{protocol::FrameEncoding, Event as StreamEvent, Server};
+use nes_rs::testrom::blargg;
+use nes_rs::testrom::{self, find_roms, golden::frame_hash, report, Verdict};
+use nes_rs::joypad::controller::{family_keys_held, HostInput};
+use nes_rs::joypad::microphone::{HostMicrophone, DEFAULT_LEVEL};
+use nes_rs::joypad::{gamepad::MAX_PADS, power_pad::PowerPad, zapper::Zapper, JoypadButton, Port2Device};
+use nes_rs::netplay::{Session, Setup};
+use tracing_subscriber::EnvFilter;
+#[cfg(feature = "scripting")]
+use nes_rs::script::Script;
+
+mod cli;
+use cli::{args, Backend, Command, PlayArgs};
 
 // Pixels are numbered from 0 to (256 * 200 - 256), from left to right, then up to down.
 // Each is identified with an x and y coordinate.
 fn nes_rs() -> Conf {
+    let (width, height) = video_settings().window_size();
     Conf {
         window_title: "nes_rs".to_owned(),
-        window_width: SCREEN_WIDTH,
-        window_height: SCREEN_HEIGHT,
+        window_width: width as i32,
+        window_height: height as i32,
+        window_resizable: true,
+        fullscreen: video_settings().fullscreen,
         ..Default::default()
     }
 }
 
-#[macroquad::main(nes_rs)]
-async fn main() {
+// The window's size and scaling, from --scale, --integer-scaling, --smooth, --fullscreen and
+// --aspect.
+fn video_settings() -> VideoSettings {
+    let args = &args().play;
+    let mut video = VideoSettings::default();
+    if let Some(scale) = args.scale {
+        video.scale = scale;
+    }
+    video.integer_scaling = args.integer_scaling;
+    video.nearest_filter = !args.smooth;
+    video.fullscreen = args.fullscreen;
+    if let Some(aspect) = args.aspect {
+        video.aspect = aspect;
+    }
+    video.overscan = cli::args().config.video.overscan;
+    video
+}
 
-    let bytes: Vec<u8> = std::fs::read("balloon.nes").unwrap();
-    let rom = Cartridge::new(&bytes).unwrap();
+// The game played when none is named on the command line, except in the window, which shows the
+// launcher instead.
+const ROM_PATH: &str = "balloon.nes";
 
-    // let mut frame = Frame::new();
-    
-    let bus = Bus::new(rom);
+fn rom_path() -> &'static str {
+    args().play.rom.as_deref().unwrap_or(ROM_PATH)
+}
 
-    let mut cpu = CPU::new(bus);
+// Loads a cartridge or, for a disk image, the disk with its BIOS.
+fn load_rom(path: &str) -> Cartridge {
+    let bytes: Vec<u8> = std::fs::read(path).unwrap();
+    match is_disk_image(&bytes) {
+        true => Cartridge::fds(&bytes, &load_bios(Path::new(path)).unwrap()).unwrap(),
+        false => Cartridge::new(&bytes).unwrap(),
+    }
+}
+
+// Creates the emulator with the options every backend shares: --blend <off|average|phosphor>
+// blends each frame with the previous one to hide sprite flicker.
+fn new_emulator(rom_path: &str) -> Emulator {
+    let mut emulator = Emulator::new(load_rom(rom_path));
+    emulator.log_cartridge();
+    if let Some(mode) = args().play.blend {
+        emulator.blender.mode = mode;
+    }
+    emulator
+}
 
-    cpu.reset();
+// Where --log's filter comes from when it isn't given.
+const LOG_ENV: &str = "NES_RS_LOG";
 
-    // let minimum_frame_time = 1. / 60.; // 60 FPS
+// Sends what the core logs to stderr, filtered by --log (see `nes_rs_core`'s docs for the targets).
+fn init_logging() {
+    let filter = args().log.clone().or_else(|| std::env::var(LOG_ENV).ok()).unwrap_or_else(|| "warn".to_owned());
+    match EnvFilter::try_new(&filter) {
+        Ok(filter) => tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(std::io::stderr)
+            .with_ansi(std::io::stderr().is_terminal())
+            .init(),
+        Err(e) => {
+            eprintln!("Invalid log filter \"{}\": {}", filter, e);
+            std::process::exit(2);
+        }
+    }
+}
 
-    // let frame_time = get_frame_time();
+fn main() {
+    // There are no command line arguments or files in the browser; see `frontend::web`.
+    if cfg!(target_arch = "wasm32") {
+        macroquad::Window::from_config(nes_rs(), nes_rs::frontend::web::run());
+        return;
+    }
+    init_logging();
+    match &args().command {
+        Some(Command::Blargg(args)) => std::process::exit(run_blargg(args)),
+        Some(Command::TestRoms(args)) => std::process::exit(run_test_roms(args)),
+        Some(Command::Determinism(args)) => std::process::exit(run_determinism(args)),
+        Some(Command::Bench(args)) => return run_bench(args),
+        None => {}
+    }
+    let args = &args().play;
+    if let Some(addr) = &args.serve {
+        run_server(addr, args.serve_encoding);
+        return;
+    }
+    if args.headless {
+        std::process::exit(run_headless(args));
+    }
+    match args.backend {
+        Backend::Wgpu => run_wgpu(),
+        Backend::Terminal => run_terminal(),
+        Backend::Macroquad => macroquad::Window::from_config(nes_rs(), run()),
+    }
+}
 
-    // println!("Frame time: {}ms", frame_time * 1000.);
-    // if frame_time < minimum_frame_time {
-    //     let time_to_sleep = (minimum_frame_time - frame_time) * 1000.;
-    //     println!("Sleep for {}ms", time_to_sleep);
-    //     std::thread::sleep(std::time::Duration::from_millis(time_to_sleep as u64));
-    // }
+// The ROMs named on the command line, with directories searched for .nes files.
+fn rom_list(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut roms = Vec::new();
+    for path in paths {
+        match path.is_dir() {
+            true => roms.extend(find_roms(path).unwrap()),
+            false => roms.push(path.clone()),
+        }
+    }
+    roms
+}
 
+// `nes_rs test-roms` runs every ROM without a window and judges it by its .hash file or its result
+// at $6000 (see `testrom`), printing a line per ROM and writing the results as JSON or JUnit XML.
+// --write-hashes records .hash files for ROMs with nothing to judge them by. Exits with 1 if any
+// ROM failed or couldn't be run.
+fn run_test_roms(args: &cli::TestRomsArgs) -> i32 {
+    let mut results = Vec::new();
+    for rom in &rom_list(&args.roms) {
+        let result = testrom::run_rom(rom, args.frames, args.write_hashes);
+        match &result.verdict {
+            Verdict::Passed => println!("{}: passed", rom.display()),
+            verdict => println!("{}: {}: {}", rom.display(), verdict.name(), verdict.message().replace('\n', "\n    ")),
+        }
+        results.push(result);
+    }
+    let (passed, failed, skipped, errors) = report::counts(&results);
+    println!("{} passed, {} failed, {} skipped, {} errors", passed, failed, skipped, errors);
+
+    if let Some(path) = &args.json {
+        std::fs::write(path, report::to_json(&results)).unwrap_or_else(|e| tracing::error!("{}: {}", path, e));
+    }
+    if let Some(path) = &args.junit {
+        std::fs::write(path, report::to_junit(&results)).unwrap_or_else(|e| tracing::error!("{}: {}", path, e));
+    }
+    (failed + errors > 0) as i32
+}
+
+// Reads a movie in our format, or FCEUX's if the file ends in .fm2.
+fn load_movie(path: &str, cartridge: &Cartridge) -> Movie {
+    if path.ends_with(".fm2") {
+        let text = std::fs::read_to_string(path).unwrap();
+        Movie::from_fm2(&text, cartridge).unwrap()
+    } else {
+        Movie::load(path).unwrap()
+    }
+}
+
+// `nes_rs determinism` runs the ROM twice in lockstep, playing the movie if given, and compares the
+// runs after every frame. --reload rebuilds the second run from its save state every frame. Exits
+// with 1 if the runs diverge.
+fn run_determinism(args: &cli::DeterminismArgs) -> i32 {
+    let cartridge = Cartridge::new(&std::fs::read(&args.rom).unwrap()).unwrap();
+    let movie = args.movie.as_ref().map(|path| load_movie(path, &cartridge));
+    let frames = args
+        .frames
+        .unwrap_or_else(|| movie.as_ref().map_or(determinism::DEFAULT_FRAMES, |movie| movie.len() as u64));
+    match determinism::verify(&cartridge, movie.as_ref(), frames, args.reload) {
+        Ok(None) => {
+            println!("{} frames of {} ran the same both times", frames, args.rom);
+            0
+        }
+        Ok(Some(divergence)) => {
+            println!("{}", divergence);
+            1
+        }
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
+    }
+}
+
+// `nes_rs bench` times the CPU on its own, the whole console running the ROM and each filter
+// preset, without a window, and prints how fast each went.
+fn run_bench(args: &cli::BenchArgs) {
+    let speed = bench::measure_cpu(args.instructions);
+    println!(
+        "CPU: {:.2}M instructions/s ({} instructions in {:.2?})",
+        speed.per_second() / 1e6,
+        speed.count,
+        speed.elapsed
+    );
+
+    let mut emulator = Emulator::new(Cartridge::new(&std::fs::read(&args.rom).unwrap()).unwrap());
+    let speed = bench::measure_frames(&mut emulator, args.frames);
+    println!(
+        "Console: {:.0} frames/s, {:.1}x real time ({} frames of {} in {:.2?})",
+        speed.per_second(),
+        speed.per_second() / NTSC_FRAME_RATE,
+        speed.count,
+        args.rom,
+        speed.elapsed
+    );
+
+    for preset in FilterPreset::ALL.into_iter().filter(|preset| *preset != FilterPreset::None) {
+        let speed = bench::measure_filter(&emulator, preset, args.frames);
+        println!(
+            "Filter {}: {:.0} frames/s ({}SIMD)",
+            preset.name(),
+            speed.per_second(),
+            if simd::enabled() { "" } else { "no " }
+        );
+    }
+}
+
+// `nes_rs --serve <host:port> [--serve-encoding raw|png]` runs the console without a window at
+// full speed and streams it to TCP clients, who send back controller input. See `stream` for the
+// protocol.
+fn run_server(addr: &str, encoding: FrameEncoding) {
+    let mut emulator = new_emulator(rom_path());
+    let mut server = Server::bind(addr, encoding).unwrap();
+    println!("Streaming {} on {}", rom_path(), server.local_addr().unwrap());
+
+    let frame_time = Duration::from_secs_f64(1.0 / NTSC_FRAME_RATE);
+    let mut next_frame = Instant::now();
     loop {
-        cpu.run_once_with_callback(move |_| {
-                // println!("{}", trace::trace(cpu));
-        });
+        for event in server.poll(&mut emulator) {
+            match event {
+                StreamEvent::Connected(addr) => println!("{} connected", addr),
+                StreamEvent::Disconnected(addr, reason) => println!("{} disconnected: {}", addr, reason),
+            }
+        }
+        emulator.run_frame();
+        if let Err(e) = server.send_frame(emulator.frame_count(), &emulator.frame) {
+            tracing::warn!("Could not send frame: {}", e);
+        }
+
+        // Stalls longer than a frame are not made up for.
+        next_frame += frame_time;
+        let now = Instant::now();
+        match next_frame.checked_duration_since(now) {
+            Some(wait) => std::thread::sleep(wait),
+            None if now - next_frame > frame_time => next_frame = now,
+            None => {}
+        }
+    }
+}
+
+// `nes_rs blargg` runs blargg's test ROMs without a window and prints how each did. Exits with 1
+// if any failed.
+fn run_blargg(args: &cli::BlarggArgs) -> i32 {
+    let roms = rom_list(&args.roms);
+    let mut passed = 0;
+    for rom in &roms {
+        match blargg::run_rom(rom, args.frames) {
+            Ok(outcome) => {
+                println!("{}: {}", rom.display(), outcome.summary());
+                if outcome.passed() {
+                    passed += 1;
+                } else if !outcome.text.is_empty() {
+                    println!("    {}", outcome.text.replace('\n', "\n    "));
+                }
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+    println!("{} of {} passed", passed, roms.len());
+    (passed != roms.len()) as i32
+}
+
+#[cfg(feature = "terminal")]
+fn run_terminal() {
+    nes_rs::frontend::terminal::run(new_emulator(rom_path())).unwrap();
+}
+
+#[cfg(not(feature = "terminal"))]
+fn run_terminal() {
+    println!("This build has no terminal backend. Rebuild with `--features terminal`.");
+    std::process::exit(1);
+}
+
+#[cfg(feature = "wgpu")]
+fn run_wgpu() {
+    let filter = args().play.filter.unwrap_or(FilterPreset::None);
+    nes_rs::frontend::wgpu_backend::run(new_emulator(rom_path()), video_settings(), filter).unwrap();
+}
+
+#[cfg(not(feature = "wgpu"))]
+fn run_wgpu() {
+    println!("This build has no wgpu backend. Rebuild with `--features wgpu`.");
+    std::process::exit(1);
+}
+
+// Ends the program over a startup option that can't be carried out, like a port already in use.
+fn exit_with(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+// Files ending in .fm2 are written in FCEUX's format.
+fn save_movie(path: &str, movie: &Movie, emulator: &Emulator, rom_path: &str) {
+    if path.ends_with(".fm2") {
+        std::fs::write(path, movie.to_fm2(emulator.cartridge(), rom_path)).unwrap();
+    } else {
+        movie.save(path).unwrap();
+    }
+}
+
+// The launcher's list of recent games, next to the config file.
+const RECENT_FILE: &str = "recent.txt";
+
+// How long the offer to resume from the automatic save stands.
+const RESUME_OFFER: Duration = Duration::from_secs(10);
+
+// Resumes from the game's automatic save straight away if `ask` is false, or else offers to.
+// Returns when the offer runs out. Nothing is offered while a movie is recording or playing.
+fn offer_resume(slots: &StateSlots, emulator: &mut Emulator, osd: &mut Osd, ask: bool) -> Option<Instant> {
+    let saved_at = slots.autosaved_at()?;
+    if *emulator.movie_state() != MovieState::Inactive {
+        return None;
+    }
+    if !ask {
+        match slots.resume(emulator) {
+            Ok(()) => osd.post(format!("Resumed from {}", saved_at)),
+            Err(e) => osd.post(format!("Could not resume: {}", e)),
+        }
+        return None;
+    }
+    osd.post(format!("Press Y to resume from {}, N to start over", saved_at));
+    Some(Instant::now() + RESUME_OFFER)
+}
+
+// Loads the game's cheat file, next to the ROM, if it has one and its settings don't turn it off.
+fn load_cheats(emulator: &mut Emulator, rom_path: &Path, game: &GameConfig, osd: &mut Osd) {
+    let path = Cheats::path_for_rom(rom_path);
+    if !game.cheats || !path.exists() {
+        return;
+    }
+    match Cheats::load(&path) {
+        Ok(cheats) => {
+            osd.post(format!("Loaded {} cheats", cheats.list().len()));
+            emulator.cpu.bus.cheats = cheats;
+        }
+        Err(e) => osd.post(e),
+    }
+}
+
+// Sets up an arcade game: --vs-ppu <model> puts in the PPU its board had (2c03, 2c04-0001 to
+// 2c04-0004 or 2c05-01 to 2c05-05) and --vs-dip <switches> sets the DIP switches, eight 0s and 1s
+// from switch 1. Home cartridges are left alone.
+fn configure_arcade(emulator: &mut Emulator, ppu: Option<PpuModel>, dip_switches: Option<u8>) {
+    if emulator.cartridge().console == Console::Nes {
+        return;
+    }
+    if let Some(model) = ppu {
+        emulator.set_ppu_model(model);
+    }
+    if let (Some(vs_system), Some(dip_switches)) = (&mut emulator.cpu.bus.vs_system, dip_switches) {
+        vs_system.dip_switches = dip_switches;
+    }
+}
+
+// The loaded game's settings from the config file, or the defaults if it has none there.
+fn game_config(emulator: &Emulator, osd: &mut Osd) -> GameConfig {
+    let hash = emulator.cartridge().hash();
+    match args().config.game(hash) {
+        Some(game) => {
+            osd.post(format!("Using the settings for {}", game.name.clone().unwrap_or(format!("{:016x}", hash))));
+            game.clone()
+        }
+        None => GameConfig::default(),
+    }
+}
+
+// Works out the region of the game just loaded, from --region, the game's settings, or else its
+// header and file name (see `region::detect`), and says which it is and why. Only NTSC consoles are
+// emulated, so games for the others are run as NTSC anyway.
+fn report_region(emulator: &Emulator, rom_path: &Path, args: &PlayArgs, game: &GameConfig, osd: &mut Osd) {
+    let (region, source) = match (args.region, game.region) {
+        (Some(region), _) => (region, "--region".to_owned()),
+        (None, Some(region)) => (region, "the game's settings".to_owned()),
+        (None, None) => {
+            let file_name = rom_path.file_name().unwrap_or_default().to_string_lossy();
+            let (region, source) = region::detect(emulator.cartridge(), &file_name);
+            if source == RegionSource::Default {
+                tracing::info!("Region {}: nothing says otherwise", region.label());
+                return;
+            }
+            (region, source.to_string())
+        }
+    };
+    if region == Region::Ntsc {
+        tracing::info!("Region NTSC, from {}", source);
+        osd.post(format!("Region NTSC, from {}", source));
+    } else {
+        let message = format!("{} game (from {}); only NTSC is emulated, so it runs as NTSC", region.label(), source);
+        tracing::warn!("{}", message);
+        osd.post(message);
+    }
+}
+
+// Sets up the console for the game just loaded, from the options and the game's settings.
+fn configure_game(emulator: &mut Emulator, args: &PlayArgs, game: &GameConfig) {
+    configure_arcade(emulator, args.vs_ppu, args.vs_dip);
+    emulator.set_accuracy(args.accuracy.or(game.accuracy).unwrap_or(cli::args().config.emulation.accuracy));
+    emulator.set_overclock(args.overclock.unwrap_or(0));
+
+    // The Zapper or the Power Pad replaces controller 2. Aim the Zapper with the mouse and fire
+    // with the left button; the Power Pad's buttons are keys.
+    let controller = match (args.zapper, args.power_pad) {
+        (true, _) => Controller::Zapper,
+        (_, true) => Controller::PowerPad,
+        _ => game.controller.unwrap_or(Controller::Joypad),
+    };
+    emulator.cpu.bus.port2 = match controller {
+        Controller::Joypad => Port2Device::Joypad,
+        Controller::Zapper => Port2Device::Zapper(Zapper::new()),
+        Controller::PowerPad => Port2Device::PowerPad(PowerPad::new()),
+    };
+
+    // The Four Score adds controllers 3 and 4, driven by the third and fourth gamepads.
+    emulator.set_four_score(args.four_score || game.four_score);
+
+    // The Family BASIC keyboard goes in the expansion port, typed on with the host keyboard.
+    emulator.set_keyboard(args.keyboard || game.keyboard);
+}
+
+// Sets up the console from the options that apply with or without a window.
+fn configure_console(emulator: &mut Emulator, args: &PlayArgs, game: &GameConfig) {
+    configure_game(emulator, args, game);
+
+    // --record <file> records a movie from power-on; --play <file> replays one. Files ending in
+    // .fm2 use FCEUX's format.
+    if args.record.is_some() {
+        emulator.record_movie();
+    }
+    if let Some(path) = &args.play {
+        let movie = load_movie(path, emulator.cartridge());
+        emulator.play_movie(movie).unwrap();
+    }
+
+    // --cdl <file> logs which ROM bytes run as code and which are read as data, carrying on from
+    // the file if it exists. The log is written back on exit.
+    if let Some(path) = &args.cdl {
+        let bus = &mut emulator.cpu.bus;
+        if Path::new(path).exists() {
+            let log = CodeDataLog::load(Path::new(path), bus.prg_rom_len(), bus.chr_rom_len()).unwrap();
+            bus.resume_code_data_log(log).unwrap();
+        } else {
+            bus.start_code_data_log();
+        }
+    }
+}
+
+// Sets up the debugger's symbols, tracer, profiler and script.
+fn new_debugger(args: &PlayArgs, rom_path: &str) -> Debugger {
+    let mut debugger = Debugger::new();
+    // --symbols <file> (repeatable) loads labels from a ca65 .dbg file or an FCEUX .nl name list.
+    // FCEUX name lists next to the ROM (balloon.nes.0.nl, balloon.nes.ram.nl, ...) load by
+    // themselves.
+    debugger.symbols.load_fceux_for_rom(Path::new(rom_path)).unwrap();
+    for path in &args.symbols {
+        debugger.symbols.load(Path::new(path)).unwrap();
+    }
+
+    // --trace <file> logs every instruction to a file; --trace ring[:<lines>] keeps the last lines
+    // in memory instead and writes them to trace-crash.log if the emulator panics. --trace-format
+    // <columns> picks the columns and --trace-range <addr-addr> (repeatable) limits the addresses.
+    if let Some(target) = &args.trace {
+        let mut tracer = match target.split_once(':') {
+            Some(("ring", lines)) => Tracer::ring(lines.parse().expect("--trace ring:<lines> takes a number")),
+            None if target == "ring" => Tracer::ring(DEFAULT_RING_LINES),
+            _ => Tracer::to_file(Path::new(target)).unwrap(),
+        };
+        if let Some(columns) = &args.trace_format {
+            tracer.columns = parse_columns(columns).unwrap();
+        }
+        for range in &args.trace_range {
+            tracer.add_range_str(range).unwrap();
+        }
+        if let Some(ring) = tracer.ring_buffer() {
+            let default_hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                default_hook(info);
+                // The panic may have happened while tracing, with the ring locked.
+                if let Ok(lines) = ring.try_lock() {
+                    let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+                    if std::fs::write("trace-crash.log", text).is_ok() {
+                        eprintln!("Wrote the last {} traced instructions to trace-crash.log", lines.len());
+                    }
+                }
+            }));
+        }
+        tracer.symbols = debugger.symbols.clone();
+        debugger.tracer = Some(tracer);
+    }
+
+    // --profile <file> attributes CPU cycles to the subroutines they were spent in and writes the
+    // report to the file on exit.
+    if args.profile.is_some() {
+        debugger.profiler = Some(Profiler::new());
+    }
+
+    // --script <file> runs a Rhai script with callbacks on frames and instructions (built with
+    // --features scripting).
+    #[cfg(feature = "scripting")]
+    if let Some(path) = &args.script {
+        debugger.script = Some(Script::load(Path::new(path)).unwrap());
+    }
+    debugger
+}
+
+// Adds the cheats from --cheats <file> and --cheat <code>.
+fn add_cheats(emulator: &mut Emulator, args: &PlayArgs) {
+    for path in &args.cheats {
+        for cheat in Cheats::load(Path::new(path)).unwrap().list() {
+            let index = emulator.cpu.bus.cheats.add_named(cheat.code, &cheat.name).unwrap();
+            emulator.cpu.bus.cheats.set_enabled(index, cheat.enabled);
+        }
+    }
+    for code in &args.cheat {
+        emulator.cpu.bus.cheats.add(CheatCode::parse(code).unwrap()).unwrap();
+    }
+}
+
+// Writes out what the options asked to keep when the emulator exits: the movie being recorded, the
+// code/data log, the trace, the cheat list and the profile.
+fn save_on_exit(emulator: &mut Emulator, debugger: &mut Debugger, args: &PlayArgs, rom_path: &str) {
+    if let (Some(path), Some(movie)) = (&args.record, emulator.stop_movie()) {
+        save_movie(path, &movie, emulator, rom_path);
+    }
+    if let (Some(path), Some(log)) = (&args.cdl, &emulator.cpu.bus.cdl) {
+        log.save(Path::new(path)).unwrap();
+    }
+    if let Some(tracer) = &mut debugger.tracer {
+        tracer.flush().unwrap();
+    }
+    if let Some(path) = &args.save_cheats {
+        emulator.cpu.bus.cheats.save(Path::new(path)).unwrap();
+    }
+    if let (Some(path), Some(profiler)) = (&args.profile, &debugger.profiler) {
+        std::fs::write(path, profiler.format_report(&debugger.symbols)).unwrap();
+    }
+}
+
+// `nes_rs [rom] --headless --frames <n>` runs the game for n frames without a window or input,
+// with the options that don't need one (movies, cheats, the arcade settings, --trace, --profile,
+// --cdl and scripts), then prints a hash of the last frame, the one `test-roms` uses. Exits with 1
+// if the CPU halted, or a script paused it, before the last frame.
+fn run_headless(args: &PlayArgs) -> i32 {
+    let rom = rom_path();
+    let mut emulator = new_emulator(rom);
+    let mut osd = Osd::default();
+    let game = game_config(&emulator, &mut osd);
+    configure_console(&mut emulator, args, &game);
+    report_region(&emulator, Path::new(rom), args, &game, &mut osd);
+    let mut debugger = new_debugger(args, rom);
+    load_cheats(&mut emulator, Path::new(rom), &game, &mut osd);
+    add_cheats(&mut emulator, args);
+
+    let frames = args.frames.unwrap_or(0);
+    let started = Instant::now();
+    let mut stopped = None;
+    for _ in 0..frames {
+        debugger.run_frame(&mut emulator);
+        #[cfg(feature = "scripting")]
+        if let Some(Err(e)) = debugger.script.as_mut().map(|script| script.after_frame(&mut emulator)) {
+            tracing::warn!("Script stopped: {}", e);
+            debugger.script = None;
+        }
+        if let Some(reason) = debugger.break_reason() {
+            stopped = Some(format!("Stopped at frame {}: {:?}", emulator.frame_count(), reason));
+            break;
+        }
+    }
+    save_on_exit(&mut emulator, &mut debugger, args, rom);
+
+    if let Some(message) = &stopped {
+        println!("{}", message);
+    }
+    println!(
+        "Ran {} frames of {} in {:.2?}; the last frame's hash is {:016x}",
+        emulator.frame_count(),
+        rom,
+        started.elapsed(),
+        frame_hash(&emulator.frame.to_rgba8())
+    );
+    stopped.is_some() as i32
+}
+
+async fn run() {
+    let args = &args().play;
+    // Where games, save states, GIFs and screenshots are, from the config file.
+    let paths = &cli::args().config.paths;
+
+    let mut frontend = Frontend::new();
+    frontend.set_video(video_settings());
+
+    // Controller 1's keys can be rebound in the config file.
+    let mut input = HostInput::new();
+    let mut microphone = HostMicrophone::new();
+    if args.microphone {
+        if let Err(e) = microphone.listen(args.microphone_level.unwrap_or(DEFAULT_LEVEL)) {
+            frontend.osd.post(e);
+        }
+    }
+    if let Err(e) = input.bind_keys(&cli::args().config.input.keyboard) {
+        frontend.osd.post(e);
+    }
+    if let Err(e) = input.bind_power_pad(&cli::args().config.input.power_pad) {
+        frontend.osd.post(e);
+    }
+
+    // Without a ROM on the command line, the launcher lists the recent games and the ROM
+    // directory's to pick from.
+    let mut recent = RecentGames::load(config::dir().map(|dir| dir.join(RECENT_FILE)));
+    let mut rom_path = match &args.rom {
+        Some(rom) => rom.clone(),
+        None => {
+            let entries = launcher::entries(&recent, &paths.roms);
+            launcher::choose(&entries, &mut input).await.to_string_lossy().into_owned()
+        }
+    };
+    if let Err(e) = recent.add(Path::new(&rom_path)) {
+        frontend.osd.post(e);
+    }
+    let mut emulator = new_emulator(&rom_path);
+    // The game's settings in the config file, applied again whenever another game is loaded.
+    let mut game = game_config(&emulator, &mut frontend.osd);
+    configure_console(&mut emulator, args, &game);
+    report_region(&emulator, Path::new(&rom_path), args, &game, &mut frontend.osd);
+    let global_overscan = cli::args().config.video.overscan;
+    frontend.set_video(VideoSettings {
+        overscan: game.overscan.unwrap_or(global_overscan),
+        ..frontend.video()
+    });
+
+    // Keep the window open long enough to write the movie out.
+    prevent_quit();
+
+    // --filter <none|scanlines|crt|scale2x> picks a post-processing filter; F8 cycles through them.
+    if let Some(preset) = args.filter {
+        frontend.set_filter_preset(preset);
+    }
+
+    // --speed <x> sets the emulation speed (0.5 is half speed), --fast-forward <x|max> the speed
+    // while Tab is held, and --vsync locks to the display's refresh when it is close to 60 Hz.
+    if let Some(speed) = args.speed {
+        frontend.timer.speed = speed;
+    }
+    if let Some(speed) = args.fast_forward {
+        frontend.timer.fast_forward = speed;
+    }
+    frontend.timer.vsync = args.vsync;
+    // --frame-skip <n> leaves n frames undrawn after each drawn one, for slow machines. Above real
+    // time frames are skipped regardless, since only about one per display refresh can be shown.
+    if let Some(frames) = args.frame_skip {
+        frontend.timer.frame_skip = frames;
+    }
+    frontend.osd.show_fps = args.show_fps;
+    // --filter-thread runs the filters on a thread of their own, for filters too slow to share
+    // the emulation thread. The picture shows one frame later.
+    if args.filter_thread {
+        frontend.filter_on_thread();
+    }
+
+    // F9 starts and stops recording a GIF. --gif-frame-skip <n> drops n frames after each recorded
+    // one (1 by default, which halves the file size and suits most GIF viewers).
+    let gif_frame_skip = args.gif_frame_skip.unwrap_or(1);
+    let mut gif_recorder: Option<GifRecorder> = None;
+    let rom_stem = |rom_path: &str| Path::new(rom_path).file_stem().unwrap().to_string_lossy().into_owned();
+
+    // F1 to F4 open the pattern table, nametable, palette and memory viewers, ` the debugger,
+    // Shift+` the event viewer, C the cheats and Shift+C cheat search; --debug opens them all at
+    // startup. --break <symbol|addr>[ if <condition>] sets a breakpoint,
+    // and --watch <addr[-addr]>[:r|w|rw] a watchpoint; both may be repeated.
+    let mut debug_windows = DebugWindows::new();
+    if args.debug {
+        for view in DebugView::ALL {
+            debug_windows.set_open(view, true);
+        }
+    }
+    let mut debugger = new_debugger(args, &rom_path);
+    for breakpoint in &args.breakpoints {
+        debugger.add_breakpoint_at(breakpoint).unwrap();
+        debug_windows.set_open(DebugView::Debugger, true);
+    }
+    for watchpoint in &args.watchpoints {
+        debugger.add_watchpoint(*watchpoint);
+        debug_windows.set_open(DebugView::Debugger, true);
+    }
+
+    // Cheats load from the file next to the ROM (balloon.nes.cheats). --cheats <file> adds those in
+    // another cheat file or an FCEUX or libretro .cht file, and --cheat <code> (a Game Genie code or
+    // "freeze <addr> to <value>") adds one; both are repeatable. --save-cheats <file> saves the list
+    // on exit, as a .cht file in FCEUX's format if it ends in .cht.
+    load_cheats(&mut emulator, Path::new(&rom_path), &game, &mut frontend.osd);
+    add_cheats(&mut emulator, args);
+
+    // Save state slots, kept per game under states/.
+    let state_slots = |emulator: &Emulator, rom_path: &str| {
+        StateSlots::new(&paths.states, &rom_stem(rom_path), emulator.cartridge().hash())
+    };
+    let mut slots = state_slots(&emulator, &rom_path);
+    // The board's EEPROMs and clocks keep what they store in the same directory.
+    if let Err(e) = chips::load(slots.dir(), &mut emulator.cpu.bus.chips) {
+        frontend.osd.post(e);
+    }
+
+    // --autosave saves the game on exit (or when another is dropped in), and --autosave-every
+    // <seconds> every so often too. Next time the same game starts, it offers to resume from there;
+    // --resume does so without asking.
+    let autosave_every = args.autosave_every.map(Duration::from_secs);
+    let autosave = autosave_every.is_some() || args.autosave;
+    let ask_to_resume = !args.resume;
+    let mut last_autosave = Instant::now();
+
+    // Holding R rewinds. --rewind <seconds> sets how far back it goes; 0 turns it off.
+    let rewind_seconds = args.rewind.unwrap_or(DEFAULT_SECONDS);
+    let mut rewind = (rewind_seconds > 0).then(|| Rewind::new(DEFAULT_INTERVAL, rewind_seconds));
+
+    // --run-ahead <frames> shows each frame that many frames early, to hide the game's input lag.
+    let mut run_ahead = RunAhead::new(args.run_ahead.unwrap_or(0));
+
+    // --gdb <port> lets gdb (or an IDE speaking its remote protocol) attach with
+    // `target remote localhost:<port>`.
+    let mut gdb_server = args.gdb.map(|port| GdbServer::listen(port).unwrap_or_else(|e| exit_with(e)));
+
+    // --netplay-host <port> waits for a second player to join over UDP, and --netplay-join
+    // <host:port> joins as player 2. --netplay-delay <frames> sets the input delay, which should
+    // cover the one-way latency (2 frames by default). Rewind, loading states, resuming and
+    // loading another game are off during netplay, since any of them would desync the two
+    // consoles, which both start from power-on once the players connect.
+    let setup = Setup::of(&emulator);
+    let mut netplay = match (args.netplay_host, &args.netplay_join) {
+        (Some(port), _) => Some(Session::host(port, setup).unwrap_or_else(|e| exit_with(e))),
+        (None, Some(addr)) => Some(Session::join(addr, setup).unwrap_or_else(|e| exit_with(e))),
+        (None, None) => None,
+    };
+    if let Some(session) = &mut netplay {
+        if let Some(delay) = args.netplay_delay {
+            session.delay = delay;
+        }
+        rewind = None;
+    }
+    let mut resume_offer = match netplay {
+        Some(_) => None,
+        None => offer_resume(&slots, &mut emulator, &mut frontend.osd, ask_to_resume),
+    };
+
+    // --tas <movie> edits the movie in the TAS editor, starting a new one if the file doesn't exist
+    // yet. It starts paused at power-on and plays with P and \ as usual; the movie, its markers and
+    // its branches are saved on exit. Rewind and loading states are off, since the editor seeks
+    // through the movie itself.
+    let mut tas = args.tas.as_ref().map(|path| TasWindow::open(path, &mut emulator).unwrap_or_else(|e| exit_with(e)));
+    if tas.is_some() {
+        rewind = None;
+        if !frontend.timer.paused {
+            frontend.timer.toggle_pause();
+        }
+    }
+
+    // --ra-user <name> logs in to RetroAchievements, with the password in NES_RS_RA_PASSWORD or
+    // with --ra-token <token>, which is printed after logging in. --hardcore turns on hardcore
+    // mode, in which the core refuses save states, rewind and cheats.
+    #[cfg(feature = "achievements")]
+    let mut achievements = args.ra_user.as_ref().map(|user| {
+        let mut client = nes_rs::frontend::achievements::Client::new();
+        client.achievements.set_hardcore(args.hardcore);
+        match (&args.ra_token, std::env::var("NES_RS_RA_PASSWORD")) {
+            (Some(token), _) => client.achievements.login_with_token(user, token),
+            (None, Ok(password)) => client.achievements.login_with_password(user, &password),
+            (None, Err(_)) => panic!("--ra-user needs --ra-token or NES_RS_RA_PASSWORD"),
+        }
+        client.achievements.load_game(&std::fs::read(&rom_path).unwrap());
+        client
+    });
+
+    // Whether the host keyboard types on the Family BASIC keyboard, when it is plugged in.
+    let mut typing = true;
+
+    loop {
+        if is_quit_requested() {
+            if autosave {
+                if let Err(e) = slots.autosave(&emulator) {
+                    tracing::error!("Could not save: {}", e);
+                }
+            }
+            save_on_exit(&mut emulator, &mut debugger, args, &rom_path);
+            if let Err(e) = chips::save(slots.dir(), &emulator.cpu.bus.chips) {
+                tracing::error!("{}", e);
+            }
+            if let Err(e) = tas.as_ref().map_or(Ok(()), TasWindow::save) {
+                tracing::error!("{}", e);
+            }
+            break;
+        }
+
+        // Dropping a .nes file onto the window swaps it in and resets the console. A movie being
+        // recorded and a code/data log are saved first, since they can't continue on another game.
+        let dropped = get_dropped_files().into_iter().filter_map(|file| file.path).next_back();
+        if dropped.is_some() && netplay.is_some() {
+            frontend.osd.post("Loading another game is off during netplay");
+        } else if let Some(path) = dropped {
+            if let (Some(record), Some(movie)) = (&args.record, emulator.stop_movie()) {
+                save_movie(record, &movie, &emulator, &rom_path);
+            }
+            if let (Some(cdl), Some(log)) = (&args.cdl, emulator.cpu.bus.cdl.take()) {
+                if let Err(e) = log.save(Path::new(cdl)) {
+                    frontend.osd.post(e);
+                }
+            }
+            if autosave {
+                if let Err(e) = slots.autosave(&emulator) {
+                    frontend.osd.post(format!("Could not save: {}", e));
+                }
+            }
+            if let Err(e) = chips::save(slots.dir(), &emulator.cpu.bus.chips) {
+                frontend.osd.post(e);
+            }
+            // The TAS editor's movie is for the old game, so it is saved and closed.
+            if let Some(Err(e)) = tas.take().map(|tas| tas.save()) {
+                frontend.osd.post(e);
+            }
+            match emulator.open_rom(&path) {
+                Ok(()) => {
+                    rom_path = path.to_string_lossy().into_owned();
+                    frontend.osd.post(format!("Loaded {}", rom_stem(&rom_path)));
+                    if let Err(e) = recent.add(&path) {
+                        frontend.osd.post(e);
+                    }
+                    game = game_config(&emulator, &mut frontend.osd);
+                    configure_game(&mut emulator, args, &game);
+                    report_region(&emulator, &path, args, &game, &mut frontend.osd);
+                    frontend.set_video(VideoSettings {
+                        overscan: game.overscan.unwrap_or(global_overscan),
+                        ..frontend.video()
+                    });
+                    #[cfg(feature = "achievements")]
+                    if let (Some(client), Ok(rom)) = (&mut achievements, std::fs::read(&path)) {
+                        client.achievements.load_game(&rom);
+                    }
+                    slots = state_slots(&emulator, &rom_path);
+                    if let Err(e) = chips::load(slots.dir(), &mut emulator.cpu.bus.chips) {
+                        frontend.osd.post(e);
+                    }
+                    load_cheats(&mut emulator, &path, &game, &mut frontend.osd);
+                    resume_offer = offer_resume(&slots, &mut emulator, &mut frontend.osd, ask_to_resume);
+                    if let Some(rewind) = &mut rewind {
+                        rewind.clear();
+                    }
+                    // Symbols belong to the old game; pick up the new one's name lists instead.
+                    debugger.symbols = SymbolTable::new();
+                    if let Err(e) = debugger.symbols.load_fceux_for_rom(&path) {
+                        frontend.osd.post(e);
+                    }
+                }
+                Err(e) => frontend.osd.post(e),
+            }
+        }
+
+        // With the Family BASIC keyboard in, the host keyboard types on it rather than pressing
+        // hotkeys or controller 1's buttons. Scroll Lock switches between the two.
+        if emulator.cpu.bus.keyboard.is_some() && is_key_pressed(KeyCode::ScrollLock) {
+            typing = !typing;
+            frontend.osd.post(if typing { "Keyboard: typing" } else { "Keyboard: hotkeys" });
+        }
+        let typing_on_keyboard = typing && emulator.cpu.bus.keyboard.is_some();
+        let hotkey = |key| !typing_on_keyboard && is_key_pressed(key);
+
+        if !typing_on_keyboard {
+            frontend.handle_hotkeys();
+            debug_windows.handle_hotkeys();
+        }
+
+        // B cycles frame blending.
+        if hotkey(KeyCode::B) {
+            emulator.blender.mode = emulator.blender.mode.next();
+            frontend.osd.post(format!("Frame blending: {}", emulator.blender.mode.name()));
+        }
+
+        // G hides and shows the background, H the sprites.
+        let mut layers = emulator.layers();
+        if hotkey(KeyCode::G) {
+            layers.background = !layers.background;
+            frontend.osd.post(if layers.background { "Background shown" } else { "Background hidden" });
+        }
+        if hotkey(KeyCode::H) {
+            layers.sprites = !layers.sprites;
+            frontend.osd.post(if layers.sprites { "Sprites shown" } else { "Sprites hidden" });
+        }
+        if layers != emulator.layers() {
+            emulator.set_layers(layers);
+        }
+
+        // D ejects a disk and puts the next side in, for games that ask for side B.
+        if hotkey(KeyCode::D) {
+            if let Some(fds) = &mut emulator.cpu.bus.fds {
+                let side = fds.switch_to_next_side();
+                frontend.osd.post(format!("Inserting {}", side_name(side)));
+            }
+        }
+
+        // On a VS System game, I and O drop coins into slots 1 and 2 and U presses the service
+        // button.
+        if let Some(vs_system) = &mut emulator.cpu.bus.vs_system {
+            for (slot, key) in [KeyCode::I, KeyCode::O].into_iter().enumerate() {
+                if hotkey(key) {
+                    vs_system.insert_coin(slot);
+                    frontend.osd.post(format!("Coin in slot {}", slot + 1));
+                }
+            }
+            if hotkey(KeyCode::U) {
+                vs_system.press_service();
+                frontend.osd.post("Service");
+            }
+        }
+
+        // Never offered during netplay, but a state loaded then would desync the consoles.
+        if let (Some(deadline), None) = (resume_offer, &netplay) {
+            if hotkey(KeyCode::Y) {
+                match slots.resume(&mut emulator) {
+                    Ok(()) => frontend.osd.post("Resumed"),
+                    Err(e) => frontend.osd.post(format!("Could not resume: {}", e)),
+                }
+                resume_offer = None;
+            } else if hotkey(KeyCode::N) || Instant::now() >= deadline {
+                resume_offer = None;
+            }
+        }
+        if let Some(every) = autosave_every {
+            if last_autosave.elapsed() >= every {
+                if let Err(e) = slots.autosave(&emulator) {
+                    frontend.osd.post(format!("Could not save: {}", e));
+                }
+                last_autosave = Instant::now();
+            }
+        }
+
+        // 0 to 9 pick a save state slot, K saves to it and L loads it.
+        let digits = [
+            KeyCode::Key0,
+            KeyCode::Key1,
+            KeyCode::Key2,
+            KeyCode::Key3,
+            KeyCode::Key4,
+            KeyCode::Key5,
+            KeyCode::Key6,
+            KeyCode::Key7,
+            KeyCode::Key8,
+            KeyCode::Key9,
+        ];
+        if let Some(slot) = digits.iter().position(|key| hotkey(*key)) {
+            slots.selected = slot;
+            match slots.saved_at(slot) {
+                Some(time) => frontend.osd.post(format!("Slot {} (saved {})", slot, time)),
+                None => frontend.osd.post(format!("Slot {} (empty)", slot)),
+            }
+        }
+        if hotkey(KeyCode::K) {
+            match slots.save(slots.selected, &emulator) {
+                Ok(_) => frontend.osd.post(format!("Saved slot {}", slots.selected)),
+                Err(e) => frontend.osd.post(format!("Could not save: {}", e)),
+            }
+        }
+        if hotkey(KeyCode::L) && netplay.is_some() {
+            frontend.osd.post("Loading states is off during netplay");
+        } else if hotkey(KeyCode::L) && tas.is_some() {
+            frontend.osd.post("Loading states is off in the TAS editor");
+        } else if hotkey(KeyCode::L) {
+            let saved_at = slots.saved_at(slots.selected).unwrap_or_default();
+            match slots.load(slots.selected, &mut emulator) {
+                Ok(()) => frontend.osd.post(format!("Loaded slot {} (saved {})", slots.selected, saved_at)),
+                Err(e) => frontend.osd.post(format!("Could not load: {}", e)),
+            }
+        }
+
+        if hotkey(KeyCode::F9) {
+            match gif_recorder.take() {
+                Some(recorder) => frontend.osd.post(format!("Recorded {} frames", recorder.frames_recorded())),
+                None => {
+                    let started = numbered_path(&paths.recordings, &rom_stem(&rom_path), "gif")
+                        .and_then(|path| Ok((GifRecorder::new(&path, gif_frame_skip)?, path)));
+                    match started {
+                        Ok((recorder, path)) => {
+                            frontend.osd.post(format!("Recording to {}", path.display()));
+                            gif_recorder = Some(recorder);
+                        }
+                        Err(e) => frontend.osd.post(format!("Could not record: {}", e)),
+                    }
+                }
+            }
+        }
+
+        if let Some(server) = &mut gdb_server {
+            server.poll(&mut emulator, &mut debugger);
+        }
+
+        // Rewinding would desync a movie, so it is off while one is recording or playing.
+        let rewinding = !typing_on_keyboard && is_key_down(KeyCode::R) && rewind.is_some();
+        if rewinding && is_key_pressed(KeyCode::R) {
+            frontend.osd.post(match emulator.movie_state() {
+                MovieState::Inactive => "Rewinding",
+                _ => "Rewind is off during movies",
+            });
+        }
+
+        if let Some(Err(e)) = netplay.as_mut().map(Session::poll) {
+            frontend.osd.post(e);
+            netplay = None;
+        }
+        if let Some(session) = &mut netplay {
+            session.start(&mut emulator);
+        }
+        frontend.osd.status = netplay.as_ref().map(Session::status);
+        #[cfg(feature = "achievements")]
+        if let Some(client) = &mut achievements {
+            client.update(&mut frontend.osd);
+            if frontend.timer.paused {
+                client.achievements.idle();
+            }
+            frontend.osd.status = frontend.osd.status.take().or_else(|| client.trackers());
+        }
+
+        let (mouse_x, mouse_y) = mouse_position();
+        let aim = frontend.screen_to_nes(mouse_x, mouse_y);
+
+        if let (true, Some(rewind), MovieState::Inactive) = (rewinding, &mut rewind, emulator.movie_state()) {
+            if let Err(e) = rewind.step_back(&mut emulator) {
+                frontend.osd.post(format!("Could not rewind: {}", e));
+                rewind.clear();
+            }
+        } else {
+            // Errors from inside the frame loop, which can't reach the OSD while it borrows the frontend.
+            let mut errors = Vec::new();
+            frontend.run_frames(|skip_render| {
+                emulator.skip_render = skip_render;
+                #[allow(unused_mut)]
+                let mut held = match typing_on_keyboard {
+                    true => input.poll_with_keyboard(JoypadButton::empty(), JoypadButton::empty()),
+                    false => input.poll(),
+                };
+                emulator.cpu.bus.microphone = microphone.poll(!typing_on_keyboard);
+                if let Some(keyboard) = &mut emulator.cpu.bus.keyboard {
+                    keyboard.set_keys(&if typing_on_keyboard { family_keys_held() } else { Vec::new() });
+                }
+                #[cfg(feature = "scripting")]
+                if let Some(script) = &mut debugger.script {
+                    script.apply_input(&mut held);
+                }
+                if let Some(session) = &mut netplay {
+                    let Some(both) = session.next_inputs(held[0]) else {
+                        return;
+                    };
+                    held = [JoypadButton::empty(); MAX_PADS];
+                    held[..2].copy_from_slice(&both);
+                }
+                for (port, buttons) in held.into_iter().enumerate() {
+                    emulator.set_buttons(port, buttons);
+                }
+                match &mut emulator.cpu.bus.port2 {
+                    Port2Device::Zapper(zapper) => {
+                        (zapper.x, zapper.y) = aim;
+                        zapper.trigger = is_mouse_button_down(MouseButton::Left);
+                    }
+                    Port2Device::PowerPad(power_pad) => power_pad.buttons = input.power_pad_held(),
+                    Port2Device::Joypad => {}
+                }
+
+                let ran = match &mut tas {
+                    Some(tas) => tas.advance(&mut emulator, held).inspect_err(|e| errors.push(format!("TAS editor: {}", e))).is_ok(),
+                    None => run_ahead.run_frame(&mut emulator, |emulator| debugger.run_frame(emulator)),
+                };
+                if !ran {
+                    return;
+                }
+                if let Some(session) = &mut netplay {
+                    session.frame_done(&emulator);
+                }
+                #[cfg(feature = "achievements")]
+                if let Some(client) = &mut achievements {
+                    client.achievements.do_frame(&mut emulator);
+                }
+                if let (Some(rewind), MovieState::Inactive) = (&mut rewind, emulator.movie_state()) {
+                    rewind.record(&emulator);
+                }
+                #[cfg(feature = "scripting")]
+                if let Some(Err(e)) = debugger.script.as_mut().map(|script| script.after_frame(&mut emulator)) {
+                    errors.push(format!("Script stopped: {}", e));
+                    debugger.script = None;
+                }
+
+                if let Some(recorder) = &mut gif_recorder {
+                    if let Err(e) = recorder.add_frame(&emulator.frame) {
+                        errors.push(format!("Recording stopped: {}", e));
+                        gif_recorder = None;
+                    }
+                }
+            });
+            for e in errors {
+                tracing::warn!("{}", e);
+                frontend.osd.post(e);
+            }
+        }
+        // What the script drew stays up until its next frame callback, or until it stops.
+        #[cfg(feature = "scripting")]
+        {
+            frontend.osd.overlay = debugger.script.as_ref().map(Script::shapes).unwrap_or_default();
+            for message in debugger.script.as_mut().map(Script::take_messages).unwrap_or_default() {
+                frontend.osd.post(message);
+            }
+        }
+        frontend.present(&emulator.frame);
+        debug_windows.show(&mut emulator, &mut debugger);
+        if let Some(tas) = &mut tas {
+            tas.show(&mut emulator, &mut frontend.osd);
+        }
+
+        // F12 saves the frame as the PPU produced it, Shift+F12 the picture as displayed.
+        if hotkey(KeyCode::F12) {
+            let saved = numbered_path(&paths.screenshots, &rom_stem(&rom_path), "png").and_then(|path| {
+                if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+                    frontend.screenshot(&path)?;
+                } else {
+                    emulator.screenshot(&path)?;
+                }
+                Ok(path)
+            });
+            match saved {
+                Ok(path) => frontend.osd.post(format!("Saved {}", path.display())),
+                Err(e) => frontend.osd.post(format!("Screenshot failed: {}", e)),
+            }
+        }
 
         next_frame().await;
     }
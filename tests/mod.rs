@@ -1,3 +0,0 @@
-mod nestest;
-mod harte;
-mod blarggcpu;
\ No newline at end of file